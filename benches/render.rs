@@ -0,0 +1,98 @@
+//! Candidate list rendering benchmarks
+//!
+//! Draws a page of 9 candidates (label, text, annotation) the way the popup's
+//! candidate section does, with the popup's text renderer. jacin is a binary
+//! crate, so the renderer is included by path, as jacinctl includes the
+//! control client. Needs a font fontconfig can find:
+//!
+//! ```sh
+//! cargo bench --bench render
+//! ```
+
+use std::hint::black_box;
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use tiny_skia::{Color, Pixmap};
+
+// Only drawing is measured; helpers for the rest of the popup go unused here
+#[allow(dead_code)]
+#[path = "../src/ui/grapheme.rs"]
+mod grapheme;
+#[allow(dead_code)]
+#[path = "../src/ui/text_render.rs"]
+mod text_render;
+
+use text_render::TextRenderer;
+
+/// Theme defaults (ui/theme.rs, ui/layout.rs)
+const FONT_SIZE: f32 = 16.0;
+const PADDING: f32 = 8.0;
+const NUMBER_WIDTH: f32 = 24.0;
+const ANNOTATION_GAP: f32 = 12.0;
+const WIDTH: u32 = 580;
+const HEIGHT: u32 = 450;
+
+const CANDIDATES: [(&str, &str); 9] = [
+    ("漢字", "名詞"),
+    ("感じ", "名詞"),
+    ("幹事", "名詞"),
+    ("監事", "名詞"),
+    ("莞爾", "副詞"),
+    ("完治", "名詞・サ変"),
+    ("寛治", "人名"),
+    ("ｶﾝｼﾞ", "半角"),
+    ("カンジ", "カタカナ"),
+];
+
+fn draw_candidates(renderer: &mut TextRenderer, pixmap: &mut Pixmap) {
+    let text = Color::from_rgba8(220, 223, 228, 255);
+    let number = Color::from_rgba8(152, 195, 121, 255);
+    let mut annotation = text;
+    annotation.apply_opacity(0.55);
+
+    pixmap.fill(Color::from_rgba8(40, 44, 52, 240));
+    let line_height = renderer.line_height();
+    for (i, (candidate, note)) in CANDIDATES.iter().enumerate() {
+        let baseline = PADDING + line_height * (i + 1) as f32 - line_height * 0.3;
+        renderer.draw_text(pixmap, &(i + 1).to_string(), PADDING, baseline, number);
+        let x = PADDING + NUMBER_WIDTH;
+        renderer.draw_text(pixmap, candidate, x, baseline, text);
+        let note_x = x + renderer.measure_text(candidate) + ANNOTATION_GAP;
+        renderer.draw_text(pixmap, note, note_x, baseline, annotation);
+    }
+}
+
+fn render(c: &mut Criterion) {
+    let Some(mut renderer) = TextRenderer::new(FONT_SIZE, None) else {
+        eprintln!("render: no font found by fontconfig, skipping");
+        return;
+    };
+    let mut pixmap = Pixmap::new(WIDTH, HEIGHT).unwrap();
+    let mut group = c.benchmark_group("render_9_candidates");
+
+    // Redraws while typing: shaped text and glyph tiles all cached
+    group.bench_function("cached", |b| {
+        b.iter(|| {
+            draw_candidates(&mut renderer, &mut pixmap);
+            black_box(&pixmap);
+        })
+    });
+
+    // First page with a new renderer (new theme or font size): shaping,
+    // font fallback, rasterizing and tinting on top of the draws
+    group.bench_function("uncached", |b| {
+        b.iter_batched(
+            || TextRenderer::new(FONT_SIZE, None).unwrap(),
+            |mut renderer| {
+                draw_candidates(&mut renderer, &mut pixmap);
+                black_box(&pixmap);
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, render);
+criterion_main!(benches);
@@ -0,0 +1,109 @@
+//! System color scheme via the XDG settings portal
+//!
+//! `org.freedesktop.appearance color-scheme` is read once and then followed
+//! through the portal's `SettingChanged` signal, so `[theme.dark]` /
+//! `[theme.light]` switch with the desktop. A watcher thread forwards the
+//! preference to the event loop through a calloop channel.
+
+use calloop::channel::{Channel, Sender, channel};
+use zbus::blocking::{Connection, MessageIterator, Proxy};
+use zbus::zvariant::{OwnedValue, Value};
+use zbus::{MatchRule, message::Type as MessageType};
+
+const PORTAL: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_PATH: &str = "/org/freedesktop/portal/desktop";
+const SETTINGS_IFACE: &str = "org.freedesktop.portal.Settings";
+const NAMESPACE: &str = "org.freedesktop.appearance";
+const KEY: &str = "color-scheme";
+
+/// Preferred color scheme of the desktop
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorScheme {
+    #[default]
+    NoPreference,
+    Dark,
+    Light,
+}
+
+/// Start following the color scheme. The current one arrives first.
+pub fn spawn_color_scheme_monitor() -> Channel<ColorScheme> {
+    let (tx, rx) = channel();
+    std::thread::Builder::new()
+        .name("color-scheme".into())
+        .spawn(move || {
+            if let Err(e) = watch(&tx) {
+                log::warn!("[THEME] Color scheme detection disabled: {}", e);
+            }
+        })
+        .expect("failed to spawn color scheme thread");
+    rx
+}
+
+fn watch(tx: &Sender<ColorScheme>) -> zbus::Result<()> {
+    let conn = Connection::session()?;
+    // Subscribe first so a change during the read isn't missed
+    let rule = MatchRule::builder()
+        .msg_type(MessageType::Signal)
+        .interface(SETTINGS_IFACE)?
+        .member("SettingChanged")?
+        .build();
+    let signals = MessageIterator::for_match_rule(rule, &conn, None)?;
+
+    let settings = Proxy::new(&conn, PORTAL, PORTAL_PATH, SETTINGS_IFACE)?;
+    // ReadOne is portal version 2; Read wraps the value in another variant
+    let current: OwnedValue = settings
+        .call("ReadOne", &(NAMESPACE, KEY))
+        .or_else(|_| settings.call("Read", &(NAMESPACE, KEY)))?;
+    let scheme = color_scheme(&current).unwrap_or_default();
+    log::info!("[THEME] Color scheme: {:?}", scheme);
+    if tx.send(scheme).is_err() {
+        return Ok(());
+    }
+
+    for msg in signals {
+        let msg = msg?;
+        let body = msg.body();
+        let (namespace, key, value): (String, String, Value) = body.deserialize()?;
+        if namespace != NAMESPACE || key != KEY {
+            continue;
+        }
+        if let Some(scheme) = color_scheme(&value) {
+            log::debug!("[THEME] Color scheme changed: {:?}", scheme);
+            if tx.send(scheme).is_err() {
+                break; // Event loop gone
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Map the `color-scheme` setting (1 = dark, 2 = light, else none), looking
+/// through variant wrapping
+fn color_scheme(value: &Value) -> Option<ColorScheme> {
+    match value {
+        Value::Value(inner) => color_scheme(inner),
+        Value::U32(1) => Some(ColorScheme::Dark),
+        Value::U32(2) => Some(ColorScheme::Light),
+        Value::U32(_) => Some(ColorScheme::NoPreference),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn setting_values_map_to_schemes() {
+        assert_eq!(color_scheme(&Value::U32(1)), Some(ColorScheme::Dark));
+        assert_eq!(color_scheme(&Value::U32(2)), Some(ColorScheme::Light));
+        assert_eq!(
+            color_scheme(&Value::U32(0)),
+            Some(ColorScheme::NoPreference)
+        );
+        // Read's extra variant
+        let wrapped = Value::Value(Box::new(Value::U32(1)));
+        assert_eq!(color_scheme(&wrapped), Some(ColorScheme::Dark));
+        assert_eq!(color_scheme(&Value::Bool(true)), None);
+    }
+}
@@ -0,0 +1,152 @@
+//! Conversion backends
+//!
+//! The IME side (coordinator, key handling, popup) drives conversion through
+//! the [`Backend`] trait: keys and commands go in, [`FromNeovim`] messages
+//! (preedit, candidates, commits, ...) come back on the backend's event source.
+//! The embedded Neovim is the only external backend so far; another engine
+//! (libskk, libkkc) implements the trait and reports its state with the same
+//! messages. `[backend] kind` selects the backend, and `"romaji"` runs without
+//! one, using the built-in romaji conversion of the `engine` module. Tests run
+//! a scripted [`FakeBackend`](crate::fake_backend::FakeBackend) instead.
+
+use std::time::Duration;
+
+use crate::config::{BackendKind, Config};
+use crate::fake_backend::{self, Script};
+use crate::neovim::{
+    self, CandidateWindow, ClipboardSelection, FromNeovim, InputMode, NeovimEventSource,
+    NeovimHandle,
+};
+
+/// A running conversion engine. Commands are non-blocking: a backend that
+/// can't keep up drops them rather than stall the event loop.
+pub trait Backend {
+    /// Unique per spawned backend: tells the active backend's messages from
+    /// the standby's (or from a backend already replaced)
+    fn id(&self) -> u64;
+
+    /// Event source delivering this backend's messages to calloop (once;
+    /// None after it has been taken)
+    fn take_event_source(&mut self) -> Option<NeovimEventSource>;
+
+    /// Send a key in Vim notation (`a`, `<CR>`, `<C-w>`)
+    fn send_key(&self, key: &str);
+
+    /// Switch the input mode (hiragana, katakana, ...)
+    fn set_input_mode(&self, mode: InputMode);
+
+    /// Select (and with `finish`, accept) a completion candidate
+    fn select_candidate(&self, index: usize, finish: bool);
+
+    /// Send `window` of the candidate list shown, for a backend that sends
+    /// long lists a window at a time
+    fn scroll_candidates(&self, _window: CandidateWindow) {}
+
+    /// Commit `text` from the start of the composition and keep the rest
+    fn commit_prefix(&self, text: String);
+
+    /// Hand the compositor's selection to the backend's clipboard
+    fn set_clipboard(&self, _selection: ClipboardSelection, _text: String) {}
+
+    /// Hand the focused input's surrounding text to the backend
+    fn set_surrounding_text(&self, _before: String, _after: String) {}
+
+    /// Hand the installed dictionary paths to the backend
+    fn set_dictionaries(&self, _paths: Vec<String>) {}
+
+    /// Put back the composition of a backend this one replaced: preedit
+    /// text, cursor byte offset, and whether it was typed in insert mode
+    fn restore(&self, _text: String, _cursor: usize, _insert: bool) {}
+
+    /// Told each event loop turn for the active backend only (never the
+    /// standby): whether the IME is enabled, i.e. whether health checks are
+    /// worth waking the backend for
+    fn watch(&self, _ime_enabled: bool) {}
+
+    /// Next message, if one is queued
+    fn try_recv(&self) -> Option<FromNeovim>;
+
+    /// Next message, waiting up to `timeout`
+    fn recv_timeout(&self, timeout: Duration) -> Option<FromNeovim>;
+
+    /// Ask the backend to exit (it reports `NvimExited` when done)
+    fn shutdown(&self);
+}
+
+/// Start the backend selected in `config`. None for `kind = "romaji"`, which
+/// converts in-process without a backend.
+pub fn spawn(config: &Config) -> anyhow::Result<Option<Box<dyn Backend>>> {
+    match config.backend.kind {
+        BackendKind::Neovim => {
+            let handle = neovim::spawn_neovim(config.clone())?;
+            Ok(Some(Box::new(handle)))
+        }
+        BackendKind::Romaji => Ok(None),
+        BackendKind::Fake(ref script) => {
+            let handle = fake_backend::spawn_fake(Script::load(script)?)?;
+            Ok(Some(Box::new(handle)))
+        }
+    }
+}
+
+impl Backend for NeovimHandle {
+    fn id(&self) -> u64 {
+        NeovimHandle::id(self)
+    }
+
+    fn take_event_source(&mut self) -> Option<NeovimEventSource> {
+        NeovimHandle::take_event_source(self)
+    }
+
+    fn send_key(&self, key: &str) {
+        NeovimHandle::send_key(self, key);
+    }
+
+    fn set_input_mode(&self, mode: InputMode) {
+        NeovimHandle::set_input_mode(self, mode);
+    }
+
+    fn select_candidate(&self, index: usize, finish: bool) {
+        NeovimHandle::select_candidate(self, index, finish);
+    }
+
+    fn scroll_candidates(&self, window: CandidateWindow) {
+        NeovimHandle::scroll_candidates(self, window);
+    }
+
+    fn commit_prefix(&self, text: String) {
+        NeovimHandle::commit_prefix(self, text);
+    }
+
+    fn set_clipboard(&self, selection: ClipboardSelection, text: String) {
+        NeovimHandle::set_clipboard(self, selection, text);
+    }
+
+    fn set_surrounding_text(&self, before: String, after: String) {
+        NeovimHandle::set_surrounding_text(self, before, after);
+    }
+
+    fn set_dictionaries(&self, paths: Vec<String>) {
+        NeovimHandle::set_dictionaries(self, paths);
+    }
+
+    fn restore(&self, text: String, cursor: usize, insert: bool) {
+        NeovimHandle::restore(self, text, cursor, insert);
+    }
+
+    fn watch(&self, ime_enabled: bool) {
+        NeovimHandle::watch(self, ime_enabled);
+    }
+
+    fn try_recv(&self) -> Option<FromNeovim> {
+        NeovimHandle::try_recv(self)
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Option<FromNeovim> {
+        NeovimHandle::recv_timeout(self, timeout)
+    }
+
+    fn shutdown(&self) {
+        NeovimHandle::shutdown(self);
+    }
+}
@@ -0,0 +1,105 @@
+//! jacinctl — talk to a running jacin over its control socket
+//!
+//! Usage: jacinctl version [--json]
+//!        jacinctl trace dump
+//!        jacinctl status|toggle|enable|disable|commit
+//!        jacinctl subscribe   (one JSON status line per change)
+//!        jacinctl privacy [on|off]
+//!        jacinctl log [<filter>]
+//!        jacinctl wakeups [--json]
+//!        jacinctl dev '<step>; <step>; ...'   (needs `jacin --dev`)
+
+#[path = "../control/client.rs"]
+mod client;
+
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let json = args.iter().any(|a| a == "--json");
+    let words: Vec<&str> = args
+        .iter()
+        .filter(|a| !a.starts_with("--"))
+        .map(String::as_str)
+        .collect();
+    if words.is_empty() {
+        eprintln!(
+            "usage: jacinctl <command> [--json]\ncommands: version, status, subscribe, toggle, enable, disable, commit, privacy [on|off], trace dump, log [<filter>], wakeups, dev <script>"
+        );
+        return ExitCode::from(2);
+    }
+    let command = words.join(" ");
+
+    if command == "subscribe" {
+        return match client::subscribe(|line| println!("{line}")) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("jacinctl: {e}");
+                ExitCode::FAILURE
+            }
+        };
+    }
+
+    let response = match client::request(&command) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!(
+                "jacinctl: cannot reach jacin at {}: {}",
+                client::socket_path().display(),
+                e
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if let Some(err) = response.get("error").and_then(|e| e.as_str()) {
+        eprintln!("jacinctl: {err}");
+        return ExitCode::FAILURE;
+    }
+
+    // Trace dumps are meant to be redirected to a file as-is
+    if json || command == "trace dump" {
+        println!("{response}");
+    } else if command == "version" {
+        print_version(&response);
+    } else {
+        println!("{:#}", response);
+    }
+    ExitCode::SUCCESS
+}
+
+fn print_version(v: &serde_json::Value) {
+    let str_of = |key: &str| v.get(key).and_then(|x| x.as_str()).unwrap_or("?");
+    println!("jacin {} ({})", str_of("version"), str_of("git_hash"));
+    println!(
+        "jacinctl {} ({})",
+        env!("CARGO_PKG_VERSION"),
+        env!("JACIN_GIT_HASH")
+    );
+
+    let features: Vec<&str> = v
+        .get("features")
+        .and_then(|f| f.as_array())
+        .map(|a| a.iter().filter_map(|x| x.as_str()).collect())
+        .unwrap_or_default();
+    if !features.is_empty() {
+        println!("features: {}", features.join(", "));
+    }
+
+    if let Some(protocols) = v.get("protocols").and_then(|p| p.as_array()) {
+        println!("protocols:");
+        for p in protocols {
+            if let (Some(name), Some(ver)) = (p[0].as_str(), p[1].as_u64()) {
+                println!("  {name} v{ver}");
+            }
+        }
+    }
+
+    match v.get("latest").and_then(|l| l.as_str()) {
+        Some(latest) if v["update_available"].as_bool() == Some(true) => {
+            println!("update available: {latest}")
+        }
+        Some(latest) => println!("up to date (latest: {latest})"),
+        None => {}
+    }
+}
@@ -0,0 +1,497 @@
+//! Clipboard bridge between Neovim's `+`/`*` registers and the compositor
+//!
+//! wlr-data-control lets a client without keyboard focus read and replace the
+//! selection. Other apps' selections are only read when a register paste
+//! (`<C-r>+`, `"+p`) asks for one, never as they change (password managers
+//! copy through the same selection). The read runs from a pipe on a helper
+//! thread, bounded in size and time, and goes to Neovim's clipboard provider
+//! cache (`lua/clipboard.lua`) while the register key waits briefly for it.
+//! Yanks into `+`/`*` come back as `FromNeovim::ClipboardCopy` and become
+//! data sources.
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use calloop::channel::{Channel, Sender, channel};
+use wayland_client::{Connection, Proxy};
+use wayland_protocols_wlr::data_control::v1::client::{
+    zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+    zwlr_data_control_offer_v1::ZwlrDataControlOfferV1,
+};
+
+use crate::State;
+use crate::backend::Backend;
+use crate::config::PublishCommits;
+use crate::neovim::ClipboardSelection;
+
+/// Text MIME types in order of preference
+const TEXT_MIME_TYPES: &[&str] = &[
+    "text/plain;charset=utf-8",
+    "UTF8_STRING",
+    "text/plain",
+    "TEXT",
+    "STRING",
+];
+
+/// The only type offered for published commits (behavior.publish_commits)
+const COMMIT_MIME_TYPE: &str = "text/x-jacin-commit";
+
+/// Selections larger than this are truncated (Neovim registers aren't meant for bulk data)
+const MAX_SELECTION_BYTES: u64 = 4 * 1024 * 1024;
+
+/// MIME types announced by an offer (user data of `ZwlrDataControlOfferV1`)
+pub type OfferMimeTypes = Mutex<Vec<String>>;
+
+/// Longest a register paste waits for a selection still being read
+const PASTE_READ_TIMEOUT: Duration = Duration::from_millis(150);
+
+/// A source that hasn't finished sending by then is abandoned (its pipe closed)
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Selection text read from the compositor
+type ReceivedSelection = (ClipboardSelection, String);
+
+/// Selection reads shared with the reader threads
+#[derive(Default)]
+struct Reads {
+    state: Mutex<ReadState>,
+    finished: Condvar,
+}
+
+#[derive(Default)]
+struct ReadState {
+    in_flight: usize,
+    received: Vec<ReceivedSelection>,
+}
+
+impl Reads {
+    fn start(&self) {
+        self.state.lock().unwrap().in_flight += 1;
+    }
+
+    /// A reader thread is done (`None` = the read failed)
+    fn finish(&self, received: Option<ReceivedSelection>) {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight -= 1;
+        state.received.extend(received);
+        self.finished.notify_all();
+    }
+
+    /// Selections read so far, after waiting up to `timeout` for reads in flight
+    fn take(&self, timeout: Duration) -> Vec<ReceivedSelection> {
+        let state = self.state.lock().unwrap();
+        let (mut state, _) = self
+            .finished
+            .wait_timeout_while(state, timeout, |state| state.in_flight > 0)
+            .unwrap();
+        std::mem::take(&mut state.received)
+    }
+}
+
+/// Data-control manager and the sync state of both selections
+pub struct Clipboard {
+    pub manager: ZwlrDataControlManagerV1,
+    /// Flushed after a receive request, so the source starts sending while a
+    /// register paste blocks the event loop waiting for it
+    conn: Connection,
+    /// Wakes the event loop when a read finishes
+    received_tx: Sender<()>,
+    reads: Arc<Reads>,
+    /// Offer currently holding each selection (destroyed when replaced)
+    offers: [Option<ZwlrDataControlOfferV1>; 2],
+    /// The offer in `offers` was already read (or is being read)
+    offers_read: [bool; 2],
+    /// Last text synced in either direction per selection. Setting the selection
+    /// makes the compositor echo it back as a new offer; this breaks the loop.
+    synced: [Option<String>; 2],
+}
+
+impl Clipboard {
+    /// Create the bridge; the returned channel signals finished reads
+    /// (collected with `State::on_selections_read`).
+    pub fn new(manager: ZwlrDataControlManagerV1, conn: Connection) -> (Self, Channel<()>) {
+        let (tx, rx) = channel();
+        let clipboard = Self {
+            manager,
+            conn,
+            received_tx: tx,
+            reads: Arc::default(),
+            offers: [None, None],
+            offers_read: [false, false],
+            synced: [None, None],
+        };
+        (clipboard, rx)
+    }
+
+    /// Whether the primary selection is available (manager version 2)
+    pub fn supports_primary(&self) -> bool {
+        self.manager.version() >= 2
+    }
+
+    /// Send the known selections to a freshly spawned backend
+    pub fn replay(&self, backend: &dyn Backend) {
+        for selection in [ClipboardSelection::Clipboard, ClipboardSelection::Primary] {
+            if let Some(ref text) = self.synced[slot(selection)] {
+                backend.set_clipboard(selection, text.clone());
+            }
+        }
+    }
+
+    /// Text last synced for `selection`
+    #[cfg(test)]
+    pub(crate) fn synced(&self, selection: ClipboardSelection) -> Option<&str> {
+        self.synced[slot(selection)].as_deref()
+    }
+
+    /// Record `text` as synced. Returns false if it already was (nothing to do).
+    fn mark_synced(&mut self, selection: ClipboardSelection, text: &str) -> bool {
+        let synced = &mut self.synced[slot(selection)];
+        if synced.as_deref() == Some(text) {
+            return false;
+        }
+        *synced = Some(text.to_string());
+        true
+    }
+}
+
+fn slot(selection: ClipboardSelection) -> usize {
+    match selection {
+        ClipboardSelection::Clipboard => 0,
+        ClipboardSelection::Primary => 1,
+    }
+}
+
+/// Most preferred text MIME type among those offered
+fn pick_text_mime(offered: &[String]) -> Option<&'static str> {
+    TEXT_MIME_TYPES
+        .iter()
+        .copied()
+        .find(|mime| offered.iter().any(|o| o == mime))
+}
+
+fn pipe() -> std::io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    // SAFETY: fds has room for the two descriptors pipe2 writes
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    // SAFETY: pipe2 succeeded, so both descriptors are open and owned by us
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// Read a selection from `fd` until the source closes it, giving up after
+/// `timeout`. Anything past `MAX_SELECTION_BYTES` is cut off.
+fn read_selection(fd: OwnedFd, timeout: Duration) -> std::io::Result<Vec<u8>> {
+    let deadline = Instant::now() + timeout;
+    let mut file = File::from(fd);
+    let mut bytes = Vec::new();
+    let mut chunk = [0; 8192];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "source did not finish sending",
+            ));
+        }
+        let mut pollfd = libc::pollfd {
+            fd: file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis = remaining.as_millis().clamp(1, i32::MAX as u128) as i32;
+        // SAFETY: pollfd is a valid, initialized pollfd for the duration of the call
+        if unsafe { libc::poll(&mut pollfd, 1, millis) } < 0 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        if pollfd.revents == 0 {
+            continue;
+        }
+        let n = match file.read(&mut chunk) {
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let room = MAX_SELECTION_BYTES as usize - bytes.len();
+        bytes.extend_from_slice(&chunk[..n.min(room)]);
+        if n == 0 || bytes.len() as u64 >= MAX_SELECTION_BYTES {
+            return Ok(bytes);
+        }
+    }
+}
+
+/// Write `text` to a data-control `send` fd without blocking the event loop
+pub fn send_text(fd: OwnedFd, text: String) {
+    std::thread::spawn(move || {
+        if let Err(e) = File::from(fd).write_all(text.as_bytes()) {
+            log::debug!("[CLIPBOARD] Write to receiver failed: {}", e);
+        }
+    });
+}
+
+impl State {
+    /// A new offer holds `selection` (None = cleared). It is kept unread until
+    /// a register paste asks for it (see `sync_clipboard_for_paste`).
+    pub(crate) fn on_selection_offer(
+        &mut self,
+        selection: ClipboardSelection,
+        offer: Option<ZwlrDataControlOfferV1>,
+    ) {
+        let Some(ref mut clipboard) = self.clipboard else {
+            return;
+        };
+        clipboard.offers_read[slot(selection)] = false;
+        if let Some(old) = std::mem::replace(&mut clipboard.offers[slot(selection)], offer) {
+            old.destroy();
+        }
+        if clipboard.offers[slot(selection)].is_none() {
+            log::debug!("[CLIPBOARD] {:?} selection cleared", selection);
+        }
+    }
+
+    /// Start reading the offer holding `selection` on a helper thread, unless
+    /// it was already read. The text is handed to Neovim when complete.
+    fn read_selection_offer(&mut self, selection: ClipboardSelection) {
+        let Some(ref mut clipboard) = self.clipboard else {
+            return;
+        };
+        let Some(ref offer) = clipboard.offers[slot(selection)] else {
+            return;
+        };
+        if std::mem::replace(&mut clipboard.offers_read[slot(selection)], true) {
+            return;
+        }
+        let mime = offer
+            .data::<OfferMimeTypes>()
+            .and_then(|mimes| pick_text_mime(&mimes.lock().unwrap()));
+        let Some(mime) = mime else {
+            log::debug!("[CLIPBOARD] {:?} selection has no text type", selection);
+            return;
+        };
+        let (read_fd, write_fd) = match pipe() {
+            Ok(fds) => fds,
+            Err(e) => {
+                log::warn!("[CLIPBOARD] pipe failed: {}", e);
+                return;
+            }
+        };
+        offer.receive(mime.to_string(), write_fd.as_fd());
+        // Our write end must close so the reader sees EOF once the source is done
+        drop(write_fd);
+        // The event loop only flushes before it polls again, after the paste gave up
+        if let Err(e) = clipboard.conn.flush() {
+            log::warn!("[CLIPBOARD] Failed to flush receive request: {}", e);
+        }
+
+        let tx = clipboard.received_tx.clone();
+        let reads = clipboard.reads.clone();
+        reads.start();
+        std::thread::spawn(move || {
+            let received = match read_selection(read_fd, READ_TIMEOUT) {
+                Ok(bytes) => Some((selection, String::from_utf8_lossy(&bytes).into_owned())),
+                Err(e) => {
+                    log::warn!(
+                        "[CLIPBOARD] Failed to read {:?} selection: {}",
+                        selection,
+                        e
+                    );
+                    None
+                }
+            };
+            reads.finish(received);
+            let _ = tx.send(());
+        });
+    }
+
+    /// Reader threads finished: hand their selections to Neovim
+    pub(crate) fn on_selections_read(&mut self) {
+        self.collect_selections(Duration::ZERO);
+    }
+
+    /// `<C-r>` or `"` is waiting for the `+`/`*` register name: read the
+    /// selection (if it changed), which must reach Neovim before the key does.
+    /// Nothing is read in privacy mode or while locked; the register then
+    /// keeps the text last synced.
+    pub(crate) fn sync_clipboard_for_paste(&mut self, selection: ClipboardSelection) {
+        // Bound only for publishing commits: Neovim has no provider to feed
+        if !self.config.behavior.clipboard_sync
+            || !self.ime.is_enabled()
+            || self.session_locked
+            || self.privacy
+        {
+            return;
+        }
+        self.read_selection_offer(selection);
+        self.collect_selections(PASTE_READ_TIMEOUT);
+    }
+
+    fn collect_selections(&mut self, timeout: Duration) {
+        let Some(ref clipboard) = self.clipboard else {
+            return;
+        };
+        for (selection, text) in clipboard.reads.take(timeout) {
+            self.on_selection_received(selection, text);
+        }
+    }
+
+    /// Selection text read from the compositor: store it in Neovim's provider cache
+    fn on_selection_received(&mut self, selection: ClipboardSelection, text: String) {
+        let Some(ref mut clipboard) = self.clipboard else {
+            return;
+        };
+        if !clipboard.mark_synced(selection, &text) {
+            return;
+        }
+        log::debug!(
+            "[CLIPBOARD] {:?} selection -> Neovim ({} bytes)",
+            selection,
+            text.len()
+        );
+        // Keep the standby current too, so promotion doesn't lose the clipboard
+        if let Some(ref standby) = self.backend_standby {
+            standby.set_clipboard(selection, text.clone());
+        }
+        if let Some(ref backend) = self.backend {
+            backend.set_clipboard(selection, text);
+        }
+    }
+
+    /// Neovim yanked into `+`/`*`: offer the text as the compositor selection
+    pub(crate) fn set_clipboard_selection(&mut self, selection: ClipboardSelection, text: String) {
+        let Some(ref mut clipboard) = self.clipboard else {
+            return;
+        };
+        let Some(ref device) = self.wayland.data_device else {
+            log::debug!("[CLIPBOARD] No data device for seat {}", self.current_seat);
+            return;
+        };
+        if selection == ClipboardSelection::Primary && !clipboard.supports_primary() {
+            log::debug!("[CLIPBOARD] Primary selection not supported by compositor");
+            return;
+        }
+        clipboard.mark_synced(selection, &text);
+        log::debug!(
+            "[CLIPBOARD] Neovim -> {:?} selection ({} bytes)",
+            selection,
+            text.len()
+        );
+
+        let source = clipboard.manager.create_data_source(&self.wayland.qh, text);
+        for mime in TEXT_MIME_TYPES {
+            source.offer(mime.to_string());
+        }
+        match selection {
+            ClipboardSelection::Clipboard => device.set_selection(Some(&source)),
+            ClipboardSelection::Primary => device.set_primary_selection(Some(&source)),
+        }
+    }
+
+    /// Text was committed: offer it as `text/x-jacin-commit` on the selection
+    /// chosen by behavior.publish_commits (never in privacy mode)
+    pub(crate) fn publish_commit(&mut self, text: &str) {
+        let selection = match self.config.behavior.publish_commits {
+            PublishCommits::Off => return,
+            PublishCommits::Primary => ClipboardSelection::Primary,
+            PublishCommits::Clipboard => ClipboardSelection::Clipboard,
+        };
+        if self.privacy || text.is_empty() {
+            return;
+        }
+        let (Some(clipboard), Some(device)) = (&self.clipboard, &self.wayland.data_device) else {
+            return;
+        };
+        if selection == ClipboardSelection::Primary && !clipboard.supports_primary() {
+            log::debug!("[CLIPBOARD] Primary selection not supported, commit not published");
+            return;
+        }
+        log::debug!(
+            "[CLIPBOARD] Commit -> {:?} selection ({} bytes)",
+            selection,
+            text.len()
+        );
+
+        let source = clipboard
+            .manager
+            .create_data_source(&self.wayland.qh, text.to_string());
+        source.offer(COMMIT_MIME_TYPE.to_string());
+        match selection {
+            ClipboardSelection::Clipboard => device.set_selection(Some(&source)),
+            ClipboardSelection::Primary => device.set_primary_selection(Some(&source)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefers_utf8_text() {
+        let offered = vec![
+            "text/html".to_string(),
+            "STRING".to_string(),
+            "text/plain;charset=utf-8".to_string(),
+        ];
+        assert_eq!(pick_text_mime(&offered), Some("text/plain;charset=utf-8"));
+        assert_eq!(pick_text_mime(&["TEXT".to_string()]), Some("TEXT"));
+    }
+
+    #[test]
+    fn non_text_offers_ignored() {
+        let offered = vec!["image/png".to_string(), "text/uri-list".to_string()];
+        assert_eq!(pick_text_mime(&offered), None);
+        assert_eq!(pick_text_mime(&[]), None);
+    }
+
+    #[test]
+    fn paste_waits_for_reads_in_flight() {
+        let reads = Arc::new(Reads::default());
+        assert!(reads.take(Duration::ZERO).is_empty());
+
+        reads.start();
+        let reader = reads.clone();
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            reader.finish(Some((ClipboardSelection::Clipboard, "copied".into())));
+        });
+        let received = reads.take(Duration::from_secs(5));
+        assert_eq!(
+            received,
+            vec![(ClipboardSelection::Clipboard, "copied".to_string())]
+        );
+        thread.join().unwrap();
+
+        // A failed read still counts as finished
+        reads.start();
+        reads.finish(None);
+        assert!(reads.take(Duration::from_secs(5)).is_empty());
+    }
+
+    #[test]
+    fn selection_read_is_bounded_in_time() {
+        let (read_fd, write_fd) = pipe().unwrap();
+        let mut writer = File::from(write_fd);
+        writer.write_all(b"copied").unwrap();
+        drop(writer);
+        assert_eq!(
+            read_selection(read_fd, Duration::from_secs(5)).unwrap(),
+            b"copied"
+        );
+
+        // A source that never closes its end is given up on
+        let (read_fd, write_fd) = pipe().unwrap();
+        File::from(write_fd.try_clone().unwrap())
+            .write_all(b"partial")
+            .unwrap();
+        let err = read_selection(read_fd, Duration::from_millis(50)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        drop(write_fd);
+    }
+}
@@ -1,13 +1,29 @@
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, Default, Deserialize)]
 #[serde(default)]
 pub struct Config {
     pub keybinds: Keybinds,
+    /// Keys sent to Neovim as other Vim keys: keysym name, with optional
+    /// `C-`/`A-`/`S-` prefixes → Vim notation (e.g. `Muhenkan = "<C-j>"`).
+    /// Default: empty.
+    pub keymap: HashMap<String, String>,
+    pub japanese_keys: JapaneseKeys,
     pub completion: Completion,
     pub behavior: Behavior,
     pub font: FontConfig,
+    pub theme: ThemeConfig,
+    pub popup: PopupConfig,
+    pub update: UpdateConfig,
+    pub backend: BackendConfig,
+    pub notifications: NotificationsConfig,
+    pub metrics: MetricsConfig,
+    pub animation: AnimationConfig,
+    #[serde(alias = "neovim")]
+    pub nvim: NvimConfig,
+    pub dictionary: DictionaryConfig,
     #[serde(skip)]
     pub clean: bool,
 }
@@ -25,6 +41,354 @@ pub struct FontConfig {
     pub size: Option<f32>,
 }
 
+/// Popup appearance. Unset fields keep the built-in theme.
+/// Colors are hex strings: "#rgb", "#rrggbb" or "#rrggbbaa".
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ThemeConfig {
+    pub background: Option<String>,
+    pub text: Option<String>,
+    pub border: Option<String>,
+    /// Selected candidate row
+    pub selected: Option<String>,
+    /// Preedit cursor
+    pub cursor: Option<String>,
+    /// Visual selection in the preedit
+    pub visual: Option<String>,
+    /// Search matches in the preedit
+    pub search: Option<String>,
+    /// Candidate numbers
+    pub number: Option<String>,
+    /// Keypress row text
+    pub keypress: Option<String>,
+    pub scrollbar: Option<String>,
+    pub scrollbar_thumb: Option<String>,
+    pub mode_insert: Option<String>,
+    pub mode_normal: Option<String>,
+    pub mode_visual: Option<String>,
+    pub mode_operator: Option<String>,
+    pub mode_command: Option<String>,
+    /// REC indicator
+    pub recording: Option<String>,
+    /// Overrides `[font] family`
+    pub font_family: Option<String>,
+    /// Overrides `[font] mono_family`
+    pub mono_font_family: Option<String>,
+    /// Overrides `[font] size`
+    pub font_size: Option<f32>,
+    /// Space between the popup edge and its content, in pixels. Default: 8.
+    pub padding: Option<f32>,
+    /// Popup border width in pixels (0 = no border). Default: 1.
+    pub border_width: Option<f32>,
+    /// Show the reading as furigana above kanji candidates when the
+    /// completion source reports one (two-line candidate rows). Default: false.
+    pub furigana: Option<bool>,
+    /// Colors used over the ones above while the desktop prefers a dark
+    /// (`[theme.dark]`) or light (`[theme.light]`) color scheme, followed
+    /// through the XDG settings portal. Only colors are read. Default: none.
+    pub dark: Option<Box<ThemeConfig>>,
+    pub light: Option<Box<ThemeConfig>>,
+}
+
+impl ThemeConfig {
+    /// Whether a dark or light palette is set (the color scheme matters)
+    pub fn follows_color_scheme(&self) -> bool {
+        self.dark.is_some() || self.light.is_some()
+    }
+}
+
+/// Popup size and placement (`[popup]`)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PopupConfig {
+    /// Where the popup goes. Default: "cursor".
+    pub mode: PopupMode,
+    /// Screen corner for `mode = "corner"`. Default: "bottom-right".
+    pub corner: PopupCorner,
+    /// Candidate rows shown at once (1-9, one per label). Default: 9.
+    pub max_candidates: usize,
+    /// Largest popup width in pixels. Default: 580.
+    pub max_width: u32,
+    /// Largest popup height in pixels. Default: 450.
+    pub max_height: u32,
+    /// Pixels the popup is moved right of where the compositor puts it
+    /// ("cursor"), or away from the screen edge ("corner"). Default: 0.
+    pub offset_x: u32,
+    /// Pixels the popup is moved down ("cursor"), or away from the screen
+    /// edge ("corner"). Default: 0.
+    pub offset_y: u32,
+    /// Per-output overrides (`[[popup.output]]`), first match wins.
+    /// Default: none.
+    #[serde(rename = "output")]
+    pub outputs: Vec<PopupOutputConfig>,
+}
+
+impl Default for PopupConfig {
+    fn default() -> Self {
+        Self {
+            mode: PopupMode::default(),
+            corner: PopupCorner::default(),
+            max_candidates: 9,
+            max_width: 580,
+            max_height: 450,
+            offset_x: 0,
+            offset_y: 0,
+            outputs: Vec::new(),
+        }
+    }
+}
+
+/// Popup size on one output (`[[popup.output]]`), e.g. larger text on a TV
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct PopupOutputConfig {
+    /// Output connector name ("HDMI-A-1"), or part of its description
+    /// ("LG TV"). Required.
+    pub name: String,
+    /// Font size on this output. Default: the theme's.
+    pub font_size: Option<f32>,
+    /// Largest popup width on this output. Default: `popup.max_width`.
+    pub max_width: Option<u32>,
+    /// Largest popup height on this output. Default: `popup.max_height`.
+    pub max_height: Option<u32>,
+}
+
+/// Popup placement (popup.mode)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PopupMode {
+    /// Input popup surface, placed at the text cursor by the compositor
+    #[default]
+    Cursor,
+    /// Overlay anchored to a screen corner (zwlr_layer_shell_v1), for
+    /// compositors that misplace input popups
+    Corner,
+}
+
+/// Screen corner of the popup (popup.corner)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PopupCorner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+}
+
+/// Conversion backend (`[backend]`)
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct BackendConfig {
+    /// Engine doing the conversion. Default: "neovim".
+    pub kind: BackendKind,
+}
+
+/// Conversion engine (backend.kind)
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    /// Embedded Neovim (`[nvim]` configures it)
+    #[default]
+    Neovim,
+    /// Built-in romaji to hiragana conversion only, no external process
+    Romaji,
+    /// Scripted fake Neovim for tests (`--backend fake:<script.json>` only)
+    #[serde(skip)]
+    Fake(PathBuf),
+}
+
+impl BackendKind {
+    /// Parse `--backend`: `neovim`, `romaji` or `fake:<script.json>`
+    pub fn from_arg(arg: &str) -> Result<Self, String> {
+        match arg {
+            "neovim" => Ok(Self::Neovim),
+            "romaji" => Ok(Self::Romaji),
+            _ => match arg.strip_prefix("fake:") {
+                Some(path) if !path.is_empty() => Ok(Self::Fake(PathBuf::from(path))),
+                _ => Err(format!(
+                    "--backend: expected neovim, romaji or fake:<script.json>, got {arg:?}"
+                )),
+            },
+        }
+    }
+}
+
+/// Desktop notifications (`[notifications]`), sent through
+/// org.freedesktop.Notifications. All off by default.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct NotificationsConfig {
+    /// Notify when the IME is enabled or disabled. Default: false.
+    pub ime_state: bool,
+    /// Notify when the conversion backend exits or is respawned. Default: false.
+    pub backend: bool,
+    /// Notify when fetched dictionaries are handed to the backend. Default: false.
+    pub dictionary: bool,
+}
+
+/// Latency metrics (`[metrics]`). All off by default.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// Collect latency histograms of the key round trip, snapshot query and
+    /// popup render, logged as a summary on exit. Default: false.
+    pub enabled: bool,
+    /// Show the last key's round trip to the backend at the right end of the
+    /// popup's mode row. Default: false.
+    pub indicator: bool,
+}
+
+/// Popup animations (`[animation]`): durations in milliseconds, 0 turns one
+/// off
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AnimationConfig {
+    /// Fade-in of the popup when it appears. Default: 80.
+    pub fade_in: u64,
+    /// Fade-out of the popup when it disappears (IME off, text committed).
+    /// Default: 120.
+    pub fade_out: u64,
+    /// Slide-in of the candidate list when candidates appear. Default: 100.
+    pub slide: u64,
+}
+
+impl Default for AnimationConfig {
+    fn default() -> Self {
+        Self {
+            fade_in: 80,
+            fade_out: 120,
+            slide: 100,
+        }
+    }
+}
+
+/// Embedded Neovim (`[nvim]`, also accepted as `[neovim]`)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct NvimConfig {
+    /// If true, keep a second pre-initialized Neovim running so it can be
+    /// swapped in instantly after `:q` or a crash (costs one idle process).
+    /// Default: false.
+    pub standby: bool,
+    /// Neovim executable: a name looked up on PATH or a path (`~/` expands).
+    /// Default: "nvim".
+    pub binary: String,
+    /// Extra arguments after `--embed --headless` (e.g. `["-u", "~/ime.lua"]`;
+    /// a leading `~/` expands). Default: none.
+    pub args: Vec<String>,
+    /// Extra environment variables (e.g. `NVIM_APPNAME`). Default: none.
+    pub env: HashMap<String, String>,
+    /// Seconds between health checks of the running Neovim, made while the
+    /// IME is enabled or Neovim is handling a request (never for an idle or
+    /// standby Neovim). One that stops answering (e.g. a plugin deadlock) is
+    /// killed and replaced, and the text being composed is restored. 0
+    /// disables the watchdog.
+    /// Default: 5.
+    pub watchdog_interval: u64,
+    /// Seconds Neovim has to answer a health check before it counts as hung.
+    /// Default: 3.
+    pub watchdog_timeout: u64,
+}
+
+impl Default for NvimConfig {
+    fn default() -> Self {
+        Self {
+            standby: false,
+            binary: "nvim".to_string(),
+            args: Vec::new(),
+            env: HashMap::new(),
+            watchdog_interval: 5,
+            watchdog_timeout: 3,
+        }
+    }
+}
+
+impl NvimConfig {
+    /// `binary` with `~/` expanded
+    pub fn binary(&self) -> PathBuf {
+        expand_home(&self.binary, std::env::var("HOME").ok().as_deref())
+    }
+
+    /// `args` with a leading `~/` expanded
+    pub fn args(&self) -> Vec<PathBuf> {
+        let home = std::env::var("HOME").ok();
+        self.args
+            .iter()
+            .map(|arg| expand_home(arg, home.as_deref()))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct UpdateConfig {
+    /// If true, check `url` for a newer release at startup
+    /// (reported by `jacinctl version`).
+    /// Default: false.
+    pub check: bool,
+    /// Release endpoint: GitHub "latest release" JSON or a plain version string.
+    pub url: String,
+}
+
+/// SKK dictionaries downloaded by `jacin dict fetch`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DictionaryConfig {
+    /// Download directory (`~/` expands to the home directory).
+    /// Default: `$XDG_DATA_HOME/jacin/dict` (`~/.local/share/jacin/dict`).
+    pub dir: Option<String>,
+    /// Dictionaries to fetch. Default: SKK-JISYO.L from skk-dev.
+    pub files: Vec<DictionaryFile>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DictionaryFile {
+    /// Download URL; a `.gz` file is decompressed after verification
+    pub url: String,
+    /// Expected SHA-256 of the downloaded file (hex). Unset: the MD5 published
+    /// at `<url>.md5` is checked instead; without one the file is refused
+    /// unless `jacin dict fetch --allow-unverified`.
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+impl Default for DictionaryConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            files: vec![DictionaryFile {
+                url: "https://skk-dev.github.io/dict/SKK-JISYO.L.gz".to_string(),
+                sha256: None,
+            }],
+        }
+    }
+}
+
+impl DictionaryConfig {
+    /// Directory dictionaries are installed into
+    pub fn dir(&self) -> Option<PathBuf> {
+        let home = std::env::var("HOME").ok();
+        match &self.dir {
+            Some(dir) => Some(expand_home(dir, home.as_deref())),
+            None => data_dir_in(
+                std::env::var("XDG_DATA_HOME").ok().as_deref(),
+                home.as_deref(),
+            )
+            .map(|dir| dir.join("dict")),
+        }
+    }
+}
+
+impl Default for UpdateConfig {
+    fn default() -> Self {
+        Self {
+            check: false,
+            url: "https://api.github.com/repos/garypippi/jacin/releases/latest".to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(default)]
 pub struct Behavior {
@@ -35,10 +399,234 @@ pub struct Behavior {
     /// If true, the REC indicator dot blinks while recording a macro.
     /// Default: true.
     pub recording_blink: bool,
+    /// Keys shown in the macro row below the keypress row: the keys typed
+    /// so far while recording, and the register's contents while `@x`
+    /// plays back (0 hides the row).
+    /// Default: 16.
+    pub macro_preview: usize,
+    /// If true, the keypress row shows the undo depth and whether `.` has
+    /// an insert to repeat ("u 3 .") after the mode icons.
+    /// Default: false.
+    pub history_indicator: bool,
     /// If true, `:w` commits preedit text to the application.
     /// Also works with `:wq` and `:x`.
     /// Default: false.
     pub write_to_commit: bool,
+    /// If true, release the keyboard and hide the popup while the session is
+    /// locked (logind), restoring the previous state on unlock.
+    /// Default: true.
+    pub suspend_on_lock: bool,
+    /// If true, mirror Neovim's `+`/`*` registers with the Wayland clipboard
+    /// and primary selection (needs wlr-data-control).
+    /// Default: true.
+    pub clipboard_sync: bool,
+    /// Selection every committed string is also offered on, as
+    /// `text/x-jacin-commit`, for clipboard managers and journaling tools
+    /// (needs wlr-data-control). The selection then holds only the commit.
+    /// Nothing is published in privacy mode.
+    /// Default: "off".
+    pub publish_commits: PublishCommits,
+    /// If true, show backend diagnostics (snapshot push/pull strategy and
+    /// latencies) in an extra popup row.
+    /// Default: false.
+    pub debug_overlay: bool,
+    /// What happens to the Neovim buffer when the text input loses focus.
+    /// Default: "clear".
+    pub deactivate_clear: DeactivateClear,
+    /// Seconds an unfocused buffer is kept before `deactivate_clear = "defer"`
+    /// clears it.
+    /// Default: 5.
+    pub deactivate_clear_delay: u64,
+    /// What happens to the composition when the text input loses focus:
+    /// dropped, committed to the input before the keyboard grab is released,
+    /// or kept (with the Neovim buffer, whatever `deactivate_clear` says) and
+    /// resumed when focus returns to the same app.
+    /// Default: "discard".
+    pub on_focus_loss: OnFocusLoss,
+    /// If true, text being composed when jacin exits normally is saved in the
+    /// state directory (not in privacy mode; readable by the user only) and
+    /// put back into the backend when the IME is first enabled after the next
+    /// start. Off by default: the text may be anything typed, in plain text.
+    /// Default: false.
+    pub save_composition: bool,
+    /// Input purposes (e.g. "email", "url") whose fields bypass the IME like
+    /// password and PIN fields always do: no keyboard grab, preedit or popup.
+    /// Default: [].
+    pub direct_purposes: Vec<String>,
+    /// Seconds a keyboard grab in a focused field may go without any key or
+    /// modifier event before it is presumed stuck (a Deactivate that never
+    /// arrived) and released, unless a composition is in progress. 0 disables
+    /// this check; a grab still held after Deactivate is always released.
+    /// Default: 0.
+    pub stuck_grab_timeout: u64,
+    /// Longest press, in milliseconds, still counted as a tap of
+    /// `keybinds.toggle_tap`.
+    /// Default: 300.
+    pub tap_timeout: u64,
+    /// If true, typing a candidate's label (1-9 by default, see
+    /// `candidate_labels`) accepts that row of the visible page.
+    /// PageUp/PageDown turn pages either way.
+    /// Default: true.
+    pub candidate_number_keys: bool,
+    /// Labels drawn before the visible candidates and typed to accept them.
+    /// Default: "numbers".
+    pub candidate_labels: CandidateLabels,
+    /// If true, the popup accepts pointer input: clicking a candidate accepts
+    /// it and scrolling browses the list.
+    /// Default: false.
+    pub popup_pointer: bool,
+    /// If true, remember whether the IME was last enabled or disabled in each
+    /// application and restore it when a text input there is focused (needs
+    /// wlr-foreign-toplevel-management; stored in the state directory).
+    /// Default: false.
+    pub remember_per_app: bool,
+    /// app_ids (e.g. "steam_app_730") for which the IME is disabled while
+    /// their toplevel is fullscreen and focused, and enabled again when it
+    /// loses either (needs wlr-foreign-toplevel-management).
+    /// Default: [].
+    pub fullscreen_blocklist: Vec<String>,
+    /// If true, the IME turns on whenever a text input gains focus and off
+    /// when it loses it, like an always-on IME. Password/PIN fields and
+    /// `direct_purposes` still bypass it; `remember_per_app` takes precedence.
+    /// Default: false.
+    pub auto_enable_on_activate: bool,
+    /// How keys Neovim doesn't handle (IME off in insert mode, `<C-CR>`
+    /// passthrough) reach the application.
+    /// Default: "keycode".
+    pub passthrough: Passthrough,
+    /// If true, a completion with exactly one candidate is accepted right
+    /// away without showing the popup. If the acceptance is undone, the
+    /// candidate is shown rather than accepted again.
+    /// Default: false.
+    pub auto_confirm_single: bool,
+    /// Punctuation that commits the text typed so far as soon as it is typed
+    /// in insert mode (e.g. ["。", "、"]); what follows starts a new preedit.
+    /// Default: [].
+    pub punctuation_commit: Vec<char>,
+    /// If true, the selected candidate replaces the text it completes in the
+    /// application's preedit, highlighted as the preedit cursor range; the
+    /// typed text comes back when the selection is cancelled.
+    /// Default: false.
+    pub candidate_preview: bool,
+    /// If true, accepted candidates are counted per reading (stored in the
+    /// data directory) and candidate lists show the most-chosen ones first
+    /// (lists of more than 64 candidates keep the backend's order).
+    /// Default: false.
+    pub learn_candidates: bool,
+    /// How often to ask for the input method again while another IME holds
+    /// it, before exiting (`--replace` keeps asking). 0 = exit right away.
+    /// Default: 0.
+    pub unavailable_retries: u32,
+    /// Seconds before the first retry; each further retry waits twice as
+    /// long (up to 30 s).
+    /// Default: 1.
+    pub unavailable_retry_delay: u64,
+    /// If true, Neovim is started when the IME is first enabled instead of
+    /// at launch, so a session that never enables it doesn't pay for it. Keys
+    /// typed while it starts are queued until it is ready.
+    /// Default: false.
+    pub lazy_spawn: bool,
+}
+
+/// Label set of the visible candidate rows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CandidateLabels {
+    /// 1 2 3 4 5 6 7 8 9
+    #[default]
+    Numbers,
+    /// a s d f g h j k l (home row)
+    Asdf,
+    /// あ い う え お か き く け (katakana from a kana layout select them too)
+    Kana,
+}
+
+impl CandidateLabels {
+    const NUMBERS: [&str; 9] = ["1", "2", "3", "4", "5", "6", "7", "8", "9"];
+    const ASDF: [&str; 9] = ["a", "s", "d", "f", "g", "h", "j", "k", "l"];
+    const KANA: [&str; 9] = ["あ", "い", "う", "え", "お", "か", "き", "く", "け"];
+    /// Halfwidth katakana of `KANA`, as typed on a kana layout
+    const HALFWIDTH_KANA: [&str; 9] = ["ｱ", "ｲ", "ｳ", "ｴ", "ｵ", "ｶ", "ｷ", "ｸ", "ｹ"];
+
+    /// Label of each row of a page, top first
+    pub fn labels(self) -> &'static [&'static str; 9] {
+        match self {
+            Self::Numbers => &Self::NUMBERS,
+            Self::Asdf => &Self::ASDF,
+            Self::Kana => &Self::KANA,
+        }
+    }
+
+    /// Row (0-based) whose label `key` types
+    pub fn row(self, key: &str) -> Option<usize> {
+        let position = |labels: &[&str; 9], key: &str| labels.iter().position(|&l| l == key);
+        match self {
+            Self::Kana => {
+                // Fullwidth katakana sits 0x60 above its hiragana
+                let hiragana: String = key
+                    .chars()
+                    .map(|c| match c {
+                        'ァ'..='ヶ' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+                        c => c,
+                    })
+                    .collect();
+                position(&Self::KANA, &hiragana).or_else(|| position(&Self::HALFWIDTH_KANA, key))
+            }
+            labels => position(labels.labels(), key),
+        }
+    }
+}
+
+/// Where committed text is published (behavior.publish_commits)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PublishCommits {
+    #[default]
+    Off,
+    /// The primary selection (needs data-control version 2)
+    Primary,
+    /// The clipboard
+    Clipboard,
+}
+
+/// Passthrough of keys to the application through the virtual keyboard
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Passthrough {
+    /// Replay the raw keycode in the app's layout group
+    #[default]
+    Keycode,
+    /// Type the key's character through a generated keymap, so layouts the
+    /// app doesn't share still produce it (keys without text or with Ctrl/Alt
+    /// still replay the keycode)
+    Text,
+}
+
+/// Composition handling on Deactivate (behavior.on_focus_loss)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnFocusLoss {
+    /// Drop the preedit (`deactivate_clear` decides about the Neovim buffer)
+    #[default]
+    Discard,
+    /// Commit the preedit to the text input losing focus
+    Commit,
+    /// Keep the composition for the next activation in the same app (any
+    /// text input when the focused app is unknown)
+    Keep,
+}
+
+/// Neovim buffer handling on Deactivate (focus leaving the text input)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeactivateClear {
+    /// Clear immediately (`<Esc>ggdG`)
+    #[default]
+    Clear,
+    /// Clear only if focus hasn't returned within `deactivate_clear_delay`
+    Defer,
+    /// Never clear; the buffer is resumed on the next activation
+    Keep,
 }
 
 impl Default for Behavior {
@@ -46,7 +634,34 @@ impl Default for Behavior {
         Self {
             startinsert: true,
             recording_blink: true,
+            macro_preview: 16,
+            history_indicator: false,
             write_to_commit: false,
+            suspend_on_lock: true,
+            clipboard_sync: true,
+            publish_commits: PublishCommits::Off,
+            debug_overlay: false,
+            deactivate_clear: DeactivateClear::Clear,
+            deactivate_clear_delay: 5,
+            on_focus_loss: OnFocusLoss::Discard,
+            save_composition: false,
+            direct_purposes: Vec::new(),
+            stuck_grab_timeout: 0,
+            tap_timeout: 300,
+            candidate_number_keys: true,
+            candidate_labels: CandidateLabels::Numbers,
+            popup_pointer: false,
+            remember_per_app: false,
+            fullscreen_blocklist: Vec::new(),
+            auto_enable_on_activate: false,
+            passthrough: Passthrough::Keycode,
+            auto_confirm_single: false,
+            punctuation_commit: Vec::new(),
+            candidate_preview: false,
+            learn_candidates: false,
+            unavailable_retries: 0,
+            unavailable_retry_delay: 1,
+            lazy_spawn: false,
         }
     }
 }
@@ -69,16 +684,103 @@ impl Default for Completion {
 #[serde(default)]
 pub struct Keybinds {
     pub commit: String,
+    /// Toggle kana-direct input (commit kana per keystroke, no henkan).
+    /// Empty = unbound. Default: "".
+    pub kana_direct: String,
+    /// Toggle privacy mode (keypresses masked in the popup, for screencasts).
+    /// Empty = unbound. Default: "".
+    pub privacy: String,
+    /// Key toggling the IME when tapped alone, as an XKB keysym name (e.g.
+    /// "Alt_R", "Caps_Lock"); held, it stays a modifier. Only seen while the
+    /// IME is on. Empty = unbound. Default: "".
+    pub toggle_tap: String,
+    /// Keys sent straight to the application while the IME is on, named like
+    /// [keymap] entries (e.g. ["C-c", "C-w", "XF86AudioPlay"]).
+    /// Default: [].
+    pub passthrough: Vec<String>,
+    /// Physical → logical key remap in Vim 'langmap' syntax (e.g. "qwerty;jkl;ui"),
+    /// applied before matching the keybinds above.
+    /// Empty = use the embedded Neovim's 'langmap'. Default: "".
+    pub langmap: String,
+    /// While candidates are shown: select the next / previous candidate.
+    /// Otherwise these keys reach the buffer as usual. Empty = unbound.
+    pub candidate_next: String,
+    pub candidate_prev: String,
+    /// While candidates are shown: turn the page. Empty = unbound.
+    pub candidate_page_down: String,
+    pub candidate_page_up: String,
+    /// While candidates are shown: accept the selected candidate.
+    /// Empty = unbound. Default: "".
+    pub candidate_confirm: String,
+    /// While candidates are shown: narrow the list by typing, locally
+    /// (Enter accepts, Esc gives the full list back). Empty = unbound.
+    /// Default: "".
+    pub candidate_filter: String,
 }
 
 impl Default for Keybinds {
     fn default() -> Self {
         Self {
             commit: "<C-CR>".to_string(),
+            kana_direct: String::new(),
+            privacy: String::new(),
+            toggle_tap: String::new(),
+            passthrough: Vec::new(),
+            langmap: String::new(),
+            candidate_next: "<Tab>".to_string(),
+            candidate_prev: "<S-Tab>".to_string(),
+            candidate_page_down: "<PageDown>".to_string(),
+            candidate_page_up: "<PageUp>".to_string(),
+            candidate_confirm: String::new(),
+            candidate_filter: String::new(),
+        }
+    }
+}
+
+/// Actions of the dedicated keys on JIS keyboards. A key also in [keymap]
+/// sends its mapping instead.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct JapaneseKeys {
+    /// 半角/全角 (also separate Zenkaku / Hankaku keys). Default: "toggle".
+    pub zenkaku_hankaku: JapaneseKeyAction,
+    /// 変換. Default: "convert".
+    pub henkan: JapaneseKeyAction,
+    /// 無変換. Default: "commit".
+    pub muhenkan: JapaneseKeyAction,
+    /// カタカナ/ひらがな (also separate Hiragana / Katakana keys).
+    /// Default: "kana".
+    pub hiragana_katakana: JapaneseKeyAction,
+}
+
+impl Default for JapaneseKeys {
+    fn default() -> Self {
+        Self {
+            zenkaku_hankaku: JapaneseKeyAction::Toggle,
+            henkan: JapaneseKeyAction::Convert,
+            muhenkan: JapaneseKeyAction::Commit,
+            hiragana_katakana: JapaneseKeyAction::Kana,
         }
     }
 }
 
+/// What a dedicated Japanese key does while the IME is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JapaneseKeyAction {
+    /// Nothing (the key is dropped)
+    None,
+    /// Turn the IME off, as the toggle command does
+    Toggle,
+    /// Toggle kana-direct input (keybinds.kana_direct)
+    Kana,
+    /// Commit the preedit as it is (keybinds.commit)
+    Commit,
+    /// Convert: sent to the buffer as `<Space>`, the henkan key of
+    /// skkeleton and most Neovim IMEs
+    Convert,
+}
+
 impl Config {
     pub fn load() -> Self {
         let Some(path) = Self::config_path() else {
@@ -122,6 +824,48 @@ impl Config {
         }
         None
     }
+
+    /// Directory for state jacin keeps between runs:
+    /// `$XDG_STATE_HOME/jacin`, falling back to `~/.local/state/jacin`
+    pub fn state_dir() -> Option<PathBuf> {
+        state_dir_in(
+            std::env::var("XDG_STATE_HOME").ok().as_deref(),
+            std::env::var("HOME").ok().as_deref(),
+        )
+    }
+
+    /// Directory for data jacin learns: `$XDG_DATA_HOME/jacin`, falling
+    /// back to `~/.local/share/jacin`
+    pub fn data_dir() -> Option<PathBuf> {
+        data_dir_in(
+            std::env::var("XDG_DATA_HOME").ok().as_deref(),
+            std::env::var("HOME").ok().as_deref(),
+        )
+    }
+}
+
+fn state_dir_in(xdg_state_home: Option<&str>, home: Option<&str>) -> Option<PathBuf> {
+    match (xdg_state_home, home) {
+        (Some(xdg), _) if !xdg.is_empty() => Some(PathBuf::from(xdg).join("jacin")),
+        (_, Some(home)) => Some(PathBuf::from(home).join(".local/state/jacin")),
+        _ => None,
+    }
+}
+
+fn data_dir_in(xdg_data_home: Option<&str>, home: Option<&str>) -> Option<PathBuf> {
+    match (xdg_data_home, home) {
+        (Some(xdg), _) if !xdg.is_empty() => Some(PathBuf::from(xdg).join("jacin")),
+        (_, Some(home)) => Some(PathBuf::from(home).join(".local/share/jacin")),
+        _ => None,
+    }
+}
+
+/// `~/...` → `$HOME/...` (left as-is without a home directory)
+fn expand_home(path: &str, home: Option<&str>) -> PathBuf {
+    match (path.strip_prefix("~/"), home) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
 }
 
 #[cfg(test)]
@@ -135,11 +879,378 @@ mod tests {
         assert_eq!(config.completion.adapter, "native");
         assert!(config.behavior.startinsert);
         assert!(config.behavior.recording_blink);
+        assert_eq!(config.behavior.macro_preview, 16);
+        assert!(!config.behavior.history_indicator);
         assert!(!config.behavior.write_to_commit);
         assert!(!config.clean);
         assert!(config.font.family.is_none());
         assert!(config.font.mono_family.is_none());
         assert!(config.font.size.is_none());
+        assert!(!config.update.check);
+        assert_eq!(config.popup.mode, PopupMode::Cursor);
+        assert_eq!(config.popup.max_candidates, 9);
+        assert_eq!(
+            (config.popup.max_width, config.popup.max_height),
+            (580, 450)
+        );
+        assert!(config.popup.outputs.is_empty());
+        assert_eq!(
+            (
+                config.animation.fade_in,
+                config.animation.fade_out,
+                config.animation.slide
+            ),
+            (80, 120, 100)
+        );
+        assert!(config.keybinds.kana_direct.is_empty());
+        assert!(config.keybinds.privacy.is_empty());
+        assert!(config.keybinds.langmap.is_empty());
+        assert_eq!(config.keybinds.candidate_next, "<Tab>");
+        assert_eq!(config.keybinds.candidate_prev, "<S-Tab>");
+        assert_eq!(config.keybinds.candidate_page_down, "<PageDown>");
+        assert_eq!(config.keybinds.candidate_page_up, "<PageUp>");
+        assert!(config.keybinds.candidate_confirm.is_empty());
+        assert!(config.keybinds.candidate_filter.is_empty());
+        assert_eq!(config.backend.kind, BackendKind::Neovim);
+        assert!(!config.notifications.ime_state);
+        assert!(!config.notifications.backend);
+        assert!(!config.notifications.dictionary);
+        assert!(!config.metrics.enabled);
+        assert!(!config.metrics.indicator);
+        assert!(!config.nvim.standby);
+        assert_eq!(config.nvim.binary, "nvim");
+        assert!(config.nvim.args.is_empty());
+        assert!(config.nvim.env.is_empty());
+        assert_eq!(config.nvim.watchdog_interval, 5);
+        assert_eq!(config.nvim.watchdog_timeout, 3);
+        assert!(config.behavior.suspend_on_lock);
+        assert!(config.behavior.clipboard_sync);
+        assert_eq!(config.behavior.publish_commits, PublishCommits::Off);
+        assert!(!config.behavior.debug_overlay);
+        assert_eq!(config.behavior.deactivate_clear, DeactivateClear::Clear);
+        assert_eq!(config.behavior.deactivate_clear_delay, 5);
+        assert_eq!(config.behavior.on_focus_loss, OnFocusLoss::Discard);
+        assert!(!config.behavior.save_composition);
+        assert!(config.behavior.direct_purposes.is_empty());
+        assert_eq!(config.behavior.stuck_grab_timeout, 0);
+        assert_eq!(config.behavior.tap_timeout, 300);
+        assert!(config.keybinds.toggle_tap.is_empty());
+        assert!(config.behavior.candidate_number_keys);
+        assert!(!config.behavior.popup_pointer);
+        assert!(!config.behavior.remember_per_app);
+        assert!(config.behavior.fullscreen_blocklist.is_empty());
+        assert!(!config.behavior.auto_enable_on_activate);
+        assert_eq!(config.behavior.passthrough, Passthrough::Keycode);
+        assert!(!config.behavior.auto_confirm_single);
+        assert!(config.behavior.punctuation_commit.is_empty());
+        assert!(!config.behavior.candidate_preview);
+        assert!(!config.behavior.learn_candidates);
+        assert_eq!(config.behavior.unavailable_retries, 0);
+        assert_eq!(config.behavior.unavailable_retry_delay, 1);
+        assert!(!config.behavior.lazy_spawn);
+        assert!(config.theme.background.is_none());
+        assert!(config.theme.padding.is_none());
+        assert!(config.update.url.starts_with("https://"));
+    }
+
+    #[test]
+    fn nvim_standby_enabled() {
+        let config: Config = toml::from_str(
+            r#"
+            [nvim]
+            standby = true
+            watchdog_interval = 0
+            "#,
+        )
+        .unwrap();
+        assert!(config.nvim.standby);
+        assert_eq!(config.nvim.watchdog_interval, 0);
+        assert_eq!(config.nvim.watchdog_timeout, 3);
+        assert!(config.behavior.startinsert);
+    }
+
+    #[test]
+    fn romaji_backend() {
+        let config: Config = toml::from_str(
+            r#"
+            [backend]
+            kind = "romaji"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.backend.kind, BackendKind::Romaji);
+        assert!(toml::from_str::<Config>("[backend]\nkind = \"libskk\"").is_err());
+        // The fake is for tests, from the command line only
+        assert!(toml::from_str::<Config>("[backend]\nkind = \"fake\"").is_err());
+    }
+
+    #[test]
+    fn backend_argument() {
+        assert_eq!(BackendKind::from_arg("romaji"), Ok(BackendKind::Romaji));
+        assert_eq!(
+            BackendKind::from_arg("fake:tests/script.json"),
+            Ok(BackendKind::Fake(PathBuf::from("tests/script.json")))
+        );
+        assert!(BackendKind::from_arg("fake:").is_err());
+        assert!(BackendKind::from_arg("libskk").is_err());
+    }
+
+    #[test]
+    fn notifications_opt_in() {
+        let config: Config = toml::from_str(
+            r#"
+            [notifications]
+            ime_state = true
+            dictionary = true
+            "#,
+        )
+        .unwrap();
+        assert!(config.notifications.ime_state);
+        assert!(!config.notifications.backend);
+        assert!(config.notifications.dictionary);
+    }
+
+    #[test]
+    fn metrics_opt_in() {
+        let config: Config = toml::from_str(
+            r#"
+            [metrics]
+            indicator = true
+            "#,
+        )
+        .unwrap();
+        assert!(!config.metrics.enabled);
+        assert!(config.metrics.indicator);
+    }
+
+    #[test]
+    fn popup_corner_mode() {
+        let config: Config = toml::from_str(
+            r#"
+            [popup]
+            mode = "corner"
+            corner = "top-left"
+            max_candidates = 5
+            offset_y = 24
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.popup.mode, PopupMode::Corner);
+        assert_eq!(config.popup.corner, PopupCorner::TopLeft);
+        assert_eq!(config.popup.max_candidates, 5);
+        assert_eq!(config.popup.max_width, 580);
+        assert_eq!((config.popup.offset_x, config.popup.offset_y), (0, 24));
+    }
+
+    #[test]
+    fn popup_per_output_overrides() {
+        let config: Config = toml::from_str(
+            r#"
+            [[popup.output]]
+            name = "HDMI-A-1"
+            font_size = 28.0
+            max_width = 1000
+
+            [[popup.output]]
+            name = "LG TV"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.popup.outputs,
+            vec![
+                PopupOutputConfig {
+                    name: "HDMI-A-1".into(),
+                    font_size: Some(28.0),
+                    max_width: Some(1000),
+                    max_height: None,
+                },
+                PopupOutputConfig {
+                    name: "LG TV".into(),
+                    ..Default::default()
+                },
+            ]
+        );
+        assert_eq!(config.popup.max_width, 580);
+    }
+
+    #[test]
+    fn animations_can_be_turned_off_one_by_one() {
+        let config: Config = toml::from_str("[animation]\nslide = 0\nfade_out = 200\n").unwrap();
+        assert_eq!(config.animation.slide, 0);
+        assert_eq!(config.animation.fade_out, 200);
+        assert_eq!(config.animation.fade_in, 80);
+    }
+
+    #[test]
+    fn custom_nvim_binary_args_and_env() {
+        let config: Config = toml::from_str(
+            r#"
+            [neovim]
+            binary = "/opt/nvim-nightly/bin/nvim"
+            args = ["-u", "~/.config/jacin/init.lua"]
+            env = { NVIM_APPNAME = "nvim-jacin" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.nvim.binary(),
+            PathBuf::from("/opt/nvim-nightly/bin/nvim")
+        );
+        assert_eq!(config.nvim.args, ["-u", "~/.config/jacin/init.lua"]);
+        assert_eq!(config.nvim.env["NVIM_APPNAME"], "nvim-jacin");
+        assert!(!config.nvim.standby);
+    }
+
+    #[test]
+    fn kana_direct_keybind() {
+        let config: Config = toml::from_str(
+            r#"
+            [keybinds]
+            kana_direct = "<C-j>"
+            privacy = "<C-S-p>"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.keybinds.kana_direct, "<C-j>");
+        assert_eq!(config.keybinds.privacy, "<C-S-p>");
+        assert_eq!(config.keybinds.commit, "<C-CR>");
+    }
+
+    #[test]
+    fn langmap_keybind() {
+        let config: Config = toml::from_str(
+            r#"
+            [keybinds]
+            langmap = "jh;hj"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.keybinds.langmap, "jh;hj");
+        assert!(config.keybinds.kana_direct.is_empty());
+    }
+
+    #[test]
+    fn toggle_tap_keybind() {
+        let config: Config = toml::from_str(
+            r#"
+            [keybinds]
+            toggle_tap = "Alt_R"
+
+            [behavior]
+            tap_timeout = 200
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.keybinds.toggle_tap, "Alt_R");
+        assert!(config.keybinds.passthrough.is_empty());
+        assert_eq!(config.behavior.tap_timeout, 200);
+    }
+
+    #[test]
+    fn keymap_table() {
+        let config: Config = toml::from_str(
+            r#"
+            [keymap]
+            Muhenkan = "<C-j>"
+            colon = ";"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.keymap.len(), 2);
+        assert_eq!(config.keymap["Muhenkan"], "<C-j>");
+        assert!(Config::default().keymap.is_empty());
+    }
+
+    #[test]
+    fn passthrough_keys() {
+        let config: Config = toml::from_str(
+            r#"
+            [keybinds]
+            passthrough = ["C-c", "XF86AudioPlay"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.keybinds.passthrough, ["C-c", "XF86AudioPlay"]);
+    }
+
+    #[test]
+    fn japanese_key_actions() {
+        let keys = Config::default().japanese_keys;
+        assert_eq!(keys.zenkaku_hankaku, JapaneseKeyAction::Toggle);
+        assert_eq!(keys.henkan, JapaneseKeyAction::Convert);
+        assert_eq!(keys.muhenkan, JapaneseKeyAction::Commit);
+        assert_eq!(keys.hiragana_katakana, JapaneseKeyAction::Kana);
+
+        let config: Config = toml::from_str(
+            r#"
+            [japanese_keys]
+            muhenkan = "toggle"
+            henkan = "none"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.japanese_keys.muhenkan, JapaneseKeyAction::Toggle);
+        assert_eq!(config.japanese_keys.henkan, JapaneseKeyAction::None);
+        assert_eq!(
+            config.japanese_keys.zenkaku_hankaku,
+            JapaneseKeyAction::Toggle
+        );
+
+        let config: Result<Config, _> = toml::from_str("[japanese_keys]\nhenkan = \"on\"\n");
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn deactivate_clear_strategy() {
+        let config: Config = toml::from_str(
+            r#"
+            [behavior]
+            deactivate_clear = "defer"
+            deactivate_clear_delay = 10
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.behavior.deactivate_clear, DeactivateClear::Defer);
+        assert_eq!(config.behavior.deactivate_clear_delay, 10);
+
+        let config: Result<Config, _> = toml::from_str("[behavior]\ndeactivate_clear = \"lazy\"\n");
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn on_focus_loss_policy() {
+        let config: Config = toml::from_str("[behavior]\non_focus_loss = \"commit\"\n").unwrap();
+        assert_eq!(config.behavior.on_focus_loss, OnFocusLoss::Commit);
+        let config: Config = toml::from_str("[behavior]\non_focus_loss = \"keep\"\n").unwrap();
+        assert_eq!(config.behavior.on_focus_loss, OnFocusLoss::Keep);
+        assert!(toml::from_str::<Config>("[behavior]\non_focus_loss = \"save\"\n").is_err());
+    }
+
+    #[test]
+    fn direct_purposes_list() {
+        let config: Config = toml::from_str(
+            r#"
+            [behavior]
+            direct_purposes = ["email", "url"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.behavior.direct_purposes, vec!["email", "url"]);
+    }
+
+    #[test]
+    fn update_check_enabled_with_custom_url() {
+        let config: Config = toml::from_str(
+            r#"
+            [update]
+            check = true
+            url = "https://example.com/jacin/latest"
+            "#,
+        )
+        .unwrap();
+        assert!(config.update.check);
+        assert_eq!(config.update.url, "https://example.com/jacin/latest");
     }
 
     #[test]
@@ -186,14 +1297,37 @@ mod tests {
             r#"
             [behavior]
             startinsert = true
+            auto_enable_on_activate = true
+            candidate_preview = true
+            learn_candidates = true
+            unavailable_retries = 5
+            lazy_spawn = true
             "#,
         )
         .unwrap();
         assert!(config.behavior.startinsert);
+        assert!(config.behavior.lazy_spawn);
+        assert!(config.behavior.auto_enable_on_activate);
+        assert!(config.behavior.candidate_preview);
+        assert!(config.behavior.learn_candidates);
+        assert_eq!(config.behavior.unavailable_retries, 5);
         assert!(config.behavior.recording_blink); // default true even when not specified
         assert_eq!(config.keybinds.commit, "<C-CR>");
     }
 
+    #[test]
+    fn punctuation_commit_chars() {
+        let config: Config = toml::from_str(
+            r#"
+            [behavior]
+            punctuation_commit = ["。", "、"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.behavior.punctuation_commit, ['。', '、']);
+        assert!(toml::from_str::<Config>("[behavior]\npunctuation_commit = [\"。、\"]").is_err());
+    }
+
     #[test]
     fn startinsert_disabled() {
         let config: Config = toml::from_str(
@@ -220,6 +1354,101 @@ mod tests {
         assert!(config.behavior.recording_blink); // default preserved
     }
 
+    #[test]
+    fn theme_section() {
+        let config: Config = toml::from_str(
+            r##"
+            [theme]
+            background = "#1e1e2e"
+            mode_insert = "#a6e3a1"
+            font_size = 18.0
+            border_width = 2
+            "##,
+        )
+        .unwrap();
+        assert_eq!(config.theme.background.as_deref(), Some("#1e1e2e"));
+        assert_eq!(config.theme.mode_insert.as_deref(), Some("#a6e3a1"));
+        assert_eq!(config.theme.font_size, Some(18.0));
+        assert_eq!(config.theme.border_width, Some(2.0));
+        assert!(config.theme.text.is_none());
+    }
+
+    #[test]
+    fn clipboard_sync_disabled() {
+        let config: Config = toml::from_str(
+            r#"
+            [behavior]
+            clipboard_sync = false
+            "#,
+        )
+        .unwrap();
+        assert!(!config.behavior.clipboard_sync);
+        assert!(config.behavior.suspend_on_lock); // default preserved
+    }
+
+    #[test]
+    fn history_indicator_enabled() {
+        let config: Config = toml::from_str(
+            r#"
+            [behavior]
+            history_indicator = true
+            "#,
+        )
+        .unwrap();
+        assert!(config.behavior.history_indicator);
+        assert!(config.behavior.recording_blink);
+    }
+
+    #[test]
+    fn publish_commits_selection() {
+        let config: Config = toml::from_str(
+            r#"
+            [behavior]
+            publish_commits = "primary"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.behavior.publish_commits, PublishCommits::Primary);
+        assert!(config.behavior.clipboard_sync);
+        assert!(toml::from_str::<Config>("[behavior]\npublish_commits = \"all\"").is_err());
+    }
+
+    #[test]
+    fn candidate_number_keys_disabled() {
+        let config: Config = toml::from_str(
+            r#"
+            [behavior]
+            candidate_number_keys = false
+            "#,
+        )
+        .unwrap();
+        assert!(!config.behavior.candidate_number_keys);
+    }
+
+    #[test]
+    fn candidate_label_sets() {
+        let config: Config = toml::from_str(
+            r#"
+            [behavior]
+            candidate_labels = "kana"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.behavior.candidate_labels, CandidateLabels::Kana);
+        assert_eq!(CandidateLabels::default().labels()[0], "1");
+        assert_eq!(CandidateLabels::Asdf.labels()[8], "l");
+
+        assert_eq!(CandidateLabels::Numbers.row("9"), Some(8));
+        assert_eq!(CandidateLabels::Numbers.row("a"), None);
+        assert_eq!(CandidateLabels::Asdf.row("a"), Some(0));
+        assert_eq!(CandidateLabels::Asdf.row("1"), None);
+        assert_eq!(CandidateLabels::Kana.row("う"), Some(2));
+        assert_eq!(CandidateLabels::Kana.row("ケ"), Some(8));
+        assert_eq!(CandidateLabels::Kana.row("ｴ"), Some(3));
+        assert_eq!(CandidateLabels::Kana.row("a"), None);
+        assert_eq!(CandidateLabels::Kana.row("こ"), None);
+    }
+
     #[test]
     fn recording_blink_disabled() {
         let config: Config = toml::from_str(
@@ -306,6 +1535,62 @@ mod tests {
         assert!(!config.clean);
     }
 
+    #[test]
+    fn state_dir_prefers_xdg_state_home() {
+        assert_eq!(
+            state_dir_in(Some("/xdg/state"), Some("/home/u")),
+            Some(PathBuf::from("/xdg/state/jacin"))
+        );
+        assert_eq!(
+            state_dir_in(Some(""), Some("/home/u")),
+            Some(PathBuf::from("/home/u/.local/state/jacin"))
+        );
+        assert_eq!(state_dir_in(None, None), None);
+    }
+
+    #[test]
+    fn dictionary_dir_defaults_to_data_dir() {
+        assert_eq!(
+            data_dir_in(Some("/xdg/data"), Some("/home/u")),
+            Some(PathBuf::from("/xdg/data/jacin"))
+        );
+        assert_eq!(
+            data_dir_in(None, Some("/home/u")),
+            Some(PathBuf::from("/home/u/.local/share/jacin"))
+        );
+        assert_eq!(
+            expand_home("~/skk", Some("/home/u")),
+            PathBuf::from("/home/u/skk")
+        );
+        assert_eq!(
+            expand_home("/opt/skk", Some("/home/u")),
+            PathBuf::from("/opt/skk")
+        );
+        assert_eq!(expand_home("~/skk", None), PathBuf::from("~/skk"));
+    }
+
+    #[test]
+    fn dictionary_files_from_toml() {
+        let config = Config::default();
+        assert_eq!(config.dictionary.files.len(), 1);
+        assert!(config.dictionary.files[0].url.ends_with("/SKK-JISYO.L.gz"));
+
+        let config: Config = toml::from_str(
+            r#"
+            [dictionary]
+            dir = "~/.skk"
+
+            [[dictionary.files]]
+            url = "https://example.com/SKK-JISYO.jinmei"
+            sha256 = "abc123"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.dictionary.dir.as_deref(), Some("~/.skk"));
+        assert_eq!(config.dictionary.files.len(), 1);
+        assert_eq!(config.dictionary.files[0].sha256.as_deref(), Some("abc123"));
+    }
+
     #[test]
     fn invalid_toml_is_err() {
         let result: Result<Config, _> = toml::from_str("{{invalid}}");
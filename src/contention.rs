@@ -0,0 +1,235 @@
+//! Contention for zwp_input_method_v2 with another input method
+//!
+//! A compositor grants the input method protocol to one client per seat. While
+//! another IME (fcitx5, IBus, a second jacin) holds it, our input method is
+//! sent `unavailable` and stays inert. jacin then asks for a new one after a
+//! backoff (`behavior.unavailable_retries`, unlimited with `--replace`, which
+//! also asks the user through a desktop notification to stop the other IME).
+//! Out of retries, it explains which protocol is contended and exits with
+//! [`EXIT_CONTENDED`].
+
+use std::time::{Duration, Instant};
+
+use crate::notify;
+use crate::state::SeatId;
+
+/// Exit status when another input method keeps the protocol (sysexits
+/// `EX_TEMPFAIL`: trying again later may work)
+pub const EXIT_CONTENDED: i32 = 75;
+
+/// The protocol only one client per seat can hold
+pub const CONTENDED_PROTOCOL: &str = "zwp_input_method_v2";
+
+/// Longest wait between two attempts
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Process names of input methods that bind zwp_input_method_v2
+const KNOWN_IMES: [&str; 6] = [
+    "fcitx5",
+    "ibus-daemon",
+    "ibus-wayland",
+    "kime",
+    "squeekboard",
+    "jacin",
+];
+
+/// Retry schedule for seats whose input method is unavailable
+#[derive(Debug)]
+pub struct Contention {
+    /// Attempts left before giving up (None = keep trying, `--replace`)
+    retries_left: Option<u32>,
+    first_delay: Duration,
+    delay: Duration,
+    /// Seats waiting for a new input method, and when to ask for it
+    pending: Vec<SeatId>,
+    deadline: Option<Instant>,
+    /// Ask the user to stop the other IME (`--replace`; once per contention)
+    replace: bool,
+    notified: bool,
+    gave_up: bool,
+    /// An input method was unavailable since the last activation
+    contended: bool,
+}
+
+impl Contention {
+    pub fn new(retries: u32, first_delay: Duration, replace: bool) -> Self {
+        Self {
+            retries_left: (!replace).then_some(retries),
+            first_delay,
+            delay: first_delay,
+            pending: Vec::new(),
+            deadline: None,
+            replace,
+            notified: false,
+            gave_up: false,
+            contended: false,
+        }
+    }
+
+    /// Schedule a new input method for `seat`. False when out of retries.
+    pub fn unavailable(&mut self, seat: SeatId, now: Instant) -> bool {
+        self.contended = true;
+        if self.retries_left == Some(0) {
+            self.gave_up = true;
+            return false;
+        }
+        if !self.pending.contains(&seat) {
+            self.pending.push(seat);
+        }
+        if self.deadline.is_none() {
+            self.deadline = Some(now + self.delay);
+            self.delay = (self.delay * 2).min(MAX_RETRY_DELAY);
+        }
+        true
+    }
+
+    /// When the next attempt is due (None = nothing pending)
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Seats to ask a new input method for now (one attempt)
+    pub fn take_due(&mut self) -> Vec<SeatId> {
+        if let Some(left) = self.retries_left.as_mut() {
+            *left = left.saturating_sub(1);
+        }
+        self.deadline = None;
+        std::mem::take(&mut self.pending)
+    }
+
+    /// An input method was activated: the other IME is gone. Returns true if
+    /// it had been contended.
+    pub fn available(&mut self) -> bool {
+        self.delay = self.first_delay;
+        self.notified = false;
+        std::mem::take(&mut self.contended)
+    }
+
+    /// Whether to send the `--replace` notification now (once per contention)
+    pub fn should_notify(&mut self) -> bool {
+        self.replace && !std::mem::replace(&mut self.notified, true)
+    }
+
+    /// Whether jacin is exiting because the protocol stayed contended
+    pub fn gave_up(&self) -> bool {
+        self.gave_up
+    }
+}
+
+/// Other running input methods known to bind zwp_input_method_v2
+pub fn running_imes() -> Vec<String> {
+    let own_pid = std::process::id().to_string();
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let mut found: Vec<String> = entries
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.bytes().all(|b| b.is_ascii_digit()) && name != own_pid
+        })
+        .filter_map(|entry| std::fs::read_to_string(entry.path().join("comm")).ok())
+        .map(|comm| comm.trim_end().to_string())
+        .filter(|comm| KNOWN_IMES.contains(&comm.as_str()))
+        .collect();
+    found.sort();
+    found.dedup();
+    found
+}
+
+/// Message printed before exiting with `EXIT_CONTENDED`
+pub fn explanation(others: &[String]) -> String {
+    let holder = if others.is_empty() {
+        "Another input method".to_string()
+    } else {
+        format!("Another input method ({})", others.join(", "))
+    };
+    format!(
+        "{holder} holds {CONTENDED_PROTOCOL}, which the compositor grants to one client \
+         per seat.\nStop it and start jacin again, or run `jacin --replace` to wait for it \
+         to exit (behavior.unavailable_retries retries a few times on its own)."
+    )
+}
+
+/// Ask the user to stop the other IME (desktop notification, `--replace`)
+pub fn notify_replace(others: &[String]) {
+    let holder = if others.is_empty() {
+        "another input method".to_string()
+    } else {
+        others.join(", ")
+    };
+    notify::show(
+        "jacin is waiting for the input method",
+        format!(
+            "{holder} holds {CONTENDED_PROTOCOL}. Stop it to let jacin take over; \
+             jacin keeps trying until then."
+        ),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retries_back_off_and_run_out() {
+        let now = Instant::now();
+        let mut contention = Contention::new(2, Duration::from_secs(1), false);
+        assert!(contention.unavailable(7, now));
+        assert_eq!(contention.deadline(), Some(now + Duration::from_secs(1)));
+        // A second seat joins the pending attempt
+        assert!(contention.unavailable(8, now));
+        assert_eq!(contention.take_due(), [7, 8]);
+        assert_eq!(contention.deadline(), None);
+
+        assert!(contention.unavailable(7, now));
+        assert_eq!(contention.deadline(), Some(now + Duration::from_secs(2)));
+        contention.take_due();
+        assert!(!contention.unavailable(7, now));
+        assert!(contention.gave_up());
+    }
+
+    #[test]
+    fn activation_resets_the_backoff() {
+        let now = Instant::now();
+        let mut contention = Contention::new(u32::MAX, Duration::from_secs(1), false);
+        for _ in 0..10 {
+            contention.unavailable(1, now);
+            contention.take_due();
+        }
+        assert!(contention.available());
+        assert!(!contention.available());
+        contention.unavailable(1, now);
+        assert_eq!(contention.deadline(), Some(now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn replace_retries_forever_and_notifies_once() {
+        let now = Instant::now();
+        let mut contention = Contention::new(0, Duration::from_secs(1), true);
+        for _ in 0..100 {
+            assert!(contention.unavailable(1, now));
+            contention.take_due();
+        }
+        assert_eq!(
+            contention.deadline(),
+            None,
+            "nothing pending after the last attempt was taken"
+        );
+        contention.unavailable(1, now);
+        assert!(contention.deadline().unwrap() - now <= MAX_RETRY_DELAY);
+        assert!(contention.should_notify());
+        assert!(!contention.should_notify());
+        contention.available();
+        assert!(contention.should_notify());
+    }
+
+    #[test]
+    fn explanation_names_the_protocol_and_holder() {
+        let message = explanation(&["fcitx5".to_string()]);
+        assert!(message.contains("(fcitx5) holds zwp_input_method_v2"));
+        assert!(message.contains("--replace"));
+        assert!(explanation(&[]).starts_with("Another input method holds"));
+    }
+}
@@ -0,0 +1,83 @@
+//! Control socket client side
+//!
+//! Shared between the daemon (socket path) and `jacinctl` (included via `#[path]`),
+//! so this file only depends on std and serde_json.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Socket file name inside the runtime directory
+const SOCKET_NAME: &str = "jacin.sock";
+
+/// How long a client waits for the daemon to answer
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Control socket path: `$XDG_RUNTIME_DIR/jacin.sock`, falling back to
+/// `/tmp/jacin-$UID/jacin.sock` (a directory only the user can enter, created
+/// by the daemon)
+pub fn socket_path() -> PathBuf {
+    socket_path_in(std::env::var("XDG_RUNTIME_DIR").ok().as_deref())
+}
+
+fn socket_path_in(runtime_dir: Option<&str>) -> PathBuf {
+    match runtime_dir {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join(SOCKET_NAME),
+        _ => PathBuf::from(format!("/tmp/jacin-{}", uid())).join(SOCKET_NAME),
+    }
+}
+
+/// Real user id of this process
+pub fn uid() -> u32 {
+    // SAFETY: getuid has no preconditions and cannot fail
+    unsafe { libc::getuid() }
+}
+
+/// Send a single-line command and return the daemon's JSON response
+pub fn request(command: &str) -> std::io::Result<serde_json::Value> {
+    let mut stream = UnixStream::connect(socket_path())?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.write_all(command.as_bytes())?;
+    stream.write_all(b"\n")?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    serde_json::from_str(&line).map_err(std::io::Error::other)
+}
+
+/// Follow the status stream (`subscribe`), calling `on_line` with each JSON
+/// line until the daemon closes the connection
+pub fn subscribe(mut on_line: impl FnMut(&str)) -> std::io::Result<()> {
+    let mut stream = UnixStream::connect(socket_path())?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.write_all(b"subscribe\n")?;
+    for line in BufReader::new(stream).lines() {
+        on_line(&line?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn socket_in_runtime_dir() {
+        assert_eq!(
+            socket_path_in(Some("/run/user/1000")),
+            PathBuf::from("/run/user/1000/jacin.sock")
+        );
+    }
+
+    #[test]
+    fn socket_falls_back_to_tmp() {
+        let path = socket_path_in(None);
+        assert_eq!(
+            path,
+            PathBuf::from(format!("/tmp/jacin-{}/jacin.sock", uid()))
+        );
+        assert_eq!(socket_path_in(Some("")), path);
+    }
+}
@@ -0,0 +1,209 @@
+//! Test hooks for manual QA (`jacinctl dev ...`, only with `jacin --dev`)
+//!
+//! A dev command is a `;`-separated script of synthetic compositor events that
+//! are fed through the same handlers as the real Wayland events, in order and
+//! within one event loop iteration. This reproduces compositor-specific
+//! sequences on any machine, e.g. rapid focus cycling:
+//!
+//! ```text
+//! jacinctl dev 'deactivate; done; activate; done; keymap; key a'
+//! ```
+
+use serde_json::json;
+use wayland_client::WEnum;
+use wayland_client::protocol::wl_keyboard::KeyState;
+use wayland_protocols_misc::zwp_input_method_v2::client::{
+    zwp_input_method_keyboard_grab_v2, zwp_input_method_v2,
+};
+use xkbcommon::xkb;
+
+use crate::State;
+use crate::state::SeatId;
+
+/// Key to inject: an evdev keycode or a keysym name looked up in the keymap
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DevKey {
+    Code(u32),
+    Name(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAction {
+    Press,
+    Release,
+    /// Press followed by release
+    Tap,
+}
+
+/// One synthetic event (or target change) in a dev script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DevStep {
+    /// Send the following events to this seat (default: the current seat)
+    Seat(SeatId),
+    Activate,
+    Deactivate,
+    Done,
+    /// Re-deliver the loaded keymap (the default keymap if none was received)
+    Keymap,
+    Key(DevKey, KeyAction),
+}
+
+/// Step syntax for error messages
+const STEPS: &str =
+    "seat <id>, activate, deactivate, done, keymap, key <code|keysym> [press|release]";
+
+/// Parse a dev script such as `"activate; done; key 30 press"`
+pub fn parse_script(script: &str) -> Result<Vec<DevStep>, String> {
+    let steps = script
+        .split(';')
+        .map(str::trim)
+        .filter(|step| !step.is_empty())
+        .map(parse_step)
+        .collect::<Result<Vec<_>, _>>()?;
+    if steps.is_empty() {
+        return Err(format!("empty dev script (steps: {STEPS})"));
+    }
+    Ok(steps)
+}
+
+fn parse_step(step: &str) -> Result<DevStep, String> {
+    let words: Vec<&str> = step.split_whitespace().collect();
+    match words.as_slice() {
+        ["seat", id] => id
+            .parse()
+            .map(DevStep::Seat)
+            .map_err(|_| format!("invalid seat id: {id}")),
+        ["activate"] => Ok(DevStep::Activate),
+        ["deactivate"] => Ok(DevStep::Deactivate),
+        ["done"] => Ok(DevStep::Done),
+        ["keymap"] => Ok(DevStep::Keymap),
+        ["key", key, action @ ..] => {
+            let action = match action {
+                [] => KeyAction::Tap,
+                ["press"] => KeyAction::Press,
+                ["release"] => KeyAction::Release,
+                _ => return Err(format!("invalid key action in {step:?}")),
+            };
+            let key = match key.parse() {
+                Ok(code) => DevKey::Code(code),
+                Err(_) => DevKey::Name(key.to_string()),
+            };
+            Ok(DevStep::Key(key, action))
+        }
+        _ => Err(format!("unknown dev step {step:?} (steps: {STEPS})")),
+    }
+}
+
+impl State {
+    /// Run a dev script (the part of the command after `dev`)
+    pub(crate) fn handle_dev_command(&mut self, script: &str) -> serde_json::Value {
+        if !self.dev_hooks {
+            return json!({ "error": "dev commands are disabled (start jacin with --dev)" });
+        }
+        let steps = match parse_script(script) {
+            Ok(steps) => steps,
+            Err(e) => return json!({ "error": e }),
+        };
+        let mut seat = self.current_seat;
+        for (i, step) in steps.iter().enumerate() {
+            log::info!("[DEV] seat {}: {:?}", seat, step);
+            if let Err(e) = self.run_dev_step(&mut seat, step) {
+                return json!({ "error": format!("step {}: {}", i + 1, e), "steps": i });
+            }
+        }
+        json!({ "ok": true, "steps": steps.len() })
+    }
+
+    fn run_dev_step(&mut self, seat: &mut SeatId, step: &DevStep) -> Result<(), String> {
+        match step {
+            DevStep::Seat(id) => {
+                if *id != self.current_seat && !self.parked_seats.iter().any(|s| s.id == *id) {
+                    return Err(format!("unknown seat {id}"));
+                }
+                *seat = *id;
+            }
+            DevStep::Activate => {
+                self.on_input_method_event(*seat, zwp_input_method_v2::Event::Activate)
+            }
+            DevStep::Deactivate => {
+                self.on_input_method_event(*seat, zwp_input_method_v2::Event::Deactivate)
+            }
+            DevStep::Done => self.on_input_method_event(*seat, zwp_input_method_v2::Event::Done),
+            DevStep::Keymap => {
+                // Keymaps arrive on the grab, which makes the seat current
+                self.select_seat(*seat);
+                let text = self
+                    .keyboard
+                    .keymap_text()
+                    .ok_or("no keymap loaded and the default keymap failed to compile")?;
+                self.on_keymap(&text);
+            }
+            DevStep::Key(key, action) => {
+                let key = match key {
+                    DevKey::Code(code) => *code,
+                    DevKey::Name(name) => {
+                        let keysym = xkb::keysym_from_name(name, xkb::KEYSYM_NO_FLAGS);
+                        if keysym.raw() == xkb::keysyms::KEY_NoSymbol {
+                            return Err(format!("unknown keysym {name:?}"));
+                        }
+                        self.keyboard
+                            .keycode_for(keysym)
+                            .ok_or_else(|| format!("{name:?} is not in the loaded keymap"))?
+                    }
+                };
+                let states: &[KeyState] = match action {
+                    KeyAction::Press => &[KeyState::Pressed],
+                    KeyAction::Release => &[KeyState::Released],
+                    KeyAction::Tap => &[KeyState::Pressed, KeyState::Released],
+                };
+                for &state in states {
+                    let event = zwp_input_method_keyboard_grab_v2::Event::Key {
+                        serial: 0,
+                        time: 0,
+                        key,
+                        state: WEnum::Value(state),
+                    };
+                    self.on_keyboard_grab_event(*seat, event);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_scripts() {
+        assert_eq!(
+            parse_script("deactivate; done ;activate;done; keymap"),
+            Ok(vec![
+                DevStep::Deactivate,
+                DevStep::Done,
+                DevStep::Activate,
+                DevStep::Done,
+                DevStep::Keymap,
+            ])
+        );
+        assert_eq!(
+            parse_script("seat 12; key 30 press; key Return"),
+            Ok(vec![
+                DevStep::Seat(12),
+                DevStep::Key(DevKey::Code(30), KeyAction::Press),
+                DevStep::Key(DevKey::Name("Return".into()), KeyAction::Tap),
+            ])
+        );
+    }
+
+    #[test]
+    fn rejects_bad_steps() {
+        assert!(parse_script("").is_err());
+        assert!(parse_script(" ; ").is_err());
+        assert!(parse_script("activate; focus").is_err());
+        assert!(parse_script("seat main").is_err());
+        assert!(parse_script("key").is_err());
+        assert!(parse_script("key a hold").is_err());
+    }
+}
@@ -0,0 +1,98 @@
+//! Control socket for external tools (`jacinctl`, `jacin --toggle`)
+//!
+//! The daemon listens on a Unix socket; each connection sends one command line
+//! and receives one JSON line back. `dev` commands inject synthetic compositor
+//! events and are only accepted when jacin runs with `--dev`. `subscribe` and
+//! `provider` connections stay open (status stream, candidate providers).
+
+pub mod client;
+mod dev;
+mod provider;
+mod server;
+mod subscribe;
+pub mod version;
+
+pub use provider::{ProviderContext, ProviderEvent, Providers};
+pub use server::insert_control_source;
+pub use subscribe::{StatusReport, Subscribers};
+
+/// How often `--subscribe` tries to reach a jacin that isn't running
+const SUBSCRIBE_RETRY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Command line flags that control a running instance instead of starting one
+const CLI_COMMANDS: &[(&str, &str)] = &[
+    ("--toggle", "toggle"),
+    ("--enable", "enable"),
+    ("--disable", "disable"),
+    ("--status", "status"),
+    ("--commit", "commit"),
+];
+
+/// Control command for the first `--toggle`-style flag in `args`
+pub fn cli_command<S: AsRef<str>>(args: &[S]) -> Option<&'static str> {
+    args.iter().find_map(|arg| {
+        CLI_COMMANDS
+            .iter()
+            .find(|(flag, _)| *flag == arg.as_ref())
+            .map(|(_, command)| *command)
+    })
+}
+
+/// Send `command` to the running instance and print the resulting status
+/// (the full JSON response with `--json`)
+pub fn run_cli_command(command: &str, json: bool) -> anyhow::Result<()> {
+    let response = client::request(command).map_err(|e| {
+        anyhow::anyhow!(
+            "cannot reach jacin at {}: {}",
+            client::socket_path().display(),
+            e
+        )
+    })?;
+    if let Some(err) = response.get("error").and_then(|e| e.as_str()) {
+        anyhow::bail!("{err}");
+    }
+    if json {
+        println!("{response}");
+    } else if let Some(status) = response.get("status").and_then(|s| s.as_str()) {
+        println!("{status}");
+    }
+    Ok(())
+}
+pub use version::UpdateCheck;
+
+/// Print the status stream of the running instance (`jacin --subscribe`),
+/// one JSON object per line. While no instance runs, a `"stopped"` status is
+/// printed once and the connection retried, so a status bar module keeps going.
+pub fn run_subscribe() -> anyhow::Result<()> {
+    let mut stopped = false;
+    loop {
+        let result = client::subscribe(|line| {
+            stopped = false;
+            println!("{line}");
+        });
+        if let Err(e) = result {
+            log::debug!("[CONTROL] subscribe: {}", e);
+        }
+        if !stopped {
+            println!(
+                "{}",
+                serde_json::json!({ "text": "", "class": "stopped", "status": "stopped" })
+            );
+            stopped = true;
+        }
+        std::thread::sleep(SUBSCRIBE_RETRY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_flags_map_to_commands() {
+        assert_eq!(cli_command(&["--toggle"]), Some("toggle"));
+        assert_eq!(cli_command(&["--json", "--status"]), Some("status"));
+        assert_eq!(cli_command(&["--clean", "--dev"]), None);
+        assert_eq!(cli_command::<&str>(&[]), None);
+    }
+}
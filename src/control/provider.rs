@@ -0,0 +1,234 @@
+//! External candidate providers
+//!
+//! A control connection that sends `provider <name>` is kept open as a
+//! candidate source (a personal name dictionary, a snippet daemon, ...). jacin
+//! writes the composition to it as one JSON line each time it changes:
+//!
+//! ```json
+//! {"preedit": "わたしはたなか", "cursor": 21}
+//! ```
+//!
+//! (`cursor` is a byte offset; both are empty / 0 while nothing is composed)
+//! and the provider answers with candidates for the text before the cursor:
+//!
+//! ```json
+//! {"reading": "たなか", "candidates": ["田中", "多中"], "annotations": ["姓"]}
+//! ```
+//!
+//! The candidates are shown after the backend's, tagged with the provider's
+//! name in the kind column, while `reading` ends the text before the cursor.
+//! Accepting one puts it in place of the reading. An empty list withdraws
+//! them. A reader thread per provider forwards its lines to the event loop
+//! through a calloop channel.
+
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use calloop::channel::{Channel, Sender, channel};
+use serde::{Deserialize, Serialize};
+
+use crate::state::ExternalCandidates;
+
+/// Name shown for a provider that didn't give one
+const DEFAULT_NAME: &str = "ext";
+
+/// How long writing the composition may block on a provider that stopped
+/// reading, before it is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// Composition sent to providers
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ProviderContext {
+    pub preedit: String,
+    /// Byte offset of the cursor in `preedit`
+    pub cursor: usize,
+}
+
+/// A provider's answer
+#[derive(Debug, Deserialize)]
+struct Answer {
+    reading: String,
+    candidates: Vec<String>,
+    #[serde(default)]
+    annotations: Vec<String>,
+}
+
+/// What a provider's reader thread reports
+#[derive(Debug)]
+pub enum ProviderEvent {
+    Candidates(ExternalCandidates),
+    /// The provider closed its connection
+    Closed(u64),
+}
+
+struct Provider {
+    id: u64,
+    stream: UnixStream,
+}
+
+/// Connected providers
+pub struct Providers {
+    streams: Vec<Provider>,
+    next_id: u64,
+    last: ProviderContext,
+    events: Sender<ProviderEvent>,
+}
+
+impl Providers {
+    /// No providers yet; their candidates arrive on the returned channel
+    pub fn new() -> (Self, Channel<ProviderEvent>) {
+        let (events, rx) = channel();
+        let providers = Self {
+            streams: Vec::new(),
+            next_id: 1,
+            last: ProviderContext::default(),
+            events,
+        };
+        (providers, rx)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    /// Take `stream` as provider `name` and send it the composition
+    pub fn add(&mut self, stream: UnixStream, name: &str, context: ProviderContext) {
+        let name = match name.trim() {
+            "" => DEFAULT_NAME,
+            name => name,
+        };
+        let reader = match stream.try_clone() {
+            Ok(reader) => reader,
+            Err(e) => {
+                log::warn!("[PROVIDER] {} failed: {}", name, e);
+                return;
+            }
+        };
+        // The reader thread waits for answers; a provider that stops reading
+        // is dropped rather than stall the loop (the halves share the socket,
+        // so only timeouts tell them apart)
+        let timeouts = reader
+            .set_read_timeout(None)
+            .and_then(|()| stream.set_write_timeout(Some(WRITE_TIMEOUT)));
+        if let Err(e) = timeouts {
+            log::warn!("[PROVIDER] {} failed: {}", name, e);
+            return;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        let events = self.events.clone();
+        let source = name.to_string();
+        let spawned = std::thread::Builder::new()
+            .name(format!("provider-{id}"))
+            .spawn(move || read_answers(reader, id, &source, &events));
+        if let Err(e) = spawned {
+            log::warn!("[PROVIDER] {} failed: {}", name, e);
+            return;
+        }
+        let mut provider = Provider { id, stream };
+        if write_context(&mut provider.stream, &context) {
+            log::info!("[PROVIDER] {} connected", name);
+            self.streams.push(provider);
+            self.last = context;
+        }
+    }
+
+    /// Send `context` to every provider if it changed
+    pub fn publish(&mut self, context: ProviderContext) {
+        if self.last == context {
+            return;
+        }
+        self.streams
+            .retain_mut(|provider| write_context(&mut provider.stream, &context));
+        self.last = context;
+    }
+
+    /// Forget provider `id` (its connection closed)
+    pub fn remove(&mut self, id: u64) {
+        self.streams.retain(|provider| provider.id != id);
+    }
+}
+
+fn write_context(stream: &mut UnixStream, context: &ProviderContext) -> bool {
+    let Ok(line) = serde_json::to_string(context) else {
+        return false;
+    };
+    writeln!(stream, "{line}").is_ok()
+}
+
+/// Reader thread: forward the answers of provider `id` until it disconnects
+fn read_answers(stream: UnixStream, id: u64, source: &str, events: &Sender<ProviderEvent>) {
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        match parse_answer(&line, id, source) {
+            Ok(candidates) => {
+                if events.send(ProviderEvent::Candidates(candidates)).is_err() {
+                    return; // Event loop gone
+                }
+            }
+            Err(e) => log::warn!("[PROVIDER] {}: invalid answer: {}", source, e),
+        }
+    }
+    log::info!("[PROVIDER] {} disconnected", source);
+    let _ = events.send(ProviderEvent::Closed(id));
+}
+
+fn parse_answer(line: &str, id: u64, source: &str) -> serde_json::Result<ExternalCandidates> {
+    let answer: Answer = serde_json::from_str(line)?;
+    Ok(ExternalCandidates {
+        provider: id,
+        source: source.to_string(),
+        reading: answer.reading,
+        candidates: answer.candidates,
+        annotations: answer.annotations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(preedit: &str) -> ProviderContext {
+        ProviderContext {
+            preedit: preedit.to_string(),
+            cursor: preedit.len(),
+        }
+    }
+
+    #[test]
+    fn providers_get_the_composition_once_per_change() {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let (mut providers, _rx) = Providers::new();
+        providers.add(ours, "names", context("た"));
+        providers.publish(context("た"));
+        providers.publish(context("たなか"));
+
+        let mut lines = BufReader::new(theirs).lines();
+        let first: serde_json::Value =
+            serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(first["preedit"], "た");
+        assert_eq!(first["cursor"], 3);
+        let second: serde_json::Value =
+            serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(second["preedit"], "たなか");
+    }
+
+    #[test]
+    fn answers_become_tagged_candidates() {
+        let answer = parse_answer(
+            r#"{"reading": "たなか", "candidates": ["田中", "多中"]}"#,
+            3,
+            "names",
+        )
+        .unwrap();
+        assert_eq!(answer.provider, 3);
+        assert_eq!(answer.source, "names");
+        assert_eq!(answer.reading, "たなか");
+        assert_eq!(answer.candidates, ["田中", "多中"]);
+        assert!(answer.annotations.is_empty());
+        assert!(parse_answer(r#"{"candidates": []}"#, 3, "names").is_err());
+    }
+}
@@ -0,0 +1,332 @@
+//! Control socket server (calloop source)
+
+use std::io::{ErrorKind, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use calloop::generic::Generic;
+use calloop::{Interest, LoopHandle, Mode, PostAction};
+use serde_json::json;
+
+use super::client::{socket_path, uid};
+use super::version::VersionInfo;
+use crate::State;
+use crate::wakeups::Counts;
+use crate::{dictionary, logging, notify, trace};
+
+/// Response write timeout (a client that doesn't read it is given up on)
+const WRITE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Longest command line accepted (`dev` scripts included)
+const MAX_COMMAND_BYTES: usize = 64 * 1024;
+
+/// Bind the control socket and register it with the event loop.
+/// Returns the socket path so it can be removed on exit.
+pub fn insert_control_source(handle: &LoopHandle<'static, State>) -> anyhow::Result<PathBuf> {
+    let path = socket_path();
+    let listener = bind(&path)?;
+    listener.set_nonblocking(true)?;
+
+    let connections = handle.clone();
+    handle
+        .insert_source(
+            Generic::new(listener, Interest::READ, Mode::Level),
+            move |_, listener, _| {
+                loop {
+                    match listener.accept() {
+                        Ok((stream, _)) => match peer_uid(&stream) {
+                            // Commands commit text, stream the preedit and inject keys
+                            Ok(peer) if peer == uid() => insert_connection(&connections, stream),
+                            Ok(peer) => {
+                                log::warn!("[CONTROL] rejected connection from uid {}", peer)
+                            }
+                            Err(e) => log::warn!("[CONTROL] peer credentials unavailable: {}", e),
+                        },
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                        Err(e) => {
+                            log::warn!("[CONTROL] accept failed: {}", e);
+                            break;
+                        }
+                    }
+                }
+                Ok(PostAction::Continue)
+            },
+        )
+        .map_err(|e| anyhow::anyhow!("failed to register control socket: {}", e.error))?;
+
+    log::info!("[CONTROL] Listening on {}", path.display());
+    Ok(path)
+}
+
+/// Register an accepted connection as its own source: its command is handled
+/// once the whole line has arrived, so a client that connects and stays
+/// silent never blocks the loop
+fn insert_connection(handle: &LoopHandle<'static, State>, stream: UnixStream) {
+    if let Err(e) = stream.set_nonblocking(true) {
+        log::warn!("[CONTROL] connection setup failed: {}", e);
+        return;
+    }
+    let mut line = Vec::new();
+    let inserted = handle.insert_source(
+        Generic::new(stream, Interest::READ, Mode::Level),
+        move |_, stream, state| {
+            let mut reader: &UnixStream = stream;
+            let mut chunk = [0; 4096];
+            loop {
+                match reader.read(&mut chunk) {
+                    // EOF: whatever was sent is the command
+                    Ok(0) => break,
+                    Ok(n) => {
+                        line.extend_from_slice(&chunk[..n]);
+                        if line.contains(&b'\n') {
+                            break;
+                        }
+                        if line.len() > MAX_COMMAND_BYTES {
+                            log::warn!("[CONTROL] command too long, closing connection");
+                            return Ok(PostAction::Remove);
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                        return Ok(PostAction::Continue);
+                    }
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    Err(e) => {
+                        log::warn!("[CONTROL] read failed: {}", e);
+                        return Ok(PostAction::Remove);
+                    }
+                }
+            }
+            let end = line.iter().position(|&b| b == b'\n').unwrap_or(line.len());
+            let command = String::from_utf8_lossy(&line[..end]).into_owned();
+            // The source closes its fd on removal; the command keeps a duplicate
+            match reader.try_clone() {
+                Ok(stream) => state.handle_control_connection(stream, &command),
+                Err(e) => log::warn!("[CONTROL] connection setup failed: {}", e),
+            }
+            Ok(PostAction::Remove)
+        },
+    );
+    if let Err(e) = inserted {
+        log::warn!("[CONTROL] failed to register connection: {}", e.error);
+    }
+}
+
+/// Bind, replacing a stale socket file left by a crashed instance. The socket
+/// is readable by the user alone, in a directory no one else can enter.
+fn bind(path: &Path) -> anyhow::Result<UnixListener> {
+    if let Some(dir) = path.parent() {
+        private_dir(dir)?;
+    }
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            anyhow::bail!("another jacin instance is listening on {}", path.display());
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Create `dir` (mode 0700) if missing, and refuse one that isn't a directory
+/// owned by the user and closed to everyone else (e.g. planted in /tmp)
+fn private_dir(dir: &Path) -> anyhow::Result<()> {
+    match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e.into()),
+    }
+    let meta = std::fs::symlink_metadata(dir)?;
+    if !meta.is_dir() || meta.uid() != uid() || meta.mode() & 0o077 != 0 {
+        anyhow::bail!("{} is not a private directory of this user", dir.display());
+    }
+    Ok(())
+}
+
+/// User id of the process at the other end of `stream` (SO_PEERCRED)
+fn peer_uid(stream: &UnixStream) -> std::io::Result<u32> {
+    let mut cred = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // SAFETY: cred and len are valid for writes of the sizes passed
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            (&mut cred as *mut libc::ucred).cast(),
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+impl State {
+    /// A connection sent its command `line`
+    fn handle_control_connection(&mut self, stream: UnixStream, line: &str) {
+        let _ = stream.set_nonblocking(false);
+        let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+
+        // Kept open for the status stream instead of one response
+        if line.trim() == "subscribe" {
+            let report = self.status_report();
+            self.subscribers.add(stream, report);
+            return;
+        }
+        // Kept open as a candidate source
+        if let Some(name) = line.trim().strip_prefix("provider")
+            && (name.is_empty() || name.starts_with(' '))
+        {
+            let context = self.provider_context();
+            self.providers.add(stream, name, context);
+            return;
+        }
+
+        let response = self.handle_control_command(line.trim());
+        let mut stream = &stream;
+        if let Err(e) = writeln!(stream, "{}", response) {
+            log::warn!("[CONTROL] write failed: {}", e);
+        }
+    }
+
+    /// IME status for `status`/`toggle`/... responses
+    fn control_status(&self) -> serde_json::Value {
+        // Privacy mode: the preedit could be on screen in a terminal
+        let preedit = if self.privacy { "" } else { &self.ime.preedit };
+        json!({
+            "status": self.ime.status(),
+            "active": self.wayland.active,
+            "preedit": preedit,
+            "privacy": self.privacy,
+        })
+    }
+
+    /// Commit the preedit as if the commit keybind was pressed
+    fn control_commit(&mut self) -> serde_json::Value {
+        if !self.wayland.active || !self.ime.is_fully_enabled() {
+            return json!({ "error": "no text input with the IME enabled" });
+        }
+        // An empty buffer would pass the (absent) commit key through instead
+        if self.ime.preedit.is_empty() {
+            return json!({ "committed": "", "status": self.ime.status() });
+        }
+        let text = self.ime.preedit.clone();
+        let key = self.config.keybinds.commit.clone();
+        self.send_to_nvim(&key);
+        self.wait_for_nvim_response();
+        json!({ "committed": text, "status": self.ime.status() })
+    }
+
+    /// Hand the installed dictionaries to the backends after `jacin dict fetch`
+    fn control_dict_reload(&self) -> serde_json::Value {
+        let paths: Vec<String> = dictionary::installed(&self.config.dictionary)
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        for backend in self.backend.iter().chain(self.backend_standby.iter()) {
+            backend.set_dictionaries(paths.clone());
+        }
+        if self.backend.is_some() {
+            notify::send(
+                &self.config.notifications,
+                notify::Event::DictionariesLoaded(paths.len()),
+            );
+        }
+        json!({ "dictionaries": paths })
+    }
+
+    /// Execute one control command and build its JSON response
+    pub(crate) fn handle_control_command(&mut self, command: &str) -> serde_json::Value {
+        log::debug!("[CONTROL] command: {:?}", command);
+        match command {
+            "version" => {
+                let latest = self.update_check.as_ref().and_then(|c| c.latest());
+                let info = VersionInfo::new(&self.wayland.bound_protocols, latest);
+                serde_json::to_value(info).unwrap_or_else(|e| json!({ "error": e.to_string() }))
+            }
+            "trace dump" => trace::dump(),
+            "wakeups" => json!(Counts::now()),
+            "status" => self.control_status(),
+            "toggle" => {
+                self.handle_ime_toggle();
+                self.control_status()
+            }
+            "enable" | "disable" => {
+                if self.ime.is_enabled() != (command == "enable") {
+                    self.handle_ime_toggle();
+                }
+                self.control_status()
+            }
+            "commit" => self.control_commit(),
+            "privacy" => {
+                self.set_privacy(!self.privacy);
+                json!({ "privacy": self.privacy })
+            }
+            "privacy on" | "privacy off" => {
+                self.set_privacy(command == "privacy on");
+                json!({ "privacy": self.privacy })
+            }
+            "dict reload" => self.control_dict_reload(),
+            "dev" => self.handle_dev_command(""),
+            "log" => json!({ "filter": logging::filter() }),
+            other => {
+                if let Some(script) = other.strip_prefix("dev ") {
+                    self.handle_dev_command(script)
+                } else if let Some(filter) = other.strip_prefix("log ") {
+                    match logging::set_filter(filter.trim()) {
+                        Ok(level) => {
+                            json!({ "filter": filter.trim(), "max_level": level.as_str() })
+                        }
+                        Err(e) => json!({ "error": e }),
+                    }
+                } else {
+                    json!({ "error": format!("unknown command: {other}") })
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("jacin-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn socket_is_private_to_the_user() {
+        let dir = scratch_dir("control");
+        let path = dir.join("jacin.sock");
+        let listener = bind(&path).unwrap();
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().mode() & 0o777;
+        assert_eq!(mode(&dir), 0o700);
+        assert_eq!(mode(&path), 0o600);
+
+        let _client = UnixStream::connect(&path).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        assert_eq!(peer_uid(&server).unwrap(), uid());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn shared_directory_refused() {
+        let dir = scratch_dir("control-shared");
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(bind(&dir.join("jacin.sock")).is_err());
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,125 @@
+//! Status stream for status bars (`jacin --subscribe`)
+//!
+//! A control connection that sends `subscribe` is kept open: it gets the
+//! current [`StatusReport`] as one JSON line, then another line each time the
+//! report changes. `text` and `class` follow waybar's `return-type = "json"`,
+//! so a custom module can run `jacin --subscribe` as its `exec` directly.
+
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+
+use serde::Serialize;
+
+/// IME state shown by status bar indicators
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StatusReport {
+    /// Indicator text: "あ" (or "ア" for kana-direct) while enabled, "A" otherwise
+    pub text: &'static str,
+    /// CSS class for the indicator: the status
+    pub class: &'static str,
+    /// "disabled", "enabling", "enabled" or "suspended"
+    pub status: &'static str,
+    /// Vim mode ("i", "n", ...; empty while disabled)
+    pub mode: String,
+    /// Register being recorded into (empty when not recording)
+    pub recording: String,
+    /// Characters in the preedit (0 in privacy mode)
+    pub preedit_len: usize,
+    pub kana_direct: bool,
+    pub privacy: bool,
+    /// A text input is focused
+    pub active: bool,
+}
+
+/// Connections following the status
+#[derive(Debug, Default)]
+pub struct Subscribers {
+    streams: Vec<UnixStream>,
+    last: Option<StatusReport>,
+}
+
+impl Subscribers {
+    pub fn is_empty(&self) -> bool {
+        self.streams.is_empty()
+    }
+
+    /// Follow the status on `stream`, starting with `report`
+    pub fn add(&mut self, mut stream: UnixStream, report: StatusReport) {
+        // A subscriber that stops reading is dropped rather than stall the loop
+        if let Err(e) = stream.set_nonblocking(true) {
+            log::warn!("[CONTROL] subscribe failed: {}", e);
+            return;
+        }
+        if write_report(&mut stream, &report) {
+            log::debug!("[CONTROL] Status subscriber added");
+            self.streams.push(stream);
+            self.last = Some(report);
+        }
+    }
+
+    /// Send `report` to every subscriber if it changed
+    pub fn publish(&mut self, report: StatusReport) {
+        if self.last.as_ref() == Some(&report) {
+            return;
+        }
+        self.streams
+            .retain_mut(|stream| write_report(stream, &report));
+        self.last = Some(report);
+    }
+}
+
+fn write_report(stream: &mut UnixStream, report: &StatusReport) -> bool {
+    let Ok(line) = serde_json::to_string(report) else {
+        return false;
+    };
+    writeln!(stream, "{line}").is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+
+    use super::*;
+
+    fn report(status: &'static str) -> StatusReport {
+        StatusReport {
+            text: "A",
+            class: status,
+            status,
+            mode: String::new(),
+            recording: String::new(),
+            preedit_len: 0,
+            kana_direct: false,
+            privacy: false,
+            active: false,
+        }
+    }
+
+    #[test]
+    fn subscribers_get_each_change_once() {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let mut subscribers = Subscribers::default();
+        subscribers.add(ours, report("disabled"));
+        subscribers.publish(report("disabled"));
+        subscribers.publish(report("enabled"));
+
+        let mut lines = BufReader::new(theirs).lines();
+        let first: serde_json::Value =
+            serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(first["status"], "disabled");
+        assert_eq!(first["class"], "disabled");
+        let second: serde_json::Value =
+            serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+        assert_eq!(second["status"], "enabled");
+    }
+
+    #[test]
+    fn closed_subscribers_are_dropped() {
+        let (ours, theirs) = UnixStream::pair().unwrap();
+        let mut subscribers = Subscribers::default();
+        subscribers.add(ours, report("disabled"));
+        drop(theirs);
+        subscribers.publish(report("enabled"));
+        assert!(subscribers.is_empty());
+    }
+}
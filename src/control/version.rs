@@ -0,0 +1,143 @@
+//! Version reporting and the optional release check
+
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+use serde::Serialize;
+
+/// Crate version from Cargo.toml
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Short git hash of the build ("unknown" outside a git checkout)
+pub const GIT_HASH: &str = env!("JACIN_GIT_HASH");
+/// Comma-separated cargo features enabled at build time
+const FEATURES: &str = env!("JACIN_FEATURES");
+
+/// Response to the `version` control command
+#[derive(Debug, Clone, Serialize)]
+pub struct VersionInfo {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    pub features: Vec<&'static str>,
+    /// Wayland interface name → bound version
+    pub protocols: Vec<(&'static str, u32)>,
+    /// Latest released version (None if the check is disabled or hasn't finished)
+    pub latest: Option<String>,
+    /// True if `latest` is newer than this build
+    pub update_available: bool,
+}
+
+impl VersionInfo {
+    pub fn new(protocols: &[(&'static str, u32)], latest: Option<String>) -> Self {
+        let update_available = latest
+            .as_deref()
+            .is_some_and(|latest| is_newer(latest, VERSION));
+        Self {
+            version: VERSION,
+            git_hash: GIT_HASH,
+            features: FEATURES.split(',').filter(|f| !f.is_empty()).collect(),
+            protocols: protocols.to_vec(),
+            latest,
+            update_available,
+        }
+    }
+}
+
+/// Background check for the latest release (result filled in when done)
+#[derive(Clone, Default)]
+pub struct UpdateCheck {
+    latest: Arc<Mutex<Option<String>>>,
+}
+
+impl UpdateCheck {
+    /// Fetch `url` on a background thread (via curl, so no TLS stack is linked in).
+    /// The body may be a GitHub release JSON (`tag_name`) or a plain version string.
+    pub fn spawn(url: String) -> Self {
+        let check = Self::default();
+        let latest = check.latest.clone();
+        std::thread::spawn(move || {
+            let output = Command::new("curl")
+                .args(["-fsSL", "--max-time", "10", &url])
+                .output();
+            match output {
+                Ok(out) if out.status.success() => {
+                    let body = String::from_utf8_lossy(&out.stdout);
+                    match parse_latest(&body) {
+                        Some(v) => {
+                            log::info!("[CONTROL] Latest release: {}", v);
+                            *latest.lock().unwrap() = Some(v);
+                        }
+                        None => log::warn!("[CONTROL] Unrecognized update response from {}", url),
+                    }
+                }
+                Ok(out) => log::warn!("[CONTROL] Update check failed: curl exited {}", out.status),
+                Err(e) => log::warn!("[CONTROL] Update check failed: {}", e),
+            }
+        });
+        check
+    }
+
+    pub fn latest(&self) -> Option<String> {
+        self.latest.lock().unwrap().clone()
+    }
+}
+
+/// Extract a version from a release response body
+fn parse_latest(body: &str) -> Option<String> {
+    let raw = match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(json) => json.get("tag_name")?.as_str()?.to_string(),
+        Err(_) => body.lines().next()?.to_string(),
+    };
+    let version = raw.trim().trim_start_matches('v');
+    (!version.is_empty()).then(|| version.to_string())
+}
+
+/// Compare dotted numeric versions ("0.10.0" > "0.9.1"); non-numeric parts count as 0
+fn is_newer(candidate: &str, current: &str) -> bool {
+    let parse = |v: &str| -> Vec<u64> {
+        v.split(['.', '-', '+'])
+            .take(3)
+            .map(|p| p.parse().unwrap_or(0))
+            .collect()
+    };
+    parse(candidate) > parse(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_github_release_json() {
+        let body = r#"{"tag_name": "v0.2.0", "name": "jacin 0.2.0"}"#;
+        assert_eq!(parse_latest(body), Some("0.2.0".to_string()));
+    }
+
+    #[test]
+    fn parse_plain_text_version() {
+        assert_eq!(parse_latest("0.3.1\n"), Some("0.3.1".to_string()));
+        assert_eq!(parse_latest(""), None);
+        assert_eq!(parse_latest(r#"{"name": "no tag"}"#), None);
+    }
+
+    #[test]
+    fn version_comparison_is_numeric() {
+        assert!(is_newer("0.10.0", "0.9.1"));
+        assert!(is_newer("1.0.0", "0.99.99"));
+        assert!(!is_newer("0.1.0", "0.1.0"));
+        assert!(!is_newer("0.0.9", "0.1.0"));
+    }
+
+    #[test]
+    fn version_info_flags_update() {
+        let info = VersionInfo::new(
+            &[("zwp_input_method_manager_v2", 1)],
+            Some("999.0.0".into()),
+        );
+        assert!(info.update_available);
+        assert_eq!(info.version, VERSION);
+        assert_eq!(info.protocols, vec![("zwp_input_method_manager_v2", 1)]);
+
+        let info = VersionInfo::new(&[], None);
+        assert!(!info.update_available);
+    }
+}
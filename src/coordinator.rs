@@ -1,8 +1,41 @@
 use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use wayland_client::backend::ObjectId;
+use wayland_protocols_misc::zwp_input_method_v2::client::zwp_input_method_v2;
 
 use crate::State;
-use crate::neovim::{self, FromNeovim};
+use crate::appearance::ColorScheme;
+use crate::backend::{self, Backend};
+use crate::config::{OnFocusLoss, Passthrough};
+use crate::contention;
+use crate::control::{ProviderContext, ProviderEvent, StatusReport};
+use crate::dispatch::content_type_from_event;
+use crate::keysym::Langmap;
+use crate::metrics;
+use crate::neovim::{self, FromNeovim, InputMode};
+use crate::notify;
+use crate::replay::TraceEvent;
+use crate::session_lock::LockEvent;
+use crate::state::{SeatId, SurroundingText, WaylandState, grab_is_stale};
 use crate::ui::PopupContent;
+use crate::ui::{Theme, UnifiedPopup};
+
+/// Popup message when enabling falls back to the built-in romaji conversion
+const NVIM_UNAVAILABLE: &str = "Neovim backend unavailable";
+/// Popup message while a backend spawned on enable starts
+const BACKEND_STARTING: &str = "Starting backend…";
+/// Popup message after the composition of the last session was restored
+const COMPOSITION_RESTORED: &str = "Restored from the last session";
+/// Stands in for each keypress entry in privacy mode
+const PRIVACY_MASK: &str = "•";
+
+fn send_surrounding_text(backend: &dyn Backend, surrounding: Option<&SurroundingText>) {
+    let (before, after) = surrounding
+        .map(|s| (s.before().to_string(), s.after().to_string()))
+        .unwrap_or_default();
+    backend.set_surrounding_text(before, after);
+}
 
 impl State {
     /// Common cleanup shared by toggle-off, deactivate, and NvimExited:
@@ -14,59 +47,734 @@ impl State {
         self.ime.clear_candidates();
         self.keypress.clear();
         self.keypress_timer_token = None;
-        self.keypress.recording.clear();
+        self.keypress.clear_macro();
         self.visual_display = None;
         self.hide_popup();
         self.wayland.release_keyboard();
         self.keyboard.reset_modifiers();
     }
 
+    /// User toggle (SIGUSR1, D-Bus): also remembered for the focused app
     pub(crate) fn handle_ime_toggle(&mut self) {
+        let was_enabled = self.ime.is_enabled();
+        self.toggle_ime();
+        // An explicit choice inside a fullscreen game sticks after leaving it
+        self.fullscreen_suspended = false;
+        self.remember_app_ime_state();
+        if self.ime.is_enabled() != was_enabled {
+            let event = if was_enabled {
+                notify::Event::ImeDisabled
+            } else {
+                notify::Event::ImeEnabled
+            };
+            notify::send(&self.config.notifications, event);
+        }
+    }
+
+    pub(crate) fn toggle_ime(&mut self) {
+        if self.session_locked {
+            log::info!("[IME] Toggle ignored while session is locked");
+            return;
+        }
         let was_enabled = self.ime.is_enabled();
         log::info!("[IME] Toggle: was_enabled = {}", was_enabled);
+        self.record(if was_enabled {
+            TraceEvent::Disable
+        } else {
+            TraceEvent::Enable
+        });
 
         if !was_enabled {
-            // Respawn Neovim if it exited (e.g., after :q)
-            if self.nvim.is_none() {
-                match neovim::spawn_neovim(self.config.clone()) {
-                    Ok(handle) => {
-                        log::info!("[IME] Respawned Neovim backend");
-                        self.nvim = Some(handle);
-                    }
-                    Err(e) => {
-                        log::error!("[IME] Failed to respawn Neovim: {}", e);
-                        return;
+            // Start the backend (lazy_spawn), or respawn it if it exited
+            // (e.g., Neovim after :q)
+            if self.backend.is_none() && !self.promote_backend_standby() {
+                let respawn = self.backend_spawned;
+                if self.spawn_backend() {
+                    if respawn {
+                        log::info!("[IME] Respawned backend");
+                        notify::send(&self.config.notifications, notify::Event::BackendRespawned);
+                    } else {
+                        log::info!("[IME] Started backend (lazy_spawn)");
                     }
                 }
             }
+            // Password/PIN (or direct_purposes) field: stay enabled but ungrabbed;
+            // the next normal field grabs on activation
+            if self.wayland.active && self.content_is_direct() {
+                log::info!("[IME] Enabled, but focused field bypasses the IME");
+                self.ime.start_enabling();
+                return;
+            }
             // Enable IME - grab keyboard
             if self.wayland.active && self.wayland.keyboard_grab.is_none() {
                 log::debug!("[IME] Grabbing keyboard");
                 self.wayland.grab_keyboard();
                 self.keyboard.pending_keymap = true;
                 self.ime.start_enabling();
+                self.restore_saved_composition();
             }
         } else {
             // Disable IME - commit preedit text BEFORE releasing keyboard
             // (must match Commit handler order: commit first, then release)
             log::debug!("[IME] Releasing keyboard");
             if !self.ime.preedit.is_empty() {
-                self.wayland.commit_string(&self.ime.preedit);
+                let preedit = self.ime.preedit.clone();
+                self.commit_to_app(&preedit);
             }
             self.reset_ime_state();
+            self.release_parked_grabs();
+            // A composition kept on focus loss goes with the buffer
+            self.ime.take_kept();
             // Clear Neovim buffer (must clear here, not rely on Deactivate —
             // rapid re-enable can happen before Deactivate fires)
-            if let Some(ref nvim) = self.nvim {
-                nvim.send_key("<Esc>ggdG");
+            if let Some(ref backend) = self.backend {
+                backend.send_key("<Esc>ggdG");
             }
+            self.buffer_clear.cancel();
             self.ime.disable();
         }
     }
 
+    /// Whether `check_stuck_grab` could release anything: a grab held while
+    /// deactivated, or one in a focused field with stuck_grab_timeout set.
+    /// An enabled IME idling in a text field doesn't need the timer.
+    pub(crate) fn grab_watchdog_needed(&self) -> bool {
+        self.wayland.keyboard_grab.is_some()
+            && (!self.wayland.active || self.config.behavior.stuck_grab_timeout > 0)
+    }
+
+    /// Dead man's switch: release a keyboard grab that looks orphaned — held while
+    /// deactivated, or idle for behavior.stuck_grab_timeout (opt-in). Otherwise a
+    /// missed Deactivate leaves every keystroke routed to jacin with nowhere to go.
+    pub(crate) fn check_stuck_grab(&mut self) {
+        let timeout = Duration::from_secs(self.config.behavior.stuck_grab_timeout);
+        let Some(idle) = self.wayland.grab_idle(Instant::now()) else {
+            return;
+        };
+        if !grab_is_stale(idle, self.wayland.active, timeout) {
+            return;
+        }
+        // A paused composition is never thrown away on idleness alone
+        if self.wayland.active && !self.ime.preedit.is_empty() {
+            return;
+        }
+        log::warn!(
+            "[IME] Keyboard grab idle for {:.0}s (active={}), presuming it stuck and releasing",
+            idle.as_secs_f64(),
+            self.wayland.active
+        );
+        // Same cleanup as Deactivate; the next Activate (or a toggle) grabs again
+        self.reset_ime_state();
+        self.clear_buffer_on_deactivate();
+        self.set_surrounding_text(None);
+    }
+
+    /// Focus left the text input with the IME enabled: commit, keep or drop
+    /// the composition (behavior.on_focus_loss) and reset for the next
+    /// activation
+    pub(crate) fn leave_text_input(&mut self) {
+        let composing = !self.ime.preedit.is_empty() && !self.session_locked;
+        match self.config.behavior.on_focus_loss {
+            OnFocusLoss::Commit if composing => {
+                log::debug!("[IME] Committing the preedit on focus loss");
+                // Before reset_ime_state releases the grab, as on disable
+                let preedit = self.ime.preedit.clone();
+                self.commit_to_app(&preedit);
+                self.reset_ime_state();
+                self.buffer_clear.cancel();
+                if let Some(ref backend) = self.backend {
+                    backend.send_key("<Esc>ggdG");
+                }
+            }
+            OnFocusLoss::Keep if composing => {
+                let app = self.toplevels.focused_app().map(str::to_string);
+                log::debug!("[IME] Keeping the composition for {:?}", app);
+                self.ime.keep_composition(app);
+                self.reset_ime_state();
+                // The buffer holds the composition
+                self.buffer_clear.cancel();
+            }
+            _ => {
+                self.reset_ime_state();
+                self.clear_buffer_on_deactivate();
+            }
+        }
+    }
+
+    /// Focus returned: resume the composition kept on focus loss if this is
+    /// the app it was typed in, else drop it with the Neovim buffer
+    pub(crate) fn resume_kept_composition(&mut self) {
+        let Some(kept) = self.ime.take_kept() else {
+            return;
+        };
+        let same_app = kept.app.as_deref() == self.toplevels.focused_app();
+        if same_app && self.ime.is_enabled() && !self.content_is_direct() {
+            log::info!("[IME] Resuming the composition kept on focus loss");
+            self.ime
+                .set_preedit(kept.preedit, kept.cursor_begin, kept.cursor_end);
+            self.update_preedit();
+        } else {
+            log::debug!("[IME] Dropping the composition kept for {:?}", kept.app);
+            if let Some(ref backend) = self.backend {
+                backend.send_key("<Esc>ggdG");
+            }
+        }
+    }
+
+    /// Focus left the text input: clear the Neovim buffer now, later or never
+    /// (behavior.deactivate_clear)
+    pub(crate) fn clear_buffer_on_deactivate(&mut self) {
+        if self.buffer_clear.on_deactivate(Instant::now())
+            && let Some(ref backend) = self.backend
+        {
+            backend.send_key("<Esc>ggdG");
+        }
+    }
+
+    /// Deferred-clear timer fired. Returns the next deadline if one is still pending.
+    pub(crate) fn run_deferred_buffer_clear(&mut self) -> Option<Instant> {
+        if self.buffer_clear.expire(Instant::now()) {
+            log::debug!("[IME] Focus did not return, clearing Neovim buffer");
+            if let Some(ref backend) = self.backend {
+                backend.send_key("<Esc>ggdG");
+            }
+        }
+        self.buffer_clear.deadline()
+    }
+
+    /// Suspend on screen lock, resume on unlock.
+    ///
+    /// Locking never commits: composed text must not reach whatever has focus on
+    /// the lock screen. The preedit stays in Neovim and is shown again on unlock.
+    pub(crate) fn handle_session_lock(&mut self, event: LockEvent) {
+        match event {
+            LockEvent::Locked if !self.session_locked => {
+                self.session_locked = true;
+                if !self.ime.suspend() {
+                    log::info!("[LOCK] Session locked (IME disabled, nothing to suspend)");
+                    return;
+                }
+                log::info!("[LOCK] Session locked, suspending IME");
+                if self.wayland.active {
+                    self.wayland.set_preedit("", 0, 0);
+                }
+                self.repeat.cancel();
+                self.repeat_timer_token = None;
+                self.keypress.clear();
+                self.keypress_timer_token = None;
+                self.hide_popup_now();
+                self.wayland.release_keyboard();
+                self.keyboard.reset_modifiers();
+                self.release_parked_grabs();
+            }
+            LockEvent::Unlocked if self.session_locked => {
+                self.session_locked = false;
+                if !self.ime.resume() {
+                    log::info!("[LOCK] Session unlocked");
+                    return;
+                }
+                log::info!("[LOCK] Session unlocked, resuming IME");
+                // Not focused: the next activate re-grabs (IME counts as enabled again)
+                if self.wayland.active
+                    && self.wayland.keyboard_grab.is_none()
+                    && !self.content_is_direct()
+                {
+                    self.wayland.grab_keyboard();
+                    self.keyboard.pending_keymap = true;
+                    self.keyboard.is_reactivation = true;
+                }
+                self.update_preedit();
+            }
+            _ => {}
+        }
+    }
+
+    /// Make `id` the current seat, parking the previous one.
+    ///
+    /// Neovim is shared by all seats, so an in-progress composition is committed
+    /// to the seat it was typed on before switching.
+    pub(crate) fn select_seat(&mut self, id: SeatId) {
+        if id == self.current_seat {
+            return;
+        }
+        let Some(idx) = self.parked_seats.iter().position(|s| s.id == id) else {
+            log::warn!("[SEAT] Event for unknown seat {}", id);
+            return;
+        };
+        log::info!("[SEAT] Switching from seat {} to {}", self.current_seat, id);
+
+        if !self.ime.preedit.is_empty() && self.wayland.active {
+            let preedit = self.ime.preedit.clone();
+            self.commit_to_app(&preedit);
+            if let Some(ref backend) = self.backend {
+                backend.send_key("<Esc>ggdGi");
+            }
+        }
+        self.ime.clear_preedit();
+        self.ime.clear_candidates();
+        self.repeat.cancel();
+        self.repeat_timer_token = None;
+        self.keypress.clear();
+        self.keypress_timer_token = None;
+        self.visual_display = None;
+        self.hide_popup_now();
+
+        let parked = &mut self.parked_seats[idx];
+        std::mem::swap(&mut self.wayland, &mut parked.wayland);
+        std::mem::swap(&mut self.keyboard, &mut parked.keyboard);
+        std::mem::swap(&mut self.popup, &mut parked.popup);
+        parked.id = self.current_seat;
+        self.current_seat = id;
+    }
+
+    /// Handle an input-method event for a parked seat. Returns true if the seat
+    /// was made current and the caller should process the event as usual.
+    pub(crate) fn route_parked_input_method_event(
+        &mut self,
+        id: SeatId,
+        event: &zwp_input_method_v2::Event,
+    ) -> bool {
+        let Some(parked) = self.parked_seats.iter_mut().find(|s| s.id == id) else {
+            log::warn!("[SEAT] Input method event for unknown seat {}", id);
+            return false;
+        };
+        let wl = &mut parked.wayland;
+        match event {
+            zwp_input_method_v2::Event::Activate => {
+                log::info!("[SEAT] IME activated on seat {}", id);
+                wl.pending_activate = true;
+                self.contention.available();
+                false
+            }
+            zwp_input_method_v2::Event::Deactivate => {
+                wl.pending_deactivate = true;
+                false
+            }
+            // Kept for the Done that may make this seat current
+            zwp_input_method_v2::Event::SurroundingText {
+                text,
+                cursor,
+                anchor,
+            } => {
+                wl.pending_surrounding = Some(SurroundingText::new(text.clone(), *cursor, *anchor));
+                false
+            }
+            zwp_input_method_v2::Event::ContentType { hint, purpose } => {
+                wl.pending_content_type = Some(content_type_from_event(*hint, *purpose));
+                false
+            }
+            // Focus moved to a text field on this seat: it becomes current
+            zwp_input_method_v2::Event::Done if wl.pending_activate => {
+                self.select_seat(id);
+                true
+            }
+            zwp_input_method_v2::Event::Done => {
+                wl.serial += 1;
+                if std::mem::take(&mut wl.pending_deactivate) {
+                    wl.active = false;
+                    wl.release_keyboard();
+                    parked.keyboard.reset_modifiers();
+                }
+                false
+            }
+            zwp_input_method_v2::Event::Unavailable => true,
+            _ => false,
+        }
+    }
+
+    /// Another IME holds the input method protocol on `seat`: ask for a new
+    /// input method later, or explain the contention and exit when out of retries
+    pub(crate) fn on_input_method_unavailable(&mut self, seat: SeatId) {
+        let others = contention::running_imes();
+        log::warn!(
+            "[IME] {} unavailable on seat {} (other input methods running: {:?})",
+            contention::CONTENDED_PROTOCOL,
+            seat,
+            others
+        );
+        if self.contention.unavailable(seat, Instant::now()) {
+            if self.contention.should_notify() {
+                contention::notify_replace(&others);
+            }
+            return;
+        }
+        log::error!("[IME] Giving up on {}", contention::CONTENDED_PROTOCOL);
+        eprintln!("{}", contention::explanation(&others));
+        if let Some(signal) = &self.loop_signal {
+            signal.stop();
+        }
+    }
+
+    /// Replace the unavailable input methods with new ones (the retry timer).
+    /// Their popups were made for the old input method and are recreated on demand.
+    pub(crate) fn retry_input_methods(&mut self) {
+        for id in self.contention.take_due() {
+            let Some((_, seat)) = self.seats.iter().find(|(s, _)| *s == id) else {
+                continue;
+            };
+            let (wayland, popup) = if id == self.current_seat {
+                (&mut self.wayland, &mut self.popup)
+            } else if let Some(parked) = self.parked_seats.iter_mut().find(|s| s.id == id) {
+                (&mut parked.wayland, &mut parked.popup)
+            } else {
+                continue;
+            };
+            log::info!(
+                "[IME] Asking for {} again on seat {}",
+                contention::CONTENDED_PROTOCOL,
+                id
+            );
+            wayland.release_keyboard();
+            if let Some(window) = popup.take() {
+                window.destroy();
+            }
+            wayland.input_method.destroy();
+            wayland.input_method =
+                self.input_method_manager
+                    .get_input_method(seat, &wayland.qh, id);
+        }
+    }
+
+    /// Release keyboard grabs held by parked seats (IME off / screen lock)
+    pub(crate) fn release_parked_grabs(&mut self) {
+        for parked in &mut self.parked_seats {
+            if parked.wayland.release_keyboard() {
+                log::debug!("[SEAT] Released grab on seat {}", parked.id);
+            }
+            parked.keyboard.reset_modifiers();
+            if let Some(ref mut popup) = parked.popup {
+                popup.hide();
+            }
+        }
+    }
+
+    /// Protocol state of seat `id`, whether current or parked
+    pub(crate) fn seat_wayland_mut(&mut self, id: SeatId) -> Option<&mut WaylandState> {
+        if id == self.current_seat {
+            return Some(&mut self.wayland);
+        }
+        self.parked_seats
+            .iter_mut()
+            .find(|s| s.id == id)
+            .map(|s| &mut s.wayland)
+    }
+
+    /// Popup owned by `id`, whether that seat is current or parked
+    pub(crate) fn seat_popup_mut(&mut self, id: SeatId) -> Option<&mut UnifiedPopup> {
+        if id == self.current_seat {
+            return self.popup.as_mut();
+        }
+        self.parked_seats
+            .iter_mut()
+            .find(|s| s.id == id)
+            .and_then(|s| s.popup.as_mut())
+    }
+
+    /// The popup of `seat` was shown on `output`: size it for that output
+    pub(crate) fn on_popup_entered_output(&mut self, seat: SeatId, output: ObjectId) {
+        if let Some(popup) = self.seat_popup_mut(seat) {
+            popup.set_output(Some(output));
+        }
+        self.apply_output_theme(seat);
+    }
+
+    /// The compositor reported where the text cursor is relative to the popup
+    /// of `seat`: restack the sections if it flipped the popup
+    pub(crate) fn on_text_input_rectangle(&mut self, seat: SeatId, y: i32, height: i32) {
+        let flipped = self
+            .seat_popup_mut(seat)
+            .is_some_and(|p| p.set_text_input_rectangle(y, height));
+        if flipped && seat == self.current_seat {
+            self.update_popup();
+        }
+    }
+
+    /// An output's properties changed: resize the popups shown on it
+    pub(crate) fn on_output_changed(&mut self, output: &ObjectId) {
+        let seats: Vec<SeatId> = self.seats.iter().map(|(id, _)| *id).collect();
+        for seat in seats {
+            let on_output = self
+                .seat_popup_mut(seat)
+                .is_some_and(|p| p.output() == Some(output));
+            if on_output {
+                self.apply_output_theme(seat);
+            }
+        }
+    }
+
+    /// Give the popup of `seat` the theme of its output (`[[popup.output]]`,
+    /// else the base theme), re-rendering it if that changed anything
+    fn apply_output_theme(&mut self, seat: SeatId) {
+        let Some(output) = self.seat_popup_mut(seat).map(|p| p.output().cloned()) else {
+            return;
+        };
+        let base = self.popup_factory.theme();
+        let info = output.and_then(|id| self.outputs.get(&id));
+        let theme = match info.and_then(|i| i.popup_override(&self.config.popup.outputs)) {
+            Some(o) => base.for_output(o),
+            None => base.clone(),
+        };
+        if let Some(info) = info {
+            log::debug!(
+                "[OUTPUT] Popup of seat {} on {} ({}), font size {}",
+                seat,
+                info.name,
+                info.description,
+                theme.font_size
+            );
+        }
+        let qh = self.wayland.qh.clone();
+        let changed = self
+            .seat_popup_mut(seat)
+            .is_some_and(|p| p.set_theme(theme, &qh));
+        if changed && seat == self.current_seat {
+            self.update_popup();
+        }
+    }
+
+    /// The desktop's color scheme changed: rebuild the theme with its palette
+    /// and re-render every popup
+    pub(crate) fn on_color_scheme(&mut self, scheme: ColorScheme) {
+        if scheme == self.color_scheme {
+            return;
+        }
+        log::info!("[THEME] Switching to the {:?} palette", scheme);
+        self.color_scheme = scheme;
+        self.popup_factory
+            .set_theme(Theme::for_scheme(&self.config, scheme));
+        let seats: Vec<SeatId> = self.seats.iter().map(|(id, _)| *id).collect();
+        for seat in seats {
+            self.apply_output_theme(seat);
+        }
+    }
+
+    /// Spawn the warm standby backend if enabled and not already running.
+    /// Spawning returns immediately; init happens on the backend thread.
+    pub(crate) fn ensure_backend_standby(&mut self) {
+        if !self.config.nvim.standby || self.backend_standby.is_some() {
+            return;
+        }
+        match backend::spawn(&self.config) {
+            Ok(Some(handle)) => {
+                log::info!("[NVIM] Standby backend spawned");
+                if let Some(ref clipboard) = self.clipboard {
+                    clipboard.replay(handle.as_ref());
+                }
+                self.backend_standby = Some(handle);
+            }
+            Ok(None) => {}
+            Err(e) => log::warn!("[NVIM] Failed to spawn standby backend: {}", e),
+        }
+    }
+
+    /// Spawn a new active backend (no standby to promote). Returns false if
+    /// none was started: configured without one, or the spawn failed.
+    fn spawn_backend(&mut self) -> bool {
+        match backend::spawn(&self.config) {
+            Ok(Some(handle)) => {
+                self.backend_spawned = true;
+                // Keys typed until it is ready are queued
+                self.startup_keys.start();
+                self.ime.set_transient_message(BACKEND_STARTING.to_string());
+                if self.ime.input_mode != InputMode::default() {
+                    handle.set_input_mode(self.ime.input_mode);
+                }
+                if let Some(ref clipboard) = self.clipboard {
+                    clipboard.replay(handle.as_ref());
+                }
+                if self.ime.surrounding.is_some() {
+                    send_surrounding_text(handle.as_ref(), self.ime.surrounding.as_ref());
+                }
+                self.backend = Some(handle);
+                self.ensure_backend_standby();
+                true
+            }
+            // Configured without a backend: the engine module converts
+            Ok(None) => false,
+            Err(e) => {
+                // Still enable: the engine module converts romaji without it
+                log::error!("[IME] Failed to respawn Neovim: {} (using fallback)", e);
+                self.ime.set_transient_message(NVIM_UNAVAILABLE.to_string());
+                false
+            }
+        }
+    }
+
+    /// The active backend hung (watchdog) and is being killed. Unlike an exit,
+    /// the IME stays on: a replacement takes over and is given the composition
+    /// back, so the text being typed isn't lost. The killed backend's
+    /// `NvimExited` is then dropped as a replaced backend's.
+    fn on_backend_unresponsive(&mut self) {
+        log::error!("[NVIM] Backend unresponsive, replacing it");
+        let restore = (self.ime.is_fully_enabled() && !self.ime.preedit.is_empty()).then(|| {
+            (
+                self.ime.preedit.clone(),
+                self.ime.cursor_begin,
+                self.keypress.vim_mode.starts_with('i'),
+            )
+        });
+        self.backend = None;
+        let dropped = self.startup_keys.finish().len();
+        if dropped > 0 {
+            log::warn!("[NVIM] Dropped {} key(s) typed during startup", dropped);
+        }
+        neovim::pending_state().clear();
+        self.keypress.clear();
+        self.ime.clear_candidates();
+        if !self.promote_backend_standby() {
+            self.spawn_backend();
+        }
+        if let (Some(backend), Some((text, cursor, insert))) = (&self.backend, restore) {
+            backend.restore(text, cursor, insert);
+        }
+        notify::send(
+            &self.config.notifications,
+            notify::Event::BackendUnresponsive,
+        );
+        self.update_preedit();
+    }
+
+    /// After a restart for a popup protocol error, bring back the text that
+    /// was being composed: the IME is enabled with it as the preedit, and the
+    /// backend is given it to continue from
+    pub(crate) fn resume_restored_preedit(&mut self) {
+        let Some(text) = self.restored_preedit.take() else {
+            return;
+        };
+        log::info!("[IME] Restoring the preedit from before the restart");
+        if !self.ime.is_enabled() {
+            self.toggle_ime();
+        }
+        let end = text.len();
+        if let Some(ref backend) = self.backend {
+            backend.restore(text.clone(), end, true);
+        }
+        self.ime.set_preedit(text, end, end);
+        self.update_preedit();
+    }
+
+    /// First enable after a start: put back the composition the last jacin
+    /// saved on exit (behavior.save_composition)
+    fn restore_saved_composition(&mut self) {
+        let Some(saved) = self.saved_composition.take() else {
+            return;
+        };
+        log::info!("[RESTORE] Restoring the composition from the last session");
+        if let Some(ref backend) = self.backend {
+            backend.restore(saved.preedit.clone(), saved.cursor_begin, saved.insert());
+        }
+        self.ime
+            .set_preedit(saved.preedit, saved.cursor_begin, saved.cursor_end);
+        self.update_preedit();
+        self.ime
+            .set_transient_message(COMPOSITION_RESTORED.to_string());
+    }
+
+    /// Let the active backend's watchdog ping it while the IME is enabled.
+    /// The standby is never told, so it is never pinged.
+    pub(crate) fn watch_backend(&self) {
+        if let Some(ref backend) = self.backend {
+            backend.watch(self.ime.is_enabled());
+        }
+    }
+
+    /// Swap the standby in as the active backend and start a replacement.
+    /// Returns false if there is no standby.
+    fn promote_backend_standby(&mut self) -> bool {
+        let Some(handle) = self.backend_standby.take() else {
+            return false;
+        };
+        log::info!("[NVIM] Promoting standby backend");
+        // Pending state is a process-wide atomic — don't inherit the dead instance's
+        neovim::pending_state().clear();
+        if self.ime.input_mode != InputMode::default() {
+            handle.set_input_mode(self.ime.input_mode);
+        }
+        if self.ime.surrounding.is_some() {
+            send_surrounding_text(handle.as_ref(), self.ime.surrounding.as_ref());
+        }
+        self.backend = Some(handle);
+        self.ensure_backend_standby();
+        true
+    }
+
+    /// Message from backend `id` (its calloop event source). The active
+    /// backend's are handled. The idle standby's startup messages are
+    /// discarded so its bounded channel never fills and blocks it; a standby
+    /// that dies is dropped, not respawned (avoids a crash loop), and a fresh
+    /// one is spawned with the next active backend. Messages from a backend
+    /// already replaced are dropped.
+    pub(crate) fn on_backend_event(&mut self, id: u64, msg: FromNeovim) {
+        if self.backend.as_ref().is_some_and(|n| n.id() == id) {
+            self.handle_nvim_message(msg);
+        } else if self.backend_standby.as_ref().is_some_and(|n| n.id() == id) {
+            if matches!(msg, FromNeovim::NvimExited) {
+                log::warn!("[NVIM] Standby backend exited");
+                self.backend_standby = None;
+            }
+        } else {
+            log::debug!("[NVIM] Dropping message from replaced backend: {:?}", msg);
+        }
+    }
+
+    /// Adopt Neovim's 'langmap' for keybind matching (config langmap takes precedence)
+    fn set_langmap(&mut self, spec: &str) {
+        if !self.config.keybinds.langmap.is_empty() {
+            return;
+        }
+        match Langmap::parse(spec) {
+            Ok(langmap) => {
+                log::info!("[NVIM] Using Neovim 'langmap' for keybinds");
+                self.langmap = langmap;
+            }
+            Err(e) => log::warn!("[NVIM] Ignoring Neovim 'langmap' {:?}: {}", spec, e),
+        }
+    }
+
+    /// Switch between conversion and kana-direct input for this session
+    pub(crate) fn toggle_input_mode(&mut self) {
+        let mode = self.ime.input_mode.toggled();
+        log::info!("[IME] Input mode -> {:?}", mode);
+        self.ime.input_mode = mode;
+        if let Some(ref backend) = self.backend {
+            backend.set_input_mode(mode);
+        }
+        // Leaving conversion mode: don't leave stale preedit in the application
+        if mode == InputMode::KanaDirect {
+            self.update_preedit();
+        }
+        self.ime.set_transient_message(
+            match mode {
+                InputMode::Conversion => "変換",
+                InputMode::KanaDirect => "かな直接",
+            }
+            .to_string(),
+        );
+        self.update_popup();
+    }
+
+    /// Turn privacy mode on or off (keybinds.privacy, `jacinctl privacy`)
+    pub(crate) fn set_privacy(&mut self, on: bool) {
+        if self.privacy == on {
+            return;
+        }
+        log::info!("[IME] Privacy mode -> {}", on);
+        self.privacy = on;
+        self.ime
+            .set_transient_message(if on { "Privacy on" } else { "Privacy off" }.to_string());
+        self.update_popup();
+    }
+
     pub(crate) fn handle_nvim_message(&mut self, msg: FromNeovim) {
+        if self.recorder.is_some() {
+            self.record(TraceEvent::Nvim {
+                message: msg.clone(),
+            });
+        }
         match msg {
             FromNeovim::Ready => {
                 log::info!("[NVIM] Backend ready!");
+                self.on_backend_ready();
             }
             FromNeovim::Preedit(info) => self.on_preedit(info),
             FromNeovim::Commit(text) => self.on_commit(text),
@@ -75,9 +783,25 @@ impl State {
             }
             FromNeovim::Candidates(info) => self.on_candidates(info),
             FromNeovim::VisualRange(selection) => self.on_visual_range(selection),
+            FromNeovim::LastMacro(last_macro) => self.keypress.last_macro = Some(last_macro),
             FromNeovim::PassthroughKey => self.on_passthrough_key(),
+            FromNeovim::ClipboardCopy { selection, text } => {
+                self.set_clipboard_selection(selection, text);
+            }
+            FromNeovim::Langmap(spec) => self.set_langmap(&spec),
+            FromNeovim::SnapshotStats(stats) => {
+                log::debug!("[NVIM] {}", stats.summary());
+                self.snapshot_stats = Some(stats);
+                if self.config.behavior.debug_overlay {
+                    self.update_popup();
+                }
+            }
             FromNeovim::KeyProcessed => {
-                // Acknowledgment only — unblocks wait_for_nvim_response
+                // Unblocks wait_for_nvim_response; a macro that outlasted
+                // the wait has finished playing
+                if self.keypress.finish_playback() {
+                    self.update_popup();
+                }
             }
             FromNeovim::CmdlineShow {
                 content,
@@ -94,6 +818,7 @@ impl State {
             FromNeovim::CmdlineMessage { text, cmdtype } => self.on_cmdline_message(text, cmdtype),
             FromNeovim::ModeChange(mode) => self.on_mode_change(mode),
             FromNeovim::AutoCommit(text) => self.on_auto_commit(text),
+            FromNeovim::Unresponsive => self.on_backend_unresponsive(),
             FromNeovim::NvimExited => self.on_nvim_exited(),
         }
     }
@@ -112,24 +837,46 @@ impl State {
         }
         self.ime
             .set_preedit(info.text, info.cursor_begin, info.cursor_end);
+        self.ime.segment = info.segment;
+        self.ime.highlights = info.highlights;
+        self.ime.refresh_external();
         self.keypress.set_vim_mode(&info.mode);
-        self.keypress.recording = info.recording;
+        self.keypress.set_recording(info.recording);
+        self.keypress.undo_seq = info.undo_seq;
+        self.keypress.repeatable = info.repeatable;
         self.update_preedit();
+        // A just-typed 。/、 commits the sentence so far; Neovim drops it from
+        // the buffer and the AutoCommit that follows hands it to the app
+        if info.mode.starts_with('i')
+            && let Some(text) = self
+                .ime
+                .punctuation_commit(&self.config.behavior.punctuation_commit)
+            && let Some(ref backend) = self.backend
+        {
+            log::debug!("[IME] Punctuation commit: {:?}", text);
+            backend.commit_prefix(text.to_string());
+        }
     }
 
-    fn on_commit(&mut self, text: String) {
+    /// Commit `text` to the application (and publish it, behavior.publish_commits)
+    pub(crate) fn commit_to_app(&mut self, text: &str) {
+        self.wayland.commit_string(text);
+        self.publish_commit(text);
+    }
+
+    pub(crate) fn on_commit(&mut self, text: String) {
         log::debug!("[NVIM] Commit: {:?}", text);
         self.ime.clear_preedit();
         self.ime.clear_candidates();
-        self.wayland.commit_string(&text);
+        self.commit_to_app(&text);
         self.keypress.clear();
         self.keypress_timer_token = None;
         // Consume any pending toggle (e.g., Alt in commit key <A-;> also
         // triggers SIGUSR1 toggle — don't let it re-enable after commit)
         self.toggle_flag.store(false, Ordering::SeqCst);
         // Clear Neovim buffer and stay in insert mode for next input
-        if let Some(ref nvim) = self.nvim {
-            nvim.send_key("<Esc>ggdGi");
+        if let Some(ref backend) = self.backend {
+            backend.send_key("<Esc>ggdGi");
         }
         // Keep IME enabled — show icon-only popup
         self.update_popup();
@@ -141,7 +888,73 @@ impl State {
             before,
             after
         );
-        self.wayland.delete_surrounding(before, after);
+        // Never ask the application to delete more than it reported
+        let (before, after) = match self.ime.surrounding {
+            Some(ref surrounding) => {
+                let clamped = surrounding.clamp_delete(before, after);
+                if clamped != (before, after) {
+                    log::warn!(
+                        "[IME] DeleteSurrounding {:?} exceeds surrounding text, clamped to {:?}",
+                        (before, after),
+                        clamped
+                    );
+                }
+                clamped
+            }
+            None => (before, after),
+        };
+        if before > 0 || after > 0 {
+            self.wayland.delete_surrounding(before, after);
+        }
+    }
+
+    /// IME bypassed for the focused input (password/PIN, behavior.direct_purposes)
+    pub(crate) fn content_is_direct(&self) -> bool {
+        self.ime
+            .content_type
+            .is_direct(&self.config.behavior.direct_purposes)
+    }
+
+    /// The focused input's content type changed: step aside for direct fields,
+    /// take the keyboard back otherwise
+    pub(crate) fn on_content_type_changed(&mut self) {
+        if !self.wayland.active || !self.ime.is_enabled() {
+            return;
+        }
+        let purpose = self.ime.content_type.purpose.name();
+        if self.content_is_direct() {
+            if self.wayland.keyboard_grab.is_none() {
+                return;
+            }
+            log::info!("[IME] {} field, bypassing IME", purpose);
+            // Same field, still focused: drop the preedit instead of committing it
+            self.wayland.set_preedit("", 0, 0);
+            self.reset_ime_state();
+            if let Some(ref backend) = self.backend {
+                backend.send_key("<Esc>ggdG");
+            }
+            self.set_surrounding_text(None);
+        } else if self.wayland.keyboard_grab.is_none() {
+            log::info!("[IME] {} field, resuming IME", purpose);
+            self.wayland.grab_keyboard();
+            self.keyboard.pending_keymap = true;
+            self.keyboard.is_reactivation = true;
+            self.ime.start_enabling();
+        }
+    }
+
+    /// The focused input reported new surrounding text (None = focus left):
+    /// keep it for DeleteSurrounding checks and hand it to Neovim
+    pub(crate) fn set_surrounding_text(&mut self, surrounding: Option<SurroundingText>) {
+        // Text around a password must never reach Neovim
+        let surrounding = surrounding.filter(|_| !self.ime.content_type.is_secret());
+        if self.ime.surrounding == surrounding {
+            return;
+        }
+        if let Some(ref backend) = self.backend {
+            send_surrounding_text(backend.as_ref(), surrounding.as_ref());
+        }
+        self.ime.surrounding = surrounding;
     }
 
     fn on_candidates(&mut self, info: neovim::CandidateInfo) {
@@ -153,12 +966,48 @@ impl State {
         if !self.ime.is_fully_enabled() {
             return;
         }
-        if info.candidates.is_empty() {
+        if self.config.behavior.auto_confirm_single && self.ime.take_auto_confirm(&info) {
+            // Accepted in Neovim like a click; the resulting preedit arrives as
+            // a normal update. Undoing it there shows the candidate next time.
+            log::debug!(
+                "[IME] Auto-confirming single candidate {:?}",
+                info.candidates[0]
+            );
+            if let Some(ref backend) = self.backend {
+                backend.select_candidate(0, true);
+            }
+            self.hide_candidates();
+        } else if info.candidates.is_empty() {
             self.hide_candidates();
         } else {
-            self.ime.set_candidates(info.candidates, info.selected);
+            let select = self
+                .ime
+                .receive_candidates(info, self.candidate_history.as_ref());
+            if let Some(index) = select
+                && let Some(ref backend) = self.backend
+            {
+                log::debug!("[HISTORY] Selecting learned candidate {}", index);
+                backend.select_candidate(index, false);
+            }
+            self.request_candidate_window();
             self.update_popup();
         }
+        // The application's preedit follows the selection, and gets the typed
+        // text back once the candidates are gone
+        if self.config.behavior.candidate_preview {
+            self.update_preedit();
+        }
+    }
+
+    /// Ask the backend for the part of a long candidate list needed next
+    /// (see `ImeState::candidate_window_request`)
+    pub(crate) fn request_candidate_window(&mut self) {
+        if let Some(window) = self.ime.candidate_window_request()
+            && let Some(ref backend) = self.backend
+        {
+            log::debug!("[IME] Requesting candidates {:?}", window);
+            backend.scroll_candidates(window);
+        }
     }
 
     fn on_visual_range(&mut self, selection: Option<neovim::VisualSelection>) {
@@ -170,9 +1019,24 @@ impl State {
         self.update_popup();
     }
 
-    fn on_passthrough_key(&mut self) {
+    pub(crate) fn on_passthrough_key(&mut self) {
         // Send the current key through the virtual keyboard to the focused app
         if let Some(keycode) = self.current_keycode {
+            // Replaying the keycode would send only the sequence's last key
+            if let Some(text) = self.keyboard.composed.clone()
+                && self.wayland.type_text(&text)
+            {
+                return;
+            }
+            if self.config.behavior.passthrough == Passthrough::Text
+                && !self.keyboard.ctrl_pressed
+                && !self.keyboard.alt_pressed
+                && let Some((_, text)) = self.keyboard.get_key_info(keycode)
+                && !text.is_empty()
+                && self.wayland.type_text(&text)
+            {
+                return;
+            }
             self.wayland.send_virtual_key(
                 keycode,
                 self.keyboard.mods_depressed,
@@ -204,13 +1068,15 @@ impl State {
         if !self.ime.is_fully_enabled() {
             return;
         }
-        // Build display text: prompt + content for @-mode, firstc + content for :/?
-        let prefix = if !prompt.is_empty() { &prompt } else { &firstc };
+        // Build display text: firstc + content for :/?. An input() prompt
+        // (@-mode, e.g. dictionary registration) gets a row of its own.
+        let prefix = if !prompt.is_empty() { "" } else { &firstc };
         let prefix_len = prefix.len();
         let display_text = format!("{}{}", prefix, content);
         let cursor_byte = prefix_len + pos;
         self.keypress
             .set_cmdline_text(display_text, cursor_byte, prefix_len, level);
+        self.keypress.set_cmdline_prompt(prompt);
         self.keypress.set_vim_mode("c");
         self.update_popup();
     }
@@ -280,10 +1146,10 @@ impl State {
             if !self.wayland.active {
                 return;
             }
-            self.wayland.commit_string(&text);
+            self.commit_to_app(&text);
             return;
         }
-        self.wayland.commit_string(&text);
+        self.commit_to_app(&text);
         self.ime.clear_preedit();
         self.ime.clear_candidates();
         self.keypress.clear();
@@ -292,13 +1158,47 @@ impl State {
         self.update_popup();
     }
 
+    /// Replay the keys typed while the backend started
+    fn on_backend_ready(&mut self) {
+        let waiting = self.startup_keys.is_waiting();
+        let keys = self.startup_keys.finish();
+        if !waiting {
+            return;
+        }
+        if self.ime.transient_message.as_deref() == Some(BACKEND_STARTING) {
+            self.ime.clear_transient_message();
+            self.update_popup();
+        }
+        if !keys.is_empty() {
+            log::debug!(
+                "[NVIM] Replaying {} key(s) typed during startup",
+                keys.len()
+            );
+        }
+        for key in keys {
+            self.current_keycode = Some(key.keycode);
+            self.send_to_nvim(&key.vim_key);
+            self.wait_for_nvim_response();
+            self.current_keycode = None;
+        }
+    }
+
     fn on_nvim_exited(&mut self) {
         log::info!("[NVIM] Neovim exited, disabling IME");
+        let dropped = self.startup_keys.finish().len();
+        if dropped > 0 {
+            log::warn!("[NVIM] Dropped {} key(s) typed during startup", dropped);
+        }
         // Clear compositor preedit (still active, compositor may show stale text)
         self.wayland.set_preedit("", 0, 0);
         self.reset_ime_state();
         self.ime.disable();
-        self.nvim = None;
+        self.backend = None;
+        let respawning = self.promote_backend_standby();
+        notify::send(
+            &self.config.notifications,
+            notify::Event::BackendExited { respawning },
+        );
     }
 
     pub(crate) fn update_preedit(&mut self) {
@@ -306,8 +1206,23 @@ impl State {
         let cursor_end = self.ime.cursor_end as i32;
         // Don't send preedit to compositor when IME is disabled or deactivated.
         if self.wayland.active && self.ime.is_enabled() {
-            self.wayland
-                .set_preedit(&self.ime.preedit, cursor_begin, cursor_end);
+            // Kana-direct: text reaches the app only by commit; pending romaji
+            // is shown in the popup alone.
+            let preview = self
+                .config
+                .behavior
+                .candidate_preview
+                .then(|| self.ime.candidate_preview())
+                .flatten();
+            let (text, begin, end) = if self.ime.input_mode == InputMode::KanaDirect {
+                ("", 0, 0)
+            } else if let Some((text, begin, end)) = &preview {
+                // The selected candidate, highlighted as the cursor range
+                (text.as_str(), *begin as i32, *end as i32)
+            } else {
+                (self.ime.preedit.as_str(), cursor_begin, cursor_end)
+            };
+            self.wayland.set_preedit(text, begin, end);
             log::debug!(
                 "[PREEDIT] updated: {:?}, cursor: {}..{}",
                 self.ime.preedit,
@@ -326,8 +1241,103 @@ impl State {
         self.update_popup();
     }
 
-    /// Update the unified popup with current state
+    /// IME state for status bar indicators (`jacin --subscribe`)
+    pub(crate) fn status_report(&self) -> StatusReport {
+        let enabled = self.ime.is_enabled();
+        let kana_direct = self.ime.input_mode == InputMode::KanaDirect;
+        StatusReport {
+            text: match (enabled, kana_direct) {
+                (false, _) => "A",
+                (true, false) => "あ",
+                (true, true) => "ア",
+            },
+            class: self.ime.status(),
+            status: self.ime.status(),
+            mode: if enabled {
+                self.keypress.vim_mode.clone()
+            } else {
+                String::new()
+            },
+            recording: self.keypress.recording.clone(),
+            preedit_len: if self.privacy {
+                0
+            } else {
+                self.ime.preedit.chars().count()
+            },
+            kana_direct,
+            privacy: self.privacy,
+            active: self.wayland.active,
+        }
+    }
+
+    /// Composition for external candidate providers: nothing while the IME
+    /// isn't composing for a text input or the session is locked
+    pub(crate) fn provider_context(&self) -> ProviderContext {
+        if !self.wayland.active || !self.ime.is_fully_enabled() || self.session_locked {
+            return ProviderContext::default();
+        }
+        ProviderContext {
+            preedit: self.ime.preedit.clone(),
+            cursor: self.ime.cursor_begin,
+        }
+    }
+
+    /// Send the composition to candidate providers if it changed this turn
+    pub(crate) fn publish_provider_context(&mut self) {
+        if self.providers.is_empty() {
+            return;
+        }
+        let context = self.provider_context();
+        self.providers.publish(context);
+    }
+
+    /// Candidates from an external provider, or its disconnection
+    pub(crate) fn on_provider_event(&mut self, event: ProviderEvent) {
+        match event {
+            ProviderEvent::Candidates(candidates) => {
+                log::debug!(
+                    "[PROVIDER] {}: {:?} for {:?}",
+                    candidates.source,
+                    candidates.candidates,
+                    candidates.reading
+                );
+                if !self.ime.is_fully_enabled() {
+                    return;
+                }
+                self.ime.set_external(candidates);
+                self.request_candidate_window();
+            }
+            ProviderEvent::Closed(id) => {
+                self.providers.remove(id);
+                self.ime.remove_external(id);
+            }
+        }
+        self.update_popup();
+    }
+
+    /// Send the status to `--subscribe` connections if it changed this turn
+    pub(crate) fn publish_status(&mut self) {
+        if self.subscribers.is_empty() {
+            return;
+        }
+        let report = self.status_report();
+        self.subscribers.publish(report);
+    }
+
+    /// Mark the unified popup for a redraw with the current state. The render
+    /// happens once at the end of the event loop turn ([`Self::flush_popup`]).
     pub(crate) fn update_popup(&mut self) {
+        self.popup_dirty = true;
+    }
+
+    /// Render the popup if anything changed since the last render
+    pub(crate) fn flush_popup(&mut self) {
+        if std::mem::take(&mut self.popup_dirty) {
+            self.render_popup();
+        }
+    }
+
+    fn render_popup(&mut self) {
         // IME disabled: skip content generation entirely and ensure popup is hidden.
         // After toggle-off, Neovim sends a burst of push notifications (<Esc>ggdG
         // triggers mode changes and autocmds) — without this guard, each notification
@@ -337,22 +1347,49 @@ impl State {
             return;
         }
         let t = std::time::Instant::now();
+        self.ensure_popup(t);
+        // Filter query in the keypress row, with a cursor like the command line
+        let filter = self.ime.filter_query().map(|query| {
+            if self.privacy {
+                format!("/{}", PRIVACY_MASK.repeat(query.chars().count()))
+            } else {
+                format!("/{query}")
+            }
+        });
         let content = PopupContent {
             preedit: self.ime.preedit.clone(),
             cursor_begin: self.ime.cursor_begin,
             cursor_end: self.ime.cursor_end,
+            preedit_segment: self.ime.segment,
+            preedit_highlights: self.ime.highlights.clone(),
             vim_mode: self.keypress.vim_mode.clone(),
-            keypress_entries: if self.keypress.should_show() {
+            keypress_entries: if let Some(ref filter) = filter {
+                vec![filter.clone()]
+            } else if self.keypress.should_show() {
                 self.keypress
                     .entries()
                     .iter()
-                    .map(|e| e.text.clone())
+                    .map(|e| {
+                        // One dot per entry, so the text length doesn't leak either
+                        if self.privacy {
+                            PRIVACY_MASK.to_string()
+                        } else {
+                            e.text.clone()
+                        }
+                    })
                     .collect()
             } else {
                 Vec::new()
             },
+            keypress_hint: self.keypress.pending_hint(),
             candidates: self.ime.candidates.clone(),
+            readings: self.ime.readings.clone(),
+            annotations: self.ime.annotations.clone(),
+            kinds: self.ime.kinds.clone(),
             selected: self.ime.selected_candidate,
+            candidate_offset: self.ime.candidate_offset,
+            candidate_total: self.ime.candidate_total,
+            candidate_labels: self.config.behavior.candidate_labels,
             transient_message: if self.ime.candidates.is_empty() {
                 self.ime.transient_message.clone()
             } else {
@@ -362,25 +1399,144 @@ impl State {
             ime_enabled: self.ime.is_enabled(),
             recording: self.keypress.recording.clone(),
             rec_blink_on: self.animations.rec_blink.on,
-            cmdline_cursor_pos: self.keypress.cmdline_cursor_byte(),
+            undo_seq: if self.config.behavior.history_indicator {
+                self.keypress.undo_seq
+            } else {
+                0
+            },
+            repeatable: self.config.behavior.history_indicator && self.keypress.repeatable,
+            privacy: self.privacy,
+            cmdline_cursor_pos: if let Some(ref filter) = filter {
+                Some(filter.len())
+            } else if self.privacy {
+                None
+            } else {
+                self.keypress.cmdline_cursor_byte()
+            },
+            // The prompt may quote the reading being registered
+            cmdline_prompt: if self.privacy || filter.is_some() {
+                None
+            } else {
+                self.keypress.cmdline_prompt().map(str::to_string)
+            },
+            // The keys are what was typed: hidden in privacy mode
+            macro_row: self
+                .keypress
+                .macro_preview(self.config.behavior.macro_preview)
+                .and_then(|mut row| {
+                    if self.privacy {
+                        row.keys.clear();
+                    }
+                    (row.playing.is_some() || !row.keys.is_empty()).then_some(row)
+                }),
+            debug_line: if self.config.behavior.debug_overlay {
+                Some(self.snapshot_stats.unwrap_or_default().summary())
+            } else {
+                None
+            },
+            latency: self
+                .last_rpc
+                .filter(|_| self.config.metrics.indicator)
+                .map(metrics::format_duration),
         };
+        if content.is_empty() {
+            self.hide_popup();
+            return;
+        }
+        let animation = &self.config.animation;
+        let (fade_in, slide) = (
+            Duration::from_millis(animation.fade_in),
+            Duration::from_millis(animation.slide),
+        );
         if let Some(ref mut popup) = self.popup {
+            let fade = &mut self.animations.popup_fade;
+            if !popup.visible || fade.is_fading_out() {
+                fade.fade_in(t, fade_in);
+            }
+            let candidate_slide = &mut self.animations.candidate_slide;
+            candidate_slide.update(t, !content.candidates.is_empty(), slide);
+            popup.set_animation(fade.opacity(t), candidate_slide.progress(t));
             let qh = self.wayland.qh.clone();
             popup.update(&content, &qh);
         }
         log::trace!(
-            "[PERF] update_popup: {:.2}ms",
+            "[PERF] render_popup: {:.2}ms",
             t.elapsed().as_secs_f64() * 1000.0
         );
     }
 
-    /// Hide the unified popup
+    /// Create the current seat's popup if it's missing (creation failed earlier)
+    fn ensure_popup(&mut self, now: std::time::Instant) {
+        if self.popup.is_some() {
+            return;
+        }
+        let Some((popup, recovered)) = self.popup_factory.create(
+            self.current_seat,
+            &self.wayland.input_method,
+            &self.wayland.qh,
+            now,
+        ) else {
+            return;
+        };
+        self.popup = Some(popup);
+        if recovered {
+            log::info!(
+                "[POPUP] Popup window available for seat {}",
+                self.current_seat
+            );
+            self.ime
+                .set_transient_message("Popup window available".to_string());
+        }
+    }
+
+    /// Hide the unified popup, fading it out first (`animation.fade_out`)
     pub(crate) fn hide_popup(&mut self) {
+        // Updates from earlier in this turn would show it again
+        self.popup_dirty = false;
+        let fade_out = Duration::from_millis(self.config.animation.fade_out);
+        match self.popup {
+            Some(ref popup) if popup.visible && !fade_out.is_zero() => {
+                self.animations
+                    .popup_fade
+                    .fade_out(Instant::now(), fade_out);
+            }
+            _ => self.hide_popup_now(),
+        }
+    }
+
+    /// Hide the unified popup without fading (it is about to be parked)
+    pub(crate) fn hide_popup_now(&mut self) {
+        self.popup_dirty = false;
+        self.animations.popup_fade.stop();
+        self.animations.candidate_slide.stop();
         if let Some(ref mut popup) = self.popup {
             popup.hide();
         }
     }
 
+    /// Advance the popup fade and slide by a frame. Returns whether one is
+    /// still running.
+    pub(crate) fn tick_popup_animations(&mut self) -> bool {
+        let now = Instant::now();
+        let fade = &mut self.animations.popup_fade;
+        if fade.is_fading_out() {
+            let opacity = fade.opacity(now);
+            let qh = self.wayland.qh.clone();
+            let faded = self
+                .popup
+                .as_mut()
+                .is_some_and(|p| opacity > 0.0 && p.fade(opacity, &qh));
+            if !faded {
+                self.hide_popup_now();
+                return false;
+            }
+        } else {
+            // Also after the last frame, to land exactly on the end state
+            self.update_popup();
+        }
+        self.animations.popup_running(now)
+    }
+
     pub(crate) fn hide_candidates(&mut self) {
         self.ime.clear_candidates();
         self.update_popup();
@@ -389,148 +1545,32 @@ impl State {
 
 #[cfg(test)]
 mod replay_tests {
+    use std::time::{Duration, Instant};
+
     use serde::Deserialize;
 
-    use crate::neovim::{FromNeovim, VisualSelection};
-    use crate::state::{ImeState, KeypressState, VimMode};
+    use crate::config::{DeactivateClear, OnFocusLoss};
+    use crate::neovim::{FromNeovim, PreeditInfo};
+    use crate::replay::{Replayer, Trace, TraceEvent};
 
-    /// Minimal state for replaying FromNeovim messages without Wayland/popup.
-    struct ReplayState {
-        ime: ImeState,
-        keypress: KeypressState,
-        visual_display: Option<VisualSelection>,
-        committed: Vec<String>,
-        exited: bool,
-        wayland_active: bool,
-    }
-
-    impl ReplayState {
-        fn new() -> Self {
-            let mut ime = ImeState::new();
-            // Start as fully enabled (most replay scenarios assume enabled IME)
-            ime.start_enabling();
-            ime.complete_enabling(VimMode::Insert);
-            Self {
-                ime,
-                keypress: KeypressState::new(),
-                visual_display: None,
-                committed: Vec::new(),
-                exited: false,
-                wayland_active: true,
-            }
-        }
-
-        fn apply(&mut self, msg: FromNeovim) {
-            match msg {
-                FromNeovim::Ready | FromNeovim::KeyProcessed | FromNeovim::PassthroughKey => {}
-                FromNeovim::DeleteSurrounding { .. } => {}
-                FromNeovim::Preedit(info) => {
-                    if self.ime.is_fully_enabled() {
-                        self.ime
-                            .set_preedit(info.text, info.cursor_begin, info.cursor_end);
-                        self.keypress.set_vim_mode(&info.mode);
-                        self.keypress.recording = info.recording;
-                    }
-                }
-                FromNeovim::Commit(text) => {
-                    self.committed.push(text);
-                    self.ime.clear_preedit();
-                    self.ime.clear_candidates();
-                    self.keypress.clear();
-                }
-                FromNeovim::Candidates(info) => {
-                    if self.ime.is_fully_enabled() {
-                        if info.candidates.is_empty() {
-                            self.ime.clear_candidates();
-                        } else {
-                            self.ime.set_candidates(info.candidates, info.selected);
-                        }
-                    }
-                }
-                FromNeovim::VisualRange(selection) => {
-                    if self.ime.is_fully_enabled() {
-                        self.visual_display = selection;
-                    }
-                }
-                FromNeovim::CmdlineShow {
-                    content,
-                    pos,
-                    firstc,
-                    prompt,
-                    level,
-                } => {
-                    if self.ime.is_fully_enabled() {
-                        let prefix = if !prompt.is_empty() { &prompt } else { &firstc };
-                        let prefix_len = prefix.len();
-                        let display_text = format!("{}{}", prefix, content);
-                        let cursor_byte = prefix_len + pos;
-                        self.keypress.set_cmdline_text(
-                            display_text,
-                            cursor_byte,
-                            prefix_len,
-                            level,
-                        );
-                        self.keypress.set_vim_mode("c");
-                    }
-                }
-                FromNeovim::CmdlinePos { pos, level } => {
-                    if self.ime.is_fully_enabled() {
-                        self.keypress.update_cmdline_cursor(pos, level);
-                    }
-                }
-                FromNeovim::CmdlineHide { level } => {
-                    self.keypress.clear_cmdline_if_level(level);
-                }
-                FromNeovim::CmdlineCancelled { cmdtype, .. } => {
-                    self.keypress.clear();
-                    self.keypress
-                        .set_vim_mode(if cmdtype == "@" { "i" } else { "n" });
-                }
-                FromNeovim::CmdlineMessage { text, .. } => {
-                    if self.ime.is_fully_enabled() {
-                        self.ime.set_transient_message(text);
-                    }
-                }
-                FromNeovim::ModeChange(mode) => {
-                    if self.ime.is_fully_enabled() {
-                        self.keypress.set_vim_mode(&mode);
-                    }
-                }
-                FromNeovim::AutoCommit(text) => {
-                    if text.is_empty() {
-                        return;
-                    }
-                    if !self.ime.is_fully_enabled() {
-                        if self.wayland_active {
-                            self.committed.push(text);
-                        }
-                    } else {
-                        self.committed.push(text);
-                        self.ime.clear_preedit();
-                        self.ime.clear_candidates();
-                        self.keypress.clear();
-                        self.visual_display = None;
-                    }
-                }
-                FromNeovim::NvimExited => {
-                    self.ime.clear_preedit();
-                    self.ime.clear_candidates();
-                    self.keypress.clear();
-                    self.keypress.recording.clear();
-                    self.visual_display = None;
-                    self.ime.disable();
-                    self.exited = true;
-                }
-            }
-        }
+    const CLEAR_DELAY: Duration = Duration::from_secs(5);
+
+    fn replayer() -> Replayer {
+        Replayer::new(DeactivateClear::Clear, CLEAR_DELAY)
     }
 
-    #[derive(Deserialize)]
-    struct Fixture {
-        #[allow(dead_code)]
-        description: String,
-        messages: Vec<serde_json::Value>,
-        expect: Expected,
+    fn preedit(text: &str) -> FromNeovim {
+        FromNeovim::Preedit(PreeditInfo {
+            text: text.to_string(),
+            cursor_begin: text.len(),
+            cursor_end: text.len(),
+            mode: "i".to_string(),
+            recording: String::new(),
+            undo_seq: 0,
+            repeatable: false,
+            segment: None,
+            highlights: Vec::new(),
+        })
     }
 
     #[derive(Deserialize)]
@@ -542,22 +1582,29 @@ mod replay_tests {
         candidates_count: usize,
         committed: Vec<String>,
         exited: bool,
+        #[serde(default)]
+        sent_keys: Vec<String>,
     }
 
-    fn run_fixture(path: &str) {
+    fn run_fixture(path: &str) -> Replayer {
         let content = std::fs::read_to_string(path)
             .unwrap_or_else(|e| panic!("failed to read fixture {path}: {e}"));
-        let fixture: Fixture = serde_json::from_str(&content)
+        let value: serde_json::Value = serde_json::from_str(&content)
             .unwrap_or_else(|e| panic!("failed to parse fixture {path}: {e}"));
+        let expect: Expected = serde_json::from_value(value["expect"].clone())
+            .unwrap_or_else(|e| panic!("failed to parse expectations in {path}: {e}"));
+        let trace =
+            Trace::from_value(value).unwrap_or_else(|e| panic!("failed to load trace {path}: {e}"));
 
-        let mut state = ReplayState::new();
-        for (i, value) in fixture.messages.iter().enumerate() {
-            let msg: FromNeovim = serde_json::from_value(value.clone())
-                .unwrap_or_else(|e| panic!("failed to parse message {i} in {path}: {e}"));
-            state.apply(msg);
+        let mut state = replayer();
+        let mut now = Instant::now();
+        for event in trace.events {
+            if let TraceEvent::Tick { ms } = event {
+                now += Duration::from_millis(ms);
+            }
+            state.replay(event, now);
         }
 
-        let expect = &fixture.expect;
         assert_eq!(
             state.ime.preedit, expect.preedit,
             "preedit mismatch in {path}"
@@ -584,6 +1631,11 @@ mod replay_tests {
             "committed mismatch in {path}"
         );
         assert_eq!(state.exited, expect.exited, "exited mismatch in {path}");
+        assert_eq!(
+            state.sent_keys, expect.sent_keys,
+            "sent_keys mismatch in {path}"
+        );
+        state
     }
 
     #[test]
@@ -606,9 +1658,21 @@ mod replay_tests {
         run_fixture("tests/fixtures/nvim_exit.json");
     }
 
+    #[test]
+    fn replay_deactivate_mid_composition() {
+        let state = run_fixture("tests/fixtures/deactivate_mid_composition.json");
+        assert_eq!(state.user_keys, vec!["k", "a"]);
+        assert!(state.wayland_active);
+    }
+
+    #[test]
+    fn replay_v1_fixture_is_upgraded() {
+        run_fixture("tests/fixtures/v1/insert_and_commit.json");
+    }
+
     #[test]
     fn replay_auto_commit_after_nvim_exit_still_commits() {
-        let mut state = ReplayState::new();
+        let mut state = replayer();
         state.apply(FromNeovim::NvimExited);
         state.apply(FromNeovim::AutoCommit("hel lo".to_string()));
 
@@ -617,9 +1681,76 @@ mod replay_tests {
 
     #[test]
     fn replay_auto_commit_ignores_empty_text() {
-        let mut state = ReplayState::new();
+        let mut state = replayer();
         state.apply(FromNeovim::AutoCommit(String::new()));
 
         assert!(state.committed.is_empty());
     }
+
+    #[test]
+    fn replay_deactivate_clear_resets_backend() {
+        let mut state = replayer();
+        state.apply(preedit("かな"));
+        state.deactivate(Instant::now());
+
+        assert_eq!(state.ime.preedit, "");
+        assert_eq!(state.sent_keys, vec!["<Esc>ggdG".to_string()]);
+    }
+
+    #[test]
+    fn replay_deactivate_keep_resumes_preedit() {
+        let mut state = Replayer::new(DeactivateClear::Keep, CLEAR_DELAY);
+        let now = Instant::now();
+        state.apply(preedit("かな"));
+        state.deactivate(now);
+        state.tick(now + CLEAR_DELAY * 10);
+        assert_eq!(state.ime.preedit, "");
+        assert!(state.sent_keys.is_empty());
+
+        // Re-activation pulls the kept buffer back as a snapshot
+        state.activate();
+        state.apply(preedit("かな"));
+        assert_eq!(state.ime.preedit, "かな");
+        assert!(state.committed.is_empty());
+    }
+
+    #[test]
+    fn replay_focus_loss_commits_or_keeps_the_preedit() {
+        let now = Instant::now();
+        let mut state = replayer().with_focus_loss(OnFocusLoss::Commit);
+        state.apply(preedit("かな"));
+        state.deactivate(now);
+        assert_eq!(state.committed, vec!["かな".to_string()]);
+        assert_eq!(state.sent_keys, vec!["<Esc>ggdG".to_string()]);
+
+        // Kept whatever deactivate_clear says, shown again on activation
+        let mut state = replayer().with_focus_loss(OnFocusLoss::Keep);
+        state.apply(preedit("かな"));
+        state.deactivate(now);
+        assert_eq!(state.ime.preedit, "");
+        state.activate();
+        assert_eq!(state.ime.preedit, "かな");
+        assert!(state.sent_keys.is_empty());
+        assert!(state.committed.is_empty());
+    }
+
+    #[test]
+    fn replay_deactivate_defer_clears_only_unrestored_buffer() {
+        let mut state = Replayer::new(DeactivateClear::Defer, CLEAR_DELAY);
+        let now = Instant::now();
+        state.apply(preedit("かな"));
+
+        // Focus returns before the deadline: nothing is cleared
+        state.deactivate(now);
+        state.tick(now + CLEAR_DELAY / 2);
+        state.activate();
+        state.tick(now + CLEAR_DELAY);
+        assert!(state.sent_keys.is_empty());
+
+        // Focus stays away: cleared once at the deadline
+        state.deactivate(now + CLEAR_DELAY);
+        state.tick(now + CLEAR_DELAY * 2);
+        state.tick(now + CLEAR_DELAY * 3);
+        assert_eq!(state.sent_keys, vec!["<Esc>ggdG".to_string()]);
+    }
 }
@@ -0,0 +1,142 @@
+//! D-Bus control interface (`org.jacin.Ime1` on the session bus)
+//!
+//! zbus serves method calls on its own executor thread; each call is forwarded
+//! to the event loop through a calloop channel and waits for the reply, so all
+//! IME state changes still happen on the main thread. `StateChanged` is emitted
+//! from the main thread whenever the reported status changes.
+
+use std::sync::mpsc::{SyncSender, sync_channel};
+use std::time::Duration;
+
+use calloop::channel::{Channel, Sender, channel};
+use zbus::blocking::Connection;
+use zbus::object_server::SignalEmitter;
+
+use crate::State;
+
+const BUS_NAME: &str = "org.jacin.Ime1";
+const OBJECT_PATH: &str = "/org/jacin/Ime1";
+
+/// How long a method call waits for the event loop
+const REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Method call forwarded to the event loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DbusCommand {
+    Toggle,
+    Enable,
+    Disable,
+    Status,
+}
+
+/// A command plus the channel its reply (the resulting status) goes to
+pub struct DbusRequest {
+    pub command: DbusCommand,
+    reply: SyncSender<&'static str>,
+}
+
+struct ImeService {
+    requests: Sender<DbusRequest>,
+}
+
+impl ImeService {
+    fn call(&self, command: DbusCommand) -> zbus::fdo::Result<String> {
+        let (reply, rx) = sync_channel(1);
+        self.requests
+            .send(DbusRequest { command, reply })
+            .map_err(|_| zbus::fdo::Error::Failed("event loop not running".into()))?;
+        rx.recv_timeout(REPLY_TIMEOUT)
+            .map(str::to_string)
+            .map_err(|_| zbus::fdo::Error::Failed("no reply from event loop".into()))
+    }
+}
+
+#[zbus::interface(name = "org.jacin.Ime1")]
+impl ImeService {
+    /// Toggle the IME (same as SIGUSR1). Returns the new status.
+    fn toggle(&self) -> zbus::fdo::Result<String> {
+        self.call(DbusCommand::Toggle)
+    }
+
+    /// Enable the IME if it is disabled. Returns the new status.
+    fn enable(&self) -> zbus::fdo::Result<String> {
+        self.call(DbusCommand::Enable)
+    }
+
+    /// Disable the IME (committing any preedit) if it is enabled. Returns the new status.
+    fn disable(&self) -> zbus::fdo::Result<String> {
+        self.call(DbusCommand::Disable)
+    }
+
+    /// Current status: "disabled", "enabling", "enabled" or "suspended"
+    fn status(&self) -> zbus::fdo::Result<String> {
+        self.call(DbusCommand::Status)
+    }
+
+    #[zbus(signal)]
+    async fn state_changed(emitter: &SignalEmitter<'_>, status: &str) -> zbus::Result<()>;
+}
+
+/// Session bus connection owning the service name
+pub struct DbusService {
+    conn: Connection,
+    /// Last status announced via StateChanged
+    last_status: &'static str,
+}
+
+impl DbusService {
+    /// Claim the bus name and serve the interface. Requests arrive on the returned channel.
+    pub fn start() -> zbus::Result<(Self, Channel<DbusRequest>)> {
+        let (tx, rx) = channel();
+        let conn = zbus::blocking::connection::Builder::session()?
+            .name(BUS_NAME)?
+            .serve_at(OBJECT_PATH, ImeService { requests: tx })?
+            .build()?;
+        log::info!("[DBUS] Serving {} at {}", BUS_NAME, OBJECT_PATH);
+        let service = Self {
+            conn,
+            last_status: "disabled",
+        };
+        Ok((service, rx))
+    }
+
+    /// Emit StateChanged if `status` differs from the last announced one
+    fn announce(&mut self, status: &'static str) {
+        if status == self.last_status {
+            return;
+        }
+        self.last_status = status;
+        let result = self
+            .conn
+            .object_server()
+            .interface::<_, ImeService>(OBJECT_PATH)
+            .and_then(|iface| {
+                zbus::block_on(ImeService::state_changed(iface.signal_emitter(), status))
+            });
+        if let Err(e) = result {
+            log::warn!("[DBUS] Failed to emit StateChanged: {}", e);
+        }
+    }
+}
+
+impl State {
+    /// Run a D-Bus method on the event loop and reply with the resulting status
+    pub(crate) fn handle_dbus_request(&mut self, request: DbusRequest) {
+        log::debug!("[DBUS] {:?}", request.command);
+        match request.command {
+            DbusCommand::Toggle => self.handle_ime_toggle(),
+            DbusCommand::Enable if !self.ime.is_enabled() => self.handle_ime_toggle(),
+            DbusCommand::Disable if self.ime.is_enabled() => self.handle_ime_toggle(),
+            _ => {}
+        }
+        let _ = request.reply.send(self.ime.status());
+    }
+
+    /// Announce the IME status if it changed (called once per event loop iteration)
+    pub(crate) fn publish_dbus_status(&mut self) {
+        let status = self.ime.status();
+        if let Some(ref mut dbus) = self.dbus {
+            dbus.announce(status);
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! SKK dictionary download (`jacin dict fetch`)
+//!
+//! Setting up SKK conversion used to start with finding SKK-JISYO.L, unpacking
+//! it somewhere and pointing the conversion plugin at it. `jacin dict fetch`
+//! downloads the `[dictionary]` files with curl (progress on stderr), checks
+//! each against its configured SHA-256 or else the MD5 published next to it
+//! (`<url>.md5`, as skk-dev does), unpacks `.gz` files into the dictionary
+//! directory and asks a running jacin to reload them (`dict reload`). A file
+//! that can't be verified is refused unless `--allow-unverified` is given.
+//! The backend keeps the installed paths in `vim.g.ime_dictionaries` and fires
+//! `User ImeDictionaryUpdated` when they change.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use flate2::read::MultiGzDecoder;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::config::{DictionaryConfig, DictionaryFile};
+use crate::control::client;
+
+const USAGE: &str = "usage: jacin dict fetch [--allow-unverified]";
+
+/// Arguments after `dict` if `args` is a `jacin dict ...` command line
+pub fn cli_args<S: AsRef<str>>(args: &[S]) -> Option<&[S]> {
+    match args.split_first() {
+        Some((first, rest)) if first.as_ref() == "dict" => Some(rest),
+        _ => None,
+    }
+}
+
+/// Run `jacin dict <args>`
+pub fn run(config: &DictionaryConfig, args: &[String]) -> anyhow::Result<()> {
+    match args {
+        [fetch] if fetch == "fetch" => fetch_all(config, false),
+        [fetch, flag] if fetch == "fetch" && flag == "--allow-unverified" => {
+            fetch_all(config, true)
+        }
+        _ => anyhow::bail!("{USAGE}"),
+    }
+}
+
+/// Installed dictionaries of `config` that exist on disk
+pub fn installed(config: &DictionaryConfig) -> Vec<PathBuf> {
+    let Some(dir) = config.dir() else {
+        return Vec::new();
+    };
+    config
+        .files
+        .iter()
+        .filter_map(|file| file_name(&file.url))
+        .map(|name| dir.join(installed_name(name)))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+fn fetch_all(config: &DictionaryConfig, allow_unverified: bool) -> anyhow::Result<()> {
+    let dir = config.dir().ok_or_else(|| {
+        anyhow::anyhow!("cannot determine the dictionary directory (set [dictionary] dir)")
+    })?;
+    std::fs::create_dir_all(&dir)
+        .map_err(|e| anyhow::anyhow!("cannot create {}: {}", dir.display(), e))?;
+    for file in &config.files {
+        let path = fetch(file, &dir, allow_unverified)?;
+        println!("{}", path.display());
+    }
+
+    // The running instance hands the new paths to Neovim
+    match client::request("dict reload") {
+        Ok(response) => match response.get("error").and_then(|e| e.as_str()) {
+            Some(err) => eprintln!("jacin: reload failed: {err}"),
+            None => eprintln!("Reloaded dictionaries in the running jacin"),
+        },
+        Err(_) => eprintln!("jacin isn't running; the dictionaries are picked up on next start"),
+    }
+    Ok(())
+}
+
+/// Download one dictionary into `dir`, returning the installed path
+fn fetch(file: &DictionaryFile, dir: &Path, allow_unverified: bool) -> anyhow::Result<PathBuf> {
+    let name = file_name(&file.url)
+        .ok_or_else(|| anyhow::anyhow!("no file name in dictionary URL {}", file.url))?;
+    let download = dir.join(format!("{name}.part"));
+    eprintln!("Fetching {}", file.url);
+    let status = Command::new("curl")
+        .args(["-fL", "--progress-bar", "-o"])
+        .arg(&download)
+        .arg(&file.url)
+        .status()
+        .map_err(|e| anyhow::anyhow!("cannot run curl: {e}"))?;
+    if !status.success() {
+        let _ = std::fs::remove_file(&download);
+        anyhow::bail!("downloading {} failed: curl exited {}", file.url, status);
+    }
+
+    if let Err(e) = verify(file, name, &download, allow_unverified) {
+        let _ = std::fs::remove_file(&download);
+        return Err(e);
+    }
+
+    let target = dir.join(installed_name(name));
+    if name.ends_with(".gz") {
+        let unpacked = dir.join(format!("{}.part", installed_name(name)));
+        let result = gunzip(&download, &unpacked);
+        let _ = std::fs::remove_file(&download);
+        if let Err(e) = result {
+            let _ = std::fs::remove_file(&unpacked);
+            return Err(e);
+        }
+        std::fs::rename(&unpacked, &target)?;
+    } else {
+        std::fs::rename(&download, &target)?;
+    }
+    Ok(target)
+}
+
+/// Check the download against the configured SHA-256, or else the MD5
+/// published at `<url>.md5`
+fn verify(
+    file: &DictionaryFile,
+    name: &str,
+    download: &Path,
+    allow_unverified: bool,
+) -> anyhow::Result<()> {
+    let (kind, expected, actual) = match &file.sha256 {
+        Some(expected) => (
+            "sha256",
+            expected.trim().to_ascii_lowercase(),
+            hex_digest::<Sha256>(File::open(download)?)?,
+        ),
+        None => match published_md5(&file.url) {
+            Some(expected) => ("md5", expected, hex_digest::<Md5>(File::open(download)?)?),
+            None if allow_unverified => {
+                eprintln!("{name}: no checksum available, installing unverified");
+                return Ok(());
+            }
+            None => anyhow::bail!(
+                "{name}: no sha256 configured and no {}.md5 published; \
+                 set one or pass --allow-unverified",
+                file.url
+            ),
+        },
+    };
+    if actual != expected {
+        anyhow::bail!("{kind} mismatch for {name}: expected {expected}, got {actual}");
+    }
+    eprintln!("{name}: {kind} ok");
+    Ok(())
+}
+
+/// Digest published next to `url` (None if there is none)
+fn published_md5(url: &str) -> Option<String> {
+    let output = Command::new("curl")
+        .args(["-fsSL", &format!("{url}.md5")])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_digest(&String::from_utf8_lossy(&output.stdout), 32)
+}
+
+/// Lowercase hex digest of everything `reader` yields
+fn hex_digest<D: Digest + std::io::Write>(mut reader: impl Read) -> anyhow::Result<String> {
+    let mut hasher = D::new();
+    std::io::copy(&mut reader, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+/// Digest of `hex_len` hex digits from a checksum file line (`<hex>  <file>`
+/// as written by md5sum/sha256sum, or the bare digest)
+fn parse_digest(line: &str, hex_len: usize) -> Option<String> {
+    let digest = line.split_whitespace().next()?;
+    (digest.len() == hex_len && digest.chars().all(|c| c.is_ascii_hexdigit()))
+        .then(|| digest.to_ascii_lowercase())
+}
+
+fn gunzip(source: &Path, target: &Path) -> anyhow::Result<()> {
+    let mut decoder = MultiGzDecoder::new(File::open(source)?);
+    std::io::copy(&mut decoder, &mut File::create(target)?)
+        .map_err(|e| anyhow::anyhow!("unpacking {} failed: {}", source.display(), e))?;
+    Ok(())
+}
+
+/// Last path segment of a URL, without query or fragment
+fn file_name(url: &str) -> Option<&str> {
+    let path = url.split(['?', '#']).next()?;
+    let name = path.rsplit('/').next()?;
+    (!name.is_empty()).then_some(name)
+}
+
+/// Name of the installed file (`.gz` archives are unpacked)
+fn installed_name(name: &str) -> &str {
+    name.strip_suffix(".gz").unwrap_or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dict_subcommand_args() {
+        assert_eq!(cli_args(&["dict", "fetch"]), Some(&["fetch"][..]));
+        assert_eq!(cli_args(&["--clean", "dict"]), None);
+        assert_eq!(cli_args::<&str>(&[]), None);
+    }
+
+    #[test]
+    fn file_names_from_urls() {
+        let name = file_name("https://skk-dev.github.io/dict/SKK-JISYO.L.gz").unwrap();
+        assert_eq!(name, "SKK-JISYO.L.gz");
+        assert_eq!(installed_name(name), "SKK-JISYO.L");
+        assert_eq!(
+            file_name("https://example.com/SKK-JISYO.jinmei?raw=true#x"),
+            Some("SKK-JISYO.jinmei")
+        );
+        assert_eq!(installed_name("SKK-JISYO.jinmei"), "SKK-JISYO.jinmei");
+        assert_eq!(file_name("https://example.com/dict/"), None);
+    }
+
+    #[test]
+    fn parses_checksum_files() {
+        let digest = "E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855";
+        assert_eq!(
+            parse_digest(&format!("{digest}  /tmp/SKK-JISYO.L.gz.part\n"), 64),
+            Some(digest.to_ascii_lowercase())
+        );
+        assert_eq!(
+            parse_digest("d41d8cd98f00b204e9800998ecf8427e\n", 32),
+            Some("d41d8cd98f00b204e9800998ecf8427e".to_string())
+        );
+        // An HTML error page served with 200
+        assert_eq!(parse_digest("<!DOCTYPE html>", 32), None);
+        assert_eq!(parse_digest(&format!("{digest}\n"), 32), None);
+        assert_eq!(parse_digest("", 32), None);
+    }
+
+    #[test]
+    fn digests_as_lowercase_hex() {
+        assert_eq!(
+            hex_digest::<Sha256>(&b""[..]).unwrap(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex_digest::<Md5>(&b"abc"[..]).unwrap(),
+            "900150983cd24fb0d6963f7d28e17f72"
+        );
+    }
+}
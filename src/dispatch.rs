@@ -1,13 +1,14 @@
 use std::os::fd::{AsFd, AsRawFd};
 
 use wayland_client::{
-    Connection, Dispatch, QueueHandle, WEnum,
+    Connection, Dispatch, Proxy, QueueHandle, WEnum, event_created_child,
     globals::GlobalListContents,
     protocol::{
-        wl_buffer, wl_compositor, wl_keyboard, wl_region, wl_registry, wl_shm, wl_shm_pool,
-        wl_surface,
+        wl_buffer, wl_compositor, wl_keyboard, wl_output, wl_pointer, wl_region, wl_registry,
+        wl_seat, wl_shm, wl_shm_pool, wl_surface,
     },
 };
+use wayland_protocols::wp::text_input::zv3::client::zwp_text_input_v3;
 use wayland_protocols_misc::zwp_input_method_v2::client::{
     zwp_input_method_keyboard_grab_v2, zwp_input_method_manager_v2, zwp_input_method_v2,
     zwp_input_popup_surface_v2,
@@ -16,8 +17,24 @@ use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::{
     zwp_virtual_keyboard_manager_v1, zwp_virtual_keyboard_v1,
 };
 
+use wayland_protocols_wlr::foreign_toplevel::v1::client::{
+    zwlr_foreign_toplevel_handle_v1, zwlr_foreign_toplevel_manager_v1,
+};
+
+use wayland_protocols_wlr::layer_shell::v1::client::{zwlr_layer_shell_v1, zwlr_layer_surface_v1};
+
+use wayland_protocols_wlr::data_control::v1::client::{
+    zwlr_data_control_device_v1, zwlr_data_control_manager_v1, zwlr_data_control_offer_v1,
+    zwlr_data_control_source_v1,
+};
+
 use crate::State;
-use crate::state::VimMode;
+use crate::clipboard::{self, OfferMimeTypes};
+use crate::contention;
+use crate::neovim::ClipboardSelection;
+use crate::replay::TraceEvent;
+use crate::state::{ContentPurpose, ContentType, SeatId, SurroundingText, VimMode};
+use crate::toplevel;
 
 // Dispatch for registry (required by registry_queue_init)
 impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
@@ -33,20 +50,95 @@ impl Dispatch<wl_registry::WlRegistry, GlobalListContents> for State {
     }
 }
 
-// Dispatch for seat
-impl Dispatch<wayland_client::protocol::wl_seat::WlSeat, ()> for State {
+// Dispatch for seat: only the pointer capability matters (behavior.popup_pointer)
+impl Dispatch<wl_seat::WlSeat, SeatId> for State {
     fn event(
-        _state: &mut Self,
-        _seat: &wayland_client::protocol::wl_seat::WlSeat,
-        _event: wayland_client::protocol::wl_seat::Event,
-        _data: &(),
+        state: &mut Self,
+        seat: &wl_seat::WlSeat,
+        event: wl_seat::Event,
+        id: &SeatId,
+        _conn: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let wl_seat::Event::Capabilities {
+            capabilities: WEnum::Value(capabilities),
+        } = event
+        else {
+            return;
+        };
+        if !state.config.behavior.popup_pointer {
+            return;
+        }
+        let Some(wayland) = state.seat_wayland_mut(*id) else {
+            return;
+        };
+        let has_pointer = capabilities.contains(wl_seat::Capability::Pointer);
+        if has_pointer && wayland.pointer.is_none() {
+            log::debug!("[POINTER] Binding pointer on seat {}", id);
+            wayland.pointer = Some(seat.get_pointer(qh, *id));
+        } else if !has_pointer && let Some(pointer) = wayland.pointer.take() {
+            if pointer.version() >= 3 {
+                pointer.release();
+            }
+            state.pointer.leave();
+        }
+    }
+}
+
+// Dispatch for pointer: clicks and scrolling on the candidate popup
+impl Dispatch<wl_pointer::WlPointer, SeatId> for State {
+    fn event(
+        state: &mut Self,
+        _pointer: &wl_pointer::WlPointer,
+        event: wl_pointer::Event,
+        id: &SeatId,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
     ) {
-        // Seat events (capabilities, name) - we don't need to handle these
+        match event {
+            wl_pointer::Event::Enter {
+                surface,
+                surface_x,
+                surface_y,
+                ..
+            } if state
+                .seat_popup_mut(*id)
+                .is_some_and(|p| p.owns_surface(&surface)) =>
+            {
+                state.pointer.enter(*id, surface_x as f32, surface_y as f32);
+            }
+            wl_pointer::Event::Leave { .. } => state.pointer.leave(),
+            wl_pointer::Event::Motion {
+                surface_x,
+                surface_y,
+                ..
+            } => state.pointer.motion(surface_x as f32, surface_y as f32),
+            wl_pointer::Event::Button {
+                button,
+                state: WEnum::Value(wl_pointer::ButtonState::Pressed),
+                ..
+            } if button == BTN_LEFT => state.handle_popup_click(*id),
+            wl_pointer::Event::AxisSource {
+                axis_source: WEnum::Value(source),
+            } => state.pointer.axis_source = Some(source),
+            wl_pointer::Event::Axis {
+                axis: WEnum::Value(wl_pointer::Axis::VerticalScroll),
+                value,
+                ..
+            } => state.handle_popup_scroll(*id, value as f32),
+            wl_pointer::Event::AxisStop {
+                axis: WEnum::Value(wl_pointer::Axis::VerticalScroll),
+                ..
+            } => state.handle_popup_scroll_stop(*id),
+            wl_pointer::Event::Frame => state.pointer.axis_source = None,
+            _ => {}
+        }
     }
 }
 
+/// Linux evdev code of the left mouse button
+const BTN_LEFT: u32 = 0x110;
+
 // Dispatch for compositor
 impl Dispatch<wl_compositor::WlCompositor, ()> for State {
     fn event(
@@ -104,57 +196,109 @@ impl Dispatch<wl_region::WlRegion, ()> for State {
     }
 }
 
-// Dispatch for surface
-impl Dispatch<wl_surface::WlSurface, ()> for State {
+// Dispatch for the popup surface (with owning seat as user data)
+impl Dispatch<wl_surface::WlSurface, SeatId> for State {
     fn event(
-        _state: &mut Self,
+        state: &mut Self,
         _surface: &wl_surface::WlSurface,
         event: wl_surface::Event,
+        seat: &SeatId,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            wl_surface::Event::Enter { output } => {
+                log::debug!("[SURFACE] Entered output {}", output.id());
+                state.on_popup_entered_output(*seat, output.id());
+            }
+            wl_surface::Event::Leave { output } => {
+                // The popup keeps the size for its last output: it is hidden
+                // or on its way to another output (Enter) by now
+                log::debug!("[SURFACE] Left output {}", output.id());
+            }
+            _ => {}
+        }
+    }
+}
+
+// Dispatch for outputs (popup sizes per output)
+impl Dispatch<wl_output::WlOutput, ()> for State {
+    fn event(
+        state: &mut Self,
+        output: &wl_output::WlOutput,
+        event: wl_output::Event,
         _data: &(),
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
     ) {
+        let id = output.id();
         match event {
-            wl_surface::Event::Enter { .. } => {
-                log::debug!("[SURFACE] Entered output");
+            wl_output::Event::Geometry { make, model, .. } => {
+                state.outputs.set_make_model(id, &make, &model);
+            }
+            wl_output::Event::Mode {
+                flags: WEnum::Value(flags),
+                width,
+                height,
+                ..
+            } if flags.contains(wl_output::Mode::Current) => {
+                state.outputs.set_mode(id, width, height);
+            }
+            wl_output::Event::Scale { factor } => {
+                state.outputs.set_scale(id, factor);
+            }
+            wl_output::Event::Name { name } => {
+                state.outputs.set_name(id, name);
+            }
+            wl_output::Event::Description { description } => {
+                state.outputs.set_description(id, description);
             }
-            wl_surface::Event::Leave { .. } => {
-                log::debug!("[SURFACE] Left output");
+            wl_output::Event::Done => {
+                if let Some(info) = state.outputs.done(id.clone()) {
+                    log::info!(
+                        "[OUTPUT] {} ({}): {}x{} scale {}",
+                        info.name,
+                        info.description,
+                        info.mode.0,
+                        info.mode.1,
+                        info.scale
+                    );
+                    state.on_output_changed(&id);
+                }
             }
             _ => {}
         }
     }
 }
 
-// Dispatch for buffer (with buffer index as user data)
+// Dispatch for buffer (with owning seat and buffer index as user data)
 // Unified popup uses indices 0 and 1 for double buffering
-impl Dispatch<wl_buffer::WlBuffer, usize> for State {
+impl Dispatch<wl_buffer::WlBuffer, (SeatId, usize)> for State {
     fn event(
         state: &mut Self,
-        _buffer: &wl_buffer::WlBuffer,
+        buffer: &wl_buffer::WlBuffer,
         event: wl_buffer::Event,
-        data: &usize,
+        data: &(SeatId, usize),
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
     ) {
         if let wl_buffer::Event::Release = event {
-            log::debug!("[BUFFER] Released: {}", data);
-            if *data < 2
-                && let Some(ref mut popup) = state.popup
-            {
-                popup.buffer_released(*data);
+            let (seat, idx) = *data;
+            log::debug!("[BUFFER] Released: {} (seat {})", idx, seat);
+            if let Some(popup) = state.seat_popup_mut(seat) {
+                popup.buffer_released(buffer);
             }
         }
     }
 }
 
 // Dispatch for input popup surface (candidate window)
-impl Dispatch<zwp_input_popup_surface_v2::ZwpInputPopupSurfaceV2, ()> for State {
+impl Dispatch<zwp_input_popup_surface_v2::ZwpInputPopupSurfaceV2, SeatId> for State {
     fn event(
-        _state: &mut Self,
+        state: &mut Self,
         _popup_surface: &zwp_input_popup_surface_v2::ZwpInputPopupSurfaceV2,
         event: zwp_input_popup_surface_v2::Event,
-        _data: &(),
+        seat: &SeatId,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
     ) {
@@ -165,8 +309,8 @@ impl Dispatch<zwp_input_popup_surface_v2::ZwpInputPopupSurfaceV2, ()> for State
             height,
         } = event
         {
-            // The compositor tells us where the text cursor is
-            // This is informational - positioning is handled by the compositor
+            // The compositor tells us where the text cursor is; positioning
+            // is up to it, but the layout follows the side it chose
             log::debug!(
                 "[POPUP] Text input rectangle: x={}, y={}, {}x{}",
                 x,
@@ -174,6 +318,56 @@ impl Dispatch<zwp_input_popup_surface_v2::ZwpInputPopupSurfaceV2, ()> for State
                 width,
                 height
             );
+            state.on_text_input_rectangle(*seat, y, height);
+        }
+    }
+}
+
+// Dispatch for the layer shell (corner popup)
+impl Dispatch<zwlr_layer_shell_v1::ZwlrLayerShellV1, ()> for State {
+    fn event(
+        _state: &mut Self,
+        _shell: &zwlr_layer_shell_v1::ZwlrLayerShellV1,
+        _event: zwlr_layer_shell_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        // Layer shell has no events
+    }
+}
+
+// Dispatch for the corner popup's layer surface (with owning seat as user data)
+impl Dispatch<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1, SeatId> for State {
+    fn event(
+        state: &mut Self,
+        _layer_surface: &zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
+        event: zwlr_layer_surface_v1::Event,
+        seat: &SeatId,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_layer_surface_v1::Event::Configure { serial, .. } => {
+                // The size we asked for is used as is; the first configure
+                // lets the waiting content be drawn
+                if let Some(popup) = state.seat_popup_mut(*seat)
+                    && popup.layer_configured(serial)
+                {
+                    state.update_popup();
+                }
+            }
+            zwlr_layer_surface_v1::Event::Closed => {
+                log::debug!(
+                    "[POPUP] Corner popup closed by the compositor (seat {})",
+                    seat
+                );
+                if let Some(popup) = state.seat_popup_mut(*seat) {
+                    popup.layer_closed();
+                    state.update_popup();
+                }
+            }
+            _ => {}
         }
     }
 }
@@ -192,83 +386,199 @@ impl Dispatch<zwp_input_method_manager_v2::ZwpInputMethodManagerV2, ()> for Stat
     }
 }
 
+/// Content type from a `zwp_input_method_v2.content_type` event
+pub(crate) fn content_type_from_event(
+    hint: WEnum<zwp_text_input_v3::ContentHint>,
+    purpose: WEnum<zwp_text_input_v3::ContentPurpose>,
+) -> ContentType {
+    use zwp_text_input_v3::ContentPurpose as P;
+
+    let sensitive = match hint {
+        WEnum::Value(hint) => hint.contains(zwp_text_input_v3::ContentHint::SensitiveData),
+        WEnum::Unknown(bits) => bits & zwp_text_input_v3::ContentHint::SensitiveData.bits() != 0,
+    };
+    let purpose = match purpose {
+        WEnum::Value(P::Alpha) => ContentPurpose::Alpha,
+        WEnum::Value(P::Digits) => ContentPurpose::Digits,
+        WEnum::Value(P::Number) => ContentPurpose::Number,
+        WEnum::Value(P::Phone) => ContentPurpose::Phone,
+        WEnum::Value(P::Url) => ContentPurpose::Url,
+        WEnum::Value(P::Email) => ContentPurpose::Email,
+        WEnum::Value(P::Name) => ContentPurpose::Name,
+        WEnum::Value(P::Password) => ContentPurpose::Password,
+        WEnum::Value(P::Pin) => ContentPurpose::Pin,
+        WEnum::Value(P::Date) => ContentPurpose::Date,
+        WEnum::Value(P::Time) => ContentPurpose::Time,
+        WEnum::Value(P::Datetime) => ContentPurpose::Datetime,
+        WEnum::Value(P::Terminal) => ContentPurpose::Terminal,
+        _ => ContentPurpose::Normal,
+    };
+    ContentType { purpose, sensitive }
+}
+
 // Dispatch for input method - this is where the action happens!
-impl Dispatch<zwp_input_method_v2::ZwpInputMethodV2, ()> for State {
+impl Dispatch<zwp_input_method_v2::ZwpInputMethodV2, SeatId> for State {
     fn event(
         state: &mut Self,
         _input_method: &zwp_input_method_v2::ZwpInputMethodV2,
         event: zwp_input_method_v2::Event,
-        _data: &(),
+        seat: &SeatId,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        state.on_input_method_event(*seat, event);
+    }
+}
+
+// Dispatch for keyboard grab
+impl Dispatch<zwp_input_method_keyboard_grab_v2::ZwpInputMethodKeyboardGrabV2, SeatId> for State {
+    fn event(
+        state: &mut Self,
+        _grab: &zwp_input_method_keyboard_grab_v2::ZwpInputMethodKeyboardGrabV2,
+        event: zwp_input_method_keyboard_grab_v2::Event,
+        seat: &SeatId,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
     ) {
+        state.on_keyboard_grab_event(*seat, event);
+    }
+}
+
+// Event handlers shared by the Dispatch impls above and `jacinctl dev` (--dev)
+impl State {
+    pub(crate) fn on_input_method_event(
+        &mut self,
+        seat: SeatId,
+        event: zwp_input_method_v2::Event,
+    ) {
+        // Events for other seats only touch their parked state, unless the
+        // seat is being activated (then it becomes current and we fall through)
+        if seat != self.current_seat && !self.route_parked_input_method_event(seat, &event) {
+            return;
+        }
         match event {
             zwp_input_method_v2::Event::Activate => {
-                log::info!("IME activated!");
-                state.wayland.pending_activate = true;
+                log::info!("[IME] Activated");
+                self.wayland.pending_activate = true;
+                if self.contention.available() {
+                    log::info!("[IME] Took over {}", contention::CONTENDED_PROTOCOL);
+                    self.ime
+                        .set_transient_message("Input method available".to_string());
+                }
             }
             zwp_input_method_v2::Event::Deactivate => {
-                log::info!("IME deactivated");
-                state.wayland.pending_deactivate = true;
+                log::info!("[IME] Deactivated");
+                self.wayland.pending_deactivate = true;
+            }
+            zwp_input_method_v2::Event::SurroundingText {
+                text,
+                cursor,
+                anchor,
+            } => {
+                self.wayland.pending_surrounding = Some(SurroundingText::new(text, cursor, anchor));
+            }
+            zwp_input_method_v2::Event::ContentType { hint, purpose } => {
+                self.wayland.pending_content_type = Some(content_type_from_event(hint, purpose));
             }
             zwp_input_method_v2::Event::Done => {
                 // Serial must equal the number of Done events received
                 // (required by the commit request protocol)
-                state.wayland.serial += 1;
+                self.wayland.serial += 1;
 
-                let pending_deactivate = std::mem::take(&mut state.wayland.pending_deactivate);
-                let pending_activate = std::mem::take(&mut state.wayland.pending_activate);
+                let pending_deactivate = std::mem::take(&mut self.wayland.pending_deactivate);
+                let pending_activate = std::mem::take(&mut self.wayland.pending_activate);
 
                 // Process deactivate first (like fcitx5)
                 if pending_deactivate {
-                    state.wayland.active = false;
-                    if state.ime.is_enabled() {
-                        // Clear local state (don't send Wayland protocol requests
-                        // while deactivated — compositor clears preedit automatically)
-                        state.reset_ime_state();
-                        // Clear Neovim buffer to reset state for next activation
-                        if let Some(ref nvim) = state.nvim {
-                            nvim.send_key("<Esc>ggdG");
+                    self.record(TraceEvent::Deactivate);
+                    self.wayland.active = false;
+                    if self.ime.is_enabled() {
+                        // Clear local state (the compositor clears the preedit;
+                        // only on_focus_loss = "commit" sends requests now) and
+                        // reset Neovim for the next activation
+                        self.leave_text_input();
+                        // Always-on IME: off with the focus, back on at the next Activate
+                        if self.config.behavior.auto_enable_on_activate {
+                            self.record(TraceEvent::Disable);
+                            self.ime.disable();
                         }
                     }
+                    // Surrounding text and content type belonged to the input that lost focus
+                    self.set_surrounding_text(None);
+                    self.ime.content_type = ContentType::default();
                 }
 
+                // Content type first: it decides whether activation grabs
+                let content_type_changed = match self.wayland.pending_content_type.take() {
+                    Some(ct) if ct != self.ime.content_type => {
+                        log::debug!("[IME] Content type: {:?}", ct);
+                        self.ime.content_type = ct;
+                        true
+                    }
+                    _ => false,
+                };
+
                 // Then process activate
                 if pending_activate {
-                    state.wayland.active = true;
-                    if state.ime.is_enabled() && state.wayland.keyboard_grab.is_none() {
+                    self.record(TraceEvent::Activate);
+                    self.wayland.active = true;
+                    // Focus is back: a deferred clear would discard the resumed buffer
+                    self.buffer_clear.cancel();
+                    if self.ime.is_enabled() && self.content_is_direct() {
+                        log::info!(
+                            "[IME] {} field, leaving keyboard ungrabbed",
+                            self.ime.content_type.purpose.name()
+                        );
+                    } else if self.ime.is_enabled() && self.wayland.keyboard_grab.is_none() {
                         log::debug!("[IME] Re-grabbing keyboard after activation");
-                        state.wayland.grab_keyboard();
-                        state.keyboard.pending_keymap = true;
-                        state.keyboard.is_reactivation = true;
-                        state.ime.start_enabling();
+                        self.wayland.grab_keyboard();
+                        self.keyboard.pending_keymap = true;
+                        self.keyboard.is_reactivation = true;
+                        self.ime.start_enabling();
                     }
                 }
-            }
-            zwp_input_method_v2::Event::Unavailable => {
-                log::warn!("IME unavailable - another IME may be running");
-                if let Some(signal) = &state.loop_signal {
-                    signal.stop();
+
+                if content_type_changed && !pending_activate {
+                    self.on_content_type_changed();
+                }
+
+                if pending_activate {
+                    if self.config.behavior.auto_enable_on_activate
+                        && !self.ime.is_enabled()
+                        && self.fullscreen_blocked().is_none()
+                    {
+                        log::info!(
+                            "[IME] Enabling for the focused input (auto_enable_on_activate)"
+                        );
+                        self.toggle_ime();
+                    }
+                    // Per-app memory (behavior.remember_per_app) may flip what was just set up
+                    self.restore_app_ime_state();
+                    // Text composed before a restart (see recovery) comes back
+                    self.resume_restored_preedit();
+                    // As does a composition kept on focus loss, in its app
+                    self.resume_kept_composition();
+                }
+
+                if let Some(surrounding) = self.wayland.pending_surrounding.take() {
+                    self.set_surrounding_text(Some(surrounding));
                 }
             }
+            zwp_input_method_v2::Event::Unavailable => self.on_input_method_unavailable(seat),
             _ => {}
         }
     }
-}
 
-// Dispatch for keyboard grab
-impl Dispatch<zwp_input_method_keyboard_grab_v2::ZwpInputMethodKeyboardGrabV2, ()> for State {
-    fn event(
-        state: &mut Self,
-        _grab: &zwp_input_method_keyboard_grab_v2::ZwpInputMethodKeyboardGrabV2,
+    pub(crate) fn on_keyboard_grab_event(
+        &mut self,
+        seat: SeatId,
         event: zwp_input_method_keyboard_grab_v2::Event,
-        _data: &(),
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
     ) {
+        // Input on a grabbed keyboard means the user is typing on that seat
+        self.select_seat(seat);
         match event {
             zwp_input_method_keyboard_grab_v2::Event::Keymap { format, fd, size } => {
-                log::debug!("Keymap received: format={:?}, size={}", format, size);
+                log::debug!("[KEY] Keymap received: format={:?}, size={}", format, size);
 
                 if let WEnum::Value(wl_keyboard::KeymapFormat::XkbV1) = format {
                     // Memory-map the keymap (fd is borrowed, we don't own it)
@@ -276,46 +586,7 @@ impl Dispatch<zwp_input_method_keyboard_grab_v2::ZwpInputMethodKeyboardGrabV2, (
                         unsafe { memmap_keymap(fd.as_fd().as_raw_fd(), size as usize) };
 
                     if let Some(data) = keymap_data {
-                        // Parse the keymap using KeyboardState
-                        if state.keyboard.load_keymap(&data) {
-                            log::info!("Keymap loaded successfully");
-
-                            // Set same keymap on virtual keyboard (needed for modifier clearing)
-                            state.wayland.set_virtual_keymap(&data);
-                            // Clear any stuck modifiers from the toggle keybind
-                            // (e.g., Alt leaked to the app before the grab started)
-                            state.wayland.clear_modifiers();
-
-                            // Complete enabling if transitioning
-                            let initial_mode = if state.config.behavior.startinsert {
-                                VimMode::Insert
-                            } else {
-                                VimMode::Normal
-                            };
-                            if state.ime.complete_enabling(initial_mode)
-                                || state.ime.is_fully_enabled()
-                            {
-                                // Set vim_mode for popup display to match initial mode
-                                if state.config.behavior.startinsert {
-                                    state.keypress.set_vim_mode("i");
-                                } else {
-                                    state.keypress.set_vim_mode("n");
-                                }
-                                state.keyboard.mark_ready();
-                                if let Some(ref nvim) = state.nvim {
-                                    if state.config.behavior.startinsert {
-                                        log::debug!("[IME] Restoring insert mode");
-                                        nvim.send_key("<Esc>i");
-                                    } else {
-                                        log::debug!("[IME] Restoring normal mode");
-                                        nvim.send_key("<Esc>");
-                                    }
-                                }
-                                state.update_popup();
-                            }
-                        } else {
-                            log::error!("Failed to parse keymap");
-                        }
+                        self.on_keymap(&data);
                     }
                 }
             }
@@ -326,18 +597,19 @@ impl Dispatch<zwp_input_method_keyboard_grab_v2::ZwpInputMethodKeyboardGrabV2, (
                 state: key_state,
             } => {
                 log::debug!("[GRAB] Key event: key={}, state={:?}", key, key_state);
+                self.wayland.touch_grab();
                 if let WEnum::Value(ks) = key_state {
                     if ks == wl_keyboard::KeyState::Pressed {
-                        if state.keyboard.key_repeats(key) {
-                            state.repeat.start(key);
+                        if self.keyboard.key_repeats(key) {
+                            self.repeat.start(key);
                         }
                     } else {
-                        state.repeat.stop(key);
-                        if !state.repeat.has_key() {
-                            state.repeat_timer_token = None;
+                        self.repeat.stop(key);
+                        if !self.repeat.has_key() {
+                            self.repeat_timer_token = None;
                         }
                     }
-                    state.handle_key(key, ks);
+                    self.handle_key(key, ks);
                 }
             }
             zwp_input_method_keyboard_grab_v2::Event::Modifiers {
@@ -347,15 +619,69 @@ impl Dispatch<zwp_input_method_keyboard_grab_v2::ZwpInputMethodKeyboardGrabV2, (
                 mods_locked,
                 group,
             } => {
-                state.update_modifiers(mods_depressed, mods_latched, mods_locked, group);
+                self.wayland.touch_grab();
+                self.record(TraceEvent::Modifiers {
+                    depressed: mods_depressed,
+                    latched: mods_latched,
+                    locked: mods_locked,
+                    group,
+                });
+                self.update_modifiers(mods_depressed, mods_latched, mods_locked, group);
             }
             zwp_input_method_keyboard_grab_v2::Event::RepeatInfo { rate, delay } => {
-                log::debug!("Repeat info: rate={}/s, delay={}ms", rate, delay);
-                state.keyboard.set_repeat_info(rate, delay);
+                log::debug!("[KEY] Repeat info: rate={}/s, delay={}ms", rate, delay);
+                self.keyboard.set_repeat_info(rate, delay);
             }
             _ => {}
         }
     }
+
+    /// XKB keymap text received on the current seat's grab
+    pub(crate) fn on_keymap(&mut self, data: &str) {
+        // Parse the keymap using KeyboardState
+        if !self.keyboard.load_keymap(data) {
+            log::error!("[KEY] Failed to parse keymap");
+            return;
+        }
+        log::info!("[KEY] Keymap loaded");
+
+        // Set same keymap on virtual keyboard (needed for modifier clearing)
+        self.wayland.set_virtual_keymap(data);
+        // Clear any stuck modifiers from the toggle keybind
+        // (e.g., Alt leaked to the app before the grab started)
+        self.wayland.clear_modifiers();
+
+        // Complete enabling if transitioning
+        let initial_mode = if self.config.behavior.startinsert {
+            VimMode::Insert
+        } else {
+            VimMode::Normal
+        };
+        if self.ime.complete_enabling(initial_mode) || self.ime.is_fully_enabled() {
+            // Set vim_mode for popup display to match initial mode
+            if self.config.behavior.startinsert {
+                self.keypress.set_vim_mode("i");
+            } else {
+                self.keypress.set_vim_mode("n");
+            }
+            self.keyboard.mark_ready();
+            if let Some(ref backend) = self.backend {
+                if self.config.behavior.startinsert {
+                    log::debug!("[IME] Restoring insert mode");
+                    // `gi` resumes a kept buffer at its insert position
+                    if self.buffer_clear.keeps_buffer() {
+                        backend.send_key("<Esc>gi");
+                    } else {
+                        backend.send_key("<Esc>i");
+                    }
+                } else {
+                    log::debug!("[IME] Restoring normal mode");
+                    backend.send_key("<Esc>");
+                }
+            }
+            self.update_popup();
+        }
+    }
 }
 
 // Dispatch for virtual keyboard manager (no events)
@@ -384,6 +710,152 @@ impl Dispatch<zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1, ()> for State {
     }
 }
 
+// Dispatch for data-control manager (no events)
+impl Dispatch<zwlr_data_control_manager_v1::ZwlrDataControlManagerV1, ()> for State {
+    fn event(
+        _state: &mut Self,
+        _manager: &zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+        _event: zwlr_data_control_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+// Dispatch for data-control device (per seat: clipboard and primary selection)
+impl Dispatch<zwlr_data_control_device_v1::ZwlrDataControlDeviceV1, SeatId> for State {
+    fn event(
+        state: &mut Self,
+        device: &zwlr_data_control_device_v1::ZwlrDataControlDeviceV1,
+        event: zwlr_data_control_device_v1::Event,
+        seat: &SeatId,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            // Offer MIME types are collected by the offer's own dispatch
+            zwlr_data_control_device_v1::Event::DataOffer { .. } => {}
+            zwlr_data_control_device_v1::Event::Selection { id } => {
+                state.on_selection_offer(ClipboardSelection::Clipboard, id);
+            }
+            zwlr_data_control_device_v1::Event::PrimarySelection { id } => {
+                state.on_selection_offer(ClipboardSelection::Primary, id);
+            }
+            zwlr_data_control_device_v1::Event::Finished => {
+                log::warn!("[CLIPBOARD] Data device for seat {} finished", seat);
+                device.destroy();
+            }
+            _ => {}
+        }
+    }
+
+    event_created_child!(State, zwlr_data_control_device_v1::ZwlrDataControlDeviceV1, [
+        zwlr_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (
+            zwlr_data_control_offer_v1::ZwlrDataControlOfferV1,
+            OfferMimeTypes::default()
+        ),
+    ]);
+}
+
+// Dispatch for data-control offer (collects offered MIME types)
+impl Dispatch<zwlr_data_control_offer_v1::ZwlrDataControlOfferV1, OfferMimeTypes> for State {
+    fn event(
+        _state: &mut Self,
+        _offer: &zwlr_data_control_offer_v1::ZwlrDataControlOfferV1,
+        event: zwlr_data_control_offer_v1::Event,
+        data: &OfferMimeTypes,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let zwlr_data_control_offer_v1::Event::Offer { mime_type } = event {
+            data.lock().unwrap().push(mime_type);
+        }
+    }
+}
+
+// Dispatch for data-control source (with the offered text as user data)
+impl Dispatch<zwlr_data_control_source_v1::ZwlrDataControlSourceV1, String> for State {
+    fn event(
+        _state: &mut Self,
+        source: &zwlr_data_control_source_v1::ZwlrDataControlSourceV1,
+        event: zwlr_data_control_source_v1::Event,
+        text: &String,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_data_control_source_v1::Event::Send { mime_type, fd } => {
+                log::debug!("[CLIPBOARD] Sending selection as {}", mime_type);
+                clipboard::send_text(fd, text.clone());
+            }
+            zwlr_data_control_source_v1::Event::Cancelled => {
+                // Another client took the selection
+                source.destroy();
+            }
+            _ => {}
+        }
+    }
+}
+
+// Dispatch for foreign toplevel manager (behavior.remember_per_app, fullscreen_blocklist)
+impl Dispatch<zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1, ()> for State {
+    fn event(
+        _state: &mut Self,
+        _manager: &zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1,
+        event: zwlr_foreign_toplevel_manager_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        if let zwlr_foreign_toplevel_manager_v1::Event::Finished = event {
+            log::warn!("[APP] Foreign toplevel manager finished (per-app state disabled)");
+        }
+    }
+
+    event_created_child!(State, zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1, [
+        zwlr_foreign_toplevel_manager_v1::EVT_TOPLEVEL_OPCODE => (
+            zwlr_foreign_toplevel_handle_v1::ZwlrForeignToplevelHandleV1,
+            ()
+        ),
+    ]);
+}
+
+// Dispatch for foreign toplevel handles (tracks the activated app)
+impl Dispatch<zwlr_foreign_toplevel_handle_v1::ZwlrForeignToplevelHandleV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        handle: &zwlr_foreign_toplevel_handle_v1::ZwlrForeignToplevelHandleV1,
+        event: zwlr_foreign_toplevel_handle_v1::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let id = handle.id();
+        match event {
+            zwlr_foreign_toplevel_handle_v1::Event::AppId { app_id } => {
+                state.toplevels.set_app_id(id, app_id);
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::State { state: states } => {
+                state
+                    .toplevels
+                    .set_activated(id.clone(), toplevel::is_activated(&states));
+                state
+                    .toplevels
+                    .set_fullscreen(id, toplevel::is_fullscreen(&states));
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::Done => {
+                state.toplevel_done(id);
+            }
+            zwlr_foreign_toplevel_handle_v1::Event::Closed => {
+                state.toplevel_closed(&id);
+                handle.destroy();
+            }
+            _ => {}
+        }
+    }
+}
+
 /// Memory-map a keymap file descriptor
 unsafe fn memmap_keymap(fd: std::os::fd::RawFd, size: usize) -> Option<String> {
     unsafe {
@@ -0,0 +1,124 @@
+//! Shadow Neovim dry run (`jacin --dry-run-keys "..."`)
+//!
+//! Spawns only the Neovim backend, feeds it a key string and prints every
+//! `FromNeovim` message with a timestamp. No Wayland connection is made, so
+//! plugin/config problems can be reproduced independent of the compositor.
+
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+use crate::keysym;
+use crate::neovim::{self, FromNeovim, NeovimHandle};
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+/// A key is considered settled once the backend stays quiet this long
+const SETTLE_TIMEOUT: Duration = Duration::from_millis(300);
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Split a key string in Vim notation into keys as the keyboard path
+/// produces them: a stray `<` is sent as `<lt>` and a literal space as `<Space>`
+fn split_keys(input: &str) -> Vec<String> {
+    keysym::split_keys(input)
+        .into_iter()
+        .map(|key| match key {
+            "<" => "<lt>".to_string(),
+            " " => "<Space>".to_string(),
+            _ => key.to_string(),
+        })
+        .collect()
+}
+
+struct Printer {
+    start: Instant,
+}
+
+impl Printer {
+    fn line(&self, text: &str) {
+        println!("[{:>8.3}s] {}", self.start.elapsed().as_secs_f64(), text);
+    }
+
+    /// Print messages until `done` matches one or the backend is quiet for `timeout`.
+    /// Returns false if the backend exited.
+    fn drain(
+        &self,
+        nvim: &NeovimHandle,
+        timeout: Duration,
+        done: impl Fn(&FromNeovim) -> bool,
+    ) -> bool {
+        while let Some(msg) = nvim.recv_timeout(timeout) {
+            self.line(&format!("{:?}", msg));
+            if matches!(msg, FromNeovim::NvimExited) {
+                return false;
+            }
+            if done(&msg) {
+                break;
+            }
+        }
+        true
+    }
+}
+
+/// Run the backend against `keys` and print its output to stdout
+pub fn run(mut config: Config, keys: &str) -> anyhow::Result<()> {
+    // Nothing to sync with outside a Wayland session
+    config.behavior.clipboard_sync = false;
+
+    let keys = split_keys(keys);
+    let printer = Printer {
+        start: Instant::now(),
+    };
+    let nvim = neovim::spawn_neovim(config)?;
+
+    let mut ready = false;
+    while let Some(msg) = nvim.recv_timeout(STARTUP_TIMEOUT) {
+        printer.line(&format!("{:?}", msg));
+        match msg {
+            FromNeovim::Ready => {
+                ready = true;
+                break;
+            }
+            FromNeovim::NvimExited => break,
+            _ => {}
+        }
+    }
+    if !ready {
+        anyhow::bail!("Neovim backend did not become ready");
+    }
+
+    for key in &keys {
+        printer.line(&format!("> {}", key));
+        nvim.send_key(key);
+        if !printer.drain(&nvim, SETTLE_TIMEOUT, |_| false) {
+            return Ok(());
+        }
+    }
+
+    nvim.shutdown();
+    printer.drain(&nvim, SHUTDOWN_TIMEOUT, |msg| {
+        matches!(msg, FromNeovim::NvimExited)
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_plain_and_special_keys() {
+        assert_eq!(
+            split_keys("kon<Space><CR>"),
+            vec!["k", "o", "n", "<Space>", "<CR>"]
+        );
+        assert_eq!(split_keys("あ<C-j>"), vec!["あ", "<C-j>"]);
+        assert!(split_keys("").is_empty());
+    }
+
+    #[test]
+    fn stray_less_than_is_escaped() {
+        assert_eq!(split_keys("a<b"), vec!["a", "<lt>", "b"]);
+        assert_eq!(split_keys("<>"), vec!["<lt>", ">"]);
+        assert_eq!(split_keys("< x>"), vec!["<lt>", "<Space>", "x", ">"]);
+        assert_eq!(split_keys("<<CR>"), vec!["<lt>", "<CR>"]);
+    }
+}
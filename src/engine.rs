@@ -0,0 +1,216 @@
+//! Built-in romaji → hiragana conversion, used when Neovim is unavailable
+//!
+//! jacin does its conversion in an embedded Neovim. Without an `nvim` binary
+//! the IME would otherwise only pass keys through, so this minimal engine keeps
+//! it usable: typed romaji becomes hiragana in the preedit, Enter commits it.
+//! There is no kanji conversion, no completion and no Vim editing.
+
+/// Romaji sequences and their kana
+#[rustfmt::skip]
+const TABLE: &[(&str, &str)] = &[
+    ("a", "あ"), ("i", "い"), ("u", "う"), ("e", "え"), ("o", "お"),
+    ("ka", "か"), ("ki", "き"), ("ku", "く"), ("ke", "け"), ("ko", "こ"),
+    ("kya", "きゃ"), ("kyu", "きゅ"), ("kyo", "きょ"),
+    ("sa", "さ"), ("si", "し"), ("shi", "し"), ("su", "す"), ("se", "せ"), ("so", "そ"),
+    ("sha", "しゃ"), ("shu", "しゅ"), ("she", "しぇ"), ("sho", "しょ"),
+    ("sya", "しゃ"), ("syu", "しゅ"), ("syo", "しょ"),
+    ("ta", "た"), ("ti", "ち"), ("chi", "ち"), ("tu", "つ"), ("tsu", "つ"), ("te", "て"), ("to", "と"),
+    ("cha", "ちゃ"), ("chu", "ちゅ"), ("che", "ちぇ"), ("cho", "ちょ"),
+    ("tya", "ちゃ"), ("tyu", "ちゅ"), ("tyo", "ちょ"),
+    ("na", "な"), ("ni", "に"), ("nu", "ぬ"), ("ne", "ね"), ("no", "の"), ("nn", "ん"),
+    ("nya", "にゃ"), ("nyu", "にゅ"), ("nyo", "にょ"),
+    ("ha", "は"), ("hi", "ひ"), ("hu", "ふ"), ("fu", "ふ"), ("he", "へ"), ("ho", "ほ"),
+    ("hya", "ひゃ"), ("hyu", "ひゅ"), ("hyo", "ひょ"),
+    ("fa", "ふぁ"), ("fi", "ふぃ"), ("fe", "ふぇ"), ("fo", "ふぉ"),
+    ("ma", "ま"), ("mi", "み"), ("mu", "む"), ("me", "め"), ("mo", "も"),
+    ("mya", "みゃ"), ("myu", "みゅ"), ("myo", "みょ"),
+    ("ya", "や"), ("yu", "ゆ"), ("yo", "よ"),
+    ("ra", "ら"), ("ri", "り"), ("ru", "る"), ("re", "れ"), ("ro", "ろ"),
+    ("rya", "りゃ"), ("ryu", "りゅ"), ("ryo", "りょ"),
+    ("wa", "わ"), ("wo", "を"),
+    ("ga", "が"), ("gi", "ぎ"), ("gu", "ぐ"), ("ge", "げ"), ("go", "ご"),
+    ("gya", "ぎゃ"), ("gyu", "ぎゅ"), ("gyo", "ぎょ"),
+    ("za", "ざ"), ("zi", "じ"), ("ji", "じ"), ("zu", "ず"), ("ze", "ぜ"), ("zo", "ぞ"),
+    ("ja", "じゃ"), ("ju", "じゅ"), ("je", "じぇ"), ("jo", "じょ"),
+    ("zya", "じゃ"), ("zyu", "じゅ"), ("zyo", "じょ"),
+    ("da", "だ"), ("di", "ぢ"), ("du", "づ"), ("de", "で"), ("do", "ど"),
+    ("ba", "ば"), ("bi", "び"), ("bu", "ぶ"), ("be", "べ"), ("bo", "ぼ"),
+    ("bya", "びゃ"), ("byu", "びゅ"), ("byo", "びょ"),
+    ("pa", "ぱ"), ("pi", "ぴ"), ("pu", "ぷ"), ("pe", "ぺ"), ("po", "ぽ"),
+    ("pya", "ぴゃ"), ("pyu", "ぴゅ"), ("pyo", "ぴょ"),
+    ("vu", "ゔ"),
+    ("xa", "ぁ"), ("xi", "ぃ"), ("xu", "ぅ"), ("xe", "ぇ"), ("xo", "ぉ"),
+    ("la", "ぁ"), ("li", "ぃ"), ("lu", "ぅ"), ("le", "ぇ"), ("lo", "ぉ"),
+    ("xtu", "っ"), ("ltu", "っ"),
+    ("xya", "ゃ"), ("xyu", "ゅ"), ("xyo", "ょ"), ("lya", "ゃ"), ("lyu", "ゅ"), ("lyo", "ょ"),
+];
+
+/// Full-width punctuation for ASCII symbols typed between romaji
+fn punctuation(c: char) -> Option<char> {
+    Some(match c {
+        '-' => 'ー',
+        ',' => '、',
+        '.' => '。',
+        '[' => '「',
+        ']' => '」',
+        '~' => '〜',
+        '/' => '・',
+        _ => return None,
+    })
+}
+
+/// Romaji being typed: converted kana plus letters still waiting for more
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RomajiEngine {
+    kana: String,
+    pending: String,
+}
+
+impl RomajiEngine {
+    pub fn is_empty(&self) -> bool {
+        self.kana.is_empty() && self.pending.is_empty()
+    }
+
+    /// Text for the preedit
+    pub fn preedit(&self) -> String {
+        format!("{}{}", self.kana, self.pending)
+    }
+
+    pub fn push(&mut self, c: char) {
+        if !c.is_ascii_alphabetic() {
+            // `n'` spells ん before a vowel
+            if c == '\'' && self.pending == "n" {
+                self.flush_pending();
+                return;
+            }
+            self.flush_pending();
+            self.kana.push(punctuation(c).unwrap_or(c));
+            return;
+        }
+        self.pending.push(c.to_ascii_lowercase());
+        self.convert();
+    }
+
+    /// Delete the last pending letter, or else the last kana
+    pub fn backspace(&mut self) {
+        if self.pending.pop().is_none() {
+            self.kana.pop();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.kana.clear();
+        self.pending.clear();
+    }
+
+    /// Everything typed, for a commit (a trailing `n` becomes ん)
+    pub fn take(&mut self) -> String {
+        self.flush_pending();
+        std::mem::take(&mut self.kana)
+    }
+
+    /// Only the finished kana, keeping pending letters (kana-direct input)
+    pub fn take_kana(&mut self) -> String {
+        std::mem::take(&mut self.kana)
+    }
+
+    fn convert(&mut self) {
+        while !self.pending.is_empty() {
+            if let Some(&(_, kana)) = TABLE.iter().find(|(romaji, _)| *romaji == self.pending) {
+                self.kana.push_str(kana);
+                self.pending.clear();
+                return;
+            }
+            let mut letters = self.pending.chars();
+            let first = letters.next().unwrap_or_default();
+            let second = letters.next();
+            if second == Some(first) && first != 'n' && !is_vowel(first) {
+                // Doubled consonant: small tsu, the second letter starts the next kana
+                self.kana.push('っ');
+            } else if first == 'n' && second.is_some_and(|c| !is_vowel(c) && c != 'y') {
+                self.kana.push('ん');
+            } else if !TABLE
+                .iter()
+                .any(|(romaji, _)| romaji.starts_with(&self.pending))
+            {
+                // No kana starts like this: keep the first letter as typed
+                self.kana.push(first);
+            } else {
+                return;
+            }
+            self.pending.remove(0);
+        }
+    }
+
+    fn flush_pending(&mut self) {
+        if self.pending == "n" {
+            self.kana.push('ん');
+        } else {
+            self.kana.push_str(&self.pending);
+        }
+        self.pending.clear();
+    }
+}
+
+fn is_vowel(c: char) -> bool {
+    matches!(c, 'a' | 'i' | 'u' | 'e' | 'o')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(romaji: &str) -> RomajiEngine {
+        let mut engine = RomajiEngine::default();
+        romaji.chars().for_each(|c| engine.push(c));
+        engine
+    }
+
+    #[test]
+    fn converts_romaji_to_hiragana() {
+        assert_eq!(typed("konnnichiha").preedit(), "こんにちは");
+        assert_eq!(typed("kyouhaame").preedit(), "きょうはあめ");
+        assert_eq!(typed("Tokyo").preedit(), "ときょ");
+        assert_eq!(typed("xtuxya").preedit(), "っゃ");
+    }
+
+    #[test]
+    fn doubled_consonants_and_n() {
+        assert_eq!(typed("kitte").preedit(), "きって");
+        assert_eq!(typed("kanji").preedit(), "かんじ");
+        assert_eq!(typed("kan'i").preedit(), "かんい");
+        assert_eq!(typed("konya").preedit(), "こにゃ");
+        // A trailing n waits for the next letter, and becomes ん on commit
+        let mut engine = typed("hon");
+        assert_eq!(engine.preedit(), "ほn");
+        assert_eq!(engine.take(), "ほん");
+        assert!(engine.is_empty());
+    }
+
+    #[test]
+    fn punctuation_and_unknown_letters() {
+        assert_eq!(typed("ra-men,oishii.").preedit(), "らーめん、おいしい。");
+        assert_eq!(typed("qa").preedit(), "qあ");
+        assert_eq!(typed("k1").preedit(), "k1");
+    }
+
+    #[test]
+    fn backspace_removes_pending_letters_first() {
+        let mut engine = typed("kak");
+        engine.backspace();
+        assert_eq!(engine.preedit(), "か");
+        engine.backspace();
+        assert!(engine.is_empty());
+        engine.backspace();
+        assert!(engine.is_empty());
+    }
+
+    #[test]
+    fn kana_direct_takes_finished_kana() {
+        let mut engine = typed("kaky");
+        assert_eq!(engine.take_kana(), "か");
+        assert_eq!(engine.preedit(), "ky");
+        engine.push('o');
+        assert_eq!(engine.take_kana(), "きょ");
+    }
+}
@@ -0,0 +1,279 @@
+//! Scripted fake backend (`--backend fake:<script.json>`)
+//!
+//! Plays [`FromNeovim`] messages from a script instead of running Neovim, so
+//! the coordinator, keypress state and pending-state handling can be driven
+//! end to end without an editor (CI, reproducing a backend race). A script is
+//! a list of steps, each answering one command after a latency:
+//!
+//! ```json
+//! { "steps": [
+//!     { "messages": ["Ready"] },
+//!     { "on": { "Key": "a" }, "delay_ms": 30,
+//!       "messages": [{ "Preedit": { "text": "あ", ... } }, "KeyProcessed"] },
+//!     { "on": { "Key": "q" }, "pending": "getchar", "messages": ["KeyProcessed"] }
+//! ] }
+//! ```
+//!
+//! Steps play in order. A step without `on` plays as soon as the one before
+//! it has (the first ones at startup); a step with `on` waits for that
+//! command, in [`ToNeovim`]'s JSON form. `pending` sets the pending state
+//! before the messages are sent, as the Neovim handler does. Commands the
+//! next step doesn't expect are logged and skipped; an unexpected key is
+//! answered with `KeyProcessed` so key handling doesn't wait it out.
+//! Shutdown is answered with `NvimExited`.
+
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel::{Receiver, Sender, bounded};
+use serde::Deserialize;
+
+use crate::backend::Backend;
+use crate::neovim::{
+    self, CandidateWindow, ClipboardSelection, FromNeovim, InputMode, NeovimEventSource,
+    NeovimSender, PendingState, ToNeovim, pending_state,
+};
+
+/// Channel capacity, as for Neovim
+const CHANNEL_CAPACITY: usize = 64;
+
+/// A backend script
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Script {
+    pub steps: Vec<Step>,
+}
+
+/// Messages sent in answer to a command
+#[derive(Debug, Clone, Deserialize)]
+pub struct Step {
+    /// Command to wait for (None: play right after the previous step)
+    #[serde(default)]
+    pub on: Option<ToNeovim>,
+    /// Latency before the messages are sent
+    #[serde(default)]
+    pub delay_ms: u64,
+    /// Pending state to set first
+    #[serde(default)]
+    pub pending: Option<PendingState>,
+    pub messages: Vec<FromNeovim>,
+}
+
+impl Script {
+    /// Read a script file
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("cannot read {}: {}", path.display(), e))?;
+        serde_json::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("invalid backend script {}: {}", path.display(), e))
+    }
+}
+
+impl Step {
+    /// Whether this step answers `command`. Commands are compared in their
+    /// JSON form (ToNeovim has no PartialEq).
+    fn answers(&self, command: &ToNeovim) -> bool {
+        let Some(ref on) = self.on else {
+            return false;
+        };
+        match (serde_json::to_value(on), serde_json::to_value(command)) {
+            (Ok(on), Ok(command)) => on == command,
+            _ => false,
+        }
+    }
+}
+
+/// Handle to a running script, used like a `NeovimHandle`
+pub struct FakeBackend {
+    id: u64,
+    sender: Sender<ToNeovim>,
+    receiver: Receiver<FromNeovim>,
+    events: Option<NeovimEventSource>,
+}
+
+/// Start playing `script` on a thread of its own
+pub fn spawn_fake(script: Script) -> anyhow::Result<FakeBackend> {
+    let (to_fake_tx, to_fake_rx) = bounded::<ToNeovim>(CHANNEL_CAPACITY);
+    let (from_fake_tx, from_fake_rx) = bounded::<FromNeovim>(CHANNEL_CAPACITY);
+    let (events, ping) = NeovimEventSource::new(from_fake_rx.clone())?;
+    let from_fake_tx = NeovimSender::new(from_fake_tx, ping);
+
+    thread::Builder::new()
+        .name("fake-backend".into())
+        .spawn(move || play(script, &to_fake_rx, &from_fake_tx))?;
+
+    Ok(FakeBackend {
+        id: neovim::next_backend_id(),
+        sender: to_fake_tx,
+        receiver: from_fake_rx,
+        events: Some(events),
+    })
+}
+
+/// Backend thread: answer commands from the script until shutdown
+fn play(script: Script, commands: &Receiver<ToNeovim>, events: &NeovimSender) {
+    log::info!("[FAKE] Playing a script of {} steps", script.steps.len());
+    let mut steps = script.steps.into_iter().peekable();
+    if !play_unprompted(&mut steps, events) {
+        return;
+    }
+    while let Ok(command) = commands.recv() {
+        if matches!(command, ToNeovim::Shutdown) {
+            let _ = events.send(FromNeovim::NvimExited);
+            return;
+        }
+        let played = match steps.next_if(|step| step.answers(&command)) {
+            Some(step) => play_step(step, events) && play_unprompted(&mut steps, events),
+            None => {
+                log::debug!("[FAKE] Unscripted {:?}", command);
+                !matches!(command, ToNeovim::Key(_))
+                    || events.send(FromNeovim::KeyProcessed).is_ok()
+            }
+        };
+        if !played {
+            return; // Event loop gone
+        }
+    }
+}
+
+/// Play the steps that wait for no command. False if the loop is gone.
+fn play_unprompted(
+    steps: &mut std::iter::Peekable<std::vec::IntoIter<Step>>,
+    events: &NeovimSender,
+) -> bool {
+    while let Some(step) = steps.next_if(|step| step.on.is_none()) {
+        if !play_step(step, events) {
+            return false;
+        }
+    }
+    true
+}
+
+fn play_step(step: Step, events: &NeovimSender) -> bool {
+    if step.delay_ms > 0 {
+        thread::sleep(Duration::from_millis(step.delay_ms));
+    }
+    if let Some(pending) = step.pending {
+        pending_state().store(pending);
+    }
+    step.messages
+        .into_iter()
+        .all(|message| events.send(message).is_ok())
+}
+
+impl FakeBackend {
+    /// Queue `command` (non-blocking: dropped if the channel is full)
+    fn command(&self, command: ToNeovim) {
+        let _ = self.sender.try_send(command);
+    }
+}
+
+impl Backend for FakeBackend {
+    fn id(&self) -> u64 {
+        self.id
+    }
+
+    fn take_event_source(&mut self) -> Option<NeovimEventSource> {
+        self.events.take()
+    }
+
+    fn send_key(&self, key: &str) {
+        self.command(ToNeovim::Key(key.to_string()));
+    }
+
+    fn set_input_mode(&self, mode: InputMode) {
+        self.command(ToNeovim::SetInputMode(mode));
+    }
+
+    fn select_candidate(&self, index: usize, finish: bool) {
+        self.command(ToNeovim::SelectCandidate { index, finish });
+    }
+
+    fn scroll_candidates(&self, window: CandidateWindow) {
+        self.command(ToNeovim::ScrollCandidates(window));
+    }
+
+    fn commit_prefix(&self, text: String) {
+        self.command(ToNeovim::CommitPrefix(text));
+    }
+
+    fn set_clipboard(&self, selection: ClipboardSelection, text: String) {
+        self.command(ToNeovim::SetClipboard { selection, text });
+    }
+
+    fn set_surrounding_text(&self, before: String, after: String) {
+        self.command(ToNeovim::SetSurroundingText { before, after });
+    }
+
+    fn set_dictionaries(&self, paths: Vec<String>) {
+        self.command(ToNeovim::SetDictionaries(paths));
+    }
+
+    fn restore(&self, text: String, cursor: usize, insert: bool) {
+        self.command(ToNeovim::Restore {
+            text,
+            cursor,
+            insert,
+        });
+    }
+
+    fn try_recv(&self) -> Option<FromNeovim> {
+        self.receiver.try_recv().ok()
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Option<FromNeovim> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    fn shutdown(&self) {
+        let _ = self
+            .sender
+            .send_timeout(ToNeovim::Shutdown, Duration::from_millis(200));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = r#"{ "steps": [
+        { "messages": ["Ready"] },
+        { "on": { "Key": "a" }, "delay_ms": 10,
+          "messages": [{ "Commit": "あ" }, "KeyProcessed"] },
+        { "on": { "SelectCandidate": { "index": 1, "finish": true } },
+          "messages": [{ "Commit": "亜" }] }
+    ] }"#;
+
+    fn next(backend: &FakeBackend) -> Option<FromNeovim> {
+        backend.recv_timeout(Duration::from_secs(1))
+    }
+
+    #[test]
+    fn script_answers_commands_in_order() {
+        let script: Script = serde_json::from_str(SCRIPT).unwrap();
+        let backend = spawn_fake(script).unwrap();
+        assert!(matches!(next(&backend), Some(FromNeovim::Ready)));
+
+        // Not the next step's command: skipped, the key acknowledged
+        backend.select_candidate(1, true);
+        backend.send_key("b");
+        assert!(matches!(next(&backend), Some(FromNeovim::KeyProcessed)));
+
+        backend.send_key("a");
+        assert!(matches!(next(&backend), Some(FromNeovim::Commit(t)) if t == "あ"));
+        assert!(matches!(next(&backend), Some(FromNeovim::KeyProcessed)));
+        backend.select_candidate(1, true);
+        assert!(matches!(next(&backend), Some(FromNeovim::Commit(t)) if t == "亜"));
+
+        backend.shutdown();
+        assert!(matches!(next(&backend), Some(FromNeovim::NvimExited)));
+    }
+
+    #[test]
+    fn scripts_name_pending_states() {
+        let step: Step =
+            serde_json::from_str(r#"{ "pending": "text_object", "messages": [] }"#).unwrap();
+        assert_eq!(step.pending, Some(PendingState::TextObject));
+        assert!(serde_json::from_str::<Script>(r#"{ "steps": [{ "on": "Nope" }] }"#).is_err());
+    }
+}
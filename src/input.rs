@@ -1,8 +1,18 @@
 use wayland_client::protocol::wl_keyboard;
 
+use std::time::{Duration, Instant};
+
+use wayland_client::protocol::wl_pointer::AxisSource;
+use xkbcommon::xkb::Keysym;
+
 use crate::State;
-use crate::keysym::{is_printable, keysym_to_vim};
-use crate::neovim::{PendingState, pending_state};
+use crate::config::{CandidateLabels, JapaneseKeyAction, JapaneseKeys, Keybinds};
+use crate::keysym::{is_printable, keysym_to_vim, typed_text};
+use crate::metrics::{self, Stage};
+use crate::neovim::{ClipboardSelection, InputMode, PendingState, pending_state};
+use crate::replay::TraceEvent;
+use crate::state::{Compose, SeatId};
+use crate::trace::{self, Span};
 
 /// Scope guard that logs elapsed time on drop.
 struct PerfGuard {
@@ -32,9 +42,64 @@ impl Drop for PerfGuard {
     }
 }
 
+/// Scroll distance of one wheel detent in surface pixels (libinput convention)
+const WHEEL_STEP: f32 = 15.0;
+
+/// A key acting on the visible candidate list instead of the buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CandidateKey {
+    /// Row label (1-9 by default): accept that row of the visible page (0-based)
+    Pick(usize),
+    /// Next (1) / previous (-1) candidate, wrapping around
+    Step(i32),
+    /// Page down (1) / page up (-1)
+    Page(i32),
+    /// Accept the selected candidate
+    Confirm,
+}
+
+impl CandidateKey {
+    /// `labels` is None when typing labels is disabled
+    fn parse(key: &str, keybinds: &Keybinds, labels: Option<CandidateLabels>) -> Option<Self> {
+        let bound = |binding: &str| !binding.is_empty() && binding == key;
+        if bound(&keybinds.candidate_next) {
+            Some(Self::Step(1))
+        } else if bound(&keybinds.candidate_prev) {
+            Some(Self::Step(-1))
+        } else if bound(&keybinds.candidate_page_down) {
+            Some(Self::Page(1))
+        } else if bound(&keybinds.candidate_page_up) {
+            Some(Self::Page(-1))
+        } else if bound(&keybinds.candidate_confirm) {
+            Some(Self::Confirm)
+        } else {
+            labels?.row(key).map(Self::Pick)
+        }
+    }
+}
+
+/// Candidate `step` rows away from `selected`, wrapping around the list
+fn step_candidate(selected: usize, step: i32, total: usize) -> usize {
+    (selected as i64 + i64::from(step)).rem_euclid(total as i64) as usize
+}
+
+/// Action bound to `keysym` if it is one of the dedicated JIS keys
+fn japanese_key_action(keys: &JapaneseKeys, keysym: Keysym) -> Option<JapaneseKeyAction> {
+    match keysym {
+        Keysym::Zenkaku_Hankaku | Keysym::Zenkaku | Keysym::Hankaku => Some(keys.zenkaku_hankaku),
+        Keysym::Henkan_Mode => Some(keys.henkan),
+        Keysym::Muhenkan => Some(keys.muhenkan),
+        Keysym::Hiragana_Katakana | Keysym::Hiragana | Keysym::Katakana => {
+            Some(keys.hiragana_katakana)
+        }
+        _ => None,
+    }
+}
+
 impl State {
     pub(crate) fn handle_key(&mut self, key: u32, key_state: wl_keyboard::KeyState) {
         let mut _perf = PerfGuard::new("handle_key");
+        let _trace = trace::span(Span::Key);
         let state_str = match key_state {
             wl_keyboard::KeyState::Pressed => "pressed",
             wl_keyboard::KeyState::Released => "released",
@@ -47,15 +112,24 @@ impl State {
             self.keyboard.ctrl_pressed
         );
 
-        // Handle key releases
+        // Handle key releases; a tap of keybinds.toggle_tap ends on one
         if key_state != wl_keyboard::KeyState::Pressed {
+            let tap_timeout = Duration::from_millis(self.config.behavior.tap_timeout);
+            let tapped = self
+                .keyboard
+                .release_is_tap(key, Instant::now(), tap_timeout);
             self.keyboard.handle_key_release(key);
+            if tapped {
+                log::info!("[KEY] Toggle key tapped");
+                self.handle_ime_toggle();
+            }
             return;
         }
 
         // Check if key should be ignored
         if self.keyboard.should_ignore_key(key) {
             log::debug!("[KEY] Ignoring key {}", key);
+            self.keyboard.press_for_tap(key, false, Instant::now());
             return;
         }
 
@@ -65,24 +139,166 @@ impl State {
             return;
         };
         log::debug!("[KEY] keysym={:?}, utf8={:?}", keysym, utf8);
+        self.keyboard
+            .press_for_tap(key, self.toggle_tap == Some(keysym), Instant::now());
 
-        // Convert key to Vim notation and send to Neovim
-        let vim_key = keysym_to_vim(
+        // Allowlisted keys go to the application as they are
+        if self.passthrough_keys.contains(
             self.keyboard.ctrl_pressed,
             self.keyboard.alt_pressed,
+            self.keyboard.shift_pressed,
             keysym,
             &utf8,
+        ) {
+            log::debug!("[KEY] Passthrough allowlist: {:?}", keysym);
+            self.wayland.send_virtual_key(
+                key,
+                self.keyboard.mods_depressed,
+                self.keyboard.mods_latched,
+                self.keyboard.mods_locked,
+                self.keyboard.mods_group,
+            );
+            return;
+        }
+
+        // Dead keys and Multi_key sequences: only the composed character goes on
+        let (keysym, utf8) = if self.keyboard.ctrl_pressed || self.keyboard.alt_pressed {
+            (keysym, utf8)
+        } else {
+            match self.keyboard.compose(keysym) {
+                Compose::Key => (keysym, utf8),
+                Compose::Composed(keysym, text) => {
+                    log::debug!("[KEY] Composed {:?}", text);
+                    (keysym, text)
+                }
+                Compose::Composing | Compose::Cancelled => {
+                    log::debug!("[KEY] Compose sequence {:?}", keysym);
+                    return;
+                }
+            }
+        };
+
+        // Convert key to Vim notation and send to Neovim, unless [keymap]
+        // sends another key for it
+        let (ctrl, alt, shift) = (
+            self.keyboard.ctrl_pressed,
+            self.keyboard.alt_pressed,
+            self.keyboard.shift_pressed,
         );
-        log::debug!("[KEY] vim_key={:?}", vim_key);
+        let remapped = self
+            .key_table
+            .lookup(ctrl, alt, shift, keysym, &utf8)
+            .map(str::to_string);
+        // Dedicated Japanese keys without a [keymap] entry: act locally, or
+        // stand for the key that does what they mean
+        let remapped = match remapped {
+            None if !ctrl && !alt => {
+                match japanese_key_action(&self.config.japanese_keys, keysym) {
+                    Some(action) => match self.run_japanese_key(action) {
+                        Some(key) => Some(key),
+                        None => return,
+                    },
+                    None => None,
+                }
+            }
+            remapped => remapped,
+        };
+        let (vim_key, logical_key, utf8) = match remapped {
+            // Already the logical key: langmap doesn't apply
+            Some(key) => {
+                log::debug!("[KEY] keymap: {:?}", key);
+                let utf8 = typed_text(&key).to_string();
+                (Some(key.clone()), Some(key), utf8)
+            }
+            None => {
+                let vim_key = keysym_to_vim(ctrl, alt, shift, keysym, &utf8);
+                // jacin's own keybinds match the key under the user's langmap
+                let logical_key = vim_key
+                    .as_deref()
+                    .map(|k| self.langmap.apply(k).into_owned());
+                (vim_key, logical_key, utf8)
+            }
+        };
+        log::debug!("[KEY] vim_key={:?}, logical={:?}", vim_key, logical_key);
+        if let Some(ref key) = vim_key {
+            self.record(TraceEvent::Key { key: key.clone() });
+        }
+
+        // Kana-direct toggle is handled locally, never forwarded
+        if let Some(ref logical_key) = logical_key
+            && !self.config.keybinds.kana_direct.is_empty()
+            && *logical_key == self.config.keybinds.kana_direct
+        {
+            self.toggle_input_mode();
+            return;
+        }
+
+        // Privacy toggle too
+        if let Some(ref logical_key) = logical_key
+            && !self.config.keybinds.privacy.is_empty()
+            && *logical_key == self.config.keybinds.privacy
+        {
+            self.set_privacy(!self.privacy);
+            return;
+        }
+
+        // Local candidate filter: typed keys narrow the list, not the buffer
+        if let Some(ref logical_key) = logical_key
+            && self.handle_filter_key(logical_key, &utf8)
+        {
+            return;
+        }
+
+        // Candidate list shown: number and paging keys act on it
+        if let Some(ref logical_key) = logical_key
+            && self.handle_candidate_key(logical_key)
+        {
+            return;
+        }
+
+        // No Neovim to send keys to: built-in romaji conversion
+        if self.backend.is_none() {
+            self.current_keycode = Some(key);
+            self.handle_fallback_key(logical_key.as_deref(), &utf8);
+            self.current_keycode = None;
+            return;
+        }
+
+        // The backend recognizes the commit keybind; hand it the bound key
+        let vim_key = match logical_key {
+            Some(logical_key) if logical_key == self.config.keybinds.commit => Some(logical_key),
+            _ => vim_key,
+        };
+
+        // Backend still starting: replayed once it is ready
+        if let Some(ref vim_key) = vim_key
+            && self.startup_keys.is_waiting()
+        {
+            if !self.startup_keys.push(vim_key, key) {
+                log::warn!("[NVIM] Key {:?} dropped while the backend starts", vim_key);
+            }
+            return;
+        }
 
         if let Some(ref vim_key) = vim_key {
             // Drain stale messages before setting current_keycode to avoid
             // stale PassthroughKey using the new key's keycode
             self.drain_stale_nvim_messages();
 
+            // Register paste from the system clipboard: the selection first
+            if let Some(selection) = ClipboardSelection::from_register(vim_key)
+                && matches!(
+                    pending_state().load(),
+                    PendingState::InsertRegister | PendingState::NormalRegister
+                )
+            {
+                self.sync_clipboard_for_paste(selection);
+            }
+
             // Store raw keycode for potential passthrough
             self.current_keycode = Some(key);
 
+            self.keypress.on_key_sent(vim_key);
             self.send_to_nvim(vim_key);
             // Wait for Neovim response with timeout
             self.wait_for_nvim_response();
@@ -111,14 +327,9 @@ impl State {
                 self.update_popup();
             }
 
+            // After the push: an operator now pending takes the motion (and
+            // its count) into its entry
             self.keypress.set_pending(after);
-        } else if is_printable(&utf8) {
-            // Fallback: if no Neovim or no vim key, use local preedit
-            if self.nvim.is_none() {
-                self.ime.preedit.push_str(&utf8);
-                log::debug!("[PREEDIT] buffer={:?}", self.ime.preedit);
-                self.update_preedit();
-            }
         } else {
             log::debug!(
                 "[SKIP] no printable char, ctrl={}",
@@ -128,15 +339,308 @@ impl State {
         _perf.mode = self.keypress.vim_mode.clone();
     }
 
+    /// Run a dedicated Japanese key's local action. Returns the key it
+    /// stands for instead, None when nothing is left to send.
+    fn run_japanese_key(&mut self, action: JapaneseKeyAction) -> Option<String> {
+        log::debug!("[KEY] Japanese key: {:?}", action);
+        match action {
+            JapaneseKeyAction::None => None,
+            JapaneseKeyAction::Toggle => {
+                self.handle_ime_toggle();
+                None
+            }
+            JapaneseKeyAction::Kana => {
+                self.toggle_input_mode();
+                None
+            }
+            JapaneseKeyAction::Commit => {
+                Some(self.config.keybinds.commit.clone()).filter(|key| !key.is_empty())
+            }
+            JapaneseKeyAction::Convert => Some("<Space>".to_string()),
+        }
+    }
+
+    /// Key while Neovim is unavailable: typed romaji becomes hiragana in the
+    /// preedit (engine module), keys the engine has no use for reach the app
+    fn handle_fallback_key(&mut self, key: Option<&str>, utf8: &str) {
+        // The preedit was committed or dropped elsewhere (toggle, deactivate)
+        if self.ime.preedit.is_empty() {
+            self.engine.clear();
+        }
+        let composing = !self.engine.is_empty();
+        let typing =
+            !self.keyboard.ctrl_pressed && !self.keyboard.alt_pressed && is_printable(utf8);
+        match key {
+            Some("<BS>") if composing => self.engine.backspace(),
+            Some("<Esc>") if composing => self.engine.clear(),
+            Some(key) if composing && (key == "<CR>" || key == self.config.keybinds.commit) => {
+                let text = self.engine.take();
+                self.on_commit(text);
+                return;
+            }
+            Some("<Space>") if !composing => {
+                self.on_passthrough_key();
+                return;
+            }
+            _ if typing => utf8.chars().for_each(|c| self.engine.push(c)),
+            _ => {
+                // Anything else (arrows, shortcuts) commits what was typed first
+                if composing {
+                    let text = self.engine.take();
+                    self.on_commit(text);
+                }
+                self.on_passthrough_key();
+                return;
+            }
+        }
+        if self.ime.input_mode == InputMode::KanaDirect {
+            let kana = self.engine.take_kana();
+            if !kana.is_empty() {
+                self.commit_to_app(&kana);
+            }
+        }
+        let preedit = self.engine.preedit();
+        let cursor = preedit.len();
+        log::debug!("[PREEDIT] fallback buffer={:?}", preedit);
+        self.ime.set_preedit(preedit, cursor, cursor);
+        self.update_preedit();
+    }
+
+    /// Number keys accept a candidate from the visible page, PageUp/PageDown turn
+    /// the page and select its first row. Returns true if the key was consumed.
+    fn handle_candidate_key(&mut self, key: &str) -> bool {
+        let total = self.ime.candidates.len();
+        if total == 0 || !self.ime.is_fully_enabled() || pending_state().load().is_pending() {
+            return false;
+        }
+        let behavior = &self.config.behavior;
+        let labels = behavior
+            .candidate_number_keys
+            .then_some(behavior.candidate_labels);
+        let Some(action) = CandidateKey::parse(key, &self.config.keybinds, labels) else {
+            return false;
+        };
+        let Some(popup) = self.popup.as_mut() else {
+            return false;
+        };
+        let (index, finish) = match action {
+            CandidateKey::Pick(row) => (popup.candidate_page_start() + row, true),
+            CandidateKey::Step(step) => {
+                // Through the whole list, of which only a window may be here:
+                // wrapping around leaves the window for the backend's next one
+                let offset = self.ime.candidate_offset;
+                let target = step_candidate(
+                    offset + self.ime.selected_candidate,
+                    step,
+                    self.ime.candidate_total,
+                );
+                match target.checked_sub(offset).filter(|&i| i < total) {
+                    Some(index) => (index, false),
+                    None => {
+                        log::debug!("[KEY] {} -> candidate {} (other window)", key, target);
+                        self.select_backend_candidate(target, false);
+                        return true;
+                    }
+                }
+            }
+            CandidateKey::Page(pages) => (popup.page_candidates(pages, total), false),
+            CandidateKey::Confirm => (self.ime.selected_candidate, true),
+        };
+        // A number past the end of a short list does nothing
+        if index >= total {
+            return true;
+        }
+        log::debug!("[KEY] {} -> candidate {} (finish={})", key, index, finish);
+        self.select_candidate(index, finish);
+        true
+    }
+
+    /// The filter key (`keybinds.candidate_filter`) starts narrowing the shown
+    /// candidates locally. While filtering, candidate keys act on the matches,
+    /// printable keys edit the query, <CR> accepts the selected match and
+    /// <Esc> (or the filter key) shows the full list again; any other key
+    /// leaves the filter and is processed as usual. Returns true if the key
+    /// was consumed.
+    fn handle_filter_key(&mut self, key: &str, utf8: &str) -> bool {
+        let binding = &self.config.keybinds.candidate_filter;
+        if self.ime.filter_query().is_none() {
+            if binding.is_empty()
+                || key != binding
+                || !self.ime.is_fully_enabled()
+                || pending_state().load().is_pending()
+                || !self.ime.start_filter()
+            {
+                return false;
+            }
+            log::debug!("[FILTER] Filtering {} candidates", self.ime.candidate_total);
+            // A long list is filtered whole once the backend sends all of it
+            self.request_candidate_window();
+            self.on_filter_changed();
+            return true;
+        }
+        if key == "<Esc>" || key == binding {
+            self.ime.cancel_filter();
+        } else if key == "<BS>" {
+            if !self.ime.pop_filter() {
+                self.ime.cancel_filter();
+            }
+        } else if key == "<CR>" {
+            if !self.ime.candidates.is_empty() {
+                self.select_candidate(self.ime.selected_candidate, true);
+            }
+            return true;
+        } else if self.handle_candidate_key(key) {
+            return true;
+        } else if !self.keyboard.ctrl_pressed && !self.keyboard.alt_pressed && is_printable(utf8) {
+            utf8.chars().for_each(|c| self.ime.push_filter(c));
+        } else {
+            log::debug!("[FILTER] {} leaves the filter", key);
+            self.ime.cancel_filter();
+            self.on_filter_changed();
+            return false;
+        }
+        self.on_filter_changed();
+        true
+    }
+
+    /// Redraw the filtered list (and the previewed candidate)
+    fn on_filter_changed(&mut self) {
+        self.update_popup();
+        if self.config.behavior.candidate_preview {
+            self.update_preedit();
+        }
+    }
+
+    /// Move the backend's completion selection to `index` (accepting it with
+    /// `finish`) and wait for the result. While filtering, `index` is in the
+    /// matches: moving the selection stays local, accepting maps it back to
+    /// the full list. Accepts are learned (`behavior.learn_candidates`)
+    /// outside privacy mode.
+    fn select_candidate(&mut self, index: usize, finish: bool) {
+        let index = if self.ime.filter_query().is_none() {
+            index
+        } else if finish {
+            match self.ime.finish_filter(index) {
+                Some(index) => index,
+                None => return,
+            }
+        } else {
+            self.ime.selected_candidate = index;
+            self.on_filter_changed();
+            return;
+        };
+        if finish
+            && !self.privacy
+            && let Some(ref mut history) = self.candidate_history
+            && let Some((reading, candidate)) = self.ime.candidate_choice(index)
+        {
+            history.record(reading, candidate);
+        }
+        // External candidates stand in for their reading without the backend
+        if let Some((text, cursor)) = self.ime.external_choice(index) {
+            if finish {
+                self.accept_external(text, cursor);
+            } else {
+                self.ime.selected_candidate = index;
+                self.on_filter_changed();
+            }
+            return;
+        }
+        self.select_backend_candidate(self.ime.backend_index(index), finish);
+    }
+
+    /// Move the backend's selection to its candidate `index` and wait for
+    /// the result
+    fn select_backend_candidate(&mut self, index: usize, finish: bool) {
+        self.drain_stale_nvim_messages();
+        if let Some(ref backend) = self.backend {
+            backend.select_candidate(index, finish);
+        }
+        self.wait_for_nvim_response();
+    }
+
+    /// Accept an external provider's candidate: the preedit becomes `text`
+    /// and the backend continues from it. Without a backend the result is
+    /// committed, as the romaji engine holds only unconverted text.
+    fn accept_external(&mut self, text: String, cursor: usize) {
+        log::debug!("[PROVIDER] Accepted, preedit {:?}", text);
+        let Some(ref backend) = self.backend else {
+            self.engine.clear();
+            self.on_commit(text);
+            return;
+        };
+        backend.restore(text.clone(), cursor, true);
+        self.ime.clear_candidates();
+        self.ime.set_preedit(text, cursor, cursor);
+        self.update_preedit();
+        self.update_popup();
+    }
+
+    /// Left click on `seat`'s popup: accept the candidate under the pointer
+    pub(crate) fn handle_popup_click(&mut self, seat: SeatId) {
+        if seat != self.current_seat
+            || !self.pointer.is_over(seat)
+            || !self.ime.is_fully_enabled()
+            || pending_state().load().is_pending()
+        {
+            return;
+        }
+        let (x, y) = (self.pointer.x, self.pointer.y);
+        let Some(index) = self.popup.as_ref().and_then(|p| p.candidate_at(x, y)) else {
+            return;
+        };
+        log::debug!("[POINTER] Click on candidate {}", index);
+        self.select_candidate(index, true);
+    }
+
+    /// Vertical scroll of `value` surface pixels over `seat`'s popup
+    pub(crate) fn handle_popup_scroll(&mut self, seat: SeatId, value: f32) {
+        if seat != self.current_seat || !self.pointer.is_over(seat) {
+            return;
+        }
+        let finger = self.pointer.axis_source == Some(AxisSource::Finger);
+        let Some(popup) = self.popup.as_mut() else {
+            return;
+        };
+        let Some(row_height) = popup.candidate_row_height() else {
+            return;
+        };
+        // Touchpads scroll by distance, wheels one row per detent
+        let rows = if finger {
+            value / row_height
+        } else {
+            value / WHEEL_STEP
+        };
+        if finger {
+            self.pointer.track_scroll(rows, Instant::now());
+        }
+        if popup.scroll_candidates(rows) {
+            self.update_popup();
+        }
+    }
+
+    /// Fingers lifted from the touchpad: continue the scroll kinetically
+    pub(crate) fn handle_popup_scroll_stop(&mut self, seat: SeatId) {
+        if seat != self.current_seat {
+            return;
+        }
+        let velocity = self.pointer.take_fling(Instant::now());
+        if velocity != 0.0
+            && let Some(ref mut popup) = self.popup
+        {
+            popup.fling_candidates(velocity);
+        }
+    }
+
     pub(crate) fn send_to_nvim(&self, key: &str) {
-        if let Some(ref nvim) = self.nvim {
-            nvim.send_key(key);
+        if let Some(ref backend) = self.backend {
+            backend.send_key(key);
         }
     }
 
     fn drain_stale_nvim_messages(&mut self) {
         loop {
-            let msg = self.nvim.as_ref().and_then(|n| n.try_recv());
+            let msg = self.backend.as_ref().and_then(|n| n.try_recv());
             match msg {
                 Some(stale) => {
                     log::debug!("[NVIM] Draining stale message: {:?}", stale);
@@ -151,21 +655,30 @@ impl State {
         use crate::neovim::FromNeovim;
 
         let _perf = PerfGuard::new("nvim_rpc");
+        let _trace = trace::span(Span::Rpc);
 
         // Loop until KeyProcessed or deadline (200ms)
-        let deadline = std::time::Instant::now() + std::time::Duration::from_millis(200);
+        let start = std::time::Instant::now();
+        let deadline = start + std::time::Duration::from_millis(200);
         loop {
             let remaining = deadline.saturating_duration_since(std::time::Instant::now());
             if remaining.is_zero() {
                 log::debug!("[NVIM] wait_for_nvim_response: deadline reached");
+                metrics::timed_out(Stage::Rpc);
                 break;
             }
-            let msg = self.nvim.as_ref().and_then(|n| n.recv_timeout(remaining));
+            let msg = self
+                .backend
+                .as_ref()
+                .and_then(|n| n.recv_timeout(remaining));
             match msg {
                 Some(msg) => {
                     let is_key_processed = matches!(msg, FromNeovim::KeyProcessed);
                     self.handle_nvim_message(msg);
                     if is_key_processed {
+                        let elapsed = start.elapsed();
+                        metrics::record(Stage::Rpc, elapsed);
+                        self.last_rpc = Some(elapsed);
                         break;
                     }
                 }
@@ -186,6 +699,7 @@ impl State {
 
         self.keyboard
             .update_modifiers(mods_depressed, mods_latched, mods_locked, group);
+        self.wayland.forward_group(group);
 
         if old_ctrl != self.keyboard.ctrl_pressed {
             log::debug!(
@@ -203,3 +717,83 @@ impl State {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(key: &str, number_keys: bool) -> Option<CandidateKey> {
+        let labels = number_keys.then_some(CandidateLabels::Numbers);
+        CandidateKey::parse(key, &Keybinds::default(), labels)
+    }
+
+    #[test]
+    fn candidate_keys() {
+        assert_eq!(parse("1", true), Some(CandidateKey::Pick(0)));
+        assert_eq!(parse("9", true), Some(CandidateKey::Pick(8)));
+        assert_eq!(parse("0", true), None);
+        assert_eq!(parse("<C-1>", true), None);
+        assert_eq!(parse("a", true), None);
+        assert_eq!(parse("<PageDown>", true), Some(CandidateKey::Page(1)));
+        assert_eq!(parse("<PageUp>", false), Some(CandidateKey::Page(-1)));
+        assert_eq!(parse("<Tab>", false), Some(CandidateKey::Step(1)));
+        assert_eq!(parse("<S-Tab>", false), Some(CandidateKey::Step(-1)));
+        assert_eq!(parse("1", false), None);
+        // Confirm is unbound by default
+        assert_eq!(parse("<CR>", true), None);
+    }
+
+    #[test]
+    fn kana_labels_pick_rows() {
+        let parse =
+            |key| CandidateKey::parse(key, &Keybinds::default(), Some(CandidateLabels::Kana));
+        assert_eq!(parse("あ"), Some(CandidateKey::Pick(0)));
+        assert_eq!(parse("ｵ"), Some(CandidateKey::Pick(4)));
+        assert_eq!(parse("1"), None);
+        assert_eq!(parse("<Tab>"), Some(CandidateKey::Step(1)));
+    }
+
+    #[test]
+    fn rebound_candidate_keys() {
+        let keybinds = Keybinds {
+            candidate_next: "<C-n>".into(),
+            candidate_prev: String::new(),
+            candidate_confirm: "<CR>".into(),
+            ..Keybinds::default()
+        };
+        let parse = |key| CandidateKey::parse(key, &keybinds, Some(CandidateLabels::Numbers));
+        assert_eq!(parse("<C-n>"), Some(CandidateKey::Step(1)));
+        assert_eq!(parse("<Tab>"), None);
+        assert_eq!(parse("<S-Tab>"), None);
+        assert_eq!(parse("<CR>"), Some(CandidateKey::Confirm));
+    }
+
+    #[test]
+    fn japanese_keys_map_to_their_actions() {
+        let keys = JapaneseKeys {
+            muhenkan: JapaneseKeyAction::None,
+            ..JapaneseKeys::default()
+        };
+        let action = |keysym| japanese_key_action(&keys, keysym);
+        assert_eq!(
+            action(Keysym::Zenkaku_Hankaku),
+            Some(JapaneseKeyAction::Toggle)
+        );
+        assert_eq!(action(Keysym::Hankaku), Some(JapaneseKeyAction::Toggle));
+        assert_eq!(
+            action(Keysym::Henkan_Mode),
+            Some(JapaneseKeyAction::Convert)
+        );
+        assert_eq!(action(Keysym::Muhenkan), Some(JapaneseKeyAction::None));
+        assert_eq!(action(Keysym::Katakana), Some(JapaneseKeyAction::Kana));
+        assert_eq!(action(Keysym::space), None);
+    }
+
+    #[test]
+    fn stepping_wraps_around() {
+        assert_eq!(step_candidate(0, 1, 3), 1);
+        assert_eq!(step_candidate(2, 1, 3), 0);
+        assert_eq!(step_candidate(0, -1, 3), 2);
+        assert_eq!(step_candidate(0, 1, 1), 0);
+    }
+}
@@ -1,8 +1,13 @@
 //! Keysym-to-Vim notation conversion
 //!
-//! Pure functions for converting XKB keysyms and modifier state into Vim key notation.
+//! Pure functions for converting XKB keysyms and modifier state into Vim key notation,
+//! plus the `Langmap` remap table applied to the result and the user's `KeyTable`
+//! consulted before it.
 //! No I/O or mutable state — all functions are side-effect free.
 
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+
 use xkbcommon::xkb;
 
 /// Map keysym to its Vim special-key name (e.g. `Return` → `"CR"`).
@@ -16,16 +21,72 @@ fn special_key_name(keysym: xkb::Keysym) -> Option<&'static str> {
         Keysym::Return | Keysym::KP_Enter => Some("CR"),
         Keysym::BackSpace => Some("BS"),
         Keysym::Tab => Some("Tab"),
+        Keysym::ISO_Left_Tab => Some("S-Tab"),
         Keysym::Escape => Some("Esc"),
         Keysym::space => Some("Space"),
         Keysym::Left => Some("Left"),
         Keysym::Right => Some("Right"),
         Keysym::Up => Some("Up"),
         Keysym::Down => Some("Down"),
+        Keysym::Page_Up => Some("PageUp"),
+        Keysym::Page_Down => Some("PageDown"),
+        Keysym::Home => Some("Home"),
+        Keysym::End => Some("End"),
+        Keysym::Insert => Some("Insert"),
+        Keysym::Delete => Some("Del"),
+        Keysym::F1 => Some("F1"),
+        Keysym::F2 => Some("F2"),
+        Keysym::F3 => Some("F3"),
+        Keysym::F4 => Some("F4"),
+        Keysym::F5 => Some("F5"),
+        Keysym::F6 => Some("F6"),
+        Keysym::F7 => Some("F7"),
+        Keysym::F8 => Some("F8"),
+        Keysym::F9 => Some("F9"),
+        Keysym::F10 => Some("F10"),
+        Keysym::F11 => Some("F11"),
+        Keysym::F12 => Some("F12"),
+        // Keypad: digits and operators (NumLock on), navigation (NumLock off)
+        Keysym::KP_0 => Some("k0"),
+        Keysym::KP_1 => Some("k1"),
+        Keysym::KP_2 => Some("k2"),
+        Keysym::KP_3 => Some("k3"),
+        Keysym::KP_4 => Some("k4"),
+        Keysym::KP_5 => Some("k5"),
+        Keysym::KP_6 => Some("k6"),
+        Keysym::KP_7 => Some("k7"),
+        Keysym::KP_8 => Some("k8"),
+        Keysym::KP_9 => Some("k9"),
+        Keysym::KP_Add => Some("kPlus"),
+        Keysym::KP_Subtract => Some("kMinus"),
+        Keysym::KP_Multiply => Some("kMultiply"),
+        Keysym::KP_Divide => Some("kDivide"),
+        Keysym::KP_Decimal => Some("kPoint"),
+        Keysym::KP_Separator => Some("kComma"),
+        Keysym::KP_Equal => Some("kEqual"),
+        Keysym::KP_Home => Some("kHome"),
+        Keysym::KP_End => Some("kEnd"),
+        Keysym::KP_Page_Up => Some("kPageUp"),
+        Keysym::KP_Page_Down => Some("kPageDown"),
+        Keysym::KP_Insert => Some("kInsert"),
+        Keysym::KP_Delete => Some("kDel"),
+        Keysym::KP_Begin => Some("kOrigin"),
+        Keysym::KP_Left => Some("kLeft"),
+        Keysym::KP_Right => Some("kRight"),
+        Keysym::KP_Up => Some("kUp"),
+        Keysym::KP_Down => Some("kDown"),
         _ => None,
     }
 }
 
+/// Whether Shift is spelled out for a special key (`<S-End>`, `<S-CR>`).
+/// `<Space>` and `<BS>` stay unshifted so they keep working while Shift is held
+/// for capitals (`<BS>` on an empty preedit passes through to the app), and
+/// `<S-Tab>` (ISO_Left_Tab) already carries it.
+fn takes_shift(name: &str) -> bool {
+    !matches!(name, "S-Tab" | "Space" | "BS")
+}
+
 /// Map keysym to a lowercase letter (a-z), if applicable.
 fn keysym_to_letter(keysym: xkb::Keysym) -> Option<char> {
     use xkbcommon::xkb::Keysym;
@@ -37,6 +98,17 @@ fn keysym_to_letter(keysym: xkb::Keysym) -> Option<char> {
     }
 }
 
+/// Map an uppercase letter keysym (A-Z, Shift held) to its lowercase letter.
+fn keysym_to_shifted_letter(keysym: xkb::Keysym) -> Option<char> {
+    use xkbcommon::xkb::Keysym;
+
+    if keysym.raw() >= Keysym::A.raw() && keysym.raw() <= Keysym::Z.raw() {
+        Some((keysym.raw() - Keysym::A.raw() + b'a' as u32) as u8 as char)
+    } else {
+        None
+    }
+}
+
 /// Returns `true` if `utf8` contains at least one printable (non-control) character.
 pub(crate) fn is_printable(utf8: &str) -> bool {
     !utf8.is_empty() && !utf8.chars().all(char::is_control)
@@ -48,13 +120,16 @@ pub(crate) fn is_printable(utf8: &str) -> bool {
 pub(crate) fn keysym_to_vim(
     ctrl: bool,
     alt: bool,
+    shift: bool,
     keysym: xkb::Keysym,
     utf8: &str,
 ) -> Option<String> {
+    let s = |name: &str| if shift && takes_shift(name) { "S-" } else { "" };
+
     // Handle Alt combinations: <A-key>
     if alt {
         if let Some(name) = special_key_name(keysym) {
-            return Some(format!("<A-{name}>"));
+            return Some(format!("<A-{}{name}>", s(name)));
         }
         if let Some(c) = keysym_to_letter(keysym) {
             return Some(format!("<A-{c}>"));
@@ -69,17 +144,21 @@ pub(crate) fn keysym_to_vim(
     // Handle Ctrl combinations: <C-key>
     if ctrl {
         if let Some(name) = special_key_name(keysym) {
-            return Some(format!("<C-{name}>"));
+            return Some(format!("<C-{}{name}>", s(name)));
         }
         if let Some(c) = keysym_to_letter(keysym) {
-            return Some(format!("<C-{c}>"));
+            return Some(format!("<C-{}{c}>", if shift { "S-" } else { "" }));
+        }
+        // Ctrl+Shift+v: <C-S-v> (Vim doesn't tell <C-V> from <C-v>)
+        if let Some(c) = keysym_to_shifted_letter(keysym) {
+            return Some(format!("<C-S-{c}>"));
         }
         return None;
     }
 
     // No modifier: special keys get <> wrapper, printable chars returned as-is
     if let Some(name) = special_key_name(keysym) {
-        return Some(format!("<{name}>"));
+        return Some(format!("<{}{name}>", s(name)));
     }
     if is_printable(utf8) {
         // Escape '<' as '<lt>' for nvim_input (bare '<' starts a key sequence)
@@ -89,9 +168,280 @@ pub(crate) fn keysym_to_vim(
     }
 }
 
+/// Physical → logical character remap in Vim 'langmap' syntax.
+///
+/// Applied to Vim keys before matching jacin's own keybinds, so they follow the
+/// user's logical layout. Keys sent to Neovim are not remapped — Neovim applies
+/// its own 'langmap' in the modes where Vim would.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Langmap(HashMap<char, char>);
+
+impl Langmap {
+    /// Parse a 'langmap' value: comma-separated parts, each either pairs
+    /// (`"aAbB"`) or from/to lists (`"ab;AB"`). `\` escapes `;`, `,` and `\`.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut map = HashMap::new();
+        let mut chars = spec.chars();
+        // Each part as (char, escaped) so escaped separators are taken literally
+        let mut part: Vec<(char, bool)> = Vec::new();
+        loop {
+            let next = chars.next();
+            match next {
+                Some('\\') => match chars.next() {
+                    Some(c) => part.push((c, true)),
+                    None => return Err("trailing backslash".to_string()),
+                },
+                Some(',') | None => {
+                    Self::parse_part(&part, &mut map)?;
+                    part.clear();
+                    if next.is_none() {
+                        break;
+                    }
+                }
+                Some(c) => part.push((c, false)),
+            }
+        }
+        Ok(Self(map))
+    }
+
+    fn parse_part(part: &[(char, bool)], map: &mut HashMap<char, char>) -> Result<(), String> {
+        let plain = |chars: &[(char, bool)]| chars.iter().map(|&(c, _)| c).collect::<Vec<_>>();
+        if let Some(semi) = part.iter().position(|&(c, escaped)| c == ';' && !escaped) {
+            let from = plain(&part[..semi]);
+            let to = plain(&part[semi + 1..]);
+            if from.len() != to.len() {
+                let from: String = from.into_iter().collect();
+                let to: String = to.into_iter().collect();
+                return Err(format!("{from:?} and {to:?} differ in length"));
+            }
+            map.extend(from.into_iter().zip(to));
+        } else {
+            let pairs = plain(part);
+            if pairs.len() % 2 != 0 {
+                let pairs: String = pairs.into_iter().collect();
+                return Err(format!("{pairs:?} has an unpaired character"));
+            }
+            map.extend(pairs.chunks(2).map(|p| (p[0], p[1])));
+        }
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn map_char(&self, c: char) -> char {
+        self.0.get(&c).copied().unwrap_or(c)
+    }
+
+    /// Remap a Vim key: plain characters and the key of `<C-x>`/`<A-x>`.
+    /// Special keys (`<CR>`, `<Space>`, …) pass through unchanged.
+    pub fn apply<'a>(&self, vim_key: &'a str) -> Cow<'a, str> {
+        if self.is_empty() {
+            return Cow::Borrowed(vim_key);
+        }
+        let single = |s: &str| {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(c),
+                _ => None,
+            }
+        };
+        if vim_key == "<lt>" {
+            return Cow::Owned(escape_key(self.map_char('<')));
+        }
+        if let Some(c) = single(vim_key) {
+            return Cow::Owned(escape_key(self.map_char(c)));
+        }
+        for prefix in ["<C-", "<A-"] {
+            if let Some(inner) = vim_key
+                .strip_prefix(prefix)
+                .and_then(|rest| rest.strip_suffix('>'))
+                && let Some(c) = single(inner)
+            {
+                let mapped = self.map_char(c);
+                if mapped != '<' && mapped != '>' && !mapped.is_whitespace() {
+                    return Cow::Owned(format!("{prefix}{mapped}>"));
+                }
+            }
+        }
+        Cow::Borrowed(vim_key)
+    }
+}
+
+/// Vim notation for a single typed character
+fn escape_key(c: char) -> String {
+    match c {
+        '<' => "<lt>".to_string(),
+        ' ' => "<Space>".to_string(),
+        _ => c.to_string(),
+    }
+}
+
+/// Key with the modifiers held, as `[keymap]` names it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Chord {
+    ctrl: bool,
+    alt: bool,
+    shift: bool,
+    keysym: u32,
+}
+
+/// User key translation (`[keymap]`): keys sent to Neovim as other Vim keys.
+///
+/// Entries name an XKB keysym with optional `C-`, `A-` and `S-` prefixes
+/// (`"Muhenkan"`, `"C-Henkan_Mode"`, `"colon"`). Shift is part of the
+/// character for keys that type one (`"colon"` is Shift+`;` on a US layout),
+/// so `S-` only matters for keys that don't.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct KeyTable(HashMap<Chord, String>);
+
+impl KeyTable {
+    /// Parse `[keymap]` entries: key name → Vim notation
+    pub fn parse<'a>(
+        entries: impl IntoIterator<Item = (&'a String, &'a String)>,
+    ) -> Result<Self, String> {
+        let mut map = HashMap::new();
+        for (name, vim_key) in entries {
+            if vim_key.is_empty() {
+                return Err(format!("{name:?} maps to nothing"));
+            }
+            map.insert(parse_chord(name)?, vim_key.clone());
+        }
+        Ok(Self(map))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Vim key to send for `keysym` with these modifiers, if remapped
+    pub fn lookup(
+        &self,
+        ctrl: bool,
+        alt: bool,
+        shift: bool,
+        keysym: xkb::Keysym,
+        utf8: &str,
+    ) -> Option<&str> {
+        if self.is_empty() {
+            return None;
+        }
+        let chord = Chord::pressed(ctrl, alt, shift, keysym, utf8);
+        self.0.get(&chord).map(String::as_str)
+    }
+}
+
+/// Keys named like `[keymap]` entries (`keybinds.passthrough`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct KeySet(HashSet<Chord>);
+
+impl KeySet {
+    pub fn parse<'a>(names: impl IntoIterator<Item = &'a String>) -> Result<Self, String> {
+        names
+            .into_iter()
+            .map(|name| parse_chord(name))
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    /// Whether `keysym` with these modifiers is in the set
+    pub fn contains(
+        &self,
+        ctrl: bool,
+        alt: bool,
+        shift: bool,
+        keysym: xkb::Keysym,
+        utf8: &str,
+    ) -> bool {
+        !self.0.is_empty()
+            && self
+                .0
+                .contains(&Chord::pressed(ctrl, alt, shift, keysym, utf8))
+    }
+}
+
+impl Chord {
+    fn pressed(ctrl: bool, alt: bool, shift: bool, keysym: xkb::Keysym, utf8: &str) -> Self {
+        Self {
+            ctrl,
+            alt,
+            // Shift went into the character typed
+            shift: shift && (ctrl || alt || !is_printable(utf8)),
+            keysym: keysym.raw(),
+        }
+    }
+}
+
+/// `"C-A-Henkan_Mode"` → chord
+fn parse_chord(name: &str) -> Result<Chord, String> {
+    let mut chord = Chord {
+        ctrl: false,
+        alt: false,
+        shift: false,
+        keysym: 0,
+    };
+    let mut rest = name;
+    // A modifier prefix needs a key after it ("C" alone is the keysym)
+    while rest.len() > 2 {
+        let flag = match rest.get(..2) {
+            Some("C-") => &mut chord.ctrl,
+            Some("A-") => &mut chord.alt,
+            Some("S-") => &mut chord.shift,
+            _ => break,
+        };
+        *flag = true;
+        rest = &rest[2..];
+    }
+    chord.keysym = parse_keysym(rest)
+        .map_err(|e| format!("{name:?}: {e}"))?
+        .raw();
+    Ok(chord)
+}
+
+/// XKB keysym by name (`"Alt_R"`, `"Caps_Lock"`)
+pub(crate) fn parse_keysym(name: &str) -> Result<xkb::Keysym, String> {
+    let keysym = xkb::keysym_from_name(name, xkb::KEYSYM_NO_FLAGS);
+    if keysym == xkb::Keysym::NoSymbol {
+        return Err(format!("unknown keysym {name:?}"));
+    }
+    Ok(keysym)
+}
+
+/// Text a remapped key types: the key itself if it is plain characters
+/// (`";"`), nothing for special keys (`"<C-j>"`)
+pub(crate) fn typed_text(vim_key: &str) -> &str {
+    if vim_key.contains('<') { "" } else { vim_key }
+}
+
+/// Split a key string in Vim notation into individual keys: a `<...>` group
+/// with a non-empty, whitespace-free name is one key, anything else (a stray
+/// `<` included) one character each
+pub(crate) fn split_keys(keys: &str) -> Vec<&str> {
+    let mut split = Vec::new();
+    let mut rest = keys;
+    while let Some(c) = rest.chars().next() {
+        let group = (c == '<')
+            .then(|| rest[1..].find(['<', '>']).map(|i| i + 1))
+            .flatten()
+            .filter(|&end| {
+                rest.as_bytes()[end] == b'>'
+                    && end > 1
+                    && !rest[1..end].contains(char::is_whitespace)
+            });
+        let len = group.map_or(c.len_utf8(), |end| end + 1);
+        split.push(&rest[..len]);
+        rest = &rest[len..];
+    }
+    split
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{is_printable, keysym_to_letter, keysym_to_vim, special_key_name};
+    use super::{
+        KeySet, KeyTable, Langmap, is_printable, keysym_to_letter, keysym_to_vim, special_key_name,
+        split_keys, typed_text,
+    };
     use xkbcommon::xkb::Keysym;
 
     // ── special_key_name ──
@@ -106,6 +456,7 @@ mod tests {
     fn special_key_name_all_mapped() {
         assert_eq!(special_key_name(Keysym::BackSpace), Some("BS"));
         assert_eq!(special_key_name(Keysym::Tab), Some("Tab"));
+        assert_eq!(special_key_name(Keysym::ISO_Left_Tab), Some("S-Tab"));
         assert_eq!(special_key_name(Keysym::Escape), Some("Esc"));
         assert_eq!(special_key_name(Keysym::space), Some("Space"));
         assert_eq!(special_key_name(Keysym::Left), Some("Left"));
@@ -166,11 +517,11 @@ mod tests {
     #[test]
     fn printable_ascii() {
         assert_eq!(
-            keysym_to_vim(false, false, Keysym::a, "a"),
+            keysym_to_vim(false, false, false, Keysym::a, "a"),
             Some("a".into())
         );
         assert_eq!(
-            keysym_to_vim(false, false, Keysym::z, "z"),
+            keysym_to_vim(false, false, false, Keysym::z, "z"),
             Some("z".into())
         );
     }
@@ -178,7 +529,7 @@ mod tests {
     #[test]
     fn uppercase_via_utf8() {
         assert_eq!(
-            keysym_to_vim(false, false, Keysym::A, "A"),
+            keysym_to_vim(false, false, false, Keysym::A, "A"),
             Some("A".into())
         );
     }
@@ -186,11 +537,11 @@ mod tests {
     #[test]
     fn digit_keys() {
         assert_eq!(
-            keysym_to_vim(false, false, Keysym::_0, "0"),
+            keysym_to_vim(false, false, false, Keysym::_0, "0"),
             Some("0".into())
         );
         assert_eq!(
-            keysym_to_vim(false, false, Keysym::_9, "9"),
+            keysym_to_vim(false, false, false, Keysym::_9, "9"),
             Some("9".into())
         );
     }
@@ -198,23 +549,23 @@ mod tests {
     #[test]
     fn special_keys() {
         assert_eq!(
-            keysym_to_vim(false, false, Keysym::Return, ""),
+            keysym_to_vim(false, false, false, Keysym::Return, ""),
             Some("<CR>".into())
         );
         assert_eq!(
-            keysym_to_vim(false, false, Keysym::BackSpace, ""),
+            keysym_to_vim(false, false, false, Keysym::BackSpace, ""),
             Some("<BS>".into())
         );
         assert_eq!(
-            keysym_to_vim(false, false, Keysym::Escape, ""),
+            keysym_to_vim(false, false, false, Keysym::Escape, ""),
             Some("<Esc>".into())
         );
         assert_eq!(
-            keysym_to_vim(false, false, Keysym::Tab, ""),
+            keysym_to_vim(false, false, false, Keysym::Tab, ""),
             Some("<Tab>".into())
         );
         assert_eq!(
-            keysym_to_vim(false, false, Keysym::space, ""),
+            keysym_to_vim(false, false, false, Keysym::space, ""),
             Some("<Space>".into())
         );
     }
@@ -222,7 +573,7 @@ mod tests {
     #[test]
     fn kp_enter_maps_to_cr() {
         assert_eq!(
-            keysym_to_vim(false, false, Keysym::KP_Enter, ""),
+            keysym_to_vim(false, false, false, Keysym::KP_Enter, ""),
             Some("<CR>".into())
         );
     }
@@ -230,45 +581,202 @@ mod tests {
     #[test]
     fn arrow_keys() {
         assert_eq!(
-            keysym_to_vim(false, false, Keysym::Left, ""),
+            keysym_to_vim(false, false, false, Keysym::Left, ""),
             Some("<Left>".into())
         );
         assert_eq!(
-            keysym_to_vim(false, false, Keysym::Right, ""),
+            keysym_to_vim(false, false, false, Keysym::Right, ""),
             Some("<Right>".into())
         );
         assert_eq!(
-            keysym_to_vim(false, false, Keysym::Up, ""),
+            keysym_to_vim(false, false, false, Keysym::Up, ""),
             Some("<Up>".into())
         );
         assert_eq!(
-            keysym_to_vim(false, false, Keysym::Down, ""),
+            keysym_to_vim(false, false, false, Keysym::Down, ""),
             Some("<Down>".into())
         );
+        assert_eq!(
+            keysym_to_vim(false, false, false, Keysym::Page_Up, ""),
+            Some("<PageUp>".into())
+        );
+        assert_eq!(
+            keysym_to_vim(false, false, false, Keysym::Page_Down, ""),
+            Some("<PageDown>".into())
+        );
+    }
+
+    #[test]
+    fn navigation_and_function_keys() {
+        for (keysym, vim) in [
+            (Keysym::Home, "<Home>"),
+            (Keysym::End, "<End>"),
+            (Keysym::Insert, "<Insert>"),
+            (Keysym::Delete, "<Del>"),
+            (Keysym::F1, "<F1>"),
+            (Keysym::F12, "<F12>"),
+        ] {
+            assert_eq!(
+                keysym_to_vim(false, false, false, keysym, ""),
+                Some(vim.into())
+            );
+        }
+    }
+
+    #[test]
+    fn keypad_keys() {
+        // NumLock on: the key still types its character, but as a keypad key
+        assert_eq!(
+            keysym_to_vim(false, false, false, Keysym::KP_1, "1"),
+            Some("<k1>".into())
+        );
+        assert_eq!(
+            keysym_to_vim(false, false, false, Keysym::KP_Add, "+"),
+            Some("<kPlus>".into())
+        );
+        assert_eq!(
+            keysym_to_vim(false, false, false, Keysym::KP_Decimal, "."),
+            Some("<kPoint>".into())
+        );
+        // NumLock off
+        assert_eq!(
+            keysym_to_vim(false, false, false, Keysym::KP_End, ""),
+            Some("<kEnd>".into())
+        );
+        assert_eq!(
+            keysym_to_vim(false, false, false, Keysym::KP_Delete, ""),
+            Some("<kDel>".into())
+        );
+    }
+
+    #[test]
+    fn shifted_special_keys() {
+        assert_eq!(
+            keysym_to_vim(false, false, true, Keysym::End, ""),
+            Some("<S-End>".into())
+        );
+        assert_eq!(
+            keysym_to_vim(true, false, true, Keysym::Home, ""),
+            Some("<C-S-Home>".into())
+        );
+        assert_eq!(
+            keysym_to_vim(false, true, true, Keysym::F5, ""),
+            Some("<A-S-F5>".into())
+        );
+        assert_eq!(
+            keysym_to_vim(false, false, true, Keysym::Left, ""),
+            Some("<S-Left>".into())
+        );
+        // Space and printable characters ignore Shift
+        assert_eq!(
+            keysym_to_vim(false, false, true, Keysym::space, " "),
+            Some("<Space>".into())
+        );
+        assert_eq!(
+            keysym_to_vim(false, false, true, Keysym::ISO_Left_Tab, ""),
+            Some("<S-Tab>".into())
+        );
+        assert_eq!(
+            keysym_to_vim(false, false, true, Keysym::A, "A"),
+            Some("A".into())
+        );
+    }
+
+    #[test]
+    fn shift_text_keys() {
+        assert_eq!(
+            keysym_to_vim(false, false, true, Keysym::Return, "\r"),
+            Some("<S-CR>".into())
+        );
+        assert_eq!(
+            keysym_to_vim(false, false, true, Keysym::Tab, "\t"),
+            Some("<S-Tab>".into())
+        );
+        assert_eq!(
+            keysym_to_vim(true, false, true, Keysym::Escape, ""),
+            Some("<C-S-Esc>".into())
+        );
+        assert_eq!(
+            keysym_to_vim(false, false, true, Keysym::BackSpace, ""),
+            Some("<BS>".into())
+        );
+    }
+
+    #[test]
+    fn ctrl_shift_letter() {
+        // Uppercase keysym as reported with Shift held
+        assert_eq!(
+            keysym_to_vim(true, false, true, Keysym::V, "\x16"),
+            Some("<C-S-v>".into())
+        );
+        assert_eq!(
+            keysym_to_vim(true, false, true, Keysym::v, "\x16"),
+            Some("<C-S-v>".into())
+        );
+        assert_eq!(
+            keysym_to_vim(true, false, false, Keysym::v, "\x16"),
+            Some("<C-v>".into())
+        );
+        // Alt+Shift keeps the uppercase character
+        assert_eq!(
+            keysym_to_vim(false, true, true, Keysym::V, "V"),
+            Some("<A-V>".into())
+        );
+    }
+
+    #[test]
+    fn ctrl_navigation_keys() {
+        assert_eq!(
+            keysym_to_vim(true, false, false, Keysym::Home, ""),
+            Some("<C-Home>".into())
+        );
+        assert_eq!(
+            keysym_to_vim(true, false, false, Keysym::Delete, ""),
+            Some("<C-Del>".into())
+        );
+        assert_eq!(
+            keysym_to_vim(false, true, false, Keysym::F12, ""),
+            Some("<A-F12>".into())
+        );
     }
 
     #[test]
     fn less_than_escaped() {
         assert_eq!(
-            keysym_to_vim(false, false, Keysym::less, "<"),
+            keysym_to_vim(false, false, false, Keysym::less, "<"),
             Some("<lt>".into())
         );
     }
 
     #[test]
     fn bare_modifier_returns_none() {
-        assert_eq!(keysym_to_vim(false, false, Keysym::Shift_L, ""), None);
-        assert_eq!(keysym_to_vim(false, false, Keysym::Shift_R, ""), None);
-        assert_eq!(keysym_to_vim(false, false, Keysym::Control_L, ""), None);
-        assert_eq!(keysym_to_vim(false, false, Keysym::Control_R, ""), None);
-        assert_eq!(keysym_to_vim(false, false, Keysym::Alt_L, ""), None);
-        assert_eq!(keysym_to_vim(false, false, Keysym::Super_L, ""), None);
+        assert_eq!(
+            keysym_to_vim(false, false, false, Keysym::Shift_L, ""),
+            None
+        );
+        assert_eq!(
+            keysym_to_vim(false, false, false, Keysym::Shift_R, ""),
+            None
+        );
+        assert_eq!(
+            keysym_to_vim(false, false, false, Keysym::Control_L, ""),
+            None
+        );
+        assert_eq!(
+            keysym_to_vim(false, false, false, Keysym::Control_R, ""),
+            None
+        );
+        assert_eq!(keysym_to_vim(false, false, false, Keysym::Alt_L, ""), None);
+        assert_eq!(
+            keysym_to_vim(false, false, false, Keysym::Super_L, ""),
+            None
+        );
     }
 
     #[test]
     fn japanese_utf8() {
         assert_eq!(
-            keysym_to_vim(false, false, Keysym::NoSymbol, "あ"),
+            keysym_to_vim(false, false, false, Keysym::NoSymbol, "あ"),
             Some("あ".into())
         );
     }
@@ -276,7 +784,7 @@ mod tests {
     #[test]
     fn kanji_utf8() {
         assert_eq!(
-            keysym_to_vim(false, false, Keysym::NoSymbol, "漢"),
+            keysym_to_vim(false, false, false, Keysym::NoSymbol, "漢"),
             Some("漢".into())
         );
     }
@@ -286,11 +794,11 @@ mod tests {
     #[test]
     fn ctrl_letter() {
         assert_eq!(
-            keysym_to_vim(true, false, Keysym::a, "a"),
+            keysym_to_vim(true, false, false, Keysym::a, "a"),
             Some("<C-a>".into())
         );
         assert_eq!(
-            keysym_to_vim(true, false, Keysym::z, "z"),
+            keysym_to_vim(true, false, false, Keysym::z, "z"),
             Some("<C-z>".into())
         );
     }
@@ -298,19 +806,19 @@ mod tests {
     #[test]
     fn ctrl_special_keys() {
         assert_eq!(
-            keysym_to_vim(true, false, Keysym::Return, ""),
+            keysym_to_vim(true, false, false, Keysym::Return, ""),
             Some("<C-CR>".into())
         );
         assert_eq!(
-            keysym_to_vim(true, false, Keysym::BackSpace, ""),
+            keysym_to_vim(true, false, false, Keysym::BackSpace, ""),
             Some("<C-BS>".into())
         );
         assert_eq!(
-            keysym_to_vim(true, false, Keysym::Tab, ""),
+            keysym_to_vim(true, false, false, Keysym::Tab, ""),
             Some("<C-Tab>".into())
         );
         assert_eq!(
-            keysym_to_vim(true, false, Keysym::space, ""),
+            keysym_to_vim(true, false, false, Keysym::space, ""),
             Some("<C-Space>".into())
         );
     }
@@ -318,7 +826,7 @@ mod tests {
     #[test]
     fn ctrl_non_letter_non_special_returns_none() {
         // Ctrl+digit: keysym_to_letter returns None, not special → None
-        assert_eq!(keysym_to_vim(true, false, Keysym::_1, "1"), None);
+        assert_eq!(keysym_to_vim(true, false, false, Keysym::_1, "1"), None);
     }
 
     // ── keysym_to_vim: Alt modifier ──
@@ -326,11 +834,11 @@ mod tests {
     #[test]
     fn alt_letter() {
         assert_eq!(
-            keysym_to_vim(false, true, Keysym::a, "a"),
+            keysym_to_vim(false, true, false, Keysym::a, "a"),
             Some("<A-a>".into())
         );
         assert_eq!(
-            keysym_to_vim(false, true, Keysym::z, "z"),
+            keysym_to_vim(false, true, false, Keysym::z, "z"),
             Some("<A-z>".into())
         );
     }
@@ -338,11 +846,11 @@ mod tests {
     #[test]
     fn alt_special_keys() {
         assert_eq!(
-            keysym_to_vim(false, true, Keysym::Return, ""),
+            keysym_to_vim(false, true, false, Keysym::Return, ""),
             Some("<A-CR>".into())
         );
         assert_eq!(
-            keysym_to_vim(false, true, Keysym::Escape, ""),
+            keysym_to_vim(false, true, false, Keysym::Escape, ""),
             Some("<A-Esc>".into())
         );
     }
@@ -350,11 +858,11 @@ mod tests {
     #[test]
     fn alt_printable_utf8() {
         assert_eq!(
-            keysym_to_vim(false, true, Keysym::_1, "1"),
+            keysym_to_vim(false, true, false, Keysym::_1, "1"),
             Some("<A-1>".into())
         );
         assert_eq!(
-            keysym_to_vim(false, true, Keysym::NoSymbol, "あ"),
+            keysym_to_vim(false, true, false, Keysym::NoSymbol, "あ"),
             Some("<A-あ>".into())
         );
     }
@@ -362,14 +870,14 @@ mod tests {
     #[test]
     fn alt_less_than_escaped() {
         assert_eq!(
-            keysym_to_vim(false, true, Keysym::less, "<"),
+            keysym_to_vim(false, true, false, Keysym::less, "<"),
             Some("<A-lt>".into())
         );
     }
 
     #[test]
     fn alt_bare_modifier_returns_none() {
-        assert_eq!(keysym_to_vim(false, true, Keysym::Shift_L, ""), None);
+        assert_eq!(keysym_to_vim(false, true, false, Keysym::Shift_L, ""), None);
     }
 
     // ── keysym_to_vim: Ctrl+Alt (Alt takes priority) ──
@@ -378,7 +886,7 @@ mod tests {
     fn ctrl_alt_letter_alt_wins() {
         // When both ctrl and alt are true, alt branch is entered first
         assert_eq!(
-            keysym_to_vim(true, true, Keysym::a, "a"),
+            keysym_to_vim(true, true, false, Keysym::a, "a"),
             Some("<A-a>".into())
         );
     }
@@ -386,8 +894,148 @@ mod tests {
     #[test]
     fn ctrl_alt_special_key_alt_wins() {
         assert_eq!(
-            keysym_to_vim(true, true, Keysym::Return, ""),
+            keysym_to_vim(true, true, false, Keysym::Return, ""),
             Some("<A-CR>".into())
         );
     }
+
+    // ── Langmap ──
+
+    #[test]
+    fn langmap_pair_and_list_forms() {
+        let map = Langmap::parse("aAbB,xy;XY").unwrap();
+        assert_eq!(map.apply("a"), "A");
+        assert_eq!(map.apply("b"), "B");
+        assert_eq!(map.apply("y"), "Y");
+        assert_eq!(map.apply("c"), "c");
+    }
+
+    #[test]
+    fn langmap_escaped_separators() {
+        let map = Langmap::parse(r"ö\;,ä\,,q\\").unwrap();
+        assert_eq!(map.apply("ö"), ";");
+        assert_eq!(map.apply("ä"), ",");
+        assert_eq!(map.apply("q"), "\\");
+    }
+
+    #[test]
+    fn langmap_modifier_keys() {
+        let map = Langmap::parse("jh;hj").unwrap();
+        assert_eq!(map.apply("<C-j>"), "<C-h>");
+        assert_eq!(map.apply("<A-h>"), "<A-j>");
+        assert_eq!(map.apply("<CR>"), "<CR>");
+        assert_eq!(map.apply("<C-CR>"), "<C-CR>");
+    }
+
+    #[test]
+    fn langmap_less_than_and_space() {
+        let map = Langmap::parse("<x,y ").unwrap();
+        assert_eq!(map.apply("<lt>"), "x");
+        assert_eq!(map.apply("y"), "<Space>");
+        let map = Langmap::parse("z<").unwrap();
+        assert_eq!(map.apply("z"), "<lt>");
+        assert_eq!(map.apply("<C-z>"), "<C-z>");
+    }
+
+    #[test]
+    fn langmap_empty_passes_through() {
+        let map = Langmap::parse("").unwrap();
+        assert!(map.is_empty());
+        assert_eq!(map.apply("a"), "a");
+        assert_eq!(Langmap::default().apply("<C-j>"), "<C-j>");
+    }
+
+    #[test]
+    fn langmap_invalid() {
+        assert!(Langmap::parse("abc").is_err());
+        assert!(Langmap::parse("ab;A").is_err());
+        assert!(Langmap::parse("a\\").is_err());
+    }
+
+    // ── KeyTable ──
+
+    fn key_table(entries: &[(&str, &str)]) -> Result<KeyTable, String> {
+        let entries: Vec<(String, String)> = entries
+            .iter()
+            .map(|&(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        KeyTable::parse(entries.iter().map(|(k, v)| (k, v)))
+    }
+
+    #[test]
+    fn key_table_matches_keysym_and_modifiers() {
+        let table = key_table(&[
+            ("Muhenkan", "<C-j>"),
+            ("S-Muhenkan", "<Esc>"),
+            ("C-Henkan_Mode", "<C-g>"),
+            ("colon", ";"),
+            ("semicolon", ":"),
+        ])
+        .unwrap();
+        let lookup = |ctrl, shift, keysym, utf8| table.lookup(ctrl, false, shift, keysym, utf8);
+        assert_eq!(lookup(false, false, Keysym::Muhenkan, ""), Some("<C-j>"));
+        assert_eq!(lookup(false, true, Keysym::Muhenkan, ""), Some("<Esc>"));
+        assert_eq!(lookup(true, false, Keysym::Muhenkan, ""), None);
+        assert_eq!(lookup(true, false, Keysym::Henkan_Mode, ""), Some("<C-g>"));
+        assert_eq!(lookup(false, false, Keysym::Henkan_Mode, ""), None);
+        // Shift+; types a colon: Shift is part of the character
+        assert_eq!(lookup(false, true, Keysym::colon, ":"), Some(";"));
+        assert_eq!(lookup(false, false, Keysym::semicolon, ";"), Some(":"));
+        assert_eq!(lookup(true, false, Keysym::semicolon, ";"), None);
+        assert_eq!(lookup(false, false, Keysym::a, "a"), None);
+    }
+
+    #[test]
+    fn key_set_matches_like_the_key_table() {
+        let names = ["C-c", "XF86AudioPlay"].map(String::from);
+        let set = KeySet::parse(&names).unwrap();
+        assert!(set.contains(true, false, false, Keysym::c, "\u{3}"));
+        assert!(!set.contains(false, false, false, Keysym::c, "c"));
+        // Ctrl+Shift+C is another key
+        assert!(!set.contains(true, false, true, Keysym::C, "\u{3}"));
+        assert!(set.contains(false, false, false, Keysym::XF86_AudioPlay, ""));
+        assert!(!KeySet::default().contains(false, false, false, Keysym::c, "c"));
+        assert!(KeySet::parse(&["C-Nothing".to_string()]).is_err());
+    }
+
+    #[test]
+    fn key_table_rejects_unknown_keys() {
+        assert!(key_table(&[]).unwrap().is_empty());
+        assert!(key_table(&[("C-", "x")]).is_err());
+        assert!(key_table(&[("C-Hyper_Key", "x")]).is_err());
+        assert!(key_table(&[("Muhenkan", "")]).is_err());
+        assert!(key_table(&[("あいう", "x")]).is_err());
+        // A lone modifier letter is the keysym
+        let table = key_table(&[("C", "<Esc>")]).unwrap();
+        assert_eq!(
+            table.lookup(false, false, true, Keysym::C, "C"),
+            Some("<Esc>")
+        );
+    }
+
+    #[test]
+    fn remapped_keys_type_plain_characters_only() {
+        assert_eq!(typed_text(";"), ";");
+        assert_eq!(typed_text("<C-j>"), "");
+    }
+
+    // ── split_keys ──
+
+    #[test]
+    fn split_keys_groups_special_keys() {
+        assert_eq!(
+            split_keys("kon<Space><CR>"),
+            vec!["k", "o", "n", "<Space>", "<CR>"]
+        );
+        assert_eq!(split_keys("あ<C-j>"), vec!["あ", "<C-j>"]);
+        assert!(split_keys("").is_empty());
+    }
+
+    #[test]
+    fn split_keys_leaves_stray_less_than_alone() {
+        assert_eq!(split_keys("a<b"), vec!["a", "<", "b"]);
+        assert_eq!(split_keys("<>"), vec!["<", ">"]);
+        assert_eq!(split_keys("< x>"), vec!["<", " ", "x", ">"]);
+        assert_eq!(split_keys("<<CR>"), vec!["<", "<CR>"]);
+    }
 }
@@ -0,0 +1,219 @@
+//! Logger setup (`--log-level`, `--log-file`, `jacinctl log`)
+//!
+//! Filters use env_logger syntax: a default level plus per-module overrides,
+//! e.g. `info,jacin::coordinator=debug`. `--log-level` wins over `RUST_LOG`;
+//! without either only warnings and errors are shown. Autostarted IMEs usually
+//! lose stderr, so `--log-file` writes to `$XDG_STATE_HOME/jacin/jacin.log`
+//! instead (the previous run is kept as `jacin.log.old`). The filter can be
+//! replaced while running with `jacinctl log <filter>`.
+
+use std::fs::{File, OpenOptions};
+use std::path::PathBuf;
+use std::sync::{OnceLock, PoisonError, RwLock};
+
+use env_logger::{Target, WriteStyle};
+use log::{LevelFilter, Log};
+
+use crate::config::Config;
+
+/// Filter when neither `--log-level` nor `RUST_LOG` is given
+const DEFAULT_FILTER: &str = "warn";
+/// Log file name inside the state directory
+const LOG_FILE: &str = "jacin.log";
+
+/// Where the log file goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogFile {
+    /// `<state_dir>/jacin.log`
+    StateDir,
+    Path(PathBuf),
+}
+
+/// Logging command line options
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LogOptions {
+    /// `--log-level <filter>`
+    pub filter: Option<String>,
+    /// `--log-file` or `--log-file=<path>`
+    pub file: Option<LogFile>,
+}
+
+impl LogOptions {
+    /// Pick the logging options out of the command line (other arguments are ignored)
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--log-level" {
+                let filter = args.next().ok_or("--log-level requires a filter")?;
+                options.filter = Some(filter);
+            } else if let Some(filter) = arg.strip_prefix("--log-level=") {
+                options.filter = Some(filter.to_string());
+            } else if arg == "--log-file" {
+                options.file = Some(LogFile::StateDir);
+            } else if let Some(path) = arg.strip_prefix("--log-file=") {
+                options.file = Some(LogFile::Path(PathBuf::from(path)));
+            }
+        }
+        Ok(options)
+    }
+
+    /// Effective filter: `--log-level`, then `RUST_LOG`, then the default
+    fn filter_spec(&self, env: Option<String>) -> String {
+        self.filter
+            .clone()
+            .or(env.filter(|s| !s.is_empty()))
+            .unwrap_or_else(|| DEFAULT_FILTER.to_string())
+    }
+}
+
+/// env_logger behind a lock so the filter can be swapped at runtime
+struct Logger {
+    inner: RwLock<env_logger::Logger>,
+    spec: RwLock<String>,
+    /// Log file (None = stderr); cloned into each rebuilt env_logger
+    file: Option<File>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        read(&self.inner).enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        read(&self.inner).log(record);
+    }
+
+    fn flush(&self) {
+        read(&self.inner).flush();
+    }
+}
+
+fn read<T>(lock: &RwLock<T>) -> std::sync::RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+fn build(spec: &str, file: Option<&File>) -> std::io::Result<env_logger::Logger> {
+    let mut builder = env_logger::Builder::new();
+    builder.parse_filters(spec);
+    if let Some(file) = file {
+        builder
+            .target(Target::Pipe(Box::new(file.try_clone()?)))
+            .write_style(WriteStyle::Never);
+    }
+    Ok(builder.build())
+}
+
+/// Open the log file, keeping the previous run's log as `<name>.old`
+fn open_log_file(path: &PathBuf) -> std::io::Result<File> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut old = path.clone().into_os_string();
+    old.push(".old");
+    let _ = std::fs::rename(path, old);
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Install the logger. Returns the log file path when logging to a file.
+pub fn init(options: &LogOptions) -> anyhow::Result<Option<PathBuf>> {
+    let spec = options.filter_spec(std::env::var("RUST_LOG").ok());
+    let path = match &options.file {
+        None => None,
+        Some(LogFile::Path(path)) => Some(path.clone()),
+        Some(LogFile::StateDir) => Some(
+            Config::state_dir()
+                .ok_or_else(|| anyhow::anyhow!("--log-file: no state directory (HOME unset)"))?
+                .join(LOG_FILE),
+        ),
+    };
+    let file = match &path {
+        Some(path) => Some(
+            open_log_file(path)
+                .map_err(|e| anyhow::anyhow!("--log-file: {}: {}", path.display(), e))?,
+        ),
+        None => None,
+    };
+
+    let inner = build(&spec, file.as_ref())?;
+    let max_level = inner.filter();
+    let logger = LOGGER.get_or_init(|| Logger {
+        inner: RwLock::new(inner),
+        spec: RwLock::new(spec),
+        file,
+    });
+    log::set_logger(logger)?;
+    log::set_max_level(max_level);
+    Ok(path)
+}
+
+/// Current filter spec
+pub fn filter() -> Option<String> {
+    LOGGER.get().map(|logger| read(&logger.spec).clone())
+}
+
+/// Replace the filter (`jacinctl log <filter>`). Returns the new maximum level.
+pub fn set_filter(spec: &str) -> Result<LevelFilter, String> {
+    let logger = LOGGER.get().ok_or("logger not initialized")?;
+    let inner = build(spec, logger.file.as_ref()).map_err(|e| e.to_string())?;
+    let max_level = inner.filter();
+    *logger.inner.write().unwrap_or_else(PoisonError::into_inner) = inner;
+    *logger.spec.write().unwrap_or_else(PoisonError::into_inner) = spec.to_string();
+    log::set_max_level(max_level);
+    log::info!("[LOG] Filter set to {:?}", spec);
+    Ok(max_level)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<LogOptions, String> {
+        LogOptions::from_args(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn parses_log_args() {
+        assert_eq!(parse(&["--clean"]), Ok(LogOptions::default()));
+        assert_eq!(
+            parse(&["--log-level", "info,jacin::coordinator=debug", "--log-file"]),
+            Ok(LogOptions {
+                filter: Some("info,jacin::coordinator=debug".into()),
+                file: Some(LogFile::StateDir),
+            })
+        );
+        assert_eq!(
+            parse(&["--log-level=debug", "--log-file=/tmp/j.log"]),
+            Ok(LogOptions {
+                filter: Some("debug".into()),
+                file: Some(LogFile::Path("/tmp/j.log".into())),
+            })
+        );
+        assert!(parse(&["--log-level"]).is_err());
+    }
+
+    #[test]
+    fn filter_precedence() {
+        let flag = LogOptions {
+            filter: Some("debug".into()),
+            file: None,
+        };
+        assert_eq!(flag.filter_spec(Some("trace".into())), "debug");
+        let none = LogOptions::default();
+        assert_eq!(none.filter_spec(Some("trace".into())), "trace");
+        assert_eq!(none.filter_spec(Some(String::new())), DEFAULT_FILTER);
+        assert_eq!(none.filter_spec(None), DEFAULT_FILTER);
+    }
+
+    #[test]
+    fn per_module_filters() {
+        let logger = build("warn,jacin::coordinator=debug", None).unwrap();
+        let metadata = |target, level| log::Metadata::builder().target(target).level(level).build();
+        assert!(logger.enabled(&metadata("jacin::coordinator", log::Level::Debug)));
+        assert!(!logger.enabled(&metadata("jacin::dispatch", log::Level::Info)));
+        assert!(logger.enabled(&metadata("jacin::dispatch", log::Level::Warn)));
+        assert_eq!(logger.filter(), LevelFilter::Debug);
+    }
+}
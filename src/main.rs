@@ -1,163 +1,192 @@
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 
 use calloop::{
-    EventLoop, LoopSignal, RegistrationToken,
+    EventLoop, LoopHandle, LoopSignal, RegistrationToken,
     ping::make_ping,
     signals::{Signal, Signals},
     timer::{TimeoutAction, Timer},
 };
 use calloop_wayland_source::WaylandSource;
 use wayland_client::{
-    Connection,
+    Connection, EventQueue, Proxy,
+    backend::ObjectId,
     globals::registry_queue_init,
-    protocol::{wl_compositor, wl_keyboard, wl_shm},
+    protocol::{wl_compositor, wl_keyboard, wl_output, wl_seat, wl_shm},
 };
 use wayland_protocols_misc::zwp_input_method_v2::client::zwp_input_method_manager_v2;
 use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::zwp_virtual_keyboard_manager_v1;
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_manager_v1;
+use wayland_protocols_wlr::foreign_toplevel::v1::client::zwlr_foreign_toplevel_manager_v1;
+use wayland_protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1;
 
+mod appearance;
+mod backend;
+mod clipboard;
 mod config;
+mod contention;
+mod control;
 mod coordinator;
+mod dbus;
+mod dictionary;
 mod dispatch;
+mod dry_run;
+mod engine;
+mod fake_backend;
 mod input;
 mod keysym;
+mod logging;
+mod metrics;
 mod neovim;
+mod notify;
+mod quirks;
+mod recovery;
+mod replay;
+mod session_lock;
+mod shutdown;
 mod state;
+mod toplevel;
+mod trace;
 mod ui;
+mod unsupported;
+mod wakeups;
+#[cfg(test)]
+mod wayland_tests;
 
-use neovim::{NeovimHandle, VisualSelection};
-use state::{Animations, ImeState, KeyRepeatState, KeyboardState, KeypressState, WaylandState};
-use ui::{TextRenderer, UnifiedPopup};
+use appearance::ColorScheme;
+use backend::Backend;
+use clipboard::Clipboard;
+use engine::RomajiEngine;
+use keysym::{KeySet, KeyTable, Langmap, parse_keysym};
+use neovim::{SnapshotStats, VisualSelection};
+use quirks::Quirks;
+use replay::TraceEvent;
+use state::{
+    ANIMATION_FRAME, APP_MEMORY_FILE, Animations, AppMemory, BufferClearState,
+    CANDIDATE_HISTORY_FILE, COMPOSITION_FILE, CandidateHistory, ContentPurpose, ImeState,
+    KeyRepeatState, KeyboardState, KeypressState, Outputs, ParkedSeat, PointerState,
+    SavedComposition, SeatId, StartupQueue, WaylandState,
+};
+use toplevel::ToplevelTracker;
+use ui::{Placement, PopupFactory, Theme, UnifiedPopup};
 
 fn main() -> anyhow::Result<()> {
-    env_logger::init();
+    let log_options =
+        logging::LogOptions::from_args(std::env::args().skip(1)).map_err(anyhow::Error::msg)?;
+    if let Some(path) = logging::init(&log_options)? {
+        log::info!("[LOG] Logging to {}", path.display());
+    }
+
+    // `jacin --toggle` etc. talk to the running instance and exit
+    let cli_args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(command) = control::cli_command(&cli_args) {
+        return control::run_cli_command(command, cli_args.iter().any(|a| a == "--json"));
+    }
+    if cli_args.iter().any(|a| a == "--subscribe") {
+        return control::run_subscribe();
+    }
 
     // Load configuration
     let mut config = config::Config::load();
     if std::env::args().any(|a| a == "--clean") {
         config.clean = true;
     }
+    // Before any thread starts: the environment variable is removed
+    let restored_preedit = recovery::take_restored_preedit();
 
-    // Connect to Wayland display
-    let conn = Connection::connect_to_env()?;
-    log::info!("Connected to Wayland display");
-
-    // Initialize registry and get globals
-    let (globals, event_queue) = registry_queue_init::<State>(&conn)?;
-    let qh = event_queue.handle();
-
-    // Bind input method manager
-    let input_method_manager: zwp_input_method_manager_v2::ZwpInputMethodManagerV2 = globals
-        .bind(&qh, 1..=1, ())
-        .expect("zwp_input_method_manager_v2 not available - is this a wlroots compositor?");
-    log::info!("Bound zwp_input_method_manager_v2");
-
-    // Get the seat (assuming single seat)
-    let seat: wayland_client::protocol::wl_seat::WlSeat =
-        globals.bind(&qh, 1..=9, ()).expect("wl_seat not available");
-
-    // Bind compositor and shm for candidate window
-    let compositor: wl_compositor::WlCompositor = globals
-        .bind(&qh, 4..=6, ())
-        .expect("wl_compositor not available");
-
-    let shm: wl_shm::WlShm = globals.bind(&qh, 1..=1, ()).expect("wl_shm not available");
-
-    // Create input method for this seat
-    let input_method = input_method_manager.get_input_method(&seat, &qh, ());
-    log::info!("Created zwp_input_method_v2");
+    // `jacin dict fetch`: download dictionaries, then exit
+    if let Some(args) = dictionary::cli_args(&cli_args) {
+        return dictionary::run(&config.dictionary, args);
+    }
 
-    // Create virtual keyboard for clearing stuck modifier state
-    let virtual_keyboard = match globals
-        .bind::<zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1, _, _>(&qh, 1..=1, ())
-    {
-        Ok(manager) => {
-            let vk = manager.create_virtual_keyboard(&seat, &qh, ());
-            log::info!("Created zwp_virtual_keyboard_v1");
-            Some(vk)
+    // Backend-only dry run and transcript replay: no Wayland connection
+    let mut record_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--dry-run-keys" {
+            let keys = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("--dry-run-keys requires a key string"))?;
+            return dry_run::run(config, &keys);
         }
-        Err(e) => {
-            log::warn!(
-                "zwp_virtual_keyboard_manager_v1 not available: {} (modifier clearing disabled)",
-                e
-            );
-            None
+        if arg == "--replay" {
+            let path = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("--replay requires a transcript file"))?;
+            return replay::run(&config, Path::new(&path));
         }
-    };
-
-    // Spawn Neovim backend
-    let nvim = match neovim::spawn_neovim(config.clone()) {
-        Ok(handle) => {
-            log::info!("Neovim backend spawned");
-            Some(handle)
+        if arg == "--backend" {
+            let kind = args.next().ok_or_else(|| {
+                anyhow::anyhow!("--backend requires neovim, romaji or fake:<script.json>")
+            })?;
+            config.backend.kind =
+                config::BackendKind::from_arg(&kind).map_err(anyhow::Error::msg)?;
         }
-        Err(e) => {
-            log::warn!("Failed to spawn Neovim: {} (continuing without backend)", e);
-            None
+        if arg == "--record" {
+            let path = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("--record requires a file name"))?;
+            record_path = Some(PathBuf::from(path));
         }
-    };
+    }
 
-    // Try to create text renderers for unified popup window
-    let font_size = config
-        .font
-        .size
-        .filter(|s| s.is_finite() && *s > 0.0)
-        .map(|s| s.clamp(8.0, 48.0))
-        .unwrap_or(16.0);
-    let text_renderer = TextRenderer::new_with_family(font_size, config.font.family.as_deref());
-    let mono_renderer =
-        TextRenderer::new_monospace_with_family(font_size, config.font.mono_family.as_deref());
-    if text_renderer.is_none() {
-        log::warn!("Font not available, popup window disabled");
+    let dev_hooks = std::env::args().any(|a| a == "--dev");
+    // Wait for another IME holding the input method protocol to exit
+    let replace = std::env::args().any(|a| a == "--replace");
+    if dev_hooks {
+        log::warn!("[CONTROL] --dev: jacinctl may inject synthetic compositor events");
+    }
+    if config.metrics.enabled {
+        metrics::enable();
     }
 
-    // Create unified popup window using input method popup surface
-    // The popup surface is automatically positioned near the cursor by the compositor
-    let popup = if let (Some(renderer), Some(mono)) = (text_renderer, mono_renderer) {
-        match UnifiedPopup::new(&compositor, &input_method, &shm, &qh, renderer, mono) {
-            Some(win) => {
-                log::info!("Unified popup window created (using input popup surface)");
-                Some(win)
-            }
-            None => {
-                log::warn!("Failed to create unified popup window");
-                None
+    // Connect to Wayland display
+    let conn = Connection::connect_to_env()?;
+    log::info!("[WAYLAND] Connected to Wayland display");
+
+    let Startup {
+        mut state,
+        event_queue,
+        quirks,
+        clipboard_rx,
+        provider_events,
+    } = init_state(&conn, config.clone(), restored_preedit, dev_hooks, replace)?;
+
+    // Composition saved by the last exit (behavior.save_composition)
+    if config.behavior.save_composition {
+        state.saved_composition = config::Config::state_dir()
+            .and_then(|dir| SavedComposition::take(dir.join(COMPOSITION_FILE)));
+    }
+
+    if let Some(path) = record_path {
+        let description = format!(
+            "Recorded by jacin {} under {:?}",
+            env!("CARGO_PKG_VERSION"),
+            quirks.compositor
+        );
+        match replay::Recorder::create(&path, &description) {
+            Ok(recorder) => {
+                log::info!("[RECORD] Recording transcript to {}", path.display());
+                state.recorder = Some(recorder);
+                // The IME starts off
+                state.record(TraceEvent::Disable);
             }
+            Err(e) => anyhow::bail!("cannot record to {}: {}", path.display(), e),
         }
-    } else {
-        None
-    };
+    }
 
-    // Create application state
-    let mut state = State {
-        loop_signal: None,
-        wayland: {
-            let mut ws = WaylandState::new(qh.clone(), input_method);
-            ws.virtual_keyboard = virtual_keyboard;
-            ws
-        },
-        keyboard: KeyboardState::new(),
-        repeat: KeyRepeatState::new(),
-        ime: ImeState::new(),
-        keypress: KeypressState::new(),
-        animations: Animations::new(),
-        pending_exit: false,
-        toggle_flag: Arc::new(AtomicBool::new(false)),
-        config: config.clone(),
-        nvim,
-        visual_display: None,
-        popup,
-        repeat_timer_token: None,
-        keypress_timer_token: None,
-        current_keycode: None,
-    };
+    if !config.behavior.lazy_spawn {
+        state.ensure_backend_standby();
+    }
 
     // Set up calloop event loop
     let mut event_loop: EventLoop<State> = EventLoop::try_new()?;
     state.loop_signal = Some(event_loop.get_signal());
 
     // Insert Wayland event source
-    WaylandSource::new(conn, event_queue).insert(event_loop.handle())?;
+    // The connection is kept to tell what a protocol error was raised against
+    WaylandSource::new(conn.clone(), event_queue).insert(event_loop.handle())?;
 
     // Set up signal handling for clean exit
     let loop_signal = state.loop_signal.clone();
@@ -165,12 +194,21 @@ fn main() -> anyhow::Result<()> {
     event_loop
         .handle()
         .insert_source(exit_signals, move |_, _, _| {
-            log::info!("Received signal, exiting...");
+            log::info!("[SIGNAL] Received signal, exiting...");
             if let Some(ref signal) = loop_signal {
                 signal.stop();
             }
         })?;
 
+    // SIGQUIT dumps the flight recorder instead of exiting
+    let trace_signals = Signals::new(&[Signal::SIGQUIT])?;
+    event_loop
+        .handle()
+        .insert_source(trace_signals, |_, _, _| match trace::dump_to_file() {
+            Ok(path) => log::info!("[TRACE] Wrote {}", path.display()),
+            Err(e) => log::warn!("[TRACE] Dump failed: {}", e),
+        })?;
+
     // Set up SIGUSR1 for IME toggle (triggered by: pkill -SIGUSR1 jacin)
     // Use a ping to wake up the event loop when signal arrives
     let (ping, ping_source) = make_ping()?;
@@ -190,6 +228,80 @@ fn main() -> anyhow::Result<()> {
         .handle()
         .insert_source(ping_source, |_, _, _| {})?;
 
+    // Screen lock: drop grab and hide popup while locked
+    if config.behavior.suspend_on_lock {
+        event_loop
+            .handle()
+            .insert_source(session_lock::spawn_lock_monitor(), |event, _, state| {
+                if let calloop::channel::Event::Msg(event) = event {
+                    state.handle_session_lock(event);
+                }
+            })
+            .map_err(|e| anyhow::anyhow!("failed to register lock monitor: {}", e.error))?;
+    }
+
+    // Dark/light palettes: follow the desktop's color scheme
+    if config.theme.follows_color_scheme() {
+        event_loop
+            .handle()
+            .insert_source(
+                appearance::spawn_color_scheme_monitor(),
+                |event, _, state| {
+                    if let calloop::channel::Event::Msg(scheme) = event {
+                        state.on_color_scheme(scheme);
+                    }
+                },
+            )
+            .map_err(|e| anyhow::anyhow!("failed to register color scheme monitor: {}", e.error))?;
+    }
+
+    // Selections read from the compositor are handed to Neovim
+    if let Some(rx) = clipboard_rx {
+        event_loop
+            .handle()
+            .insert_source(rx, |event, _, state| {
+                if let calloop::channel::Event::Msg(()) = event {
+                    state.on_selections_read();
+                }
+            })
+            .map_err(|e| anyhow::anyhow!("failed to register clipboard source: {}", e.error))?;
+    }
+
+    // Candidates of external providers connected to the control socket
+    event_loop
+        .handle()
+        .insert_source(provider_events, |event, _, state| {
+            if let calloop::channel::Event::Msg(event) = event {
+                state.on_provider_event(event);
+            }
+        })
+        .map_err(|e| anyhow::anyhow!("failed to register provider source: {}", e.error))?;
+
+    // Control socket for jacinctl (non-fatal: the IME works without it)
+    let control_socket = match control::insert_control_source(&event_loop.handle()) {
+        Ok(path) => Some(path),
+        Err(e) => {
+            log::warn!("[CONTROL] Control socket disabled: {}", e);
+            None
+        }
+    };
+
+    // D-Bus control interface (non-fatal, like the control socket)
+    match dbus::DbusService::start() {
+        Ok((service, requests)) => {
+            event_loop
+                .handle()
+                .insert_source(requests, |event, _, state| {
+                    if let calloop::channel::Event::Msg(request) = event {
+                        state.handle_dbus_request(request);
+                    }
+                })
+                .map_err(|e| anyhow::anyhow!("failed to register D-Bus source: {}", e.error))?;
+            state.dbus = Some(service);
+        }
+        Err(e) => log::warn!("[DBUS] D-Bus interface disabled: {}", e),
+    }
+
     // Small delay to let any pending key events (like Enter from "cargo run") clear
     std::thread::sleep(std::time::Duration::from_millis(500));
 
@@ -198,29 +310,29 @@ fn main() -> anyhow::Result<()> {
 
     // Run the event loop
     let handle = event_loop.handle();
-    event_loop.run(None, &mut state, |state| {
+    insert_backend_sources(&handle, &mut state);
+    shutdown::install_panic_hook();
+    let result = event_loop.run(None, &mut state, |state| {
+        wakeups::loop_iteration();
+
         // Check for IME toggle signal (SIGUSR1)
         if state.toggle_flag.swap(false, Ordering::SeqCst) {
             state.handle_ime_toggle();
         }
 
-        // Check for messages from Neovim
-        // Collect messages first to avoid borrow conflict
-        let messages: Vec<_> = state
-            .nvim
-            .as_ref()
-            .map(|nvim| std::iter::from_fn(|| nvim.try_recv()).collect())
-            .unwrap_or_default();
-
-        for msg in messages {
-            state.handle_nvim_message(msg);
-        }
+        // Backends spawned while dispatching (respawn, standby) deliver their
+        // messages through their own event source
+        insert_backend_sources(&handle, state);
+        state.publish_dbus_status();
+        state.publish_status();
+        state.publish_provider_context();
 
         // Insert on-demand repeat timer when a key is held
         if state.repeat.has_key() && state.repeat_timer_token.is_none() {
             match handle.insert_source(
                 Timer::from_duration(std::time::Duration::from_millis(5)),
                 |_, _, state| {
+                    wakeups::timer_fired(wakeups::Timer::Repeat);
                     if state.ime.is_fully_enabled()
                         && let Some(key) = state
                             .repeat
@@ -255,6 +367,7 @@ fn main() -> anyhow::Result<()> {
             match handle.insert_source(
                 Timer::from_duration(std::time::Duration::from_millis(100)),
                 |_, _, state| {
+                    wakeups::timer_fired(wakeups::Timer::Keypress);
                     let now = std::time::Instant::now();
                     let mut changed = state.keypress.cleanup_inactive();
 
@@ -291,33 +404,187 @@ fn main() -> anyhow::Result<()> {
             }
         }
 
+        // Insert on-demand deferred buffer clear timer (deactivate_clear = "defer")
+        if let Some(deadline) = state.buffer_clear.deadline()
+            && state.buffer_clear_timer_token.is_none()
+        {
+            match handle.insert_source(Timer::from_deadline(deadline), |_, _, state| {
+                wakeups::timer_fired(wakeups::Timer::BufferClear);
+                match state.run_deferred_buffer_clear() {
+                    Some(next) => TimeoutAction::ToInstant(next),
+                    None => {
+                        state.buffer_clear_timer_token = None;
+                        TimeoutAction::Drop
+                    }
+                }
+            }) {
+                Ok(token) => state.buffer_clear_timer_token = Some(token),
+                Err(e) => {
+                    log::error!("[TIMER] Failed to insert buffer clear timer: {e}");
+                    state.buffer_clear.cancel();
+                }
+            }
+        }
+
+        // Insert on-demand input method retry while another IME holds the protocol
+        if let Some(deadline) = state.contention.deadline()
+            && state.contention_timer_token.is_none()
+        {
+            match handle.insert_source(Timer::from_deadline(deadline), |_, _, state| {
+                wakeups::timer_fired(wakeups::Timer::Contention);
+                state.contention_timer_token = None;
+                state.retry_input_methods();
+                TimeoutAction::Drop
+            }) {
+                Ok(token) => state.contention_timer_token = Some(token),
+                Err(e) => log::error!("[TIMER] Failed to insert input method retry: {e}"),
+            }
+        }
+
+        // Insert on-demand candidate history save after accepts (behavior.learn_candidates)
+        if let Some(deadline) = state
+            .candidate_history
+            .as_ref()
+            .and_then(CandidateHistory::save_deadline)
+            && state.history_save_timer_token.is_none()
+        {
+            match handle.insert_source(Timer::from_deadline(deadline), |_, _, state| {
+                wakeups::timer_fired(wakeups::Timer::HistorySave);
+                state.history_save_timer_token = None;
+                if let Some(ref mut history) = state.candidate_history {
+                    history.save_if_unsaved();
+                }
+                TimeoutAction::Drop
+            }) {
+                Ok(token) => state.history_save_timer_token = Some(token),
+                Err(e) => log::error!("[TIMER] Failed to insert history save: {e}"),
+            }
+        }
+
+        // Insert on-demand stuck grab watchdog while a grab could be stuck
+        if state.grab_watchdog_needed() && state.grab_watchdog_token.is_none() {
+            match handle.insert_source(
+                Timer::from_duration(GRAB_WATCHDOG_INTERVAL),
+                |_, _, state| {
+                    wakeups::timer_fired(wakeups::Timer::GrabWatchdog);
+                    state.check_stuck_grab();
+                    if state.grab_watchdog_needed() {
+                        TimeoutAction::ToDuration(GRAB_WATCHDOG_INTERVAL)
+                    } else {
+                        state.grab_watchdog_token = None;
+                        TimeoutAction::Drop
+                    }
+                },
+            ) {
+                Ok(token) => state.grab_watchdog_token = Some(token),
+                Err(e) => log::error!("[TIMER] Failed to insert grab watchdog: {e}"),
+            }
+        }
+
+        // Insert on-demand kinetic scroll timer while the candidate list is flung
+        if state.scroll_timer_token.is_none()
+            && state
+                .popup
+                .as_ref()
+                .is_some_and(|p| p.is_scroll_animating())
+        {
+            match handle.insert_source(Timer::from_duration(SCROLL_FRAME), |_, _, state| {
+                wakeups::timer_fired(wakeups::Timer::Scroll);
+                let Some(ref mut popup) = state.popup else {
+                    state.scroll_timer_token = None;
+                    return TimeoutAction::Drop;
+                };
+                let moved = popup.tick_scroll(SCROLL_FRAME);
+                let animating = popup.is_scroll_animating();
+                if moved {
+                    state.update_popup();
+                }
+                if animating {
+                    TimeoutAction::ToDuration(SCROLL_FRAME)
+                } else {
+                    state.scroll_timer_token = None;
+                    TimeoutAction::Drop
+                }
+            }) {
+                Ok(token) => state.scroll_timer_token = Some(token),
+                Err(e) => log::error!("[TIMER] Failed to insert scroll timer: {e}"),
+            }
+        }
+
+        // One render for everything dispatched this turn (a key alone brings
+        // preedit, visual range, candidates and KeyProcessed)
+        state.flush_popup();
+
+        // Health checks only while there is something to lose
+        state.watch_backend();
+
+        // Insert on-demand popup animation timer while a fade or slide runs
+        // (after the render, which starts them)
+        if state.animation_timer_token.is_none()
+            && state.animations.popup_running(std::time::Instant::now())
+        {
+            match handle.insert_source(Timer::from_duration(ANIMATION_FRAME), |_, _, state| {
+                wakeups::timer_fired(wakeups::Timer::Animation);
+                if state.tick_popup_animations() {
+                    TimeoutAction::ToDuration(ANIMATION_FRAME)
+                } else {
+                    state.animation_timer_token = None;
+                    TimeoutAction::Drop
+                }
+            }) {
+                Ok(token) => state.animation_timer_token = Some(token),
+                Err(e) => log::error!("[TIMER] Failed to insert animation timer: {e}"),
+            }
+        }
+
         if state.pending_exit
             && let Some(ref signal) = state.loop_signal
         {
             signal.stop();
         }
-    })?;
+
+        // What the panic hook commits and releases
+        shutdown::arm(state, &conn);
+    });
 
     // Cleanup
-    state.wayland.release_keyboard();
-    if let Some(ref nvim) = state.nvim {
-        nvim.shutdown();
-    }
-    if let Some(window) = state.popup.take() {
-        window.destroy();
+    shutdown::finish(&mut state, &conn, result.is_err());
+    if let Some(path) = control_socket {
+        let _ = std::fs::remove_file(path);
     }
 
+    metrics::log_summary();
+    if let Err(e) = result {
+        // Restarts for a popup protocol error; the text is reported otherwise
+        return Err(recovery::after_disconnect(
+            &conn,
+            e,
+            &state.ime.preedit,
+            state.privacy,
+        ));
+    }
     log::info!("Goodbye!");
 
     // Force clean exit to avoid any stuck keyboard state
+    if state.contention.gave_up() {
+        std::process::exit(contention::EXIT_CONTENDED);
+    }
     std::process::exit(0);
 }
 
+/// How often a held keyboard grab is checked for being stuck
+const GRAB_WATCHDOG_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+/// Kinetic candidate scroll frame interval
+const SCROLL_FRAME: std::time::Duration = std::time::Duration::from_millis(16);
+
 pub struct State {
     pub(crate) loop_signal: Option<LoopSignal>,
     // Component state structs
     pub(crate) wayland: WaylandState,
     pub(crate) keyboard: KeyboardState,
+    // Seat owning `wayland`/`keyboard`/`popup`; other seats wait in `parked_seats`
+    pub(crate) current_seat: SeatId,
+    pub(crate) parked_seats: Vec<ParkedSeat>,
     pub(crate) repeat: KeyRepeatState,
     pub(crate) ime: ImeState,
     pub(crate) keypress: KeypressState,
@@ -328,14 +595,498 @@ pub struct State {
     // Config (needed for Neovim respawn after :q)
     pub(crate) config: config::Config,
     // Neovim backend
-    pub(crate) nvim: Option<NeovimHandle>,
+    pub(crate) backend: Option<Box<dyn Backend>>,
+    // Pre-initialized spare backend, promoted when `backend` exits (config nvim.standby)
+    pub(crate) backend_standby: Option<Box<dyn Backend>>,
+    // A backend was started this session (false until the first enable with lazy_spawn)
+    pub(crate) backend_spawned: bool,
+    // Keys typed while a backend spawned on enable starts, replayed on Ready
+    pub(crate) startup_keys: StartupQueue,
+    // Built-in romaji conversion, used while `backend` is None
+    pub(crate) engine: RomajiEngine,
+    // Privacy mode (keybinds.privacy / `jacinctl privacy`): keypresses masked in the popup
+    pub(crate) privacy: bool,
+    // Session is locked (logind) — IME suspended, toggles ignored
+    pub(crate) session_locked: bool,
+    // IME disabled because a blocklisted fullscreen app took focus (re-enabled when it leaves)
+    pub(crate) fullscreen_suspended: bool,
+    // Desktop color scheme the popup theme follows ([theme.dark] / [theme.light])
+    pub(crate) color_scheme: ColorScheme,
+    // + / * register bridge (None when disabled or wlr-data-control is missing)
+    pub(crate) clipboard: Option<Clipboard>,
+    // org.jacin.Ime1 session bus service (None if the bus is unavailable)
+    pub(crate) dbus: Option<dbus::DbusService>,
+    // Physical → logical key remap for jacin's keybinds (config or Neovim 'langmap')
+    pub(crate) langmap: Langmap,
+    // User key translation applied before keys become Vim notation ([keymap])
+    pub(crate) key_table: KeyTable,
+    // Keys sent straight to the app while grabbed (keybinds.passthrough)
+    pub(crate) passthrough_keys: KeySet,
+    // Key toggling the IME when tapped alone (keybinds.toggle_tap)
+    pub(crate) toggle_tap: Option<xkbcommon::xkb::Keysym>,
+    // Latest snapshot strategy report from the backend (shown with behavior.debug_overlay)
+    pub(crate) snapshot_stats: Option<SnapshotStats>,
+    // Last key's round trip to the backend (shown with metrics.indicator)
+    pub(crate) last_rpc: Option<std::time::Duration>,
     // Transient visual selection display state (observed from Neovim, not IME-owned)
     pub(crate) visual_display: Option<VisualSelection>,
     // Unified popup window (preedit, keypress, candidates)
     pub(crate) popup: Option<UnifiedPopup>,
+    // State changed since the popup was last drawn (rendered once per loop turn)
+    pub(crate) popup_dirty: bool,
+    // Creates popups on demand (startup failures are retried with backoff)
+    pub(crate) popup_factory: PopupFactory,
+    // Activated toplevel per wlr-foreign-toplevel (behavior.remember_per_app,
+    // behavior.fullscreen_blocklist)
+    pub(crate) toplevels: ToplevelTracker<ObjectId>,
+    // Output properties, for per-output popup sizes ([[popup.output]])
+    pub(crate) outputs: Outputs<ObjectId>,
+    // Last enabled state per app_id (None unless behavior.remember_per_app works)
+    pub(crate) app_memory: Option<AppMemory>,
+    // Accepted candidates per reading (None unless behavior.learn_candidates)
+    pub(crate) candidate_history: Option<CandidateHistory>,
+    // Text composed before a restart after a popup protocol error, restored
+    // at the next activation
+    pub(crate) restored_preedit: Option<String>,
+    // Composition the last jacin saved on exit, restored at the first enable
+    // (behavior.save_composition)
+    pub(crate) saved_composition: Option<SavedComposition>,
+    // Pointer over a popup (behavior.popup_pointer)
+    pub(crate) pointer: PointerState,
+    // Pending Neovim buffer clear after focus loss (behavior.deactivate_clear)
+    pub(crate) buffer_clear: BufferClearState,
     // On-demand timer tokens (None = timer not running)
     pub(crate) repeat_timer_token: Option<RegistrationToken>,
     pub(crate) keypress_timer_token: Option<RegistrationToken>,
+    pub(crate) buffer_clear_timer_token: Option<RegistrationToken>,
+    pub(crate) grab_watchdog_token: Option<RegistrationToken>,
+    pub(crate) scroll_timer_token: Option<RegistrationToken>,
+    pub(crate) animation_timer_token: Option<RegistrationToken>,
     // Raw evdev keycode of the currently-being-processed key (for passthrough)
     pub(crate) current_keycode: Option<u32>,
+    // Control-socket test hooks (`jacin --dev`)
+    pub(crate) dev_hooks: bool,
+    // Background release check (None when disabled in config)
+    pub(crate) update_check: Option<control::UpdateCheck>,
+    // Session transcript being written (`--record`)
+    pub(crate) recorder: Option<replay::Recorder>,
+    // Asked for a new input method while another IME holds the protocol
+    pub(crate) input_method_manager: zwp_input_method_manager_v2::ZwpInputMethodManagerV2,
+    pub(crate) seats: Vec<(SeatId, wl_seat::WlSeat)>,
+    // Retry schedule for unavailable input methods (behavior.unavailable_retries, --replace)
+    pub(crate) contention: contention::Contention,
+    pub(crate) contention_timer_token: Option<RegistrationToken>,
+    pub(crate) history_save_timer_token: Option<RegistrationToken>,
+    // Status stream connections (`jacin --subscribe`)
+    pub(crate) subscribers: control::Subscribers,
+    // External candidate providers (control socket `provider`)
+    pub(crate) providers: control::Providers,
+}
+
+/// The state and what comes with it, before the event loop runs
+struct Startup {
+    state: State,
+    event_queue: EventQueue<State>,
+    quirks: Quirks,
+    /// Selections read from the compositor (clipboard bridge bound)
+    clipboard_rx: Option<calloop::channel::Channel<()>>,
+    /// Candidates of external providers
+    provider_events: calloop::channel::Channel<control::ProviderEvent>,
+}
+
+/// Bind the globals of `conn` and build the state: the first seat current,
+/// the others parked. Exits with a distinct status where the input method
+/// protocol is missing.
+fn init_state(
+    conn: &Connection,
+    mut config: config::Config,
+    restored_preedit: Option<String>,
+    dev_hooks: bool,
+    replace: bool,
+) -> anyhow::Result<Startup> {
+    // Initialize registry and get globals
+    let (globals, event_queue) = registry_queue_init::<State>(conn)?;
+    let qh = event_queue.handle();
+    let quirks = Quirks::detect();
+
+    // Explain and exit with a distinct status where the input method protocol is missing
+    let advertised: Vec<(String, u32)> = globals.contents().with_list(|list| {
+        list.iter()
+            .map(|g| (g.interface.clone(), g.version))
+            .collect()
+    });
+    let missing = unsupported::missing_globals(&advertised);
+    if !missing.is_empty() {
+        let message = unsupported::explanation(quirks.compositor, &missing, &advertised);
+        log::error!("[WAYLAND] Unsupported compositor ({:?})", quirks.compositor);
+        eprintln!("{message}");
+        std::process::exit(unsupported::EXIT_UNSUPPORTED);
+    }
+
+    // Bind input method manager
+    let input_method_manager: zwp_input_method_manager_v2::ZwpInputMethodManagerV2 = globals
+        .bind(&qh, 1..=1, ())
+        .expect("zwp_input_method_manager_v2 checked above");
+    log::info!("[WAYLAND] Bound zwp_input_method_manager_v2");
+
+    // Enumerate all seats; each gets its own input method, grab and popup
+    let seat_globals: Vec<(u32, u32)> = globals.contents().with_list(|list| {
+        list.iter()
+            .filter(|g| g.interface == wl_seat::WlSeat::interface().name)
+            .map(|g| (g.name, g.version))
+            .collect()
+    });
+    if seat_globals.is_empty() {
+        anyhow::bail!("wl_seat not available");
+    }
+    let seats: Vec<(SeatId, wl_seat::WlSeat)> = seat_globals
+        .iter()
+        .map(|&(name, version)| {
+            let seat: wl_seat::WlSeat = globals.registry().bind(name, version.min(9), &qh, name);
+            (name, seat)
+        })
+        .collect();
+    log::info!("[SEAT] Found {} seat(s)", seats.len());
+
+    // Bind compositor and shm for candidate window
+    let compositor: wl_compositor::WlCompositor = globals
+        .bind(&qh, 4..=6, ())
+        .expect("wl_compositor not available");
+
+    let shm: wl_shm::WlShm = globals.bind(&qh, 1..=1, ()).expect("wl_shm not available");
+
+    // Outputs, to size the popup for the one it is shown on ([[popup.output]]).
+    // Outputs connected later are not tracked.
+    let output_globals: Vec<(u32, u32)> = globals.contents().with_list(|list| {
+        list.iter()
+            .filter(|g| g.interface == wl_output::WlOutput::interface().name)
+            .map(|g| (g.name, g.version))
+            .collect()
+    });
+    for &(name, version) in &output_globals {
+        let _: wl_output::WlOutput = globals.registry().bind(name, version.min(4), &qh, ());
+    }
+    log::info!("[OUTPUT] Found {} output(s)", output_globals.len());
+
+    let mut bound_protocols = vec![
+        bound_version(&input_method_manager),
+        bound_version(&seats[0].1),
+        bound_version(&compositor),
+        bound_version(&shm),
+    ];
+
+    // Virtual keyboards clear stuck modifier state after grab release
+    let virtual_keyboard_manager =
+        match globals.bind::<zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1, _, _>(
+            &qh,
+            1..=1,
+            (),
+        ) {
+            Ok(manager) => {
+                bound_protocols.push(bound_version(&manager));
+                Some(manager)
+            }
+            Err(e) => {
+                log::warn!(
+                    "[WAYLAND] zwp_virtual_keyboard_manager_v1 not available: {} (modifier clearing disabled)",
+                    e
+                );
+                None
+            }
+        };
+
+    // Clipboard bridge for + / * registers (Neovim only installs its provider when bound)
+    // and published commits
+    let mut clipboard = None;
+    let mut clipboard_rx = None;
+    if config.behavior.clipboard_sync
+        || config.behavior.publish_commits != config::PublishCommits::Off
+    {
+        match globals.bind::<zwlr_data_control_manager_v1::ZwlrDataControlManagerV1, _, _>(
+            &qh,
+            1..=2,
+            (),
+        ) {
+            Ok(manager) => {
+                bound_protocols.push(bound_version(&manager));
+                let (bridge, rx) = Clipboard::new(manager, conn.clone());
+                clipboard = Some(bridge);
+                clipboard_rx = Some(rx);
+            }
+            Err(e) => {
+                log::warn!(
+                    "[WAYLAND] zwlr_data_control_manager_v1 not available: {} (clipboard sync and commit publishing disabled)",
+                    e
+                );
+                config.behavior.clipboard_sync = false;
+                config.behavior.publish_commits = config::PublishCommits::Off;
+            }
+        }
+    }
+
+    // Focused app tracking for per-app enabled state and the fullscreen
+    // blocklist (toplevel handles arrive as events)
+    let mut app_memory = None;
+    if config.behavior.remember_per_app || !config.behavior.fullscreen_blocklist.is_empty() {
+        match globals.bind::<zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1, _, _>(
+            &qh,
+            1..=3,
+            (),
+        ) {
+            Ok(manager) => {
+                bound_protocols.push(bound_version(&manager));
+                if config.behavior.remember_per_app {
+                    match config::Config::state_dir() {
+                        Some(dir) => app_memory = Some(AppMemory::load(dir.join(APP_MEMORY_FILE))),
+                        None => {
+                            log::warn!(
+                                "[APP] No state directory (HOME unset), per-app state disabled"
+                            )
+                        }
+                    }
+                }
+            }
+            Err(e) => log::warn!(
+                "[WAYLAND] zwlr_foreign_toplevel_manager_v1 not available: {} (per-app state and fullscreen blocklist disabled)",
+                e
+            ),
+        }
+    }
+
+    // Accepted candidates per reading (behavior.learn_candidates)
+    let candidate_history = if config.behavior.learn_candidates {
+        let history = config::Config::data_dir()
+            .map(|dir| CandidateHistory::load(dir.join(CANDIDATE_HISTORY_FILE)));
+        if history.is_none() {
+            log::warn!("[HISTORY] No data directory (HOME unset), candidate learning disabled");
+        }
+        history
+    } else {
+        None
+    };
+
+    // Corner popup for compositors that misplace input popups
+    let mut placement = Placement::Cursor;
+    if config.popup.mode == config::PopupMode::Corner {
+        match globals.bind::<zwlr_layer_shell_v1::ZwlrLayerShellV1, _, _>(&qh, 1..=4, ()) {
+            Ok(layer_shell) => {
+                bound_protocols.push(bound_version(&layer_shell));
+                placement = Placement::Corner {
+                    layer_shell,
+                    corner: config.popup.corner,
+                };
+            }
+            Err(e) => log::warn!(
+                "[WAYLAND] zwlr_layer_shell_v1 not available: {} (popup placed at the cursor)",
+                e
+            ),
+        }
+    }
+
+    // Spawn the conversion backend (with lazy_spawn: on first enable)
+    let backend = match backend::spawn(&config) {
+        _ if config.behavior.lazy_spawn => {
+            log::info!("[NVIM] lazy_spawn: backend starts when the IME is first enabled");
+            None
+        }
+        Ok(Some(handle)) => {
+            log::info!("[NVIM] Backend spawned ({:?})", config.backend.kind);
+            Some(handle)
+        }
+        Ok(None) => {
+            log::info!("[CONFIG] backend.kind = \"romaji\": using the built-in romaji conversion");
+            None
+        }
+        Err(e) => {
+            log::warn!(
+                "[NVIM] Failed to spawn Neovim: {} (falling back to romaji conversion)",
+                e
+            );
+            None
+        }
+    };
+
+    let theme = Theme::from_config(&config);
+    log::info!(
+        "[WAYLAND] Compositor: {:?} (popup hide: {:?})",
+        quirks.compositor,
+        quirks.popup_hide
+    );
+
+    let mut popup_factory = PopupFactory::new(
+        compositor.clone(),
+        shm.clone(),
+        theme,
+        quirks.popup_hide,
+        config.behavior.popup_pointer,
+        placement,
+    );
+
+    let mut seat_states: Vec<ParkedSeat> = seats
+        .iter()
+        .map(|(id, seat)| {
+            let input_method = input_method_manager.get_input_method(seat, &qh, *id);
+            log::info!("[SEAT] Created zwp_input_method_v2 for seat {}", id);
+
+            let mut wayland = WaylandState::new(qh.clone(), *id, input_method);
+            wayland.virtual_keyboard = virtual_keyboard_manager
+                .as_ref()
+                .map(|m| m.create_virtual_keyboard(seat, &qh, ()));
+            wayland.data_device = clipboard
+                .as_ref()
+                .map(|c| c.manager.get_data_device(seat, &qh, *id));
+
+            // Create unified popup window using input popup surface
+            // The popup surface is automatically positioned near the cursor by the compositor.
+            // On failure it is retried when the popup is next needed.
+            let popup = popup_factory
+                .create(*id, &wayland.input_method, &qh, std::time::Instant::now())
+                .map(|(popup, _)| popup);
+
+            ParkedSeat {
+                id: *id,
+                wayland,
+                keyboard: KeyboardState::new(),
+                popup,
+            }
+        })
+        .collect();
+    // The first seat starts current; the others are parked until they activate
+    let first = seat_states.remove(0);
+    let mut wayland = first.wayland;
+    wayland.bound_protocols = bound_protocols;
+
+    for purpose in &config.behavior.direct_purposes {
+        if ContentPurpose::from_name(purpose).is_none() {
+            log::warn!(
+                "[CONFIG] Unknown behavior.direct_purposes entry {:?}",
+                purpose
+            );
+        }
+    }
+
+    let (providers, provider_events) = control::Providers::new();
+
+    // Create application state
+    let state = State {
+        loop_signal: None,
+        wayland,
+        keyboard: first.keyboard,
+        current_seat: first.id,
+        parked_seats: seat_states,
+        repeat: KeyRepeatState::new(),
+        ime: ImeState::new(),
+        keypress: KeypressState::new(),
+        animations: Animations::new(),
+        pending_exit: false,
+        toggle_flag: Arc::new(AtomicBool::new(false)),
+        config: config.clone(),
+        backend,
+        langmap: Langmap::parse(&config.keybinds.langmap).unwrap_or_else(|e| {
+            log::warn!("[CONFIG] Invalid keybinds.langmap: {}", e);
+            Langmap::default()
+        }),
+        key_table: KeyTable::parse(&config.keymap).unwrap_or_else(|e| {
+            log::warn!("[CONFIG] Invalid [keymap] ignored: {}", e);
+            KeyTable::default()
+        }),
+        passthrough_keys: KeySet::parse(&config.keybinds.passthrough).unwrap_or_else(|e| {
+            log::warn!("[CONFIG] Invalid keybinds.passthrough ignored: {}", e);
+            KeySet::default()
+        }),
+        toggle_tap: (!config.keybinds.toggle_tap.is_empty())
+            .then(|| parse_keysym(&config.keybinds.toggle_tap))
+            .transpose()
+            .unwrap_or_else(|e| {
+                log::warn!("[CONFIG] Invalid keybinds.toggle_tap ignored: {}", e);
+                None
+            }),
+        snapshot_stats: None,
+        last_rpc: None,
+        restored_preedit,
+        saved_composition: None,
+        visual_display: None,
+        popup: first.popup,
+        popup_dirty: false,
+        popup_factory,
+        toplevels: ToplevelTracker::default(),
+        outputs: Outputs::default(),
+        app_memory,
+        candidate_history,
+        pointer: PointerState::default(),
+        buffer_clear: BufferClearState::new(
+            config.behavior.deactivate_clear,
+            std::time::Duration::from_secs(config.behavior.deactivate_clear_delay),
+        ),
+        repeat_timer_token: None,
+        keypress_timer_token: None,
+        buffer_clear_timer_token: None,
+        grab_watchdog_token: None,
+        scroll_timer_token: None,
+        animation_timer_token: None,
+        current_keycode: None,
+        backend_standby: None,
+        backend_spawned: !config.behavior.lazy_spawn,
+        startup_keys: StartupQueue::default(),
+        engine: RomajiEngine::default(),
+        privacy: false,
+        session_locked: false,
+        fullscreen_suspended: false,
+        color_scheme: ColorScheme::NoPreference,
+        clipboard,
+        dbus: None,
+        dev_hooks,
+        update_check: config
+            .update
+            .check
+            .then(|| control::UpdateCheck::spawn(config.update.url.clone())),
+        recorder: None,
+        input_method_manager,
+        seats,
+        contention: contention::Contention::new(
+            config.behavior.unavailable_retries,
+            std::time::Duration::from_secs(config.behavior.unavailable_retry_delay),
+            replace,
+        ),
+        contention_timer_token: None,
+        history_save_timer_token: None,
+        subscribers: control::Subscribers::default(),
+        providers,
+    };
+
+    Ok(Startup {
+        state,
+        event_queue,
+        quirks,
+        clipboard_rx,
+        provider_events,
+    })
+}
+
+/// Register the event source of each backend not registered yet, so its
+/// messages are handled as soon as they arrive. A source removes itself once
+/// its backend thread has ended.
+fn insert_backend_sources(handle: &LoopHandle<'static, State>, state: &mut State) {
+    for backend in [&mut state.backend, &mut state.backend_standby]
+        .into_iter()
+        .flatten()
+    {
+        let Some(source) = backend.take_event_source() else {
+            continue;
+        };
+        let id = backend.id();
+        if let Err(e) = handle.insert_source(source, move |msg, _, state| {
+            state.on_backend_event(id, msg);
+        }) {
+            log::error!(
+                "[NVIM] Failed to register backend event source: {}",
+                e.error
+            );
+        }
+    }
+}
+
+/// Interface name and negotiated version of a bound global
+fn bound_version<P: Proxy>(proxy: &P) -> (&'static str, u32) {
+    (P::interface().name, proxy.version())
 }
@@ -0,0 +1,252 @@
+//! Latency metrics (`[metrics]`)
+//!
+//! Histograms of where a key's time goes: the round trip from sending it to
+//! the backend until it is processed, the snapshot query inside the backend,
+//! and the popup render. Unlike the trace ring, which keeps the last seconds
+//! of raw events, these aggregate the whole session. Recording is lock-free
+//! (atomic counters, safe from the Neovim thread) and a no-op unless
+//! `metrics.enabled`; the summary is logged on exit.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Histogram buckets: bucket `i` counts durations below 2^(i+1) µs (the last
+/// one everything longer, from ~4 s)
+const BUCKETS: usize = 23;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static HISTOGRAMS: [Histogram; 3] = [Histogram::new(), Histogram::new(), Histogram::new()];
+
+/// Measured stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Key sent to the backend until it is processed (main thread)
+    Rpc,
+    /// Snapshot pulled from Neovim (Neovim thread)
+    Snapshot,
+    /// Popup render
+    Render,
+}
+
+impl Stage {
+    const ALL: [Stage; 3] = [Stage::Rpc, Stage::Snapshot, Stage::Render];
+
+    fn name(self) -> &'static str {
+        match self {
+            Stage::Rpc => "rpc",
+            Stage::Snapshot => "snapshot",
+            Stage::Render => "render",
+        }
+    }
+
+    fn histogram(self) -> &'static Histogram {
+        &HISTOGRAMS[self as usize]
+    }
+}
+
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+    count: AtomicU64,
+    total_us: AtomicU64,
+    max_us: AtomicU64,
+    /// Waits given up on (the stage's deadline passed)
+    timeouts: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS],
+            count: AtomicU64::new(0),
+            total_us: AtomicU64::new(0),
+            max_us: AtomicU64::new(0),
+            timeouts: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, elapsed: Duration) {
+        let us = elapsed.as_micros().min(u128::from(u64::MAX)) as u64;
+        self.buckets[bucket(us)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_us.fetch_add(us, Ordering::Relaxed);
+        self.max_us.fetch_max(us, Ordering::Relaxed);
+    }
+
+    fn summary(&self) -> Summary {
+        Summary {
+            buckets: std::array::from_fn(|i| self.buckets[i].load(Ordering::Relaxed)),
+            count: self.count.load(Ordering::Relaxed),
+            total_us: self.total_us.load(Ordering::Relaxed),
+            max_us: self.max_us.load(Ordering::Relaxed),
+            timeouts: self.timeouts.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Bucket counting `us`
+fn bucket(us: u64) -> usize {
+    (us.max(1).ilog2() as usize).min(BUCKETS - 1)
+}
+
+/// A histogram read out at one point
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Summary {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    total_us: u64,
+    max_us: u64,
+    timeouts: u64,
+}
+
+impl Summary {
+    /// Upper bound (µs) of the bucket holding quantile `q`: the histogram
+    /// only knows each duration to within a factor of two
+    fn quantile_us(&self, q: f64) -> u64 {
+        let rank = ((self.count as f64 * q).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return (1u64 << (i + 1)).min(self.max_us);
+            }
+        }
+        self.max_us
+    }
+
+    /// One log line: count, mean, p50/p95/p99 and max
+    fn line(&self, name: &str) -> String {
+        let mut line = if self.count == 0 {
+            format!("{name}: no samples")
+        } else {
+            format!(
+                "{name}: n={} mean {} p50 {} p95 {} p99 {} max {}",
+                self.count,
+                format_us(self.total_us / self.count),
+                format_us(self.quantile_us(0.5)),
+                format_us(self.quantile_us(0.95)),
+                format_us(self.quantile_us(0.99)),
+                format_us(self.max_us)
+            )
+        };
+        if self.timeouts > 0 {
+            line.push_str(&format!(" timeouts {}", self.timeouts));
+        }
+        line
+    }
+}
+
+fn format_us(us: u64) -> String {
+    format_duration(Duration::from_micros(us))
+}
+
+/// Short latency label: "0.42ms", "3.1ms", "120ms"
+pub fn format_duration(duration: Duration) -> String {
+    let ms = duration.as_secs_f64() * 1000.0;
+    if ms < 1.0 {
+        format!("{ms:.2}ms")
+    } else if ms < 10.0 {
+        format!("{ms:.1}ms")
+    } else {
+        format!("{ms:.0}ms")
+    }
+}
+
+/// Start collecting (`metrics.enabled`)
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Record one `stage` that took `elapsed`
+pub fn record(stage: Stage, elapsed: Duration) {
+    if enabled() {
+        stage.histogram().record(elapsed);
+    }
+}
+
+/// Count a wait on `stage` that was given up on
+pub fn timed_out(stage: Stage) {
+    if enabled() {
+        stage.histogram().timeouts.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Records `stage` from now until dropped
+pub fn timer(stage: Stage) -> Timer {
+    Timer(enabled().then(|| (stage, Instant::now())))
+}
+
+pub struct Timer(Option<(Stage, Instant)>);
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some((stage, start)) = self.0 {
+            stage.histogram().record(start.elapsed());
+        }
+    }
+}
+
+/// Log the session's histograms (on exit)
+pub fn log_summary() {
+    if !enabled() {
+        return;
+    }
+    for stage in Stage::ALL {
+        log::info!(
+            "[METRICS] {}",
+            stage.histogram().summary().line(stage.name())
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_land_in_power_of_two_buckets() {
+        assert_eq!(bucket(0), 0);
+        assert_eq!(bucket(1), 0);
+        assert_eq!(bucket(2), 1);
+        assert_eq!(bucket(1023), 9);
+        assert_eq!(bucket(1024), 10);
+        assert_eq!(bucket(u64::MAX), BUCKETS - 1);
+    }
+
+    #[test]
+    fn summary_reports_quantiles_and_timeouts() {
+        let histogram = Histogram::new();
+        for _ in 0..90 {
+            histogram.record(Duration::from_micros(600));
+        }
+        for _ in 0..10 {
+            histogram.record(Duration::from_millis(20));
+        }
+        histogram.timeouts.fetch_add(2, Ordering::Relaxed);
+        let summary = histogram.summary();
+        assert_eq!(summary.count, 100);
+        // 600 µs is in the 512..1024 µs bucket
+        assert_eq!(summary.quantile_us(0.5), 1024);
+        // 20 ms is in the 16.4..32.8 ms bucket, capped at the max seen
+        assert_eq!(summary.quantile_us(0.95), 20_000);
+        assert_eq!(
+            summary.line("rpc"),
+            "rpc: n=100 mean 2.5ms p50 1.0ms p95 20ms p99 20ms max 20ms timeouts 2"
+        );
+        assert_eq!(
+            Histogram::new().summary().line("render"),
+            "render: no samples"
+        );
+    }
+
+    #[test]
+    fn latency_labels_keep_two_significant_digits() {
+        assert_eq!(format_duration(Duration::from_micros(420)), "0.42ms");
+        assert_eq!(format_duration(Duration::from_micros(3140)), "3.1ms");
+        assert_eq!(format_duration(Duration::from_millis(120)), "120ms");
+    }
+}
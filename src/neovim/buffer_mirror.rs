@@ -0,0 +1,291 @@
+//! Preedit updates from byte-level buffer edits (`nvim_buf_attach` on_bytes)
+//!
+//! Plugins that edit the buffer without a key (autopairs closing a bracket
+//! after a delay, snippet expansion, timers) change the preedit when no key is
+//! waiting for a snapshot, so the popup would stay stale until the next key.
+//! Each on_bytes edit arrives as `ime_buf_bytes` with the edited line; edits
+//! on the cursor line become a preedit directly, with the cursor shifted by
+//! the edit. Snapshots (push or pull) stay authoritative: they replace the
+//! mirrored state, and a push older than an edit already shown is dropped.
+//!
+//! The mirror also remembers the last recorded macro sent on, so a snapshot
+//! only reports it again once it changed.
+
+use super::protocol::{MacroRegister, PreeditInfo, Snapshot};
+
+/// One on_bytes edit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ByteEdit {
+    /// b:changedtick after the edit
+    pub tick: u64,
+    /// Start of the edit (0-indexed row, byte column)
+    pub row: usize,
+    pub col: usize,
+    /// Bytes replaced and inserted
+    pub old_len: usize,
+    pub new_len: usize,
+    /// The edit spans or creates line breaks
+    pub multiline: bool,
+    /// Text of `row` after the edit
+    pub line: String,
+}
+
+/// What an edit means for the popup
+#[derive(Debug, Clone, PartialEq)]
+pub enum EditOutcome {
+    /// Show this preedit
+    Preedit(PreeditInfo),
+    /// Not visible, or a snapshot is already coming
+    Ignore,
+    /// Can't be translated (line breaks, non-insert mode): pull a snapshot
+    Resync,
+}
+
+/// Cursor line state from the latest snapshot, kept current by edits
+#[derive(Debug, Clone)]
+struct CursorLine {
+    row: usize,
+    text: String,
+    /// 0-indexed byte offset
+    cursor: usize,
+    mode: String,
+    recording: String,
+    undo_seq: u64,
+    repeatable: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct BufferMirror {
+    line: Option<CursorLine>,
+    /// changedtick of the newest state seen (snapshot or edit)
+    tick: u64,
+    /// A key is being processed; its own snapshot will follow
+    busy: bool,
+    /// Last recorded macro sent to the event loop
+    last_macro: Option<MacroRegister>,
+}
+
+impl BufferMirror {
+    pub fn set_busy(&mut self, busy: bool) {
+        self.busy = busy;
+    }
+
+    /// The snapshot's last recorded macro, if it isn't the one already sent
+    pub fn changed_macro(&mut self, snapshot: &Snapshot) -> Option<MacroRegister> {
+        let last_macro = snapshot.last_macro()?;
+        if self.last_macro.as_ref() == Some(&last_macro) {
+            return None;
+        }
+        self.last_macro = Some(last_macro.clone());
+        Some(last_macro)
+    }
+
+    /// Take a snapshot as the current state. Returns false if it predates an
+    /// edit already applied (the caller should not show it).
+    pub fn reconcile(&mut self, snapshot: &Snapshot) -> bool {
+        if snapshot.tick < self.tick {
+            return false;
+        }
+        self.tick = snapshot.tick;
+        self.line = Some(CursorLine {
+            row: snapshot.row,
+            text: snapshot.preedit.clone(),
+            cursor: snapshot.cursor_byte.saturating_sub(1),
+            mode: snapshot.mode.clone(),
+            recording: snapshot.recording.clone(),
+            undo_seq: snapshot.undo_seq,
+            repeatable: !snapshot.last_change.is_empty(),
+        });
+        true
+    }
+
+    pub fn apply(&mut self, edit: &ByteEdit) -> EditOutcome {
+        // Already covered by a snapshot
+        if edit.tick <= self.tick {
+            return EditOutcome::Ignore;
+        }
+        self.tick = edit.tick;
+        let busy = self.busy;
+        let Some(line) = self.line.as_mut() else {
+            return EditOutcome::Ignore;
+        };
+        if edit.multiline {
+            // Lines may have moved under the cursor
+            return if busy {
+                EditOutcome::Ignore
+            } else {
+                EditOutcome::Resync
+            };
+        }
+        if edit.row != line.row {
+            return EditOutcome::Ignore;
+        }
+        line.text = edit.line.clone();
+        line.cursor = shift_cursor(line.cursor, edit).min(line.text.len());
+        if busy {
+            EditOutcome::Ignore
+        } else if !line.mode.starts_with('i') {
+            // Normal-mode cursors cover a character; let a snapshot measure it
+            EditOutcome::Resync
+        } else {
+            EditOutcome::Preedit(
+                PreeditInfo::new(
+                    line.text.clone(),
+                    line.cursor,
+                    line.cursor,
+                    line.mode.clone(),
+                    line.recording.clone(),
+                )
+                .with_history(line.undo_seq, line.repeatable),
+            )
+        }
+    }
+}
+
+/// Cursor byte offset after `edit` on the cursor line: text after the cursor
+/// doesn't move it, text before it shifts it, and a replaced range containing
+/// it leaves it at the end of the replacement at most.
+fn shift_cursor(cursor: usize, edit: &ByteEdit) -> usize {
+    let old_end = edit.col + edit.old_len;
+    if cursor <= edit.col {
+        cursor
+    } else if cursor >= old_end {
+        cursor - edit.old_len + edit.new_len
+    } else {
+        cursor.min(edit.col + edit.new_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(preedit: &str, cursor_byte: usize, mode: &str, tick: u64) -> Snapshot {
+        Snapshot {
+            preedit: preedit.into(),
+            cursor_byte,
+            mode: mode.into(),
+            blocking: false,
+            char_width: 0,
+            visual_begin: None,
+            visual_end: None,
+            visual_overhang: 0,
+            recording: String::new(),
+            row: 0,
+            tick,
+            undo_seq: 0,
+            last_change: String::new(),
+            segment_begin: None,
+            segment_converted: false,
+            recorded: String::new(),
+            recorded_keys: String::new(),
+            highlights: Vec::new(),
+        }
+    }
+
+    fn edit(tick: u64, col: usize, old_len: usize, new_len: usize, line: &str) -> ByteEdit {
+        ByteEdit {
+            tick,
+            row: 0,
+            col,
+            old_len,
+            new_len,
+            multiline: false,
+            line: line.into(),
+        }
+    }
+
+    fn preedit(outcome: EditOutcome) -> (String, usize) {
+        match outcome {
+            EditOutcome::Preedit(info) => (info.text, info.cursor_begin),
+            other => panic!("expected Preedit, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn async_autopair_keeps_cursor_inside() {
+        let mut mirror = BufferMirror::default();
+        // "f(" typed, cursor after "("
+        assert!(mirror.reconcile(&snapshot("f(", 3, "i", 5)));
+        // Autopairs inserts ")" at the cursor from a timer
+        let (text, cursor) = preedit(mirror.apply(&edit(6, 2, 0, 1, "f()")));
+        assert_eq!((text.as_str(), cursor), ("f()", 2));
+    }
+
+    #[test]
+    fn edits_before_the_cursor_shift_it() {
+        let mut mirror = BufferMirror::default();
+        mirror.reconcile(&snapshot("かな漢字", 13, "i", 1));
+        // Snippet replaces "かな" (6 bytes) with "仮名" (6) then "k" (1)
+        let (_, cursor) = preedit(mirror.apply(&edit(2, 0, 6, 6, "仮名漢字")));
+        assert_eq!(cursor, 12);
+        let (text, cursor) = preedit(mirror.apply(&edit(3, 0, 6, 1, "k漢字")));
+        assert_eq!((text.as_str(), cursor), ("k漢字", 7));
+        // Deleting a range around the cursor leaves it at the range start
+        mirror.reconcile(&snapshot("abcdef", 4, "i", 4));
+        let (_, cursor) = preedit(mirror.apply(&edit(5, 1, 4, 0, "af")));
+        assert_eq!(cursor, 1);
+    }
+
+    #[test]
+    fn stale_pushes_and_replayed_edits_are_dropped() {
+        let mut mirror = BufferMirror::default();
+        mirror.reconcile(&snapshot("a", 2, "i", 10));
+        assert_eq!(mirror.apply(&edit(10, 1, 0, 1, "ab")), EditOutcome::Ignore);
+        preedit(mirror.apply(&edit(11, 1, 0, 1, "ab")));
+        // A push collected before the edit
+        assert!(!mirror.reconcile(&snapshot("a", 2, "i", 10)));
+        // Same tick = cursor move only: accepted
+        assert!(mirror.reconcile(&snapshot("ab", 2, "i", 11)));
+    }
+
+    #[test]
+    fn untranslatable_edits_resync_when_idle() {
+        let mut mirror = BufferMirror::default();
+        // Nothing shown yet
+        assert_eq!(mirror.apply(&edit(1, 0, 0, 1, "a")), EditOutcome::Ignore);
+
+        mirror.reconcile(&snapshot("abc", 1, "n", 1));
+        assert_eq!(mirror.apply(&edit(2, 0, 0, 1, "xabc")), EditOutcome::Resync);
+        let mut multiline = edit(3, 0, 0, 1, "");
+        multiline.multiline = true;
+        assert_eq!(mirror.apply(&multiline), EditOutcome::Resync);
+
+        // Other lines don't show
+        mirror.reconcile(&snapshot("abc", 1, "i", 3));
+        let mut other_row = edit(4, 0, 0, 1, "x");
+        other_row.row = 1;
+        assert_eq!(mirror.apply(&other_row), EditOutcome::Ignore);
+    }
+
+    #[test]
+    fn busy_mirror_tracks_without_emitting() {
+        let mut mirror = BufferMirror::default();
+        mirror.reconcile(&snapshot("", 1, "i", 1));
+        mirror.set_busy(true);
+        assert_eq!(mirror.apply(&edit(2, 0, 0, 1, "(")), EditOutcome::Ignore);
+        // The key's own snapshot
+        assert!(mirror.reconcile(&snapshot("(", 2, "i", 2)));
+        mirror.set_busy(false);
+        let (text, cursor) = preedit(mirror.apply(&edit(3, 1, 0, 1, "()")));
+        assert_eq!((text.as_str(), cursor), ("()", 1));
+    }
+
+    #[test]
+    fn recorded_macro_is_sent_once_per_mirror() {
+        let mut recorded = snapshot("", 0, "n", 1);
+        recorded.recorded = "q".into();
+        recorded.recorded_keys = "dd".into();
+
+        let mut mirror = BufferMirror::default();
+        assert!(mirror.changed_macro(&snapshot("", 0, "n", 1)).is_none());
+        assert_eq!(
+            mirror.changed_macro(&recorded).map(|m| m.keys),
+            Some("dd".to_string())
+        );
+        assert!(mirror.changed_macro(&recorded).is_none());
+
+        // A respawned or standby backend starts with its own mirror
+        assert!(BufferMirror::default().changed_macro(&recorded).is_some());
+    }
+}
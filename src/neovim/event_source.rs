@@ -1,18 +1,15 @@
 //! Calloop event source for Neovim messages
 //!
 //! Wraps crossbeam receiver with a ping mechanism for integration with calloop.
-//!
-//! Note: This is infrastructure for event-driven Neovim message handling.
-//! Currently the IME uses polling in the event loop callback, which is
-//! sufficient since key events trigger Wayland events that wake the loop.
-
-#![allow(dead_code)]
+//! The backend thread sends through a `NeovimSender`, which pings after every
+//! message, so a push notification arriving while the loop is idle is handled
+//! right away instead of on the next unrelated wakeup.
 
 use calloop::{
     EventSource, Poll, PostAction, Readiness, Token, TokenFactory,
     ping::{Ping, PingSource, make_ping},
 };
-use crossbeam_channel::{Receiver, TryRecvError};
+use crossbeam_channel::{Receiver, SendError, Sender, TryRecvError};
 use std::io;
 
 use super::protocol::FromNeovim;
@@ -34,6 +31,28 @@ impl NeovimPing {
     }
 }
 
+/// Sending half used by the backend thread: queues a message and wakes the loop
+#[derive(Clone)]
+pub struct NeovimSender {
+    tx: Sender<FromNeovim>,
+    ping: NeovimPing,
+}
+
+impl NeovimSender {
+    pub fn new(tx: Sender<FromNeovim>, ping: NeovimPing) -> Self {
+        Self { tx, ping }
+    }
+
+    /// Queue `msg` (blocking while the channel is full), then wake the loop.
+    /// A closed channel drops `msg` rather than handing it back: no caller
+    /// has a use for it, and it keeps the error small.
+    pub fn send(&self, msg: FromNeovim) -> Result<(), SendError<()>> {
+        self.tx.send(msg).map_err(|_| SendError(()))?;
+        self.ping.ping();
+        Ok(())
+    }
+}
+
 impl NeovimEventSource {
     /// Create a new event source wrapping a crossbeam receiver
     ///
@@ -50,15 +69,6 @@ impl NeovimEventSource {
             NeovimPing(ping),
         ))
     }
-
-    /// Try to receive all pending messages (non-blocking)
-    pub fn drain_messages(&self) -> Vec<FromNeovim> {
-        let mut messages = Vec::new();
-        while let Ok(msg) = self.receiver.try_recv() {
-            messages.push(msg);
-        }
-        messages
-    }
 }
 
 impl EventSource for NeovimEventSource {
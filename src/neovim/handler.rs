@@ -4,21 +4,31 @@
 
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::{error::Error, fmt};
 
 use async_trait::async_trait;
-use crossbeam_channel::{Receiver, Sender};
+use crossbeam_channel::Receiver;
 use tokio::runtime::Runtime;
+use tokio::sync::Notify;
 
 use nvim_rs::create::tokio::new_child_cmd;
 use nvim_rs::error::CallError;
 use nvim_rs::{Handler, Neovim, Value};
-use tokio::process::Command;
+use tokio::process::{Child, Command};
 
+use super::buffer_mirror::{BufferMirror, ByteEdit, EditOutcome};
+use super::event_source::NeovimSender;
+use super::msgpack::{self, FieldError, Map, Params};
 use super::protocol::{
-    AtomicPendingState, CandidateInfo, FromNeovim, PendingState, PreeditInfo, Snapshot, ToNeovim,
+    AtomicPendingState, CandidateInfo, CandidateWindow, ClipboardSelection, FromNeovim, InputMode,
+    PendingState, PreeditInfo, Snapshot, SnapshotStrategy, ToNeovim,
 };
-use crate::config::Config;
+use super::snapshot_strategy::{AdaptiveSnapshot, PushTracker};
+use crate::config::{Config, NvimConfig};
+use crate::dictionary;
+use crate::metrics::{self, Stage};
+use crate::trace::{self, Span};
 
 /// Single pending state for multi-key sequences (mutually exclusive).
 static PENDING: AtomicPendingState = AtomicPendingState::new();
@@ -35,7 +45,7 @@ type NvimResult<T> = Result<T, NvimError>;
 enum NvimError {
     RuntimeInit(std::io::Error),
     Backend(anyhow::Error),
-    SnapshotParse(&'static str),
+    SnapshotParse(FieldError),
 }
 
 impl fmt::Display for NvimError {
@@ -43,7 +53,7 @@ impl fmt::Display for NvimError {
         match self {
             NvimError::RuntimeInit(e) => write!(f, "runtime init failed: {e}"),
             NvimError::Backend(e) => write!(f, "backend error: {e}"),
-            NvimError::SnapshotParse(msg) => write!(f, "snapshot parse failed: {msg}"),
+            NvimError::SnapshotParse(e) => write!(f, "snapshot parse failed: {e}"),
         }
     }
 }
@@ -53,7 +63,7 @@ impl Error for NvimError {
         match self {
             NvimError::RuntimeInit(e) => Some(e),
             NvimError::Backend(e) => Some(e.root_cause()),
-            NvimError::SnapshotParse(_) => None,
+            NvimError::SnapshotParse(e) => Some(e),
         }
     }
 }
@@ -64,26 +74,150 @@ impl From<anyhow::Error> for NvimError {
     }
 }
 
-fn send_msg(tx: &Sender<FromNeovim>, msg: FromNeovim) {
+fn send_msg(tx: &NeovimSender, msg: FromNeovim) {
     if let Err(e) = tx.send(msg) {
         log::warn!("[NVIM] Failed to send message to main thread: {}", e);
     }
 }
 
+/// Column of the last grid_cursor_goto ([grid, row, col]) in a redraw event group
+fn last_cursor_col(event_group: &Value) -> Option<usize> {
+    let group = Params::new(event_group, 1).ok()?;
+    if group.str(0).ok()? != "grid_cursor_goto" {
+        return None;
+    }
+    let params = Params::new(group.rest(1).last()?, 3).ok()?;
+    params.u64(2).ok().map(|col| col as usize)
+}
+
+/// Split an SKK dictionary candidate `word;annotation` ("渡辺;person name").
+/// Without a non-empty word and annotation the whole text is the word.
+fn split_annotation(candidate: &str) -> (&str, &str) {
+    match candidate.split_once(';') {
+        Some((word, annotation)) if !word.is_empty() && !annotation.is_empty() => {
+            (word, annotation)
+        }
+        _ => (candidate, ""),
+    }
+}
+
+/// Latest compositor selections, served to the clipboard provider's paste
+/// (`ime_clipboard_get`). Neovim is blocked in getchar after `<C-r>` or `"`,
+/// so a selection arriving then can't be stored with exec_lua before the
+/// register name is typed.
+#[derive(Debug, Default)]
+struct SelectionCache {
+    clipboard: Option<String>,
+    primary: Option<String>,
+}
+
+/// Screen columns behind `CandidateInfo::base_width`: the popupmenu is
+/// anchored at the start of the completed text, and the cursor sits at its end
+#[derive(Debug, Default)]
+struct PopupColumns {
+    /// Column of the cursor after the latest redraw batch (grid_cursor_goto)
+    cursor: usize,
+    /// Column the popupmenu is anchored at (None for the command line)
+    anchor: Option<usize>,
+}
+
+impl PopupColumns {
+    fn base_width(&self) -> Option<usize> {
+        self.cursor.checked_sub(self.anchor?)
+    }
+}
+
+/// Candidate list as last shown, for popupmenu_select and the event loop's
+/// scroll requests. Lists longer than `CANDIDATE_WINDOW` are sent a window at
+/// a time; the window sent last is kept, so moving the selection within it
+/// resends the same shared slices.
+#[derive(Debug, Default)]
+struct CandidateCache {
+    /// The whole list
+    full: CandidateInfo,
+    /// What was sent last: all of `full`, or a window of it
+    sent: CandidateInfo,
+}
+
+impl CandidateCache {
+    /// A new list: what to send of it
+    fn show(&mut self, info: CandidateInfo) -> CandidateInfo {
+        let window = CandidateWindow::around(info.selected, info.candidates.len());
+        self.sent = info.window(window);
+        self.full = info;
+        self.sent.clone()
+    }
+
+    /// The selection moved to `selected` (in the whole list): the window sent
+    /// last, or one around the selection if that doesn't hold it
+    fn select(&mut self, selected: usize, base_width: Option<usize>) -> CandidateInfo {
+        self.full.selected = selected;
+        self.full.base_width = base_width;
+        if !self.sent.holds(selected) {
+            let window = CandidateWindow::around(selected, self.full.candidates.len());
+            self.sent = self.full.window(window);
+        }
+        self.sent.selected = selected - self.sent.offset;
+        self.sent.base_width = base_width;
+        self.sent.clone()
+    }
+
+    /// `window` of the list, asked for by the event loop (None without one)
+    fn scroll(&mut self, window: CandidateWindow) -> Option<CandidateInfo> {
+        if self.full.candidates.is_empty() {
+            return None;
+        }
+        self.sent = self.full.window(window);
+        Some(self.sent.clone())
+    }
+}
+
+impl SelectionCache {
+    fn slot(&mut self, selection: ClipboardSelection) -> &mut Option<String> {
+        match selection {
+            ClipboardSelection::Clipboard => &mut self.clipboard,
+            ClipboardSelection::Primary => &mut self.primary,
+        }
+    }
+}
+
 /// Handler for Neovim RPC notifications.
 /// Receives push notifications (e.g., ime_snapshot from autocmds) and
 /// forwards them to the main thread via the tx channel.
 #[derive(Clone)]
 pub struct NvimHandler {
-    tx: Sender<FromNeovim>,
-    /// Cached popupmenu items for popupmenu_select (ext_popupmenu).
-    last_popupmenu_items: Arc<Mutex<Vec<String>>>,
+    tx: NeovimSender,
+    /// Candidate list shown (ext_popupmenu or ime_candidates), shared with
+    /// the backend thread for scroll requests
+    candidates: Arc<Mutex<CandidateCache>>,
+    /// Cursor and popupmenu columns, for the width of the completed text
+    columns: Arc<Mutex<PopupColumns>>,
+    /// Push snapshots seen, for the adaptive snapshot strategy
+    pushes: Arc<PushTracker>,
+    /// Selections for register paste, updated as soon as they arrive
+    selections: Arc<Mutex<SelectionCache>>,
+    /// Cursor line as last seen by snapshots and buffer edits (see buffer_mirror).
+    /// Per backend: a standby Neovim has its own buffer and changedtick.
+    mirror: Arc<Mutex<BufferMirror>>,
 }
 
 #[async_trait]
 impl Handler for NvimHandler {
     type Writer = NvimWriter;
 
+    async fn handle_request(
+        &self,
+        name: String,
+        args: Vec<nvim_rs::Value>,
+        _neovim: Neovim<NvimWriter>,
+    ) -> Result<Value, Value> {
+        if name == "ime_clipboard_get" {
+            self.clipboard_get(&args)
+        } else {
+            Err(Value::from(format!("unknown request {name}")))
+        }
+    }
+
     async fn handle_notify(
         &self,
         name: String,
@@ -101,47 +235,36 @@ impl Handler for NvimHandler {
                         snapshot.preedit
                     );
 
-                    send_msg(&self.tx, FromNeovim::Preedit(snapshot.to_preedit_info()));
-                    send_msg(
-                        &self.tx,
-                        FromNeovim::VisualRange(snapshot.to_visual_selection()),
-                    );
-                }
-                Err(e) => {
-                    log::error!("[NVIM] Failed to parse push snapshot: {}", e);
+                    if self.mirror.lock().unwrap().reconcile(&snapshot) {
+                        send_msg(&self.tx, FromNeovim::Preedit(snapshot.to_preedit_info()));
+                        send_msg(
+                            &self.tx,
+                            FromNeovim::VisualRange(snapshot.to_visual_selection()),
+                        );
+                        publish_macro(&snapshot, &self.tx, &self.mirror);
+                    } else {
+                        log::debug!("[NVIM] Push snapshot older than a buffer edit, dropped");
+                    }
+                    // After sending, so a key waiting on it acks behind the preedit
+                    self.pushes.record_push(&snapshot);
                 }
+                Err(e) => msgpack::warn_malformed(&name, &e, value),
             }
         } else if name == "ime_candidates"
             && let Some(value) = args.first()
-            && let Some(map) = value.as_map()
         {
-            let get_arr = |field: &str| -> Option<&Vec<nvim_rs::Value>> {
-                map.iter()
-                    .find(|(k, _)| k.as_str() == Some(field))
-                    .and_then(|(_, v)| v.as_array())
-            };
-            let get_i64 = |field: &str| -> Option<i64> {
-                map.iter()
-                    .find(|(k, _)| k.as_str() == Some(field))
-                    .and_then(|(_, v)| v.as_i64())
-            };
-
-            let words: Vec<String> = get_arr("candidates")
-                .map(|arr| {
-                    arr.iter()
-                        .filter_map(|item| item.as_str().map(std::string::ToString::to_string))
-                        .collect()
-                })
-                .unwrap_or_default();
-            let selected = get_i64("selected").unwrap_or(-1);
-
-            if words.is_empty() {
-                send_msg(&self.tx, FromNeovim::Candidates(CandidateInfo::empty()));
-            } else {
-                let sel = selected.max(0) as usize;
-                let mut info = CandidateInfo::new(words, sel);
-                info.selected = info.selected.min(info.candidates.len().saturating_sub(1));
-                send_msg(&self.tx, FromNeovim::Candidates(info));
+            match parse_candidates(value) {
+                Ok(info) => {
+                    let info = self.candidates.lock().unwrap().show(info);
+                    send_msg(&self.tx, FromNeovim::Candidates(info));
+                }
+                Err(e) => msgpack::warn_malformed(&name, &e, value),
+            }
+        } else if name == "ime_buf_bytes"
+            && let Some(value) = args.first()
+        {
+            if let Err(e) = self.handle_buf_bytes(value, neovim) {
+                msgpack::warn_malformed(&name, &e, value);
             }
         } else if name == "ime_auto_commit" {
             if let Some(text) = args.first().and_then(|v| v.as_str()) {
@@ -150,34 +273,26 @@ impl Handler for NvimHandler {
             }
         } else if name == "ime_cmdline"
             && let Some(value) = args.first()
-            && let Some(map) = value.as_map()
         {
-            if let Some((executed, cmdtype)) = self.handle_ime_cmdline(map) {
-                PENDING.clear();
-                // After a ':' command executes, the buffer may have changed
-                // (e.g. :tabnew, :bnext). Query snapshot to update preedit.
-                if executed && cmdtype == ":" {
-                    // Avoid blocking notify processing here: :wq can emit
-                    // ime_auto_commit and then exit quickly.
-                    let tx = self.tx.clone();
-                    let nvim = neovim.clone();
-                    tokio::spawn(async move {
-                        if let Err(e) = query_snapshot(&nvim, &tx).await {
-                            // :q/:wq exit Neovim; channel closed errors are expected
-                            let is_channel_closed = e
-                                .downcast_ref::<Box<CallError>>()
-                                .is_some_and(|ce| ce.is_channel_closed());
-                            if is_channel_closed {
-                                log::debug!(
-                                    "[NVIM] Snapshot after command skipped (Neovim exiting)"
-                                );
-                            } else {
-                                log::warn!("[NVIM] Failed to query snapshot after command: {}", e);
-                            }
-                        }
-                    });
+            match Map::new(value).and_then(|map| self.handle_ime_cmdline(&map)) {
+                Ok(Some((executed, cmdtype))) => {
+                    PENDING.clear();
+                    // After a ':' command executes, the buffer may have changed
+                    // (e.g. :tabnew, :bnext). Query snapshot to update preedit.
+                    if executed && cmdtype == ":" {
+                        // Avoid blocking notify processing here: :wq can emit
+                        // ime_auto_commit and then exit quickly.
+                        self.spawn_snapshot_query(neovim, "command");
+                    }
                 }
+                Ok(None) => {}
+                Err(e) => msgpack::warn_malformed(&name, &e, value),
             }
+        } else if name == "ime_clipboard_copy"
+            && let Some(value) = args.first()
+            && let Err(e) = self.handle_clipboard_copy(value)
+        {
+            msgpack::warn_malformed(&name, &e, value);
         } else if name == "redraw" {
             self.handle_redraw(&args);
         }
@@ -185,6 +300,40 @@ impl Handler for NvimHandler {
 }
 
 impl NvimHandler {
+    /// Query a snapshot without blocking notification processing
+    fn spawn_snapshot_query(&self, nvim: Neovim<NvimWriter>, after: &'static str) {
+        let tx = self.tx.clone();
+        let mirror = self.mirror.clone();
+        tokio::spawn(async move {
+            if let Err(e) = query_snapshot(&nvim, &tx, &mirror).await {
+                // :q/:wq exit Neovim; channel closed errors are expected
+                let is_channel_closed = e
+                    .downcast_ref::<Box<CallError>>()
+                    .is_some_and(|ce| ce.is_channel_closed());
+                if is_channel_closed {
+                    log::debug!("[NVIM] Snapshot after {} skipped (Neovim exiting)", after);
+                } else {
+                    log::warn!("[NVIM] Failed to query snapshot after {}: {}", after, e);
+                }
+            }
+        });
+    }
+
+    /// ime_buf_bytes: an on_bytes edit, shown directly when no key is in flight
+    fn handle_buf_bytes(&self, value: &Value, nvim: Neovim<NvimWriter>) -> Result<(), FieldError> {
+        let edit = parse_byte_edit(value)?;
+        let outcome = self.mirror.lock().unwrap().apply(&edit);
+        match outcome {
+            EditOutcome::Preedit(info) => {
+                log::debug!("[NVIM] Buffer edit: preedit={:?}", info.text);
+                send_msg(&self.tx, FromNeovim::Preedit(info));
+            }
+            EditOutcome::Resync => self.spawn_snapshot_query(nvim, "buffer edit"),
+            EditOutcome::Ignore => {}
+        }
+        Ok(())
+    }
+
     fn ui_mode_to_short_mode(ui_mode: &str) -> Option<&'static str> {
         match ui_mode {
             "normal" => Some("n"),
@@ -199,30 +348,78 @@ impl NvimHandler {
         }
     }
 
+    /// ime_clipboard_get(register): latest selection text for the clipboard
+    /// provider's paste (nil if none was received)
+    fn clipboard_get(&self, args: &[Value]) -> Result<Value, Value> {
+        let selection = args
+            .first()
+            .and_then(|v| v.as_str())
+            .and_then(ClipboardSelection::from_register)
+            .ok_or_else(|| Value::from("ime_clipboard_get: unknown register"))?;
+        let text = self.selections.lock().unwrap().slot(selection).clone();
+        Ok(text.map_or(Value::Nil, Value::from))
+    }
+
+    /// ime_clipboard_copy: {register, text} from the clipboard provider's copy function
+    fn handle_clipboard_copy(&self, value: &Value) -> Result<(), FieldError> {
+        let map = Map::new(value)?;
+        let register = map.str("register")?;
+        let text = map.opt_str("text")?.unwrap_or("").to_string();
+        let Some(selection) = ClipboardSelection::from_register(register) else {
+            log::debug!("[NVIM] ime_clipboard_copy: unknown register {:?}", register);
+            return Ok(());
+        };
+        log::debug!(
+            "[NVIM] Clipboard copy to {:?} ({} bytes)",
+            selection,
+            text.len()
+        );
+        send_msg(&self.tx, FromNeovim::ClipboardCopy { selection, text });
+        Ok(())
+    }
+
     /// Parse and dispatch redraw notification events (ext_cmdline, ext_popupmenu).
     fn handle_redraw(&self, args: &[Value]) {
+        // The cursor is moved after the popupmenu is drawn: take its final
+        // column before the popupmenu events need it
+        if let Some(col) = args.iter().rev().find_map(last_cursor_col) {
+            self.columns.lock().unwrap().cursor = col;
+        }
         for event_group in args {
-            let Some(arr) = event_group.as_array() else {
-                continue;
-            };
-            let Some(event_name) = arr.first().and_then(|v| v.as_str()) else {
-                continue;
-            };
             // Each event_group: ["event_name", params_call1, params_call2, ...]
-            for params in &arr[1..] {
-                match event_name {
+            let event =
+                Params::new(event_group, 1).and_then(|group| Ok((group.str(0)?, group.rest(1))));
+            let (event_name, calls) = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    msgpack::warn_malformed("redraw event", &e, event_group);
+                    continue;
+                }
+            };
+            for params in calls {
+                let result = match event_name {
                     "cmdline_show" => self.handle_cmdline_show(params),
                     "cmdline_pos" => self.handle_cmdline_pos(params),
                     "cmdline_hide" => self.handle_cmdline_hide(params),
                     "popupmenu_show" => self.handle_popupmenu_show(params),
                     "popupmenu_select" => self.handle_popupmenu_select(params),
-                    "popupmenu_hide" => self.handle_popupmenu_hide(),
+                    "popupmenu_hide" => {
+                        self.handle_popupmenu_hide();
+                        Ok(())
+                    }
                     "msg_show" => self.handle_msg_show(params),
-                    "msg_clear" => self.handle_msg_clear(),
+                    "msg_clear" => {
+                        self.handle_msg_clear();
+                        Ok(())
+                    }
                     "mode_change" => self.handle_mode_change(params),
                     _ => {
                         log::trace!("[NVIM] Ignoring redraw event: {}", event_name);
+                        Ok(())
                     }
+                };
+                if let Err(e) = result {
+                    msgpack::warn_malformed(event_name, &e, params);
                 }
             }
         }
@@ -230,35 +427,14 @@ impl NvimHandler {
 
     /// cmdline_show: [content, pos, firstc, prompt, indent, level]
     /// content: [[attr_id, text], ...]
-    fn handle_cmdline_show(&self, params: &Value) {
-        let Some(arr) = params.as_array() else {
-            log::debug!("[NVIM] cmdline_show: expected array params");
-            return;
-        };
-        if arr.len() < 6 {
-            log::debug!("[NVIM] cmdline_show: expected 6 params, got {}", arr.len());
-            return;
-        }
-        // Parse content: array of [attr_id, text] chunks
-        let content = if let Some(chunks) = arr[0].as_array() {
-            chunks
-                .iter()
-                .filter_map(|chunk| {
-                    chunk
-                        .as_array()
-                        .and_then(|c| c.get(1))
-                        .and_then(|v| v.as_str())
-                })
-                .collect::<Vec<&str>>()
-                .join("")
-        } else {
-            String::new()
-        };
-        let pos = arr[1].as_u64().unwrap_or(0) as usize;
-        let firstc = arr[2].as_str().unwrap_or("").to_string();
-        let prompt = arr[3].as_str().unwrap_or("").to_string();
-        // arr[4] = indent (unused)
-        let level = arr[5].as_u64().unwrap_or(1);
+    fn handle_cmdline_show(&self, params: &Value) -> Result<(), FieldError> {
+        let params = Params::new(params, 6)?;
+        let content = msgpack::chunks_text(params.array(0)?)?;
+        let pos = params.u64(1)? as usize;
+        let firstc = params.str(2)?.to_string();
+        let prompt = params.str(3)?.to_string();
+        // #4 = indent (unused)
+        let level = params.u64(5)?;
 
         // Set CommandLine pending from the redraw notification side so that
         // plugin-triggered command-line mode (e.g., input() from
@@ -283,65 +459,57 @@ impl NvimHandler {
                 level,
             },
         );
+        Ok(())
     }
 
     /// cmdline_pos: [pos, level]
-    fn handle_cmdline_pos(&self, params: &Value) {
-        let Some(arr) = params.as_array() else {
-            log::debug!("[NVIM] cmdline_pos: expected array params");
-            return;
-        };
-        if arr.len() < 2 {
-            log::debug!("[NVIM] cmdline_pos: expected 2 params, got {}", arr.len());
-            return;
-        }
-        let pos = arr[0].as_u64().unwrap_or(0) as usize;
-        let level = arr[1].as_u64().unwrap_or(1);
+    fn handle_cmdline_pos(&self, params: &Value) -> Result<(), FieldError> {
+        let params = Params::new(params, 2)?;
+        let pos = params.u64(0)? as usize;
+        let level = params.u64(1)?;
         log::trace!("[NVIM] cmdline_pos: pos={}, level={}", pos, level);
         send_msg(&self.tx, FromNeovim::CmdlinePos { pos, level });
+        Ok(())
     }
 
     /// popupmenu_show: [items, selected, row, col, grid]
     /// items: [[word, kind, menu, info], ...]
-    fn handle_popupmenu_show(&self, params: &Value) {
-        let Some(arr) = params.as_array() else {
-            log::debug!("[NVIM] popupmenu_show: expected array params");
-            return;
-        };
-        if arr.len() < 2 {
-            log::debug!(
-                "[NVIM] popupmenu_show: expected >= 2 params, got {}",
-                arr.len()
-            );
-            return;
+    /// The menu text of an item with a word doubles as its reading (kana-only
+    /// readings are shown as furigana; anything else is ignored by the popup).
+    /// The annotation comes from an SKK `word;annotation`, else the first line of info.
+    fn handle_popupmenu_show(&self, params: &Value) -> Result<(), FieldError> {
+        let params = Params::new(params, 2)?;
+        let items = params.array(0)?;
+        let selected = params.i64(1)?;
+        let col = params.opt_i64(3)?;
+        let grid = params.opt_i64(4)?;
+
+        let mut words = Vec::new();
+        let mut readings = Vec::new();
+        let mut annotations = Vec::new();
+        let mut kinds = Vec::new();
+        for item in items {
+            let item = Params::new(item, 0)?;
+            let field = |i: usize| item.opt_str(i).map(|s| s.unwrap_or(""));
+            // Try word first, then menu, then kind (Codex: kind is label-like)
+            let (word, kind, menu, info) = (field(0)?, field(1)?, field(2)?, field(3)?);
+            let (word, reading, annotation, kind) = if !word.is_empty() {
+                let (word, annotation) = split_annotation(word);
+                let annotation = match annotation {
+                    "" => info.lines().next().unwrap_or(""),
+                    a => a,
+                };
+                (word, menu, annotation, kind)
+            } else if !menu.is_empty() {
+                (menu, "", "", kind)
+            } else {
+                (kind, "", "", "")
+            };
+            words.push(word.to_string());
+            readings.push(reading.to_string());
+            annotations.push(annotation.to_string());
+            kinds.push(kind.to_string());
         }
-        let items = arr[0].as_array();
-        let selected = arr[1].as_i64().unwrap_or(-1);
-
-        let words: Vec<String> = items
-            .map(|item_arr| {
-                item_arr
-                    .iter()
-                    .map(|item| {
-                        let fields = match item.as_array() {
-                            Some(f) => f,
-                            None => return String::new(),
-                        };
-                        // Try word first, then menu, then kind (Codex: kind is label-like)
-                        let word = fields.first().and_then(|v| v.as_str()).unwrap_or("");
-                        if !word.is_empty() {
-                            return word.to_string();
-                        }
-                        let menu = fields.get(2).and_then(|v| v.as_str()).unwrap_or("");
-                        if !menu.is_empty() {
-                            return menu.to_string();
-                        }
-                        let kind = fields.get(1).and_then(|v| v.as_str()).unwrap_or("");
-                        kind.to_string()
-                    })
-                    .collect()
-            })
-            .unwrap_or_default();
 
         log::debug!(
             "[NVIM] popupmenu_show: {} items, selected={}",
@@ -349,64 +517,62 @@ impl NvimHandler {
             selected
         );
 
+        let sel = (selected.max(0) as usize).min(words.len().saturating_sub(1));
+        let base_width = {
+            let mut columns = self.columns.lock().unwrap();
+            // grid -1: the popupmenu completes the command line
+            columns.anchor = col
+                .filter(|&col| col >= 0 && grid != Some(-1))
+                .map(|col| col as usize);
+            columns.base_width()
+        };
+        let info = CandidateInfo::new(words, sel)
+            .with_readings(readings)
+            .with_annotations(annotations)
+            .with_kinds(kinds)
+            .with_base_width(base_width);
         // Cache items for popupmenu_select
-        *self.last_popupmenu_items.lock().unwrap() = words.clone();
-
-        if words.is_empty() {
-            send_msg(&self.tx, FromNeovim::Candidates(CandidateInfo::empty()));
-        } else {
-            let sel = selected.max(0) as usize;
-            let mut info = CandidateInfo::new(words, sel);
-            info.selected = info.selected.min(info.candidates.len().saturating_sub(1));
-            send_msg(&self.tx, FromNeovim::Candidates(info));
-        }
+        let info = self.candidates.lock().unwrap().show(info);
+        send_msg(&self.tx, FromNeovim::Candidates(info));
+        Ok(())
     }
 
     /// popupmenu_select: [selected]
-    fn handle_popupmenu_select(&self, params: &Value) {
-        let selected = params
-            .as_array()
-            .and_then(|a| a.first())
-            .and_then(|v| v.as_i64())
-            .unwrap_or(-1);
-
-        let items = self.last_popupmenu_items.lock().unwrap();
+    fn handle_popupmenu_select(&self, params: &Value) -> Result<(), FieldError> {
+        let selected = Params::new(params, 1)?.i64(0)?;
+
         log::trace!("[NVIM] popupmenu_select: selected={}", selected);
 
-        if items.is_empty() {
-            send_msg(&self.tx, FromNeovim::Candidates(CandidateInfo::empty()));
-        } else {
-            // selected = -1 means no selection; clamp to 0
-            let sel = (selected.max(0) as usize).min(items.len().saturating_sub(1));
-            send_msg(
-                &self.tx,
-                FromNeovim::Candidates(CandidateInfo::new(items.clone(), sel)),
-            );
-        }
+        let mut candidates = self.candidates.lock().unwrap();
+        // selected = -1 means no selection; clamp to 0
+        let selected = (selected.max(0) as usize).min(candidates.full.total().saturating_sub(1));
+        // Inserting the selected word moves the cursor
+        let base_width = self.columns.lock().unwrap().base_width();
+        let info = candidates.select(selected, base_width);
+        drop(candidates);
+        send_msg(&self.tx, FromNeovim::Candidates(info));
+        Ok(())
     }
 
     /// popupmenu_hide
     fn handle_popupmenu_hide(&self) {
         log::debug!("[NVIM] popupmenu_hide");
-        self.last_popupmenu_items.lock().unwrap().clear();
+        *self.candidates.lock().unwrap() = CandidateCache::default();
         send_msg(&self.tx, FromNeovim::Candidates(CandidateInfo::empty()));
     }
 
     /// mode_change: [mode, mode_idx]
     /// mode is a UI-namespace name (e.g., "normal", "insert", "cmdline_normal")
     /// which differs from nvim_get_mode() short forms ("n", "i", "c").
-    fn handle_mode_change(&self, params: &Value) {
-        let ui_mode = params
-            .as_array()
-            .and_then(|a| a.first())
-            .and_then(|v| v.as_str())
-            .unwrap_or("");
+    fn handle_mode_change(&self, params: &Value) -> Result<(), FieldError> {
+        let ui_mode = Params::new(params, 1)?.str(0)?;
         log::debug!("[NVIM] mode_change: {:?}", ui_mode);
         let Some(short_mode) = Self::ui_mode_to_short_mode(ui_mode) else {
             log::trace!("[NVIM] mode_change: unmapped UI mode {:?}", ui_mode);
-            return;
+            return Ok(());
         };
         send_msg(&self.tx, FromNeovim::ModeChange(short_mode.to_string()));
+        Ok(())
     }
 
     /// Blocklist of msg_show kinds to ignore.
@@ -419,16 +585,9 @@ impl NvimHandler {
 
     /// msg_show: [kind, content, replace_last]
     /// content: [[attr_id, text], ...]
-    fn handle_msg_show(&self, params: &Value) {
-        let Some(arr) = params.as_array() else {
-            log::debug!("[NVIM] msg_show: expected array params");
-            return;
-        };
-        if arr.len() < 2 {
-            log::debug!("[NVIM] msg_show: expected >= 2 params, got {}", arr.len());
-            return;
-        }
-        let kind = arr[0].as_str().unwrap_or("");
+    fn handle_msg_show(&self, params: &Value) -> Result<(), FieldError> {
+        let params = Params::new(params, 2)?;
+        let kind = params.str(0)?;
 
         // Log all kinds for debugging (Codex: observe in real environment)
         log::debug!("[NVIM] msg_show: kind={:?}", kind);
@@ -436,29 +595,13 @@ impl NvimHandler {
         // Blocklist filter
         if Self::MSG_KIND_BLOCKLIST.contains(&kind) {
             log::trace!("[NVIM] msg_show: blocked kind={:?}", kind);
-            return;
+            return Ok(());
         }
 
-        // Parse content: array of [attr_id, text] chunks
-        let text = if let Some(chunks) = arr[1].as_array() {
-            chunks
-                .iter()
-                .filter_map(|chunk| {
-                    chunk
-                        .as_array()
-                        .and_then(|c| c.get(1))
-                        .and_then(|v| v.as_str())
-                })
-                .collect::<Vec<&str>>()
-                .join("")
-        } else {
-            log::debug!("[NVIM] msg_show: raw content={:?}", arr[1]);
-            return;
-        };
-
+        let text = msgpack::chunks_text(params.array(1)?)?;
         let text = text.trim().to_string();
         if text.is_empty() {
-            return;
+            return Ok(());
         }
 
         log::debug!("[NVIM] msg_show: kind={:?}, text={:?}", kind, text);
@@ -469,6 +612,7 @@ impl NvimHandler {
                 cmdtype: String::new(),
             },
         );
+        Ok(())
     }
 
     /// msg_clear
@@ -484,30 +628,20 @@ impl NvimHandler {
     }
 
     /// cmdline_hide: [level]
-    fn handle_cmdline_hide(&self, params: &Value) {
-        let Some(arr) = params.as_array() else {
-            log::debug!("[NVIM] cmdline_hide: expected array params");
-            return;
-        };
-        let level = arr.first().and_then(|v| v.as_u64()).unwrap_or(1);
+    fn handle_cmdline_hide(&self, params: &Value) -> Result<(), FieldError> {
+        let level = Params::new(params, 0)?.opt_u64(0)?.unwrap_or(1);
         log::debug!("[NVIM] cmdline_hide: level={}", level);
         send_msg(&self.tx, FromNeovim::CmdlineHide { level });
+        Ok(())
     }
 
     /// Handle ime_cmdline notification (CmdlineLeave autocmd).
-    /// Returns Some((executed, cmdtype)) if the event was processed, None otherwise.
-    /// Note: caller is responsible for clearing PENDING state.
-    fn handle_ime_cmdline(&self, map: &[(Value, Value)]) -> Option<(bool, String)> {
-        let get_str = |field: &str| -> Option<String> {
-            map.iter()
-                .find(|(k, _)| k.as_str() == Some(field))
-                .and_then(|(_, v)| v.as_str().map(std::string::ToString::to_string))
-        };
-
-        match get_str("type").as_deref() {
-            Some("cancelled" | "executed") => {
-                let event = get_str("type").unwrap_or_default();
-                let cmdtype = get_str("cmdtype").unwrap_or_else(|| ":".to_string());
+    /// Returns Some((executed, cmdtype)) if the event was processed, None for an
+    /// unknown type. Note: caller is responsible for clearing PENDING state.
+    fn handle_ime_cmdline(&self, map: &Map) -> Result<Option<(bool, String)>, FieldError> {
+        match map.str("type")? {
+            event @ ("cancelled" | "executed") => {
+                let cmdtype = map.opt_str("cmdtype")?.unwrap_or(":").to_string();
                 let executed = event == "executed";
                 log::debug!("[NVIM] Cmdline left ({}, cmdtype={})", event, cmdtype);
                 send_msg(
@@ -517,303 +651,105 @@ impl NvimHandler {
                         executed,
                     },
                 );
-                Some((executed, cmdtype))
+                Ok(Some((executed, cmdtype)))
             }
             other => {
                 log::warn!("[NVIM] Unknown cmdline type: {:?}", other);
-                None
+                Ok(None)
             }
         }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crossbeam_channel::{TryRecvError, unbounded};
-
-    fn make_handler() -> (NvimHandler, crossbeam_channel::Receiver<FromNeovim>) {
-        let (tx, rx) = unbounded();
-        (
-            NvimHandler {
-                tx,
-                last_popupmenu_items: Arc::new(Mutex::new(Vec::new())),
-            },
-            rx,
-        )
-    }
+/// Run the Neovim event loop in a blocking manner
+pub fn run_blocking(rx: Receiver<ToNeovim>, tx: NeovimSender, watch: Arc<Watch>, config: Config) {
+    let rt = match Runtime::new() {
+        Ok(rt) => rt,
+        Err(e) => {
+            let err = NvimError::RuntimeInit(e);
+            log::error!("[NVIM] {}", err);
+            return;
+        }
+    };
+    rt.block_on(async move {
+        if let Err(e) = run_neovim(rx, tx, watch, &config).await {
+            log::error!("[NVIM] Error: {}", e);
+        }
+    });
+}
 
-    #[test]
-    fn ui_mode_mapping_covers_cmdline_and_operator() {
-        assert_eq!(
-            NvimHandler::ui_mode_to_short_mode("cmdline_normal"),
-            Some("c")
-        );
-        assert_eq!(
-            NvimHandler::ui_mode_to_short_mode("cmdline_insert"),
-            Some("c")
-        );
-        assert_eq!(NvimHandler::ui_mode_to_short_mode("operator"), Some("no"));
-    }
+async fn run_neovim(
+    rx: Receiver<ToNeovim>,
+    tx: NeovimSender,
+    watch: Arc<Watch>,
+    config: &Config,
+) -> NvimResult<()> {
+    log::info!("[NVIM] Starting Neovim...");
 
-    #[test]
-    fn ui_mode_mapping_covers_visual_line_and_block() {
-        assert_eq!(NvimHandler::ui_mode_to_short_mode("visual"), Some("v"));
-        assert_eq!(NvimHandler::ui_mode_to_short_mode("visual_line"), Some("V"));
-        assert_eq!(
-            NvimHandler::ui_mode_to_short_mode("visual_block"),
-            Some("\x16")
-        );
+    // Start Neovim in embedded mode
+    let mut cmd = Command::new(config.nvim.binary());
+    cmd.args(["--embed", "--headless"]);
+    if config.clean {
+        cmd.arg("--clean");
     }
+    cmd.args(config.nvim.args());
+    cmd.envs(&config.nvim.env);
 
-    #[test]
-    fn mode_change_emits_short_mode_message() {
-        let (handler, rx) = make_handler();
-
-        handler.handle_mode_change(&Value::Array(vec![
-            Value::from("cmdline_normal"),
-            Value::from(0),
-        ]));
-        match rx.try_recv().unwrap() {
-            FromNeovim::ModeChange(mode) => assert_eq!(mode, "c"),
-            other => panic!("expected ModeChange(\"c\"), got {other:?}"),
-        }
+    let pushes = Arc::new(PushTracker::default());
+    let selections = Arc::new(Mutex::new(SelectionCache::default()));
+    let mirror = Arc::new(Mutex::new(BufferMirror::default()));
+    let candidates = Arc::new(Mutex::new(CandidateCache::default()));
+    let handler = NvimHandler {
+        tx: tx.clone(),
+        candidates: candidates.clone(),
+        columns: Arc::default(),
+        pushes: pushes.clone(),
+        selections: selections.clone(),
+        mirror: mirror.clone(),
+    };
+    let (nvim, io_handler, child) = new_child_cmd(&mut cmd, handler)
+        .await
+        .map_err(|e| NvimError::Backend(e.into()))?;
 
-        handler.handle_mode_change(&Value::Array(vec![Value::from("operator"), Value::from(0)]));
-        match rx.try_recv().unwrap() {
-            FromNeovim::ModeChange(mode) => assert_eq!(mode, "no"),
-            other => panic!("expected ModeChange(\"no\"), got {other:?}"),
-        }
-    }
+    log::info!("[NVIM] Connected to Neovim");
 
-    #[test]
-    fn cmdline_show_and_hide_emit_messages_and_set_pending_state() {
-        PENDING.clear();
-        let (handler, rx) = make_handler();
+    // Initialize
+    init_neovim(&nvim, config).await.map_err(NvimError::from)?;
 
-        handler.handle_cmdline_show(&Value::Array(vec![
-            Value::Array(vec![Value::Array(vec![Value::from(0), Value::from("set")])]),
-            Value::from(3),
-            Value::from(":"),
-            Value::from(""),
-            Value::from(0),
-            Value::from(1),
-        ]));
+    send_msg(&tx, FromNeovim::Ready);
 
-        assert_eq!(PENDING.load(), PendingState::CommandLine);
-        match rx.try_recv().unwrap() {
-            FromNeovim::CmdlineShow {
-                content,
-                pos,
-                firstc,
-                prompt,
-                level,
-            } => {
-                assert_eq!(content, "set");
-                assert_eq!(pos, 3);
-                assert_eq!(firstc, ":");
-                assert_eq!(prompt, "");
-                assert_eq!(level, 1);
+    // Keybinds follow Neovim's 'langmap' unless jacin's config sets one
+    if config.keybinds.langmap.is_empty() {
+        match nvim.exec_lua("return vim.o.langmap", vec![]).await {
+            Ok(value) => {
+                if let Some(langmap) = value.as_str().filter(|s| !s.is_empty()) {
+                    log::info!("[NVIM] langmap: {:?}", langmap);
+                    send_msg(&tx, FromNeovim::Langmap(langmap.to_string()));
+                }
             }
-            other => panic!("expected CmdlineShow, got {other:?}"),
+            Err(e) => log::warn!("[NVIM] Failed to read 'langmap': {}", e),
         }
+    }
 
-        handler.handle_cmdline_hide(&Value::Array(vec![Value::from(1)]));
-        match rx.try_recv().unwrap() {
-            FromNeovim::CmdlineHide { level } => assert_eq!(level, 1),
-            other => panic!("expected CmdlineHide, got {other:?}"),
-        }
-        PENDING.clear();
+    // Track whether Neovim has exited (e.g., via :q) to avoid sending qa! to dead process.
+    let exited = Arc::new(AtomicBool::new(false));
+    {
+        let tx = tx.clone();
+        let exited = exited.clone();
+        tokio::spawn(async move {
+            match io_handler.await {
+                Ok(Ok(())) => log::info!("[NVIM] I/O loop ended cleanly"),
+                Ok(Err(e)) => log::error!("[NVIM] I/O loop error: {}", e),
+                Err(e) => log::error!("[NVIM] I/O task panicked: {}", e),
+            }
+            if !exited.swap(true, Ordering::SeqCst) {
+                send_msg(&tx, FromNeovim::NvimExited);
+            }
+        });
     }
 
-    #[test]
-    fn popupmenu_select_uses_cached_items() {
-        let (handler, rx) = make_handler();
-
-        handler.handle_popupmenu_show(&Value::Array(vec![
-            Value::Array(vec![
-                Value::Array(vec![
-                    Value::from("漢字"),
-                    Value::from(""),
-                    Value::from(""),
-                    Value::from(""),
-                ]),
-                Value::Array(vec![
-                    Value::from("感じ"),
-                    Value::from(""),
-                    Value::from(""),
-                    Value::from(""),
-                ]),
-            ]),
-            Value::from(0),
-            Value::from(0),
-            Value::from(0),
-            Value::from(0),
-        ]));
-
-        match rx.try_recv().unwrap() {
-            FromNeovim::Candidates(info) => {
-                assert_eq!(
-                    info.candidates,
-                    vec!["漢字".to_string(), "感じ".to_string()]
-                );
-                assert_eq!(info.selected, 0);
-            }
-            other => panic!("expected Candidates from popupmenu_show, got {other:?}"),
-        }
-
-        handler.handle_popupmenu_select(&Value::Array(vec![Value::from(1)]));
-        match rx.try_recv().unwrap() {
-            FromNeovim::Candidates(info) => {
-                assert_eq!(
-                    info.candidates,
-                    vec!["漢字".to_string(), "感じ".to_string()]
-                );
-                assert_eq!(info.selected, 1);
-            }
-            other => panic!("expected Candidates from popupmenu_select, got {other:?}"),
-        }
-    }
-
-    #[test]
-    fn msg_show_filters_blocked_kinds_and_emits_normal_messages() {
-        let (handler, rx) = make_handler();
-
-        handler.handle_msg_show(&Value::Array(vec![
-            Value::from("search_count"),
-            Value::Array(vec![Value::Array(vec![
-                Value::from(0),
-                Value::from("[1/2]"),
-            ])]),
-            Value::from(false),
-        ]));
-        assert!(matches!(rx.try_recv(), Err(TryRecvError::Empty)));
-
-        handler.handle_msg_show(&Value::Array(vec![
-            Value::from("echo"),
-            Value::Array(vec![Value::Array(vec![
-                Value::from(0),
-                Value::from("applied"),
-            ])]),
-            Value::from(false),
-        ]));
-        match rx.try_recv().unwrap() {
-            FromNeovim::CmdlineMessage { text, cmdtype } => {
-                assert_eq!(text, "applied");
-                assert!(cmdtype.is_empty());
-            }
-            other => panic!("expected CmdlineMessage, got {other:?}"),
-        }
-    }
-
-    #[test]
-    fn ime_cmdline_executed_sends_cmdline_cancelled_and_signals_snapshot_needed() {
-        let (handler, rx) = make_handler();
-
-        let map = vec![
-            (Value::from("type"), Value::from("executed")),
-            (Value::from("cmdtype"), Value::from(":")),
-        ];
-        let result = handler.handle_ime_cmdline(&map);
-
-        // Should return (executed=true, cmdtype=":") to signal snapshot is needed
-        assert_eq!(result, Some((true, ":".to_string())));
-
-        // CmdlineCancelled message should be sent
-        match rx.try_recv().unwrap() {
-            FromNeovim::CmdlineCancelled { cmdtype, executed } => {
-                assert_eq!(cmdtype, ":");
-                assert!(executed);
-            }
-            other => panic!("expected CmdlineCancelled, got {other:?}"),
-        }
-    }
-
-    #[test]
-    fn ime_cmdline_cancelled_does_not_signal_snapshot() {
-        let (handler, rx) = make_handler();
-
-        let map = vec![
-            (Value::from("type"), Value::from("cancelled")),
-            (Value::from("cmdtype"), Value::from(":")),
-        ];
-        let result = handler.handle_ime_cmdline(&map);
-
-        // Should return (executed=false, cmdtype=":") — no snapshot needed
-        assert_eq!(result, Some((false, ":".to_string())));
-
-        match rx.try_recv().unwrap() {
-            FromNeovim::CmdlineCancelled { cmdtype, executed } => {
-                assert_eq!(cmdtype, ":");
-                assert!(!executed);
-            }
-            other => panic!("expected CmdlineCancelled, got {other:?}"),
-        }
-    }
-}
-
-/// Run the Neovim event loop in a blocking manner
-pub fn run_blocking(rx: Receiver<ToNeovim>, tx: Sender<FromNeovim>, config: Config) {
-    let rt = match Runtime::new() {
-        Ok(rt) => rt,
-        Err(e) => {
-            let err = NvimError::RuntimeInit(e);
-            log::error!("[NVIM] {}", err);
-            return;
-        }
-    };
-    rt.block_on(async move {
-        if let Err(e) = run_neovim(rx, tx, &config).await {
-            log::error!("[NVIM] Error: {}", e);
-        }
-    });
-}
-
-async fn run_neovim(
-    rx: Receiver<ToNeovim>,
-    tx: Sender<FromNeovim>,
-    config: &Config,
-) -> NvimResult<()> {
-    log::info!("[NVIM] Starting Neovim...");
-
-    // Start Neovim in embedded mode
-    let mut cmd = Command::new("nvim");
-    cmd.args(["--embed", "--headless"]);
-    if config.clean {
-        cmd.arg("--clean");
-    }
-
-    let handler = NvimHandler {
-        tx: tx.clone(),
-        last_popupmenu_items: Arc::new(Mutex::new(Vec::new())),
-    };
-    let (nvim, io_handler, _child) = new_child_cmd(&mut cmd, handler)
-        .await
-        .map_err(|e| NvimError::Backend(e.into()))?;
-
-    log::info!("[NVIM] Connected to Neovim");
-
-    // Initialize
-    init_neovim(&nvim, config).await.map_err(NvimError::from)?;
-
-    send_msg(&tx, FromNeovim::Ready);
-
-    // Track whether Neovim has exited (e.g., via :q) to avoid sending qa! to dead process.
-    let exited = Arc::new(AtomicBool::new(false));
-    {
-        let tx = tx.clone();
-        let exited = exited.clone();
-        tokio::spawn(async move {
-            match io_handler.await {
-                Ok(Ok(())) => log::info!("[NVIM] I/O loop ended cleanly"),
-                Ok(Err(e)) => log::error!("[NVIM] I/O loop error: {}", e),
-                Err(e) => log::error!("[NVIM] I/O task panicked: {}", e),
-            }
-            if !exited.swap(true, Ordering::SeqCst) {
-                send_msg(&tx, FromNeovim::NvimExited);
-            }
-        });
-    }
+    // The watchdog holds the child from here (to kill it if it hangs)
+    let _child = spawn_watchdog(&nvim, child, &tx, &exited, &watch, &config.nvim);
 
     // Track last known vim mode for insert-mode fire-and-forget optimization.
     let mut last_mode = if config.behavior.startinsert {
@@ -822,18 +758,153 @@ async fn run_neovim(
         String::from("n")
     };
 
+    // Session input mode (reset to Conversion on respawn; IME re-sends it)
+    let mut input_mode = InputMode::default();
+
+    // Insert-mode snapshot delivery (push vs pull), adapted to observed pushes
+    let mut paths = SnapshotPaths {
+        pushes,
+        mirror,
+        adaptive: AdaptiveSnapshot::default(),
+        probe: None,
+        report_all: config.behavior.debug_overlay,
+    };
+
+    // Selections received while Neovim was blocked (exec_lua would deadlock)
+    let mut deferred_clipboard: Vec<(ClipboardSelection, String)> = Vec::new();
+    // Latest surrounding text received while Neovim was blocked
+    let mut deferred_surrounding: Option<(String, String)> = None;
+    let mut deferred_dictionaries: Option<Vec<String>> = None;
+
     // Main loop - process messages from IME
     loop {
-        match rx.recv() {
+        let msg = rx.recv();
+        // Watched until handled, whatever the IME state
+        let _busy = Busy::start(&watch);
+        match msg {
             Ok(ToNeovim::Key(key)) => {
                 if exited.load(Ordering::SeqCst) {
                     log::debug!("[NVIM] Ignoring key {:?} — Neovim already exited", key);
                     continue;
                 }
                 log::debug!("[NVIM] Received key: {:?}", key);
-                if let Err(e) = handle_key(&nvim, &key, &tx, config, &mut last_mode).await {
+                let trace = trace::span(Span::NvimKey);
+                paths.mirror.lock().unwrap().set_busy(true);
+                if let Err(e) = handle_key(
+                    &nvim,
+                    &key,
+                    &tx,
+                    config,
+                    &mut last_mode,
+                    input_mode,
+                    &mut paths,
+                )
+                .await
+                {
                     log::error!("[NVIM] Key handling error: {}", e);
                 }
+                paths.mirror.lock().unwrap().set_busy(false);
+                drop(trace);
+                if !PENDING.load().is_pending() {
+                    for (selection, text) in deferred_clipboard.drain(..) {
+                        store_clipboard(&nvim, selection, &text).await;
+                    }
+                    if let Some((before, after)) = deferred_surrounding.take() {
+                        store_surrounding(&nvim, &before, &after).await;
+                    }
+                    if let Some(paths) = deferred_dictionaries.take() {
+                        store_dictionaries(&nvim, &paths).await;
+                    }
+                }
+            }
+            Ok(ToNeovim::SetInputMode(mode)) => {
+                log::info!("[NVIM] Input mode: {:?}", mode);
+                input_mode = mode;
+            }
+            Ok(ToNeovim::SetClipboard { selection, text }) => {
+                if exited.load(Ordering::SeqCst) {
+                    continue;
+                }
+                // Available to a register paste right away, blocked or not
+                *selections.lock().unwrap().slot(selection) = Some(text.clone());
+                if PENDING.load().is_pending() {
+                    log::debug!("[NVIM] Deferring clipboard update until key sequence completes");
+                    deferred_clipboard.retain(|(s, _)| *s != selection);
+                    deferred_clipboard.push((selection, text));
+                } else {
+                    store_clipboard(&nvim, selection, &text).await;
+                }
+            }
+            Ok(ToNeovim::SetSurroundingText { before, after }) => {
+                if exited.load(Ordering::SeqCst) {
+                    continue;
+                }
+                if PENDING.load().is_pending() {
+                    deferred_surrounding = Some((before, after));
+                } else {
+                    store_surrounding(&nvim, &before, &after).await;
+                }
+            }
+            Ok(ToNeovim::SetDictionaries(paths)) => {
+                if exited.load(Ordering::SeqCst) {
+                    continue;
+                }
+                // The autocmd may run plugin code; keep it out of a key sequence
+                if PENDING.load().is_pending() {
+                    deferred_dictionaries = Some(paths);
+                } else {
+                    store_dictionaries(&nvim, &paths).await;
+                }
+            }
+            Ok(ToNeovim::SelectCandidate { index, finish }) => {
+                if exited.load(Ordering::SeqCst) {
+                    continue;
+                }
+                let trace = trace::span(Span::NvimKey);
+                paths.mirror.lock().unwrap().set_busy(true);
+                if let Err(e) = select_candidate(&nvim, index, finish, &tx, &mut paths).await {
+                    log::error!("[NVIM] Candidate selection error: {}", e);
+                }
+                paths.mirror.lock().unwrap().set_busy(false);
+                drop(trace);
+            }
+            Ok(ToNeovim::ScrollCandidates(window)) => {
+                // Answered from the list already received, even while
+                // Neovim is blocked
+                let info = candidates.lock().unwrap().scroll(window);
+                if let Some(info) = info {
+                    send_msg(&tx, FromNeovim::Candidates(info));
+                }
+            }
+            Ok(ToNeovim::CommitPrefix(text)) => {
+                if exited.load(Ordering::SeqCst) {
+                    continue;
+                }
+                if let Err(e) = commit_prefix(&nvim, &text, &tx, &paths.mirror).await {
+                    log::error!("[NVIM] Punctuation commit error: {}", e);
+                }
+            }
+            Ok(ToNeovim::Restore {
+                text,
+                cursor,
+                insert,
+            }) => {
+                if exited.load(Ordering::SeqCst) {
+                    continue;
+                }
+                if let Err(e) = restore(
+                    &nvim,
+                    &text,
+                    cursor,
+                    insert,
+                    &tx,
+                    &mut last_mode,
+                    &paths.mirror,
+                )
+                .await
+                {
+                    log::error!("[NVIM] Restore error: {}", e);
+                }
             }
             Ok(ToNeovim::Shutdown) | Err(_) => {
                 log::info!("[NVIM] Shutting down...");
@@ -851,6 +922,123 @@ async fn run_neovim(
     Ok(())
 }
 
+/// When the watchdog pings, shared by the handle, the message loop and the
+/// watchdog task. Only the active backend is watched, and only while the IME
+/// is enabled or a message is being handled: an idle backend, and the
+/// standby always, are left alone (no timer runs either).
+#[derive(Default)]
+pub struct Watch {
+    /// Told by the event loop, which only tells the active backend
+    active: AtomicBool,
+    /// The IME is enabled
+    enabled: AtomicBool,
+    /// The message loop is handling a message
+    busy: AtomicBool,
+    /// Wakes the watchdog waiting for something to watch
+    wake: Notify,
+}
+
+impl Watch {
+    /// This is the active backend, and the IME is `enabled` or not
+    pub fn set_enabled(&self, enabled: bool) {
+        let was_watched = self.is_watched();
+        self.active.store(true, Ordering::SeqCst);
+        self.enabled.store(enabled, Ordering::SeqCst);
+        self.wake_if_watched(was_watched);
+    }
+
+    fn set_busy(&self, busy: bool) {
+        let was_watched = self.is_watched();
+        self.busy.store(busy, Ordering::SeqCst);
+        self.wake_if_watched(was_watched);
+    }
+
+    fn is_watched(&self) -> bool {
+        self.active.load(Ordering::SeqCst)
+            && (self.enabled.load(Ordering::SeqCst) || self.busy.load(Ordering::SeqCst))
+    }
+
+    fn wake_if_watched(&self, was_watched: bool) {
+        // Stored as a permit if the watchdog isn't waiting yet
+        if !was_watched && self.is_watched() {
+            self.wake.notify_one();
+        }
+    }
+}
+
+/// Marks the message loop busy until dropped
+struct Busy<'a>(&'a Watch);
+
+impl<'a> Busy<'a> {
+    fn start(watch: &'a Watch) -> Self {
+        watch.set_busy(true);
+        Self(watch)
+    }
+}
+
+impl Drop for Busy<'_> {
+    fn drop(&mut self) {
+        self.0.set_busy(false);
+    }
+}
+
+/// Ping Neovim every `nvim.watchdog_interval` seconds while `watch` says so.
+/// A Neovim that doesn't answer nvim_get_mode (answered even while blocked in
+/// getchar) within `nvim.watchdog_timeout` is hung, e.g. a plugin deadlock: it
+/// is reported as `Unresponsive` and killed, which ends the I/O loop
+/// (`NvimExited`) and fails the requests still waiting on it. Returns the
+/// child when there is no watchdog to hand it to.
+fn spawn_watchdog(
+    nvim: &Neovim<NvimWriter>,
+    mut child: Child,
+    tx: &NeovimSender,
+    exited: &Arc<AtomicBool>,
+    watch: &Arc<Watch>,
+    config: &NvimConfig,
+) -> Option<Child> {
+    if config.watchdog_interval == 0 {
+        return Some(child);
+    }
+    let interval = Duration::from_secs(config.watchdog_interval);
+    let timeout = Duration::from_secs(config.watchdog_timeout.max(1));
+    let nvim = nvim.clone();
+    let tx = tx.clone();
+    let exited = exited.clone();
+    let watch = watch.clone();
+    tokio::spawn(async move {
+        loop {
+            if !watch.is_watched() {
+                watch.wake.notified().await;
+                continue;
+            }
+            tokio::time::sleep(interval).await;
+            if exited.load(Ordering::SeqCst) {
+                return;
+            }
+            if !watch.is_watched() {
+                continue;
+            }
+            // An error is the I/O loop ending: NvimExited is on its way
+            if tokio::time::timeout(timeout, nvim.get_mode()).await.is_ok() {
+                continue;
+            }
+            if exited.load(Ordering::SeqCst) {
+                return;
+            }
+            log::error!(
+                "[NVIM] No response to nvim_get_mode in {:?}, killing Neovim",
+                timeout
+            );
+            send_msg(&tx, FromNeovim::Unresponsive);
+            if let Err(e) = child.start_kill() {
+                log::error!("[NVIM] Failed to kill Neovim: {}", e);
+            }
+            return;
+        }
+    });
+    None
+}
+
 async fn init_neovim(nvim: &Neovim<NvimWriter>, config: &Config) -> anyhow::Result<()> {
     log::info!("[NVIM] Initializing...");
 
@@ -889,8 +1077,21 @@ async fn init_neovim(nvim: &Neovim<NvimWriter>, config: &Config) -> anyhow::Resu
 
     nvim.exec_lua(include_str!("lua/auto_commit.lua"), vec![])
         .await?;
+    nvim.exec_lua(include_str!("lua/surrounding.lua"), vec![])
+        .await?;
+    let dictionaries = dictionary::installed(&config.dictionary)
+        .iter()
+        .map(|path| Value::from(path.to_string_lossy().into_owned()))
+        .collect();
+    nvim.exec_lua(
+        include_str!("lua/dictionary.lua"),
+        vec![Value::Array(dictionaries)],
+    )
+    .await?;
     nvim.exec_lua(include_str!("lua/autocmds.lua"), vec![])
         .await?;
+    nvim.exec_lua(include_str!("lua/buf_attach.lua"), vec![])
+        .await?;
 
     // Write-to-commit: hook :w via BufWriteCmd to commit preedit
     if config.behavior.write_to_commit {
@@ -898,6 +1099,12 @@ async fn init_neovim(nvim: &Neovim<NvimWriter>, config: &Config) -> anyhow::Resu
             .await?;
     }
 
+    // Clipboard provider for + / * (only when the compositor supports data-control)
+    if config.behavior.clipboard_sync {
+        nvim.exec_lua(include_str!("lua/clipboard.lua"), vec![])
+            .await?;
+    }
+
     // Completion adapter — nvim-cmp requires Lua hooks; native uses ext_popupmenu
     if config.completion.adapter == "nvim-cmp" {
         nvim.exec_lua(include_str!("lua/completion_cmp.lua"), vec![])
@@ -938,14 +1145,31 @@ async fn init_neovim(nvim: &Neovim<NvimWriter>, config: &Config) -> anyhow::Resu
 async fn handle_key(
     nvim: &Neovim<NvimWriter>,
     key: &str,
-    tx: &Sender<FromNeovim>,
+    tx: &NeovimSender,
     config: &Config,
     last_mode: &mut String,
+    input_mode: InputMode,
+    paths: &mut SnapshotPaths,
 ) -> anyhow::Result<()> {
+    paths.resolve_probe(tx);
+
+    // Kana-direct: an uppercase letter would start skkeleton henkan — send it lowercase
+    let lowered;
+    let key = if input_mode == InputMode::KanaDirect
+        && last_mode.as_str() == "i"
+        && key.len() == 1
+        && key.as_bytes()[0].is_ascii_uppercase()
+    {
+        lowered = key.to_ascii_lowercase();
+        lowered.as_str()
+    } else {
+        key
+    };
+
     // Dispatch through handlers in priority order.
     // Each returns Ok(true) if it fully handled the key.
     if handle_commandline_mode(nvim, key, tx).await?
-        || handle_getchar_pending(nvim, key, tx, last_mode).await?
+        || handle_getchar_pending(nvim, key, tx, last_mode, &paths.mirror).await?
         || handle_commit_key(nvim, key, tx, config, last_mode).await?
         || handle_backspace(nvim, key, tx).await?
         || handle_enter(nvim, key, tx).await?
@@ -955,6 +1179,10 @@ async fn handle_key(
         return Ok(());
     }
 
+    // Pushes caused by this key have a higher sequence number
+    let push_seq = paths.pushes.seq();
+    let sent_at = Instant::now();
+
     // Register-pending and motion-pending may send the key themselves.
     // `key_sent` tracks whether nvim.input(key) was already called.
     let current = PENDING.load();
@@ -977,17 +1205,44 @@ async fn handle_key(
         if !handle_motion_pending(nvim, key, tx, current).await? {
             return Ok(());
         }
-        // Motion completed — fall through to query snapshot
-    } else if !key_sent {
-        let _ = nvim.input(key).await;
+        // Motion completed, and handle_motion_pending saw Neovim unblocked
+        return handle_snapshot_response(nvim, tx, last_mode, paths).await;
     }
 
-    // Insert mode fire-and-forget: autocmd will push snapshot via rpcnotify.
+    // nvim_input is a fast request: it only queues the key as typeahead and
+    // replies before the key is processed, so waiting for its reply ordered
+    // nothing. The queries below go out without waiting for it and still see
+    // the key: nvim_get_mode is deferred while queued input is pending, and
+    // Neovim processes typeahead before the requests (exec_lua) queued on
+    // its event loop. A key that leaves Neovim waiting for more input
+    // (getchar, an ambiguous mapping) is answered in that waiting state, as
+    // it was when the queries waited for nvim_input.
+    let input = async {
+        if !key_sent {
+            let _ = nvim.input(key).await;
+        }
+    };
+
+    // Insert mode: autocmd pushes the snapshot via rpcnotify, or we pull it
+    // when pushes have proven unreliable (see SnapshotPaths).
     // Exception: Escape changes mode but no insert-mode autocmd fires after it.
     if last_mode.as_str() == "i" && key != "<Esc>" && key != "<C-c>" {
-        if matches!(key, "<C-k>" | "<C-v>" | "<C-q>") && is_blocked(nvim).await? {
-            PENDING.store(PendingState::Getchar);
-            log::debug!("[NVIM] Insert-mode key {} triggered blocking state", key);
+        if matches!(key, "<C-k>" | "<C-v>" | "<C-q>") {
+            let ((), blocked) = tokio::join!(input, is_blocked(nvim));
+            if blocked? {
+                PENDING.store(PendingState::Getchar);
+                log::debug!("[NVIM] Insert-mode key {} triggered blocking state", key);
+                send_msg(tx, FromNeovim::KeyProcessed);
+                return Ok(());
+            }
+            paths.after_insert_key(nvim, tx, push_seq, sent_at).await?;
+        } else {
+            let ((), settled) =
+                tokio::join!(input, paths.after_insert_key(nvim, tx, push_seq, sent_at));
+            settled?;
+        }
+        if input_mode == InputMode::KanaDirect {
+            handle_kana_direct_commit(nvim, tx, &paths.mirror).await?;
         }
         send_msg(tx, FromNeovim::KeyProcessed);
         return Ok(());
@@ -998,60 +1253,228 @@ async fn handle_key(
     // Must set PENDING synchronously to prevent handle_snapshot_response
     // from escaping command-line mode before cmdline_show arrives.
     if matches!(key, ":" | "/" | "?") && last_mode.as_str() == "n" {
+        input.await;
         PENDING.store(PendingState::CommandLine);
         log::debug!("[NVIM] Entered command-line mode ({})", key);
         send_msg(tx, FromNeovim::KeyProcessed);
         return Ok(());
     }
 
-    // Check blocking before querying snapshot.
-    if is_blocked(nvim).await? {
+    // Check blocking before querying snapshot (a blocked Neovim would never
+    // answer the snapshot request, so it can't be sent along)
+    let ((), blocked) = tokio::join!(input, is_blocked(nvim));
+    if blocked? {
         PENDING.store(PendingState::Getchar);
         log::debug!("[NVIM] Blocked in getchar, waiting for next key");
         send_msg(tx, FromNeovim::KeyProcessed);
         return Ok(());
     }
 
-    handle_snapshot_response(nvim, tx, last_mode).await
+    handle_snapshot_response(nvim, tx, last_mode, paths).await
 }
 
-// --- Sub-handlers: each returns Ok(true) when it fully handled the key ---
-
-/// Forward key in command-line mode (display comes via CmdlineChanged autocmd).
-async fn handle_commandline_mode(
-    nvim: &Neovim<NvimWriter>,
-    key: &str,
-    tx: &Sender<FromNeovim>,
-) -> anyhow::Result<bool> {
-    if PENDING.load() != PendingState::CommandLine {
-        return Ok(false);
-    }
-    log::debug!("[NVIM] CommandLine mode, forwarding key: {}", key);
-    let _ = nvim.input(key).await;
-    send_msg(tx, FromNeovim::KeyProcessed);
-    Ok(true)
+/// Insert-mode snapshot delivery, switched between push and pull by `AdaptiveSnapshot`
+struct SnapshotPaths {
+    pushes: Arc<PushTracker>,
+    /// This backend's buffer mirror, shared with its handler
+    mirror: Arc<Mutex<BufferMirror>>,
+    adaptive: AdaptiveSnapshot,
+    /// Pull-mode key whose push is checked at the next key (push seq before it, send time)
+    probe: Option<(u64, Instant)>,
+    /// Report every measurement (debug overlay), not only strategy changes
+    report_all: bool,
 }
 
-/// Complete a getchar-blocking key (q, f, t, r, m, etc.).
+impl SnapshotPaths {
+    /// Wait for the key's push, or pull when pushes are unreliable or this one is late
+    async fn after_insert_key(
+        &mut self,
+        nvim: &Neovim<NvimWriter>,
+        tx: &NeovimSender,
+        seq: u64,
+        sent_at: Instant,
+    ) -> anyhow::Result<()> {
+        let strategy = self.adaptive.strategy();
+        if strategy == SnapshotStrategy::Push
+            && let Some(at) = self.pushes.wait_since(seq, self.adaptive.push_wait()).await
+        {
+            let changed = self
+                .adaptive
+                .record_push(at.saturating_duration_since(sent_at));
+            self.report(tx, changed);
+            return Ok(());
+        }
+
+        let (_, unchanged) = self.pull(nvim, tx).await?;
+        let changed = match self.pushes.arrived_since(seq) {
+            Some(at) => self
+                .adaptive
+                .record_push(at.saturating_duration_since(sent_at)),
+            // Key changed nothing: no push was due
+            None if unchanged => false,
+            None if strategy == SnapshotStrategy::Push => {
+                log::debug!("[NVIM] Push snapshot missing, pulled instead");
+                self.adaptive.record_missed_push()
+            }
+            None => {
+                // The push may still be on its way; check at the next key
+                self.probe = Some((seq, sent_at));
+                false
+            }
+        };
+        self.report(tx, changed);
+        Ok(())
+    }
+
+    /// Settle the previous pull-mode key: did its push ever arrive?
+    fn resolve_probe(&mut self, tx: &NeovimSender) {
+        let Some((seq, sent_at)) = self.probe.take() else {
+            return;
+        };
+        let changed = match self.pushes.arrived_since(seq) {
+            Some(at) => self
+                .adaptive
+                .record_push(at.saturating_duration_since(sent_at)),
+            None => self.adaptive.record_missed_push(),
+        };
+        self.report(tx, changed);
+    }
+
+    /// Pull a snapshot, timing the round trip. Also returns whether the state
+    /// was already known (i.e. there was nothing for a push to deliver).
+    async fn pull(
+        &mut self,
+        nvim: &Neovim<NvimWriter>,
+        tx: &NeovimSender,
+    ) -> anyhow::Result<(Snapshot, bool)> {
+        let start = Instant::now();
+        let snapshot = query_snapshot(nvim, tx, &self.mirror).await?;
+        let elapsed = start.elapsed();
+        metrics::record(Stage::Snapshot, elapsed);
+        self.adaptive.record_pull(elapsed);
+        let unchanged = self.pushes.is_unchanged(&snapshot);
+        self.pushes.record_pull(&snapshot);
+        Ok((snapshot, unchanged))
+    }
+
+    fn report(&self, tx: &NeovimSender, changed: bool) {
+        if changed || self.report_all {
+            send_msg(tx, FromNeovim::SnapshotStats(self.adaptive.stats()));
+        }
+    }
+}
+
+/// Candidate number/paging keys: move the completion selection, then pull the
+/// result. Selection fires TextChangedP rather than TextChangedI, so no push is
+/// expected. Skipped while blocked (exec_lua would deadlock).
+async fn select_candidate(
+    nvim: &Neovim<NvimWriter>,
+    index: usize,
+    finish: bool,
+    tx: &NeovimSender,
+    paths: &mut SnapshotPaths,
+) -> anyhow::Result<()> {
+    if !PENDING.load().is_pending() {
+        log::debug!("[NVIM] Select candidate {} (finish={})", index, finish);
+        let result = nvim
+            .exec_lua(
+                "return ime_select_candidate(...)",
+                vec![Value::from(index as i64), Value::from(finish)],
+            )
+            .await?;
+        if get_map_str(&result, "type") == Some("processing") {
+            paths.pull(nvim, tx).await?;
+        }
+    }
+    send_msg(tx, FromNeovim::KeyProcessed);
+    Ok(())
+}
+
+/// Update the clipboard provider's cache for `selection` (read by `<C-r>+`, `"+p`).
+async fn store_clipboard(nvim: &Neovim<NvimWriter>, selection: ClipboardSelection, text: &str) {
+    log::debug!(
+        "[NVIM] Storing {:?} selection ({} bytes)",
+        selection,
+        text.len()
+    );
+    if let Err(e) = nvim
+        .exec_lua(
+            "ime_clipboard_store(...)",
+            vec![Value::from(selection.register()), Value::from(text)],
+        )
+        .await
+    {
+        log::warn!("[NVIM] Failed to store clipboard: {}", e);
+    }
+}
+
+/// Publish the focused input's surrounding text (`vim.g.ime_surrounding`)
+async fn store_dictionaries(nvim: &Neovim<NvimWriter>, paths: &[String]) {
+    log::info!("[NVIM] Dictionaries: {:?}", paths);
+    let paths = paths.iter().map(|p| Value::from(p.as_str())).collect();
+    if let Err(e) = nvim
+        .exec_lua("ime_set_dictionaries(...)", vec![Value::Array(paths)])
+        .await
+    {
+        log::warn!("[NVIM] Failed to store dictionaries: {}", e);
+    }
+}
+
+async fn store_surrounding(nvim: &Neovim<NvimWriter>, before: &str, after: &str) {
+    log::debug!(
+        "[NVIM] Surrounding text: {} bytes before, {} after",
+        before.len(),
+        after.len()
+    );
+    if let Err(e) = nvim
+        .exec_lua(
+            "ime_set_surrounding(...)",
+            vec![Value::from(before), Value::from(after)],
+        )
+        .await
+    {
+        log::warn!("[NVIM] Failed to store surrounding text: {}", e);
+    }
+}
+
+// --- Sub-handlers: each returns Ok(true) when it fully handled the key ---
+
+/// Forward key in command-line mode (display comes via CmdlineChanged autocmd).
+async fn handle_commandline_mode(
+    nvim: &Neovim<NvimWriter>,
+    key: &str,
+    tx: &NeovimSender,
+) -> anyhow::Result<bool> {
+    if PENDING.load() != PendingState::CommandLine {
+        return Ok(false);
+    }
+    log::debug!("[NVIM] CommandLine mode, forwarding key: {}", key);
+    let _ = nvim.input(key).await;
+    send_msg(tx, FromNeovim::KeyProcessed);
+    Ok(true)
+}
+
+/// Complete a getchar-blocking key (q, f, t, r, m, etc.).
 async fn handle_getchar_pending(
     nvim: &Neovim<NvimWriter>,
     key: &str,
-    tx: &Sender<FromNeovim>,
+    tx: &NeovimSender,
     last_mode: &mut String,
+    mirror: &Mutex<BufferMirror>,
 ) -> anyhow::Result<bool> {
     if PENDING.load() != PendingState::Getchar {
         return Ok(false);
     }
     log::debug!("[NVIM] Completing getchar with key: {}", key);
-    let _ = nvim.input(key).await;
+    let mode = input_then_mode(nvim, key).await?;
     PENDING.clear();
-    if is_blocked(nvim).await? {
+    if mode.blocking {
         PENDING.store(PendingState::Getchar);
         log::debug!("[NVIM] Still blocked in getchar after key: {}", key);
         send_msg(tx, FromNeovim::KeyProcessed);
         return Ok(true);
     }
-    let snapshot = query_snapshot(nvim, tx).await?;
+    let snapshot = query_snapshot(nvim, tx, mirror).await?;
     *last_mode = snapshot.mode.clone();
     if snapshot.mode.starts_with("no") {
         PENDING.store(PendingState::Motion);
@@ -1067,7 +1490,7 @@ async fn handle_getchar_pending(
 async fn handle_commit_key(
     nvim: &Neovim<NvimWriter>,
     key: &str,
-    tx: &Sender<FromNeovim>,
+    tx: &NeovimSender,
     config: &Config,
     last_mode: &mut String,
 ) -> anyhow::Result<bool> {
@@ -1090,11 +1513,79 @@ async fn handle_commit_key(
     Ok(true)
 }
 
+/// Kana-direct: commit settled text before the cursor (pending romaji stays in the buffer).
+async fn handle_kana_direct_commit(
+    nvim: &Neovim<NvimWriter>,
+    tx: &NeovimSender,
+    mirror: &Mutex<BufferMirror>,
+) -> anyhow::Result<()> {
+    let result = nvim
+        .exec_lua("return ime_kana_direct_commit()", vec![])
+        .await?;
+    if get_map_str(&result, "type") == Some("commit")
+        && let Some(text) = get_map_str(&result, "text")
+    {
+        log::debug!("[NVIM] Kana-direct commit: {:?}", text);
+        send_msg(tx, FromNeovim::AutoCommit(text.to_string()));
+        // Lua deleted the committed text with autocmds suppressed — push the remainder
+        publish_snapshot(get_map_value(&result, "snapshot"), tx, mirror)?;
+    }
+    Ok(())
+}
+
+/// Punctuation commit: commit `text` from the start of the cursor line. Keys
+/// typed since the snapshot it was found in only add text after it, so the
+/// line is checked to still start with it. Skipped while blocked.
+async fn commit_prefix(
+    nvim: &Neovim<NvimWriter>,
+    text: &str,
+    tx: &NeovimSender,
+    mirror: &Mutex<BufferMirror>,
+) -> anyhow::Result<()> {
+    if PENDING.load().is_pending() {
+        return Ok(());
+    }
+    let result = nvim
+        .exec_lua("return ime_commit_prefix(...)", vec![Value::from(text)])
+        .await?;
+    if get_map_str(&result, "type") == Some("commit") {
+        log::debug!("[NVIM] Punctuation commit: {:?}", text);
+        send_msg(tx, FromNeovim::AutoCommit(text.to_string()));
+        // Lua deleted the committed text with autocmds suppressed — push the remainder
+        publish_snapshot(get_map_value(&result, "snapshot"), tx, mirror)?;
+    }
+    Ok(())
+}
+
+/// Put back the composition of the backend this one replaced, then show it
+async fn restore(
+    nvim: &Neovim<NvimWriter>,
+    text: &str,
+    cursor: usize,
+    insert: bool,
+    tx: &NeovimSender,
+    last_mode: &mut String,
+    mirror: &Mutex<BufferMirror>,
+) -> anyhow::Result<()> {
+    nvim.exec_lua(
+        "ime_restore(...)",
+        vec![
+            Value::from(text),
+            Value::from(cursor as u64),
+            Value::from(insert),
+        ],
+    )
+    .await?;
+    log::info!("[NVIM] Restored preedit {:?}", text);
+    *last_mode = query_snapshot(nvim, tx, mirror).await?.mode;
+    Ok(())
+}
+
 /// Handle Backspace — in empty preedit, passthrough to app; otherwise process in Neovim.
 async fn handle_backspace(
     nvim: &Neovim<NvimWriter>,
     key: &str,
-    tx: &Sender<FromNeovim>,
+    tx: &NeovimSender,
 ) -> anyhow::Result<bool> {
     let pending = PENDING.load();
     if key != "<BS>" || pending.is_motion() || pending.is_register() {
@@ -1114,7 +1605,7 @@ async fn handle_backspace(
 async fn handle_enter(
     nvim: &Neovim<NvimWriter>,
     key: &str,
-    tx: &Sender<FromNeovim>,
+    tx: &NeovimSender,
 ) -> anyhow::Result<bool> {
     let pending = PENDING.load();
     if !matches!(key, "<CR>" | "<C-CR>" | "<A-CR>") || pending.is_motion() || pending.is_register()
@@ -1133,7 +1624,7 @@ async fn handle_enter(
 async fn handle_insert_register(
     nvim: &Neovim<NvimWriter>,
     key: &str,
-    tx: &Sender<FromNeovim>,
+    tx: &NeovimSender,
 ) -> anyhow::Result<bool> {
     if key != "<C-r>" || PENDING.load().is_pending() {
         return Ok(false);
@@ -1153,7 +1644,7 @@ async fn handle_insert_register(
 async fn handle_normal_register(
     nvim: &Neovim<NvimWriter>,
     key: &str,
-    tx: &Sender<FromNeovim>,
+    tx: &NeovimSender,
 ) -> anyhow::Result<bool> {
     if key != "\"" || PENDING.load().is_pending() {
         return Ok(false);
@@ -1177,7 +1668,7 @@ async fn handle_normal_register(
 async fn handle_register_pending(
     nvim: &Neovim<NvimWriter>,
     key: &str,
-    tx: &Sender<FromNeovim>,
+    tx: &NeovimSender,
     current: PendingState,
 ) -> anyhow::Result<Option<bool>> {
     if !current.is_register() {
@@ -1218,7 +1709,7 @@ async fn handle_register_pending(
 async fn handle_motion_pending(
     nvim: &Neovim<NvimWriter>,
     key: &str,
-    tx: &Sender<FromNeovim>,
+    tx: &NeovimSender,
     current: PendingState,
 ) -> anyhow::Result<bool> {
     log::debug!(
@@ -1226,20 +1717,10 @@ async fn handle_motion_pending(
         current,
         key
     );
-    let _ = nvim.input(key).await;
-
     // Query Neovim's actual mode to determine if the motion completed.
-    let mode_info = nvim.get_mode().await?;
-    let blocking = mode_info
-        .iter()
-        .any(|(k, v)| k.as_str() == Some("blocking") && v.as_bool() == Some(true));
-    let mode = mode_info
-        .iter()
-        .find(|(k, _)| k.as_str() == Some("mode"))
-        .and_then(|(_, v)| v.as_str())
-        .unwrap_or("n");
+    let mode = input_then_mode(nvim, key).await?;
 
-    if blocking || mode.starts_with("no") {
+    if mode.blocking || mode.mode.starts_with("no") {
         // Still pending: either blocked in getchar (e.g., f/t waiting for char)
         // or still in operator-pending (e.g., "di" waiting for text object name).
         send_msg(tx, FromNeovim::KeyProcessed);
@@ -1255,10 +1736,12 @@ async fn handle_motion_pending(
 /// Query snapshot and handle post-key mode transitions (operator-pending, command-line recovery).
 async fn handle_snapshot_response(
     nvim: &Neovim<NvimWriter>,
-    tx: &Sender<FromNeovim>,
+    tx: &NeovimSender,
     last_mode: &mut String,
+    paths: &mut SnapshotPaths,
 ) -> anyhow::Result<()> {
-    let snapshot = query_snapshot(nvim, tx).await?;
+    let (snapshot, _) = paths.pull(nvim, tx).await?;
+    paths.report(tx, false);
     *last_mode = snapshot.mode.clone();
 
     if snapshot.mode.starts_with("no") {
@@ -1274,10 +1757,13 @@ async fn handle_snapshot_response(
             "[NVIM] Unexpected command-line mode ({}), escaping",
             snapshot.mode
         );
-        let _ = nvim.input("<C-c>").await;
-        nvim.command("startinsert").await?;
-        let snapshot = query_snapshot(nvim, tx).await?;
-        *last_mode = snapshot.mode.clone();
+        let (_, restarted, snapshot) = tokio::join!(
+            nvim.input("<C-c>"),
+            nvim.command("startinsert"),
+            query_snapshot(nvim, tx, &paths.mirror)
+        );
+        restarted?;
+        *last_mode = snapshot?.mode.clone();
     }
 
     send_msg(tx, FromNeovim::KeyProcessed);
@@ -1288,21 +1774,57 @@ async fn handle_snapshot_response(
 /// This is a "fast" API call that works even when Neovim is blocked — unlike
 /// exec_lua which would deadlock.
 async fn is_blocked(nvim: &Neovim<NvimWriter>) -> anyhow::Result<bool> {
-    let mode_info = nvim.get_mode().await?;
-    Ok(mode_info
-        .iter()
-        .any(|(k, v)| k.as_str() == Some("blocking") && v.as_bool() == Some(true)))
+    Ok(Mode::parse(&nvim.get_mode().await?)?.blocking)
+}
+
+/// `nvim_get_mode()` result
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Mode {
+    mode: String,
+    blocking: bool,
+}
+
+impl Mode {
+    fn parse(mode_info: &[(Value, Value)]) -> Result<Self, FieldError> {
+        let map = Map::from_pairs(mode_info);
+        Ok(Self {
+            mode: map.opt_str("mode")?.unwrap_or("n").to_string(),
+            blocking: map.opt_bool("blocking")?.unwrap_or(false),
+        })
+    }
+}
+
+/// Send `key` and read the mode it leads to in one round trip. nvim_input
+/// only queues the key, and Neovim defers nvim_get_mode until queued input is
+/// processed, so the reply reflects the key (or the getchar it blocks in).
+async fn input_then_mode(nvim: &Neovim<NvimWriter>, key: &str) -> anyhow::Result<Mode> {
+    let (_, mode_info) = tokio::join!(nvim.input(key), nvim.get_mode());
+    Ok(Mode::parse(&mode_info?)?)
 }
 
 /// Query full state snapshot from Neovim via collect_snapshot() Lua function.
 /// Replaces separate getline/col/strlen queries with a single RPC call.
 async fn query_snapshot(
     nvim: &Neovim<NvimWriter>,
-    tx: &Sender<FromNeovim>,
+    tx: &NeovimSender,
+    mirror: &Mutex<BufferMirror>,
 ) -> anyhow::Result<Snapshot> {
     let result = nvim.exec_lua("return collect_snapshot()", vec![]).await?;
-    let snapshot = parse_snapshot(&result).map_err(|e| anyhow::anyhow!(e.to_string()))?;
+    publish_snapshot(Some(&result), tx, mirror)
+}
+
+/// Show a snapshot collected by Lua (`collect_snapshot()`), possibly returned
+/// along with another handler's result
+fn publish_snapshot(
+    value: Option<&Value>,
+    tx: &NeovimSender,
+    mirror: &Mutex<BufferMirror>,
+) -> anyhow::Result<Snapshot> {
+    let value = value.ok_or_else(|| anyhow::anyhow!("missing snapshot"))?;
+    let snapshot = parse_snapshot(value).map_err(NvimError::SnapshotParse)?;
 
+    // Pulled snapshots are current: always shown
+    mirror.lock().unwrap().reconcile(&snapshot);
     let preedit = snapshot.to_preedit_info();
     log::debug!(
         "[NVIM] snapshot: preedit={:?}, cursor={}..{}, mode={}, blocking={}, visual={:?}..{:?}",
@@ -1317,70 +1839,780 @@ async fn query_snapshot(
 
     send_msg(tx, FromNeovim::Preedit(preedit));
     send_msg(tx, FromNeovim::VisualRange(snapshot.to_visual_selection()));
+    publish_macro(&snapshot, tx, mirror);
 
     Ok(snapshot)
 }
 
+/// Send the snapshot's last recorded macro, if it changed
+fn publish_macro(snapshot: &Snapshot, tx: &NeovimSender, mirror: &Mutex<BufferMirror>) {
+    let changed = mirror.lock().unwrap().changed_macro(snapshot);
+    if let Some(last_macro) = changed {
+        send_msg(tx, FromNeovim::LastMacro(last_macro));
+    }
+}
+
 /// Parse a msgpack Value (Lua table) into a Snapshot struct.
-fn parse_snapshot(value: &nvim_rs::Value) -> NvimResult<Snapshot> {
-    let map = value
-        .as_map()
-        .ok_or(NvimError::SnapshotParse("expected map"))?;
-
-    let mut snapshot = Snapshot {
-        preedit: String::new(),
-        cursor_byte: 1,
-        mode: "n".to_string(),
-        blocking: false,
-        char_width: 0,
-        visual_begin: None,
-        visual_end: None,
-        recording: String::new(),
-    };
+fn parse_snapshot(value: &Value) -> Result<Snapshot, FieldError> {
+    let map = Map::new(value)?;
+    let string = |field: &str| map.opt_str(field).map(|s| s.unwrap_or("").to_string());
+    let number = |field: &str| map.opt_u64(field).map(|n| n.unwrap_or(0));
+    Ok(Snapshot {
+        preedit: string("preedit")?,
+        cursor_byte: map.opt_u64("cursor_byte")?.unwrap_or(1) as usize,
+        mode: map.opt_str("mode")?.unwrap_or("n").to_string(),
+        blocking: map.opt_bool("blocking")?.unwrap_or(false),
+        char_width: number("char_width")? as usize,
+        visual_begin: map.opt_u64("visual_begin")?.map(|n| n as usize),
+        visual_end: map.opt_u64("visual_end")?.map(|n| n as usize),
+        visual_overhang: number("visual_overhang")? as usize,
+        recording: string("recording")?,
+        row: number("row")? as usize,
+        tick: number("tick")?,
+        undo_seq: number("undo_seq")?,
+        last_change: string("last_change")?,
+        segment_begin: map.opt_u64("segment_begin")?.map(|n| n as usize),
+        segment_converted: map.opt_bool("segment_converted")?.unwrap_or(false),
+        recorded: string("recorded")?,
+        recorded_keys: string("recorded_keys")?,
+        highlights: map.ranges("highlights")?,
+    })
+}
+
+/// Parse an ime_buf_bytes notification (see lua/buf_attach.lua)
+fn parse_byte_edit(value: &Value) -> Result<ByteEdit, FieldError> {
+    let map = Map::new(value)?;
+    Ok(ByteEdit {
+        tick: map.u64("tick")?,
+        row: map.u64("row")? as usize,
+        col: map.u64("col")? as usize,
+        old_len: map.u64("old_len")? as usize,
+        new_len: map.u64("new_len")? as usize,
+        multiline: map.bool("multiline")?,
+        line: map.str("line")?.to_string(),
+    })
+}
+
+/// Parse an ime_candidates notification: {candidates, annotations?, readings?, kinds?, selected?}
+fn parse_candidates(value: &Value) -> Result<CandidateInfo, FieldError> {
+    let map = Map::new(value)?;
+    let explicit = map.strings("annotations")?;
+    let (words, annotations): (Vec<String>, Vec<String>) = map
+        .strings("candidates")?
+        .iter()
+        .enumerate()
+        .map(|(i, word)| {
+            let (word, annotation) = split_annotation(word);
+            let annotation = match explicit.get(i) {
+                Some(a) if !a.is_empty() => a,
+                _ => annotation,
+            };
+            (word.to_string(), annotation.to_string())
+        })
+        .unzip();
+    let selected = map.opt_i64("selected")?.unwrap_or(-1);
+
+    if words.is_empty() {
+        return Ok(CandidateInfo::empty());
+    }
+    let sel = selected.max(0) as usize;
+    let mut info = CandidateInfo::new(words, sel)
+        .with_readings(map.strings("readings")?)
+        .with_annotations(annotations)
+        .with_kinds(map.strings("kinds")?);
+    info.selected = info.selected.min(info.candidates.len().saturating_sub(1));
+    Ok(info)
+}
+
+/// Field of a Lua handler's result table, logging a malformed result
+fn lua_field<'a, T>(
+    value: &'a Value,
+    field: &str,
+    get: impl FnOnce(&Map<'a>, &str) -> Result<Option<T>, FieldError>,
+) -> Option<T> {
+    match Map::new(value).and_then(|map| get(&map, field)) {
+        Ok(v) => v,
+        Err(e) => {
+            msgpack::warn_malformed("Lua result", &e, value);
+            None
+        }
+    }
+}
+
+/// Extract a string field from a msgpack map (Lua table return value).
+fn get_map_str<'a>(value: &'a Value, field: &str) -> Option<&'a str> {
+    lua_field(value, field, Map::opt_str)
+}
+
+/// Extract a field of any type from a msgpack map.
+fn get_map_value<'a>(value: &'a Value, field: &str) -> Option<&'a Value> {
+    lua_field(value, field, |map, field| Ok(map.get(field)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neovim::CANDIDATE_WINDOW;
+    use crossbeam_channel::{TryRecvError, unbounded};
+
+    fn make_handler() -> (NvimHandler, crossbeam_channel::Receiver<FromNeovim>) {
+        let (tx, rx) = unbounded();
+        let (_events, ping) = super::super::NeovimEventSource::new(rx.clone()).unwrap();
+        (
+            NvimHandler {
+                tx: NeovimSender::new(tx, ping),
+                candidates: Arc::default(),
+                columns: Arc::default(),
+                pushes: Arc::default(),
+                selections: Arc::default(),
+                mirror: Arc::default(),
+            },
+            rx,
+        )
+    }
+
+    #[test]
+    fn ui_mode_mapping_covers_cmdline_and_operator() {
+        assert_eq!(
+            NvimHandler::ui_mode_to_short_mode("cmdline_normal"),
+            Some("c")
+        );
+        assert_eq!(
+            NvimHandler::ui_mode_to_short_mode("cmdline_insert"),
+            Some("c")
+        );
+        assert_eq!(NvimHandler::ui_mode_to_short_mode("operator"), Some("no"));
+    }
 
-    for (k, v) in map {
-        let Some(key) = k.as_str() else { continue };
-        match key {
-            "preedit" => {
-                snapshot.preedit = v.as_str().unwrap_or("").to_string();
+    #[test]
+    fn ui_mode_mapping_covers_visual_line_and_block() {
+        assert_eq!(NvimHandler::ui_mode_to_short_mode("visual"), Some("v"));
+        assert_eq!(NvimHandler::ui_mode_to_short_mode("visual_line"), Some("V"));
+        assert_eq!(
+            NvimHandler::ui_mode_to_short_mode("visual_block"),
+            Some("\x16")
+        );
+    }
+
+    #[test]
+    fn get_mode_result_parses_mode_and_blocking() {
+        let mode = Mode::parse(&[
+            (Value::from("mode"), Value::from("no")),
+            (Value::from("blocking"), Value::from(true)),
+        ])
+        .unwrap();
+        assert_eq!(mode.mode, "no");
+        assert!(mode.blocking);
+        // Missing fields: normal mode, not blocked
+        assert_eq!(
+            Mode::parse(&[]).unwrap(),
+            Mode {
+                mode: "n".to_string(),
+                blocking: false
+            }
+        );
+    }
+
+    #[test]
+    fn mode_change_emits_short_mode_message() {
+        let (handler, rx) = make_handler();
+
+        handler
+            .handle_mode_change(&Value::Array(vec![
+                Value::from("cmdline_normal"),
+                Value::from(0),
+            ]))
+            .unwrap();
+        match rx.try_recv().unwrap() {
+            FromNeovim::ModeChange(mode) => assert_eq!(mode, "c"),
+            other => panic!("expected ModeChange(\"c\"), got {other:?}"),
+        }
+
+        handler
+            .handle_mode_change(&Value::Array(vec![Value::from("operator"), Value::from(0)]))
+            .unwrap();
+        match rx.try_recv().unwrap() {
+            FromNeovim::ModeChange(mode) => assert_eq!(mode, "no"),
+            other => panic!("expected ModeChange(\"no\"), got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn cmdline_show_and_hide_emit_messages_and_set_pending_state() {
+        PENDING.clear();
+        let (handler, rx) = make_handler();
+
+        handler
+            .handle_cmdline_show(&Value::Array(vec![
+                Value::Array(vec![Value::Array(vec![Value::from(0), Value::from("set")])]),
+                Value::from(3),
+                Value::from(":"),
+                Value::from(""),
+                Value::from(0),
+                Value::from(1),
+            ]))
+            .unwrap();
+
+        assert_eq!(PENDING.load(), PendingState::CommandLine);
+        match rx.try_recv().unwrap() {
+            FromNeovim::CmdlineShow {
+                content,
+                pos,
+                firstc,
+                prompt,
+                level,
+            } => {
+                assert_eq!(content, "set");
+                assert_eq!(pos, 3);
+                assert_eq!(firstc, ":");
+                assert_eq!(prompt, "");
+                assert_eq!(level, 1);
             }
-            "cursor_byte" => {
-                snapshot.cursor_byte = v.as_u64().unwrap_or(1) as usize;
+            other => panic!("expected CmdlineShow, got {other:?}"),
+        }
+
+        handler
+            .handle_cmdline_hide(&Value::Array(vec![Value::from(1)]))
+            .unwrap();
+        match rx.try_recv().unwrap() {
+            FromNeovim::CmdlineHide { level } => assert_eq!(level, 1),
+            other => panic!("expected CmdlineHide, got {other:?}"),
+        }
+        PENDING.clear();
+    }
+
+    #[test]
+    fn split_annotation_only_splits_word_and_annotation() {
+        assert_eq!(
+            split_annotation("渡辺;person name"),
+            ("渡辺", "person name")
+        );
+        assert_eq!(split_annotation("渡辺"), ("渡辺", ""));
+        assert_eq!(split_annotation(";"), (";", ""));
+        assert_eq!(split_annotation(";semicolon"), (";semicolon", ""));
+        assert_eq!(split_annotation("word;"), ("word;", ""));
+    }
+
+    #[test]
+    fn popupmenu_show_extracts_annotations() {
+        let (handler, rx) = make_handler();
+
+        let item = |word: &str, info: &str| {
+            Value::Array(vec![
+                Value::from(word),
+                Value::from(""),
+                Value::from(""),
+                Value::from(info),
+            ])
+        };
+        handler
+            .handle_popupmenu_show(&Value::Array(vec![
+                Value::Array(vec![
+                    item("渡辺;person name", ""),
+                    item("渡部", "surname\nmore"),
+                    item("わたなべ", ""),
+                ]),
+                Value::from(0),
+            ]))
+            .unwrap();
+
+        match rx.try_recv().unwrap() {
+            FromNeovim::Candidates(info) => {
+                assert_eq!(
+                    *info.candidates,
+                    [
+                        "渡辺".to_string(),
+                        "渡部".to_string(),
+                        "わたなべ".to_string()
+                    ]
+                );
+                assert_eq!(
+                    *info.annotations,
+                    [
+                        "person name".to_string(),
+                        "surname".to_string(),
+                        String::new()
+                    ]
+                );
             }
-            "mode" => {
-                snapshot.mode = v.as_str().unwrap_or("n").to_string();
+            other => panic!("expected Candidates from popupmenu_show, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn popupmenu_select_uses_cached_items() {
+        let (handler, rx) = make_handler();
+
+        handler
+            .handle_popupmenu_show(&Value::Array(vec![
+                Value::Array(vec![
+                    Value::Array(vec![
+                        Value::from("漢字"),
+                        Value::from(""),
+                        Value::from(""),
+                        Value::from(""),
+                    ]),
+                    Value::Array(vec![
+                        Value::from("感じ"),
+                        Value::from("skk"),
+                        Value::from("かんじ"),
+                        Value::from(""),
+                    ]),
+                ]),
+                Value::from(0),
+                Value::from(0),
+                Value::from(0),
+                Value::from(0),
+            ]))
+            .unwrap();
+
+        match rx.try_recv().unwrap() {
+            FromNeovim::Candidates(info) => {
+                assert_eq!(*info.candidates, ["漢字".to_string(), "感じ".to_string()]);
+                assert_eq!(info.selected, 0);
             }
-            "blocking" => {
-                snapshot.blocking = v.as_bool().unwrap_or(false);
+            other => panic!("expected Candidates from popupmenu_show, got {other:?}"),
+        }
+
+        handler
+            .handle_popupmenu_select(&Value::Array(vec![Value::from(1)]))
+            .unwrap();
+        match rx.try_recv().unwrap() {
+            FromNeovim::Candidates(info) => {
+                assert_eq!(*info.candidates, ["漢字".to_string(), "感じ".to_string()]);
+                assert_eq!(*info.readings, ["".to_string(), "かんじ".to_string()]);
+                assert_eq!(*info.kinds, ["".to_string(), "skk".to_string()]);
+                assert_eq!(info.selected, 1);
             }
-            "char_width" => {
-                snapshot.char_width = v.as_u64().unwrap_or(0) as usize;
+            other => panic!("expected Candidates from popupmenu_select, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn long_popupmenus_are_sent_a_window_at_a_time() {
+        let (handler, rx) = make_handler();
+        let items = (0..300)
+            .map(|i| Value::Array(vec![Value::from(format!("item{i}")); 4]))
+            .collect();
+        handler
+            .handle_popupmenu_show(&Value::Array(vec![Value::Array(items), Value::from(0)]))
+            .unwrap();
+        let received = || match rx.try_recv().unwrap() {
+            FromNeovim::Candidates(info) => info,
+            other => panic!("expected Candidates, got {other:?}"),
+        };
+        let shown = received();
+        assert_eq!(shown.candidates.len(), CANDIDATE_WINDOW);
+        assert_eq!((shown.offset, shown.total(), shown.selected), (0, 300, 0));
+
+        // Within the window the same slices go out again
+        let select = |index: i64| {
+            handler
+                .handle_popupmenu_select(&Value::Array(vec![Value::from(index)]))
+                .unwrap();
+        };
+        select(10);
+        let moved = received();
+        assert!(Arc::ptr_eq(&moved.candidates, &shown.candidates));
+        assert_eq!(moved.selected, 10);
+
+        // Past it, the window around the selection
+        select(299);
+        let last = received();
+        assert_eq!(last.offset, 300 - CANDIDATE_WINDOW);
+        assert_eq!(last.candidates[last.selected], "item299");
+
+        // The event loop scrolls, or asks for the whole list to filter it
+        let candidates = handler.candidates.clone();
+        let window = candidates
+            .lock()
+            .unwrap()
+            .scroll(CandidateWindow::From(100))
+            .unwrap();
+        assert_eq!(
+            (window.offset, window.candidates[0].as_str()),
+            (100, "item100")
+        );
+        let whole = candidates
+            .lock()
+            .unwrap()
+            .scroll(CandidateWindow::Whole)
+            .unwrap();
+        assert_eq!(whole.candidates.len(), 300);
+        select(5);
+        assert_eq!(received().candidates.len(), 300);
+
+        handler.handle_popupmenu_hide();
+        assert!(received().candidates.is_empty());
+        assert!(
+            candidates
+                .lock()
+                .unwrap()
+                .scroll(CandidateWindow::Whole)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn popupmenu_base_width_spans_anchor_to_cursor() {
+        let (handler, rx) = make_handler();
+        let redraw = |grid: i64, cursor: u64| {
+            let item = Value::Array(vec![Value::from("漢字"); 4]);
+            vec![
+                Value::Array(vec![
+                    Value::from("popupmenu_show"),
+                    Value::Array(vec![
+                        Value::Array(vec![item]),
+                        Value::from(0),
+                        Value::from(1),
+                        Value::from(2),
+                        Value::from(grid),
+                    ]),
+                ]),
+                Value::Array(vec![
+                    Value::from("grid_cursor_goto"),
+                    Value::Array(vec![Value::from(1), Value::from(0), Value::from(3)]),
+                    Value::Array(vec![Value::from(1), Value::from(0), Value::from(cursor)]),
+                ]),
+            ]
+        };
+        let base_width = || match rx.try_recv().unwrap() {
+            FromNeovim::Candidates(info) => info.base_width,
+            other => panic!("expected Candidates, got {other:?}"),
+        };
+
+        // The cursor goto after popupmenu_show counts, the last one wins
+        handler.handle_redraw(&redraw(1, 6));
+        assert_eq!(base_width(), Some(4));
+        handler
+            .handle_popupmenu_select(&Value::Array(vec![Value::from(0)]))
+            .unwrap();
+        assert_eq!(base_width(), Some(4));
+        // Command-line completion has no preedit to preview in
+        handler.handle_redraw(&redraw(-1, 6));
+        assert_eq!(base_width(), None);
+        // Cursor left of the anchor
+        handler.handle_redraw(&redraw(1, 1));
+        assert_eq!(base_width(), None);
+    }
+
+    #[test]
+    fn msg_show_filters_blocked_kinds_and_emits_normal_messages() {
+        let (handler, rx) = make_handler();
+
+        handler
+            .handle_msg_show(&Value::Array(vec![
+                Value::from("search_count"),
+                Value::Array(vec![Value::Array(vec![
+                    Value::from(0),
+                    Value::from("[1/2]"),
+                ])]),
+                Value::from(false),
+            ]))
+            .unwrap();
+        assert!(matches!(rx.try_recv(), Err(TryRecvError::Empty)));
+
+        handler
+            .handle_msg_show(&Value::Array(vec![
+                Value::from("echo"),
+                Value::Array(vec![Value::Array(vec![
+                    Value::from(0),
+                    Value::from("applied"),
+                ])]),
+                Value::from(false),
+            ]))
+            .unwrap();
+        match rx.try_recv().unwrap() {
+            FromNeovim::CmdlineMessage { text, cmdtype } => {
+                assert_eq!(text, "applied");
+                assert!(cmdtype.is_empty());
             }
-            "visual_begin" => {
-                if let Some(n) = v.as_u64() {
-                    snapshot.visual_begin = Some(n as usize);
-                }
+            other => panic!("expected CmdlineMessage, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn clipboard_copy_emits_selection_message() {
+        let (handler, rx) = make_handler();
+
+        handler
+            .handle_clipboard_copy(&Value::Map(vec![
+                (Value::from("register"), Value::from("+")),
+                (Value::from("text"), Value::from("コピー\n")),
+            ]))
+            .unwrap();
+        match rx.try_recv().unwrap() {
+            FromNeovim::ClipboardCopy { selection, text } => {
+                assert_eq!(selection, ClipboardSelection::Clipboard);
+                assert_eq!(text, "コピー\n");
             }
-            "visual_end" => {
-                if let Some(n) = v.as_u64() {
-                    snapshot.visual_end = Some(n as usize);
-                }
+            other => panic!("expected ClipboardCopy, got {other:?}"),
+        }
+
+        // Non-clipboard registers never reach the compositor
+        handler
+            .handle_clipboard_copy(&Value::Map(vec![
+                (Value::from("register"), Value::from("a")),
+                (Value::from("text"), Value::from("x")),
+            ]))
+            .unwrap();
+        assert!(matches!(rx.try_recv(), Err(TryRecvError::Empty)));
+    }
+
+    #[test]
+    fn ime_cmdline_executed_sends_cmdline_cancelled_and_signals_snapshot_needed() {
+        let (handler, rx) = make_handler();
+
+        let map = vec![
+            (Value::from("type"), Value::from("executed")),
+            (Value::from("cmdtype"), Value::from(":")),
+        ];
+        let result = handler.handle_ime_cmdline(&Map::from_pairs(&map)).unwrap();
+
+        // Should return (executed=true, cmdtype=":") to signal snapshot is needed
+        assert_eq!(result, Some((true, ":".to_string())));
+
+        // CmdlineCancelled message should be sent
+        match rx.try_recv().unwrap() {
+            FromNeovim::CmdlineCancelled { cmdtype, executed } => {
+                assert_eq!(cmdtype, ":");
+                assert!(executed);
             }
-            "recording" => {
-                snapshot.recording = v.as_str().unwrap_or("").to_string();
+            other => panic!("expected CmdlineCancelled, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn ime_cmdline_cancelled_does_not_signal_snapshot() {
+        let (handler, rx) = make_handler();
+
+        let map = vec![
+            (Value::from("type"), Value::from("cancelled")),
+            (Value::from("cmdtype"), Value::from(":")),
+        ];
+        let result = handler.handle_ime_cmdline(&Map::from_pairs(&map)).unwrap();
+
+        // Should return (executed=false, cmdtype=":") — no snapshot needed
+        assert_eq!(result, Some((false, ":".to_string())));
+
+        match rx.try_recv().unwrap() {
+            FromNeovim::CmdlineCancelled { cmdtype, executed } => {
+                assert_eq!(cmdtype, ":");
+                assert!(!executed);
             }
-            _ => {}
+            other => panic!("expected CmdlineCancelled, got {other:?}"),
         }
     }
 
-    Ok(snapshot)
-}
+    #[test]
+    fn byte_edit_parsing() {
+        let value = Value::Map(vec![
+            (Value::from("tick"), Value::from(7)),
+            (Value::from("row"), Value::from(0)),
+            (Value::from("col"), Value::from(2)),
+            (Value::from("old_len"), Value::from(0)),
+            (Value::from("new_len"), Value::from(1)),
+            (Value::from("multiline"), Value::from(false)),
+            (Value::from("line"), Value::from("f()")),
+        ]);
+        assert_eq!(
+            parse_byte_edit(&value),
+            Ok(ByteEdit {
+                tick: 7,
+                row: 0,
+                col: 2,
+                old_len: 0,
+                new_len: 1,
+                multiline: false,
+                line: "f()".into(),
+            })
+        );
+        assert_eq!(
+            parse_byte_edit(&Value::Map(vec![(Value::from("tick"), Value::from(7))])),
+            Err(FieldError::Missing("row".into()))
+        );
+    }
 
-/// Extract a string field from a msgpack map (Lua table return value).
-fn get_map_str<'a>(value: &'a nvim_rs::Value, field: &str) -> Option<&'a str> {
-    value
-        .as_map()?
-        .iter()
-        .find(|(k, _)| k.as_str() == Some(field))
-        .and_then(|(_, v)| v.as_str())
+    #[test]
+    fn malformed_redraw_params_send_nothing() {
+        let (handler, rx) = make_handler();
+        let chunk = |items: Vec<Value>| Value::Array(vec![Value::Array(items)]);
+
+        // Truncated, and a content chunk without its text
+        assert_eq!(
+            handler.handle_cmdline_show(&Value::Array(vec![chunk(vec![]), Value::from(0)])),
+            Err(FieldError::TooShort {
+                expected: 6,
+                got: 2,
+            })
+        );
+        let textless = Value::Array(vec![
+            chunk(vec![Value::from(0)]),
+            Value::from(0),
+            Value::from(":"),
+            Value::from(""),
+            Value::from(0),
+            Value::from(1),
+        ]);
+        assert!(handler.handle_cmdline_show(&textless).is_err());
+        assert!(handler.handle_cmdline_pos(&Value::from("3")).is_err());
+        // Items that aren't lists, or lists of non-strings
+        let popup = |items: Value| Value::Array(vec![items, Value::from(0)]);
+        assert!(
+            handler
+                .handle_popupmenu_show(&popup(Value::from("漢字")))
+                .is_err()
+        );
+        assert!(
+            handler
+                .handle_popupmenu_show(&popup(chunk(vec![Value::from(1)])))
+                .is_err()
+        );
+        assert!(
+            handler
+                .handle_popupmenu_select(&Value::Array(vec![]))
+                .is_err()
+        );
+        assert!(
+            handler
+                .handle_mode_change(&Value::Array(vec![Value::from(0)]))
+                .is_err()
+        );
+        assert!(
+            handler
+                .handle_msg_show(&Value::Array(vec![Value::from("echo"), Value::from("raw")]))
+                .is_err()
+        );
+        assert!(handler.handle_cmdline_hide(&Value::Nil).is_err());
+        assert!(matches!(rx.try_recv(), Err(TryRecvError::Empty)));
+
+        // A malformed event group doesn't stop the ones after it
+        handler.handle_redraw(&[
+            Value::from("flush"),
+            Value::Array(vec![
+                Value::from("mode_change"),
+                Value::Array(vec![Value::from(0)]),
+                Value::Array(vec![Value::from("insert"), Value::from(0)]),
+            ]),
+        ]);
+        match rx.try_recv().unwrap() {
+            FromNeovim::ModeChange(mode) => assert_eq!(mode, "i"),
+            other => panic!("expected ModeChange(\"i\"), got {other:?}"),
+        }
+        assert!(matches!(rx.try_recv(), Err(TryRecvError::Empty)));
+    }
+
+    #[test]
+    fn malformed_notifications_are_rejected() {
+        let (handler, rx) = make_handler();
+        let map = |pairs: Vec<(&str, Value)>| {
+            Value::Map(
+                pairs
+                    .into_iter()
+                    .map(|(k, v)| (Value::from(k), v))
+                    .collect(),
+            )
+        };
+
+        assert_eq!(
+            parse_candidates(&Value::from("漢字")).unwrap_err(),
+            FieldError::NotMap
+        );
+        let words = || Value::Array(vec![Value::from("漢字"), Value::from("感じ")]);
+        assert!(parse_candidates(&map(vec![("candidates", Value::from("漢字"))])).is_err());
+        assert!(
+            parse_candidates(&map(vec![
+                ("candidates", words()),
+                ("selected", Value::from("1")),
+            ]))
+            .is_err()
+        );
+        let info = parse_candidates(&map(vec![
+            ("candidates", words()),
+            ("selected", Value::from(9)),
+        ]))
+        .unwrap();
+        assert_eq!(info.selected, 1);
+        // An empty Lua table arrives as an array: no candidates
+        assert!(
+            parse_candidates(&Value::Array(vec![]))
+                .unwrap()
+                .candidates
+                .is_empty()
+        );
+
+        assert_eq!(parse_snapshot(&Value::Nil).unwrap_err(), FieldError::NotMap);
+        assert!(parse_snapshot(&map(vec![("cursor_byte", Value::from("1"))])).is_err());
+        // Absent (or nil) fields take their defaults
+        let snapshot = parse_snapshot(&map(vec![("visual_begin", Value::Nil)])).unwrap();
+        assert_eq!((snapshot.cursor_byte, snapshot.visual_begin), (1, None));
+        assert_eq!(
+            NvimError::SnapshotParse(FieldError::NotMap).to_string(),
+            "snapshot parse failed: expected a map"
+        );
+
+        assert!(parse_byte_edit(&Value::Array(vec![Value::from(7)])).is_err());
+        assert!(Mode::parse(&[(Value::from("blocking"), Value::from(1))]).is_err());
+
+        assert_eq!(
+            handler.handle_clipboard_copy(&map(vec![("text", Value::from("x"))])),
+            Err(FieldError::Missing("register".into()))
+        );
+        let pairs = [(Value::from("cmdtype"), Value::from(":"))];
+        assert_eq!(
+            handler.handle_ime_cmdline(&Map::from_pairs(&pairs)),
+            Err(FieldError::Missing("type".into()))
+        );
+        assert!(matches!(rx.try_recv(), Err(TryRecvError::Empty)));
+
+        // Lua results that aren't tables, or with mistyped fields, have no fields
+        let commit = map(vec![
+            ("type", Value::from("commit")),
+            ("text", Value::from(1)),
+        ]);
+        assert_eq!(get_map_str(&commit, "type"), Some("commit"));
+        assert_eq!(get_map_str(&commit, "text"), None);
+        assert_eq!(get_map_str(&Value::Nil, "type"), None);
+        assert_eq!(get_map_value(&Value::from(true), "snapshot"), None);
+    }
+
+    #[test]
+    fn clipboard_get_serves_latest_selection() {
+        let (handler, _rx) = make_handler();
+        let get = |register: &str| handler.clipboard_get(&[Value::from(register)]);
+        assert_eq!(get("+"), Ok(Value::Nil));
+        *handler
+            .selections
+            .lock()
+            .unwrap()
+            .slot(ClipboardSelection::Clipboard) = Some("コピー".into());
+        assert_eq!(get("+"), Ok(Value::from("コピー")));
+        assert_eq!(get("*"), Ok(Value::Nil));
+        assert!(get("a").is_err());
+        assert!(handler.clipboard_get(&[]).is_err());
+    }
+
+    #[test]
+    fn watchdog_watches_only_the_active_backend_while_needed() {
+        let watch = Watch::default();
+        // The standby isn't watched, even handling a message
+        {
+            let _busy = Busy::start(&watch);
+            assert!(!watch.is_watched());
+        }
+
+        // Active but disabled: only while a message is handled
+        watch.set_enabled(false);
+        assert!(!watch.is_watched());
+        {
+            let _busy = Busy::start(&watch);
+            assert!(watch.is_watched());
+        }
+        assert!(!watch.is_watched());
+
+        watch.set_enabled(true);
+        assert!(watch.is_watched());
+        watch.set_enabled(false);
+        assert!(!watch.is_watched());
+    }
 }
@@ -6,7 +6,7 @@
 
 use std::time::{Duration, Instant};
 
-use super::{FromNeovim, spawn_neovim};
+use super::{FromNeovim, InputMode, spawn_neovim};
 use crate::config::Config;
 
 const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
@@ -37,10 +37,10 @@ fn recv_until(
         if remaining.is_zero() {
             return None;
         }
-        if let Some(msg) = handle.recv_timeout(remaining.min(Duration::from_millis(100))) {
-            if predicate(&msg) {
-                return Some(msg);
-            }
+        if let Some(msg) = handle.recv_timeout(remaining.min(Duration::from_millis(100)))
+            && predicate(&msg)
+        {
+            return Some(msg);
         }
     }
 }
@@ -122,6 +122,79 @@ fn escape_switches_to_normal_mode() {
     shutdown_and_wait(&handle);
 }
 
+#[test]
+#[ignore]
+fn kana_direct_commits_settled_text_and_keeps_romaji() {
+    let handle = spawn_and_wait_ready();
+    handle.set_input_mode(InputMode::KanaDirect);
+
+    handle.send_key("あ");
+    let msg = recv_until(
+        &handle,
+        |m| matches!(m, FromNeovim::AutoCommit(text) if text == "あ"),
+        MSG_TIMEOUT,
+    );
+    assert!(
+        msg.is_some(),
+        "expected AutoCommit 'あ' in kana-direct mode"
+    );
+
+    // Trailing ASCII is pending romaji — stays in the buffer, no commit
+    handle.send_key("k");
+    let msg = recv_until(
+        &handle,
+        |m| matches!(m, FromNeovim::Preedit(info) if info.text == "k"),
+        MSG_TIMEOUT,
+    );
+    assert!(
+        msg.is_some(),
+        "expected pending romaji 'k' to remain as preedit"
+    );
+
+    shutdown_and_wait(&handle);
+}
+
+#[test]
+#[ignore]
+fn commit_prefix_keeps_text_after_it() {
+    let handle = spawn_and_wait_ready();
+
+    for key in ["は", "い", "。", "え"] {
+        handle.send_key(key);
+    }
+    recv_until(
+        &handle,
+        |m| matches!(m, FromNeovim::Preedit(info) if info.text == "はい。え"),
+        MSG_TIMEOUT,
+    )
+    .expect("expected preedit 'はい。え'");
+
+    handle.commit_prefix("はい。".to_string());
+    recv_until(
+        &handle,
+        |m| matches!(m, FromNeovim::AutoCommit(text) if text == "はい。"),
+        MSG_TIMEOUT,
+    )
+    .expect("expected AutoCommit 'はい。'");
+    recv_until(
+        &handle,
+        |m| matches!(m, FromNeovim::Preedit(info) if info.text == "え"),
+        MSG_TIMEOUT,
+    )
+    .expect("expected the rest to stay as preedit");
+
+    // The line no longer starts with it: nothing more is committed
+    handle.commit_prefix("はい。".to_string());
+    let msg = recv_until(
+        &handle,
+        |m| matches!(m, FromNeovim::AutoCommit(_)),
+        Duration::from_millis(300),
+    );
+    assert!(msg.is_none(), "stale prefix must not commit");
+
+    shutdown_and_wait(&handle);
+}
+
 #[test]
 #[ignore]
 fn shutdown_exits_cleanly() {
@@ -205,3 +278,76 @@ fn startinsert_false_starts_in_normal_mode() {
 
     shutdown_and_wait(&handle);
 }
+
+#[test]
+#[ignore]
+fn restore_puts_back_text_cursor_and_insert_mode() {
+    let handle = spawn_and_wait_ready();
+
+    handle.restore("かな".to_string(), 3, true);
+    let msg = recv_until(
+        &handle,
+        |m| matches!(m, FromNeovim::Preedit(info) if info.text == "かな"),
+        MSG_TIMEOUT,
+    );
+    let Some(FromNeovim::Preedit(info)) = msg else {
+        panic!("expected Preedit with the restored text");
+    };
+    assert_eq!(info.cursor_begin, 3);
+    assert_eq!(info.mode, "i");
+
+    // Typing continues at the restored cursor
+    handle.send_key("x");
+    let msg = recv_until(
+        &handle,
+        |m| matches!(m, FromNeovim::Preedit(info) if info.text == "かxな"),
+        MSG_TIMEOUT,
+    );
+    assert!(
+        msg.is_some(),
+        "expected typing to resume at the restored cursor"
+    );
+
+    shutdown_and_wait(&handle);
+}
+
+/// Key sent to KeyProcessed, per key of `keys`
+fn key_round_trips(handle: &super::NeovimHandle, keys: &[&str]) -> Vec<Duration> {
+    keys.iter()
+        .map(|key| {
+            let sent = Instant::now();
+            handle.send_key(key);
+            recv_until(
+                handle,
+                |m| matches!(m, FromNeovim::KeyProcessed),
+                MSG_TIMEOUT,
+            )
+            .unwrap_or_else(|| panic!("{key} was never processed"));
+            sent.elapsed()
+        })
+        .collect()
+}
+
+/// Key latency through the backend, for comparing changes to the key path:
+/// `cargo test -- --ignored key_round_trip_latency --nocapture`
+#[test]
+#[ignore]
+fn key_round_trip_latency() {
+    let handle = spawn_and_wait_ready();
+
+    let report = |path: &str, mut times: Vec<Duration>| {
+        times.sort();
+        println!(
+            "{path}: median {:?}, p90 {:?}, max {:?} over {} keys",
+            times[times.len() / 2],
+            times[times.len() * 9 / 10],
+            times[times.len() - 1],
+            times.len()
+        );
+    };
+    report("insert", key_round_trips(&handle, &["a"; 50]));
+    key_round_trips(&handle, &["<Esc>"]);
+    report("normal", key_round_trips(&handle, &["h", "l"].repeat(25)));
+
+    shutdown_and_wait(&handle);
+}
@@ -0,0 +1,35 @@
+-- Byte-level buffer edits for preedit consistency. Plugins that edit the
+-- buffer without a key (autopairs, snippet expansion, timers) aren't always
+-- followed by a TextChangedI push; on_bytes reports every edit with the
+-- edited line so jacin can update the preedit directly.
+local function attach(buf)
+    if vim.b[buf].ime_attached then return end
+    vim.b[buf].ime_attached = true
+    vim.api.nvim_buf_attach(buf, false, {
+        on_bytes = function(_, b, tick, start_row, start_col, _, old_end_row, _, old_len, new_end_row, _, new_len)
+            if ime_context.clearing then return end
+            local line = vim.api.nvim_buf_get_lines(b, start_row, start_row + 1, false)[1] or ''
+            vim.rpcnotify(vim.g.ime_channel, 'ime_buf_bytes', {
+                tick = tick,
+                row = start_row,
+                col = start_col,
+                old_len = old_len,
+                new_len = new_len,
+                multiline = old_end_row > 0 or new_end_row > 0,
+                line = line,
+            })
+        end,
+        on_detach = function(_, b)
+            pcall(function() vim.b[b].ime_attached = nil end)
+        end,
+    })
+end
+
+attach(vim.api.nvim_get_current_buf())
+
+-- :enew, :bnext etc. replace the scratch buffer
+vim.api.nvim_create_autocmd('BufEnter', {
+    callback = function(args)
+        attach(args.buf)
+    end,
+})
@@ -0,0 +1,46 @@
+-- Clipboard provider backed by jacin: the compositor selection is pushed into
+-- ime_clipboard by the IME; yanks to + / * are sent back for the compositor.
+_G.ime_clipboard = { ['+'] = { {}, 'v' }, ['*'] = { {}, 'v' } }
+
+-- Called by jacin when the Wayland selection changes
+function _G.ime_clipboard_store(reg, text)
+    local regtype = 'v'
+    if text:sub(-1) == '\n' then
+        text = text:sub(1, -2)
+        regtype = 'V'
+    end
+    ime_clipboard[reg] = { vim.split(text, '\n', { plain = true }), regtype }
+end
+
+local function copy(reg)
+    return function(lines, regtype)
+        ime_clipboard[reg] = { lines, regtype }
+        local text = table.concat(lines, '\n')
+        if regtype == 'V' then
+            text = text .. '\n'
+        end
+        vim.rpcnotify(vim.g.ime_channel, 'ime_clipboard_copy', { register = reg, text = text })
+    end
+end
+
+local function paste(reg)
+    return function()
+        -- A selection that arrived while Neovim was blocked (after <C-r> or ")
+        -- is only known to jacin; ask for the latest one
+        local ok, text = pcall(vim.rpcrequest, vim.g.ime_channel, 'ime_clipboard_get', reg)
+        if ok and type(text) == 'string' then
+            ime_clipboard_store(reg, text)
+        end
+        return ime_clipboard[reg]
+    end
+end
+
+vim.g.clipboard = {
+    name = 'jacin',
+    copy = { ['+'] = copy('+'), ['*'] = copy('*') },
+    paste = { ['+'] = paste('+'), ['*'] = paste('*') },
+    cache_enabled = 0,
+}
+-- The provider may already be loaded by user config; reload so g:clipboard applies
+vim.g.loaded_clipboard_provider = nil
+vim.cmd('runtime autoload/provider/clipboard.vim')
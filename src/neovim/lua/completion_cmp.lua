@@ -35,12 +35,29 @@ local function ime_setup_cmp()
         last_sel = sel
         last_count = #entries
         local words = {}
+        -- Reading per word (furigana): sources like cmp-skkeleton filter on the kana
+        local readings = {}
+        -- Annotation per word (first line of the documentation, e.g. SKK annotations)
+        local annotations = {}
+        -- Source per word (kind column), to tell nvim-cmp sources apart
+        local kinds = {}
         for _, e in ipairs(entries) do
             local w = e:get_word()
-            if w and w ~= '' then words[#words + 1] = w end
+            if w and w ~= '' then
+                local item = e:get_completion_item()
+                words[#words + 1] = w
+                readings[#words] = item.filterText or item.detail or ''
+                local doc = item.documentation
+                if type(doc) == 'table' then doc = doc.value end
+                annotations[#words] = type(doc) == 'string' and doc:match('^[^\n]*') or ''
+                kinds[#words] = e.source and e.source.name or ''
+            end
         end
         vim.rpcnotify(vim.g.ime_channel, 'ime_candidates', {
             candidates = words,
+            readings = readings,
+            annotations = annotations,
+            kinds = kinds,
             selected = sel,
         })
     end
@@ -0,0 +1,9 @@
+-- SKK dictionaries installed by `jacin dict fetch` (paths). Conversion plugins
+-- can read vim.g.ime_dictionaries and reload on `User ImeDictionaryUpdated`.
+vim.g.ime_dictionaries = ...
+
+-- Called by jacin after `jacin dict fetch` installed new dictionaries
+function _G.ime_set_dictionaries(paths)
+    vim.g.ime_dictionaries = paths
+    vim.api.nvim_exec_autocmds('User', { pattern = 'ImeDictionaryUpdated', modeline = false })
+end
@@ -18,6 +18,36 @@ function _G.ime_handle_enter()
     return { type = 'processing' }
 end
 
+-- Candidate keys: move the completion selection to `index` (0-based) and,
+-- with `finish`, accept it. nvim-cmp menus use its API; the native popup
+-- menu gets the equivalent <C-n>/<C-p>/<C-y> input.
+function _G.ime_select_candidate(index, finish)
+    local ok, cmp = pcall(require, 'cmp')
+    if ok and cmp.visible() then
+        local entries = cmp.get_entries() or {}
+        local active = cmp.get_active_entry()
+        local sel = -1
+        for i, e in ipairs(entries) do
+            if e == active then
+                sel = i - 1
+                break
+            end
+        end
+        local opts = { behavior = cmp.SelectBehavior.Insert }
+        for _ = sel + 1, index do cmp.select_next_item(opts) end
+        for _ = index + 1, sel do cmp.select_prev_item(opts) end
+        if finish then cmp.confirm({ select = true }) end
+        return { type = 'processing' }
+    end
+    if vim.fn.pumvisible() == 0 then
+        return { type = 'none' }
+    end
+    local sel = vim.fn.complete_info({ 'selected' }).selected
+    local keys = index > sel and string.rep('<C-n>', index - sel) or string.rep('<C-p>', sel - index)
+    vim.api.nvim_input(keys .. (finish and '<C-y>' or ''))
+    return { type = 'processing' }
+end
+
 -- Commit: get preedit text, clear buffer, return text for commit
 function _G.ime_handle_commit()
     local line = vim.fn.getline('.')
@@ -28,3 +58,68 @@ function _G.ime_handle_commit()
     vim.cmd('startinsert')
     return { type = 'commit', text = line }
 end
+
+-- Kana-direct: commit settled text before the cursor. Trailing ASCII (pending
+-- romaji) and anything from a henkan marker onward stay in the buffer.
+function _G.ime_kana_direct_commit()
+    if vim.api.nvim_get_mode().mode ~= 'i' or vim.fn.pumvisible() == 1 then
+        return { type = 'none' }
+    end
+    local row = vim.fn.line('.') - 1
+    local col = vim.fn.col('.') - 1
+    local before = vim.fn.getline('.'):sub(1, col)
+    local settled = before:match('^(.-)[\33-\126]*$') or ''
+    for _, marker in ipairs({ '▽', '▼' }) do
+        local pos = settled:find(marker, 1, true)
+        if pos then
+            settled = settled:sub(1, pos - 1)
+        end
+    end
+    if settled == '' then
+        return { type = 'none' }
+    end
+    ime_context.clearing = true
+    vim.o.eventignore = 'all'
+    vim.api.nvim_buf_set_text(0, row, 0, row, #settled, {})
+    vim.api.nvim_win_set_cursor(0, { row + 1, col - #settled })
+    vim.o.eventignore = ''
+    ime_context.clearing = false
+    -- The remainder, saving jacin a second round trip to fetch it
+    return { type = 'commit', text = settled, snapshot = collect_snapshot() }
+end
+
+-- Punctuation commit: drop `prefix` from the start of the cursor line if the
+-- line still starts with it and the cursor is past it
+function _G.ime_commit_prefix(prefix)
+    if vim.api.nvim_get_mode().mode ~= 'i' or vim.fn.pumvisible() == 1 then
+        return { type = 'none' }
+    end
+    local row = vim.fn.line('.') - 1
+    local col = vim.fn.col('.') - 1
+    if col < #prefix or vim.fn.getline('.'):sub(1, #prefix) ~= prefix then
+        return { type = 'none' }
+    end
+    ime_context.clearing = true
+    vim.o.eventignore = 'all'
+    vim.api.nvim_buf_set_text(0, row, 0, row, #prefix, {})
+    vim.api.nvim_win_set_cursor(0, { row + 1, col - #prefix })
+    vim.o.eventignore = ''
+    ime_context.clearing = false
+    return { type = 'commit', snapshot = collect_snapshot() }
+end
+
+-- Restore: put back the composition of a backend jacin replaced (watchdog).
+-- `cursor` is a byte offset into `text`.
+function _G.ime_restore(text, cursor, insert)
+    ime_context.clearing = true
+    vim.o.eventignore = 'all'
+    vim.api.nvim_buf_set_lines(0, 0, -1, false, { text })
+    vim.o.eventignore = ''
+    ime_context.clearing = false
+    if insert and cursor >= #text then
+        vim.cmd('startinsert!')
+        return
+    end
+    vim.api.nvim_win_set_cursor(0, { 1, cursor })
+    vim.cmd(insert and 'startinsert' or 'stopinsert')
+end
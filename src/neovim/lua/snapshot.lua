@@ -1,3 +1,44 @@
+-- Byte column (1-indexed) of the last skkeleton henkan marker in `text`,
+-- and whether it is ▼ (choosing a conversion) rather than ▽ (typing a reading)
+local function henkan_marker(text)
+    local found, converted
+    for _, marker in ipairs({ '▽', '▼' }) do
+        local init = 1
+        while true do
+            local pos = text:find(marker, init, true)
+            if not pos then
+                break
+            end
+            if not found or pos > found then
+                found, converted = pos, marker == '▼'
+            end
+            init = pos + 1
+        end
+    end
+    return found, converted
+end
+
+-- Byte ranges ({begin, end}, 0-indexed, end exclusive) of the last search
+-- pattern's matches in `line`, while Neovim highlights them
+local function search_matches(line)
+    local pattern = vim.fn.getreg('/')
+    if pattern == '' or not vim.o.hlsearch or vim.v.hlsearch == 0 then
+        return nil
+    end
+    local matches = {}
+    -- At most one match per byte: a pattern matching everywhere stays cheap
+    for count = 1, #line do
+        local ok, found = pcall(vim.fn.matchstrpos, line, pattern, 0, count)
+        if not ok or found[2] < 0 then
+            break
+        end
+        if found[3] > found[2] then
+            table.insert(matches, { found[2], found[3] })
+        end
+    end
+    return matches
+end
+
 function _G.collect_snapshot()
     local mode = vim.api.nvim_get_mode()
     local line = vim.fn.getline('.')
@@ -10,19 +51,58 @@ function _G.collect_snapshot()
         blocking = mode.blocking,
         char_width = 0,
         recording = vim.fn.reg_recording(),
+        recorded = vim.fn.reg_recorded(),
+        row = vim.fn.line('.') - 1,
+        tick = vim.b.changedtick,
+        undo_seq = vim.fn.changenr(),
+        last_change = vim.fn.getreg('.'),
     }
 
+    snapshot.highlights = search_matches(line)
+
+    -- Last recorded macro's keys, previewed while it plays back
+    if snapshot.recorded ~= '' then
+        snapshot.recorded_keys = vim.fn.keytrans(vim.fn.getreg(snapshot.recorded))
+    end
+
     -- Normal/visual mode: character width under cursor
     if mode.mode == 'n' or mode.mode:find('^no') or mode.mode:find('^v') or mode.mode == 'V' or mode.mode == '\22' then
         local char = vim.fn.matchstr(line, '\\%' .. col .. 'c.')
         snapshot.char_width = vim.fn.strlen(char)
     end
 
+    -- Insert mode: conversion segment from the henkan marker to the cursor
+    if mode.mode:find('^i') then
+        snapshot.segment_begin, snapshot.segment_converted = henkan_marker(line:sub(1, col - 1))
+    end
+
     -- Visual mode: selection range
-    if mode.mode == 'V' or mode.mode == '\22' then
-        -- Line-wise / block-wise: highlight entire line
+    if mode.mode == 'V' then
+        -- Line-wise: entire line
         snapshot.visual_begin = 1
         snapshot.visual_end = vim.fn.strlen(line) + 1
+    elseif mode.mode == '\22' then
+        -- Block-wise: the block's screen columns on the cursor line
+        local v = vim.fn.getpos('v')
+        local v_cols = vim.fn.virtcol({ v[2], v[3] }, 1)
+        local cur_cols = vim.fn.virtcol('.', 1)
+        local left = math.min(v_cols[1], cur_cols[1])
+        local right = math.max(v_cols[2], cur_cols[2])
+        local lnum = vim.fn.line('.')
+        local len = vim.fn.strlen(line)
+        local width = vim.fn.strdisplaywidth(line)
+        snapshot.visual_begin = left <= width and vim.fn.virtcol2col(0, lnum, left) or len + 1
+        if vim.fn.winsaveview().curswant == vim.v.maxcol then
+            -- `$`: every line up to its end
+            snapshot.visual_end = len + 1
+        elseif right > width then
+            snapshot.visual_end = len + 1
+            snapshot.visual_overhang = right - width
+        else
+            local last = vim.fn.virtcol2col(0, lnum, right)
+            local last_char = vim.fn.matchstr(line, '\\%' .. last .. 'c.')
+            snapshot.visual_end = last + vim.fn.strlen(last_char)
+        end
     elseif mode.mode:find('^v') then
         local v_col = vim.fn.getpos('v')[3]
         local sel_start = math.min(v_col, col)
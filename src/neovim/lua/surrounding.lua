@@ -0,0 +1,10 @@
+-- Text around the cursor in the focused application (input-method surrounding
+-- text, preedit excluded). Conversion and completion sources can read
+-- vim.g.ime_surrounding and listen for `User ImeSurroundingText`.
+vim.g.ime_surrounding = { before = '', after = '' }
+
+-- Called by jacin whenever the application reports new surrounding text
+function _G.ime_set_surrounding(before, after)
+    vim.g.ime_surrounding = { before = before, after = after }
+    vim.api.nvim_exec_autocmds('User', { pattern = 'ImeSurroundingText', modeline = false })
+end
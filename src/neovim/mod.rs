@@ -3,12 +3,20 @@
 //! Provides communication with an embedded Neovim instance for input processing.
 //! Users manage their own Japanese input plugins inside Neovim.
 
+mod buffer_mirror;
 mod event_source;
 mod handler;
 #[cfg(test)]
 mod integration_tests;
+mod msgpack;
 pub mod protocol;
+mod snapshot_strategy;
 
+use std::ffi::OsStr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::thread;
 use std::time::Duration;
 
@@ -16,31 +24,113 @@ use crossbeam_channel::{Receiver, Sender, bounded};
 
 use crate::config::Config;
 
-// Re-export event source types (for future calloop integration)
-#[allow(unused_imports)]
-pub use event_source::{NeovimEventSource, NeovimPing};
+pub use event_source::{NeovimEventSource, NeovimSender};
 
+use handler::Watch;
 pub use handler::pending_state;
 pub use protocol::{
-    CandidateInfo, FromNeovim, PendingState, PreeditInfo, ToNeovim, VisualSelection,
+    CANDIDATE_WINDOW, CandidateInfo, CandidateWindow, ClipboardSelection, FromNeovim, InputMode,
+    MacroRegister, PendingState, PreeditInfo, PreeditSegment, SegmentKind, SnapshotStats, ToNeovim,
+    VisualSelection,
 };
 
 /// Channel capacity for Neovim communication
 /// This provides backpressure if messages accumulate
 const CHANNEL_CAPACITY: usize = 64;
 
+/// Identifies backends across spawns (see `NeovimHandle::id`)
+static NEXT_BACKEND_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Id of a backend being spawned (see `NeovimHandle::id`)
+pub fn next_backend_id() -> u64 {
+    NEXT_BACKEND_ID.fetch_add(1, Ordering::Relaxed)
+}
+
 /// Handle to communicate with Neovim backend
 pub struct NeovimHandle {
+    id: u64,
     sender: Sender<ToNeovim>,
     receiver: Receiver<FromNeovim>,
+    /// Wakes the event loop for this backend's messages until registered
+    events: Option<NeovimEventSource>,
+    /// When the watchdog pings this Neovim
+    watch: Arc<Watch>,
 }
 
 impl NeovimHandle {
+    /// Unique per spawned backend: tells the active backend's messages from
+    /// the standby's (or from a backend already replaced)
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Event source delivering this backend's messages to calloop (once;
+    /// None after it has been taken)
+    pub fn take_event_source(&mut self) -> Option<NeovimEventSource> {
+        self.events.take()
+    }
+
     /// Send a key to Neovim (non-blocking: drops key if channel full)
     pub fn send_key(&self, key: &str) {
         let _ = self.sender.try_send(ToNeovim::Key(key.to_string()));
     }
 
+    /// Switch the backend's input mode (non-blocking)
+    pub fn set_input_mode(&self, mode: InputMode) {
+        let _ = self.sender.try_send(ToNeovim::SetInputMode(mode));
+    }
+
+    /// Hand the compositor's selection to Neovim's clipboard provider (non-blocking)
+    pub fn set_clipboard(&self, selection: ClipboardSelection, text: String) {
+        let _ = self
+            .sender
+            .try_send(ToNeovim::SetClipboard { selection, text });
+    }
+
+    /// Hand the focused input's surrounding text to Neovim (non-blocking)
+    pub fn set_surrounding_text(&self, before: String, after: String) {
+        let _ = self
+            .sender
+            .try_send(ToNeovim::SetSurroundingText { before, after });
+    }
+
+    /// Select (and with `finish`, accept) a completion candidate (non-blocking)
+    pub fn select_candidate(&self, index: usize, finish: bool) {
+        let _ = self
+            .sender
+            .try_send(ToNeovim::SelectCandidate { index, finish });
+    }
+
+    /// Ask for another part of a long candidate list (non-blocking)
+    pub fn scroll_candidates(&self, window: CandidateWindow) {
+        let _ = self.sender.try_send(ToNeovim::ScrollCandidates(window));
+    }
+
+    /// Commit `text` from the start of the cursor line and keep the rest (non-blocking)
+    pub fn commit_prefix(&self, text: String) {
+        let _ = self.sender.try_send(ToNeovim::CommitPrefix(text));
+    }
+
+    /// Hand the installed dictionary paths to Neovim (non-blocking)
+    pub fn set_dictionaries(&self, paths: Vec<String>) {
+        let _ = self.sender.try_send(ToNeovim::SetDictionaries(paths));
+    }
+
+    /// Put back a replaced backend's composition (non-blocking)
+    pub fn restore(&self, text: String, cursor: usize, insert: bool) {
+        let _ = self.sender.try_send(ToNeovim::Restore {
+            text,
+            cursor,
+            insert,
+        });
+    }
+
+    /// This is the active backend and the IME is enabled or not: its
+    /// watchdog runs while enabled (or while a message is being handled)
+    pub fn watch(&self, ime_enabled: bool) {
+        self.watch.set_enabled(ime_enabled);
+    }
+
     /// Try to receive a message from Neovim (non-blocking)
     pub fn try_recv(&self) -> Option<FromNeovim> {
         self.receiver.try_recv().ok()
@@ -59,26 +149,68 @@ impl NeovimHandle {
             .sender
             .send_timeout(ToNeovim::Shutdown, Duration::from_millis(200));
     }
-
-    /// Get the receiver for use with calloop event source
-    #[allow(dead_code)]
-    pub fn receiver(&self) -> &Receiver<FromNeovim> {
-        &self.receiver
-    }
 }
 
 /// Spawn Neovim backend in a separate thread
 pub fn spawn_neovim(config: Config) -> anyhow::Result<NeovimHandle> {
+    // Fail here rather than on the backend thread, so callers can fall back
+    let binary = config.nvim.binary();
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    if find_executable(&binary, &path).is_none() {
+        anyhow::bail!("{} not found", binary.display());
+    }
     // Use bounded channels for backpressure
     let (to_nvim_tx, to_nvim_rx) = bounded::<ToNeovim>(CHANNEL_CAPACITY);
     let (from_nvim_tx, from_nvim_rx) = bounded::<FromNeovim>(CHANNEL_CAPACITY);
+    let (events, ping) = NeovimEventSource::new(from_nvim_rx.clone())?;
+    let from_nvim_tx = NeovimSender::new(from_nvim_tx, ping);
+    let watch = Arc::new(Watch::default());
 
+    let watched = watch.clone();
     thread::spawn(move || {
-        handler::run_blocking(to_nvim_rx, from_nvim_tx, config);
+        handler::run_blocking(to_nvim_rx, from_nvim_tx, watched, config);
     });
 
     Ok(NeovimHandle {
+        id: next_backend_id(),
         sender: to_nvim_tx,
         receiver: from_nvim_rx,
+        events: Some(events),
+        watch,
     })
 }
+
+/// `binary` if it is a path, else the first executable of that name in the
+/// directories of a PATH value
+fn find_executable(binary: &Path, path: &OsStr) -> Option<PathBuf> {
+    let is_executable = |file: &PathBuf| {
+        file.metadata()
+            .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+    };
+    if binary.components().count() > 1 {
+        return Some(binary.to_path_buf()).filter(is_executable);
+    }
+    std::env::split_paths(path)
+        .map(|dir| dir.join(binary))
+        .find(is_executable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_executables_on_path() {
+        let path = OsStr::new("/nonexistent:/bin");
+        let find = |binary: &str, path| find_executable(Path::new(binary), path);
+        assert_eq!(find("sh", path), Some(PathBuf::from("/bin/sh")));
+        assert_eq!(find("jacin-no-such-binary", path), None);
+        assert_eq!(find("sh", OsStr::new("")), None);
+        // Paths are used as-is
+        assert_eq!(
+            find("/bin/sh", OsStr::new("")),
+            Some(PathBuf::from("/bin/sh"))
+        );
+        assert_eq!(find("/nonexistent/nvim", path), None);
+    }
+}
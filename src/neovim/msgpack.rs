@@ -0,0 +1,398 @@
+//! Typed access to msgpack maps and arrays from Neovim
+//!
+//! Lua return values, `rpcnotify` payloads and redraw events all arrive as
+//! `nvim_rs::Value`. Defaulting every field that isn't what we expect hides
+//! bugs in plugins and in our own Lua, so these accessors tell an absent field
+//! (`nil` counts as absent) from one of the wrong type and return a
+//! [`FieldError`] for the latter. [`warn_malformed`] logs a rejected payload:
+//! at warn level the first time for each kind, at debug level after that.
+
+use std::fmt;
+use std::sync::Mutex;
+
+use nvim_rs::Value;
+
+/// Why a payload was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldError {
+    /// Expected a map (Lua table with string keys)
+    NotMap,
+    /// Expected an array (event parameters, Lua list)
+    NotArray,
+    /// Array with fewer elements than required
+    TooShort { expected: usize, got: usize },
+    /// Required field (or array element) absent
+    Missing(String),
+    /// Field (or array element) of the wrong type
+    WrongType {
+        field: String,
+        expected: &'static str,
+    },
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldError::NotMap => write!(f, "expected a map"),
+            FieldError::NotArray => write!(f, "expected an array"),
+            FieldError::TooShort { expected, got } => {
+                write!(f, "expected {expected} elements, got {got}")
+            }
+            FieldError::Missing(field) => write!(f, "missing {field}"),
+            FieldError::WrongType { field, expected } => write!(f, "{field}: expected {expected}"),
+        }
+    }
+}
+
+impl std::error::Error for FieldError {}
+
+/// Kinds already warned about (later payloads of a kind are logged at debug level)
+static WARNED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Log a payload of `kind` that was rejected with `error`
+pub fn warn_malformed(kind: &str, error: &FieldError, payload: &Value) {
+    let first = {
+        let mut warned = WARNED.lock().unwrap();
+        let first = !warned.iter().any(|k| k == kind);
+        if first {
+            warned.push(kind.to_string());
+        }
+        first
+    };
+    if first {
+        log::warn!(
+            "[NVIM] Malformed {} ({}): {:?} (repeats are logged at debug level)",
+            kind,
+            error,
+            payload
+        );
+    } else {
+        log::debug!("[NVIM] Malformed {} ({}): {:?}", kind, error, payload);
+    }
+}
+
+fn wrong_type(field: impl fmt::Display, expected: &'static str) -> FieldError {
+    FieldError::WrongType {
+        field: field.to_string(),
+        expected,
+    }
+}
+
+fn as_ranges(value: &Value) -> Option<Vec<(usize, usize)>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|item| match item.as_array()?.as_slice() {
+            [begin, end] => Some((begin.as_u64()? as usize, end.as_u64()? as usize)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn as_strings(value: &Value) -> Option<Vec<String>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|item| item.as_str().map(str::to_string))
+        .collect()
+}
+
+/// Fields of a msgpack map
+#[derive(Debug, Clone, Copy)]
+pub struct Map<'a>(&'a [(Value, Value)]);
+
+impl<'a> Map<'a> {
+    /// The map in `value`. An empty array is an empty map: Lua can't tell
+    /// `{}` apart and sends it as an array.
+    pub fn new(value: &'a Value) -> Result<Self, FieldError> {
+        match value {
+            Value::Map(pairs) => Ok(Self(pairs)),
+            Value::Array(items) if items.is_empty() => Ok(Self(&[])),
+            _ => Err(FieldError::NotMap),
+        }
+    }
+
+    /// Map from pairs already taken apart (typed nvim-rs API results)
+    pub fn from_pairs(pairs: &'a [(Value, Value)]) -> Self {
+        Self(pairs)
+    }
+
+    /// Value of `field`, None if absent or nil
+    pub fn get(&self, field: &str) -> Option<&'a Value> {
+        self.0
+            .iter()
+            .find(|(k, _)| k.as_str() == Some(field))
+            .map(|(_, v)| v)
+            .filter(|v| !v.is_nil())
+    }
+
+    fn opt<T>(
+        &self,
+        field: &str,
+        expected: &'static str,
+        convert: impl FnOnce(&'a Value) -> Option<T>,
+    ) -> Result<Option<T>, FieldError> {
+        self.get(field)
+            .map(|v| convert(v).ok_or_else(|| wrong_type(field, expected)))
+            .transpose()
+    }
+
+    fn required<T>(&self, field: &str, value: Option<T>) -> Result<T, FieldError> {
+        value.ok_or_else(|| FieldError::Missing(field.to_string()))
+    }
+
+    pub fn opt_str(&self, field: &str) -> Result<Option<&'a str>, FieldError> {
+        self.opt(field, "a string", Value::as_str)
+    }
+
+    pub fn opt_u64(&self, field: &str) -> Result<Option<u64>, FieldError> {
+        self.opt(field, "an unsigned integer", Value::as_u64)
+    }
+
+    pub fn opt_i64(&self, field: &str) -> Result<Option<i64>, FieldError> {
+        self.opt(field, "an integer", Value::as_i64)
+    }
+
+    pub fn opt_bool(&self, field: &str) -> Result<Option<bool>, FieldError> {
+        self.opt(field, "a boolean", Value::as_bool)
+    }
+
+    pub fn str(&self, field: &str) -> Result<&'a str, FieldError> {
+        let value = self.opt_str(field)?;
+        self.required(field, value)
+    }
+
+    pub fn u64(&self, field: &str) -> Result<u64, FieldError> {
+        let value = self.opt_u64(field)?;
+        self.required(field, value)
+    }
+
+    pub fn bool(&self, field: &str) -> Result<bool, FieldError> {
+        let value = self.opt_bool(field)?;
+        self.required(field, value)
+    }
+
+    /// List of strings in `field` (empty if absent)
+    pub fn strings(&self, field: &str) -> Result<Vec<String>, FieldError> {
+        Ok(self
+            .opt(field, "a list of strings", as_strings)?
+            .unwrap_or_default())
+    }
+
+    /// List of `[begin, end]` pairs in `field` (empty if absent)
+    pub fn ranges(&self, field: &str) -> Result<Vec<(usize, usize)>, FieldError> {
+        Ok(self
+            .opt(field, "a list of ranges", as_ranges)?
+            .unwrap_or_default())
+    }
+}
+
+/// Elements of a msgpack array (redraw event parameters)
+#[derive(Debug, Clone, Copy)]
+pub struct Params<'a>(&'a [Value]);
+
+impl<'a> Params<'a> {
+    /// The array in `value`, with at least `min_len` elements
+    pub fn new(value: &'a Value, min_len: usize) -> Result<Self, FieldError> {
+        let items = value.as_array().ok_or(FieldError::NotArray)?;
+        if items.len() < min_len {
+            return Err(FieldError::TooShort {
+                expected: min_len,
+                got: items.len(),
+            });
+        }
+        Ok(Self(items))
+    }
+
+    fn opt<T>(
+        &self,
+        index: usize,
+        expected: &'static str,
+        convert: impl FnOnce(&'a Value) -> Option<T>,
+    ) -> Result<Option<T>, FieldError> {
+        self.0
+            .get(index)
+            .filter(|v| !v.is_nil())
+            .map(|v| convert(v).ok_or_else(|| wrong_type(format!("#{index}"), expected)))
+            .transpose()
+    }
+
+    fn required<T>(&self, index: usize, value: Option<T>) -> Result<T, FieldError> {
+        value.ok_or_else(|| FieldError::Missing(format!("#{index}")))
+    }
+
+    pub fn opt_str(&self, index: usize) -> Result<Option<&'a str>, FieldError> {
+        self.opt(index, "a string", Value::as_str)
+    }
+
+    pub fn opt_u64(&self, index: usize) -> Result<Option<u64>, FieldError> {
+        self.opt(index, "an unsigned integer", Value::as_u64)
+    }
+
+    pub fn opt_i64(&self, index: usize) -> Result<Option<i64>, FieldError> {
+        self.opt(index, "an integer", Value::as_i64)
+    }
+
+    pub fn str(&self, index: usize) -> Result<&'a str, FieldError> {
+        let value = self.opt_str(index)?;
+        self.required(index, value)
+    }
+
+    pub fn u64(&self, index: usize) -> Result<u64, FieldError> {
+        let value = self.opt_u64(index)?;
+        self.required(index, value)
+    }
+
+    pub fn i64(&self, index: usize) -> Result<i64, FieldError> {
+        let value = self.opt_i64(index)?;
+        self.required(index, value)
+    }
+
+    pub fn array(&self, index: usize) -> Result<&'a [Value], FieldError> {
+        let value = self.opt(index, "an array", |v| v.as_array().map(Vec::as_slice))?;
+        self.required(index, value)
+    }
+
+    /// Elements from `index` on
+    pub fn rest(&self, index: usize) -> &'a [Value] {
+        self.0.get(index..).unwrap_or_default()
+    }
+}
+
+/// Text of a `[[attr_id, text, ...], ...]` chunk list (cmdline and message content)
+pub fn chunks_text(chunks: &[Value]) -> Result<String, FieldError> {
+    chunks
+        .iter()
+        .map(|chunk| Params::new(chunk, 2)?.str(1))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(pairs: &[(&str, Value)]) -> Value {
+        Value::Map(
+            pairs
+                .iter()
+                .map(|(k, v)| (Value::from(*k), v.clone()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn map_fields_absent_present_and_mistyped() {
+        let value = map(&[
+            ("type", Value::from("commit")),
+            ("count", Value::from(3)),
+            ("gone", Value::Nil),
+            ("flag", Value::from("yes")),
+        ]);
+        let fields = Map::new(&value).unwrap();
+        assert_eq!(fields.str("type"), Ok("commit"));
+        assert_eq!(fields.opt_u64("count"), Ok(Some(3)));
+        // nil and absent fields are the same
+        assert_eq!(fields.opt_str("gone"), Ok(None));
+        assert_eq!(fields.opt_str("missing"), Ok(None));
+        assert_eq!(fields.u64("gone"), Err(FieldError::Missing("gone".into())));
+        assert_eq!(
+            fields.opt_bool("flag"),
+            Err(FieldError::WrongType {
+                field: "flag".into(),
+                expected: "a boolean",
+            })
+        );
+        assert_eq!(
+            fields.opt_u64("type").unwrap_err().to_string(),
+            "type: expected an unsigned integer"
+        );
+    }
+
+    #[test]
+    fn map_rejects_non_maps_but_takes_empty_lua_tables() {
+        assert_eq!(Map::new(&Value::Nil).unwrap_err(), FieldError::NotMap);
+        assert_eq!(Map::new(&Value::from("x")).unwrap_err(), FieldError::NotMap);
+        assert_eq!(
+            Map::new(&Value::Array(vec![Value::from(1)])).unwrap_err(),
+            FieldError::NotMap
+        );
+        let empty = Value::Array(Vec::new());
+        assert_eq!(Map::new(&empty).unwrap().opt_str("type"), Ok(None));
+    }
+
+    #[test]
+    fn string_lists_must_hold_only_strings() {
+        let value = map(&[
+            (
+                "words",
+                Value::Array(vec![Value::from("a"), Value::from("b")]),
+            ),
+            (
+                "mixed",
+                Value::Array(vec![Value::from("a"), Value::from(1)]),
+            ),
+            ("scalar", Value::from("a")),
+        ]);
+        let fields = Map::new(&value).unwrap();
+        assert_eq!(fields.strings("words"), Ok(vec!["a".into(), "b".into()]));
+        assert_eq!(fields.strings("absent"), Ok(Vec::new()));
+        assert!(fields.strings("mixed").is_err());
+        assert!(fields.strings("scalar").is_err());
+    }
+
+    #[test]
+    fn ranges_are_pairs_of_offsets() {
+        let pair = |a: u64, b: u64| Value::Array(vec![Value::from(a), Value::from(b)]);
+        let value = map(&[
+            ("matches", Value::Array(vec![pair(0, 3), pair(6, 9)])),
+            (
+                "short",
+                Value::Array(vec![Value::Array(vec![Value::from(1)])]),
+            ),
+            ("empty", Value::Array(Vec::new())),
+        ]);
+        let fields = Map::new(&value).unwrap();
+        assert_eq!(fields.ranges("matches"), Ok(vec![(0, 3), (6, 9)]));
+        assert_eq!(fields.ranges("empty"), Ok(Vec::new()));
+        assert_eq!(fields.ranges("absent"), Ok(Vec::new()));
+        assert!(fields.ranges("short").is_err());
+    }
+
+    #[test]
+    fn params_check_length_and_element_types() {
+        let value = Value::Array(vec![Value::from("insert"), Value::from(-1), Value::Nil]);
+        assert_eq!(
+            Params::new(&value, 4).unwrap_err(),
+            FieldError::TooShort {
+                expected: 4,
+                got: 3,
+            }
+        );
+        assert_eq!(
+            Params::new(&Value::from(1), 0).unwrap_err(),
+            FieldError::NotArray
+        );
+        let params = Params::new(&value, 2).unwrap();
+        assert_eq!(params.str(0), Ok("insert"));
+        assert_eq!(params.i64(1), Ok(-1));
+        assert!(params.u64(1).is_err()); // negative
+        assert_eq!(params.opt_u64(2), Ok(None));
+        assert_eq!(params.opt_str(7), Ok(None));
+        assert_eq!(params.str(7), Err(FieldError::Missing("#7".into())));
+        assert_eq!(
+            params.array(0).unwrap_err().to_string(),
+            "#0: expected an array"
+        );
+    }
+
+    #[test]
+    fn chunk_text_rejects_truncated_chunks() {
+        let chunk = |text: &str| Value::Array(vec![Value::from(0), Value::from(text)]);
+        assert_eq!(
+            chunks_text(&[chunk("se"), chunk("t")]),
+            Ok("set".to_string())
+        );
+        assert!(chunks_text(&[Value::Array(vec![Value::from(0)])]).is_err());
+        assert!(chunks_text(&[Value::from("set")]).is_err());
+    }
+}
@@ -2,6 +2,7 @@
 //!
 //! Defines all messages that can be sent to/from the Neovim backend.
 
+use std::sync::Arc;
 use std::sync::atomic::{AtomicU8, Ordering};
 
 use serde::{Deserialize, Serialize};
@@ -9,7 +10,8 @@ use serde::{Deserialize, Serialize};
 /// Pending state for multi-key sequences in the Neovim handler.
 ///
 /// These states are mutually exclusive — only one can be active at a time.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[repr(u8)]
 pub enum PendingState {
     /// No pending operation
@@ -82,20 +84,143 @@ impl AtomicPendingState {
     }
 }
 
+/// How insert-mode keys are turned into committed text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum InputMode {
+    /// Keys build up preedit (including henkan) until an explicit commit
+    #[default]
+    Conversion,
+    /// Kana is committed as soon as it is settled; henkan is never started
+    KanaDirect,
+}
+
+impl InputMode {
+    /// The other mode (for toggle keybinds)
+    pub fn toggled(self) -> Self {
+        match self {
+            Self::Conversion => Self::KanaDirect,
+            Self::KanaDirect => Self::Conversion,
+        }
+    }
+}
+
+/// Wayland selection mirrored by a Neovim clipboard register
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClipboardSelection {
+    /// Regular clipboard (`+` register)
+    Clipboard,
+    /// Primary selection (`*` register)
+    Primary,
+}
+
+impl ClipboardSelection {
+    /// Neovim register name
+    pub fn register(self) -> &'static str {
+        match self {
+            Self::Clipboard => "+",
+            Self::Primary => "*",
+        }
+    }
+
+    /// Selection for a register name (None for non-clipboard registers)
+    pub fn from_register(register: &str) -> Option<Self> {
+        match register {
+            "+" => Some(Self::Clipboard),
+            "*" => Some(Self::Primary),
+            _ => None,
+        }
+    }
+}
+
+/// Which path delivers the snapshot after an insert-mode key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum SnapshotStrategy {
+    /// Wait for the autocmd push (`ime_snapshot`)
+    #[default]
+    Push,
+    /// Query `collect_snapshot()` after every key
+    Pull,
+}
+
+/// Snapshot path measurements, shown in the debug overlay
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct SnapshotStats {
+    pub strategy: SnapshotStrategy,
+    /// Smoothed key-to-push latency in milliseconds (None until measured)
+    pub push_ms: Option<f32>,
+    /// Smoothed pull round trip in milliseconds (None until measured)
+    pub pull_ms: Option<f32>,
+    /// Pushes missed among recent insert-mode keys
+    pub missed: u32,
+}
+
+impl SnapshotStats {
+    /// One-line summary for the debug overlay
+    pub fn summary(&self) -> String {
+        let ms = |v: Option<f32>| v.map_or_else(|| "-".to_string(), |v| format!("{v:.1}ms"));
+        format!(
+            "snapshot {} push {} pull {} miss {}",
+            match self.strategy {
+                SnapshotStrategy::Push => "PUSH",
+                SnapshotStrategy::Pull => "PULL",
+            },
+            ms(self.push_ms),
+            ms(self.pull_ms),
+            self.missed
+        )
+    }
+}
+
 /// Messages sent from IME to Neovim
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ToNeovim {
     /// Send a key to Neovim (raw key string like "a", "A", "<BS>", "<CR>")
     Key(String),
+    /// Switch input mode for the rest of the session
+    SetInputMode(InputMode),
+    /// Compositor selection changed: store it for the clipboard provider
+    SetClipboard {
+        selection: ClipboardSelection,
+        text: String,
+    },
+    /// Focused input's text around the cursor changed (empty when unfocused)
+    SetSurroundingText { before: String, after: String },
+    /// Select completion candidate `index` (0-based); `finish` accepts it
+    SelectCandidate { index: usize, finish: bool },
+    /// Send another part of a long candidate list (answered from the list
+    /// already received, without asking Neovim)
+    ScrollCandidates(CandidateWindow),
+    /// Commit this text from the start of the cursor line, if the line still
+    /// starts with it (punctuation commit)
+    CommitPrefix(String),
+    /// Installed SKK dictionaries changed (`jacin dict fetch`)
+    SetDictionaries(Vec<String>),
+    /// Put back the composition of a backend this one replaced: the cursor
+    /// line, the cursor byte offset, and whether it was in insert mode
+    Restore {
+        text: String,
+        cursor: usize,
+        insert: bool,
+    },
     /// Shutdown Neovim
     Shutdown,
 }
 
 /// Visual selection range from Neovim
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum VisualSelection {
     /// Character-wise visual selection with 0-indexed byte offsets (exclusive end)
     Charwise { begin: usize, end: usize },
+    /// Line-wise visual selection (`V`): the whole preedit row
+    Linewise,
+    /// Block-wise visual selection (`<C-v>`) on the cursor line: 0-indexed byte
+    /// offsets (exclusive end), plus the display cells the block extends past
+    /// the end of the line
+    Blockwise {
+        begin: usize,
+        end: usize,
+        overhang: usize,
+    },
 }
 
 /// Messages sent from Neovim to IME
@@ -113,6 +238,8 @@ pub enum FromNeovim {
     Candidates(CandidateInfo),
     /// Visual selection range (None = no visual selection)
     VisualRange(Option<VisualSelection>),
+    /// Register last recorded into changed, or its contents did
+    LastMacro(MacroRegister),
     /// Key was processed (acknowledgment for paths that send no data)
     KeyProcessed,
     /// Command-line shown (from ext_cmdline redraw event)
@@ -137,12 +264,24 @@ pub enum FromNeovim {
     ModeChange(String),
     /// Key should be passed through to the application via virtual keyboard
     PassthroughKey,
+    /// Text yanked into `+`/`*` should become the compositor selection
+    ClipboardCopy {
+        selection: ClipboardSelection,
+        text: String,
+    },
+    /// Snapshot strategy or its measurements changed
+    SnapshotStats(SnapshotStats),
+    /// The embedded Neovim's 'langmap' (sent after Ready when not configured)
+    Langmap(String),
+    /// Neovim stopped answering requests and is being killed (watchdog);
+    /// `NvimExited` follows
+    Unresponsive,
     /// Neovim process exited (e.g., :q)
     NvimExited,
 }
 
 /// Preedit information
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct PreeditInfo {
     /// The preedit text
     pub text: String,
@@ -156,15 +295,105 @@ pub struct PreeditInfo {
     pub mode: String,
     /// Currently recording macro register ("" when not recording)
     pub recording: String,
+    /// Undo sequence number of the buffer state (`changenr()`)
+    #[serde(default)]
+    pub undo_seq: u64,
+    /// Whether `.` has an insert to repeat
+    #[serde(default)]
+    pub repeatable: bool,
+    /// Conversion segment being composed (skkeleton's henkan region)
+    #[serde(default)]
+    pub segment: Option<PreeditSegment>,
+    /// Matches of the last search pattern while Neovim highlights them
+    /// (`begin..end` byte offsets)
+    #[serde(default)]
+    pub highlights: Vec<(usize, usize)>,
+}
+
+/// A macro register and its contents
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MacroRegister {
+    pub register: String,
+    /// Contents in key notation (`keytrans()`, e.g. "ihello<Esc>j")
+    pub keys: String,
+}
+
+/// Conversion segment of the preedit, from a skkeleton henkan marker (▽/▼)
+/// to the cursor. The text-input protocol has no preedit styling, so only
+/// the popup draws it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreeditSegment {
+    /// Start byte offset (at the marker); the segment ends at the cursor
+    pub begin: usize,
+    /// Reading still being typed (▽) or a conversion being chosen (▼)
+    pub kind: SegmentKind,
+}
+
+/// Stage of a conversion segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SegmentKind {
+    /// ▽: the reading to convert
+    Reading,
+    /// ▼: the selected conversion
+    Converted,
+}
+
+/// Candidates sent at a time from a longer list. A selection update then
+/// copies no more than this, however many items the completion returned.
+pub const CANDIDATE_WINDOW: usize = 64;
+
+/// Part of a long candidate list the event loop asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CandidateWindow {
+    /// `CANDIDATE_WINDOW` candidates from this index
+    From(usize),
+    /// All of them (to filter the list or add external candidates to it)
+    Whole,
+}
+
+impl CandidateWindow {
+    /// Window of a `total` long list with `index` in its middle
+    pub fn around(index: usize, total: usize) -> Self {
+        Self::From(
+            index
+                .saturating_sub(CANDIDATE_WINDOW / 2)
+                .min(total.saturating_sub(CANDIDATE_WINDOW)),
+        )
+    }
 }
 
 /// Candidate information
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CandidateInfo {
-    /// List of candidate words
-    pub candidates: Vec<String>,
+    /// List of candidate words (shared — selection updates reuse the same list)
+    pub candidates: Arc<[String]>,
+    /// Reading of each candidate (parallel to `candidates`; empty or shorter
+    /// when the completion source doesn't report one)
+    #[serde(default)]
+    pub readings: Arc<[String]>,
+    /// Annotation of each candidate (SKK `word;annotation`; parallel to
+    /// `candidates`, empty or shorter when there are none)
+    #[serde(default)]
+    pub annotations: Arc<[String]>,
+    /// Kind of each candidate, drawn in a column of its own (completion item
+    /// kind, or the nvim-cmp source; parallel to `candidates`, empty or
+    /// shorter when unknown)
+    #[serde(default)]
+    pub kinds: Arc<[String]>,
     /// Currently selected index
     pub selected: usize,
+    /// Screen cells of the text the completion replaces, ending at the
+    /// cursor (None for command-line completion or when unknown)
+    #[serde(default)]
+    pub base_width: Option<usize>,
+    /// Index of the first of `candidates` in the whole list, when they are
+    /// only a window of it (`selected` counts from there too)
+    #[serde(default)]
+    pub offset: usize,
+    /// Length of the whole list (0: `candidates` are all of it)
+    #[serde(default)]
+    pub total: usize,
 }
 
 impl PreeditInfo {
@@ -182,9 +411,32 @@ impl PreeditInfo {
             cursor_end,
             mode,
             recording,
+            undo_seq: 0,
+            repeatable: false,
+            segment: None,
+            highlights: Vec::new(),
         }
     }
 
+    /// Attach the undo depth and dot-repeat state
+    pub fn with_history(mut self, undo_seq: u64, repeatable: bool) -> Self {
+        self.undo_seq = undo_seq;
+        self.repeatable = repeatable;
+        self
+    }
+
+    /// Attach the conversion segment
+    pub fn with_segment(mut self, segment: Option<PreeditSegment>) -> Self {
+        self.segment = segment;
+        self
+    }
+
+    /// Attach the search matches
+    pub fn with_highlights(mut self, highlights: Vec<(usize, usize)>) -> Self {
+        self.highlights = highlights;
+        self
+    }
+
     /// Create empty preedit
     pub fn empty() -> Self {
         Self::default()
@@ -193,22 +445,124 @@ impl PreeditInfo {
 
 impl CandidateInfo {
     /// Create new candidate info
-    pub fn new(candidates: Vec<String>, selected: usize) -> Self {
+    pub fn new(candidates: impl Into<Arc<[String]>>, selected: usize) -> Self {
         Self {
-            candidates,
+            candidates: candidates.into(),
+            readings: Arc::default(),
+            annotations: Arc::default(),
+            kinds: Arc::default(),
             selected,
+            base_width: None,
+            offset: 0,
+            total: 0,
         }
     }
 
+    /// Attach per-candidate readings (furigana source)
+    pub fn with_readings(mut self, readings: impl Into<Arc<[String]>>) -> Self {
+        self.readings = readings.into();
+        self
+    }
+
+    /// Attach per-candidate annotations
+    pub fn with_annotations(mut self, annotations: impl Into<Arc<[String]>>) -> Self {
+        self.annotations = annotations.into();
+        self
+    }
+
+    /// Attach per-candidate kinds
+    pub fn with_kinds(mut self, kinds: impl Into<Arc<[String]>>) -> Self {
+        self.kinds = kinds.into();
+        self
+    }
+
+    /// Attach the width of the completed text
+    pub fn with_base_width(mut self, base_width: Option<usize>) -> Self {
+        self.base_width = base_width;
+        self
+    }
+
     /// Create empty candidate info
     pub fn empty() -> Self {
         Self::default()
     }
+
+    /// The candidates at `indices`, in that order, with their readings,
+    /// annotations and kinds, selecting `selected`
+    pub fn pick(&self, indices: &[usize], selected: usize) -> Self {
+        let pick = |column: &Arc<[String]>| -> Arc<[String]> {
+            if column.is_empty() {
+                return Arc::default();
+            }
+            indices
+                .iter()
+                .map(|&i| column.get(i).cloned().unwrap_or_default())
+                .collect()
+        };
+        Self {
+            candidates: pick(&self.candidates),
+            readings: pick(&self.readings),
+            annotations: pick(&self.annotations),
+            kinds: pick(&self.kinds),
+            selected,
+            base_width: self.base_width,
+            offset: 0,
+            total: 0,
+        }
+    }
+
+    /// Length of the whole list
+    pub fn total(&self) -> usize {
+        self.total.max(self.candidates.len())
+    }
+
+    /// Whether `candidates` are only a window of a longer list
+    pub fn is_window(&self) -> bool {
+        self.total > self.candidates.len()
+    }
+
+    /// What of this (whole) list to send: all of it, or `window` of it when
+    /// it is longer than `CANDIDATE_WINDOW`. The selection is kept when the
+    /// window holds it.
+    pub fn window(&self, window: CandidateWindow) -> Self {
+        let total = self.candidates.len();
+        let start = match window {
+            CandidateWindow::From(start) if total > CANDIDATE_WINDOW => {
+                start.min(total - CANDIDATE_WINDOW)
+            }
+            _ => return self.clone(),
+        };
+        let slice = |column: &Arc<[String]>| -> Arc<[String]> {
+            let end = (start + CANDIDATE_WINDOW).min(column.len());
+            column.get(start..end).map(Arc::from).unwrap_or_default()
+        };
+        Self {
+            candidates: slice(&self.candidates),
+            readings: slice(&self.readings),
+            annotations: slice(&self.annotations),
+            kinds: slice(&self.kinds),
+            selected: self
+                .selected
+                .checked_sub(start)
+                .filter(|&i| i < CANDIDATE_WINDOW)
+                .unwrap_or(0),
+            base_width: self.base_width,
+            offset: start,
+            total,
+        }
+    }
+
+    /// Whether whole-list index `index` is among `candidates`
+    pub fn holds(&self, index: usize) -> bool {
+        index
+            .checked_sub(self.offset)
+            .is_some_and(|i| i < self.candidates.len())
+    }
 }
 
 /// State snapshot from collect_snapshot() Lua function.
 /// Consolidates all state queries into a single RPC call.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct Snapshot {
     /// Current line text (preedit)
     pub preedit: String,
@@ -227,9 +581,40 @@ pub struct Snapshot {
     /// Visual selection end column (1-indexed byte offset, from Lua, exclusive)
     #[serde(default)]
     pub visual_end: Option<usize>,
+    /// Block-wise visual: display cells the block extends past the line end
+    #[serde(default)]
+    pub visual_overhang: usize,
     /// Currently recording macro register ("" when not recording)
     #[serde(default)]
     pub recording: String,
+    /// Cursor row (0-indexed, from line('.'))
+    #[serde(default)]
+    pub row: usize,
+    /// b:changedtick when the snapshot was collected
+    #[serde(default)]
+    pub tick: u64,
+    /// Undo sequence number (`changenr()`)
+    #[serde(default)]
+    pub undo_seq: u64,
+    /// Last inserted text, what `.` repeats (register `.`)
+    #[serde(default)]
+    pub last_change: String,
+    /// Henkan marker column (1-indexed byte offset, insert mode only)
+    #[serde(default)]
+    pub segment_begin: Option<usize>,
+    /// Whether the marker is ▼ (a conversion is being chosen) rather than ▽
+    #[serde(default)]
+    pub segment_converted: bool,
+    /// Register last recorded into (`reg_recorded()`, "" before any recording)
+    #[serde(default)]
+    pub recorded: String,
+    /// Its contents in key notation
+    #[serde(default)]
+    pub recorded_keys: String,
+    /// Search matches on the line, 0-indexed byte offsets (while
+    /// `v:hlsearch` is set)
+    #[serde(default)]
+    pub highlights: Vec<(usize, usize)>,
 }
 
 impl Snapshot {
@@ -249,17 +634,59 @@ impl Snapshot {
             self.mode.clone(),
             self.recording.clone(),
         )
+        .with_history(self.undo_seq, !self.last_change.is_empty())
+        .with_segment(self.segment())
+        .with_highlights(self.valid_highlights())
+    }
+
+    /// Search matches that fall on the line's character boundaries
+    fn valid_highlights(&self) -> Vec<(usize, usize)> {
+        self.highlights
+            .iter()
+            .copied()
+            .filter(|&(begin, end)| {
+                begin < end
+                    && self.preedit.is_char_boundary(begin)
+                    && self.preedit.is_char_boundary(end)
+            })
+            .collect()
+    }
+
+    /// Register last recorded into, with its keys (None before any recording)
+    pub fn last_macro(&self) -> Option<MacroRegister> {
+        (!self.recorded.is_empty()).then(|| MacroRegister {
+            register: self.recorded.clone(),
+            keys: self.recorded_keys.clone(),
+        })
+    }
+
+    /// Conversion segment: from the henkan marker to the cursor
+    fn segment(&self) -> Option<PreeditSegment> {
+        let begin = self.segment_begin?.checked_sub(1)?;
+        let kind = if self.segment_converted {
+            SegmentKind::Converted
+        } else {
+            SegmentKind::Reading
+        };
+        (begin + 1 < self.cursor_byte).then_some(PreeditSegment { begin, kind })
     }
 
     /// Convert visual fields to VisualSelection (1-indexed Lua → 0-indexed byte offsets).
+    /// The kind follows the mode (`V` line-wise, `<C-v>` block-wise).
     pub fn to_visual_selection(&self) -> Option<VisualSelection> {
-        match (self.visual_begin, self.visual_end) {
-            (Some(begin), Some(end)) => Some(VisualSelection::Charwise {
-                begin: begin.saturating_sub(1),
-                end: end.saturating_sub(1),
-            }),
-            _ => None,
-        }
+        let (Some(begin), Some(end)) = (self.visual_begin, self.visual_end) else {
+            return None;
+        };
+        let (begin, end) = (begin.saturating_sub(1), end.saturating_sub(1));
+        Some(match self.mode.as_str() {
+            "V" => VisualSelection::Linewise,
+            "\x16" => VisualSelection::Blockwise {
+                begin,
+                end,
+                overhang: self.visual_overhang,
+            },
+            _ => VisualSelection::Charwise { begin, end },
+        })
     }
 }
 
@@ -267,6 +694,22 @@ impl Snapshot {
 mod tests {
     use super::*;
 
+    #[test]
+    fn input_mode_toggles() {
+        assert_eq!(InputMode::default(), InputMode::Conversion);
+        assert_eq!(InputMode::Conversion.toggled(), InputMode::KanaDirect);
+        assert_eq!(InputMode::KanaDirect.toggled(), InputMode::Conversion);
+    }
+
+    #[test]
+    fn clipboard_selection_registers() {
+        for sel in [ClipboardSelection::Clipboard, ClipboardSelection::Primary] {
+            assert_eq!(ClipboardSelection::from_register(sel.register()), Some(sel));
+        }
+        assert_eq!(ClipboardSelection::from_register("\""), None);
+        assert_eq!(ClipboardSelection::from_register("a"), None);
+    }
+
     #[test]
     fn pending_state_classification() {
         assert!(!PendingState::None.is_pending());
@@ -313,7 +756,17 @@ mod tests {
             char_width,
             visual_begin: None,
             visual_end: None,
+            visual_overhang: 0,
             recording: String::new(),
+            row: 0,
+            tick: 0,
+            undo_seq: 0,
+            last_change: String::new(),
+            segment_begin: None,
+            segment_converted: false,
+            recorded: String::new(),
+            recorded_keys: String::new(),
+            highlights: Vec::new(),
         }
     }
 
@@ -337,6 +790,40 @@ mod tests {
         assert_eq!(info.cursor_end, 5); // Block cursor: begin + char_width
     }
 
+    #[test]
+    fn snapshot_segment_runs_from_marker_to_cursor() {
+        let mut snap = make_snapshot(10, 0, "i");
+        snap.preedit = "a▽かんb".into();
+        assert_eq!(snap.to_preedit_info().segment, None);
+        snap.segment_begin = Some(2);
+        assert_eq!(
+            snap.to_preedit_info().segment,
+            Some(PreeditSegment {
+                begin: 1,
+                kind: SegmentKind::Reading
+            })
+        );
+        snap.segment_converted = true;
+        assert_eq!(
+            snap.to_preedit_info().segment.map(|s| s.kind),
+            Some(SegmentKind::Converted)
+        );
+        // Marker at the cursor: nothing composed yet
+        snap.cursor_byte = 2;
+        assert_eq!(snap.to_preedit_info().segment, None);
+    }
+
+    #[test]
+    fn snapshot_to_preedit_history() {
+        let mut snap = make_snapshot(1, 1, "n");
+        let info = snap.to_preedit_info();
+        assert_eq!((info.undo_seq, info.repeatable), (0, false));
+        snap.undo_seq = 3;
+        snap.last_change = "かな".into();
+        let info = snap.to_preedit_info();
+        assert_eq!((info.undo_seq, info.repeatable), (3, true));
+    }
+
     #[test]
     fn snapshot_to_preedit_cursor_at_start() {
         // cursor_byte=1 (first byte) → 0-indexed = 0
@@ -351,13 +838,10 @@ mod tests {
         let mut snap = make_snapshot(3, 1, "v");
         snap.visual_begin = Some(2);
         snap.visual_end = Some(5);
-        let sel = snap.to_visual_selection().unwrap();
-        match sel {
-            VisualSelection::Charwise { begin, end } => {
-                assert_eq!(begin, 1); // 2 - 1 = 1
-                assert_eq!(end, 4); // 5 - 1 = 4
-            }
-        }
+        assert_eq!(
+            snap.to_visual_selection(),
+            Some(VisualSelection::Charwise { begin: 1, end: 4 }) // 2 - 1, 5 - 1
+        );
     }
 
     #[test]
@@ -373,13 +857,24 @@ mod tests {
         let mut snap = make_snapshot(5, 1, "V");
         snap.visual_begin = Some(1);
         snap.visual_end = Some(6);
-        let sel = snap.to_visual_selection().unwrap();
-        match sel {
-            VisualSelection::Charwise { begin, end } => {
-                assert_eq!(begin, 0); // 1 - 1 = 0 (start of line)
-                assert_eq!(end, 5); // 6 - 1 = 5 (end of "hello")
-            }
-        }
+        assert_eq!(snap.to_visual_selection(), Some(VisualSelection::Linewise));
+    }
+
+    #[test]
+    fn snapshot_blockwise_visual_columns() {
+        // <C-v> over "ell" of "hello", extending 2 cells past the line end
+        let mut snap = make_snapshot(2, 1, "\x16");
+        snap.visual_begin = Some(2);
+        snap.visual_end = Some(6);
+        snap.visual_overhang = 2;
+        assert_eq!(
+            snap.to_visual_selection(),
+            Some(VisualSelection::Blockwise {
+                begin: 1,
+                end: 5,
+                overhang: 2,
+            })
+        );
     }
 
     // --- Serde roundtrip tests ---
@@ -459,6 +954,38 @@ mod tests {
         }
     }
 
+    #[test]
+    fn long_lists_are_sent_a_window_at_a_time() {
+        let words: Vec<String> = (0..200).map(|i| format!("w{i}")).collect();
+        let kinds: Vec<String> = (0..100).map(|i| format!("k{i}")).collect();
+        let info = CandidateInfo::new(words, 150).with_kinds(kinds);
+
+        let window = info.window(CandidateWindow::around(150, 200));
+        assert_eq!(window.offset, 150 - CANDIDATE_WINDOW / 2);
+        assert_eq!(window.candidates.len(), CANDIDATE_WINDOW);
+        assert_eq!(window.candidates[window.selected], "w150");
+        assert!(window.kinds.is_empty());
+        assert_eq!((window.total(), window.is_window()), (200, true));
+        assert!(window.holds(150) && !window.holds(100));
+
+        // Near the end the window ends with the list
+        let last = info.window(CandidateWindow::around(199, 200));
+        assert_eq!(last.offset, 200 - CANDIDATE_WINDOW);
+        assert_eq!(last.candidates.last().unwrap(), "w199");
+        assert_eq!(info.window(CandidateWindow::From(500)).offset, last.offset);
+
+        // A selection outside the window isn't carried over
+        assert_eq!(info.window(CandidateWindow::From(0)).selected, 0);
+
+        let whole = info.window(CandidateWindow::Whole);
+        assert_eq!((whole.candidates.len(), whole.is_window()), (200, false));
+
+        // Short lists always go whole
+        let short = CandidateInfo::new(vec!["漢字".to_string(), "感じ".to_string()], 1);
+        let sent = short.window(CandidateWindow::From(1));
+        assert_eq!((sent.offset, sent.selected, sent.total()), (0, 1, 2));
+    }
+
     #[test]
     fn from_neovim_visual_range_some_roundtrip() {
         let msg = FromNeovim::VisualRange(Some(VisualSelection::Charwise { begin: 2, end: 8 }));
@@ -485,6 +1012,7 @@ mod tests {
         for msg in [
             FromNeovim::KeyProcessed,
             FromNeovim::PassthroughKey,
+            FromNeovim::Unresponsive,
             FromNeovim::NvimExited,
             FromNeovim::CmdlineShow {
                 content: "s/foo/bar/g".into(),
@@ -504,6 +1032,10 @@ mod tests {
                 cmdtype: ":".into(),
             },
             FromNeovim::AutoCommit("自動確定".into()),
+            FromNeovim::ClipboardCopy {
+                selection: ClipboardSelection::Primary,
+                text: "コピー".into(),
+            },
         ] {
             let json = serde_json::to_string(&msg).unwrap();
             let rt: FromNeovim = serde_json::from_str(&json).unwrap();
@@ -522,6 +1054,20 @@ mod tests {
             _ => panic!("expected Key"),
         }
 
+        let select = ToNeovim::SelectCandidate {
+            index: 3,
+            finish: true,
+        };
+        let json = serde_json::to_string(&select).unwrap();
+        let rt: ToNeovim = serde_json::from_str(&json).unwrap();
+        assert!(matches!(
+            rt,
+            ToNeovim::SelectCandidate {
+                index: 3,
+                finish: true
+            }
+        ));
+
         let shutdown = ToNeovim::Shutdown;
         let json = serde_json::to_string(&shutdown).unwrap();
         let rt: ToNeovim = serde_json::from_str(&json).unwrap();
@@ -567,6 +1113,33 @@ mod tests {
         assert_eq!(snap.recording, "q");
     }
 
+    #[test]
+    fn snapshot_keeps_search_matches_on_character_boundaries() {
+        let snap = Snapshot {
+            preedit: "かなかな".into(),
+            highlights: vec![(0, 6), (6, 12), (1, 6), (12, 15), (3, 3)],
+            ..make_snapshot(1, 3, "n")
+        };
+        assert_eq!(snap.to_preedit_info().highlights, [(0, 6), (6, 12)]);
+    }
+
+    #[test]
+    fn snapshot_reports_the_last_recorded_macro() {
+        assert_eq!(make_snapshot(1, 0, "n").last_macro(), None);
+        let snap = Snapshot {
+            recorded: "q".into(),
+            recorded_keys: "A!<Esc>j".into(),
+            ..make_snapshot(1, 0, "n")
+        };
+        assert_eq!(
+            snap.last_macro(),
+            Some(MacroRegister {
+                register: "q".into(),
+                keys: "A!<Esc>j".into(),
+            })
+        );
+    }
+
     #[test]
     fn snapshot_cursor_byte_zero_saturates() {
         // cursor_byte=0 should not underflow
@@ -592,4 +1165,15 @@ mod tests {
         assert!(info.candidates.is_empty());
         assert_eq!(info.selected, 0);
     }
+
+    #[test]
+    fn snapshot_stats_summary() {
+        let stats = SnapshotStats {
+            strategy: SnapshotStrategy::Pull,
+            push_ms: None,
+            pull_ms: Some(3.24),
+            missed: 4,
+        };
+        assert_eq!(stats.summary(), "snapshot PULL push - pull 3.2ms miss 4");
+    }
 }
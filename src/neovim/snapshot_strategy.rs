@@ -0,0 +1,267 @@
+//! Adaptive choice between push and pull snapshots for insert-mode keys
+//!
+//! Insert-mode keys normally rely on the TextChangedI/CursorMovedI autocmds to
+//! push a snapshot (`ime_snapshot`). Some plugins suppress or delay those
+//! autocmds, leaving the preedit stale until the next key. `AdaptiveSnapshot`
+//! tracks how often pushes go missing and how long each path takes, and
+//! switches insert-mode keys to pulling (`collect_snapshot()` per key) when
+//! pushes are unreliable, probing until they recover.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+
+use super::protocol::{Snapshot, SnapshotStats, SnapshotStrategy};
+
+/// Number of recent insert-mode keys considered for the miss rate
+const WINDOW: u32 = 16;
+/// Misses within the window that switch push → pull
+const MISS_THRESHOLD: u32 = 3;
+/// Consecutive observed pushes that switch pull → push
+const RECOVER_STREAK: u32 = 8;
+/// Weight of the newest sample in the latency averages
+const EMA_ALPHA: f32 = 0.2;
+/// Bounds for how long a push-mode key waits for its snapshot
+const MIN_PUSH_WAIT: Duration = Duration::from_millis(20);
+const MAX_PUSH_WAIT: Duration = Duration::from_millis(100);
+const DEFAULT_PUSH_WAIT: Duration = Duration::from_millis(50);
+
+/// Push snapshots seen by the notification handler, shared with the key loop
+#[derive(Default)]
+pub struct PushTracker {
+    seq: AtomicU64,
+    /// Arrival time of the latest push
+    last_at: Mutex<Option<Instant>>,
+    /// Latest known buffer state (from either path), to tell a missing push
+    /// from a key that changed nothing
+    last_state: Mutex<Option<Snapshot>>,
+    notify: Notify,
+}
+
+impl PushTracker {
+    /// Record a push snapshot (called from the notification handler)
+    pub fn record_push(&self, snapshot: &Snapshot) {
+        *self.last_at.lock().unwrap() = Some(Instant::now());
+        *self.last_state.lock().unwrap() = Some(snapshot.clone());
+        self.seq.fetch_add(1, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    /// Record a pulled snapshot as the latest known state
+    pub fn record_pull(&self, snapshot: &Snapshot) {
+        *self.last_state.lock().unwrap() = Some(snapshot.clone());
+    }
+
+    /// Number of pushes seen so far
+    pub fn seq(&self) -> u64 {
+        self.seq.load(Ordering::SeqCst)
+    }
+
+    /// Arrival time of the latest push if one arrived after `since`
+    pub fn arrived_since(&self, since: u64) -> Option<Instant> {
+        if self.seq() > since {
+            *self.last_at.lock().unwrap()
+        } else {
+            None
+        }
+    }
+
+    /// Wait up to `timeout` for a push after `since`; returns its arrival time
+    pub async fn wait_since(&self, since: u64, timeout: Duration) -> Option<Instant> {
+        let wait = async {
+            loop {
+                // Register before checking so a push in between isn't lost
+                let notified = self.notify.notified();
+                if let Some(at) = self.arrived_since(since) {
+                    return at;
+                }
+                notified.await;
+            }
+        };
+        tokio::time::timeout(timeout, wait).await.ok()
+    }
+
+    /// Whether `snapshot` matches the latest known state (nothing to push)
+    pub fn is_unchanged(&self, snapshot: &Snapshot) -> bool {
+        self.last_state.lock().unwrap().as_ref() == Some(snapshot)
+    }
+}
+
+/// Strategy selection from observed push reliability and latencies
+#[derive(Debug)]
+pub struct AdaptiveSnapshot {
+    strategy: SnapshotStrategy,
+    /// One bit per recent insert-mode key, newest in bit 0 (1 = push missed)
+    history: u16,
+    /// Consecutive observed pushes
+    streak: u32,
+    push_ms: Option<f32>,
+    pull_ms: Option<f32>,
+}
+
+impl Default for AdaptiveSnapshot {
+    fn default() -> Self {
+        Self {
+            strategy: SnapshotStrategy::Push,
+            history: 0,
+            streak: 0,
+            push_ms: None,
+            pull_ms: None,
+        }
+    }
+}
+
+fn ema(avg: Option<f32>, sample: Duration) -> Option<f32> {
+    let ms = sample.as_secs_f32() * 1000.0;
+    Some(avg.map_or(ms, |avg| avg + EMA_ALPHA * (ms - avg)))
+}
+
+impl AdaptiveSnapshot {
+    pub fn strategy(&self) -> SnapshotStrategy {
+        self.strategy
+    }
+
+    /// Pushes missed among the last `WINDOW` insert-mode keys
+    pub fn missed(&self) -> u32 {
+        (u32::from(self.history) & ((1 << WINDOW) - 1)).count_ones()
+    }
+
+    /// How long a push-mode key waits before falling back to a pull
+    pub fn push_wait(&self) -> Duration {
+        self.push_ms.map_or(DEFAULT_PUSH_WAIT, |ms| {
+            Duration::from_secs_f32(ms * 4.0 / 1000.0).clamp(MIN_PUSH_WAIT, MAX_PUSH_WAIT)
+        })
+    }
+
+    /// A push arrived `latency` after its key. Returns true if the strategy changed.
+    pub fn record_push(&mut self, latency: Duration) -> bool {
+        self.push_ms = ema(self.push_ms, latency);
+        self.history <<= 1;
+        self.streak += 1;
+        if self.strategy == SnapshotStrategy::Pull && self.streak >= RECOVER_STREAK {
+            self.switch(SnapshotStrategy::Push);
+            return true;
+        }
+        false
+    }
+
+    /// A key changed the buffer but no push arrived. Returns true if the strategy changed.
+    pub fn record_missed_push(&mut self) -> bool {
+        self.history = (self.history << 1) | 1;
+        self.streak = 0;
+        if self.strategy == SnapshotStrategy::Push && self.missed() >= MISS_THRESHOLD {
+            self.switch(SnapshotStrategy::Pull);
+            return true;
+        }
+        false
+    }
+
+    /// A pull round trip took `latency`
+    pub fn record_pull(&mut self, latency: Duration) {
+        self.pull_ms = ema(self.pull_ms, latency);
+    }
+
+    fn switch(&mut self, strategy: SnapshotStrategy) {
+        log::info!(
+            "[NVIM] Snapshot strategy {:?} -> {:?} (missed {}/{}, push {:?}ms, pull {:?}ms)",
+            self.strategy,
+            strategy,
+            self.missed(),
+            WINDOW,
+            self.push_ms,
+            self.pull_ms
+        );
+        self.strategy = strategy;
+        self.history = 0;
+        self.streak = 0;
+    }
+
+    pub fn stats(&self) -> SnapshotStats {
+        SnapshotStats {
+            strategy: self.strategy,
+            push_ms: self.push_ms,
+            pull_ms: self.pull_ms,
+            missed: self.missed(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn starts_with_push() {
+        let adaptive = AdaptiveSnapshot::default();
+        assert_eq!(adaptive.strategy(), SnapshotStrategy::Push);
+        assert_eq!(adaptive.push_wait(), DEFAULT_PUSH_WAIT);
+    }
+
+    #[test]
+    fn repeated_misses_switch_to_pull() {
+        let mut adaptive = AdaptiveSnapshot::default();
+        assert!(!adaptive.record_missed_push());
+        adaptive.record_push(2 * MS);
+        assert!(!adaptive.record_missed_push());
+        assert!(adaptive.record_missed_push());
+        assert_eq!(adaptive.strategy(), SnapshotStrategy::Pull);
+    }
+
+    #[test]
+    fn misses_age_out_of_window() {
+        let mut adaptive = AdaptiveSnapshot::default();
+        adaptive.record_missed_push();
+        adaptive.record_missed_push();
+        for _ in 0..WINDOW {
+            adaptive.record_push(2 * MS);
+        }
+        assert_eq!(adaptive.missed(), 0);
+        assert!(!adaptive.record_missed_push());
+        assert_eq!(adaptive.strategy(), SnapshotStrategy::Push);
+    }
+
+    #[test]
+    fn reliable_pushes_recover_from_pull() {
+        let mut adaptive = AdaptiveSnapshot::default();
+        for _ in 0..MISS_THRESHOLD {
+            adaptive.record_missed_push();
+        }
+        assert_eq!(adaptive.strategy(), SnapshotStrategy::Pull);
+        for _ in 0..RECOVER_STREAK - 1 {
+            assert!(!adaptive.record_push(2 * MS));
+        }
+        adaptive.record_missed_push();
+        for _ in 0..RECOVER_STREAK - 1 {
+            adaptive.record_push(2 * MS);
+        }
+        assert!(adaptive.record_push(2 * MS));
+        assert_eq!(adaptive.strategy(), SnapshotStrategy::Push);
+        assert_eq!(adaptive.missed(), 0);
+    }
+
+    #[test]
+    fn push_wait_follows_latency() {
+        let mut adaptive = AdaptiveSnapshot::default();
+        adaptive.record_push(MS);
+        assert_eq!(adaptive.push_wait(), MIN_PUSH_WAIT);
+        for _ in 0..50 {
+            adaptive.record_push(80 * MS);
+        }
+        assert_eq!(adaptive.push_wait(), MAX_PUSH_WAIT);
+    }
+
+    #[test]
+    fn stats_report_averages() {
+        let mut adaptive = AdaptiveSnapshot::default();
+        adaptive.record_pull(10 * MS);
+        adaptive.record_pull(20 * MS);
+        let stats = adaptive.stats();
+        assert_eq!(stats.strategy, SnapshotStrategy::Push);
+        assert_eq!(stats.push_ms, None);
+        assert!((stats.pull_ms.unwrap() - 12.0).abs() < 0.01);
+    }
+}
@@ -0,0 +1,154 @@
+//! Desktop notifications (org.freedesktop.Notifications on the session bus)
+//!
+//! Opt-in per kind of event through `[notifications]`. Each notification is
+//! sent from its own thread: connecting to the bus can take a while, and
+//! without a notification daemon there is only a log line to show for it.
+//! Enabled/disabled notifications replace each other instead of piling up.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::config::NotificationsConfig;
+
+/// Id of the last IME state notification (0 = none yet), replaced by the next
+static LAST_STATE_ID: AtomicU32 = AtomicU32::new(0);
+
+/// Something worth a notification
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    ImeEnabled,
+    ImeDisabled,
+    /// The backend exited; `respawning` if the standby took over
+    BackendExited {
+        respawning: bool,
+    },
+    BackendRespawned,
+    /// The backend hung and was replaced (watchdog)
+    BackendUnresponsive,
+    /// Fetched dictionaries handed to the backend
+    DictionariesLoaded(usize),
+}
+
+impl Event {
+    fn wanted(&self, config: &NotificationsConfig) -> bool {
+        match self {
+            Event::ImeEnabled | Event::ImeDisabled => config.ime_state,
+            Event::BackendExited { .. } | Event::BackendRespawned | Event::BackendUnresponsive => {
+                config.backend
+            }
+            Event::DictionariesLoaded(_) => config.dictionary,
+        }
+    }
+
+    /// Summary and body
+    fn text(&self) -> (&'static str, String) {
+        match self {
+            Event::ImeEnabled => ("IME enabled", String::new()),
+            Event::ImeDisabled => ("IME disabled", String::new()),
+            Event::BackendExited { respawning: true } => {
+                ("Neovim exited", "The standby Neovim took over.".to_string())
+            }
+            Event::BackendExited { respawning: false } => (
+                "Neovim exited",
+                "It is started again when the IME is next enabled.".to_string(),
+            ),
+            Event::BackendRespawned => ("Neovim respawned", String::new()),
+            Event::BackendUnresponsive => (
+                "Neovim stopped responding",
+                "It was restarted with the text being typed.".to_string(),
+            ),
+            Event::DictionariesLoaded(count) => (
+                "Dictionaries loaded",
+                format!("{count} dictionaries handed to Neovim."),
+            ),
+        }
+    }
+
+    fn is_state(&self) -> bool {
+        matches!(self, Event::ImeEnabled | Event::ImeDisabled)
+    }
+}
+
+/// Notify `event` if `[notifications]` asks for it
+pub fn send(config: &NotificationsConfig, event: Event) {
+    if !event.wanted(config) {
+        return;
+    }
+    let (summary, body) = event.text();
+    let replaces = event.is_state();
+    std::thread::spawn(move || {
+        let replaces_id = if replaces {
+            LAST_STATE_ID.load(Ordering::Relaxed)
+        } else {
+            0
+        };
+        match notify(summary, &body, replaces_id) {
+            Ok(id) if replaces => LAST_STATE_ID.store(id, Ordering::Relaxed),
+            Ok(_) => {}
+            Err(e) => log::debug!("[NOTIFY] {:?} not sent: {}", summary, e),
+        }
+    });
+}
+
+/// Show a notification regardless of `[notifications]` (asked for explicitly,
+/// e.g. by `--replace`)
+pub fn show(summary: &'static str, body: String) {
+    std::thread::spawn(move || {
+        if let Err(e) = notify(summary, &body, 0) {
+            log::warn!("[NOTIFY] {:?} not sent: {}", summary, e);
+        }
+    });
+}
+
+/// org.freedesktop.Notifications.Notify; returns the notification id
+fn notify(summary: &str, body: &str, replaces_id: u32) -> zbus::Result<u32> {
+    let connection = zbus::blocking::Connection::session()?;
+    let hints: HashMap<&str, zbus::zvariant::Value> = HashMap::new();
+    let reply = connection.call_method(
+        Some("org.freedesktop.Notifications"),
+        "/org/freedesktop/Notifications",
+        Some("org.freedesktop.Notifications"),
+        "Notify",
+        &(
+            "jacin",
+            replaces_id,
+            "input-keyboard",
+            summary,
+            body,
+            Vec::<&str>::new(),
+            hints,
+            -1i32,
+        ),
+    )?;
+    reply.body().deserialize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_follow_their_config_switch() {
+        let config = NotificationsConfig {
+            ime_state: true,
+            backend: false,
+            dictionary: true,
+        };
+        assert!(Event::ImeDisabled.wanted(&config));
+        assert!(!Event::BackendExited { respawning: true }.wanted(&config));
+        assert!(!Event::BackendRespawned.wanted(&config));
+        assert!(!Event::BackendUnresponsive.wanted(&config));
+        assert!(Event::DictionariesLoaded(1).wanted(&config));
+        assert!(!Event::ImeEnabled.wanted(&NotificationsConfig::default()));
+    }
+
+    #[test]
+    fn only_state_notifications_replace_each_other() {
+        assert!(Event::ImeEnabled.is_state());
+        assert!(!Event::BackendRespawned.is_state());
+        assert_eq!(
+            Event::DictionariesLoaded(2).text().1,
+            "2 dictionaries handed to Neovim."
+        );
+    }
+}
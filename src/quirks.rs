@@ -0,0 +1,130 @@
+//! Per-compositor workarounds
+//!
+//! Compositors differ in details the protocols leave open. `Quirks::detect`
+//! identifies the running compositor from its environment and picks a strategy
+//! for each known difference; unknown compositors get the conservative choice.
+
+/// Compositor jacin runs under (best effort, from environment variables)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compositor {
+    Hyprland,
+    Sway,
+    Niri,
+    Kwin,
+    /// Mutter; has no input method protocol (see `unsupported`)
+    Gnome,
+    Other,
+}
+
+impl Compositor {
+    fn from_env(var: impl Fn(&str) -> Option<String>) -> Self {
+        let set = |name: &str| var(name).is_some_and(|v| !v.is_empty());
+        let desktop = |name: &str| {
+            var("XDG_CURRENT_DESKTOP")
+                .is_some_and(|d| d.split(':').any(|d| d.eq_ignore_ascii_case(name)))
+        };
+        if set("HYPRLAND_INSTANCE_SIGNATURE") {
+            Compositor::Hyprland
+        } else if set("SWAYSOCK") {
+            Compositor::Sway
+        } else if set("NIRI_SOCKET") {
+            Compositor::Niri
+        } else if desktop("KDE") {
+            Compositor::Kwin
+        } else if desktop("GNOME") {
+            Compositor::Gnome
+        } else {
+            Compositor::Other
+        }
+    }
+}
+
+/// How the popup is hidden
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PopupHide {
+    /// Attach a null buffer and keep the surface and popup role for the next show
+    Unmap,
+    /// Destroy both and recreate them on the next show. Slower, but needed where
+    /// an unmapped popup surface keeps absorbing pointer clicks.
+    Destroy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    pub compositor: Compositor,
+    pub popup_hide: PopupHide,
+}
+
+impl Quirks {
+    /// Quirks for the compositor named by the environment
+    pub fn detect() -> Self {
+        Self::for_compositor(Compositor::from_env(|name| std::env::var(name).ok()))
+    }
+
+    pub fn for_compositor(compositor: Compositor) -> Self {
+        let popup_hide = match compositor {
+            Compositor::Sway | Compositor::Niri | Compositor::Kwin => PopupHide::Unmap,
+            Compositor::Hyprland | Compositor::Gnome | Compositor::Other => PopupHide::Destroy,
+        };
+        Self {
+            compositor,
+            popup_hide,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(vars: &[(&str, &str)]) -> Compositor {
+        Compositor::from_env(|name| {
+            vars.iter()
+                .find(|(k, _)| *k == name)
+                .map(|(_, v)| v.to_string())
+        })
+    }
+
+    #[test]
+    fn compositor_from_env() {
+        assert_eq!(
+            detect(&[("HYPRLAND_INSTANCE_SIGNATURE", "abc")]),
+            Compositor::Hyprland
+        );
+        assert_eq!(
+            detect(&[("SWAYSOCK", "/run/user/1000/sway-ipc.sock")]),
+            Compositor::Sway
+        );
+        assert_eq!(detect(&[("NIRI_SOCKET", "/tmp/niri")]), Compositor::Niri);
+        assert_eq!(
+            detect(&[("XDG_CURRENT_DESKTOP", "ubuntu:KDE")]),
+            Compositor::Kwin
+        );
+        assert_eq!(
+            detect(&[("XDG_CURRENT_DESKTOP", "ubuntu:GNOME")]),
+            Compositor::Gnome
+        );
+        assert_eq!(
+            detect(&[("XDG_CURRENT_DESKTOP", "XFCE")]),
+            Compositor::Other
+        );
+        assert_eq!(detect(&[("SWAYSOCK", "")]), Compositor::Other);
+        assert_eq!(detect(&[]), Compositor::Other);
+    }
+
+    #[test]
+    fn unknown_compositors_destroy_the_popup() {
+        assert_eq!(
+            Quirks::for_compositor(Compositor::Other).popup_hide,
+            PopupHide::Destroy
+        );
+        assert_eq!(
+            Quirks::for_compositor(Compositor::Hyprland).popup_hide,
+            PopupHide::Destroy
+        );
+        assert_eq!(
+            Quirks::for_compositor(Compositor::Sway).popup_hide,
+            PopupHide::Unmap
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! Recovery from a Wayland protocol error
+//!
+//! A protocol error is fatal to the whole connection: the compositor
+//! disconnects the client, and with it the input method and any preedit it
+//! showed. Most errors a compositor raises are against popup objects (surface
+//! sizes, buffers, layer-shell state), so for those jacin restarts itself on a
+//! fresh connection, bringing the text being composed along: the new process
+//! gets it back into Neovim and the application's preedit when its input
+//! method is next activated. Errors on anything else (the input method itself)
+//! end the session; the text that could no longer be committed is shown in a
+//! desktop notification rather than silently lost.
+
+use std::ffi::OsString;
+use std::os::unix::process::CommandExt;
+
+use wayland_client::Connection;
+use wayland_client::backend::protocol::ProtocolError;
+
+use crate::notify;
+
+/// Restarts per launch (counted across re-execs) before giving up
+const MAX_RESTARTS: u32 = 3;
+
+/// Restarts so far, handed to the re-executed process
+const RESTARTS_ENV: &str = "JACIN_RESTARTS";
+
+/// Text being composed when the connection was lost
+const PREEDIT_ENV: &str = "JACIN_RESTORE_PREEDIT";
+
+/// Interfaces of the objects behind the candidate popup
+const POPUP_INTERFACES: [&str; 7] = [
+    "wl_surface",
+    "wl_buffer",
+    "wl_shm",
+    "wl_shm_pool",
+    "zwp_input_popup_surface_v2",
+    "zwlr_layer_shell_v1",
+    "zwlr_layer_surface_v1",
+];
+
+/// Whether a protocol error was raised against the popup (a restart recovers)
+fn on_popup(error: &ProtocolError) -> bool {
+    POPUP_INTERFACES.contains(&error.object_interface.as_str())
+}
+
+fn restarts_so_far(value: Option<OsString>) -> u32 {
+    value.and_then(|v| v.to_str()?.parse().ok()).unwrap_or(0)
+}
+
+/// Text composed before a restart, to bring back once activated (read once
+/// at startup)
+pub fn take_restored_preedit() -> Option<String> {
+    let text = std::env::var(PREEDIT_ENV).ok().filter(|t| !t.is_empty());
+    // SAFETY: called at startup, before any other thread is spawned
+    unsafe { std::env::remove_var(PREEDIT_ENV) };
+    text
+}
+
+/// The event loop ended with `error`: the connection is gone. Restarts jacin
+/// for a popup protocol error (does not return then); otherwise reports the
+/// text that was being composed and returns the error to exit with.
+/// Backends must already be shut down.
+pub fn after_disconnect(
+    conn: &Connection,
+    error: calloop::Error,
+    preedit: &str,
+    privacy: bool,
+) -> anyhow::Error {
+    let protocol_error = conn.protocol_error();
+    if let Some(ref e) = protocol_error {
+        log::error!("[WAYLAND] {}", e);
+    }
+    if protocol_error.as_ref().is_some_and(on_popup) {
+        let restarts = restarts_so_far(std::env::var_os(RESTARTS_ENV));
+        if restarts < MAX_RESTARTS {
+            log::warn!("[WAYLAND] Popup protocol error, restarting jacin");
+            let err = restart(restarts + 1, preedit);
+            log::error!("[WAYLAND] Restart failed: {}", err);
+        } else {
+            log::error!(
+                "[WAYLAND] Popup protocol error again after {} restarts",
+                restarts
+            );
+        }
+    }
+    if !preedit.is_empty() {
+        notify::show(
+            "jacin lost the Wayland connection",
+            if privacy {
+                "The text being typed could not be committed.".to_string()
+            } else {
+                format!("Text not committed: {preedit}")
+            },
+        );
+        // The notification is sent from its own thread
+        std::thread::sleep(std::time::Duration::from_millis(500));
+    }
+    match protocol_error {
+        Some(e) => anyhow::Error::new(e).context("Wayland protocol error"),
+        None => anyhow::Error::new(error).context("Event loop failed"),
+    }
+}
+
+/// Replace this process with a fresh jacin (same arguments). Returns only if
+/// that failed.
+fn restart(restarts: u32, preedit: &str) -> std::io::Error {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => return e,
+    };
+    let mut command = std::process::Command::new(exe);
+    command
+        .args(std::env::args_os().skip(1))
+        .env(RESTARTS_ENV, restarts.to_string());
+    if !preedit.is_empty() {
+        command.env(PREEDIT_ENV, preedit);
+    }
+    command.exec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_on(interface: &str) -> ProtocolError {
+        ProtocolError {
+            code: 0,
+            object_id: 42,
+            object_interface: interface.to_string(),
+            message: "invalid size".to_string(),
+        }
+    }
+
+    #[test]
+    fn only_popup_objects_are_recoverable() {
+        assert!(on_popup(&error_on("zwp_input_popup_surface_v2")));
+        assert!(on_popup(&error_on("wl_buffer")));
+        assert!(on_popup(&error_on("zwlr_layer_surface_v1")));
+        assert!(!on_popup(&error_on("zwp_input_method_v2")));
+        assert!(!on_popup(&error_on("zwp_virtual_keyboard_v1")));
+    }
+
+    #[test]
+    fn restart_count_defaults_to_zero() {
+        assert_eq!(restarts_so_far(None), 0);
+        assert_eq!(restarts_so_far(Some("2".into())), 2);
+        assert_eq!(restarts_so_far(Some("x".into())), 0);
+    }
+}
@@ -0,0 +1,650 @@
+//! Versioned trace schema for record/replay fixtures (`tests/fixtures`)
+//!
+//! A trace is a header (schema version, description) plus an ordered list of
+//! events: keys the user pressed, messages from the Neovim backend, input
+//! method activation changes and clock advances. Each event is a JSON object
+//! tagged by `"type"`. Traces written by older schema versions are upgraded on
+//! load, so fixtures stay replayable as the protocol evolves.
+//!
+//! ```json
+//! { "schema_version": 3, "description": "...", "events": [
+//!     { "type": "key", "key": "a" },
+//!     { "type": "nvim", "message": { "Preedit": { "text": "あ", ... } } },
+//!     { "type": "deactivate" },
+//!     { "type": "tick", "ms": 5000 }
+//! ] }
+//! ```
+//!
+//! `jacin --record <file>` writes a session as a transcript: the header on the
+//! first line, then one event per line with the milliseconds since recording
+//! started (`{"ms": 1520, "type": "key", "key": "a"}`). `jacin --replay <file>`
+//! feeds a transcript or fixture to a [`Replayer`], a headless mirror of how
+//! the coordinator reacts to each event, and prints the resulting state, so a
+//! transcript attached to a bug report reproduces the session without the
+//! reporter's compositor. The gaps between transcript events become `tick`s.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::State;
+use crate::config::{Config, DeactivateClear, OnFocusLoss};
+use crate::neovim::{FromNeovim, VisualSelection};
+use crate::state::{BufferClearState, ImeState, KeypressState, VimMode};
+
+/// Current schema version
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// One recorded event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TraceEvent {
+    /// Key pressed by the user (Vim notation, as sent to the backend)
+    Key { key: String },
+    /// Message from the Neovim backend
+    Nvim { message: FromNeovim },
+    /// zwp_input_method_v2 activate (applied on done)
+    Activate,
+    /// zwp_input_method_v2 deactivate (applied on done)
+    Deactivate,
+    /// Keyboard grab modifier state
+    Modifiers {
+        depressed: u32,
+        latched: u32,
+        locked: u32,
+        group: u32,
+    },
+    /// IME turned on (toggle keybind, signal, auto_enable_on_activate)
+    Enable,
+    /// IME turned off
+    Disable,
+    /// Clock advanced by `ms` (timers due by then fire)
+    Tick { ms: u64 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trace {
+    pub schema_version: u32,
+    #[serde(default)]
+    pub description: String,
+    pub events: Vec<TraceEvent>,
+}
+
+impl Trace {
+    /// Parse a trace of any known schema version, upgrading it to the current one.
+    /// Unknown top-level fields (e.g. a fixture's expectations) are ignored.
+    pub fn from_value(value: Value) -> Result<Self, String> {
+        let upgraded = upgrade(value)?;
+        serde_json::from_value(upgraded).map_err(|e| e.to_string())
+    }
+
+    /// Parse a `--record` transcript (JSON lines, see the module docs)
+    pub fn from_transcript(text: &str) -> Result<Self, String> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+        let (_, header) = lines.next().ok_or("empty transcript")?;
+        let mut header: Value = serde_json::from_str(header).map_err(|e| format!("line 1: {e}"))?;
+        if let Some(object) = header.as_object_mut() {
+            object.insert("events".to_string(), Value::Array(Vec::new()));
+        }
+        let mut trace = Self::from_value(header)?;
+
+        let mut last_ms = 0;
+        for (index, line) in lines {
+            let line_error = |e: String| format!("line {}: {}", index + 1, e);
+            let mut value: Value =
+                serde_json::from_str(line).map_err(|e| line_error(e.to_string()))?;
+            let ms = value
+                .as_object_mut()
+                .and_then(|object| object.remove("ms"))
+                .and_then(|ms| ms.as_u64())
+                .ok_or_else(|| line_error("missing ms".to_string()))?;
+            let event = serde_json::from_value(value).map_err(|e| line_error(e.to_string()))?;
+            if ms > last_ms {
+                trace.events.push(TraceEvent::Tick { ms: ms - last_ms });
+                last_ms = ms;
+            }
+            trace.events.push(event);
+        }
+        Ok(trace)
+    }
+
+    /// Load a fixture (one JSON document) or a transcript
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        match serde_json::from_str::<Value>(&text) {
+            Ok(value) if value.get("events").is_some() || value.get("messages").is_some() => {
+                Self::from_value(value)
+            }
+            _ => Self::from_transcript(&text),
+        }
+    }
+}
+
+/// Writes a transcript of the running session (`--record`)
+pub struct Recorder {
+    out: BufWriter<File>,
+    start: Instant,
+}
+
+impl Recorder {
+    /// Start a transcript at `path`, replacing any file there
+    pub fn create(path: &Path, description: &str) -> std::io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let header = serde_json::json!({
+            "schema_version": SCHEMA_VERSION,
+            "description": description,
+        });
+        writeln!(out, "{header}")?;
+        out.flush()?;
+        Ok(Self {
+            out,
+            start: Instant::now(),
+        })
+    }
+
+    /// Append `event`, flushed at once so a crash keeps everything before it
+    pub fn record(&mut self, event: &TraceEvent) -> std::io::Result<()> {
+        let mut value = serde_json::to_value(event)?;
+        if let Some(object) = value.as_object_mut() {
+            let ms = self.start.elapsed().as_millis() as u64;
+            object.insert("ms".to_string(), Value::from(ms));
+        }
+        writeln!(self.out, "{value}")?;
+        self.out.flush()
+    }
+}
+
+impl State {
+    /// Add `event` to the `--record` transcript, if recording. In privacy
+    /// mode keys and backend messages (which carry the typed text) are left out.
+    pub(crate) fn record(&mut self, event: TraceEvent) {
+        let private = matches!(event, TraceEvent::Key { .. } | TraceEvent::Nvim { .. });
+        if private && self.privacy {
+            return;
+        }
+        let Some(recorder) = self.recorder.as_mut() else {
+            return;
+        };
+        if let Err(e) = recorder.record(&event) {
+            log::warn!(
+                "[RECORD] Failed to write transcript, recording stopped: {}",
+                e
+            );
+            self.recorder = None;
+        }
+    }
+}
+
+/// `jacin --replay <file>`: replay a transcript or fixture and print the state
+/// after every event that changes it
+pub fn run(config: &Config, path: &Path) -> anyhow::Result<()> {
+    let trace = Trace::load(path).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+    if !trace.description.is_empty() {
+        println!("# {}", trace.description);
+    }
+    let behavior = &config.behavior;
+    let mut replayer = Replayer::new(
+        behavior.deactivate_clear,
+        Duration::from_secs(behavior.deactivate_clear_delay),
+    )
+    .with_focus_loss(behavior.on_focus_loss);
+    let start = Instant::now();
+    let mut now = start;
+    let mut summary = replayer.summary();
+    for event in trace.events {
+        if let TraceEvent::Tick { ms } = event {
+            now += Duration::from_millis(ms);
+        } else {
+            let line = serde_json::to_string(&event)?;
+            println!("[{:>8.3}s] {}", (now - start).as_secs_f64(), line);
+        }
+        replayer.replay(event, now);
+        let next = replayer.summary();
+        if next != summary {
+            println!("           = {next}");
+            summary = next;
+        }
+    }
+    println!("{}", serde_json::to_string_pretty(&summary)?);
+    Ok(())
+}
+
+/// Upgrade a trace to `SCHEMA_VERSION` one version at a time
+pub fn upgrade(mut value: Value) -> Result<Value, String> {
+    loop {
+        let version = schema_version(&value)?;
+        value = match version {
+            SCHEMA_VERSION => return Ok(value),
+            1 => upgrade_v1(value)?,
+            2 => upgrade_v2(value)?,
+            v => return Err(format!("unsupported trace schema version {v}")),
+        };
+    }
+}
+
+/// Version of a trace; version 1 predates the header field
+fn schema_version(value: &Value) -> Result<u32, String> {
+    match value.get("schema_version") {
+        None => Ok(1),
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| format!("invalid schema_version {v}")),
+    }
+}
+
+/// v1 → v2: `messages` (bare FromNeovim values) become `nvim` events
+fn upgrade_v1(mut value: Value) -> Result<Value, String> {
+    let object = value.as_object_mut().ok_or("trace is not an object")?;
+    let messages = match object.remove("messages") {
+        Some(Value::Array(messages)) => messages,
+        Some(_) => return Err("v1 trace: messages is not an array".to_string()),
+        None => Vec::new(),
+    };
+    let events = messages
+        .into_iter()
+        .map(|message| serde_json::json!({ "type": "nvim", "message": message }))
+        .collect();
+    object.insert("events".to_string(), Value::Array(events));
+    object.insert("schema_version".to_string(), Value::from(2));
+    Ok(value)
+}
+
+/// v2 → v3: adds `modifiers`, `enable` and `disable` events; nothing to rewrite
+fn upgrade_v2(mut value: Value) -> Result<Value, String> {
+    let object = value.as_object_mut().ok_or("trace is not an object")?;
+    object.insert("schema_version".to_string(), Value::from(3));
+    Ok(value)
+}
+
+/// Minimal mirror of `State` for replaying traces without Wayland or a popup.
+/// It follows the coordinator's handling of each event; keep the two in sync.
+pub struct Replayer {
+    pub ime: ImeState,
+    pub keypress: KeypressState,
+    pub visual_display: Option<VisualSelection>,
+    pub committed: Vec<String>,
+    pub exited: bool,
+    pub wayland_active: bool,
+    buffer_clear: BufferClearState,
+    on_focus_loss: OnFocusLoss,
+    /// Keys sent to Neovim by the IME itself (not by the user)
+    pub sent_keys: Vec<String>,
+    /// Keys pressed by the user
+    pub user_keys: Vec<String>,
+}
+
+impl Replayer {
+    /// Replayer with the IME enabled in insert mode (what most fixtures
+    /// assume), clearing the buffer on deactivate like `strategy` would
+    pub fn new(strategy: DeactivateClear, clear_delay: Duration) -> Self {
+        let mut ime = ImeState::new();
+        // Start as fully enabled (most replay scenarios assume enabled IME)
+        ime.start_enabling();
+        ime.complete_enabling(VimMode::Insert);
+        Self {
+            ime,
+            keypress: KeypressState::new(),
+            visual_display: None,
+            committed: Vec::new(),
+            exited: false,
+            wayland_active: true,
+            buffer_clear: BufferClearState::new(strategy, clear_delay),
+            on_focus_loss: OnFocusLoss::Discard,
+            sent_keys: Vec::new(),
+            user_keys: Vec::new(),
+        }
+    }
+
+    /// Handle the composition on focus loss like `policy` would
+    pub fn with_focus_loss(mut self, policy: OnFocusLoss) -> Self {
+        self.on_focus_loss = policy;
+        self
+    }
+
+    /// Mirrors the Deactivate handling in dispatch.rs
+    pub fn deactivate(&mut self, now: Instant) {
+        self.wayland_active = false;
+        if self.ime.is_enabled() {
+            let composing = !self.ime.preedit.is_empty();
+            match self.on_focus_loss {
+                OnFocusLoss::Commit if composing => {
+                    self.committed.push(self.ime.preedit.clone());
+                    self.buffer_clear.cancel();
+                    self.sent_keys.push("<Esc>ggdG".to_string());
+                }
+                // No toplevel tracking here: any activation resumes it
+                OnFocusLoss::Keep if composing => {
+                    self.ime.keep_composition(None);
+                    self.buffer_clear.cancel();
+                }
+                _ => {
+                    if self.buffer_clear.on_deactivate(now) {
+                        self.sent_keys.push("<Esc>ggdG".to_string());
+                    }
+                }
+            }
+            self.ime.clear_preedit();
+            self.ime.clear_candidates();
+            self.keypress.clear();
+            self.visual_display = None;
+        }
+    }
+
+    /// Mirrors the Activate handling in dispatch.rs
+    pub fn activate(&mut self) {
+        self.wayland_active = true;
+        self.buffer_clear.cancel();
+        if let Some(kept) = self.ime.take_kept() {
+            if self.ime.is_enabled() {
+                self.ime
+                    .set_preedit(kept.preedit, kept.cursor_begin, kept.cursor_end);
+            } else {
+                self.sent_keys.push("<Esc>ggdG".to_string());
+            }
+        }
+    }
+
+    /// Mirrors the deferred buffer clear timer
+    pub fn tick(&mut self, now: Instant) {
+        if self.buffer_clear.expire(now) {
+            self.sent_keys.push("<Esc>ggdG".to_string());
+        }
+    }
+
+    pub fn apply(&mut self, msg: FromNeovim) {
+        match msg {
+            FromNeovim::Ready
+            | FromNeovim::KeyProcessed
+            | FromNeovim::PassthroughKey
+            | FromNeovim::ClipboardCopy { .. }
+            | FromNeovim::SnapshotStats(_)
+            | FromNeovim::LastMacro(_)
+            | FromNeovim::Langmap(_) => {}
+            // The replacement backend is given the composition back
+            FromNeovim::Unresponsive => {}
+            FromNeovim::DeleteSurrounding { .. } => {}
+            FromNeovim::Preedit(info) => {
+                if self.ime.is_fully_enabled() {
+                    self.ime
+                        .set_preedit(info.text, info.cursor_begin, info.cursor_end);
+                    self.keypress.set_vim_mode(&info.mode);
+                    self.keypress.recording = info.recording;
+                }
+            }
+            FromNeovim::Commit(text) => {
+                self.committed.push(text);
+                self.ime.clear_preedit();
+                self.ime.clear_candidates();
+                self.keypress.clear();
+            }
+            FromNeovim::Candidates(info) => {
+                if self.ime.is_fully_enabled() {
+                    if info.candidates.is_empty() {
+                        self.ime.clear_candidates();
+                    } else {
+                        self.ime.set_candidates(info);
+                    }
+                }
+            }
+            FromNeovim::VisualRange(selection) => {
+                if self.ime.is_fully_enabled() {
+                    self.visual_display = selection;
+                }
+            }
+            FromNeovim::CmdlineShow {
+                content,
+                pos,
+                firstc,
+                prompt,
+                level,
+            } => {
+                if self.ime.is_fully_enabled() {
+                    let prefix = if !prompt.is_empty() { &prompt } else { &firstc };
+                    let prefix_len = prefix.len();
+                    let display_text = format!("{}{}", prefix, content);
+                    let cursor_byte = prefix_len + pos;
+                    self.keypress
+                        .set_cmdline_text(display_text, cursor_byte, prefix_len, level);
+                    self.keypress.set_vim_mode("c");
+                }
+            }
+            FromNeovim::CmdlinePos { pos, level } => {
+                if self.ime.is_fully_enabled() {
+                    self.keypress.update_cmdline_cursor(pos, level);
+                }
+            }
+            FromNeovim::CmdlineHide { level } => {
+                self.keypress.clear_cmdline_if_level(level);
+            }
+            FromNeovim::CmdlineCancelled { cmdtype, .. } => {
+                self.keypress.clear();
+                self.keypress
+                    .set_vim_mode(if cmdtype == "@" { "i" } else { "n" });
+            }
+            FromNeovim::CmdlineMessage { text, .. } => {
+                if self.ime.is_fully_enabled() {
+                    self.ime.set_transient_message(text);
+                }
+            }
+            FromNeovim::ModeChange(mode) => {
+                if self.ime.is_fully_enabled() {
+                    self.keypress.set_vim_mode(&mode);
+                }
+            }
+            FromNeovim::AutoCommit(text) => {
+                if text.is_empty() {
+                    return;
+                }
+                if !self.ime.is_fully_enabled() {
+                    if self.wayland_active {
+                        self.committed.push(text);
+                    }
+                } else {
+                    self.committed.push(text);
+                    self.ime.clear_preedit();
+                    self.ime.clear_candidates();
+                    self.keypress.clear();
+                    self.visual_display = None;
+                }
+            }
+            FromNeovim::NvimExited => {
+                self.ime.clear_preedit();
+                self.ime.clear_candidates();
+                self.keypress.clear();
+                self.keypress.recording.clear();
+                self.visual_display = None;
+                self.ime.disable();
+                self.exited = true;
+            }
+        }
+    }
+
+    /// Mirrors toggle_ime turning the IME on
+    pub fn enable(&mut self) {
+        if !self.ime.is_enabled() {
+            self.ime.start_enabling();
+            self.ime.complete_enabling(VimMode::Insert);
+        }
+    }
+
+    /// Mirrors toggle_ime turning the IME off
+    pub fn disable(&mut self) {
+        self.ime.take_kept();
+        self.ime.clear_preedit();
+        self.ime.clear_candidates();
+        self.keypress.clear();
+        self.visual_display = None;
+        self.ime.disable();
+    }
+
+    /// Replay one trace event at `now`
+    pub fn replay(&mut self, event: TraceEvent, now: Instant) {
+        match event {
+            // The backend's response follows as Nvim events
+            TraceEvent::Key { key } => self.user_keys.push(key),
+            TraceEvent::Nvim { message } => self.apply(message),
+            TraceEvent::Activate => self.activate(),
+            TraceEvent::Deactivate => self.deactivate(now),
+            TraceEvent::Enable => self.enable(),
+            TraceEvent::Disable => self.disable(),
+            // Modifiers only change how later keys are spelled
+            TraceEvent::Modifiers { .. } => {}
+            TraceEvent::Tick { .. } => self.tick(now),
+        }
+    }
+
+    /// The state a fixture's `expect` checks
+    pub fn summary(&self) -> Value {
+        serde_json::json!({
+            "preedit": self.ime.preedit,
+            "cursor_begin": self.ime.cursor_begin,
+            "cursor_end": self.ime.cursor_end,
+            "vim_mode": self.keypress.vim_mode,
+            "candidates_count": self.ime.candidates.len(),
+            "committed": self.committed,
+            "exited": self.exited,
+            "sent_keys": self.sent_keys,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn v1_messages_become_nvim_events() {
+        let v1 = json!({
+            "description": "old fixture",
+            "messages": [{ "Commit": "hello" }, "NvimExited"],
+            "expect": { "committed": ["hello"] }
+        });
+        let trace = Trace::from_value(v1).unwrap();
+        assert_eq!(trace.schema_version, SCHEMA_VERSION);
+        assert_eq!(trace.description, "old fixture");
+        assert!(matches!(
+            &trace.events[..],
+            [
+                TraceEvent::Nvim { message: FromNeovim::Commit(text) },
+                TraceEvent::Nvim { message: FromNeovim::NvimExited },
+            ] if text == "hello"
+        ));
+    }
+
+    #[test]
+    fn current_version_round_trips() {
+        let value = json!({
+            "schema_version": 2,
+            "events": [
+                { "type": "key", "key": "<C-CR>" },
+                { "type": "activate" },
+                { "type": "deactivate" },
+                { "type": "tick", "ms": 5000 },
+                { "type": "nvim", "message": "KeyProcessed" }
+            ]
+        });
+        let trace = Trace::from_value(value).unwrap();
+        assert_eq!(trace.events.len(), 5);
+        assert!(matches!(trace.events[3], TraceEvent::Tick { ms: 5000 }));
+
+        let again = Trace::from_value(serde_json::to_value(&trace).unwrap()).unwrap();
+        assert_eq!(again.events.len(), 5);
+    }
+
+    #[test]
+    fn transcripts_turn_gaps_into_ticks() {
+        let transcript = r#"{"schema_version": 3, "description": "sway session"}
+{"ms": 0, "type": "disable"}
+{"ms": 0, "type": "enable"}
+{"ms": 40, "type": "modifiers", "depressed": 1, "latched": 0, "locked": 0, "group": 0}
+
+{"ms": 1500, "type": "key", "key": "A"}
+"#;
+        let trace = Trace::from_transcript(transcript).unwrap();
+        assert_eq!(trace.description, "sway session");
+        assert!(matches!(
+            &trace.events[..],
+            [
+                TraceEvent::Disable,
+                TraceEvent::Enable,
+                TraceEvent::Tick { ms: 40 },
+                TraceEvent::Modifiers { depressed: 1, .. },
+                TraceEvent::Tick { ms: 1460 },
+                TraceEvent::Key { key },
+            ] if key == "A"
+        ));
+
+        let missing_ms = "{\"schema_version\": 3}\n{\"type\": \"activate\"}";
+        assert_eq!(
+            Trace::from_transcript(missing_ms).unwrap_err(),
+            "line 2: missing ms"
+        );
+        assert!(Trace::from_transcript("").is_err());
+    }
+
+    #[test]
+    fn recorded_transcripts_load_back() {
+        let path = std::env::temp_dir().join(format!("jacin-test-record-{}", std::process::id()));
+        let mut recorder = Recorder::create(&path, "test").unwrap();
+        recorder.record(&TraceEvent::Activate).unwrap();
+        recorder
+            .record(&TraceEvent::Nvim {
+                message: FromNeovim::Commit("漢字".to_string()),
+            })
+            .unwrap();
+        drop(recorder);
+
+        let trace = Trace::load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        let events: Vec<_> = trace
+            .events
+            .into_iter()
+            .filter(|e| !matches!(e, TraceEvent::Tick { .. }))
+            .collect();
+        assert!(matches!(
+            &events[..],
+            [
+                TraceEvent::Activate,
+                TraceEvent::Nvim { message: FromNeovim::Commit(text) },
+            ] if text == "漢字"
+        ));
+    }
+
+    #[test]
+    fn replayer_follows_toggles() {
+        let mut replayer = Replayer::new(DeactivateClear::Clear, Duration::from_secs(5));
+        let now = Instant::now();
+        replayer.replay(TraceEvent::Disable, now);
+        let normal = || TraceEvent::Nvim {
+            message: FromNeovim::ModeChange("n".to_string()),
+        };
+        // Backend output while off is not shown
+        replayer.replay(normal(), now);
+        assert!(!replayer.ime.is_enabled());
+        assert_ne!(replayer.summary()["vim_mode"], "n");
+        replayer.replay(TraceEvent::Enable, now);
+        replayer.replay(normal(), now);
+        assert!(replayer.ime.is_fully_enabled());
+        assert_eq!(replayer.summary()["vim_mode"], "n");
+    }
+
+    #[test]
+    fn rejects_unknown_versions_and_events() {
+        assert!(Trace::from_value(json!({ "schema_version": 99, "events": [] })).is_err());
+        assert!(Trace::from_value(json!({ "schema_version": "2", "events": [] })).is_err());
+        assert!(
+            Trace::from_value(json!({ "schema_version": 2, "events": [{ "type": "mouse" }] }))
+                .is_err()
+        );
+    }
+}
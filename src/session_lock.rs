@@ -0,0 +1,126 @@
+//! Screen lock detection via systemd-logind
+//!
+//! ext-session-lock-v1 only notifies the client that holds the lock, so other
+//! clients can't observe it; logind's `Lock`/`Unlock` signals and `LockedHint`
+//! property are the portable way to learn the session is locked. A watcher
+//! thread forwards transitions to the event loop through a calloop channel.
+
+use std::collections::HashMap;
+
+use calloop::channel::{Channel, Sender, channel};
+use zbus::blocking::{Connection, MessageIterator, Proxy};
+use zbus::zvariant::{OwnedObjectPath, Value};
+use zbus::{MatchRule, message::Type as MessageType};
+
+const LOGIND: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+const MANAGER_IFACE: &str = "org.freedesktop.login1.Manager";
+const SESSION_IFACE: &str = "org.freedesktop.login1.Session";
+
+/// Session lock transition
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockEvent {
+    Locked,
+    Unlocked,
+}
+
+/// Start watching the current logind session. Events arrive on the returned channel.
+pub fn spawn_lock_monitor() -> Channel<LockEvent> {
+    let (tx, rx) = channel();
+    std::thread::Builder::new()
+        .name("session-lock".into())
+        .spawn(move || {
+            if let Err(e) = watch(&tx) {
+                log::warn!("[LOCK] Session lock detection disabled: {}", e);
+            }
+        })
+        .expect("failed to spawn session lock thread");
+    rx
+}
+
+fn watch(tx: &Sender<LockEvent>) -> zbus::Result<()> {
+    let conn = Connection::system()?;
+    let session = current_session(&conn)?;
+    log::info!("[LOCK] Watching logind session {}", session.as_str());
+
+    let rule = MatchRule::builder()
+        .msg_type(MessageType::Signal)
+        .sender(LOGIND)?
+        .path(session.as_str())?
+        .build();
+    for msg in MessageIterator::for_match_rule(rule, &conn, None)? {
+        let msg = msg?;
+        let header = msg.header();
+        let member = header.member().map(|m| m.as_str()).unwrap_or_default();
+        let event = match member {
+            "Lock" => Some(LockEvent::Locked),
+            "Unlock" => Some(LockEvent::Unlocked),
+            "PropertiesChanged" => {
+                let body = msg.body();
+                let (iface, changed, _): (String, HashMap<String, Value>, Vec<String>) =
+                    body.deserialize()?;
+                locked_hint_event(&iface, &changed)
+            }
+            _ => None,
+        };
+        if let Some(event) = event {
+            log::debug!("[LOCK] {:?} ({})", event, member);
+            if tx.send(event).is_err() {
+                break; // Event loop gone
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Object path of this process's session (XDG_SESSION_ID, else logind's "auto")
+fn current_session(conn: &Connection) -> zbus::Result<OwnedObjectPath> {
+    let manager = Proxy::new(conn, LOGIND, LOGIND_PATH, MANAGER_IFACE)?;
+    let id = std::env::var("XDG_SESSION_ID")
+        .ok()
+        .filter(|id| !id.is_empty())
+        .unwrap_or_else(|| "auto".to_string());
+    manager.call("GetSession", &(id,))
+}
+
+/// Map a `LockedHint` property change (set by lockers via SetLockedHint)
+fn locked_hint_event(iface: &str, changed: &HashMap<String, Value>) -> Option<LockEvent> {
+    if iface != SESSION_IFACE {
+        return None;
+    }
+    match changed.get("LockedHint")? {
+        Value::Bool(true) => Some(LockEvent::Locked),
+        Value::Bool(false) => Some(LockEvent::Unlocked),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locked_hint_maps_to_events() {
+        let mut changed = HashMap::new();
+        changed.insert("LockedHint".to_string(), Value::Bool(true));
+        assert_eq!(
+            locked_hint_event(SESSION_IFACE, &changed),
+            Some(LockEvent::Locked)
+        );
+        changed.insert("LockedHint".to_string(), Value::Bool(false));
+        assert_eq!(
+            locked_hint_event(SESSION_IFACE, &changed),
+            Some(LockEvent::Unlocked)
+        );
+    }
+
+    #[test]
+    fn unrelated_property_changes_ignored() {
+        let mut changed = HashMap::new();
+        changed.insert("Active".to_string(), Value::Bool(true));
+        assert_eq!(locked_hint_event(SESSION_IFACE, &changed), None);
+
+        changed.insert("LockedHint".to_string(), Value::Bool(true));
+        assert_eq!(locked_hint_event("org.example.Other", &changed), None);
+    }
+}
@@ -0,0 +1,169 @@
+//! Leaving the session: normal exit, fatal errors and panics
+//!
+//! The normal exit and a fatal event loop error go through [`finish`]: the
+//! keyboard grabs are released, backends shut down and popups destroyed; after
+//! an error the preedit is committed first if the connection still works, and
+//! on a normal exit it is saved for the next start (behavior.save_composition).
+//!
+//! A panic can't reach the [`State`](crate::State) it unwinds through, so the
+//! event loop [`arm`]s the hook after every iteration with what it needs: the
+//! input method, the grabs and the preedit. On a panic of the main thread the
+//! hook commits the preedit, releases the grabs and flushes the connection
+//! before the default hook reports the panic. Best effort: the state is the
+//! one of the last completed iteration.
+
+use std::sync::Mutex;
+use std::thread::ThreadId;
+
+use wayland_client::Connection;
+use wayland_protocols_misc::zwp_input_method_v2::client::{
+    zwp_input_method_keyboard_grab_v2::ZwpInputMethodKeyboardGrabV2,
+    zwp_input_method_v2::ZwpInputMethodV2,
+};
+
+use crate::State;
+use crate::config::Config;
+use crate::state::{COMPOSITION_FILE, SavedComposition};
+
+/// What the panic hook needs, as of the last event loop iteration
+struct Armed {
+    conn: Connection,
+    input_method: ZwpInputMethodV2,
+    serial: u32,
+    /// Text to commit (empty when nothing may be: no text field, locked session)
+    preedit: String,
+    grabs: Vec<ZwpInputMethodKeyboardGrabV2>,
+}
+
+static ARMED: Mutex<Option<Armed>> = Mutex::new(None);
+
+/// Commit `text` and clear the preedit (as `WaylandState::commit_string`)
+fn commit(input_method: &ZwpInputMethodV2, serial: u32, text: &str) {
+    input_method.commit_string(text.to_string());
+    input_method.set_preedit_string(String::new(), 0, 0);
+    input_method.commit(serial);
+}
+
+/// Install the panic hook (once, on the main thread, before the event loop)
+pub fn install_panic_hook() {
+    let main_thread: ThreadId = std::thread::current().id();
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        // Worker threads (backend, D-Bus) panicking leave the session alone
+        if std::thread::current().id() == main_thread {
+            on_panic();
+        }
+        default_hook(info);
+    }));
+}
+
+fn on_panic() {
+    // The panic may have hit while arming: never block on the lock
+    let Some(armed) = ARMED.try_lock().ok().and_then(|mut armed| armed.take()) else {
+        return;
+    };
+    if !armed.preedit.is_empty() {
+        commit(&armed.input_method, armed.serial, &armed.preedit);
+    }
+    for grab in &armed.grabs {
+        grab.release();
+    }
+    let _ = armed.conn.flush();
+    log::error!(
+        "[PANIC] Committed {} chars of preedit, released {} keyboard grab(s)",
+        armed.preedit.chars().count(),
+        armed.grabs.len()
+    );
+}
+
+/// Record the state for the panic hook (after every event loop iteration)
+pub fn arm(state: &State, conn: &Connection) {
+    // Locking never commits (see handle_session_lock)
+    let preedit = if state.wayland.active && !state.session_locked {
+        state.ime.preedit.clone()
+    } else {
+        String::new()
+    };
+    let grabs = std::iter::once(&state.wayland)
+        .chain(state.parked_seats.iter().map(|s| &s.wayland))
+        .filter_map(|w| w.keyboard_grab.clone())
+        .collect();
+    let armed = Armed {
+        conn: conn.clone(),
+        input_method: state.wayland.input_method.clone(),
+        serial: state.wayland.serial,
+        preedit,
+        grabs,
+    };
+    if let Ok(mut slot) = ARMED.lock() {
+        *slot = Some(armed);
+    }
+}
+
+/// Leave the session after the event loop ended, `failed` if with an error.
+/// The preedit is committed after an error unless the connection is gone
+/// (recovery brings it back or reports it then).
+pub fn finish(state: &mut State, conn: &Connection, failed: bool) {
+    if let Ok(mut slot) = ARMED.lock() {
+        *slot = None;
+    }
+    if failed && conn.protocol_error().is_none() {
+        commit_preedit(state);
+        let _ = conn.flush();
+    }
+    if !failed {
+        save_composition(state);
+    }
+    if let Some(ref mut history) = state.candidate_history {
+        history.save_if_unsaved();
+    }
+    state.wayland.release_keyboard();
+    state.release_parked_grabs();
+    if let Some(ref backend) = state.backend {
+        backend.shutdown();
+    }
+    if let Some(ref standby) = state.backend_standby {
+        standby.shutdown();
+    }
+    if let Some(window) = state.popup.take() {
+        window.destroy();
+    }
+    for window in state.parked_seats.drain(..).filter_map(|s| s.popup) {
+        window.destroy();
+    }
+}
+
+/// Keep the text being composed for the next jacin (behavior.save_composition),
+/// or a saved composition this session never got to restore
+fn save_composition(state: &State) {
+    if !state.config.behavior.save_composition || state.privacy || state.session_locked {
+        return;
+    }
+    let composing =
+        (state.ime.is_enabled() && !state.ime.preedit.is_empty()).then(|| SavedComposition {
+            preedit: state.ime.preedit.clone(),
+            cursor_begin: state.ime.cursor_begin,
+            cursor_end: state.ime.cursor_end,
+            mode: state.keypress.vim_mode.clone(),
+        });
+    let Some(saved) = composing.or_else(|| state.saved_composition.clone()) else {
+        return;
+    };
+    let Some(path) = Config::state_dir().map(|dir| dir.join(COMPOSITION_FILE)) else {
+        return;
+    };
+    match saved.save(&path) {
+        Ok(()) => log::info!("[EXIT] Saved the composition to {}", path.display()),
+        Err(e) => log::warn!("[EXIT] Failed to save {}: {}", path.display(), e),
+    }
+}
+
+/// Commit the preedit to the focused text field, leaving nothing to report
+fn commit_preedit(state: &mut State) {
+    if state.ime.preedit.is_empty() || !state.wayland.active || state.session_locked {
+        return;
+    }
+    log::warn!("[EXIT] Committing the preedit before exiting");
+    state.wayland.commit_string(&state.ime.preedit);
+    state.ime.clear_preedit();
+}
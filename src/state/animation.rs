@@ -1,9 +1,9 @@
 //! Lightweight animation infrastructure.
 //!
 //! Provides a thin abstraction to centralise timer-driven visual updates
-//! (currently REC-dot blink) behind a uniform `update(now) -> changed` API.
-//! Future animations (cursor blink, fade-outs, …) can be added here without
-//! touching the main-loop timer wiring.
+//! behind a uniform, time-based API: the REC-dot blink (ticked by the
+//! keypress timer), and the popup fade in/out and candidate slide-in (ticked
+//! by a frame timer while one runs, `[animation]`).
 
 use std::time::{Duration, Instant};
 
@@ -56,6 +56,134 @@ impl RecBlink {
     }
 }
 
+// ── Transitions ─────────────────────────────────────────────────────────────
+
+/// Frame interval of the popup fade and slide
+pub const ANIMATION_FRAME: Duration = Duration::from_millis(16);
+
+/// Ease-out cubic: fast start, gentle landing
+fn ease_out(t: f32) -> f32 {
+    1.0 - (1.0 - t).powi(3)
+}
+
+/// A timed transition from `start` over `duration`
+#[derive(Debug, Clone, Copy)]
+struct Transition {
+    start: Instant,
+    duration: Duration,
+}
+
+impl Transition {
+    /// Linear progress (0.0 to 1.0)
+    fn progress(&self, now: Instant) -> f32 {
+        if self.duration.is_zero() {
+            return 1.0;
+        }
+        let elapsed = now.saturating_duration_since(self.start);
+        (elapsed.as_secs_f32() / self.duration.as_secs_f32()).min(1.0)
+    }
+}
+
+/// Popup opacity: fades in when the popup appears and out when it goes away.
+#[derive(Debug, Default)]
+pub struct PopupFade {
+    /// Running transition and the opacity it started from
+    running: Option<(Transition, f32)>,
+    /// Whether the running transition goes to transparent
+    out: bool,
+}
+
+impl PopupFade {
+    /// Opacity at `now`
+    pub fn opacity(&self, now: Instant) -> f32 {
+        let Some((transition, from)) = self.running else {
+            return if self.out { 0.0 } else { 1.0 };
+        };
+        let to = if self.out { 0.0 } else { 1.0 };
+        from + (to - from) * ease_out(transition.progress(now))
+    }
+
+    /// The popup appears (or comes back while fading out)
+    pub fn fade_in(&mut self, now: Instant, duration: Duration) {
+        let from = if self.out { self.opacity(now) } else { 0.0 };
+        self.out = false;
+        self.running = (!duration.is_zero()).then_some((
+            Transition {
+                start: now,
+                duration,
+            },
+            from,
+        ));
+    }
+
+    /// The popup goes away: keep showing it while it fades
+    pub fn fade_out(&mut self, now: Instant, duration: Duration) {
+        if self.out {
+            return;
+        }
+        let from = self.opacity(now);
+        self.out = true;
+        self.running = Some((
+            Transition {
+                start: now,
+                duration,
+            },
+            from,
+        ));
+    }
+
+    /// Whether the popup is fading out (its content is gone)
+    pub fn is_fading_out(&self) -> bool {
+        self.out && self.running.is_some()
+    }
+
+    pub fn is_running(&self, now: Instant) -> bool {
+        self.running.is_some_and(|(t, _)| t.progress(now) < 1.0)
+    }
+
+    /// Popup hidden: back to opaque for the next one
+    pub fn stop(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Slide-in of the candidate list when candidates appear
+#[derive(Debug, Default)]
+pub struct CandidateSlide {
+    running: Option<Transition>,
+    /// Whether the last render had candidates
+    shown: bool,
+}
+
+impl CandidateSlide {
+    /// Note whether the popup shows candidates; a new list starts the slide
+    pub fn update(&mut self, now: Instant, has_candidates: bool, duration: Duration) {
+        if has_candidates && !self.shown && !duration.is_zero() {
+            self.running = Some(Transition {
+                start: now,
+                duration,
+            });
+        } else if !has_candidates {
+            self.running = None;
+        }
+        self.shown = has_candidates;
+    }
+
+    /// Eased progress at `now` (1.0 = in place)
+    pub fn progress(&self, now: Instant) -> f32 {
+        self.running.map_or(1.0, |t| ease_out(t.progress(now)))
+    }
+
+    pub fn is_running(&self, now: Instant) -> bool {
+        self.running.is_some_and(|t| t.progress(now) < 1.0)
+    }
+
+    /// Popup hidden: the next list slides in again
+    pub fn stop(&mut self) {
+        *self = Self::default();
+    }
+}
+
 // ── Animations (aggregate) ──────────────────────────────────────────────────
 
 /// Aggregate of all running animations.
@@ -65,15 +193,24 @@ impl RecBlink {
 #[derive(Debug)]
 pub struct Animations {
     pub rec_blink: RecBlink,
+    pub popup_fade: PopupFade,
+    pub candidate_slide: CandidateSlide,
 }
 
 impl Animations {
     pub fn new() -> Self {
         Self {
             rec_blink: RecBlink::new(),
+            popup_fade: PopupFade::default(),
+            candidate_slide: CandidateSlide::default(),
         }
     }
 
+    /// Whether a popup fade or slide is running (the frame timer ticks)
+    pub fn popup_running(&self, now: Instant) -> bool {
+        self.popup_fade.is_running(now) || self.candidate_slide.is_running(now)
+    }
+
     /// Advance every animation by one tick.  Returns `true` when any visual
     /// state changed and the popup needs a repaint.
     ///
@@ -148,6 +285,69 @@ mod tests {
         assert!(b.on); // visible on fresh start
     }
 
+    // ── Popup transitions ───────────────────────────────────────────────
+
+    #[test]
+    fn fade_in_then_out() {
+        let mut f = PopupFade::default();
+        let t0 = Instant::now();
+        let d = Duration::from_millis(100);
+        assert_eq!(f.opacity(t0), 1.0);
+
+        f.fade_in(t0, d);
+        assert_eq!(f.opacity(t0), 0.0);
+        assert!(f.opacity(t0 + d / 2) > 0.5); // eased: past half at half time
+        assert_eq!(f.opacity(t0 + d), 1.0);
+        assert!(!f.is_running(t0 + d));
+
+        let t1 = t0 + d;
+        f.fade_out(t1, d);
+        assert!(f.is_fading_out());
+        assert_eq!(f.opacity(t1), 1.0);
+        assert_eq!(f.opacity(t1 + d), 0.0);
+        f.stop();
+        assert!(!f.is_fading_out());
+        assert_eq!(f.opacity(t1), 1.0);
+    }
+
+    #[test]
+    fn fade_in_resumes_from_a_partial_fade_out() {
+        let mut f = PopupFade::default();
+        let t0 = Instant::now();
+        let d = Duration::from_millis(100);
+        f.fade_out(t0, d);
+        let halfway = f.opacity(t0 + d / 2);
+        f.fade_in(t0 + d / 2, d);
+        assert!(!f.is_fading_out());
+        assert_eq!(f.opacity(t0 + d / 2), halfway);
+
+        // Without a duration the popup is opaque at once
+        f.fade_in(t0, Duration::ZERO);
+        assert_eq!(f.opacity(t0), 1.0);
+        assert!(!f.is_running(t0));
+    }
+
+    #[test]
+    fn slide_starts_when_candidates_appear() {
+        let mut s = CandidateSlide::default();
+        let t0 = Instant::now();
+        let d = Duration::from_millis(100);
+        s.update(t0, false, d);
+        assert_eq!(s.progress(t0), 1.0);
+
+        s.update(t0, true, d);
+        assert_eq!(s.progress(t0), 0.0);
+        assert!(s.is_running(t0));
+        // A later render of the same list doesn't restart it
+        s.update(t0 + d / 2, true, d);
+        assert!(s.progress(t0 + d / 2) > 0.5);
+        assert_eq!(s.progress(t0 + d), 1.0);
+
+        s.update(t0 + d, false, d);
+        s.update(t0 + d, true, Duration::ZERO);
+        assert_eq!(s.progress(t0 + d), 1.0);
+    }
+
     // ── Animations aggregate ────────────────────────────────────────────
 
     #[test]
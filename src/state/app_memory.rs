@@ -0,0 +1,89 @@
+//! Per-application IME enabled state (`behavior.remember_per_app`)
+//!
+//! A small JSON map from app_id to the last enabled state, kept in
+//! `$XDG_STATE_HOME/jacin/apps.json` and rewritten whenever an entry changes.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use super::persist::write_atomic;
+
+/// File name inside the state directory
+pub const APP_MEMORY_FILE: &str = "apps.json";
+
+#[derive(Debug)]
+pub struct AppMemory {
+    path: PathBuf,
+    apps: BTreeMap<String, bool>,
+}
+
+impl AppMemory {
+    /// Load from `path` (missing or unreadable = empty)
+    pub fn load(path: PathBuf) -> Self {
+        let apps = match std::fs::read_to_string(&path) {
+            Ok(s) => serde_json::from_str(&s).unwrap_or_else(|e| {
+                log::warn!("[APP] Ignoring invalid {}: {}", path.display(), e);
+                BTreeMap::new()
+            }),
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    log::warn!("[APP] Failed to read {}: {}", path.display(), e);
+                }
+                BTreeMap::new()
+            }
+        };
+        Self { path, apps }
+    }
+
+    /// Last enabled state used in `app` (None = never toggled there)
+    pub fn get(&self, app: &str) -> Option<bool> {
+        self.apps.get(app).copied()
+    }
+
+    /// Remember `enabled` for `app`, saving if it changed
+    pub fn set(&mut self, app: &str, enabled: bool) {
+        if self.get(app) == Some(enabled) {
+            return;
+        }
+        self.apps.insert(app.to_string(), enabled);
+        if let Err(e) = self.save() {
+            log::warn!("[APP] Failed to save {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        write_atomic(&self.path, serde_json::to_string_pretty(&self.apps)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::persist::scratch_path;
+
+    #[test]
+    fn remembers_across_loads() {
+        let path = scratch_path("app-memory", APP_MEMORY_FILE);
+        let mut memory = AppMemory::load(path.clone());
+        assert_eq!(memory.get("firefox"), None);
+
+        memory.set("firefox", true);
+        memory.set("foot", false);
+        let reloaded = AppMemory::load(path.clone());
+        assert_eq!(reloaded.get("firefox"), Some(true));
+        assert_eq!(reloaded.get("foot"), Some(false));
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap().parent().unwrap());
+    }
+
+    #[test]
+    fn invalid_file_starts_empty() {
+        let path = scratch_path("app-memory-invalid", APP_MEMORY_FILE);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, "not json").unwrap();
+        let memory = AppMemory::load(path.clone());
+        assert_eq!(memory.get("firefox"), None);
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap().parent().unwrap());
+    }
+}
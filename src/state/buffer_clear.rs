@@ -0,0 +1,121 @@
+//! When to clear the Neovim buffer after the text input loses focus
+//!
+//! Clearing on every Deactivate churns the backend while switching windows.
+//! `BufferClearState` applies `behavior.deactivate_clear`: clear immediately,
+//! clear only if focus hasn't returned by a deadline, or never.
+
+use std::time::{Duration, Instant};
+
+use crate::config::DeactivateClear;
+
+#[derive(Debug)]
+pub struct BufferClearState {
+    strategy: DeactivateClear,
+    delay: Duration,
+    /// Pending deferred clear (Some only with `Defer`, between Deactivate and
+    /// either re-activation or the deadline)
+    deadline: Option<Instant>,
+}
+
+impl BufferClearState {
+    pub fn new(strategy: DeactivateClear, delay: Duration) -> Self {
+        Self {
+            strategy,
+            delay,
+            deadline: None,
+        }
+    }
+
+    /// Whether an unfocused buffer may survive until the next activation
+    pub fn keeps_buffer(&self) -> bool {
+        self.strategy != DeactivateClear::Clear
+    }
+
+    /// Focus left the text input. Returns true if the buffer should be cleared now.
+    pub fn on_deactivate(&mut self, now: Instant) -> bool {
+        match self.strategy {
+            DeactivateClear::Clear => true,
+            DeactivateClear::Defer => {
+                self.deadline = Some(now + self.delay);
+                false
+            }
+            DeactivateClear::Keep => false,
+        }
+    }
+
+    /// Focus returned or the buffer was cleared for another reason:
+    /// drop any pending deferred clear
+    pub fn cancel(&mut self) {
+        self.deadline = None;
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Timer check. Returns true (once) if the deferred clear is due at `now`.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        match self.deadline {
+            Some(deadline) if now >= deadline => {
+                self.deadline = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELAY: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn clear_strategy_clears_immediately() {
+        let mut state = BufferClearState::new(DeactivateClear::Clear, DELAY);
+        assert!(state.on_deactivate(Instant::now()));
+        assert!(state.deadline().is_none());
+        assert!(!state.keeps_buffer());
+    }
+
+    #[test]
+    fn keep_strategy_never_clears() {
+        let mut state = BufferClearState::new(DeactivateClear::Keep, DELAY);
+        let now = Instant::now();
+        assert!(!state.on_deactivate(now));
+        assert!(!state.expire(now + DELAY * 100));
+        assert!(state.keeps_buffer());
+    }
+
+    #[test]
+    fn defer_clears_after_deadline_once() {
+        let mut state = BufferClearState::new(DeactivateClear::Defer, DELAY);
+        let now = Instant::now();
+        assert!(!state.on_deactivate(now));
+        assert_eq!(state.deadline(), Some(now + DELAY));
+        assert!(!state.expire(now + DELAY / 2));
+        assert!(state.expire(now + DELAY));
+        assert!(!state.expire(now + DELAY * 2));
+    }
+
+    #[test]
+    fn defer_cancelled_by_reactivation() {
+        let mut state = BufferClearState::new(DeactivateClear::Defer, DELAY);
+        let now = Instant::now();
+        state.on_deactivate(now);
+        state.cancel();
+        assert!(!state.expire(now + DELAY));
+    }
+
+    #[test]
+    fn repeated_deactivate_extends_deadline() {
+        let mut state = BufferClearState::new(DeactivateClear::Defer, DELAY);
+        let now = Instant::now();
+        state.on_deactivate(now);
+        state.cancel();
+        state.on_deactivate(now + DELAY / 2);
+        assert!(!state.expire(now + DELAY));
+        assert!(state.expire(now + DELAY + DELAY / 2));
+    }
+}
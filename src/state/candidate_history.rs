@@ -0,0 +1,242 @@
+//! Candidate history (`behavior.learn_candidates`)
+//!
+//! Counts how often each candidate was accepted for a reading, kept as JSON in
+//! `$XDG_DATA_HOME/jacin/candidates.json`. Accepts are saved a few seconds
+//! after the first unsaved one (off the key path) and at exit. Lists from the
+//! backend are shown most-chosen first, so conversions picked again
+//! and again float to the top even when the backend doesn't learn.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::persist::write_atomic;
+use crate::neovim::CandidateInfo;
+
+/// File name inside the data directory
+pub const CANDIDATE_HISTORY_FILE: &str = "candidates.json";
+
+/// Readings kept; the one chosen from longest ago goes first
+const MAX_READINGS: usize = 5000;
+
+/// Unsaved accepts are written this long after the first of them
+const SAVE_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+struct Choice {
+    count: u32,
+    /// Accept number of the latest choice (orders readings for eviction)
+    last: u64,
+}
+
+#[derive(Debug)]
+pub struct CandidateHistory {
+    path: PathBuf,
+    readings: BTreeMap<String, BTreeMap<String, Choice>>,
+    /// Accepts so far, across sessions
+    clock: u64,
+    /// First accept not saved yet
+    unsaved_since: Option<Instant>,
+}
+
+impl CandidateHistory {
+    /// Load from `path` (missing or unreadable = empty)
+    pub fn load(path: PathBuf) -> Self {
+        let readings: BTreeMap<String, BTreeMap<String, Choice>> =
+            match std::fs::read_to_string(&path) {
+                Ok(s) => serde_json::from_str(&s).unwrap_or_else(|e| {
+                    log::warn!("[HISTORY] Ignoring invalid {}: {}", path.display(), e);
+                    BTreeMap::new()
+                }),
+                Err(e) => {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        log::warn!("[HISTORY] Failed to read {}: {}", path.display(), e);
+                    }
+                    BTreeMap::new()
+                }
+            };
+        let clock = readings
+            .values()
+            .flat_map(BTreeMap::values)
+            .map(|c| c.last)
+            .max()
+            .unwrap_or(0);
+        Self {
+            path,
+            readings,
+            clock,
+            unsaved_since: None,
+        }
+    }
+
+    /// Times `candidate` was accepted for `reading`
+    pub fn count(&self, reading: &str, candidate: &str) -> u32 {
+        self.readings
+            .get(reading)
+            .and_then(|choices| choices.get(candidate))
+            .map_or(0, |c| c.count)
+    }
+
+    /// Count an accept of `candidate` for `reading` (saved by `save_if_unsaved`)
+    pub fn record(&mut self, reading: &str, candidate: &str) {
+        if reading.is_empty() || candidate.is_empty() {
+            return;
+        }
+        self.clock += 1;
+        let choice = self
+            .readings
+            .entry(reading.to_string())
+            .or_default()
+            .entry(candidate.to_string())
+            .or_default();
+        choice.count = choice.count.saturating_add(1);
+        choice.last = self.clock;
+        if self.readings.len() > MAX_READINGS {
+            self.evict_oldest();
+        }
+        self.unsaved_since.get_or_insert_with(Instant::now);
+    }
+
+    /// When the unsaved accepts are due to be written (None = all saved)
+    pub fn save_deadline(&self) -> Option<Instant> {
+        self.unsaved_since.map(|since| since + SAVE_DELAY)
+    }
+
+    /// Write the accepts not saved yet, if any
+    pub fn save_if_unsaved(&mut self) {
+        if self.unsaved_since.take().is_none() {
+            return;
+        }
+        if let Err(e) = self.save() {
+            log::warn!("[HISTORY] Failed to save {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Order to show `info`'s candidates in: their backend indices, the most
+    /// chosen first and the backend's order among equals. Empty when that is
+    /// the backend's order anyway. `base` is the reading of candidates that
+    /// have none of their own.
+    pub fn order(&self, info: &CandidateInfo, base: Option<&str>) -> Vec<usize> {
+        let mut scored: Vec<(u32, usize)> = info
+            .candidates
+            .iter()
+            .enumerate()
+            .map(|(i, candidate)| {
+                let count =
+                    reading(&info.readings, i, base).map_or(0, |r| self.count(r, candidate));
+                (count, i)
+            })
+            .collect();
+        scored.sort_by_key(|&(count, i)| (Reverse(count), i));
+        let order: Vec<usize> = scored.into_iter().map(|(_, i)| i).collect();
+        if order.iter().enumerate().all(|(pos, &i)| pos == i) {
+            return Vec::new();
+        }
+        order
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .readings
+            .iter()
+            .min_by_key(|(_, choices)| choices.values().map(|c| c.last).max())
+            .map(|(reading, _)| reading.clone());
+        if let Some(reading) = oldest {
+            self.readings.remove(&reading);
+        }
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        write_atomic(&self.path, serde_json::to_string(&self.readings)?)
+    }
+}
+
+/// Reading candidate `index` is learned under: its own from `readings`, or
+/// `base` (the text the list completes) without one
+pub fn reading<'a>(readings: &'a [String], index: usize, base: Option<&'a str>) -> Option<&'a str> {
+    readings
+        .get(index)
+        .map(String::as_str)
+        .filter(|r| !r.is_empty())
+        .or(base)
+        .filter(|r| !r.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::persist::scratch_path;
+
+    fn kanji() -> CandidateInfo {
+        CandidateInfo::new(["漢字", "感じ", "幹事"].map(String::from).to_vec(), 0)
+    }
+
+    #[test]
+    fn chosen_candidates_come_first_across_loads() {
+        let path = scratch_path("candidate-history", CANDIDATE_HISTORY_FILE);
+        let mut history = CandidateHistory::load(path.clone());
+        assert!(history.order(&kanji(), Some("かんじ")).is_empty());
+
+        history.record("かんじ", "幹事");
+        history.record("かんじ", "感じ");
+        history.record("かんじ", "幹事");
+        // Nothing is written on the key path
+        assert!(!path.exists());
+        assert!(history.save_deadline().is_some());
+        history.save_if_unsaved();
+        assert_eq!(history.save_deadline(), None);
+        let reloaded = CandidateHistory::load(path.clone());
+        assert_eq!(reloaded.count("かんじ", "幹事"), 2);
+        assert_eq!(reloaded.order(&kanji(), Some("かんじ")), [2, 1, 0]);
+        // Another reading knows nothing of these
+        assert!(reloaded.order(&kanji(), Some("かんし")).is_empty());
+        assert!(reloaded.order(&kanji(), None).is_empty());
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap().parent().unwrap());
+    }
+
+    #[test]
+    fn candidate_readings_take_precedence_over_the_base() {
+        let info = kanji().with_readings(["かんじ", "", "かんじ"].map(String::from).to_vec());
+        assert_eq!(reading(&info.readings, 0, Some("kanji")), Some("かんじ"));
+        assert_eq!(reading(&info.readings, 1, Some("kanji")), Some("kanji"));
+        assert_eq!(reading(&info.readings, 1, None), None);
+        assert_eq!(reading(&[], 2, Some("")), None);
+        assert_eq!(
+            CandidateHistory::load(scratch_path(
+                "candidate-history-empty",
+                CANDIDATE_HISTORY_FILE
+            ))
+            .order(&info, Some("kanji")),
+            Vec::<usize>::new()
+        );
+    }
+
+    #[test]
+    fn least_recent_reading_is_evicted() {
+        let path = scratch_path("candidate-history-evict", CANDIDATE_HISTORY_FILE);
+        let mut history = CandidateHistory::load(path.clone());
+        for i in 0..=MAX_READINGS {
+            history.readings.insert(
+                format!("r{i}"),
+                BTreeMap::from([(
+                    "c".to_string(),
+                    Choice {
+                        count: 1,
+                        last: i as u64 + 1,
+                    },
+                )]),
+            );
+        }
+        history.clock = MAX_READINGS as u64 + 1;
+        history.record("r1", "c");
+        assert_eq!(history.readings.len(), MAX_READINGS);
+        assert_eq!(history.count("r0", "c"), 0);
+        assert_eq!(history.count("r1", "c"), 2);
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap().parent().unwrap());
+    }
+}
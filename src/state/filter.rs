@@ -0,0 +1,198 @@
+//! Local candidate filter (`keybinds.candidate_filter`)
+//!
+//! Narrows a long candidate list while it is shown, without sending the typed
+//! keys to Neovim: the list the backend sent is kept, and each query change
+//! picks and orders the candidates matching it. The query matches as typed
+//! (ASCII completions) and, converted from romaji, as kana (readings).
+
+use crate::engine::RomajiEngine;
+use crate::neovim::CandidateInfo;
+
+/// Filter over the backend's candidate list
+#[derive(Debug, Clone)]
+pub struct CandidateFilter {
+    query: String,
+    /// List as the backend sent it
+    full: CandidateInfo,
+    /// Index in `full` of each candidate shown, best match first
+    shown: Vec<usize>,
+}
+
+impl CandidateFilter {
+    pub fn new(full: CandidateInfo) -> Self {
+        let shown = (0..full.candidates.len()).collect();
+        Self {
+            query: String::new(),
+            full,
+            shown,
+        }
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn push(&mut self, c: char) {
+        self.query.push(c);
+        self.refilter();
+    }
+
+    /// Delete the last query character. False if the query was empty.
+    pub fn pop(&mut self) -> bool {
+        if self.query.pop().is_none() {
+            return false;
+        }
+        self.refilter();
+        true
+    }
+
+    /// The backend sent a new list: filter that one
+    pub fn set_full(&mut self, full: CandidateInfo) {
+        self.full = full;
+        self.refilter();
+    }
+
+    /// The list as the backend sent it, selecting `full_index`
+    pub fn into_full(self, full_index: usize) -> CandidateInfo {
+        CandidateInfo {
+            selected: full_index,
+            ..self.full
+        }
+    }
+
+    /// Index in the backend's list of shown candidate `index`
+    pub fn full_index(&self, index: usize) -> Option<usize> {
+        self.shown.get(index).copied()
+    }
+
+    /// The matching candidates, first one selected
+    pub fn view(&self) -> CandidateInfo {
+        self.full.pick(&self.shown, 0)
+    }
+
+    fn refilter(&mut self) {
+        // Kana of the query's finished romaji (unused unless it all converts)
+        let mut engine = RomajiEngine::default();
+        self.query.chars().for_each(|c| engine.push(c));
+        let kana = engine.take_kana();
+        let kana = if kana.chars().any(|c| c.is_ascii_alphabetic()) {
+            String::new()
+        } else {
+            kana
+        };
+
+        let full = &self.full;
+        let mut scored: Vec<(u32, usize)> = (0..full.candidates.len())
+            .filter_map(|i| {
+                let texts = [&full.candidates, &full.readings, &full.annotations]
+                    .map(|column| column.get(i).map_or("", String::as_str));
+                let queries = [self.query.as_str(), kana.as_str()];
+                queries
+                    .iter()
+                    .filter(|q| !q.is_empty() || self.query.is_empty())
+                    .flat_map(|q| texts.iter().filter_map(|t| fuzzy_score(q, t)))
+                    .max()
+                    .map(|score| (score, i))
+            })
+            .collect();
+        // Best first; equal scores keep the backend's order
+        scored.sort_by_key(|&(score, i)| (std::cmp::Reverse(score), i));
+        self.shown = scored.into_iter().map(|(_, i)| i).collect();
+    }
+}
+
+/// Score of `query` as a subsequence of `text`, ignoring case: higher for
+/// runs of consecutive characters and a match at the start. None if `text`
+/// doesn't contain it.
+fn fuzzy_score(query: &str, text: &str) -> Option<u32> {
+    let mut wanted = query.chars().flat_map(char::to_lowercase).peekable();
+    let mut score = 0;
+    let mut run = false;
+    for (i, c) in text.chars().flat_map(char::to_lowercase).enumerate() {
+        let Some(&want) = wanted.peek() else {
+            break;
+        };
+        if c == want {
+            score += 1 + if run { 4 } else { 0 } + if i == 0 { 8 } else { 0 };
+            run = true;
+            wanted.next();
+        } else {
+            run = false;
+        }
+    }
+    wanted.peek().is_none().then_some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(candidates: &[&str], readings: &[&str]) -> CandidateInfo {
+        let column = |v: &[&str]| v.iter().map(|s| s.to_string()).collect();
+        CandidateInfo {
+            candidates: column(candidates),
+            readings: column(readings),
+            selected: 3,
+            base_width: Some(2),
+            ..Default::default()
+        }
+    }
+
+    fn shown(filter: &CandidateFilter) -> Vec<String> {
+        filter.view().candidates.to_vec()
+    }
+
+    #[test]
+    fn fuzzy_matches_prefer_runs_and_prefixes() {
+        assert_eq!(fuzzy_score("", "abc"), Some(0));
+        assert_eq!(fuzzy_score("xyz", "abc"), None);
+        assert!(fuzzy_score("frt", "format") < fuzzy_score("for", "format"));
+        assert!(fuzzy_score("mat", "format") < fuzzy_score("for", "format"));
+        assert!(fuzzy_score("Fo", "format").is_some());
+    }
+
+    #[test]
+    fn query_narrows_and_orders_the_list() {
+        let mut filter = CandidateFilter::new(info(&["println", "print", "format", "eprint"], &[]));
+        assert_eq!(shown(&filter).len(), 4);
+        "pri".chars().for_each(|c| filter.push(c));
+        assert_eq!(shown(&filter), ["println", "print", "eprint"]);
+        assert_eq!(filter.full_index(2), Some(3));
+        assert_eq!(filter.view().selected, 0);
+        assert_eq!(filter.view().base_width, Some(2));
+
+        filter.push('z');
+        assert!(shown(&filter).is_empty());
+        assert!(filter.pop());
+        assert_eq!(shown(&filter).len(), 3);
+    }
+
+    #[test]
+    fn romaji_query_matches_readings() {
+        let mut filter = CandidateFilter::new(info(
+            &["漢字", "感じ", "幹事"],
+            &["かんじ", "かんじ", "かんじ"],
+        ));
+        "kanzi".chars().for_each(|c| filter.push(c));
+        assert_eq!(shown(&filter).len(), 3);
+        // Readings follow their candidates
+        assert_eq!(filter.view().readings.len(), 3);
+
+        let mut filter = CandidateFilter::new(info(&["寿司", "鮨", "すし"], &["すし", "", ""]));
+        "susi".chars().for_each(|c| filter.push(c));
+        assert_eq!(shown(&filter), ["寿司", "すし"]);
+    }
+
+    #[test]
+    fn new_list_keeps_the_query_and_full_index_is_restored() {
+        let mut filter = CandidateFilter::new(info(&["foo", "bar"], &[]));
+        filter.push('b');
+        filter.set_full(info(&["baz", "qux", "bar"], &[]));
+        assert_eq!(shown(&filter), ["baz", "bar"]);
+        assert!(filter.pop());
+        assert!(!filter.pop());
+        let full = filter.into_full(2);
+        assert_eq!(full.candidates.len(), 3);
+        assert_eq!(full.selected, 2);
+    }
+}
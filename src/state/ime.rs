@@ -2,8 +2,13 @@
 //!
 //! Explicit state machine for IME mode transitions, replacing scattered boolean flags.
 
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+use super::candidate_history::{self, CandidateHistory};
+use super::filter::CandidateFilter;
+use crate::neovim::{CANDIDATE_WINDOW, CandidateInfo, CandidateWindow, InputMode, PreeditSegment};
+
 /// Main IME mode state machine
 #[derive(Debug, Clone, PartialEq, Default)]
 pub enum ImeMode {
@@ -17,6 +22,8 @@ pub enum ImeMode {
         /// Current Vim editing mode
         vim_mode: VimMode,
     },
+    /// Was enabled when the session locked; keyboard released, preedit kept
+    Suspended,
 }
 
 /// Vim editing mode within the IME
@@ -32,6 +39,156 @@ pub enum VimMode {
 /// How long a transient message stays visible before auto-clearing
 pub const TRANSIENT_MESSAGE_DURATION: Duration = Duration::from_millis(2000);
 
+/// Text around the cursor reported by the application (preedit excluded)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SurroundingText {
+    pub text: String,
+    /// Cursor byte offset into `text`
+    pub cursor: usize,
+    /// Selection anchor byte offset (equal to `cursor` without a selection)
+    pub anchor: usize,
+}
+
+/// Largest char boundary of `text` at or below `offset`
+fn floor_boundary(text: &str, offset: usize) -> usize {
+    let mut offset = offset.min(text.len());
+    while !text.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+impl SurroundingText {
+    /// Offsets outside `text` or inside a character are moved back to a boundary
+    pub fn new(text: String, cursor: u32, anchor: u32) -> Self {
+        let cursor = floor_boundary(&text, cursor as usize);
+        let anchor = floor_boundary(&text, anchor as usize);
+        Self {
+            text,
+            cursor,
+            anchor,
+        }
+    }
+
+    /// Text before the cursor
+    pub fn before(&self) -> &str {
+        &self.text[..self.cursor]
+    }
+
+    /// Text after the cursor
+    pub fn after(&self) -> &str {
+        &self.text[self.cursor..]
+    }
+
+    /// Limit a delete request (byte lengths around the cursor) to the text that
+    /// exists, ending on character boundaries
+    pub fn clamp_delete(&self, before: u32, after: u32) -> (u32, u32) {
+        let mut start = self.cursor.saturating_sub(before as usize);
+        while !self.text.is_char_boundary(start) {
+            start += 1;
+        }
+        let end = floor_boundary(&self.text, self.cursor.saturating_add(after as usize));
+        ((self.cursor - start) as u32, (end - self.cursor) as u32)
+    }
+}
+
+/// Purpose of the focused input (text-input-v3 `content_purpose`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ContentPurpose {
+    #[default]
+    Normal,
+    Alpha,
+    Digits,
+    Number,
+    Phone,
+    Url,
+    Email,
+    Name,
+    Password,
+    Pin,
+    Date,
+    Time,
+    Datetime,
+    Terminal,
+}
+
+impl ContentPurpose {
+    pub const ALL: [ContentPurpose; 14] = [
+        ContentPurpose::Normal,
+        ContentPurpose::Alpha,
+        ContentPurpose::Digits,
+        ContentPurpose::Number,
+        ContentPurpose::Phone,
+        ContentPurpose::Url,
+        ContentPurpose::Email,
+        ContentPurpose::Name,
+        ContentPurpose::Password,
+        ContentPurpose::Pin,
+        ContentPurpose::Date,
+        ContentPurpose::Time,
+        ContentPurpose::Datetime,
+        ContentPurpose::Terminal,
+    ];
+
+    /// Protocol name, as used in `behavior.direct_purposes`
+    pub fn name(self) -> &'static str {
+        match self {
+            ContentPurpose::Normal => "normal",
+            ContentPurpose::Alpha => "alpha",
+            ContentPurpose::Digits => "digits",
+            ContentPurpose::Number => "number",
+            ContentPurpose::Phone => "phone",
+            ContentPurpose::Url => "url",
+            ContentPurpose::Email => "email",
+            ContentPurpose::Name => "name",
+            ContentPurpose::Password => "password",
+            ContentPurpose::Pin => "pin",
+            ContentPurpose::Date => "date",
+            ContentPurpose::Time => "time",
+            ContentPurpose::Datetime => "datetime",
+            ContentPurpose::Terminal => "terminal",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
+}
+
+/// Content type of the focused input
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ContentType {
+    pub purpose: ContentPurpose,
+    /// `sensitive_data` hint: typed text must not be stored
+    pub sensitive: bool,
+}
+
+impl ContentType {
+    /// Password/PIN or sensitive input: keystrokes must never reach Neovim
+    pub fn is_secret(&self) -> bool {
+        self.sensitive || matches!(self.purpose, ContentPurpose::Password | ContentPurpose::Pin)
+    }
+
+    /// Whether the IME should stay out of the way (no grab, preedit or popup)
+    pub fn is_direct(&self, direct_purposes: &[String]) -> bool {
+        self.is_secret() || direct_purposes.iter().any(|p| p == self.purpose.name())
+    }
+}
+
+/// Candidates pushed by an external provider (control socket `provider`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExternalCandidates {
+    /// Connection the candidates came from
+    pub provider: u64,
+    /// Provider name, shown in the kind column
+    pub source: String,
+    /// Text before the cursor the candidates replace
+    pub reading: String,
+    pub candidates: Vec<String>,
+    /// Annotation per candidate (may be shorter)
+    pub annotations: Vec<String>,
+}
+
 /// IME state including mode, preedit, and candidates
 pub struct ImeState {
     /// Current IME mode
@@ -42,14 +199,69 @@ pub struct ImeState {
     pub cursor_begin: usize,
     /// Cursor end position (byte offset)
     pub cursor_end: usize,
-    /// Completion candidates
-    pub candidates: Vec<String>,
+    /// Conversion segment of the preedit (underlined in the popup)
+    pub segment: Option<PreeditSegment>,
+    /// Search matches in the preedit (byte ranges, highlighted in the popup)
+    pub highlights: Vec<(usize, usize)>,
+    /// Completion candidates (shared with the popup without copying)
+    pub candidates: Arc<[String]>,
+    /// Reading per candidate, for furigana (may be empty)
+    pub readings: Arc<[String]>,
+    /// Annotation per candidate (may be empty)
+    pub annotations: Arc<[String]>,
+    /// Kind per candidate, for the kind column (may be empty)
+    pub kinds: Arc<[String]>,
     /// Selected candidate index
     pub selected_candidate: usize,
+    /// Index of the first shown candidate in the whole list, when the backend
+    /// sent only a window of a long one
+    pub candidate_offset: usize,
+    /// Length of the whole list
+    pub candidate_total: usize,
+    /// Window of a long list asked for and not received yet
+    window_requested: Option<CandidateWindow>,
+    /// Local filter narrowing the candidates (None = showing the full list)
+    filter: Option<CandidateFilter>,
+    /// Candidates as the backend listed them
+    backend_candidates: Arc<[String]>,
+    /// The backend's list as shown, before external candidates were added
+    backend_shown: CandidateInfo,
+    /// Candidates of external providers, shown after the backend's while
+    /// their reading ends the text before the cursor
+    external: Vec<ExternalCandidates>,
+    /// Backend index of each shown candidate when `behavior.learn_candidates`
+    /// reordered the list (empty = the backend's order)
+    order: Vec<usize>,
+    /// Text the candidate list completes, when it arrived
+    candidate_base: Option<String>,
+    /// Screen cells of the text the candidates complete, ending at the cursor
+    candidate_base_width: Option<usize>,
     /// Transient message shown in candidate area (e.g., command output)
     pub transient_message: Option<String>,
     /// When the transient message was set
     transient_message_at: Option<Instant>,
+    /// Session input mode (kept across enable/disable, re-sent on Neovim respawn)
+    pub input_mode: InputMode,
+    /// Latest surrounding text of the focused input (None = not reported)
+    pub surrounding: Option<SurroundingText>,
+    /// Content type of the focused input (default until reported)
+    pub content_type: ContentType,
+    /// Single candidate auto-confirmed in this composition (not confirmed
+    /// again, so undoing the confirmation brings the popup back)
+    auto_confirmed: Option<String>,
+    /// Composition set aside when the text input lost focus
+    /// (behavior.on_focus_loss = "keep")
+    kept: Option<KeptComposition>,
+}
+
+/// Preedit kept across a focus loss, for the app it was typed in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeptComposition {
+    /// Focused app when it was kept (None if unknown)
+    pub app: Option<String>,
+    pub preedit: String,
+    pub cursor_begin: usize,
+    pub cursor_end: usize,
 }
 
 impl ImeState {
@@ -60,13 +272,53 @@ impl ImeState {
             preedit: String::new(),
             cursor_begin: 0,
             cursor_end: 0,
-            candidates: Vec::new(),
+            segment: None,
+            highlights: Vec::new(),
+            candidates: Arc::default(),
+            readings: Arc::default(),
+            annotations: Arc::default(),
+            kinds: Arc::default(),
             selected_candidate: 0,
+            candidate_offset: 0,
+            candidate_total: 0,
+            window_requested: None,
+            filter: None,
+            backend_candidates: Arc::default(),
+            backend_shown: CandidateInfo::default(),
+            external: Vec::new(),
+            order: Vec::new(),
+            candidate_base: None,
+            candidate_base_width: None,
             transient_message: None,
             transient_message_at: None,
+            input_mode: InputMode::default(),
+            surrounding: None,
+            content_type: ContentType::default(),
+            auto_confirmed: None,
+            kept: None,
         }
     }
 
+    /// Set the preedit aside for `app` until the next activation. False if
+    /// there is nothing to keep.
+    pub fn keep_composition(&mut self, app: Option<String>) -> bool {
+        if self.preedit.is_empty() {
+            return false;
+        }
+        self.kept = Some(KeptComposition {
+            app,
+            preedit: self.preedit.clone(),
+            cursor_begin: self.cursor_begin,
+            cursor_end: self.cursor_end,
+        });
+        true
+    }
+
+    /// The composition set aside by `keep_composition`, if any
+    pub fn take_kept(&mut self) -> Option<KeptComposition> {
+        self.kept.take()
+    }
+
     /// Set a transient message to display in the candidate area
     pub fn set_transient_message(&mut self, text: String) {
         self.transient_message = Some(text);
@@ -106,6 +358,16 @@ impl ImeState {
         matches!(self.mode, ImeMode::Enabled { .. })
     }
 
+    /// Short status name for external reporting (D-Bus)
+    pub fn status(&self) -> &'static str {
+        match self.mode {
+            ImeMode::Disabled => "disabled",
+            ImeMode::Enabling => "enabling",
+            ImeMode::Enabled { .. } => "enabled",
+            ImeMode::Suspended => "suspended",
+        }
+    }
+
     /// Start enabling the IME
     pub fn start_enabling(&mut self) {
         self.mode = ImeMode::Enabling;
@@ -123,6 +385,26 @@ impl ImeState {
         }
     }
 
+    /// Suspend for screen lock. Returns true if the IME was enabled (and now suspended).
+    /// Preedit is kept so it can be shown again on resume.
+    pub fn suspend(&mut self) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        self.mode = ImeMode::Suspended;
+        self.clear_transient_message();
+        true
+    }
+
+    /// Leave suspension, restarting the enable sequence. Returns true if was suspended.
+    pub fn resume(&mut self) -> bool {
+        if self.mode != ImeMode::Suspended {
+            return false;
+        }
+        self.mode = ImeMode::Enabling;
+        true
+    }
+
     /// Disable immediately (for toggle off)
     pub fn disable(&mut self) {
         self.mode = ImeMode::Disabled;
@@ -130,11 +412,14 @@ impl ImeState {
         self.clear_transient_message();
     }
 
-    /// Update preedit
+    /// Update preedit (without a conversion segment or search matches
+    /// until they are set)
     pub fn set_preedit(&mut self, text: String, cursor_begin: usize, cursor_end: usize) {
         self.preedit = text;
         self.cursor_begin = cursor_begin;
         self.cursor_end = cursor_end;
+        self.segment = None;
+        self.highlights.clear();
     }
 
     /// Clear preedit
@@ -142,21 +427,421 @@ impl ImeState {
         self.preedit.clear();
         self.cursor_begin = 0;
         self.cursor_end = 0;
+        self.segment = None;
+        self.highlights.clear();
+        self.auto_confirmed = None;
+    }
+
+    /// Show a candidate list from the backend, the most-chosen candidates
+    /// first when `history` knows any (`behavior.learn_candidates`; lists
+    /// longer than `CANDIDATE_WINDOW` keep the backend's order). Returns the
+    /// backend index to select when a new list now starts with another
+    /// candidate than the backend's first, so that it is the one accepted.
+    pub fn receive_candidates(
+        &mut self,
+        info: CandidateInfo,
+        history: Option<&CandidateHistory>,
+    ) -> Option<usize> {
+        // Selection updates resend the same list (or window of it)
+        let new_list = info.candidates != self.backend_candidates;
+        if new_list {
+            self.window_requested = None;
+            self.candidate_base = self
+                .base_start(info.base_width)
+                .map(|start| self.preedit[start..self.cursor_begin].to_string());
+            self.order = history
+                .filter(|_| info.total() <= CANDIDATE_WINDOW)
+                .map(|h| h.order(&info, self.candidate_base.as_deref()))
+                .unwrap_or_default();
+            self.backend_candidates = info.candidates.clone();
+        } else if self.filter.is_none() && self.external.is_empty() && !self.candidates.is_empty() {
+            // Only the selection moved: the shown lists stay as they are,
+            // however long (no reordering or copying per selection)
+            let selected = self.shown_index(info.selected);
+            self.backend_shown.selected = selected;
+            self.backend_shown.base_width = info.base_width;
+            self.selected_candidate = selected;
+            self.candidate_base_width = info.base_width;
+            return None;
+        }
+        let Some(&first) = self.order.first() else {
+            self.set_candidates(info);
+            return None;
+        };
+        let sync = new_list && info.selected == 0 && first != 0;
+        let selected = if sync {
+            0
+        } else {
+            self.shown_index(info.selected)
+        };
+        self.set_candidates(info.pick(&self.order, selected));
+        sync.then_some(first)
+    }
+
+    /// Shown index of backend candidate `index`
+    fn shown_index(&self, index: usize) -> usize {
+        if self.order.is_empty() {
+            return index;
+        }
+        self.order.iter().position(|&i| i == index).unwrap_or(0)
+    }
+
+    /// Backend index of shown candidate `index`
+    pub fn backend_index(&self, index: usize) -> usize {
+        self.order.get(index).copied().unwrap_or(index) + self.backend_shown.offset
+    }
+
+    /// Part of a long candidate list to ask the backend for: all of it while
+    /// filtering or with external candidates, otherwise a window around the
+    /// selection once it comes within a quarter window of an edge the list
+    /// goes on past. None while the window shown will do, or the one needed
+    /// was asked for already.
+    pub fn candidate_window_request(&mut self) -> Option<CandidateWindow> {
+        let shown = &self.backend_shown;
+        if !shown.is_window() {
+            return None;
+        }
+        let window = if self.filter.is_some() || !self.external.is_empty() {
+            CandidateWindow::Whole
+        } else {
+            let margin = CANDIDATE_WINDOW / 4;
+            let count = shown.candidates.len();
+            let near_start = shown.offset > 0 && self.selected_candidate < margin;
+            let near_end =
+                shown.offset + count < shown.total && self.selected_candidate + margin >= count;
+            if !near_start && !near_end {
+                return None;
+            }
+            CandidateWindow::around(shown.offset + self.selected_candidate, shown.total)
+        };
+        if self.window_requested == Some(window) {
+            return None;
+        }
+        self.window_requested = Some(window);
+        Some(window)
+    }
+
+    /// Reading and text of shown candidate `index`, to learn its accept
+    pub fn candidate_choice(&self, index: usize) -> Option<(&str, &str)> {
+        let candidate = self.candidates.get(index)?;
+        let reading =
+            candidate_history::reading(&self.readings, index, self.candidate_base.as_deref())?;
+        Some((reading, candidate))
     }
 
-    /// Update candidates (clears any transient message — candidates take priority)
-    pub fn set_candidates(&mut self, candidates: Vec<String>, selected: usize) {
-        self.candidates = candidates;
-        self.selected_candidate = selected;
+    /// Update candidates (clears any transient message — candidates take priority).
+    /// External candidates that apply follow the list. While filtering, the
+    /// new list is filtered with the same query.
+    pub fn set_candidates(&mut self, info: CandidateInfo) {
+        self.backend_shown = info;
+        let info = self.with_external(self.backend_shown.clone());
+        let info = match self.filter {
+            Some(ref mut filter) => {
+                filter.set_full(info);
+                filter.view()
+            }
+            None => info,
+        };
+        self.show_candidates(info);
+    }
+
+    fn show_candidates(&mut self, info: CandidateInfo) {
+        self.candidate_total = info.total();
+        self.candidates = info.candidates;
+        self.readings = info.readings;
+        self.annotations = info.annotations;
+        self.kinds = info.kinds;
+        self.selected_candidate = info.selected;
+        self.candidate_offset = info.offset;
+        self.candidate_base_width = info.base_width;
         if !self.candidates.is_empty() {
             self.clear_transient_message();
         }
     }
 
+    /// Whether `info` is a single candidate to confirm without showing it
+    /// (`behavior.auto_confirm_single`). Each candidate is auto-confirmed once
+    /// per composition.
+    pub fn take_auto_confirm(&mut self, info: &CandidateInfo) -> bool {
+        let [candidate] = &info.candidates[..] else {
+            return false;
+        };
+        if self.auto_confirmed.as_ref() == Some(candidate) {
+            return false;
+        }
+        self.auto_confirmed = Some(candidate.clone());
+        true
+    }
+
+    /// Preedit through the cursor, if it ends with one of `marks` and should be
+    /// committed on its own (`behavior.punctuation_commit`): a line cursor
+    /// right after the mark, no candidates open and no henkan marker (▽▼)
+    /// before it, which would still be converting
+    pub fn punctuation_commit(&self, marks: &[char]) -> Option<&str> {
+        if self.cursor_begin != self.cursor_end || !self.candidates.is_empty() {
+            return None;
+        }
+        let before = self.preedit.get(..self.cursor_begin)?;
+        let mark = before.chars().next_back()?;
+        (marks.contains(&mark) && !before.contains(['▽', '▼'])).then_some(before)
+    }
+
+    /// Start filtering the candidates locally. False without candidates.
+    pub fn start_filter(&mut self) -> bool {
+        if self.candidates.is_empty() {
+            return false;
+        }
+        self.filter = Some(CandidateFilter::new(self.candidate_info()));
+        true
+    }
+
+    /// Filter query while filtering
+    pub fn filter_query(&self) -> Option<&str> {
+        self.filter.as_ref().map(CandidateFilter::query)
+    }
+
+    /// Type `c` into the filter query
+    pub fn push_filter(&mut self, c: char) {
+        if let Some(ref mut filter) = self.filter {
+            filter.push(c);
+            let view = filter.view();
+            self.show_candidates(view);
+        }
+    }
+
+    /// Delete the last character of the filter query. False if it was empty.
+    pub fn pop_filter(&mut self) -> bool {
+        let Some(ref mut filter) = self.filter else {
+            return false;
+        };
+        if !filter.pop() {
+            return false;
+        }
+        let view = filter.view();
+        self.show_candidates(view);
+        true
+    }
+
+    /// Stop filtering and show the full list again
+    pub fn cancel_filter(&mut self) {
+        if let Some(filter) = self.filter.take() {
+            let selected = filter.full_index(self.selected_candidate).unwrap_or(0);
+            self.show_candidates(filter.into_full(selected));
+        }
+    }
+
+    /// Stop filtering to accept shown candidate `index`: its index in the
+    /// full list, which is shown again meanwhile. None if `index` is past
+    /// the matches.
+    pub fn finish_filter(&mut self, index: usize) -> Option<usize> {
+        let full_index = self.filter.as_ref()?.full_index(index)?;
+        let filter = self.filter.take()?;
+        self.show_candidates(filter.into_full(full_index));
+        Some(full_index)
+    }
+
+    /// Replace the candidates of `external.provider` (an empty list
+    /// withdraws them) and show the list again
+    pub fn set_external(&mut self, external: ExternalCandidates) {
+        self.external.retain(|e| e.provider != external.provider);
+        if !external.candidates.is_empty() {
+            self.external.push(external);
+        }
+        self.refresh_external();
+    }
+
+    /// Drop the candidates of a provider that disconnected
+    pub fn remove_external(&mut self, provider: u64) {
+        self.external.retain(|e| e.provider != provider);
+        self.refresh_external();
+    }
+
+    /// Show the backend's list again with the external candidates that
+    /// apply to the current preedit, keeping the selection where it can
+    pub fn refresh_external(&mut self) {
+        if self.filter.is_some() {
+            // The filter's list is updated with the next backend list
+            return;
+        }
+        let selected = self.selected_candidate;
+        let info = self.with_external(self.backend_shown.clone());
+        let selected = if selected < info.candidates.len() {
+            selected
+        } else {
+            self.backend_shown.selected
+        };
+        self.show_candidates(CandidateInfo { selected, ..info });
+    }
+
+    /// `info` followed by the external candidates whose reading ends the
+    /// text before the cursor (and that `info` doesn't list already). A
+    /// window of a long list gets them once the whole list arrives.
+    fn with_external(&self, info: CandidateInfo) -> CandidateInfo {
+        if info.is_window() {
+            return info;
+        }
+        let before = self.preedit.get(..self.cursor_begin).unwrap_or("");
+        let mut applying = self
+            .external
+            .iter()
+            .filter(|e| !e.reading.is_empty() && before.ends_with(&e.reading))
+            .peekable();
+        if applying.peek().is_none() {
+            return info;
+        }
+        let count = info.candidates.len();
+        let column = |list: &[String]| {
+            let mut column = list.to_vec();
+            column.resize(count, String::new());
+            column
+        };
+        let mut candidates = info.candidates.to_vec();
+        let mut readings = column(&info.readings);
+        let mut annotations = column(&info.annotations);
+        let mut kinds = column(&info.kinds);
+        for external in applying {
+            for (i, candidate) in external.candidates.iter().enumerate() {
+                if candidates.contains(candidate) {
+                    continue;
+                }
+                candidates.push(candidate.clone());
+                readings.push(external.reading.clone());
+                annotations.push(external.annotations.get(i).cloned().unwrap_or_default());
+                kinds.push(external.source.clone());
+            }
+        }
+        CandidateInfo {
+            candidates: candidates.into(),
+            readings: readings.into(),
+            annotations: annotations.into(),
+            kinds: kinds.into(),
+            ..info
+        }
+    }
+
+    /// Whether shown candidate `index` came from an external provider
+    fn is_external(&self, index: usize) -> bool {
+        let full_index = match self.filter {
+            Some(ref filter) => filter.full_index(index),
+            None => Some(index),
+        };
+        full_index.is_some_and(|i| i >= self.backend_shown.candidates.len())
+    }
+
+    /// Byte offset in the preedit where the reading of external candidate
+    /// `index` starts
+    fn external_start(&self, index: usize) -> Option<usize> {
+        let reading = self.readings.get(index)?;
+        let start = self.cursor_begin.checked_sub(reading.len())?;
+        (self.preedit.get(start..self.cursor_begin)? == reading).then_some(start)
+    }
+
+    /// Preedit and cursor after accepting shown candidate `index` if it is
+    /// an external one: the candidate in place of its reading
+    pub fn external_choice(&self, index: usize) -> Option<(String, usize)> {
+        if !self.is_external(index) {
+            return None;
+        }
+        let candidate = self.candidates.get(index)?;
+        let start = self.external_start(index)?;
+        let text = format!(
+            "{}{}{}",
+            &self.preedit[..start],
+            candidate,
+            &self.preedit[self.cursor_begin..]
+        );
+        Some((text, start + candidate.len()))
+    }
+
+    /// The shown candidates as the backend would send them
+    fn candidate_info(&self) -> CandidateInfo {
+        CandidateInfo {
+            candidates: self.candidates.clone(),
+            readings: self.readings.clone(),
+            annotations: self.annotations.clone(),
+            kinds: self.kinds.clone(),
+            selected: self.selected_candidate,
+            base_width: self.candidate_base_width,
+            offset: self.candidate_offset,
+            total: self.candidate_total,
+        }
+    }
+
     /// Clear candidates
     pub fn clear_candidates(&mut self) {
-        self.candidates.clear();
+        self.filter = None;
+        self.backend_candidates = Arc::default();
+        self.backend_shown = CandidateInfo::default();
+        self.order.clear();
+        self.candidate_base = None;
+        self.candidates = Arc::default();
+        self.readings = Arc::default();
+        self.annotations = Arc::default();
+        self.kinds = Arc::default();
         self.selected_candidate = 0;
+        self.candidate_offset = 0;
+        self.candidate_total = 0;
+        self.window_requested = None;
+        self.candidate_base_width = None;
+    }
+
+    /// Preedit with the selected candidate in place of the text it completes,
+    /// and the candidate's byte range (`behavior.candidate_preview`). None
+    /// without a selection or when the completed text isn't in the preedit.
+    pub fn candidate_preview(&self) -> Option<(String, usize, usize)> {
+        let candidate = self.candidates.get(self.selected_candidate)?;
+        let start = if self.is_external(self.selected_candidate) {
+            self.external_start(self.selected_candidate)?
+        } else {
+            self.base_start(self.candidate_base_width)?
+        };
+        let text = format!(
+            "{}{}{}",
+            &self.preedit[..start],
+            candidate,
+            &self.preedit[self.cursor_begin..]
+        );
+        Some((text, start, start + candidate.len()))
+    }
+
+    /// Byte offset in the preedit where the `base_width` cells before the
+    /// cursor start (the text a completion replaces)
+    fn base_start(&self, base_width: Option<usize>) -> Option<usize> {
+        let before = self.preedit.get(..self.cursor_begin)?;
+        let mut cells = base_width?;
+        let mut start = before.len();
+        for (i, c) in before.char_indices().rev() {
+            if cells == 0 {
+                break;
+            }
+            cells = cells.checked_sub(cell_width(c))?;
+            start = i;
+        }
+        (cells == 0).then_some(start)
+    }
+}
+
+/// Screen cells Neovim gives `c`: two for East Asian wide and fullwidth
+/// characters, none for combining marks and joiners
+fn cell_width(c: char) -> usize {
+    match c {
+        '\u{0300}'..='\u{036F}' | '\u{3099}'..='\u{309A}' | '\u{200B}'..='\u{200F}' => 0,
+        '\u{FE00}'..='\u{FE0F}' => 0,
+        '\u{1100}'..='\u{115F}'
+        | '\u{2E80}'..='\u{303E}'
+        | '\u{3041}'..='\u{33FF}'
+        | '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{A000}'..='\u{A4CF}'
+        | '\u{AC00}'..='\u{D7A3}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{FE30}'..='\u{FE4F}'
+        | '\u{FF00}'..='\u{FF60}'
+        | '\u{FFE0}'..='\u{FFE6}'
+        | '\u{1F300}'..='\u{1F64F}'
+        | '\u{1F900}'..='\u{1F9FF}'
+        | '\u{20000}'..='\u{3FFFD}' => 2,
+        _ => 1,
     }
 }
 
@@ -170,6 +855,71 @@ impl Default for ImeState {
 mod tests {
     use super::*;
 
+    #[test]
+    fn surrounding_text_splits_at_cursor() {
+        let s = SurroundingText::new("今日は晴れ".to_string(), 9, 9);
+        assert_eq!(s.before(), "今日は");
+        assert_eq!(s.after(), "晴れ");
+    }
+
+    #[test]
+    fn surrounding_offsets_snap_to_char_boundary() {
+        // 10 is inside "晴"; 100 is past the end
+        let s = SurroundingText::new("今日は晴れ".to_string(), 10, 100);
+        assert_eq!(s.cursor, 9);
+        assert_eq!(s.anchor, 15);
+    }
+
+    #[test]
+    fn delete_surrounding_clamped_to_existing_text() {
+        let s = SurroundingText::new("abcあい".to_string(), 3, 3);
+        assert_eq!(s.clamp_delete(2, 3), (2, 3));
+        assert_eq!(s.clamp_delete(10, 10), (3, 6));
+        // Partial character after the cursor is not deleted
+        assert_eq!(s.clamp_delete(0, 4), (0, 3));
+
+        let s = SurroundingText::new("あいう".to_string(), 6, 6);
+        assert_eq!(s.clamp_delete(4, 0), (3, 0));
+    }
+
+    #[test]
+    fn password_and_pin_are_secret() {
+        for purpose in [ContentPurpose::Password, ContentPurpose::Pin] {
+            let ct = ContentType {
+                purpose,
+                sensitive: false,
+            };
+            assert!(ct.is_secret());
+            assert!(ct.is_direct(&[]));
+        }
+        let sensitive = ContentType {
+            purpose: ContentPurpose::Normal,
+            sensitive: true,
+        };
+        assert!(sensitive.is_secret());
+        assert!(!ContentType::default().is_direct(&[]));
+    }
+
+    #[test]
+    fn direct_purposes_from_config() {
+        let direct = vec!["email".to_string(), "url".to_string()];
+        let email = ContentType {
+            purpose: ContentPurpose::Email,
+            sensitive: false,
+        };
+        assert!(email.is_direct(&direct));
+        assert!(!email.is_secret());
+        assert!(!ContentType::default().is_direct(&direct));
+    }
+
+    #[test]
+    fn purpose_names_roundtrip() {
+        for purpose in ContentPurpose::ALL {
+            assert_eq!(ContentPurpose::from_name(purpose.name()), Some(purpose));
+        }
+        assert_eq!(ContentPurpose::from_name("mail"), None);
+    }
+
     #[test]
     fn starts_disabled() {
         let state = ImeState::new();
@@ -191,6 +941,18 @@ mod tests {
         assert!(state.is_fully_enabled());
     }
 
+    #[test]
+    fn status_follows_mode() {
+        let mut state = ImeState::new();
+        assert_eq!(state.status(), "disabled");
+        state.start_enabling();
+        assert_eq!(state.status(), "enabling");
+        state.complete_enabling(VimMode::Insert);
+        assert_eq!(state.status(), "enabled");
+        state.suspend();
+        assert_eq!(state.status(), "suspended");
+    }
+
     #[test]
     fn complete_enabling_only_from_enabling() {
         let mut state = ImeState::new();
@@ -231,6 +993,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn suspend_and_resume_keep_preedit() {
+        let mut ime = ImeState::new();
+        ime.start_enabling();
+        ime.complete_enabling(VimMode::Insert);
+        ime.set_preedit("かな".to_string(), 6, 6);
+
+        assert!(ime.suspend());
+        assert_eq!(ime.mode, ImeMode::Suspended);
+        assert!(!ime.is_enabled());
+        assert_eq!(ime.preedit, "かな");
+
+        assert!(ime.resume());
+        assert_eq!(ime.mode, ImeMode::Enabling);
+        assert_eq!(ime.preedit, "かな");
+    }
+
+    #[test]
+    fn suspend_when_disabled_is_noop() {
+        let mut ime = ImeState::new();
+        assert!(!ime.suspend());
+        assert_eq!(ime.mode, ImeMode::Disabled);
+        assert!(!ime.resume());
+        assert_eq!(ime.mode, ImeMode::Disabled);
+    }
+
     #[test]
     fn disable_clears_preedit() {
         let mut state = ImeState::new();
@@ -261,12 +1049,265 @@ mod tests {
     #[test]
     fn candidate_operations() {
         let mut state = ImeState::new();
-        state.set_candidates(vec!["a".into(), "b".into()], 1);
+        state.set_candidates(
+            CandidateInfo::new(vec!["a".into(), "b".into()], 1)
+                .with_readings(vec!["x".into(), "y".into()])
+                .with_annotations(vec!["note".into()])
+                .with_kinds(vec!["Text".into(), "Snippet".into()]),
+        );
         assert_eq!(state.candidates.len(), 2);
+        assert_eq!(state.readings.len(), 2);
+        assert_eq!(state.annotations.len(), 1);
+        assert_eq!(state.kinds.len(), 2);
         assert_eq!(state.selected_candidate, 1);
 
         state.clear_candidates();
         assert!(state.candidates.is_empty());
+        assert!(state.readings.is_empty());
+        assert!(state.annotations.is_empty());
+        assert!(state.kinds.is_empty());
+        assert_eq!(state.selected_candidate, 0);
+    }
+
+    #[test]
+    fn filter_narrows_and_gives_the_full_list_back() {
+        let mut state = ImeState::new();
+        assert!(!state.start_filter());
+        state.set_candidates(CandidateInfo::new(
+            vec!["print".into(), "format".into(), "eprint".into()],
+            1,
+        ));
+        assert!(state.start_filter());
+        assert_eq!(state.filter_query(), Some(""));
+        state.push_filter('p');
+        assert_eq!(&state.candidates[..], ["print", "eprint"]);
         assert_eq!(state.selected_candidate, 0);
+
+        // A new list from the backend is filtered too
+        state.set_candidates(CandidateInfo::new(vec!["map".into(), "eprintln".into()], 0));
+        assert_eq!(&state.candidates[..], ["map", "eprintln"]);
+        state.push_filter('r');
+        assert_eq!(&state.candidates[..], ["eprintln"]);
+        assert_eq!(state.finish_filter(1), None);
+        assert_eq!(state.finish_filter(0), Some(1));
+        assert_eq!(state.filter_query(), None);
+        assert_eq!(state.candidates.len(), 2);
+        assert_eq!(state.selected_candidate, 1);
+
+        state.start_filter();
+        assert!(!state.pop_filter());
+        state.push_filter('z');
+        assert!(state.candidates.is_empty());
+        state.cancel_filter();
+        assert_eq!(state.candidates.len(), 2);
+        state.start_filter();
+        state.clear_candidates();
+        assert_eq!(state.filter_query(), None);
+    }
+
+    #[test]
+    fn auto_confirm_once_per_composition() {
+        let mut state = ImeState::new();
+        let single = CandidateInfo::new(vec!["漢字".into()], 0);
+        assert!(!state.take_auto_confirm(&CandidateInfo::new(vec!["a".into(), "b".into()], 0)));
+        assert!(state.take_auto_confirm(&single));
+        // Undone: the same candidate comes back and is shown instead
+        assert!(!state.take_auto_confirm(&single));
+        assert!(state.take_auto_confirm(&CandidateInfo::new(vec!["感じ".into()], 0)));
+
+        // A new composition
+        state.clear_preedit();
+        assert!(state.take_auto_confirm(&single));
+        assert!(!state.take_auto_confirm(&CandidateInfo::empty()));
+    }
+
+    #[test]
+    fn punctuation_commit_takes_text_through_the_mark() {
+        let marks = ['。', '、'];
+        let mut ime = ImeState::new();
+        ime.set_preedit("今日は、".to_string(), 12, 12);
+        assert_eq!(ime.punctuation_commit(&marks), Some("今日は、"));
+        // Text after the cursor stays
+        ime.set_preedit("晴れ。です".to_string(), 9, 9);
+        assert_eq!(ime.punctuation_commit(&marks), Some("晴れ。"));
+        ime.set_preedit("晴れ。".to_string(), 9, 9);
+        assert_eq!(ime.punctuation_commit(&['、']), None);
+        assert_eq!(ime.punctuation_commit(&[]), None);
+    }
+
+    #[test]
+    fn punctuation_commit_waits_for_conversion() {
+        let marks = ['。'];
+        let mut ime = ImeState::new();
+        // Cursor not after the mark, block cursor, mid-character offset
+        ime.set_preedit("あ。い".to_string(), 9, 9);
+        assert_eq!(ime.punctuation_commit(&marks), None);
+        ime.set_preedit("あ。".to_string(), 3, 6);
+        assert_eq!(ime.punctuation_commit(&marks), None);
+        ime.set_preedit("あ。".to_string(), 5, 5);
+        assert_eq!(ime.punctuation_commit(&marks), None);
+        // skkeleton still converting
+        ime.set_preedit("▽かんじ。".to_string(), 15, 15);
+        assert_eq!(ime.punctuation_commit(&marks), None);
+        // Candidates open
+        ime.set_preedit("漢字。".to_string(), 9, 9);
+        ime.set_candidates(CandidateInfo::new(vec!["漢字。".to_string()], 0));
+        assert_eq!(ime.punctuation_commit(&marks), None);
+    }
+
+    #[test]
+    fn candidate_preview_replaces_completed_text() {
+        let mut ime = ImeState::new();
+        // "かんじ" (6 cells) before the cursor, "です" after it
+        ime.set_preedit("わかんじです".to_string(), 12, 12);
+        assert_eq!(ime.candidate_preview(), None);
+        let candidates = vec!["漢字".to_string(), "感じ".to_string()];
+        ime.set_candidates(CandidateInfo::new(candidates.clone(), 1).with_base_width(Some(6)));
+        assert_eq!(
+            ime.candidate_preview(),
+            Some(("わ感じです".to_string(), 3, 9))
+        );
+        // Narrow text counts one cell per character
+        ime.set_preedit("a kan".to_string(), 5, 5);
+        ime.set_candidates(CandidateInfo::new(candidates.clone(), 0).with_base_width(Some(3)));
+        assert_eq!(ime.candidate_preview(), Some(("a 漢字".to_string(), 2, 8)));
+    }
+
+    #[test]
+    fn candidate_preview_needs_the_completed_text() {
+        let mut ime = ImeState::new();
+        ime.set_preedit("かんじ".to_string(), 9, 9);
+        // Command-line completion, more cells than the preedit, half a wide char
+        for base_width in [None, Some(8), Some(5)] {
+            ime.set_candidates(
+                CandidateInfo::new(vec!["漢字".to_string()], 0).with_base_width(base_width),
+            );
+            assert_eq!(ime.candidate_preview(), None, "{base_width:?}");
+        }
+        ime.set_candidates(
+            CandidateInfo::new(vec!["漢字".to_string()], 0).with_base_width(Some(6)),
+        );
+        assert_eq!(ime.candidate_preview(), Some(("漢字".to_string(), 0, 6)));
+        ime.clear_candidates();
+        assert_eq!(ime.candidate_preview(), None);
+    }
+
+    #[test]
+    fn external_candidates_follow_the_backend_list() {
+        let mut ime = ImeState::new();
+        ime.set_preedit("わたしはたなか".to_string(), 21, 21);
+        ime.set_candidates(
+            CandidateInfo::new(vec!["田中".to_string(), "棚化".to_string()], 0)
+                .with_base_width(Some(6)),
+        );
+        ime.set_external(ExternalCandidates {
+            provider: 1,
+            source: "names".to_string(),
+            reading: "たなか".to_string(),
+            candidates: vec!["田中".to_string(), "多中".to_string()],
+            annotations: vec![String::new(), "姓".to_string()],
+        });
+        // The duplicate is left out; the rest is tagged with the provider
+        assert_eq!(&*ime.candidates, ["田中", "棚化", "多中"]);
+        assert_eq!(&*ime.kinds, ["", "", "names"]);
+        assert_eq!(&*ime.annotations, ["", "", "姓"]);
+        assert_eq!(ime.external_choice(1), None);
+        assert_eq!(
+            ime.external_choice(2),
+            Some(("わたしは多中".to_string(), 18))
+        );
+        ime.selected_candidate = 2;
+        assert_eq!(
+            ime.candidate_preview(),
+            Some(("わたしは多中".to_string(), 12, 18))
+        );
+
+        // Another reading before the cursor: only the backend's list
+        ime.set_preedit("わたしはたな".to_string(), 18, 18);
+        ime.refresh_external();
+        assert_eq!(ime.candidates.len(), 2);
+        assert_eq!(ime.selected_candidate, 0);
+        ime.set_preedit("たなか".to_string(), 9, 9);
+        ime.clear_candidates();
+        ime.refresh_external();
+        assert_eq!(&*ime.candidates, ["田中", "多中"]);
+        ime.remove_external(1);
+        assert!(ime.candidates.is_empty());
+    }
+
+    #[test]
+    fn learned_candidates_are_shown_first() {
+        let dir =
+            std::env::temp_dir().join(format!("jacin-test-ime-history-{}", std::process::id()));
+        let mut history = CandidateHistory::load(dir.join("candidates.json"));
+        history.record("かんじ", "幹事");
+
+        let mut ime = ImeState::new();
+        ime.set_preedit("かんじ".to_string(), 9, 9);
+        let candidates: Arc<[String]> = ["漢字", "感じ", "幹事"].map(String::from).into();
+        let info =
+            |selected| CandidateInfo::new(candidates.clone(), selected).with_base_width(Some(6));
+        // The backend is asked to select the learned candidate
+        assert_eq!(ime.receive_candidates(info(0), Some(&history)), Some(2));
+        assert_eq!(&ime.candidates[..], ["幹事", "漢字", "感じ"]);
+        assert_eq!(ime.selected_candidate, 0);
+        assert_eq!(ime.candidate_choice(0), Some(("かんじ", "幹事")));
+        // Selection updates of the same list map to the shown order
+        assert_eq!(ime.receive_candidates(info(1), Some(&history)), None);
+        assert_eq!(ime.selected_candidate, 2);
+        assert_eq!(ime.backend_index(2), 1);
+        assert_eq!(ime.backend_index(0), 2);
+
+        // ...without rebuilding the reordered lists
+        let shown = ime.candidates.clone();
+        assert_eq!(ime.receive_candidates(info(0), Some(&history)), None);
+        assert!(Arc::ptr_eq(&ime.candidates, &shown));
+        assert_eq!(ime.selected_candidate, 1);
+
+        ime.clear_candidates();
+        assert_eq!(ime.receive_candidates(info(0), None), None);
+        assert_eq!(ime.candidates[0], "漢字");
+        assert_eq!(ime.backend_index(2), 2);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn long_lists_ask_for_the_window_they_need() {
+        let words: Vec<String> = (0..300).map(|i| format!("w{i}")).collect();
+        let full = CandidateInfo::new(words, 0);
+        let mut ime = ImeState::new();
+        ime.receive_candidates(full.window(CandidateWindow::From(0)), None);
+        assert_eq!(ime.candidates.len(), CANDIDATE_WINDOW);
+        assert_eq!((ime.candidate_offset, ime.candidate_total), (0, 300));
+        assert_eq!(ime.candidate_window_request(), None);
+
+        // Nearing the end of the window asks for the one around the selection, once
+        let select = |selected| {
+            let mut info = full.clone();
+            info.selected = selected;
+            info
+        };
+        ime.receive_candidates(select(50).window(CandidateWindow::From(0)), None);
+        assert_eq!(
+            ime.candidate_window_request(),
+            Some(CandidateWindow::From(50 - CANDIDATE_WINDOW / 2))
+        );
+        assert_eq!(ime.candidate_window_request(), None);
+
+        let next = select(50).window(CandidateWindow::From(18));
+        ime.receive_candidates(next, None);
+        assert_eq!((ime.candidate_offset, ime.selected_candidate), (18, 32));
+        assert_eq!(ime.backend_index(ime.selected_candidate), 50);
+        assert_eq!(ime.candidate_window_request(), None);
+
+        // Filtering needs all of it
+        assert!(ime.start_filter());
+        assert_eq!(ime.candidate_window_request(), Some(CandidateWindow::Whole));
+        ime.push_filter('2');
+        ime.receive_candidates(select(50).window(CandidateWindow::Whole), None);
+        assert_eq!(ime.candidate_total, ime.candidates.len());
+        assert!(ime.candidates.len() > CANDIDATE_WINDOW / 2);
+        assert_eq!(ime.candidate_window_request(), None);
     }
 }
@@ -1,12 +1,27 @@
 //! Keyboard state management
 //!
-//! Handles XKB keymap, modifier tracking, and key debouncing.
+//! Handles XKB keymap, modifier tracking, compose sequences, and key debouncing.
 
 use std::collections::HashSet;
 use std::collections::hash_map::DefaultHasher;
+use std::ffi::OsString;
 use std::hash::{Hash, Hasher};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use xkbcommon::xkb;
+use xkbcommon::xkb::compose;
+
+/// A pressed keysym after the compose state machine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Compose {
+    /// Not part of a sequence: process the key as usual
+    Key,
+    /// Sequence started or continued (dead key, Multi_key): swallow the key
+    Composing,
+    /// Sequence finished: process this keysym and text instead of the key
+    Composed(xkb::Keysym, String),
+    /// Sequence didn't match anything: swallow the key
+    Cancelled,
+}
 
 /// Keyboard state including XKB and modifier tracking
 pub struct KeyboardState {
@@ -18,6 +33,8 @@ pub struct KeyboardState {
     pub ctrl_pressed: bool,
     /// Alt modifier pressed
     pub alt_pressed: bool,
+    /// Shift modifier pressed
+    pub shift_pressed: bool,
     /// Keys that should be ignored (pressed before we were ready)
     pub ignored_keys: HashSet<u32>,
     /// Time when we became ready (for debouncing)
@@ -37,16 +54,27 @@ pub struct KeyboardState {
     pub mods_latched: u32,
     pub mods_locked: u32,
     pub mods_group: u32,
+    /// Compose (dead key / Multi_key) state for the user's locale (None if
+    /// the locale has no compose table)
+    compose: Option<compose::State>,
+    /// Text composed by the key being processed (passthrough types it, since
+    /// replaying the keycode would send only the last key of the sequence)
+    pub composed: Option<String>,
+    /// Tap key pressed with no other key since, and when (tap detection)
+    tap: Option<(u32, Instant)>,
 }
 
 impl KeyboardState {
     /// Create new keyboard state
     pub fn new() -> Self {
+        let xkb_context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let compose = Self::compose_state(&xkb_context);
         Self {
-            xkb_context: xkb::Context::new(xkb::CONTEXT_NO_FLAGS),
+            xkb_context,
             xkb_state: None,
             ctrl_pressed: false,
             alt_pressed: false,
+            shift_pressed: false,
             ignored_keys: HashSet::new(),
             ready_time: None,
             pending_keymap: false,
@@ -58,6 +86,52 @@ impl KeyboardState {
             mods_latched: 0,
             mods_locked: 0,
             mods_group: 0,
+            compose,
+            composed: None,
+            tap: None,
+        }
+    }
+
+    /// Compose state from the locale's table (LC_ALL, LC_CTYPE, LANG)
+    fn compose_state(context: &xkb::Context) -> Option<compose::State> {
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .into_iter()
+            .filter_map(std::env::var_os)
+            .find(|v| !v.is_empty())
+            .unwrap_or_else(|| OsString::from("C"));
+        match compose::Table::new_from_locale(context, &locale, compose::COMPILE_NO_FLAGS) {
+            Ok(table) => Some(compose::State::new(&table, compose::STATE_NO_FLAGS)),
+            Err(()) => {
+                log::info!("[KEY] No compose table for locale {:?}", locale);
+                None
+            }
+        }
+    }
+
+    /// Feed a pressed keysym through the compose state machine
+    pub fn compose(&mut self, keysym: xkb::Keysym) -> Compose {
+        self.composed = None;
+        let Some(state) = self.compose.as_mut() else {
+            return Compose::Key;
+        };
+        // Modifier keys don't take part in sequences
+        if state.feed(keysym) == compose::FeedResult::Ignored {
+            return Compose::Key;
+        }
+        match state.status() {
+            compose::Status::Nothing => Compose::Key,
+            compose::Status::Composing => Compose::Composing,
+            compose::Status::Composed => {
+                let text = state.utf8().unwrap_or_default();
+                let keysym = state.keysym().unwrap_or(keysym);
+                state.reset();
+                self.composed = Some(text.clone());
+                Compose::Composed(keysym, text)
+            }
+            compose::Status::Cancelled => {
+                state.reset();
+                Compose::Cancelled
+            }
         }
     }
 
@@ -97,11 +171,13 @@ impl KeyboardState {
         mods_locked: u32,
         group: u32,
     ) {
+        const SHIFT_MASK: u32 = 0x1;
         const CTRL_MASK: u32 = 0x4;
         const ALT_MASK: u32 = 0x8;
 
         self.ctrl_pressed = (mods_depressed & CTRL_MASK) != 0;
         self.alt_pressed = (mods_depressed & ALT_MASK) != 0;
+        self.shift_pressed = (mods_depressed & SHIFT_MASK) != 0;
 
         // Store raw values for virtual keyboard passthrough
         self.mods_depressed = mods_depressed;
@@ -154,6 +230,27 @@ impl KeyboardState {
         }
     }
 
+    /// Track a key press for tap detection: `tap_key` starts a possible tap,
+    /// any other key ends one (a repeat of the tap key keeps it going)
+    pub fn press_for_tap(&mut self, key: u32, tap_key: bool, now: Instant) {
+        if tap_key && self.tap.is_some_and(|(pressed, _)| pressed == key) {
+            return;
+        }
+        self.tap = tap_key.then_some((key, now));
+    }
+
+    /// Whether releasing `key` finishes a tap: its press, no other key in
+    /// between, released within `timeout` (held longer, it was a modifier)
+    pub fn release_is_tap(&mut self, key: u32, now: Instant, timeout: Duration) -> bool {
+        match self.tap.take() {
+            Some((pressed, at)) if pressed == key => now.duration_since(at) <= timeout,
+            other => {
+                self.tap = other;
+                false
+            }
+        }
+    }
+
     /// Get keysym and UTF-8 for a key
     pub fn get_key_info(&self, key: u32) -> Option<(xkb::Keysym, String)> {
         let xkb_state = self.xkb_state.as_ref()?;
@@ -167,10 +264,16 @@ impl KeyboardState {
     pub fn reset_modifiers(&mut self) {
         self.ctrl_pressed = false;
         self.alt_pressed = false;
+        self.shift_pressed = false;
         self.mods_depressed = 0;
         self.mods_latched = 0;
         self.mods_locked = 0;
         self.mods_group = 0;
+        self.tap = None;
+        // A sequence doesn't survive the grab
+        if let Some(ref mut state) = self.compose {
+            state.reset();
+        }
     }
 
     /// Store compositor repeat info
@@ -179,6 +282,34 @@ impl KeyboardState {
         self.repeat_delay = delay;
     }
 
+    /// Text of the loaded keymap, or of the default keymap (XKB_DEFAULT_* / system
+    /// defaults) if none was received yet
+    pub fn keymap_text(&self) -> Option<String> {
+        let keymap = match &self.xkb_state {
+            Some(state) => state.get_keymap(),
+            None => xkb::Keymap::new_from_names(
+                &self.xkb_context,
+                "",
+                "",
+                "",
+                "",
+                None,
+                xkb::KEYMAP_COMPILE_NO_FLAGS,
+            )?,
+        };
+        Some(keymap.get_as_string(xkb::KEYMAP_FORMAT_TEXT_V1))
+    }
+
+    /// Evdev keycode producing `keysym` without modifiers in the first layout
+    pub fn keycode_for(&self, keysym: xkb::Keysym) -> Option<u32> {
+        let keymap = self.xkb_state.as_ref()?.get_keymap();
+        let (min, max) = (keymap.min_keycode().raw(), keymap.max_keycode().raw());
+        (min.max(8)..=max).find_map(|raw| {
+            let syms = keymap.key_get_syms_by_level(xkb::Keycode::new(raw), 0, 0);
+            syms.contains(&keysym).then_some(raw - 8)
+        })
+    }
+
     /// Check if a key should repeat according to XKB keymap
     pub fn key_repeats(&self, key: u32) -> bool {
         self.xkb_state.as_ref().is_some_and(|state| {
@@ -193,3 +324,93 @@ impl Default for KeyboardState {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_table(rules: &str) -> KeyboardState {
+        let mut keyboard = KeyboardState::new();
+        let table = compose::Table::new_from_buffer(
+            &keyboard.xkb_context,
+            rules,
+            "C",
+            compose::FORMAT_TEXT_V1,
+            compose::COMPILE_NO_FLAGS,
+        )
+        .expect("compose rules parse");
+        keyboard.compose = Some(compose::State::new(&table, compose::STATE_NO_FLAGS));
+        keyboard
+    }
+
+    #[test]
+    fn dead_key_sequences() {
+        use xkb::Keysym;
+
+        let mut keyboard =
+            with_table("<dead_acute> <e> : \"é\" eacute\n<Multi_key> <o> <c> : \"©\" copyright\n");
+        assert_eq!(keyboard.compose(Keysym::dead_acute), Compose::Composing);
+        // Shift pressed mid-sequence doesn't cancel it
+        assert_eq!(keyboard.compose(Keysym::Shift_L), Compose::Key);
+        assert_eq!(
+            keyboard.compose(Keysym::e),
+            Compose::Composed(Keysym::eacute, "é".into())
+        );
+        assert_eq!(keyboard.composed.as_deref(), Some("é"));
+
+        assert_eq!(keyboard.compose(Keysym::Multi_key), Compose::Composing);
+        assert_eq!(keyboard.compose(Keysym::o), Compose::Composing);
+        assert_eq!(
+            keyboard.compose(Keysym::c),
+            Compose::Composed(Keysym::copyright, "©".into())
+        );
+
+        // Plain keys pass, unknown sequences are swallowed
+        assert_eq!(keyboard.compose(Keysym::a), Compose::Key);
+        assert_eq!(keyboard.composed, None);
+        assert_eq!(keyboard.compose(Keysym::dead_acute), Compose::Composing);
+        assert_eq!(keyboard.compose(Keysym::q), Compose::Cancelled);
+        assert_eq!(keyboard.compose(Keysym::q), Compose::Key);
+    }
+
+    #[test]
+    fn releasing_the_grab_resets_a_sequence() {
+        use xkb::Keysym;
+
+        let mut keyboard = with_table("<dead_acute> <e> : \"é\" eacute\n");
+        assert_eq!(keyboard.compose(Keysym::dead_acute), Compose::Composing);
+        keyboard.reset_modifiers();
+        assert_eq!(keyboard.compose(Keysym::e), Compose::Key);
+    }
+
+    #[test]
+    fn tap_is_a_quick_press_and_release_alone() {
+        const ALT_R: u32 = 100;
+        const A: u32 = 30;
+        let timeout = Duration::from_millis(300);
+        let start = Instant::now();
+        let after = |ms| start + Duration::from_millis(ms);
+        let mut keyboard = KeyboardState::new();
+
+        keyboard.press_for_tap(ALT_R, true, start);
+        assert!(keyboard.release_is_tap(ALT_R, after(100), timeout));
+        // Counted once
+        assert!(!keyboard.release_is_tap(ALT_R, after(120), timeout));
+
+        // Held as a modifier for another key
+        keyboard.press_for_tap(ALT_R, true, start);
+        keyboard.press_for_tap(A, false, after(50));
+        assert!(!keyboard.release_is_tap(A, after(80), timeout));
+        assert!(!keyboard.release_is_tap(ALT_R, after(100), timeout));
+
+        // Held too long, even if it repeats
+        keyboard.press_for_tap(ALT_R, true, start);
+        keyboard.press_for_tap(ALT_R, true, after(350));
+        assert!(!keyboard.release_is_tap(ALT_R, after(400), timeout));
+
+        // Releasing a key held from before doesn't end the tap
+        keyboard.press_for_tap(ALT_R, true, start);
+        assert!(!keyboard.release_is_tap(A, after(50), timeout));
+        assert!(keyboard.release_is_tap(ALT_R, after(100), timeout));
+    }
+}
@@ -1,10 +1,19 @@
 //! Keypress display state
 //!
 //! Tracks accumulated key sequences for visual feedback during Vim-style input.
+//! In normal and operator-pending mode a command is shown as one entry: a
+//! count prefix, the operator and its motion (with the motion's own count)
+//! accumulate into it, e.g. `12dd` or `d2w`.
+//!
+//! Macros get a row of their own: the keys typed since the recording started,
+//! and while `@x` plays back (longer than a key's wait), the register's
+//! contents after a "▶ @x" indicator.
 
+use std::borrow::Borrow;
 use std::time::{Duration, Instant};
 
-use crate::neovim::PendingState;
+use crate::keysym::split_keys;
+use crate::neovim::{MacroRegister, PendingState};
 
 /// Duration of inactivity before all keypress entries are cleared
 pub const KEYPRESS_DISPLAY_DURATION: Duration = Duration::from_millis(1500);
@@ -25,16 +34,38 @@ pub struct KeypressState {
     entries: Vec<KeypressEntry>,
     /// Timestamp of the last entry addition (None when empty)
     last_added_at: Option<Instant>,
+    /// Display timed out while Neovim was still waiting for input: the entries
+    /// stay and a hint is shown instead
+    awaiting: bool,
+    /// The last entry is a command still being typed (after a count digit, or
+    /// an operator awaiting its motion): the next key joins it
+    building: bool,
+    /// The last key was a count digit, so `0` continues the count
+    counting: bool,
     /// Pending mode type
     pub pending_type: PendingState,
     /// Current vim mode string (i, n, v, no, etc.)
     pub vim_mode: String,
     /// Currently recording macro register ("" when not recording)
     pub recording: String,
+    /// Keys sent since the recording started
+    macro_keys: Vec<String>,
+    /// Register last recorded into, with its contents (from the snapshots)
+    pub last_macro: Option<MacroRegister>,
+    /// Register `@@` plays
+    last_played: Option<String>,
+    /// Macro Neovim is playing back (until the key's KeyProcessed)
+    playback: Option<Playback>,
+    /// Undo sequence number of the buffer (shown as `u<n>`)
+    pub undo_seq: u64,
+    /// Whether `.` has an insert to repeat
+    pub repeatable: bool,
     /// Command-line cursor byte offset within display_text (None when not in cmdline)
     cmdline_cursor_byte: Option<usize>,
-    /// Byte length of command-line prefix (firstc or prompt)
+    /// Byte length of command-line prefix (firstc)
     cmdline_prefix_len: usize,
+    /// `input()` prompt, shown on its own row above the text ("" for `:`/`/`)
+    cmdline_prompt: String,
     /// Active command-line level for guard (None when not in cmdline)
     cmdline_level: Option<u64>,
 }
@@ -45,21 +76,38 @@ impl KeypressState {
         Self {
             entries: Vec::new(),
             last_added_at: None,
+            awaiting: false,
+            building: false,
+            counting: false,
             pending_type: PendingState::None,
             vim_mode: String::new(),
             recording: String::new(),
+            macro_keys: Vec::new(),
+            last_macro: None,
+            last_played: None,
+            playback: None,
+            undo_seq: 0,
+            repeatable: false,
             cmdline_cursor_byte: None,
             cmdline_prefix_len: 0,
+            cmdline_prompt: String::new(),
             cmdline_level: None,
         }
     }
 
-    /// Push a key to the entries
+    /// Push a key to the entries (joining the command being typed, if any)
     pub fn push_key(&mut self, key: &str) {
-        self.entries.push(KeypressEntry {
-            text: key.to_string(),
-        });
+        let count_digit = self.takes_count() && is_count_digit(key, self.counting);
+        match self.entries.last_mut() {
+            Some(last) if self.building => last.text.push_str(key),
+            _ => self.entries.push(KeypressEntry {
+                text: key.to_string(),
+            }),
+        }
+        self.building = count_digit;
+        self.counting = count_digit;
         self.last_added_at = Some(Instant::now());
+        self.awaiting = false;
         // Trim oldest entries if over limit
         if self.entries.len() > MAX_DISPLAY_ENTRIES {
             let excess = self.entries.len() - MAX_DISPLAY_ENTRIES;
@@ -71,17 +119,111 @@ impl KeypressState {
     pub fn clear(&mut self) {
         self.entries.clear();
         self.last_added_at = None;
+        self.awaiting = false;
+        self.building = false;
+        self.counting = false;
         self.pending_type = PendingState::None;
         self.cmdline_cursor_byte = None;
         self.cmdline_prefix_len = 0;
+        self.cmdline_prompt.clear();
         self.cmdline_level = None;
-        // NOTE: recording is NOT cleared here — it's driven by Neovim snapshots,
-        // not by keypress display lifecycle. Cleared explicitly on disable/exit.
+        // NOTE: recording (and the macro state) is NOT cleared here — it's driven
+        // by Neovim snapshots, not by keypress display lifecycle. Cleared
+        // explicitly on disable/exit.
     }
 
-    /// Set the pending type
+    /// Set the pending type (after the key that led to it was pushed)
     pub fn set_pending(&mut self, pending_type: PendingState) {
         self.pending_type = pending_type;
+        // An operator waiting for its motion: the motion joins its entry
+        if pending_type.is_motion() {
+            self.building = true;
+        }
+    }
+
+    /// Set the register being recorded ("" when not recording). A new
+    /// recording starts with no keys captured.
+    pub fn set_recording(&mut self, recording: String) {
+        if recording != self.recording {
+            self.macro_keys.clear();
+        }
+        self.recording = recording;
+    }
+
+    /// Stop tracking macros (IME disabled or Neovim gone)
+    pub fn clear_macro(&mut self) {
+        self.recording.clear();
+        self.macro_keys.clear();
+        self.playback = None;
+    }
+
+    /// Note a key about to be sent to Neovim: captured while recording, and
+    /// the register name after `@` (or `5@`) starts a playback
+    pub fn on_key_sent(&mut self, key: &str) {
+        if !self.recording.is_empty() {
+            self.macro_keys.push(key.to_string());
+        }
+        let count = match self.entries.last() {
+            Some(last) if self.pending_type == PendingState::Getchar && self.vim_mode == "n" => {
+                last.text.strip_suffix('@')
+            }
+            _ => None,
+        };
+        let Some(count) = count.filter(|c| c.bytes().all(|b| b.is_ascii_digit())) else {
+            return;
+        };
+        let register = match key {
+            "@" => self.last_played.clone(),
+            // `@:` repeats a command line, not a macro
+            ":" => None,
+            _ if key.chars().count() == 1 => Some(key.to_string()),
+            _ => None,
+        };
+        if let Some(register) = register {
+            self.last_played = Some(register.clone());
+            self.playback = Some(Playback {
+                register,
+                count: count.parse().ok(),
+            });
+        }
+    }
+
+    /// The key Neovim was working on is done. True if that ended a playback.
+    pub fn finish_playback(&mut self) -> bool {
+        self.playback.take().is_some()
+    }
+
+    /// The macro row: the last `limit` keys captured while recording, or the
+    /// contents of the register playing back (None when neither)
+    pub fn macro_preview(&self, limit: usize) -> Option<MacroPreview> {
+        if limit == 0 {
+            return None;
+        }
+        if let Some(ref playback) = self.playback {
+            // Uppercase registers append to the lowercase one
+            let keys = self
+                .last_macro
+                .as_ref()
+                .filter(|m| m.register.eq_ignore_ascii_case(&playback.register))
+                .map(|m| truncate_keys(&split_keys(&m.keys), limit))
+                .unwrap_or_default();
+            return Some(MacroPreview {
+                playing: Some(playback.label()),
+                keys,
+            });
+        }
+        if self.recording.is_empty() || self.macro_keys.is_empty() {
+            return None;
+        }
+        Some(MacroPreview {
+            playing: None,
+            keys: truncate_keys(&self.macro_keys, limit),
+        })
+    }
+
+    /// Whether a digit typed now is a count (normal and operator-pending mode)
+    fn takes_count(&self) -> bool {
+        self.vim_mode == "n" || self.vim_mode.starts_with("no")
     }
 
     /// Update vim mode
@@ -105,21 +247,35 @@ impl KeypressState {
 
     /// Clear all entries if no new entries have been added within KEYPRESS_DISPLAY_DURATION.
     /// Skips clearing in command-line mode (display is managed by CmdlineShow).
-    /// Returns true if entries were cleared.
+    /// While an operator, register or getchar is still pending the entries are
+    /// kept and the "awaiting" hint is shown instead.
+    /// Returns true if the display changed.
     pub fn cleanup_inactive(&mut self) -> bool {
         if self.vim_mode.starts_with('c') {
             return false;
         }
-        if let Some(last) = self.last_added_at {
-            if last.elapsed() >= KEYPRESS_DISPLAY_DURATION && !self.entries.is_empty() {
-                self.entries.clear();
-                self.last_added_at = None;
-                return true;
+        if let Some(last) = self.last_added_at
+            && last.elapsed() >= KEYPRESS_DISPLAY_DURATION
+            && !self.entries.is_empty()
+        {
+            if awaiting_hint(self.pending_type).is_some() {
+                return !std::mem::replace(&mut self.awaiting, true);
             }
+            self.entries.clear();
+            self.last_added_at = None;
+            self.awaiting = false;
+            return true;
         }
         false
     }
 
+    /// Hint shown after the timed-out entries while Neovim waits for input
+    pub fn pending_hint(&self) -> Option<&'static str> {
+        self.awaiting
+            .then(|| awaiting_hint(self.pending_type))
+            .flatten()
+    }
+
     /// Check if we should show the keypress display
     pub fn should_show(&self) -> bool {
         !self.entries.is_empty()
@@ -180,6 +336,74 @@ impl KeypressState {
     pub fn cmdline_cursor_byte(&self) -> Option<usize> {
         self.cmdline_cursor_byte
     }
+
+    /// Set the `input()` prompt of the command line ("" for none)
+    pub fn set_cmdline_prompt(&mut self, prompt: String) {
+        self.cmdline_prompt = prompt;
+    }
+
+    /// `input()` prompt of the active command line, if it has one
+    pub fn cmdline_prompt(&self) -> Option<&str> {
+        Some(self.cmdline_prompt.as_str()).filter(|p| !p.is_empty() && self.cmdline_level.is_some())
+    }
+}
+
+/// A macro being played back
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Playback {
+    register: String,
+    /// Count before the `@` (None for a single run)
+    count: Option<u32>,
+}
+
+impl Playback {
+    /// Progress indicator, e.g. "▶ 5@q"
+    fn label(&self) -> String {
+        match self.count {
+            Some(count) => format!("▶ {count}@{}", self.register),
+            None => format!("▶ @{}", self.register),
+        }
+    }
+}
+
+/// Contents of the macro row
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MacroPreview {
+    /// Playback indicator ("▶ @q"), None while recording
+    pub playing: Option<String>,
+    /// Keys, the earlier ones elided
+    pub keys: String,
+}
+
+/// The last `limit` keys, "…" standing for the ones before
+fn truncate_keys<S: Borrow<str>>(keys: &[S], limit: usize) -> String {
+    let start = keys.len().saturating_sub(limit);
+    let tail = keys[start..].concat();
+    if start > 0 {
+        format!("…{tail}")
+    } else {
+        tail
+    }
+}
+
+/// Whether `key` continues or starts a count: `0` only continues one (alone
+/// it is the motion to the start of the line)
+fn is_count_digit(key: &str, counting: bool) -> bool {
+    match key.as_bytes() {
+        [b'0'] => counting,
+        [b'1'..=b'9'] => true,
+        _ => false,
+    }
+}
+
+/// What Neovim is waiting for, as shown once the keypress display times out
+fn awaiting_hint(pending: PendingState) -> Option<&'static str> {
+    match pending {
+        PendingState::Motion | PendingState::TextObject => Some("awaiting motion…"),
+        PendingState::InsertRegister | PendingState::NormalRegister => Some("awaiting register…"),
+        PendingState::Getchar => Some("awaiting key…"),
+        PendingState::None | PendingState::CommandLine => None,
+    }
 }
 
 impl Default for KeypressState {
@@ -227,6 +451,70 @@ mod tests {
         assert!(!state.should_show());
     }
 
+    #[test]
+    fn recording_captures_the_keys_sent() {
+        let mut state = KeypressState::new();
+        state.on_key_sent("q");
+        state.on_key_sent("a");
+        assert_eq!(state.macro_preview(16), None);
+
+        state.set_recording("a".to_string());
+        for key in ["i", "h", "i", "<Esc>", "j"] {
+            state.on_key_sent(key);
+        }
+        let preview = state.macro_preview(16).unwrap();
+        assert_eq!(preview.playing, None);
+        assert_eq!(preview.keys, "ihi<Esc>j");
+        assert_eq!(state.macro_preview(2).unwrap().keys, "…<Esc>j");
+        assert_eq!(state.macro_preview(0), None);
+
+        // A new recording starts empty
+        state.set_recording(String::new());
+        assert_eq!(state.macro_preview(16), None);
+        state.set_recording("b".to_string());
+        assert_eq!(state.macro_preview(16), None);
+    }
+
+    #[test]
+    fn playback_shows_the_register_until_processed() {
+        let mut state = KeypressState::new();
+        state.set_vim_mode("n");
+        state.last_macro = Some(MacroRegister {
+            register: "q".to_string(),
+            keys: "A!<Esc>j".to_string(),
+        });
+
+        state.push_key("3");
+        state.push_key("@");
+        state.set_pending(PendingState::Getchar);
+        state.on_key_sent("q");
+        let preview = state.macro_preview(3).unwrap();
+        assert_eq!(preview.playing.as_deref(), Some("▶ 3@q"));
+        assert_eq!(preview.keys, "…!<Esc>j");
+
+        assert!(state.finish_playback());
+        assert!(!state.finish_playback());
+        assert_eq!(state.macro_preview(3), None);
+
+        // `@@` plays the same register again; another one shows no keys
+        state.push_key("@");
+        state.set_pending(PendingState::Getchar);
+        state.on_key_sent("@");
+        assert_eq!(
+            state.macro_preview(16).unwrap().playing.as_deref(),
+            Some("▶ @q")
+        );
+        state.finish_playback();
+        state.set_pending(PendingState::None);
+        state.on_key_sent("w");
+        state.on_key_sent("x");
+        assert_eq!(state.macro_preview(16), None);
+        state.push_key("@");
+        state.set_pending(PendingState::Getchar);
+        state.on_key_sent("w");
+        assert_eq!(state.macro_preview(16).unwrap().keys, "");
+    }
+
     #[test]
     fn mode_classification_normal_mode() {
         let mut state = KeypressState::new();
@@ -300,6 +588,92 @@ mod tests {
         assert!(state.should_show());
     }
 
+    #[test]
+    fn cleanup_inactive_keeps_pending_operator_and_shows_hint() {
+        let mut state = KeypressState::new();
+        state.push_key("d");
+        state.set_pending(PendingState::Motion);
+        state.last_added_at =
+            Some(Instant::now() - KEYPRESS_DISPLAY_DURATION - Duration::from_millis(1));
+        assert_eq!(state.pending_hint(), None);
+
+        assert!(state.cleanup_inactive());
+        assert_eq!(state.display_text(), "d");
+        assert_eq!(state.pending_hint(), Some("awaiting motion…"));
+        // Already showing the hint: nothing changes on later ticks
+        assert!(!state.cleanup_inactive());
+
+        // The next key hides the hint
+        state.push_key("w");
+        assert_eq!(state.pending_hint(), None);
+    }
+
+    #[test]
+    fn cleanup_inactive_clears_once_pending_resolves() {
+        let mut state = KeypressState::new();
+        state.push_key("\"");
+        state.set_pending(PendingState::NormalRegister);
+        state.last_added_at =
+            Some(Instant::now() - KEYPRESS_DISPLAY_DURATION - Duration::from_millis(1));
+        assert!(state.cleanup_inactive());
+        assert_eq!(state.pending_hint(), Some("awaiting register…"));
+
+        state.set_pending(PendingState::None);
+        assert_eq!(state.pending_hint(), None);
+        assert!(state.cleanup_inactive());
+        assert!(!state.should_show());
+    }
+
+    #[test]
+    fn counts_join_the_operator_and_motion() {
+        let mut state = KeypressState::new();
+        state.set_vim_mode("n");
+        for key in ["1", "2", "d"] {
+            state.push_key(key);
+            state.set_pending(PendingState::None);
+        }
+        state.set_pending(PendingState::Motion);
+        state.set_vim_mode("no");
+        state.push_key("d");
+        state.set_pending(PendingState::None);
+        state.set_vim_mode("n");
+        assert_eq!(state.entries.len(), 1);
+        assert_eq!(state.entries[0].text, "12dd");
+
+        // The motion's count joins too; the next command is an entry of its own
+        for key in ["d", "2", "w"] {
+            state.push_key(key);
+            state.set_pending(if key == "w" {
+                PendingState::None
+            } else {
+                PendingState::Motion
+            });
+            state.set_vim_mode(if key == "w" { "n" } else { "no" });
+        }
+        state.push_key("x");
+        let texts: Vec<&str> = state.entries.iter().map(|e| e.text.as_str()).collect();
+        assert_eq!(texts, ["12dd", "d2w", "x"]);
+    }
+
+    #[test]
+    fn zero_is_a_motion_unless_it_continues_a_count() {
+        let mut state = KeypressState::new();
+        state.set_vim_mode("n");
+        state.push_key("0");
+        state.push_key("1");
+        state.push_key("0");
+        state.push_key("j");
+        let texts: Vec<&str> = state.entries.iter().map(|e| e.text.as_str()).collect();
+        assert_eq!(texts, ["0", "10j"]);
+
+        // Digits typed in insert mode aren't counts
+        state.clear();
+        state.set_vim_mode("i");
+        state.push_key("1");
+        state.push_key("2");
+        assert_eq!(state.entries.len(), 2);
+    }
+
     #[test]
     fn max_entries_trims_oldest() {
         let mut state = KeypressState::new();
@@ -385,4 +759,19 @@ mod tests {
         state.set_cmdline_text(display, prefix_len + pos, prefix_len, 1);
         assert_eq!(state.cmdline_cursor_byte(), Some(16)); // 14 + 2
     }
+
+    #[test]
+    fn cmdline_prompt_lives_with_its_cmdline() {
+        let mut state = KeypressState::new();
+        state.set_cmdline_text("かんじ".to_string(), 9, 0, 1);
+        state.set_cmdline_prompt("辞書登録: ".to_string());
+        assert_eq!(state.cmdline_prompt(), Some("辞書登録: "));
+        assert_eq!(state.cmdline_cursor_byte(), Some(9));
+
+        state.set_cmdline_prompt(String::new());
+        assert_eq!(state.cmdline_prompt(), None);
+        state.set_cmdline_prompt("辞書登録: ".to_string());
+        assert!(state.clear_cmdline_if_level(1));
+        assert_eq!(state.cmdline_prompt(), None);
+    }
 }
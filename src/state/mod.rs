@@ -4,17 +4,49 @@
 //! - WaylandState: Protocol handles and serial tracking
 //! - KeyboardState: XKB context and modifier tracking
 //! - ImeState: IME mode state machine and preedit
+//! - CandidateFilter: local narrowing of the candidate list
+//! - CandidateHistory: accepted candidates per reading, to show them first
+//! - ParkedSeat: per-seat state of seats other than the current one
+//! - BufferClearState: when the Neovim buffer is cleared after focus loss
+//! - PointerState: pointer over the candidate popup
+//! - AppMemory: last enabled state per application
+//! - SavedComposition: preedit saved on exit, restored after the next start
+//! - StartupQueue: keys typed while a backend spawned on enable starts
+//! - Outputs: wl_output properties, for per-output popup sizes
+//! - TextKeymap: generated keymaps for typing text via the virtual keyboard
 
 mod animation;
+mod app_memory;
+mod buffer_clear;
+mod candidate_history;
+mod filter;
 mod ime;
 mod keyboard;
 mod keypress;
+mod output;
+mod persist;
+mod pointer;
 mod repeat;
+mod saved_composition;
+mod seat;
+mod startup;
+mod virtual_keymap;
 mod wayland;
 
-pub use animation::Animations;
-pub use ime::{ImeState, VimMode};
-pub use keyboard::KeyboardState;
-pub use keypress::KeypressState;
+pub use animation::{ANIMATION_FRAME, Animations};
+pub use app_memory::{APP_MEMORY_FILE, AppMemory};
+pub use buffer_clear::BufferClearState;
+pub use candidate_history::{CANDIDATE_HISTORY_FILE, CandidateHistory};
+pub use ime::{
+    ContentPurpose, ContentType, ExternalCandidates, ImeState, SurroundingText, VimMode,
+};
+pub use keyboard::{Compose, KeyboardState};
+pub use keypress::{KeypressState, MacroPreview};
+pub use output::Outputs;
+pub use pointer::PointerState;
 pub use repeat::KeyRepeatState;
-pub use wayland::WaylandState;
+pub use saved_composition::{COMPOSITION_FILE, SavedComposition};
+pub use seat::ParkedSeat;
+pub use startup::StartupQueue;
+pub use virtual_keymap::{KEYS_PER_KEYMAP, TextKeymap};
+pub use wayland::{SeatId, WaylandState, grab_is_stale};
@@ -0,0 +1,181 @@
+//! Outputs (wl_output) and the `[[popup.output]]` overrides that apply to them
+//!
+//! The popup surface reports the output it is shown on (wl_surface enter);
+//! the output's name and description pick the popup size for it.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::config::PopupOutputConfig;
+
+/// Output properties (applied on `done`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct OutputInfo {
+    /// Connector name ("HDMI-A-1", wl_output v4)
+    pub name: String,
+    /// Human-readable description ("LG Electronics LG TV SSCR2 (HDMI-A-1)"),
+    /// or make and model before wl_output v4
+    pub description: String,
+    /// Integer buffer scale
+    pub scale: i32,
+    /// Current mode in pixels
+    pub mode: (i32, i32),
+}
+
+impl OutputInfo {
+    /// Whether `[[popup.output]] name = pattern` selects this output: the exact
+    /// connector name or part of the description
+    pub fn matches(&self, pattern: &str) -> bool {
+        !pattern.is_empty() && (self.name == pattern || self.description.contains(pattern))
+    }
+
+    /// First `[[popup.output]]` selecting this output
+    pub fn popup_override<'a>(
+        &self,
+        overrides: &'a [PopupOutputConfig],
+    ) -> Option<&'a PopupOutputConfig> {
+        overrides.iter().find(|o| self.matches(&o.name))
+    }
+}
+
+/// Pending changes of one output
+#[derive(Debug, Default)]
+struct Output {
+    info: Option<OutputInfo>,
+    pending: OutputInfo,
+    /// Make and model from geometry, the description until one arrives
+    make_model: String,
+    has_description: bool,
+}
+
+/// Outputs by handle
+#[derive(Debug)]
+pub struct Outputs<K> {
+    outputs: HashMap<K, Output>,
+}
+
+impl<K> Default for Outputs<K> {
+    fn default() -> Self {
+        Self {
+            outputs: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq> Outputs<K> {
+    fn pending(&mut self, key: K) -> &mut Output {
+        self.outputs.entry(key).or_insert_with(|| Output {
+            pending: OutputInfo {
+                scale: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    pub fn set_name(&mut self, key: K, name: String) {
+        self.pending(key).pending.name = name;
+    }
+
+    pub fn set_description(&mut self, key: K, description: String) {
+        let output = self.pending(key);
+        output.pending.description = description;
+        output.has_description = true;
+    }
+
+    pub fn set_make_model(&mut self, key: K, make: &str, model: &str) {
+        self.pending(key).make_model = format!("{make} {model}");
+    }
+
+    pub fn set_scale(&mut self, key: K, scale: i32) {
+        self.pending(key).pending.scale = scale;
+    }
+
+    pub fn set_mode(&mut self, key: K, width: i32, height: i32) {
+        self.pending(key).pending.mode = (width, height);
+    }
+
+    /// Apply pending properties. Returns the output if anything changed.
+    pub fn done(&mut self, key: K) -> Option<&OutputInfo> {
+        let output = self.pending(key);
+        let mut info = output.pending.clone();
+        if !output.has_description {
+            info.description = output.make_model.clone();
+        }
+        if output.info.as_ref() == Some(&info) {
+            return None;
+        }
+        output.info = Some(info);
+        output.info.as_ref()
+    }
+
+    /// Output properties (None until its first `done`)
+    pub fn get(&self, key: &K) -> Option<&OutputInfo> {
+        self.outputs.get(key)?.info.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tv() -> PopupOutputConfig {
+        PopupOutputConfig {
+            name: "LG TV".into(),
+            font_size: Some(28.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn properties_apply_on_done() {
+        let mut outputs = Outputs::default();
+        outputs.set_name(1, "HDMI-A-1".into());
+        outputs.set_scale(1, 2);
+        outputs.set_mode(1, 3840, 2160);
+        assert!(outputs.get(&1).is_none());
+
+        let info = outputs.done(1).unwrap();
+        assert_eq!(info.name, "HDMI-A-1");
+        assert_eq!((info.scale, info.mode), (2, (3840, 2160)));
+        // Unchanged properties sent again are no change
+        outputs.set_scale(1, 2);
+        assert!(outputs.done(1).is_none());
+    }
+
+    #[test]
+    fn make_and_model_stand_in_for_description() {
+        let mut outputs = Outputs::default();
+        outputs.set_make_model(1, "LG Electronics", "LG TV SSCR2");
+        assert_eq!(
+            outputs.done(1).unwrap().description,
+            "LG Electronics LG TV SSCR2"
+        );
+        outputs.set_description(1, "Living room".into());
+        assert_eq!(outputs.done(1).unwrap().description, "Living room");
+    }
+
+    #[test]
+    fn overrides_match_name_or_description() {
+        let info = OutputInfo {
+            name: "HDMI-A-1".into(),
+            description: "LG Electronics LG TV SSCR2 (HDMI-A-1)".into(),
+            scale: 1,
+            mode: (3840, 2160),
+        };
+        assert!(info.matches("HDMI-A-1"));
+        assert!(info.matches("LG TV"));
+        assert!(!info.matches("DP-1"));
+        assert!(!info.matches(""));
+
+        let overrides = [
+            PopupOutputConfig {
+                name: "eDP-1".into(),
+                ..Default::default()
+            },
+            tv(),
+        ];
+        assert_eq!(info.popup_override(&overrides), Some(&tv()));
+        assert_eq!(info.popup_override(&overrides[..1]), None);
+    }
+}
@@ -0,0 +1,51 @@
+//! Files jacin keeps between sessions
+//!
+//! Shared by the per-app memory, the candidate history and the saved
+//! composition: each is a small JSON file replaced as a whole.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Replace `path` with `contents`, creating its directory. Written to a
+/// sibling first and renamed so a crash never leaves a truncated file.
+pub(super) fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> std::io::Result<()> {
+    write_with_mode(path, contents.as_ref(), 0o666)
+}
+
+/// [`write_atomic`] for a file only the user may read (mode 0600)
+pub(super) fn write_private(path: &Path, contents: impl AsRef<[u8]>) -> std::io::Result<()> {
+    write_with_mode(path, contents.as_ref(), 0o600)
+}
+
+/// `mode` is narrowed by the umask, as for any new file
+fn write_with_mode(path: &Path, contents: &[u8], mode: u32) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp = tmp_path(path);
+    // A sibling left by a crash would keep its own mode
+    let _ = std::fs::remove_file(&tmp);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(&tmp)?;
+    file.write_all(contents)?;
+    std::fs::rename(&tmp, path)
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// `file` inside a fresh, not yet created per-test directory
+#[cfg(test)]
+pub(super) fn scratch_path(name: &str, file: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("jacin-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir.join("state").join(file)
+}
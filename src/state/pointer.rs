@@ -0,0 +1,132 @@
+//! Pointer over the candidate popup (`behavior.popup_pointer`)
+//!
+//! Tracks which seat's pointer is over its popup, where, and how fast a
+//! touchpad scroll was moving when the fingers lifted (for the kinetic fling).
+
+use std::time::{Duration, Instant};
+
+use wayland_client::protocol::wl_pointer::AxisSource;
+
+use super::SeatId;
+
+/// A scroll paused longer than this before the fingers lifted doesn't fling
+const FLING_MAX_PAUSE: Duration = Duration::from_millis(50);
+/// Scroll samples further apart than this start a new velocity estimate
+const VELOCITY_RESET: Duration = Duration::from_millis(100);
+/// Weight of the newest sample in the velocity estimate
+const VELOCITY_ALPHA: f32 = 0.5;
+
+#[derive(Debug, Default)]
+pub struct PointerState {
+    /// Seat whose pointer is over its popup (None = not over any popup)
+    pub focus: Option<SeatId>,
+    /// Position in popup-local coordinates
+    pub x: f32,
+    pub y: f32,
+    /// Source of the axis events in the current frame (None before wl_pointer v5)
+    pub axis_source: Option<AxisSource>,
+    /// Touchpad scroll velocity in rows per second
+    velocity: f32,
+    /// Time of the last touchpad scroll sample
+    last_scroll: Option<Instant>,
+}
+
+impl PointerState {
+    pub fn enter(&mut self, seat: SeatId, x: f32, y: f32) {
+        self.focus = Some(seat);
+        self.motion(x, y);
+    }
+
+    pub fn motion(&mut self, x: f32, y: f32) {
+        self.x = x;
+        self.y = y;
+    }
+
+    pub fn leave(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Whether `seat`'s pointer is over its popup
+    pub fn is_over(&self, seat: SeatId) -> bool {
+        self.focus == Some(seat)
+    }
+
+    /// Record a touchpad scroll of `rows` at `now` for the velocity estimate
+    pub fn track_scroll(&mut self, rows: f32, now: Instant) {
+        let dt = self
+            .last_scroll
+            .map(|last| now.saturating_duration_since(last))
+            .filter(|dt| *dt < VELOCITY_RESET);
+        self.velocity = match dt {
+            Some(dt) if !dt.is_zero() => {
+                let sample = rows / dt.as_secs_f32();
+                self.velocity + VELOCITY_ALPHA * (sample - self.velocity)
+            }
+            Some(_) => self.velocity,
+            None => 0.0,
+        };
+        self.last_scroll = Some(now);
+    }
+
+    /// Fingers lifted at `now`: velocity (rows/s) to fling with, 0 if the
+    /// scroll had already stopped
+    pub fn take_fling(&mut self, now: Instant) -> f32 {
+        let velocity = match self.last_scroll.take() {
+            Some(last) if now.saturating_duration_since(last) <= FLING_MAX_PAUSE => self.velocity,
+            _ => 0.0,
+        };
+        self.velocity = 0.0;
+        velocity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn enter_and_leave() {
+        let mut pointer = PointerState::default();
+        pointer.enter(7, 10.0, 20.0);
+        assert!(pointer.is_over(7));
+        assert!(!pointer.is_over(8));
+        pointer.motion(11.0, 21.0);
+        assert_eq!((pointer.x, pointer.y), (11.0, 21.0));
+        pointer.leave();
+        assert!(!pointer.is_over(7));
+    }
+
+    #[test]
+    fn steady_scroll_flings_at_its_speed() {
+        let mut pointer = PointerState::default();
+        let start = Instant::now();
+        for i in 0..10 {
+            pointer.track_scroll(0.5, start + 10 * MS * i);
+        }
+        let velocity = pointer.take_fling(start + 95 * MS);
+        assert!((velocity - 50.0).abs() < 1.0, "{velocity}");
+        // Consumed
+        assert_eq!(pointer.take_fling(start + 96 * MS), 0.0);
+    }
+
+    #[test]
+    fn paused_scroll_does_not_fling() {
+        let mut pointer = PointerState::default();
+        let start = Instant::now();
+        pointer.track_scroll(0.5, start);
+        pointer.track_scroll(0.5, start + 10 * MS);
+        assert_eq!(pointer.take_fling(start + 200 * MS), 0.0);
+    }
+
+    #[test]
+    fn slow_samples_restart_the_estimate() {
+        let mut pointer = PointerState::default();
+        let start = Instant::now();
+        pointer.track_scroll(5.0, start);
+        pointer.track_scroll(5.0, start + 10 * MS);
+        pointer.track_scroll(0.1, start + 500 * MS);
+        assert_eq!(pointer.take_fling(start + 501 * MS), 0.0);
+    }
+}
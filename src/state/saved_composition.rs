@@ -0,0 +1,108 @@
+//! Composition carried across a restart (`behavior.save_composition`)
+//!
+//! On a normal exit with text being composed, the preedit, its cursor and
+//! the Vim mode are written to `$XDG_STATE_HOME/jacin/composition.json`. The
+//! next jacin takes the file at startup (it is removed, so a composition is
+//! offered once) and puts the text back into the fresh backend when the IME
+//! is first enabled. The file is readable by the user only.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::persist::write_private;
+
+/// File name inside the state directory
+pub const COMPOSITION_FILE: &str = "composition.json";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedComposition {
+    pub preedit: String,
+    /// Cursor byte offsets in `preedit`
+    pub cursor_begin: usize,
+    pub cursor_end: usize,
+    /// Vim mode as the backend reported it ("i", "n", ...)
+    pub mode: String,
+}
+
+impl SavedComposition {
+    /// Whether it was typed in insert mode
+    pub fn insert(&self) -> bool {
+        self.mode.starts_with('i')
+    }
+
+    /// Write to `path` (mode 0600)
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        write_private(path, serde_json::to_string_pretty(self)?)
+    }
+
+    /// Read and remove the composition saved at `path` (None if there is
+    /// none or it can't be used)
+    pub fn take(path: PathBuf) -> Option<Self> {
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) => {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    log::warn!("[RESTORE] Failed to read {}: {}", path.display(), e);
+                }
+                return None;
+            }
+        };
+        if let Err(e) = std::fs::remove_file(&path) {
+            log::warn!("[RESTORE] Failed to remove {}: {}", path.display(), e);
+        }
+        let saved: Self = serde_json::from_str(&text)
+            .map_err(|e| log::warn!("[RESTORE] Ignoring invalid {}: {}", path.display(), e))
+            .ok()?;
+        let valid = !saved.preedit.is_empty()
+            && saved.cursor_begin <= saved.cursor_end
+            && saved.preedit.is_char_boundary(saved.cursor_begin)
+            && saved.preedit.is_char_boundary(saved.cursor_end);
+        valid.then_some(saved)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::state::persist::scratch_path;
+
+    #[test]
+    fn saved_composition_is_taken_once() {
+        let path = scratch_path("composition", COMPOSITION_FILE);
+        let saved = SavedComposition {
+            preedit: "かんじ".to_string(),
+            cursor_begin: 3,
+            cursor_end: 6,
+            mode: "n".to_string(),
+        };
+        saved.save(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let taken = SavedComposition::take(path.clone()).unwrap();
+        assert_eq!(taken, saved);
+        assert!(!taken.insert());
+        assert_eq!(SavedComposition::take(path.clone()), None);
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap().parent().unwrap());
+    }
+
+    #[test]
+    fn cursor_inside_a_character_is_dropped() {
+        let path = scratch_path("composition-invalid", COMPOSITION_FILE);
+        let saved = SavedComposition {
+            preedit: "かな".to_string(),
+            cursor_begin: 1,
+            cursor_end: 1,
+            mode: "i".to_string(),
+        };
+        saved.save(&path).unwrap();
+        assert_eq!(SavedComposition::take(path.clone()), None);
+        assert!(!path.exists());
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap().parent().unwrap());
+    }
+}
@@ -0,0 +1,15 @@
+//! Parked per-seat state
+//!
+//! The seat the user is typing on lives in `State`'s own `wayland`/`keyboard`/`popup`
+//! fields; every other seat is parked here and swapped in by `State::select_seat`.
+
+use super::{KeyboardState, SeatId, WaylandState};
+use crate::ui::UnifiedPopup;
+
+/// Protocol, keyboard and popup state of a seat that isn't current
+pub struct ParkedSeat {
+    pub id: SeatId,
+    pub wayland: WaylandState,
+    pub keyboard: KeyboardState,
+    pub popup: Option<UnifiedPopup>,
+}
@@ -0,0 +1,86 @@
+//! Keys typed while a backend spawned on enable is still starting
+//!
+//! With `behavior.lazy_spawn` (or after the backend exited), Neovim is spawned
+//! when the IME is enabled and takes a moment before it sends `Ready`. Keys
+//! typed meanwhile are queued here instead of each waiting out the response
+//! deadline, and replayed in order once the backend is ready.
+
+/// Most keys kept while waiting; later ones are dropped
+const MAX_QUEUED_KEYS: usize = 32;
+
+/// A key waiting for the backend: Vim notation and the raw keycode (for passthrough)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueuedKey {
+    pub vim_key: String,
+    pub keycode: u32,
+}
+
+/// Keys typed before the backend's `Ready`
+#[derive(Debug, Default)]
+pub struct StartupQueue {
+    /// Some while waiting for `Ready`
+    keys: Option<Vec<QueuedKey>>,
+}
+
+impl StartupQueue {
+    /// A backend was spawned: queue keys until it is ready
+    pub fn start(&mut self) {
+        self.keys = Some(Vec::new());
+    }
+
+    pub fn is_waiting(&self) -> bool {
+        self.keys.is_some()
+    }
+
+    /// Queue a key. Returns false if it was dropped (not waiting, or queue full).
+    pub fn push(&mut self, vim_key: &str, keycode: u32) -> bool {
+        match self.keys.as_mut() {
+            Some(keys) if keys.len() < MAX_QUEUED_KEYS => {
+                keys.push(QueuedKey {
+                    vim_key: vim_key.to_string(),
+                    keycode,
+                });
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The backend is ready (or gone): stop waiting and hand out the queued keys
+    pub fn finish(&mut self) -> Vec<QueuedKey> {
+        self.keys.take().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_queue_only_while_waiting() {
+        let mut queue = StartupQueue::default();
+        assert!(!queue.push("a", 30));
+
+        queue.start();
+        assert!(queue.is_waiting());
+        assert!(queue.push("k", 37));
+        assert!(queue.push("a", 30));
+        let keys = queue.finish();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].vim_key, "k");
+        assert_eq!(keys[1].keycode, 30);
+        assert!(!queue.is_waiting());
+        assert!(queue.finish().is_empty());
+    }
+
+    #[test]
+    fn queue_is_capped() {
+        let mut queue = StartupQueue::default();
+        queue.start();
+        for _ in 0..MAX_QUEUED_KEYS {
+            assert!(queue.push("a", 30));
+        }
+        assert!(!queue.push("b", 48));
+        assert_eq!(queue.finish().len(), MAX_QUEUED_KEYS);
+    }
+}
@@ -0,0 +1,120 @@
+//! Generated keymaps for typing text through the virtual keyboard
+//!
+//! Replaying a keycode only reproduces the user's character if the app
+//! interprets it with the same keymap and layout group. For text that the
+//! current layout can't produce (or produces differently), a throwaway keymap
+//! is uploaded with one key per character, bound to the character's Unicode
+//! keysym, and those keys are pressed instead. The grabbed keymap is restored
+//! afterwards.
+
+use xkbcommon::xkb;
+
+/// First xkb keycode used (evdev 1); 8 is the lowest valid xkb keycode
+const FIRST_KEYCODE: u32 = 9;
+/// Highest xkb keycode clients are guaranteed to accept
+const MAX_KEYCODE: u32 = 255;
+/// Distinct characters one generated keymap can hold
+pub const KEYS_PER_KEYMAP: usize = (MAX_KEYCODE - FIRST_KEYCODE + 1) as usize;
+
+/// A generated keymap and the evdev keycode typing each of its characters
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextKeymap {
+    pub keymap: String,
+    keys: Vec<(char, u32)>,
+}
+
+impl TextKeymap {
+    /// Keymap for the distinct characters of `chars` (at most
+    /// `KEYS_PER_KEYMAP`). Returns None if a character has no keysym
+    /// (control characters).
+    pub fn new(chars: impl IntoIterator<Item = char>) -> Option<Self> {
+        let mut keys: Vec<(char, u32)> = Vec::new();
+        let mut codes = String::new();
+        let mut symbols = String::new();
+        for c in chars {
+            if keys.iter().any(|&(k, _)| k == c) {
+                continue;
+            }
+            if keys.len() == KEYS_PER_KEYMAP {
+                return None;
+            }
+            let name = keysym_name(c)?;
+            let keycode = FIRST_KEYCODE + keys.len() as u32;
+            codes.push_str(&format!("    <K{keycode}> = {keycode};\n"));
+            symbols.push_str(&format!("    key <K{keycode}> {{ [ {name} ] }};\n"));
+            keys.push((c, keycode - 8));
+        }
+        let max = FIRST_KEYCODE + (keys.len() as u32).saturating_sub(1);
+        let keymap = format!(
+            "xkb_keymap {{\n\
+             xkb_keycodes \"jacin\" {{\n    minimum = 8;\n    maximum = {max};\n{codes}}};\n\
+             xkb_types \"jacin\" {{\n    type \"ONE_LEVEL\" {{\n        modifiers = none;\n        level_name[Level1] = \"Any\";\n    }};\n}};\n\
+             xkb_compatibility \"jacin\" {{\n}};\n\
+             xkb_symbols \"jacin\" {{\n{symbols}}};\n\
+             }};\n"
+        );
+        Some(Self { keymap, keys })
+    }
+
+    /// Evdev keycode typing `c`
+    pub fn keycode(&self, c: char) -> Option<u32> {
+        self.keys
+            .iter()
+            .find(|&&(k, _)| k == c)
+            .map(|&(_, code)| code)
+    }
+}
+
+/// Keysym name of a character (`a`, `U3042`); None for control characters
+fn keysym_name(c: char) -> Option<String> {
+    if c.is_control() {
+        return None;
+    }
+    let keysym = xkb::utf32_to_keysym(c as u32);
+    if keysym.raw() == 0 {
+        return None;
+    }
+    Some(xkb::keysym_get_name(keysym))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compile(keymap: &str) -> xkb::Keymap {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        xkb::Keymap::new_from_string(
+            &context,
+            keymap.to_string(),
+            xkb::KEYMAP_FORMAT_TEXT_V1,
+            xkb::KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .expect("generated keymap compiles")
+    }
+
+    #[test]
+    fn each_character_gets_a_key() {
+        let text = TextKeymap::new("aあ€あ".chars()).unwrap();
+        let keymap = compile(&text.keymap);
+        let state = xkb::State::new(&keymap);
+        for c in ['a', 'あ', '€'] {
+            let keycode = text.keycode(c).unwrap();
+            assert_eq!(
+                state.key_get_utf8(xkb::Keycode::new(keycode + 8)),
+                c.to_string()
+            );
+        }
+        // Duplicates share a key
+        assert_eq!(text.keycode('あ'), Some(2));
+        assert_eq!(text.keycode('b'), None);
+    }
+
+    #[test]
+    fn rejects_control_and_oversized_input() {
+        assert!(TextKeymap::new("a\n".chars()).is_none());
+        let many = (0..=KEYS_PER_KEYMAP as u32).map(|i| char::from_u32(0x4E00 + i).unwrap());
+        assert!(TextKeymap::new(many).is_none());
+        let full = (0..KEYS_PER_KEYMAP as u32).map(|i| char::from_u32(0x4E00 + i).unwrap());
+        compile(&TextKeymap::new(full).unwrap().keymap);
+    }
+}
@@ -3,20 +3,45 @@
 //! Manages Wayland protocol handles, serial numbers, and activation state.
 
 use std::os::fd::{AsFd, FromRawFd, OwnedFd};
+use std::time::{Duration, Instant};
 
 use wayland_client::QueueHandle;
+use wayland_client::protocol::wl_pointer::WlPointer;
 use wayland_protocols_misc::zwp_input_method_v2::client::{
     zwp_input_method_keyboard_grab_v2::ZwpInputMethodKeyboardGrabV2,
     zwp_input_method_v2::ZwpInputMethodV2,
 };
 use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1;
+use wayland_protocols_wlr::data_control::v1::client::zwlr_data_control_device_v1::ZwlrDataControlDeviceV1;
 
+use super::{ContentType, KEYS_PER_KEYMAP, SurroundingText, TextKeymap};
 use crate::State;
+use crate::trace::{self, Span};
+
+/// Registry name of a wl_seat global (user data on per-seat protocol objects)
+pub type SeatId = u32;
+
+/// A grab held while deactivated is released after this long
+const INACTIVE_GRAB_GRACE: Duration = Duration::from_secs(2);
+
+/// Whether a grab idle for `idle` is presumed stuck. A grab should never outlive
+/// Deactivate, so one held while deactivated is released after a short grace.
+/// While active, idleness alone is weak evidence (the user may just be
+/// pausing), so it only counts when the user opted in with `timeout` (0 = never).
+pub fn grab_is_stale(idle: Duration, active: bool, timeout: Duration) -> bool {
+    if active {
+        !timeout.is_zero() && idle >= timeout
+    } else {
+        idle >= INACTIVE_GRAB_GRACE
+    }
+}
 
 /// Wayland protocol state
 pub struct WaylandState {
     /// Queue handle for creating new protocol objects
     pub qh: QueueHandle<State>,
+    /// Seat this input method belongs to
+    pub seat_id: SeatId,
     /// Input method protocol instance
     pub input_method: ZwpInputMethodV2,
     /// Active keyboard grab (when IME is enabled)
@@ -29,25 +54,50 @@ pub struct WaylandState {
     pub virtual_keyboard: Option<ZwpVirtualKeyboardV1>,
     /// Whether the virtual keyboard has a keymap set (required before sending events)
     pub virtual_keyboard_ready: bool,
+    /// Grabbed keymap set on the virtual keyboard (restored after typing text)
+    virtual_keymap: Option<String>,
+    /// Layout group last sent to the app (kept when clearing modifiers)
+    pub app_group: u32,
+    /// Data-control device for this seat's clipboard (None without wlr-data-control)
+    pub data_device: Option<ZwlrDataControlDeviceV1>,
+    /// Pointer for clicks/scrolling on the popup (behavior.popup_pointer)
+    pub pointer: Option<WlPointer>,
     /// Pending activate flag (set in Activate, processed in Done)
     pub pending_activate: bool,
     /// Pending deactivate flag (set in Deactivate, processed in Done)
     pub pending_deactivate: bool,
+    /// Pending surrounding text (set in SurroundingText, applied in Done)
+    pub pending_surrounding: Option<SurroundingText>,
+    /// Pending content type (set in ContentType, applied in Done)
+    pub pending_content_type: Option<ContentType>,
+    /// Last Key/Modifiers event on the grab (or when it was taken)
+    pub grab_activity: Option<Instant>,
+    /// Interface name and bound version of each global (for version reporting)
+    pub bound_protocols: Vec<(&'static str, u32)>,
 }
 
 impl WaylandState {
     /// Create new Wayland state
-    pub fn new(qh: QueueHandle<State>, input_method: ZwpInputMethodV2) -> Self {
+    pub fn new(qh: QueueHandle<State>, seat_id: SeatId, input_method: ZwpInputMethodV2) -> Self {
         Self {
             qh,
+            seat_id,
             input_method,
             keyboard_grab: None,
             serial: 0,
             active: false,
             virtual_keyboard: None,
             virtual_keyboard_ready: false,
+            virtual_keymap: None,
+            app_group: 0,
+            data_device: None,
+            pointer: None,
             pending_activate: false,
             pending_deactivate: false,
+            pending_surrounding: None,
+            pending_content_type: None,
+            grab_activity: None,
+            bound_protocols: Vec::new(),
         }
     }
 
@@ -56,13 +106,29 @@ impl WaylandState {
         if self.keyboard_grab.is_some() {
             return false;
         }
-        let grab = self.input_method.grab_keyboard(&self.qh, ());
+        let grab = self.input_method.grab_keyboard(&self.qh, self.seat_id);
         self.keyboard_grab = Some(grab);
+        self.grab_activity = Some(Instant::now());
         true
     }
 
+    /// Record input on the grab (it is evidently still routed to us)
+    pub fn touch_grab(&mut self) {
+        if self.keyboard_grab.is_some() {
+            self.grab_activity = Some(Instant::now());
+        }
+    }
+
+    /// How long the grab has gone without input (None = no grab)
+    pub fn grab_idle(&self, now: Instant) -> Option<Duration> {
+        self.keyboard_grab.as_ref()?;
+        self.grab_activity
+            .map(|at| now.saturating_duration_since(at))
+    }
+
     /// Release the keyboard grab
     pub fn release_keyboard(&mut self) -> bool {
+        self.grab_activity = None;
         if let Some(grab) = self.keyboard_grab.take() {
             grab.release();
             self.clear_modifiers();
@@ -74,28 +140,54 @@ impl WaylandState {
 
     /// Set the keymap on the virtual keyboard (must be called before clear_modifiers)
     pub fn set_virtual_keymap(&mut self, keymap_str: &str) {
+        if self.upload_keymap(keymap_str) {
+            self.virtual_keymap = Some(keymap_str.to_string());
+            self.virtual_keyboard_ready = true;
+        }
+    }
+
+    /// Send a keymap to the virtual keyboard
+    fn upload_keymap(&self, keymap_str: &str) -> bool {
         if let Some(ref vk) = self.virtual_keyboard
             && let Some(fd) = create_keymap_memfd(keymap_str)
         {
             let size = (keymap_str.len() + 1) as u32; // +1 for null terminator
             vk.keymap(1, fd.as_fd(), size); // 1 = XKB_V1 format
-            self.virtual_keyboard_ready = true;
             log::debug!("[VK] Keymap set on virtual keyboard (size={})", size);
+            true
+        } else {
+            false
         }
     }
 
     /// Clear all modifier state via virtual keyboard.
     /// This fixes stuck modifiers (e.g., Alt from toggle keybind leaking to the app
     /// before the keyboard grab starts, then the release being consumed by the grab).
+    /// The layout group is kept, so the app stays on the user's layout.
     pub fn clear_modifiers(&self) {
         if self.virtual_keyboard_ready
             && let Some(ref vk) = self.virtual_keyboard
         {
-            vk.modifiers(0, 0, 0, 0);
+            vk.modifiers(0, 0, 0, self.app_group);
             log::debug!("[VK] Cleared modifiers via virtual keyboard");
         }
     }
 
+    /// Forward a layout group switch made while grabbed (the grab consumes it,
+    /// so the app would keep interpreting passthrough keys in the old group)
+    pub fn forward_group(&mut self, group: u32) {
+        if group == self.app_group {
+            return;
+        }
+        self.app_group = group;
+        if self.virtual_keyboard_ready
+            && let Some(ref vk) = self.virtual_keyboard
+        {
+            vk.modifiers(0, 0, 0, group);
+            log::debug!("[VK] Forwarded layout group {}", group);
+        }
+    }
+
     /// Update preedit and commit
     pub fn set_preedit(&mut self, text: &str, cursor_begin: i32, cursor_end: i32) {
         self.input_method
@@ -105,6 +197,7 @@ impl WaylandState {
 
     /// Commit text to the application
     pub fn commit_string(&mut self, text: &str) {
+        trace::instant(Span::Commit);
         self.input_method.commit_string(text.to_string());
         self.input_method.set_preedit_string(String::new(), 0, 0);
         self.input_method.commit(self.serial);
@@ -119,7 +212,7 @@ impl WaylandState {
     /// Send a key event via the virtual keyboard (for passthrough).
     /// Sends modifiers, key press, key release, then clears modifiers.
     pub fn send_virtual_key(
-        &self,
+        &mut self,
         keycode: u32,
         mods_depressed: u32,
         mods_latched: u32,
@@ -140,14 +233,67 @@ impl WaylandState {
         vk.key(0, keycode, 1); // 1 = pressed
         // Key release
         vk.key(0, keycode, 0); // 0 = released
-        // Clear modifiers after the key event
-        vk.modifiers(0, 0, 0, 0);
+        // Clear modifiers after the key event (the group stays)
+        vk.modifiers(0, 0, 0, mods_group);
+        self.app_group = mods_group;
         log::debug!(
-            "[VK] Sent virtual key: keycode={}, mods_depressed=0x{:x}",
+            "[VK] Sent virtual key: keycode={}, mods_depressed=0x{:x}, group={}",
             keycode,
-            mods_depressed
+            mods_depressed,
+            mods_group
         );
     }
+
+    /// Type `text` via the virtual keyboard with generated keymaps, independent
+    /// of the layout the app uses. Returns false if nothing could be sent.
+    pub fn type_text(&mut self, text: &str) -> bool {
+        if !self.virtual_keyboard_ready {
+            log::warn!("[VK] Cannot type text — keymap not set");
+            return false;
+        }
+        let Some(ref vk) = self.virtual_keyboard else {
+            log::warn!("[VK] Cannot type text — no virtual keyboard");
+            return false;
+        };
+        let chars: Vec<char> = text.chars().collect();
+        let mut start = 0;
+        while start < chars.len() {
+            // Longest run that fits in one keymap
+            let mut end = start;
+            let mut distinct: Vec<char> = Vec::new();
+            while end < chars.len() {
+                if !distinct.contains(&chars[end]) {
+                    if distinct.len() == KEYS_PER_KEYMAP {
+                        break;
+                    }
+                    distinct.push(chars[end]);
+                }
+                end += 1;
+            }
+            let Some(keymap) = TextKeymap::new(distinct) else {
+                log::warn!("[VK] Cannot type {:?} — no keysym", text);
+                break;
+            };
+            self.upload_keymap(&keymap.keymap);
+            vk.modifiers(0, 0, 0, 0);
+            for &c in &chars[start..end] {
+                if let Some(keycode) = keymap.keycode(c) {
+                    vk.key(0, keycode, 1);
+                    vk.key(0, keycode, 0);
+                }
+            }
+            start = end;
+        }
+        // Back to the grabbed keymap and the app's group
+        if let Some(keymap) = self.virtual_keymap.clone() {
+            self.upload_keymap(&keymap);
+        }
+        if let Some(ref vk) = self.virtual_keyboard {
+            vk.modifiers(0, 0, 0, self.app_group);
+        }
+        log::debug!("[VK] Typed {} chars via generated keymap", start);
+        start > 0
+    }
 }
 
 /// Create a memfd containing the keymap string (with null terminator) for the virtual keyboard
@@ -165,3 +311,33 @@ fn create_keymap_memfd(keymap_str: &str) -> Option<OwnedFd> {
     file.seek(std::io::SeekFrom::Start(0)).ok()?;
     Some(OwnedFd::from(file))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(300);
+
+    #[test]
+    fn active_grab_stale_only_after_timeout() {
+        assert!(!grab_is_stale(Duration::from_secs(60), true, TIMEOUT));
+        assert!(grab_is_stale(TIMEOUT, true, TIMEOUT));
+    }
+
+    #[test]
+    fn inactive_grab_stale_after_grace() {
+        assert!(!grab_is_stale(Duration::from_secs(1), false, TIMEOUT));
+        assert!(grab_is_stale(INACTIVE_GRAB_GRACE, false, TIMEOUT));
+    }
+
+    #[test]
+    fn zero_timeout_disables_only_the_active_check() {
+        assert!(!grab_is_stale(
+            Duration::from_secs(3600),
+            true,
+            Duration::ZERO
+        ));
+        // Held after Deactivate: stuck whatever the timeout
+        assert!(grab_is_stale(INACTIVE_GRAB_GRACE, false, Duration::ZERO));
+    }
+}
@@ -0,0 +1,310 @@
+//! Focused application tracking for per-app IME state (`behavior.remember_per_app`)
+//!
+//! zwp_input_method_v2 doesn't say which client owns the focused text input, so
+//! the app_id comes from wlr-foreign-toplevel-management: the activated toplevel
+//! is taken to own the text input. Each Activate (and each focus change while a
+//! text input is active) restores the enabled state last used in that app;
+//! toggles record it in `AppMemory`.
+//!
+//! The same tracking disables the IME while a fullscreen toplevel whose app_id
+//! is in `behavior.fullscreen_blocklist` (a game) is focused, and enables it
+//! again once focus or fullscreen leaves it.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use wayland_client::backend::ObjectId;
+use wayland_protocols_wlr::foreign_toplevel::v1::client::zwlr_foreign_toplevel_handle_v1;
+
+use crate::State;
+
+/// Double-buffered toplevel properties (applied on `done`)
+#[derive(Debug, Default)]
+struct Toplevel {
+    app_id: String,
+    activated: bool,
+    fullscreen: bool,
+    pending_app_id: Option<String>,
+    pending_activated: Option<bool>,
+    pending_fullscreen: Option<bool>,
+}
+
+/// Toplevels by handle and which one is activated
+#[derive(Debug)]
+pub struct ToplevelTracker<K> {
+    toplevels: HashMap<K, Toplevel>,
+    focused: Option<K>,
+}
+
+impl<K> Default for ToplevelTracker<K> {
+    fn default() -> Self {
+        Self {
+            toplevels: HashMap::new(),
+            focused: None,
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone> ToplevelTracker<K> {
+    pub fn set_app_id(&mut self, key: K, app_id: String) {
+        self.toplevels.entry(key).or_default().pending_app_id = Some(app_id);
+    }
+
+    pub fn set_activated(&mut self, key: K, activated: bool) {
+        self.toplevels.entry(key).or_default().pending_activated = Some(activated);
+    }
+
+    pub fn set_fullscreen(&mut self, key: K, fullscreen: bool) {
+        self.toplevels.entry(key).or_default().pending_fullscreen = Some(fullscreen);
+    }
+
+    /// Apply pending properties. Returns true if the focused app changed.
+    pub fn done(&mut self, key: K) -> bool {
+        let before = self.focused_app().map(str::to_string);
+        let toplevel = self.toplevels.entry(key.clone()).or_default();
+        if let Some(app_id) = toplevel.pending_app_id.take() {
+            toplevel.app_id = app_id;
+        }
+        if let Some(activated) = toplevel.pending_activated.take() {
+            toplevel.activated = activated;
+        }
+        if let Some(fullscreen) = toplevel.pending_fullscreen.take() {
+            toplevel.fullscreen = fullscreen;
+        }
+        if toplevel.activated {
+            self.focused = Some(key);
+        } else if self.focused.as_ref() == Some(&key) {
+            // Another toplevel's activation may already have replaced it
+            self.focused = None;
+        }
+        self.focused_app() != before.as_deref()
+    }
+
+    /// Toplevel closed. Returns true if it was the focused one.
+    pub fn closed(&mut self, key: &K) -> bool {
+        self.toplevels.remove(key);
+        if self.focused.as_ref() == Some(key) {
+            self.focused = None;
+            return true;
+        }
+        false
+    }
+
+    /// app_id of the activated toplevel (None if unknown or empty)
+    pub fn focused_app(&self) -> Option<&str> {
+        let toplevel = self.toplevels.get(self.focused.as_ref()?)?;
+        Some(toplevel.app_id.as_str()).filter(|id| !id.is_empty())
+    }
+
+    /// app_id of the activated toplevel if it is fullscreen and in `blocklist`
+    pub fn focused_blocked<'a>(&'a self, blocklist: &[String]) -> Option<&'a str> {
+        let toplevel = self.toplevels.get(self.focused.as_ref()?)?;
+        let app_id = toplevel.app_id.as_str();
+        (toplevel.fullscreen && blocklist.iter().any(|b| b == app_id)).then_some(app_id)
+    }
+}
+
+/// Whether a `state` event array contains `state`
+fn has_state(states: &[u8], state: zwlr_foreign_toplevel_handle_v1::State) -> bool {
+    let state = state as u32;
+    states
+        .chunks_exact(4)
+        .any(|c| u32::from_ne_bytes([c[0], c[1], c[2], c[3]]) == state)
+}
+
+/// Whether a `state` event array contains `activated`
+pub fn is_activated(states: &[u8]) -> bool {
+    has_state(states, zwlr_foreign_toplevel_handle_v1::State::Activated)
+}
+
+/// Whether a `state` event array contains `fullscreen`
+pub fn is_fullscreen(states: &[u8]) -> bool {
+    has_state(states, zwlr_foreign_toplevel_handle_v1::State::Fullscreen)
+}
+
+impl State {
+    /// A toplevel applied its pending properties
+    pub(crate) fn toplevel_done(&mut self, id: ObjectId) {
+        let was_blocked = self.fullscreen_blocked().is_some();
+        let focus_changed = self.toplevels.done(id);
+        let blocked = self.fullscreen_blocked().map(str::to_string);
+        if was_blocked && blocked.is_none() {
+            self.leave_blocked_app();
+        }
+        if focus_changed {
+            self.on_focused_app_changed();
+        }
+        if !was_blocked && let Some(app) = blocked {
+            self.enter_blocked_app(&app);
+        }
+    }
+
+    /// A toplevel went away; closing a blocklisted game leaves it like a focus change
+    pub(crate) fn toplevel_closed(&mut self, id: &ObjectId) {
+        let was_blocked = self.fullscreen_blocked().is_some();
+        self.toplevels.closed(id);
+        if was_blocked && self.fullscreen_blocked().is_none() {
+            self.leave_blocked_app();
+        }
+    }
+
+    /// The focused app_id if it is a fullscreen app in `behavior.fullscreen_blocklist`
+    pub(crate) fn fullscreen_blocked(&self) -> Option<&str> {
+        self.toplevels
+            .focused_blocked(&self.config.behavior.fullscreen_blocklist)
+    }
+
+    /// A blocklisted app went fullscreen with focus: disable the IME (releasing
+    /// the keyboard and hiding the popup) until it loses either
+    fn enter_blocked_app(&mut self, app: &str) {
+        if !self.ime.is_enabled() {
+            log::info!("[APP] Fullscreen {} focused (IME already disabled)", app);
+            return;
+        }
+        log::info!("[APP] Fullscreen {} focused, disabling IME", app);
+        self.toggle_ime();
+        self.fullscreen_suspended = !self.ime.is_enabled();
+    }
+
+    /// Focus or fullscreen left a blocklisted app: enable the IME again if it
+    /// was on when the app took over
+    fn leave_blocked_app(&mut self) {
+        if !std::mem::take(&mut self.fullscreen_suspended) {
+            return;
+        }
+        if !self.ime.is_enabled() {
+            log::info!("[APP] Left fullscreen app, enabling IME again");
+            self.toggle_ime();
+        }
+    }
+
+    /// The activated toplevel changed: a text input already active belongs to it
+    pub(crate) fn on_focused_app_changed(&mut self) {
+        log::debug!("[APP] Focused app: {:?}", self.toplevels.focused_app());
+        if self.wayland.active {
+            self.restore_app_ime_state();
+        }
+    }
+
+    /// Enable or disable the IME as it was last used in the focused app
+    pub(crate) fn restore_app_ime_state(&mut self) {
+        if self.fullscreen_blocked().is_some() {
+            return;
+        }
+        let Some(ref memory) = self.app_memory else {
+            return;
+        };
+        let Some(app) = self.toplevels.focused_app() else {
+            return;
+        };
+        if let Some(enabled) = memory.get(app)
+            && enabled != self.ime.is_enabled()
+        {
+            log::info!(
+                "[APP] Restoring IME {} for {}",
+                if enabled { "enabled" } else { "disabled" },
+                app
+            );
+            self.toggle_ime();
+        }
+    }
+
+    /// Record the current enabled state for the focused app
+    pub(crate) fn remember_app_ime_state(&mut self) {
+        let enabled = self.ime.is_enabled();
+        if let Some(ref mut memory) = self.app_memory
+            && let Some(app) = self.toplevels.focused_app()
+        {
+            memory.set(app, enabled);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn states(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_ne_bytes()).collect()
+    }
+
+    #[test]
+    fn activated_state_parsing() {
+        assert!(is_activated(&states(&[0, 2])));
+        assert!(!is_activated(&states(&[0, 1, 3])));
+        assert!(!is_activated(&[]));
+        assert!(is_fullscreen(&states(&[2, 3])));
+        assert!(!is_fullscreen(&states(&[0, 2])));
+    }
+
+    #[test]
+    fn blocked_only_while_fullscreen_and_focused() {
+        let blocklist = vec!["steam_app_730".to_string()];
+        let mut tracker = ToplevelTracker::default();
+        tracker.set_app_id(1, "steam_app_730".into());
+        tracker.set_activated(1, true);
+        tracker.done(1);
+        // Windowed: still usable for chat etc.
+        assert_eq!(tracker.focused_blocked(&blocklist), None);
+        tracker.set_fullscreen(1, true);
+        tracker.done(1);
+        assert_eq!(tracker.focused_blocked(&blocklist), Some("steam_app_730"));
+
+        // Alt-tab to a browser: the game stays fullscreen but isn't focused
+        tracker.set_app_id(2, "firefox".into());
+        tracker.set_activated(2, true);
+        tracker.set_fullscreen(2, true);
+        tracker.done(2);
+        assert_eq!(tracker.focused_blocked(&blocklist), None);
+    }
+
+    #[test]
+    fn blocked_game_closing_while_focused() {
+        let blocklist = vec!["steam_app_730".to_string()];
+        let mut tracker = ToplevelTracker::default();
+        tracker.set_app_id(1, "steam_app_730".into());
+        tracker.set_activated(1, true);
+        tracker.set_fullscreen(1, true);
+        tracker.done(1);
+        assert_eq!(tracker.focused_blocked(&blocklist), Some("steam_app_730"));
+
+        // Quit from the game's menu: closed without ever losing activation
+        assert!(tracker.closed(&1));
+        assert_eq!(tracker.focused_blocked(&blocklist), None);
+    }
+
+    #[test]
+    fn focus_follows_activation() {
+        let mut tracker = ToplevelTracker::default();
+        tracker.set_app_id(1, "firefox".into());
+        tracker.set_activated(1, true);
+        // Nothing applies before done
+        assert_eq!(tracker.focused_app(), None);
+        assert!(tracker.done(1));
+        assert_eq!(tracker.focused_app(), Some("firefox"));
+
+        // B activated before A reports losing activation
+        tracker.set_app_id(2, "foot".into());
+        tracker.set_activated(2, true);
+        assert!(tracker.done(2));
+        tracker.set_activated(1, false);
+        assert!(!tracker.done(1));
+        assert_eq!(tracker.focused_app(), Some("foot"));
+
+        assert!(tracker.closed(&2));
+        assert_eq!(tracker.focused_app(), None);
+        assert!(!tracker.closed(&1));
+    }
+
+    #[test]
+    fn app_id_change_of_focused_toplevel() {
+        let mut tracker = ToplevelTracker::default();
+        tracker.set_activated(1, true);
+        // Activated but without an app_id yet
+        assert!(!tracker.done(1));
+        assert_eq!(tracker.focused_app(), None);
+        tracker.set_app_id(1, "org.gnome.Nautilus".into());
+        assert!(tracker.done(1));
+        assert_eq!(tracker.focused_app(), Some("org.gnome.Nautilus"));
+    }
+}
@@ -0,0 +1,302 @@
+//! Performance flight recorder
+//!
+//! A fixed ring of timing events (key in, RPC, render, commit) that is always
+//! recording and never read during normal operation. Each event is packed into
+//! a single `AtomicU64`, so recording is lock-free and safe from any thread.
+//! On SIGQUIT or `jacinctl trace dump` the recent events are exported in Chrome
+//! trace-event JSON (load in Perfetto or `chrome://tracing`).
+
+use std::cell::Cell;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU8, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+/// Ring capacity (events); at typing speed this covers far more than `WINDOW`
+const CAPACITY: usize = 8192;
+/// Only events this recent are exported
+const WINDOW: Duration = Duration::from_secs(10);
+
+/// Traced operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Span {
+    /// A key event handled on the main thread
+    Key = 1,
+    /// Main thread waiting for the backend to finish a key
+    Rpc,
+    /// Backend processing a key (Neovim thread)
+    NvimKey,
+    /// Popup render
+    Render,
+    /// Text committed to the application
+    Commit,
+}
+
+impl Span {
+    const ALL: [Span; 5] = [
+        Span::Key,
+        Span::Rpc,
+        Span::NvimKey,
+        Span::Render,
+        Span::Commit,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Span::Key => "key",
+            Span::Rpc => "rpc",
+            Span::NvimKey => "nvim_key",
+            Span::Render => "render",
+            Span::Commit => "commit",
+        }
+    }
+
+    fn from_u8(v: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| *s as u8 == v)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Begin = 0,
+    End = 1,
+    Instant = 2,
+}
+
+impl Phase {
+    /// Chrome trace-event `ph` value
+    fn code(self) -> &'static str {
+        match self {
+            Phase::Begin => "B",
+            Phase::End => "E",
+            Phase::Instant => "i",
+        }
+    }
+
+    fn from_bits(v: u64) -> Self {
+        match v {
+            0 => Phase::Begin,
+            1 => Phase::End,
+            _ => Phase::Instant,
+        }
+    }
+}
+
+/// Decoded ring entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Event {
+    span: Span,
+    phase: Phase,
+    tid: u8,
+    /// Microseconds since the recorder epoch
+    ts_us: u64,
+}
+
+// Packed layout: bits 0-7 span, 8-9 phase, 10-17 thread, 18-63 timestamp (µs)
+const TS_SHIFT: u32 = 18;
+
+impl Event {
+    fn pack(self) -> u64 {
+        (self.span as u64)
+            | ((self.phase as u64) << 8)
+            | (u64::from(self.tid) << 10)
+            | (self.ts_us << TS_SHIFT)
+    }
+
+    /// None for empty slots
+    fn unpack(v: u64) -> Option<Self> {
+        Some(Self {
+            span: Span::from_u8((v & 0xff) as u8)?,
+            phase: Phase::from_bits((v >> 8) & 0b11),
+            tid: ((v >> 10) & 0xff) as u8,
+            ts_us: v >> TS_SHIFT,
+        })
+    }
+}
+
+struct Recorder {
+    epoch: Instant,
+    next: AtomicUsize,
+    slots: Box<[AtomicU64]>,
+}
+
+impl Recorder {
+    fn new(capacity: usize) -> Self {
+        Self {
+            epoch: Instant::now(),
+            next: AtomicUsize::new(0),
+            slots: (0..capacity).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    fn now_us(&self) -> u64 {
+        self.epoch.elapsed().as_micros() as u64
+    }
+
+    fn record(&self, event: Event) {
+        let i = self.next.fetch_add(1, Ordering::Relaxed) % self.slots.len();
+        self.slots[i].store(event.pack(), Ordering::Relaxed);
+    }
+
+    /// Events newer than `window` before `now_us`, oldest first
+    fn events(&self, now_us: u64, window: Duration) -> Vec<Event> {
+        let since = now_us.saturating_sub(window.as_micros() as u64);
+        let mut events: Vec<Event> = self
+            .slots
+            .iter()
+            .filter_map(|slot| Event::unpack(slot.load(Ordering::Relaxed)))
+            .filter(|e| e.ts_us >= since)
+            .collect();
+        events.sort_by_key(|e| e.ts_us);
+        events
+    }
+}
+
+fn recorder() -> &'static Recorder {
+    static RECORDER: OnceLock<Recorder> = OnceLock::new();
+    RECORDER.get_or_init(|| Recorder::new(CAPACITY))
+}
+
+/// Small per-thread id for the `tid` field (assigned on first event)
+fn thread_id() -> u8 {
+    static NEXT: AtomicU8 = AtomicU8::new(1);
+    thread_local! {
+        static TID: Cell<u8> = const { Cell::new(0) };
+    }
+    TID.with(|tid| {
+        if tid.get() == 0 {
+            tid.set(NEXT.fetch_add(1, Ordering::Relaxed).max(1));
+        }
+        tid.get()
+    })
+}
+
+fn record(span: Span, phase: Phase) {
+    let recorder = recorder();
+    recorder.record(Event {
+        span,
+        phase,
+        tid: thread_id(),
+        ts_us: recorder.now_us(),
+    });
+}
+
+/// Record a point event
+pub fn instant(span: Span) {
+    record(span, Phase::Instant);
+}
+
+/// Record the start of `span`; the end is recorded when the guard drops
+pub fn span(span: Span) -> SpanGuard {
+    record(span, Phase::Begin);
+    SpanGuard(span)
+}
+
+pub struct SpanGuard(Span);
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        record(self.0, Phase::End);
+    }
+}
+
+fn to_chrome(events: &[Event]) -> serde_json::Value {
+    let pid = std::process::id();
+    let events: Vec<_> = events
+        .iter()
+        .map(|e| {
+            let mut v = json!({
+                "name": e.span.name(),
+                "ph": e.phase.code(),
+                "ts": e.ts_us,
+                "pid": pid,
+                "tid": e.tid,
+            });
+            if e.phase == Phase::Instant {
+                v["s"] = json!("t");
+            }
+            v
+        })
+        .collect();
+    json!({ "traceEvents": events, "displayTimeUnit": "ms" })
+}
+
+/// Recent events as a Chrome trace-event document
+pub fn dump() -> serde_json::Value {
+    let recorder = recorder();
+    to_chrome(&recorder.events(recorder.now_us(), WINDOW))
+}
+
+/// Write `dump()` to `$XDG_RUNTIME_DIR/jacin-trace-<pid>.json` (or the temp dir)
+pub fn dump_to_file() -> std::io::Result<PathBuf> {
+    let dir = std::env::var_os("XDG_RUNTIME_DIR")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    let path = dir.join(format!("jacin-trace-{}.json", std::process::id()));
+    std::fs::write(&path, dump().to_string())?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(span: Span, phase: Phase, ts_us: u64) -> Event {
+        Event {
+            span,
+            phase,
+            tid: 3,
+            ts_us,
+        }
+    }
+
+    #[test]
+    fn pack_roundtrip() {
+        for span in Span::ALL {
+            for phase in [Phase::Begin, Phase::End, Phase::Instant] {
+                let e = event(span, phase, 123_456_789);
+                assert_eq!(Event::unpack(e.pack()), Some(e));
+            }
+        }
+        assert_eq!(Event::unpack(0), None);
+    }
+
+    #[test]
+    fn ring_keeps_latest_events() {
+        let recorder = Recorder::new(4);
+        for ts in 1..=6 {
+            recorder.record(event(Span::Key, Phase::Instant, ts));
+        }
+        let ts: Vec<u64> = recorder.events(6, WINDOW).iter().map(|e| e.ts_us).collect();
+        assert_eq!(ts, vec![3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn old_events_outside_window() {
+        let recorder = Recorder::new(8);
+        recorder.record(event(Span::Render, Phase::Begin, 1_000));
+        recorder.record(event(Span::Render, Phase::End, 5_000_000));
+        let events = recorder.events(5_000_000, Duration::from_secs(1));
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].phase, Phase::End);
+    }
+
+    #[test]
+    fn chrome_format() {
+        let doc = to_chrome(&[
+            event(Span::Rpc, Phase::Begin, 10),
+            event(Span::Commit, Phase::Instant, 20),
+        ]);
+        let events = doc["traceEvents"].as_array().unwrap();
+        assert_eq!(events[0]["name"], "rpc");
+        assert_eq!(events[0]["ph"], "B");
+        assert_eq!(events[0]["ts"], 10);
+        assert_eq!(events[1]["ph"], "i");
+        assert_eq!(events[1]["s"], "t");
+        assert_eq!(events[1]["tid"], 3);
+    }
+}
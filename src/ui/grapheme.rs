@@ -0,0 +1,107 @@
+//! Grapheme clusters for measuring and drawing text
+//!
+//! Text is split into user-perceived characters: a kanji with its ideographic
+//! variation selector, an emoji with its skin tone modifier or ZWJ sequence, a
+//! flag (two regional indicators) and a base with combining marks each pick one
+//! font for shaping and take cursor positions as one unit.
+//!
+//! The segmentation is UAX #29's extended grapheme clusters
+//! (unicode-segmentation).
+
+use unicode_segmentation::{Graphemes, UnicodeSegmentation};
+
+/// Iterator over the grapheme clusters of a string
+pub fn clusters(text: &str) -> Graphemes<'_> {
+    text.graphemes(true)
+}
+
+/// Map every byte offset of `text` (and `text.len()`) to the index of the
+/// cluster it falls in, so cursor offsets inside a cluster snap to its start.
+/// Returns the mapping and the clusters.
+pub fn byte_to_cluster(text: &str) -> (Vec<usize>, Vec<&str>) {
+    let clusters: Vec<&str> = clusters(text).collect();
+    let mut map = Vec::with_capacity(text.len() + 1);
+    for (i, cluster) in clusters.iter().enumerate() {
+        map.extend(std::iter::repeat_n(i, cluster.len()));
+    }
+    map.push(clusters.len());
+    (map, clusters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(text: &str) -> Vec<&str> {
+        clusters(text).collect()
+    }
+
+    #[test]
+    fn ivs_kanji_is_one_cluster() {
+        // 葛 with IVS (Adobe-Japan1 葛 with 匂 radical), then plain 城
+        assert_eq!(split("葛\u{E0100}城"), vec!["葛\u{E0100}", "城"]);
+        assert_eq!(split("辻\u{E0101}\u{E0100}"), vec!["辻\u{E0101}\u{E0100}"]);
+        // Standardized variation selector on a kanji
+        assert_eq!(split("祇\u{FE00}園"), vec!["祇\u{FE00}", "園"]);
+    }
+
+    #[test]
+    fn non_bmp_kanji() {
+        assert_eq!(split("𠮷野家"), vec!["𠮷", "野", "家"]);
+        assert_eq!(split("𩸽\u{E0100}"), vec!["𩸽\u{E0100}"]);
+    }
+
+    #[test]
+    fn emoji_sequences() {
+        // Family (ZWJ), skin tone, keycap, emoji presentation
+        assert_eq!(
+            split("👨\u{200D}👩\u{200D}👧👍🏽1\u{FE0F}\u{20E3}❤\u{FE0F}"),
+            vec![
+                "👨\u{200D}👩\u{200D}👧",
+                "👍🏽",
+                "1\u{FE0F}\u{20E3}",
+                "❤\u{FE0F}"
+            ]
+        );
+    }
+
+    #[test]
+    fn flags_pair_regional_indicators() {
+        assert_eq!(split("🇯🇵🇺🇸🇫"), vec!["🇯🇵", "🇺🇸", "🇫"]);
+    }
+
+    #[test]
+    fn combining_marks() {
+        assert_eq!(
+            split("か\u{3099}a\u{0301}"),
+            vec!["か\u{3099}", "a\u{0301}"]
+        );
+        // A stray mark still forms a cluster
+        assert_eq!(split("\u{0301}a"), vec!["\u{0301}", "a"]);
+        assert_eq!(split("a\r\nb"), vec!["a", "\r\n", "b"]);
+        // Thai vowel sign, Devanagari vowel sign (spacing mark), Hebrew point
+        assert_eq!(split("กัน"), vec!["กั", "น"]);
+        assert_eq!(split("किस"), vec!["कि", "स"]);
+        assert_eq!(split("\u{05E9}\u{05C1}"), vec!["\u{05E9}\u{05C1}"]);
+    }
+
+    #[test]
+    fn zwj_joins_only_pictographs() {
+        assert_eq!(split("a\u{200D}b"), vec!["a\u{200D}", "b"]);
+        assert_eq!(split("👩\u{200D}💻"), vec!["👩\u{200D}💻"]);
+    }
+
+    #[test]
+    fn cursor_offsets_snap_to_cluster_starts() {
+        let text = "a葛\u{E0100}b";
+        let (map, clusters) = byte_to_cluster(text);
+        assert_eq!(clusters.len(), 3);
+        assert_eq!(map.len(), text.len() + 1);
+        assert_eq!(map[0], 0);
+        assert_eq!(map[1], 1); // 葛
+        assert_eq!(map[4], 1); // inside the selector
+        assert_eq!(map[8], 2); // b
+        assert_eq!(map[text.len()], 3);
+        assert_eq!(byte_to_cluster("").0, vec![0]);
+    }
+}
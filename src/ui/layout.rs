@@ -4,9 +4,18 @@
 //! depends on `TextRenderer` for text measurement; a future step can make it
 //! fully pure by accepting measurement results as parameters.
 
-use crate::neovim::VisualSelection;
+use std::borrow::Cow;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
 
+use crate::config::CandidateLabels;
+use crate::neovim::{PreeditSegment, VisualSelection};
+use crate::state::MacroPreview;
+
+use super::grapheme;
 use super::text_render::TextRenderer;
+use super::theme::Theme;
 
 /// RGBA color as (r, g, b, a) tuple — converted to Color at use via `rgba()`.
 pub(crate) type Rgba = (u8, u8, u8, u8);
@@ -15,28 +24,56 @@ pub(crate) fn rgba(c: Rgba) -> tiny_skia::Color {
     tiny_skia::Color::from_rgba8(c.0, c.1, c.2, c.3)
 }
 
-// Colors (matching existing windows)
+// Default colors (overridable via `[theme]`, see `Theme`)
 pub(crate) const BG_COLOR: Rgba = (40, 44, 52, 240);
 pub(crate) const TEXT_COLOR: Rgba = (220, 223, 228, 255);
 pub(crate) const BORDER_COLOR: Rgba = (80, 84, 92, 255);
 pub(crate) const SELECTED_BG: Rgba = (61, 89, 161, 255);
 pub(crate) const CURSOR_BG: Rgba = (97, 175, 239, 255);
 pub(crate) const VISUAL_BG: Rgba = (61, 89, 161, 200);
+pub(crate) const SEARCH_BG: Rgba = (229, 192, 123, 110);
 pub(crate) const NUMBER_COLOR: Rgba = (152, 195, 121, 255);
 pub(crate) const SCROLLBAR_BG: Rgba = (60, 64, 72, 255);
 pub(crate) const SCROLLBAR_THUMB: Rgba = (100, 104, 112, 255);
 
 pub(crate) const PADDING: f32 = 8.0;
+/// Most candidate rows on a page (one per label); `popup.max_candidates` may pick fewer
 pub(crate) const MAX_VISIBLE_CANDIDATES: usize = 9;
+/// Extra candidate rows laid out beyond each edge of the viewport, so small
+/// scrolls neither change the popup width nor need rows that were never measured.
+pub(crate) const CANDIDATE_OVERSCAN: usize = 2;
+/// Kinetic scroll deceleration (fraction of velocity kept per second)
+pub(crate) const KINETIC_FRICTION: f32 = 0.05;
+/// Kinetic scrolling stops below this speed (rows per second)
+pub(crate) const KINETIC_MIN_VELOCITY: f32 = 0.5;
 pub(crate) const SCROLLBAR_WIDTH: f32 = 8.0;
 pub(crate) const NUMBER_WIDTH: f32 = 24.0;
 pub(crate) const SECTION_SEPARATOR_HEIGHT: f32 = 1.0;
 pub(crate) const MAX_PREEDIT_WIDTH: f32 = 400.0;
+/// Smallest popup size (the largest comes from `[popup]`, see `Theme`)
+pub(crate) const MIN_POPUP_WIDTH: u32 = 100;
+pub(crate) const MIN_POPUP_HEIGHT: u32 = 30;
+/// Furigana font size relative to the candidate font
+pub(crate) const FURIGANA_SCALE: f32 = 0.5;
+/// Space between a candidate and its annotation
+pub(crate) const ANNOTATION_GAP: f32 = 12.0;
+/// Space before the kind column
+pub(crate) const KIND_GAP: f32 = 12.0;
+/// Annotations and kinds are drawn in the text color at this opacity
+pub(crate) const ANNOTATION_ALPHA: f32 = 0.55;
+/// Ends text cut to fit a candidate row
+pub(crate) const ELLIPSIS: &str = "…";
+/// Lines of the detail row showing a cut selected candidate in full
+pub(crate) const MAX_DETAIL_LINES: usize = 4;
 
 pub(crate) const ICON_SEPARATOR_WIDTH: f32 = 1.0;
 pub(crate) const ICON_SEPARATOR_GAP: f32 = 6.0;
 pub(crate) const MODE_GAP: f32 = 4.0;
 pub(crate) const KEYPRESS_ENTRY_GAP: f32 = 4.0;
+/// Space kept between the keypress text and the latency indicator
+pub(crate) const LATENCY_GAP: f32 = 12.0;
+/// Width of the command-line text cursor (vertical line)
+pub(crate) const CMDLINE_CURSOR_WIDTH: f32 = 2.0;
 pub(crate) const KEYPRESS_TEXT_COLOR: Rgba = (166, 173, 186, 255);
 
 // Mode indicator colors
@@ -48,21 +85,55 @@ pub(crate) const MODE_CMD_COLOR: Rgba = (224, 108, 117, 255); // Red
 pub(crate) const MODE_RECORDING_COLOR: Rgba = (224, 108, 117, 255); // Red
 
 /// Content to display in the unified popup
-#[derive(Default, Clone)]
+#[derive(Default, Clone, PartialEq)]
 pub struct PopupContent {
     pub preedit: String,
     pub cursor_begin: usize,
     pub cursor_end: usize,
+    /// Conversion segment, underlined (thicker once converted)
+    pub preedit_segment: Option<PreeditSegment>,
+    /// Search matches in the preedit (byte ranges), drawn on the search color
+    pub preedit_highlights: Vec<(usize, usize)>,
     pub vim_mode: String,
     pub keypress_entries: Vec<String>,
-    pub candidates: Vec<String>,
+    /// Shown after the entries while Neovim still waits for input ("awaiting motion…")
+    pub keypress_hint: Option<&'static str>,
+    pub candidates: Arc<[String]>,
+    /// Reading per candidate (may be shorter than `candidates`)
+    pub readings: Arc<[String]>,
+    /// Annotation per candidate, drawn dimmed after it (may be shorter too)
+    pub annotations: Arc<[String]>,
+    /// Kind per candidate, drawn dimmed in an aligned column (may be shorter too)
+    pub kinds: Arc<[String]>,
     pub selected: usize,
+    /// Index of the first of `candidates` in the whole list and the list's
+    /// length, for the footer and scrollbar when only a window of a long
+    /// list is at hand
+    pub candidate_offset: usize,
+    pub candidate_total: usize,
+    /// Labels before the visible candidate rows (behavior.candidate_labels)
+    pub candidate_labels: CandidateLabels,
     pub transient_message: Option<String>,
     pub visual_selection: Option<VisualSelection>,
     pub ime_enabled: bool,
     pub recording: String,
     pub rec_blink_on: bool,
+    /// Undo sequence number of the buffer (0 = nothing to undo)
+    pub undo_seq: u64,
+    /// Whether `.` has an insert to repeat
+    pub repeatable: bool,
+    /// Privacy mode: badge after the mode/REC icons, entries arrive masked
+    pub privacy: bool,
     pub cmdline_cursor_pos: Option<usize>,
+    /// `input()` prompt: the command line then takes two rows, the prompt
+    /// after the mode icons and the text below it
+    pub cmdline_prompt: Option<String>,
+    /// Macro row below the keypress row (behavior.macro_preview)
+    pub macro_row: Option<MacroPreview>,
+    /// Diagnostics row at the bottom (behavior.debug_overlay)
+    pub debug_line: Option<String>,
+    /// Last key's round trip, right-aligned in the keypress row (metrics.indicator)
+    pub latency: Option<String>,
 }
 
 impl PopupContent {
@@ -73,28 +144,140 @@ impl PopupContent {
             && self.candidates.is_empty()
             && self.transient_message.is_none()
     }
+
+    /// Undo depth and dot-repeat indicator after the mode icons ("u 3 ."),
+    /// if there is anything to show
+    pub fn history_label(&self) -> Option<String> {
+        let undo = (self.undo_seq > 0).then(|| format!("u {}", self.undo_seq));
+        let repeat = self.repeatable.then(|| ".".to_string());
+        match (undo, repeat) {
+            (Some(undo), Some(repeat)) => Some(format!("{undo} {repeat}")),
+            (undo, repeat) => undo.or(repeat),
+        }
+    }
+
+    /// Length of the whole candidate list
+    pub fn candidate_total(&self) -> usize {
+        self.candidate_total.max(self.candidates.len())
+    }
+
+    /// Furigana to draw above candidate `index`, if it has a usable reading
+    pub fn furigana(&self, index: usize) -> Option<&str> {
+        furigana(self.candidates.get(index)?, self.readings.get(index)?)
+    }
+
+    /// Annotation to draw after candidate `index`, if it has one
+    pub fn annotation(&self, index: usize) -> Option<&str> {
+        self.annotations
+            .get(index)
+            .map(String::as_str)
+            .filter(|a| !a.is_empty())
+    }
+
+    /// Kind to draw in the kind column for candidate `index`, if it has one
+    pub fn kind(&self, index: usize) -> Option<&str> {
+        self.kinds
+            .get(index)
+            .map(String::as_str)
+            .filter(|k| !k.is_empty())
+    }
 }
 
-/// Get mode label text and color from vim_mode string
-pub(crate) fn mode_label(vim_mode: &str) -> (&'static str, Rgba) {
+fn is_kana(c: char) -> bool {
+    matches!(c, '\u{3041}'..='\u{309f}' | '\u{30a0}'..='\u{30ff}')
+}
+
+fn is_kanji(c: char) -> bool {
+    matches!(c, '\u{4e00}'..='\u{9fff}' | '\u{3400}'..='\u{4dbf}' | '々')
+}
+
+/// `reading` as furigana for `candidate`: only kana readings of candidates
+/// that contain kanji (other menu/detail text isn't a reading)
+pub(crate) fn furigana<'a>(candidate: &str, reading: &'a str) -> Option<&'a str> {
+    let reading = reading.trim();
+    (!reading.is_empty()
+        && reading != candidate
+        && reading.chars().all(is_kana)
+        && candidate.chars().any(is_kanji))
+    .then_some(reading)
+}
+
+/// Every label `mode_label` can return (the mode column is sized for the widest)
+pub(crate) const MODE_LABELS: [&str; 5] = ["INS", "NOR", "VIS", "OP", "CMD"];
+
+/// Get mode label text and its theme color from vim_mode string
+pub(crate) fn mode_label(vim_mode: &str, theme: &Theme) -> (&'static str, Rgba) {
     if vim_mode.starts_with("no") {
-        ("OP", MODE_OP_COLOR)
+        ("OP", theme.mode_operator)
     } else {
         match vim_mode {
-            "n" => ("NOR", MODE_NORMAL_COLOR),
-            "v" | "V" | "\x16" => ("VIS", MODE_VISUAL_COLOR),
-            "c" => ("CMD", MODE_CMD_COLOR),
+            "n" => ("NOR", theme.mode_normal),
+            "v" | "V" | "\x16" => ("VIS", theme.mode_visual),
+            "c" => ("CMD", theme.mode_command),
             _ => {
                 if vim_mode.starts_with('v') || vim_mode.starts_with('V') {
-                    ("VIS", MODE_VISUAL_COLOR)
+                    ("VIS", theme.mode_visual)
                 } else {
-                    ("INS", MODE_INSERT_COLOR)
+                    ("INS", theme.mode_insert)
                 }
             }
         }
     }
 }
 
+/// Width of the insert-mode preedit cursor (vertical bar)
+pub(crate) const BAR_CURSOR_WIDTH: f32 = 2.0;
+/// Height of the replace-mode preedit cursor (underline)
+pub(crate) const UNDERLINE_CURSOR_HEIGHT: f32 = 2.0;
+
+/// Shape of the preedit cursor, following Neovim's default 'guicursor'
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CursorShape {
+    /// Insert (and anything unknown): a bar before the character
+    Bar,
+    /// Normal, visual (including blockwise) and select: the whole character
+    Block,
+    /// Operator-pending: the lower half of the character
+    HalfBlock,
+    /// Replace and virtual replace: a line under the character
+    Underline,
+}
+
+/// Cursor shape for a `mode()` string
+pub(crate) fn cursor_shape(vim_mode: &str) -> CursorShape {
+    if vim_mode.starts_with("no") {
+        CursorShape::HalfBlock
+    } else if vim_mode.starts_with('R') {
+        CursorShape::Underline
+    } else if vim_mode.starts_with(['n', 'v', 'V', '\x16', 's', 'S', '\x13']) {
+        CursorShape::Block
+    } else {
+        CursorShape::Bar
+    }
+}
+
+/// Cursor rectangle `(x, y, width, height)` for a character cell at `x` of
+/// `width` in a row at `y` of `height`
+pub(crate) fn cursor_rect(
+    shape: CursorShape,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+) -> (f32, f32, f32, f32) {
+    match shape {
+        CursorShape::Bar => (x, y, BAR_CURSOR_WIDTH, height),
+        CursorShape::Block => (x, y, width, height),
+        CursorShape::HalfBlock => (x, y + height / 2.0, width, height / 2.0),
+        CursorShape::Underline => (
+            x,
+            y + height - UNDERLINE_CURSOR_HEIGHT,
+            width,
+            UNDERLINE_CURSOR_HEIGHT,
+        ),
+    }
+}
+
 /// Radius of the red recording indicator circle
 pub(crate) const REC_CIRCLE_RADIUS: f32 = 4.0;
 /// Gap between recording circle and @reg text
@@ -105,7 +288,116 @@ pub(crate) fn format_recording_label(reg: &str) -> String {
     format!("@{}", reg)
 }
 
+/// Badge shown in the keypress row while privacy mode is on
+pub(crate) const PRIVACY_LABEL: &str = "PRIV";
+
+/// Stable horizontal columns for the keypress row and candidate numbers.
+///
+/// Computed once per font from monospace cell metrics and snapped to whole
+/// pixels, so changing content (mode label, REC register, candidate count
+/// within the same digit width) never shifts a column.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ColumnGrid {
+    /// Monospace cell advance, whole pixels
+    pub cell: f32,
+    /// Width reserved for the widest mode label, whole pixels
+    pub mode_width: f32,
+    /// Left padding (theme), whole pixels
+    pub padding: f32,
+}
+
+impl ColumnGrid {
+    pub fn new(cell: f32, widest_mode_label: f32, padding: f32) -> Self {
+        Self {
+            cell: cell.ceil().max(1.0),
+            mode_width: widest_mode_label.ceil(),
+            padding: padding.round(),
+        }
+    }
+
+    /// Measure the grid from the monospace renderer
+    pub fn measure(mono: &mut TextRenderer, padding: f32) -> Self {
+        let widest = MODE_LABELS
+            .iter()
+            .map(|l| mono.measure_text(l))
+            .fold(0.0, f32::max);
+        Self::new(mono.measure_text("0"), widest, padding)
+    }
+
+    /// x of the mode label
+    pub fn mode_x(&self) -> f32 {
+        self.padding
+    }
+
+    /// x of the REC circle's left edge
+    pub fn rec_x(&self) -> f32 {
+        self.mode_x() + self.mode_width + MODE_GAP
+    }
+
+    /// x of the `@reg` text after the REC circle
+    pub fn rec_text_x(&self) -> f32 {
+        (self.rec_x() + REC_CIRCLE_RADIUS * 2.0 + REC_CIRCLE_TEXT_GAP).round()
+    }
+
+    /// Right edge of the mode (and REC) icons
+    fn rec_end(&self, recording: &str) -> f32 {
+        if recording.is_empty() {
+            self.mode_x() + self.mode_width
+        } else {
+            let label_cells = format_recording_label(recording).chars().count() as f32;
+            self.rec_text_x() + label_cells * self.cell
+        }
+    }
+
+    /// x of the undo/dot-repeat indicator after the mode (and REC) icons
+    pub fn history_x(&self, content: &PopupContent) -> f32 {
+        (self.rec_end(&content.recording) + MODE_GAP).round()
+    }
+
+    /// Right edge of the mode, REC and undo/dot-repeat icons
+    fn history_end(&self, content: &PopupContent) -> f32 {
+        match content.history_label() {
+            Some(label) => self.history_x(content) + label.chars().count() as f32 * self.cell,
+            None => self.rec_end(&content.recording),
+        }
+    }
+
+    /// x of the privacy badge after the other icons
+    pub fn privacy_x(&self, content: &PopupContent) -> f32 {
+        (self.history_end(content) + MODE_GAP).round()
+    }
+
+    /// x of the vertical separator after all icons shown for `content`
+    pub fn separator_x(&self, content: &PopupContent) -> f32 {
+        let icons_end = if content.privacy {
+            self.privacy_x(content) + PRIVACY_LABEL.len() as f32 * self.cell
+        } else {
+            self.history_end(content)
+        };
+        (icons_end + ICON_SEPARATOR_GAP).round()
+    }
+
+    /// x where keypress text starts
+    pub fn keypress_text_x(&self, content: &PopupContent) -> f32 {
+        self.separator_x(content) + ICON_SEPARATOR_WIDTH + ICON_SEPARATOR_GAP
+    }
+
+    /// Width of the candidate number column ("123." plus one cell of gap)
+    /// for `total_count` numbered rows
+    pub fn number_width(&self, total_count: usize) -> f32 {
+        let digits = total_count.max(1).ilog10() + 1;
+        NUMBER_WIDTH.max((digits as f32 + 2.0) * self.cell)
+    }
+
+    /// Width of the label column for labels whose widest (with its ".")
+    /// measures `widest_label`, plus one cell of gap
+    pub fn label_width(&self, widest_label: f32) -> f32 {
+        NUMBER_WIDTH.max(widest_label.ceil() + self.cell)
+    }
+}
+
 /// Layout information for rendering
+#[derive(Debug, Clone)]
 pub(crate) struct Layout {
     pub width: u32,
     pub height: u32,
@@ -113,13 +405,93 @@ pub(crate) struct Layout {
     pub has_keypress: bool,
     pub has_candidates: bool,
     pub has_transient_message: bool,
+    pub has_debug: bool,
+    /// Command line with an `input()` prompt: its text gets a row below the keypress row
+    pub has_prompt: bool,
     pub preedit_y: f32,
     pub keypress_y: f32,
+    /// Top of the prompt's text row (0 without a prompt)
+    pub prompt_y: f32,
+    /// Macro being recorded or played back: a row below the keypress row
+    /// (and the prompt's)
+    pub has_macro: bool,
+    pub macro_y: f32,
     pub candidates_y: f32,
+    /// Position/page row below the candidates ("4/23  page 1/3"), empty without candidates
+    pub footer: String,
+    pub footer_y: f32,
+    pub debug_y: f32,
     pub visible_count: usize,
+    /// Height of one candidate row (furigana line included)
+    pub row_height: f32,
+    /// Height of the furigana line above candidate text (0 = single-line rows)
+    pub furigana_height: f32,
     pub has_scrollbar: bool,
     /// Width of mode+REC icons in keypress row (text starts after this)
     pub keypress_icon_width: f32,
+    /// Width of the candidate number column (candidate text starts after it)
+    pub number_width: f32,
+    /// Width of the kind column at the right of the rows (0 = no kinds)
+    pub kind_width: f32,
+    /// Room for a candidate and its annotation in a row; longer ones are cut
+    pub candidate_text_width: f32,
+    /// The selected candidate wrapped into lines below the list, when it
+    /// doesn't fit its row (empty otherwise)
+    pub detail: Vec<String>,
+    /// Top of the detail lines
+    pub detail_y: f32,
+    /// Tops of the separator lines between sections
+    pub separators: Vec<f32>,
+    /// Bottom of the last section
+    pub content_bottom: f32,
+    /// Sections stacked bottom-up, preedit last (the popup is above the
+    /// text cursor)
+    pub inverted: bool,
+}
+
+impl Layout {
+    /// Stack the sections of this top-down layout in reverse order between
+    /// `top` and the bottom of the last section, each keeping its height, for
+    /// a popup above the text cursor. Rows inside a section keep their order.
+    pub(crate) fn invert(&mut self, top: f32, line_height: f32, mono_line_height: f32) {
+        let bottom = self.content_bottom;
+        let mirror = |y: f32, height: f32| top + bottom - y - height;
+        if self.has_preedit {
+            self.preedit_y = mirror(self.preedit_y, line_height);
+        }
+        if self.has_keypress {
+            let rows = [true, self.has_prompt, self.has_macro]
+                .into_iter()
+                .filter(|&row| row)
+                .count() as f32;
+            self.keypress_y = mirror(self.keypress_y, rows * line_height);
+            if self.has_prompt {
+                self.prompt_y = self.keypress_y + line_height;
+            }
+            if self.has_macro {
+                self.macro_y = self.keypress_y + (rows - 1.0) * line_height;
+            }
+        }
+        if self.has_candidates {
+            self.candidates_y = mirror(
+                self.candidates_y,
+                self.visible_count as f32 * self.row_height,
+            );
+            self.footer_y = mirror(self.footer_y, mono_line_height);
+        } else if self.has_transient_message {
+            self.candidates_y = mirror(self.candidates_y, line_height);
+        }
+        if !self.detail.is_empty() {
+            self.detail_y = mirror(self.detail_y, self.detail.len() as f32 * line_height);
+        }
+        if self.has_debug {
+            self.debug_y = mirror(self.debug_y, mono_line_height);
+        }
+        for y in &mut self.separators {
+            *y = mirror(*y, SECTION_SEPARATOR_HEIGHT);
+        }
+        self.inverted = true;
+    }
 }
 
 /// Calculate preedit scroll offset to keep cursor visible with center-biased scrolling.
@@ -143,6 +515,242 @@ pub(crate) fn preedit_scroll_offset(
     }
 }
 
+/// Smooth scroll position of the candidate list, measured in rows.
+///
+/// The fractional part of `offset` is a partially scrolled row (pointer/axis
+/// scrolling); selection-driven scrolling always lands on whole rows.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) struct CandidateScroll {
+    /// Row index at the top edge of the viewport
+    pub offset: f32,
+    /// Kinetic velocity in rows per second (0.0 when idle)
+    pub velocity: f32,
+}
+
+impl CandidateScroll {
+    fn max_offset(visible_count: usize, total_count: usize) -> f32 {
+        total_count.saturating_sub(visible_count) as f32
+    }
+
+    /// First row intersecting the viewport
+    pub fn first_row(&self) -> usize {
+        self.offset.max(0.0).floor() as usize
+    }
+
+    /// Reset to the top and stop any kinetic motion
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Scroll the minimum amount that keeps `selected` fully visible.
+    /// Stops kinetic motion — keyboard selection always wins over a fling.
+    pub fn ensure_visible(&mut self, selected: usize, visible_count: usize, total_count: usize) {
+        self.velocity = 0.0;
+        let selected = selected as f32;
+        if selected < self.offset {
+            self.offset = selected;
+        } else if selected + 1.0 > self.offset + visible_count as f32 {
+            self.offset = selected + 1.0 - visible_count as f32;
+        }
+        self.offset = self
+            .offset
+            .clamp(0.0, Self::max_offset(visible_count, total_count));
+    }
+
+    /// Page shown and page count, as 1-based (page, pages). Pages start every
+    /// `visible_count` rows, except the last one, which ends at the last row
+    /// (that is where `page` and scrolling stop), so any viewport at the end of
+    /// the list is the last page.
+    pub fn page_number(&self, visible_count: usize, total_count: usize) -> (usize, usize) {
+        if visible_count == 0 {
+            return (1, 1);
+        }
+        let pages = total_count.div_ceil(visible_count).max(1);
+        let first_row = self.first_row();
+        let page = if first_row >= total_count.saturating_sub(visible_count) {
+            pages
+        } else {
+            first_row / visible_count + 1
+        };
+        (page, pages)
+    }
+
+    /// Turn `pages` whole pages (negative = up), landing on a row boundary.
+    /// Stops kinetic motion. Returns the new first row.
+    pub fn page(&mut self, pages: i32, visible_count: usize, total_count: usize) -> usize {
+        self.velocity = 0.0;
+        let target = self.first_row() as i64 + i64::from(pages) * visible_count as i64;
+        self.offset = (target.max(0) as f32).min(Self::max_offset(visible_count, total_count));
+        self.first_row()
+    }
+
+    /// Scroll by a (possibly fractional) number of rows. Returns true if moved.
+    pub fn scroll_by(&mut self, rows: f32, visible_count: usize, total_count: usize) -> bool {
+        let old = self.offset;
+        self.offset = (self.offset + rows).clamp(0.0, Self::max_offset(visible_count, total_count));
+        self.offset != old
+    }
+
+    /// Start a kinetic scroll (e.g. at the end of a touchpad swipe)
+    pub fn fling(&mut self, velocity: f32) {
+        self.velocity = velocity;
+    }
+
+    /// Whether a kinetic scroll is still running (for timer scheduling)
+    pub fn is_animating(&self) -> bool {
+        self.velocity != 0.0
+    }
+
+    /// Advance kinetic scrolling by `dt`. Returns true if the offset changed.
+    pub fn tick(&mut self, dt: Duration, visible_count: usize, total_count: usize) -> bool {
+        if self.velocity == 0.0 {
+            return false;
+        }
+        let secs = dt.as_secs_f32();
+        let moved = self.scroll_by(self.velocity * secs, visible_count, total_count);
+        self.velocity *= KINETIC_FRICTION.powf(secs);
+        if !moved || self.velocity.abs() < KINETIC_MIN_VELOCITY {
+            self.velocity = 0.0;
+        }
+        moved
+    }
+}
+
+/// Range of candidate indices to lay out for a viewport starting at `offset` rows:
+/// every row intersecting the viewport plus [`CANDIDATE_OVERSCAN`] rows on each side.
+pub(crate) fn candidate_window(
+    total_count: usize,
+    offset: f32,
+    visible_count: usize,
+) -> Range<usize> {
+    let first = offset.max(0.0).floor() as usize;
+    // +1: a fractional offset exposes part of one extra row at the bottom
+    let end = (first + visible_count + 1 + CANDIDATE_OVERSCAN).min(total_count);
+    let start = first.saturating_sub(CANDIDATE_OVERSCAN).min(end);
+    start..end
+}
+
+/// Candidate under the popup-local point (`x`, `y`) for a list of `total_count`
+/// scrolled to `offset` rows
+pub(crate) fn candidate_at(
+    layout: &Layout,
+    offset: f32,
+    total_count: usize,
+    x: f32,
+    y: f32,
+) -> Option<usize> {
+    let rows_height = layout.visible_count as f32 * layout.row_height;
+    let rel = y - layout.candidates_y;
+    if !layout.has_candidates
+        || x < 0.0
+        || x >= layout.width as f32
+        || rel < 0.0
+        || rel >= rows_height
+    {
+        return None;
+    }
+    let index = (offset.max(0.0) + rel / layout.row_height).floor() as usize;
+    (index < total_count).then_some(index)
+}
+
+/// Width of the `input()` prompt rows: the prompt after the mode icons, and
+/// below it the text with its cursor (scrolled beyond `MAX_PREEDIT_WIDTH`)
+pub(crate) fn prompt_rows_width(
+    icon_width: f32,
+    prompt_width: f32,
+    text_width: f32,
+    padding: f32,
+) -> f32 {
+    let prompt_row = icon_width + prompt_width + padding;
+    let text_row = padding + text_width.min(MAX_PREEDIT_WIDTH) + CMDLINE_CURSOR_WIDTH + padding;
+    prompt_row.max(text_row)
+}
+
+/// Byte length of the clusters of `text` that end within `width`, given
+/// their `offsets` (`TextRenderer::cluster_offsets`)
+pub(crate) fn fitting_prefix(text: &str, offsets: &[f32], width: f32) -> usize {
+    grapheme::clusters(text)
+        .zip(offsets.iter().skip(1))
+        .take_while(|&(_, &end)| end <= width)
+        .map(|(cluster, _)| cluster.len())
+        .sum()
+}
+
+/// Width of the macro row's text: the playback indicator, a gap, the keys
+pub(crate) fn macro_row_width(mono_renderer: &mut TextRenderer, row: &MacroPreview) -> f32 {
+    let label = row.playing.as_deref().map_or(0.0, |label| {
+        mono_renderer.measure_text(label) + KEYPRESS_ENTRY_GAP
+    });
+    label + mono_renderer.measure_text(&row.keys)
+}
+
+/// `text` cut to `width` with a trailing ellipsis if it is wider (empty if
+/// not even the ellipsis fits)
+pub(crate) fn fit_text<'a>(renderer: &mut TextRenderer, text: &'a str, width: f32) -> Cow<'a, str> {
+    let offsets = renderer.cluster_offsets(text);
+    if offsets.last().is_none_or(|&w| w <= width) {
+        return Cow::Borrowed(text);
+    }
+    let room = width - renderer.measure_text(ELLIPSIS);
+    if room < 0.0 {
+        return Cow::Borrowed("");
+    }
+    let end = fitting_prefix(text, &offsets, room);
+    Cow::Owned(format!("{}{ELLIPSIS}", text[..end].trim_end()))
+}
+
+/// `text` broken into lines of at most `width` (at least one cluster each),
+/// the last of `max_lines` cut with an ellipsis if text remains
+pub(crate) fn wrap_text(
+    renderer: &mut TextRenderer,
+    text: &str,
+    width: f32,
+    max_lines: usize,
+) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        if lines.len() + 1 == max_lines {
+            lines.push(fit_text(renderer, rest, width).into_owned());
+            break;
+        }
+        let offsets = renderer.cluster_offsets(rest);
+        let first = grapheme::clusters(rest).next().map_or(0, str::len);
+        let end = fitting_prefix(rest, &offsets, width).max(first);
+        let (line, tail) = rest.split_at(end);
+        lines.push(line.to_string());
+        rest = tail;
+    }
+    lines
+}
+
+/// Popup width while a candidate menu stays open: never narrower than it has
+/// been since the menu opened, so moving through candidates of different
+/// widths doesn't resize it. `menu_width` is that widest width so far (0
+/// without a menu).
+pub(crate) fn stable_width(width: u32, has_candidates: bool, menu_width: &mut u32) -> u32 {
+    if !has_candidates {
+        *menu_width = 0;
+        return width;
+    }
+    *menu_width = (*menu_width).max(width);
+    *menu_width
+}
+
+/// Footer text for the candidate list: the selection's position in the whole
+/// list, plus the page when the list spans more than one
+pub(crate) fn candidate_footer(
+    selected: usize,
+    total_count: usize,
+    page: (usize, usize),
+) -> String {
+    let position = format!("{}/{}", (selected + 1).min(total_count), total_count);
+    match page {
+        (_, 1) => position,
+        (page, pages) => format!("{position}  page {page}/{pages}"),
+    }
+}
+
 /// Scrollbar thumb geometry for candidate list.
 pub(crate) struct ScrollbarThumb {
     pub height: f32,
@@ -154,15 +762,14 @@ pub(crate) fn scrollbar_thumb_geometry(
     visible_count: usize,
     total_count: usize,
     scrollbar_height: f32,
-    scroll_offset: usize,
+    scroll_offset: f32,
     candidates_y: f32,
 ) -> ScrollbarThumb {
     debug_assert!(total_count > 0 && visible_count <= total_count);
     let thumb_height = ((visible_count as f32 / total_count as f32) * scrollbar_height).max(20.0);
     let scroll_range = total_count - visible_count;
     let y = if scroll_range > 0 {
-        candidates_y
-            + (scroll_offset as f32 / scroll_range as f32) * (scrollbar_height - thumb_height)
+        candidates_y + (scroll_offset / scroll_range as f32) * (scrollbar_height - thumb_height)
     } else {
         candidates_y
     };
@@ -174,11 +781,19 @@ pub(crate) fn scrollbar_thumb_geometry(
 
 /// Calculate layout dimensions and section positions.
 ///
-/// `mono_renderer` is used for measuring mode/REC icon text in the keypress row.
+/// `mono_renderer` is used for measuring keypress text.
+/// Only candidates within [`candidate_window`] of `candidate_scroll` are measured,
+/// so layout cost doesn't grow with the size of the completion list.
+/// Keypress-row icons and candidate numbers are placed on `grid` columns;
+/// spacing follows `theme`.
 pub(crate) fn calculate_layout(
     content: &PopupContent,
+    candidate_scroll: f32,
+    grid: &ColumnGrid,
+    theme: &Theme,
     renderer: &mut TextRenderer,
     mono_renderer: &mut TextRenderer,
+    mut furigana_renderer: Option<&mut TextRenderer>,
 ) -> Layout {
     // Preedit row is always visible when IME is enabled to prevent
     // layout jumps that cause visual confusion with the keypress row
@@ -193,58 +808,89 @@ pub(crate) fn calculate_layout(
         content.candidates.is_empty() && content.transient_message.is_some();
 
     let line_height = renderer.line_height();
-    let mut y = PADDING;
+    let padding = theme.padding;
+    let mut y = padding;
     let mut max_width: f32 = 0.0;
+    let mut separators = Vec::new();
 
-    // Keypress row icon width: mode_label + [gap + circle + gap + @reg] + separator area
-    let (mode_text, _) = mode_label(&content.vim_mode);
-    let mode_text_width = mono_renderer.measure_text(mode_text);
-    let recording_width = if !content.recording.is_empty() {
-        let rec_label = format_recording_label(&content.recording);
-        MODE_GAP
-            + REC_CIRCLE_RADIUS * 2.0
-            + REC_CIRCLE_TEXT_GAP
-            + mono_renderer.measure_text(&rec_label)
-    } else {
-        0.0
+    // Keypress row icon width: mode column + [gap + circle + gap + @reg] + separator area
+    let keypress_icon_width = grid.keypress_text_x(content);
+    // Numbers are relative to the visible page; other label sets (kana is
+    // double-width, drawn from a fallback font) are measured
+    let visible_rows = content.candidates.len().min(theme.max_candidates);
+    let number_width = match content.candidate_labels {
+        CandidateLabels::Numbers => grid.number_width(visible_rows),
+        labels => grid.label_width(
+            labels.labels()[..visible_rows]
+                .iter()
+                .map(|l| mono_renderer.measure_text(&format!("{l}.")))
+                .fold(0.0, f32::max),
+        ),
     };
-    let keypress_icon_width = PADDING
-        + mode_text_width
-        + recording_width
-        + ICON_SEPARATOR_GAP
-        + ICON_SEPARATOR_WIDTH
-        + ICON_SEPARATOR_GAP;
-
-    // Preedit section (no icon area — preedit starts at PADDING)
+
+    // Preedit section (no icon area — preedit starts at the padding)
     let preedit_y = y;
     if has_preedit {
         if !content.preedit.is_empty() {
             let text_width = renderer.measure_text(&content.preedit);
             let preedit_width =
-                (PADDING + text_width + PADDING + 4.0).min(MAX_PREEDIT_WIDTH + PADDING * 2.0);
+                (padding + text_width + padding + 4.0).min(MAX_PREEDIT_WIDTH + padding * 2.0);
             max_width = max_width.max(preedit_width);
         }
         y += line_height;
         if has_keypress || has_candidates {
+            separators.push(y);
             y += SECTION_SEPARATOR_HEIGHT;
         }
     }
 
     // Keypress section (always present when IME enabled)
     let keypress_y = if has_keypress { y } else { 0.0 };
+    let prompt = content
+        .cmdline_prompt
+        .as_deref()
+        .filter(|_| has_keypress && has_keypress_text && content.cmdline_cursor_pos.is_some());
+    let mut prompt_y = 0.0;
+    let mut macro_y = 0.0;
     if has_keypress {
         let mut keypress_width = keypress_icon_width;
-        if has_keypress_text {
+        if let Some(prompt) = prompt {
+            max_width = max_width.max(prompt_rows_width(
+                keypress_icon_width,
+                mono_renderer.measure_text(prompt),
+                renderer.measure_text(&content.keypress_entries[0]),
+                padding,
+            ));
+        } else if has_keypress_text {
             for (i, entry) in content.keypress_entries.iter().enumerate() {
                 if i > 0 {
                     keypress_width += KEYPRESS_ENTRY_GAP;
                 }
                 keypress_width += mono_renderer.measure_text(entry);
             }
+            if let Some(hint) = content.keypress_hint {
+                keypress_width += KEYPRESS_ENTRY_GAP + mono_renderer.measure_text(hint);
+            }
+        }
+        if let Some(ref latency) = content.latency {
+            keypress_width += LATENCY_GAP + mono_renderer.measure_text(latency);
         }
-        keypress_width += PADDING; // right padding
+        keypress_width += padding; // right padding
         max_width = max_width.max(keypress_width);
         y += line_height;
+        if prompt.is_some() {
+            prompt_y = y;
+            y += line_height;
+        }
+        if let Some(ref row) = content.macro_row {
+            max_width = max_width.max(macro_row_width(mono_renderer, row) + padding * 2.0);
+            macro_y = y;
+            y += line_height;
+        }
+        // A transient message gets the space but no line
+        if has_candidates {
+            separators.push(y);
+        }
         if has_candidates || has_transient_message {
             y += SECTION_SEPARATOR_HEIGHT;
         }
@@ -257,12 +903,28 @@ pub(crate) fn calculate_layout(
         0.0
     };
     let visible_count = if has_candidates {
-        MAX_VISIBLE_CANDIDATES.min(content.candidates.len())
+        theme.max_candidates.min(content.candidates.len())
     } else {
         0
     };
-    let has_scrollbar = content.candidates.len() > MAX_VISIBLE_CANDIDATES;
+    let has_scrollbar = content.candidate_total() > theme.max_candidates;
+    // Rows become two-line (furigana above) for the whole list when any
+    // candidate has a reading, so scrolling keeps a constant row height
+    let furigana_height = match furigana_renderer.as_deref() {
+        Some(r)
+            if has_candidates
+                && (0..content.candidates.len()).any(|i| content.furigana(i).is_some()) =>
+        {
+            r.line_height()
+        }
+        _ => 0.0,
+    };
+    let row_height = line_height + furigana_height;
 
+    let mut kind_width = 0.0;
+    let mut candidate_text_width = 0.0;
+    let mut detail = Vec::new();
+    let mut detail_y = 0.0;
     if has_candidates {
         let scrollbar_space = if has_scrollbar {
             SCROLLBAR_WIDTH + 4.0
@@ -270,27 +932,104 @@ pub(crate) fn calculate_layout(
             0.0
         };
 
-        // Calculate max candidate width
-        for candidate in content.candidates.iter().take(MAX_VISIBLE_CANDIDATES) {
-            let text_width = renderer.measure_text(candidate);
-            max_width = max_width.max(text_width + NUMBER_WIDTH + PADDING * 2.0 + scrollbar_space);
+        // Calculate max candidate width over the laid-out window only
+        let window = candidate_window(content.candidates.len(), candidate_scroll, visible_count);
+        kind_width = window
+            .clone()
+            .filter_map(|i| content.kind(i))
+            .map(|kind| mono_renderer.measure_text(kind))
+            .fold(0.0, f32::max);
+        let kind_space = if kind_width > 0.0 {
+            KIND_GAP + kind_width
+        } else {
+            0.0
+        };
+        for i in window {
+            let mut text_width = renderer.measure_text(&content.candidates[i]);
+            if furigana_height > 0.0
+                && let Some(r) = furigana_renderer.as_deref_mut()
+                && let Some(reading) = content.furigana(i)
+            {
+                text_width = text_width.max(r.measure_text(reading));
+            }
+            if let Some(annotation) = content.annotation(i) {
+                text_width += ANNOTATION_GAP + renderer.measure_text(annotation);
+            }
+            max_width = max_width
+                .max(text_width + kind_space + number_width + padding * 2.0 + scrollbar_space);
+        }
+
+        // A row that doesn't fit makes the popup as wide as it gets, which
+        // leaves this much for the text; the selected candidate, if cut, is
+        // shown in full below the list
+        candidate_text_width =
+            theme.max_width as f32 - (kind_space + number_width + padding * 2.0 + scrollbar_space);
+        if let Some(selected) = content.candidates.get(content.selected)
+            && renderer.measure_text(selected) > candidate_text_width
+        {
+            let detail_width = theme.max_width as f32 - padding * 2.0;
+            detail = wrap_text(renderer, selected, detail_width, MAX_DETAIL_LINES);
         }
 
-        y += visible_count as f32 * line_height;
+        y += visible_count as f32 * row_height;
+        if !detail.is_empty() {
+            separators.push(y);
+            y += SECTION_SEPARATOR_HEIGHT;
+            detail_y = y;
+            y += detail.len() as f32 * line_height;
+        }
     } else if has_transient_message {
         if let Some(ref msg) = content.transient_message {
             let text_width = renderer.measure_text(msg);
-            max_width = max_width.max(text_width + PADDING * 2.0);
+            max_width = max_width.max(text_width + padding * 2.0);
         }
         y += line_height;
     }
 
-    y += PADDING;
+    // Footer row (selection position and page) below the candidates
+    let mut footer = String::new();
+    let mut footer_y = 0.0;
+    if has_candidates {
+        // In the whole list, of which only a window may be here
+        let scroll = CandidateScroll {
+            offset: candidate_scroll + content.candidate_offset as f32,
+            velocity: 0.0,
+        };
+        let total_count = content.candidate_total();
+        let page = scroll.page_number(visible_count, total_count);
+        footer = candidate_footer(
+            content.candidate_offset + content.selected,
+            total_count,
+            page,
+        );
+        separators.push(y);
+        y += SECTION_SEPARATOR_HEIGHT;
+        footer_y = y;
+        max_width = max_width.max(mono_renderer.measure_text(&footer) + padding * 2.0);
+        y += mono_renderer.line_height();
+    }
+
+    // Debug row (below everything else)
+    let has_debug = content.ime_enabled && content.debug_line.is_some();
+    let debug_y = if has_debug {
+        separators.push(y);
+        y += SECTION_SEPARATOR_HEIGHT;
+        y
+    } else {
+        0.0
+    };
+    if has_debug && let Some(ref line) = content.debug_line {
+        max_width = max_width.max(mono_renderer.measure_text(line) + padding * 2.0);
+        y += mono_renderer.line_height();
+    }
+
+    let content_bottom = y;
+    y += padding;
 
     // Align width to 4 bytes for wl_shm
     let width = ((max_width.ceil() as u32) + 3) & !3;
-    let width = width.clamp(100, 580);
-    let height = (y.ceil() as u32).clamp(30, 450);
+    let width = width.clamp(MIN_POPUP_WIDTH, theme.max_width);
+    let height = (y.ceil() as u32).clamp(MIN_POPUP_HEIGHT, theme.max_height);
 
     Layout {
         width,
@@ -299,15 +1038,40 @@ pub(crate) fn calculate_layout(
         has_keypress,
         has_candidates,
         has_transient_message,
+        has_debug,
+        has_prompt: prompt.is_some(),
         preedit_y,
         keypress_y,
+        prompt_y,
+        has_macro: has_keypress && content.macro_row.is_some(),
+        macro_y,
         candidates_y,
+        footer,
+        footer_y,
+        debug_y,
         visible_count,
+        row_height,
+        furigana_height,
         has_scrollbar,
         keypress_icon_width,
+        number_width,
+        kind_width,
+        candidate_text_width,
+        detail,
+        detail_y,
+        separators,
+        content_bottom,
+        inverted: false,
     }
 }
 
+/// Whether the compositor placed the popup above the text cursor: the cursor
+/// rectangle (`zwp_input_popup_surface_v2.text_input_rectangle`, surface
+/// coordinates) is in the lower half of the surface or below it
+pub(crate) fn popup_above_cursor(rect_y: i32, rect_height: i32, surface_height: u32) -> bool {
+    i64::from(rect_y) + i64::from(rect_height) / 2 > i64::from(surface_height) / 2
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -341,12 +1105,46 @@ mod tests {
         assert_eq!(offset, 150.0);
     }
 
+    // --- prompt_rows_width ---
+
+    #[test]
+    fn prompt_rows_fit_the_longer_row() {
+        // Long prompt: the prompt row sets the width
+        assert_eq!(prompt_rows_width(40.0, 150.0, 30.0, 8.0), 198.0);
+        // Long text: padding, text and cursor
+        assert_eq!(prompt_rows_width(40.0, 50.0, 200.0, 8.0), 218.0);
+        // Text beyond MAX_PREEDIT_WIDTH scrolls instead of widening the popup
+        assert_eq!(
+            prompt_rows_width(40.0, 50.0, 1000.0, 8.0),
+            MAX_PREEDIT_WIDTH + CMDLINE_CURSOR_WIDTH + 16.0
+        );
+    }
+
+    // --- fitting_prefix ---
+
+    #[test]
+    fn fitting_prefix_stops_at_the_first_cluster_past_the_width() {
+        // Ten pixels per character
+        let offsets = [0.0, 10.0, 20.0, 30.0, 40.0];
+        assert_eq!(fitting_prefix("abcd", &offsets, 25.0), 2);
+        assert_eq!(fitting_prefix("abcd", &offsets, 40.0), 4);
+        assert_eq!(fitting_prefix("abcd", &offsets, 5.0), 0);
+        // Byte lengths of wide characters
+        assert_eq!(fitting_prefix("漢字変換", &offsets, 30.0), 9);
+        // A cluster is kept or cut whole
+        let offsets = [0.0, 10.0, 20.0];
+        assert_eq!(
+            fitting_prefix("か\u{3099}字", &offsets, 15.0),
+            "か\u{3099}".len()
+        );
+    }
+
     // --- scrollbar_thumb_geometry ---
 
     #[test]
     fn thumb_no_scroll_range() {
         // All items visible (visible == total)
-        let thumb = scrollbar_thumb_geometry(10, 10, 200.0, 0, 50.0);
+        let thumb = scrollbar_thumb_geometry(10, 10, 200.0, 0.0, 50.0);
         assert_eq!(thumb.y, 50.0);
         // thumb_height = (10/10)*200 = 200, but min 20
         assert_eq!(thumb.height, 200.0);
@@ -354,14 +1152,14 @@ mod tests {
 
     #[test]
     fn thumb_at_top() {
-        let thumb = scrollbar_thumb_geometry(5, 20, 100.0, 0, 50.0);
+        let thumb = scrollbar_thumb_geometry(5, 20, 100.0, 0.0, 50.0);
         assert_eq!(thumb.y, 50.0); // scroll_offset=0, at top
         assert!(thumb.height >= 20.0);
     }
 
     #[test]
     fn thumb_at_bottom() {
-        let thumb = scrollbar_thumb_geometry(5, 20, 100.0, 15, 50.0);
+        let thumb = scrollbar_thumb_geometry(5, 20, 100.0, 15.0, 50.0);
         // scroll_offset=15 = scroll_range=15, so ratio=1.0
         let expected_y = 50.0 + (100.0 - thumb.height);
         assert!((thumb.y - expected_y).abs() < 0.01);
@@ -370,45 +1168,499 @@ mod tests {
     #[test]
     fn thumb_minimum_height() {
         // With many items, thumb proportion would be tiny — clamped to 20
-        let thumb = scrollbar_thumb_geometry(1, 100, 100.0, 0, 0.0);
+        let thumb = scrollbar_thumb_geometry(1, 100, 100.0, 0.0, 0.0);
         assert_eq!(thumb.height, 20.0);
     }
 
+    #[test]
+    fn thumb_fractional_offset_is_between_rows() {
+        let a = scrollbar_thumb_geometry(5, 20, 100.0, 3.0, 0.0);
+        let b = scrollbar_thumb_geometry(5, 20, 100.0, 3.5, 0.0);
+        let c = scrollbar_thumb_geometry(5, 20, 100.0, 4.0, 0.0);
+        assert!(a.y < b.y && b.y < c.y);
+    }
+
+    // --- ColumnGrid ---
+
+    fn icons(recording: &str, undo_seq: u64, privacy: bool) -> PopupContent {
+        PopupContent {
+            recording: recording.to_string(),
+            undo_seq,
+            privacy,
+            ..PopupContent::default()
+        }
+    }
+
+    #[test]
+    fn grid_snaps_to_whole_pixels() {
+        let grid = ColumnGrid::new(8.4, 25.2, PADDING);
+        assert_eq!(grid.cell, 9.0);
+        assert_eq!(grid.mode_width, 26.0);
+        for x in [
+            grid.rec_text_x(),
+            grid.separator_x(&icons("", 0, false)),
+            grid.separator_x(&icons("q", 0, false)),
+            grid.history_x(&icons("q", 3, false)),
+            grid.privacy_x(&icons("q", 3, true)),
+            grid.separator_x(&icons("q", 3, true)),
+            grid.keypress_text_x(&icons("q", 3, true)),
+        ] {
+            assert_eq!(x, x.round());
+        }
+    }
+
+    #[test]
+    fn separator_column_independent_of_mode_label() {
+        // Columns depend only on the grid and REC state, never on which label is shown
+        let grid = ColumnGrid::new(8.0, 24.0, PADDING);
+        assert_eq!(
+            grid.separator_x(&icons("", 0, false)),
+            PADDING + 24.0 + ICON_SEPARATOR_GAP
+        );
+        assert!(grid.separator_x(&icons("q", 0, false)) > grid.separator_x(&icons("", 0, false)));
+        // Any single-char register occupies the same cells
+        assert_eq!(
+            grid.separator_x(&icons("q", 0, false)),
+            grid.separator_x(&icons("a", 0, false))
+        );
+    }
+
+    #[test]
+    fn privacy_badge_follows_the_mode_icons() {
+        let grid = ColumnGrid::new(8.0, 24.0, PADDING);
+        let private = icons("", 0, true);
+        assert_eq!(grid.privacy_x(&private), PADDING + 24.0 + MODE_GAP);
+        assert!(grid.privacy_x(&icons("q", 0, true)) > grid.privacy_x(&private));
+        // The badge's cells push the separator right
+        assert_eq!(
+            grid.separator_x(&private),
+            grid.privacy_x(&private) + 4.0 * 8.0 + ICON_SEPARATOR_GAP
+        );
+        assert!(grid.separator_x(&icons("q", 0, true)) > grid.separator_x(&icons("q", 0, false)));
+    }
+
+    #[test]
+    fn history_indicator_between_rec_and_privacy() {
+        let grid = ColumnGrid::new(8.0, 24.0, PADDING);
+        let content = icons("q", 3, true);
+        assert_eq!(content.history_label().as_deref(), Some("u 3"));
+        assert_eq!(
+            grid.history_x(&content),
+            grid.privacy_x(&icons("q", 0, true))
+        );
+        // "u 3" is three cells
+        assert_eq!(
+            grid.privacy_x(&content),
+            grid.history_x(&content) + 3.0 * 8.0 + MODE_GAP
+        );
+    }
+
+    #[test]
+    fn history_label_shows_undo_depth_and_repeat() {
+        let mut content = icons("", 0, false);
+        assert_eq!(content.history_label(), None);
+        content.repeatable = true;
+        assert_eq!(content.history_label().as_deref(), Some("."));
+        content.undo_seq = 12;
+        assert_eq!(content.history_label().as_deref(), Some("u 12 ."));
+        content.repeatable = false;
+        assert_eq!(content.history_label().as_deref(), Some("u 12"));
+    }
+
+    #[test]
+    fn number_column_grows_with_digit_count_only() {
+        let grid = ColumnGrid::new(10.0, 30.0, PADDING);
+        assert_eq!(grid.number_width(1), NUMBER_WIDTH.max(30.0));
+        assert_eq!(grid.number_width(9), grid.number_width(1));
+        assert_eq!(grid.number_width(99), 40.0);
+        assert_eq!(grid.number_width(10), grid.number_width(99));
+        assert_eq!(grid.number_width(100), 50.0);
+        assert_eq!(grid.number_width(0), grid.number_width(1));
+    }
+
+    #[test]
+    fn label_column_fits_the_widest_label() {
+        let grid = ColumnGrid::new(10.0, 30.0, PADDING);
+        // "a." is two cells like "1."
+        assert_eq!(grid.label_width(20.0), grid.number_width(9));
+        // "あ." with a double-width kana
+        assert_eq!(grid.label_width(29.5), 40.0);
+    }
+
+    // --- candidate_window ---
+
+    #[test]
+    fn window_at_top_has_no_leading_overscan() {
+        assert_eq!(candidate_window(1000, 0.0, 9), 0..12);
+    }
+
+    #[test]
+    fn window_in_middle_has_overscan_on_both_sides() {
+        assert_eq!(candidate_window(1000, 500.0, 9), 498..512);
+        // Fractional offset: same rows as the integer part
+        assert_eq!(candidate_window(1000, 500.5, 9), 498..512);
+    }
+
+    #[test]
+    fn window_clamped_to_total() {
+        assert_eq!(candidate_window(5, 0.0, 5), 0..5);
+        assert_eq!(candidate_window(20, 11.0, 9), 9..20);
+        assert_eq!(candidate_window(0, 0.0, 0), 0..0);
+    }
+
+    // --- furigana ---
+
+    #[test]
+    fn furigana_for_kanji_candidates_with_kana_readings() {
+        assert_eq!(furigana("漢字", "かんじ"), Some("かんじ"));
+        assert_eq!(furigana("感じ", " かんじ "), Some("かんじ"));
+        assert_eq!(furigana("佐々木", "ササキ"), Some("ササキ"));
+    }
+
+    #[test]
+    fn furigana_skips_non_readings() {
+        assert_eq!(furigana("かんじ", "かんじ"), None); // nothing to annotate
+        assert_eq!(furigana("カタカナ", "かたかな"), None); // no kanji
+        assert_eq!(furigana("漢字", "[LSP]"), None);
+        assert_eq!(furigana("漢字", "kanji"), None);
+        assert_eq!(furigana("漢字", ""), None);
+    }
+
+    #[test]
+    fn popup_content_annotation_skips_empty_and_missing() {
+        let content = PopupContent {
+            candidates: vec![
+                "渡辺".to_string(),
+                "渡部".to_string(),
+                "わたなべ".to_string(),
+            ]
+            .into(),
+            annotations: vec!["person name".to_string(), String::new()].into(),
+            kinds: vec![String::new(), "nvim_lsp".to_string()].into(),
+            ..PopupContent::default()
+        };
+        assert_eq!(content.annotation(0), Some("person name"));
+        assert_eq!(content.annotation(1), None);
+        assert_eq!(content.annotation(2), None);
+        assert_eq!(content.kind(0), None);
+        assert_eq!(content.kind(1), Some("nvim_lsp"));
+        assert_eq!(content.kind(2), None);
+    }
+
+    #[test]
+    fn popup_content_furigana_tolerates_missing_readings() {
+        let content = PopupContent {
+            candidates: vec!["漢字".to_string(), "幹事".to_string()].into(),
+            readings: vec!["かんじ".to_string()].into(),
+            ..PopupContent::default()
+        };
+        assert_eq!(content.furigana(0), Some("かんじ"));
+        assert_eq!(content.furigana(1), None);
+        assert_eq!(content.furigana(2), None);
+    }
+
+    // --- candidate_at ---
+
+    fn candidate_layout(visible_count: usize, row_height: f32) -> Layout {
+        Layout {
+            width: 200,
+            height: 300,
+            has_preedit: true,
+            has_keypress: true,
+            has_candidates: true,
+            has_transient_message: false,
+            has_debug: false,
+            has_prompt: false,
+            preedit_y: 8.0,
+            keypress_y: 31.0,
+            prompt_y: 0.0,
+            has_macro: false,
+            macro_y: 0.0,
+            candidates_y: 54.0,
+            footer: String::new(),
+            footer_y: 0.0,
+            debug_y: 0.0,
+            visible_count,
+            row_height,
+            furigana_height: 0.0,
+            has_scrollbar: false,
+            keypress_icon_width: 40.0,
+            number_width: 24.0,
+            kind_width: 0.0,
+            candidate_text_width: 144.0,
+            detail: Vec::new(),
+            detail_y: 0.0,
+            separators: vec![29.0, 52.0],
+            content_bottom: 114.0,
+            inverted: false,
+        }
+    }
+
+    #[test]
+    fn inverted_layout_reverses_the_sections() {
+        // Preedit 8..29, keypress 31..52, candidates 54..114, footer 116..132
+        let mut layout = candidate_layout(3, 20.0);
+        layout.footer_y = 116.0;
+        layout.separators.push(114.0);
+        layout.content_bottom = 132.0;
+        layout.invert(8.0, 21.0, 16.0);
+        assert!(layout.inverted);
+        assert_eq!(layout.footer_y, 8.0);
+        assert_eq!(layout.candidates_y, 26.0);
+        assert_eq!(layout.keypress_y, 88.0);
+        assert_eq!(layout.preedit_y, 111.0);
+        assert_eq!(layout.separators, [110.0, 87.0, 25.0]);
+        // Hit-testing follows the rows
+        assert_eq!(candidate_at(&layout, 0.0, 3, 50.0, 26.0), Some(0));
+        assert_eq!(candidate_at(&layout, 0.0, 3, 50.0, 70.0), Some(2));
+    }
+
+    #[test]
+    fn inverted_layout_keeps_the_macro_row_below_the_keypress_row() {
+        // Preedit 8..29, keypress 31..52, macro row 52..73
+        let mut layout = candidate_layout(0, 20.0);
+        layout.has_candidates = false;
+        layout.has_macro = true;
+        layout.macro_y = 52.0;
+        layout.separators = vec![29.0];
+        layout.content_bottom = 73.0;
+        layout.invert(8.0, 21.0, 16.0);
+        assert_eq!(layout.keypress_y, 8.0);
+        assert_eq!(layout.macro_y, 29.0);
+        assert_eq!(layout.preedit_y, 52.0);
+        assert_eq!(layout.separators, [51.0]);
+    }
+
+    #[test]
+    fn popup_above_cursor_from_the_rectangle() {
+        // Popup below the cursor: the rectangle is above the surface
+        assert!(!popup_above_cursor(-20, 18, 200));
+        // Popup above the cursor: the rectangle is below it
+        assert!(popup_above_cursor(204, 18, 200));
+        assert!(!popup_above_cursor(0, 0, 0));
+    }
+
+    #[test]
+    fn candidate_at_maps_rows() {
+        let layout = candidate_layout(3, 20.0);
+        assert_eq!(candidate_at(&layout, 0.0, 3, 50.0, 54.0), Some(0));
+        assert_eq!(candidate_at(&layout, 0.0, 3, 50.0, 75.0), Some(1));
+        assert_eq!(candidate_at(&layout, 0.0, 3, 50.0, 113.9), Some(2));
+        // Above the list, below it, outside horizontally
+        assert_eq!(candidate_at(&layout, 0.0, 3, 50.0, 40.0), None);
+        assert_eq!(candidate_at(&layout, 0.0, 3, 50.0, 114.0), None);
+        assert_eq!(candidate_at(&layout, 0.0, 3, 200.0, 60.0), None);
+    }
+
+    #[test]
+    fn candidate_at_follows_scroll_offset() {
+        let layout = candidate_layout(9, 20.0);
+        assert_eq!(candidate_at(&layout, 10.0, 30, 50.0, 54.0), Some(10));
+        // Half-scrolled: the top half row belongs to the partially visible candidate
+        assert_eq!(candidate_at(&layout, 10.5, 30, 50.0, 55.0), Some(10));
+        assert_eq!(candidate_at(&layout, 10.5, 30, 50.0, 65.0), Some(11));
+        assert_eq!(
+            candidate_at(&layout, 25.0, 30, 50.0, 54.0 + 8.0 * 20.0),
+            None
+        );
+    }
+
+    // --- CandidateScroll ---
+
+    #[test]
+    fn ensure_visible_scrolls_minimally() {
+        let mut scroll = CandidateScroll::default();
+        scroll.ensure_visible(3, 9, 100);
+        assert_eq!(scroll.offset, 0.0);
+
+        scroll.ensure_visible(9, 9, 100);
+        assert_eq!(scroll.offset, 1.0); // selected is now the bottom row
+
+        scroll.ensure_visible(0, 9, 100);
+        assert_eq!(scroll.offset, 0.0);
+    }
+
+    #[test]
+    fn ensure_visible_snaps_fractional_offset_and_stops_fling() {
+        let mut scroll = CandidateScroll {
+            offset: 4.5,
+            velocity: 10.0,
+        };
+        scroll.ensure_visible(4, 9, 100);
+        assert_eq!(scroll.offset, 4.0);
+        assert_eq!(scroll.velocity, 0.0);
+    }
+
+    #[test]
+    fn page_turns_whole_pages_within_range() {
+        let mut scroll = CandidateScroll::default();
+        assert_eq!(scroll.page(1, 9, 20), 9);
+        assert_eq!(scroll.page(1, 9, 20), 11); // last page ends at the last row
+        assert_eq!(scroll.page(-1, 9, 20), 2);
+        assert_eq!(scroll.page(-1, 9, 20), 0);
+
+        let mut scroll = CandidateScroll {
+            offset: 3.5,
+            velocity: 10.0,
+        };
+        assert_eq!(scroll.page(1, 9, 100), 12);
+        assert_eq!(scroll.velocity, 0.0);
+        assert_eq!(CandidateScroll::default().page(1, 5, 5), 0);
+    }
+
+    #[test]
+    fn page_number_treats_the_end_of_the_list_as_the_last_page() {
+        let at = |offset| CandidateScroll {
+            offset,
+            velocity: 0.0,
+        };
+        assert_eq!(at(0.0).page_number(9, 20), (1, 3));
+        assert_eq!(at(2.0).page_number(9, 20), (1, 3));
+        assert_eq!(at(9.0).page_number(9, 20), (2, 3));
+        // `page` stops at row 11, which shows the last rows
+        assert_eq!(at(11.0).page_number(9, 20), (3, 3));
+        assert_eq!(at(10.5).page_number(9, 20), (2, 3));
+        assert_eq!(at(0.0).page_number(5, 5), (1, 1));
+        assert_eq!(at(0.0).page_number(0, 0), (1, 1));
+
+        // Paging down visits every page number once
+        let mut scroll = CandidateScroll::default();
+        let pages: Vec<_> = (0..3)
+            .map(|_| {
+                let page = scroll.page_number(9, 20).0;
+                scroll.page(1, 9, 20);
+                page
+            })
+            .collect();
+        assert_eq!(pages, [1, 2, 3]);
+    }
+
+    #[test]
+    fn footer_shows_position_and_page() {
+        assert_eq!(candidate_footer(3, 23, (1, 3)), "4/23  page 1/3");
+        assert_eq!(candidate_footer(0, 5, (1, 1)), "1/5");
+        assert_eq!(candidate_footer(7, 5, (1, 1)), "5/5");
+    }
+
+    #[test]
+    fn menu_width_only_grows_until_the_menu_closes() {
+        let mut menu_width = 0;
+        assert_eq!(stable_width(240, true, &mut menu_width), 240);
+        // Scrolling to narrower candidates keeps the width
+        assert_eq!(stable_width(180, true, &mut menu_width), 240);
+        assert_eq!(stable_width(300, true, &mut menu_width), 300);
+        assert_eq!(stable_width(200, true, &mut menu_width), 300);
+        // Closing the menu lets the popup shrink, and the next menu starts over
+        assert_eq!(stable_width(120, false, &mut menu_width), 120);
+        assert_eq!(stable_width(160, true, &mut menu_width), 160);
+    }
+
+    #[test]
+    fn scroll_by_clamps_to_range() {
+        let mut scroll = CandidateScroll::default();
+        assert!(!scroll.scroll_by(-1.0, 9, 20));
+        assert!(scroll.scroll_by(0.25, 9, 20));
+        assert_eq!(scroll.offset, 0.25);
+        assert!(scroll.scroll_by(100.0, 9, 20));
+        assert_eq!(scroll.offset, 11.0);
+        assert_eq!(scroll.first_row(), 11);
+    }
+
+    #[test]
+    fn kinetic_tick_decelerates_and_stops() {
+        let mut scroll = CandidateScroll::default();
+        scroll.fling(20.0);
+        assert!(scroll.is_animating());
+
+        assert!(scroll.tick(Duration::from_millis(100), 9, 1000));
+        let first_step = scroll.offset;
+        assert!(first_step > 0.0);
+        assert!(scroll.velocity < 20.0);
+
+        // Run until friction stops it
+        for _ in 0..100 {
+            scroll.tick(Duration::from_millis(100), 9, 1000);
+        }
+        assert!(!scroll.is_animating());
+        assert!(scroll.offset > first_step);
+    }
+
+    #[test]
+    fn kinetic_tick_stops_at_edge() {
+        let mut scroll = CandidateScroll {
+            offset: 10.5,
+            velocity: 50.0,
+        };
+        scroll.tick(Duration::from_millis(100), 9, 20);
+        assert_eq!(scroll.offset, 11.0);
+        // Next tick can't move further — velocity is dropped
+        assert!(!scroll.tick(Duration::from_millis(100), 9, 20));
+        assert!(!scroll.is_animating());
+    }
+
     // --- mode_label ---
 
     #[test]
     fn mode_label_insert() {
-        let (label, color) = mode_label("i");
+        let (label, color) = mode_label("i", &Theme::default());
         assert_eq!(label, "INS");
         assert_eq!(color, MODE_INSERT_COLOR);
     }
 
     #[test]
     fn mode_label_normal() {
-        let (label, color) = mode_label("n");
+        let (label, color) = mode_label("n", &Theme::default());
         assert_eq!(label, "NOR");
         assert_eq!(color, MODE_NORMAL_COLOR);
     }
 
     #[test]
     fn mode_label_visual() {
-        assert_eq!(mode_label("v").0, "VIS");
-        assert_eq!(mode_label("V").0, "VIS");
-        assert_eq!(mode_label("\x16").0, "VIS");
+        assert_eq!(mode_label("v", &Theme::default()).0, "VIS");
+        assert_eq!(mode_label("V", &Theme::default()).0, "VIS");
+        assert_eq!(mode_label("\x16", &Theme::default()).0, "VIS");
         // v-prefix
-        assert_eq!(mode_label("vs").0, "VIS");
+        assert_eq!(mode_label("vs", &Theme::default()).0, "VIS");
     }
 
     #[test]
     fn mode_label_operator_pending() {
-        assert_eq!(mode_label("no").0, "OP");
-        assert_eq!(mode_label("nov").0, "OP");
+        assert_eq!(mode_label("no", &Theme::default()).0, "OP");
+        assert_eq!(mode_label("nov", &Theme::default()).0, "OP");
     }
 
     #[test]
     fn mode_label_command() {
-        let (label, color) = mode_label("c");
+        let (label, color) = mode_label("c", &Theme::default());
         assert_eq!(label, "CMD");
         assert_eq!(color, MODE_CMD_COLOR);
     }
+
+    #[test]
+    fn cursor_shapes_follow_the_mode() {
+        assert_eq!(cursor_shape("i"), CursorShape::Bar);
+        assert_eq!(cursor_shape("ic"), CursorShape::Bar);
+        assert_eq!(cursor_shape("c"), CursorShape::Bar);
+        assert_eq!(cursor_shape("n"), CursorShape::Block);
+        assert_eq!(cursor_shape("niI"), CursorShape::Block);
+        assert_eq!(cursor_shape("v"), CursorShape::Block);
+        assert_eq!(cursor_shape("\x16"), CursorShape::Block);
+        assert_eq!(cursor_shape("no"), CursorShape::HalfBlock);
+        assert_eq!(cursor_shape("no\x16"), CursorShape::HalfBlock);
+        assert_eq!(cursor_shape("R"), CursorShape::Underline);
+        assert_eq!(cursor_shape("Rv"), CursorShape::Underline);
+        assert_eq!(cursor_shape(""), CursorShape::Bar);
+    }
+
+    #[test]
+    fn cursor_rects_per_shape() {
+        let rect = |shape| cursor_rect(shape, 10.0, 20.0, 8.0, 16.0);
+        assert_eq!(rect(CursorShape::Bar), (10.0, 20.0, BAR_CURSOR_WIDTH, 16.0));
+        assert_eq!(rect(CursorShape::Block), (10.0, 20.0, 8.0, 16.0));
+        assert_eq!(rect(CursorShape::HalfBlock), (10.0, 28.0, 8.0, 8.0));
+        assert_eq!(
+            rect(CursorShape::Underline),
+            (10.0, 34.0, 8.0, UNDERLINE_CURSOR_HEIGHT)
+        );
+    }
 }
@@ -1,11 +1,16 @@
 //! UI components for the IME
 //!
-//! Contains the unified popup window and text rendering functionality.
+//! Contains the unified popup window, its on-demand factory and text rendering
+//! functionality.
 
+mod grapheme;
 mod layout;
+mod popup_factory;
 mod text_render;
+mod theme;
 mod unified_window;
 
 pub use layout::PopupContent;
-pub use text_render::TextRenderer;
-pub use unified_window::UnifiedPopup;
+pub use popup_factory::PopupFactory;
+pub use theme::Theme;
+pub use unified_window::{Placement, UnifiedPopup};
@@ -0,0 +1,170 @@
+//! On-demand popup creation
+//!
+//! A popup that can't be created (no usable font, shm failure) is retried on
+//! later popup updates with exponential backoff, so installing fonts while jacin
+//! runs brings the popup back without a restart.
+
+use std::time::{Duration, Instant};
+
+use wayland_client::QueueHandle;
+use wayland_client::protocol::{wl_compositor::WlCompositor, wl_shm::WlShm};
+use wayland_protocols_misc::zwp_input_method_v2::client::zwp_input_method_v2::ZwpInputMethodV2;
+
+use super::{Placement, Theme, UnifiedPopup};
+use crate::State;
+use crate::quirks::PopupHide;
+use crate::state::SeatId;
+
+/// Wait after the first failure; doubles per failure up to `MAX_RETRY_DELAY`
+const INITIAL_RETRY_DELAY: Duration = Duration::from_secs(2);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Exponential backoff between popup creation attempts
+#[derive(Debug, Default)]
+pub struct RetryBackoff {
+    /// Consecutive failed attempts
+    failures: u32,
+    /// No attempt before this (None = try now)
+    next_attempt: Option<Instant>,
+}
+
+impl RetryBackoff {
+    pub fn ready(&self, now: Instant) -> bool {
+        self.next_attempt.is_none_or(|t| now >= t)
+    }
+
+    /// Record a failed attempt at `now`. Returns the delay until the next one.
+    pub fn fail(&mut self, now: Instant) -> Duration {
+        let delay = INITIAL_RETRY_DELAY
+            .saturating_mul(1 << self.failures.min(16))
+            .min(MAX_RETRY_DELAY);
+        self.failures += 1;
+        self.next_attempt = Some(now + delay);
+        delay
+    }
+
+    /// Record a successful attempt. Returns true if earlier attempts had failed.
+    pub fn succeed(&mut self) -> bool {
+        self.next_attempt = None;
+        std::mem::take(&mut self.failures) > 0
+    }
+}
+
+/// Everything needed to create a seat's popup, kept for retries
+pub struct PopupFactory {
+    compositor: WlCompositor,
+    shm: WlShm,
+    theme: Theme,
+    hide_strategy: PopupHide,
+    pointer_input: bool,
+    placement: Placement,
+    retry: RetryBackoff,
+}
+
+impl PopupFactory {
+    pub fn new(
+        compositor: WlCompositor,
+        shm: WlShm,
+        theme: Theme,
+        hide_strategy: PopupHide,
+        pointer_input: bool,
+        placement: Placement,
+    ) -> Self {
+        Self {
+            compositor,
+            shm,
+            theme,
+            hide_strategy,
+            pointer_input,
+            placement,
+            retry: RetryBackoff::default(),
+        }
+    }
+
+    /// Theme of new popups (before any `[[popup.output]]` override)
+    pub fn theme(&self) -> &Theme {
+        &self.theme
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    /// Try to create the popup for `seat` unless still backing off from a failure.
+    /// Returns the popup and whether it recovers from earlier failures.
+    pub fn create(
+        &mut self,
+        seat: SeatId,
+        input_method: &ZwpInputMethodV2,
+        qh: &QueueHandle<State>,
+        now: Instant,
+    ) -> Option<(UnifiedPopup, bool)> {
+        if !self.retry.ready(now) {
+            return None;
+        }
+        let popup = UnifiedPopup::new(
+            seat,
+            &self.compositor,
+            input_method,
+            &self.shm,
+            qh,
+            self.theme.clone(),
+            self.hide_strategy,
+        );
+        match popup {
+            Some(popup) => {
+                let recovered = self.retry.succeed();
+                let popup = popup
+                    .with_pointer_input(self.pointer_input)
+                    .with_placement(self.placement.clone());
+                Some((popup, recovered))
+            }
+            None => {
+                let delay = self.retry.fail(now);
+                log::warn!(
+                    "Failed to create popup window for seat {} (retrying in {}s)",
+                    seat,
+                    delay.as_secs()
+                );
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let mut retry = RetryBackoff::default();
+        let now = Instant::now();
+        assert!(retry.ready(now));
+
+        assert_eq!(retry.fail(now), Duration::from_secs(2));
+        assert!(!retry.ready(now + Duration::from_secs(1)));
+        assert!(retry.ready(now + Duration::from_secs(2)));
+        assert_eq!(retry.fail(now), Duration::from_secs(4));
+        assert_eq!(retry.fail(now), Duration::from_secs(8));
+        for _ in 0..40 {
+            retry.fail(now);
+        }
+        assert_eq!(retry.fail(now), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn success_reports_recovery_once() {
+        let mut retry = RetryBackoff::default();
+        assert!(!retry.succeed());
+
+        let now = Instant::now();
+        retry.fail(now);
+        retry.fail(now);
+        assert!(retry.succeed());
+        assert!(retry.ready(now));
+        assert!(!retry.succeed());
+        // Backoff restarts from the initial delay
+        assert_eq!(retry.fail(now), INITIAL_RETRY_DELAY);
+    }
+}
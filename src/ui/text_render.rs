@@ -1,40 +1,123 @@
-//! Text rendering for candidate window using fontconfig, fontdue, and tiny-skia
+//! Text rendering for candidate window using fontconfig, rustybuzz, fontdue, and tiny-skia
+//!
+//! fontdue only rasterizes outlines into coverage bitmaps. Color emoji fonts
+//! (Noto Color Emoji's CBDT, Apple's sbix) carry PNG strikes instead, which
+//! are decoded and scaled to the font size here.
 
 use fontconfig::{FC_CHARSET, Fontconfig};
 use fontconfig_sys as sys;
 use fontconfig_sys::ffi_dispatch;
 // Without dlopen, ffi_dispatch! expands to direct function calls from sys::*
 use fontdue::{Font, FontSettings};
-use memmap2::MmapMut;
+use rustybuzz::ttf_parser;
 use std::collections::HashMap;
-use std::os::fd::AsFd;
+use std::hash::Hash;
+use std::ops::Range;
 use std::sync::Arc;
 use sys::*;
-use tiny_skia::{Color, Paint, Pixmap, Rect, Transform};
-use wayland_client::QueueHandle;
-use wayland_client::protocol::{wl_shm, wl_shm_pool};
+use tiny_skia::{Color, FilterQuality, Paint, Pixmap, PixmapPaint, Rect, Transform};
 
-use crate::State;
+use super::grapheme;
 
-/// Font renderer with glyph caching and per-glyph font fallback
+/// Font renderer with shaping, glyph caching and per-cluster font fallback
 pub struct TextRenderer {
-    font: Font,
-    fallback_fonts: Vec<Font>,
+    /// Primary font first, then fallbacks in the order they were needed
+    fonts: Vec<LoadedFont>,
     fc: Fontconfig,
     font_size: f32,
-    glyph_cache: HashMap<char, GlyphData>,
+    /// Font (index into `fonts`) drawing each char
+    font_for: HashMap<char, usize>,
+    /// Rasterized glyphs. This and the caches below start over when full,
+    /// and go with the renderer when the theme or font changes.
+    glyph_cache: HashMap<(usize, u16), GlyphData>,
+    /// Glyphs tinted per color (RGBA8) at this renderer's size, blitted whole
+    /// instead of blending the coverage bitmap pixel by pixel on every render.
+    /// Color glyphs are stored untinted. None for glyphs without pixels (spaces).
+    tile_cache: HashMap<(usize, u16, [u8; 4]), Option<Pixmap>>,
+    /// Shaped glyph runs of recently measured or drawn text
+    shape_cache: HashMap<String, Arc<[ShapedGlyph]>>,
+}
+
+/// A font file: fontdue rasterizes it, rustybuzz shapes with it
+struct LoadedFont {
+    raster: Font,
+    data: Arc<[u8]>,
+    index: u32,
+    /// Has bitmap strikes (CBDT or sbix), drawn in their own colors
+    color: bool,
+}
+
+impl LoadedFont {
+    fn parse(data: Vec<u8>, index: u32) -> Result<Self, &'static str> {
+        let raster = Font::from_bytes(
+            data.as_slice(),
+            FontSettings {
+                collection_index: index,
+                ..Default::default()
+            },
+        )?;
+        let color = ttf_parser::Face::parse(&data, index)
+            .is_ok_and(|face| face.tables().cbdt.is_some() || face.tables().sbix.is_some());
+        Ok(Self {
+            raster,
+            data: data.into(),
+            index,
+            color,
+        })
+    }
 }
 
 #[derive(Clone)]
 struct GlyphData {
     metrics: fontdue::Metrics,
-    bitmap: Arc<[u8]>,
+    image: GlyphImage,
+}
+
+#[derive(Clone)]
+enum GlyphImage {
+    /// Outline coverage (fontdue), drawn in the text color
+    Coverage(Arc<[u8]>),
+    /// Bitmap strike scaled to the font size, drawn as is
+    Color(Arc<Pixmap>),
+}
+
+/// A glyph placed by shaping (positions in pixels from the start of the text)
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ShapedGlyph {
+    font: usize,
+    glyph: u16,
+    /// Byte offset of the shaping cluster (ligature, base with marks) it belongs to
+    cluster: usize,
+    /// Pen position before the glyph
+    pen: f32,
+    x_offset: f32,
+    y_offset: f32,
+}
+
+/// Shaped texts kept per renderer before the cache starts over
+const SHAPE_CACHE_LIMIT: usize = 1024;
+
+/// Rasterized glyphs kept per renderer (a few thousand kanji) before the
+/// cache starts over
+const GLYPH_CACHE_LIMIT: usize = 4096;
+
+/// Tinted tiles kept per renderer (one per glyph and color, each font size
+/// squared RGBA) before the cache starts over
+const TILE_CACHE_LIMIT: usize = 2048;
+
+/// Insert into a cache that starts over once it holds `limit` entries
+fn insert_capped<K: Eq + Hash, V>(cache: &mut HashMap<K, V>, limit: usize, key: K, value: V) {
+    if cache.len() >= limit {
+        cache.clear();
+    }
+    cache.insert(key, value);
 }
 
 impl TextRenderer {
-    /// Create a text renderer with an optional font family name.
-    /// Falls back to fontconfig auto-detection if the family is not found.
-    pub fn new_with_family(font_size: f32, family: Option<&str>) -> Option<Self> {
+    /// Proportional renderer (preedit/candidates/furigana) with an optional
+    /// font family name. Falls back to fontconfig auto-detection if the family
+    /// is not found.
+    pub fn new(font_size: f32, family: Option<&str>) -> Option<Self> {
         let (font, fc) = if let Some(name) = family {
             load_font_with_family(Some(name)).or_else(|| {
                 log::warn!("[FONT] Family {:?} not found, using default", name);
@@ -43,27 +126,27 @@ impl TextRenderer {
         } else {
             load_font()?
         };
-        Some(Self {
-            font,
-            fallback_fonts: Vec::new(),
+        Some(Self::with_font(font, fc, font_size))
+    }
+
+    fn with_font(font: LoadedFont, fc: Fontconfig, font_size: f32) -> Self {
+        Self {
+            fonts: vec![font],
             fc,
             font_size,
+            font_for: HashMap::new(),
             glyph_cache: HashMap::new(),
-        })
+            tile_cache: HashMap::new(),
+            shape_cache: HashMap::new(),
+        }
     }
 
-    /// Create a monospace text renderer with an optional font family name.
-    /// Falls back to fontconfig "monospace" match, then default font.
-    pub fn new_monospace_with_family(font_size: f32, family: Option<&str>) -> Option<Self> {
+    /// Monospace renderer (keypress/mode display) with an optional font family
+    /// name. Falls back to fontconfig "monospace" match, then default font.
+    pub fn new_monospace(font_size: f32, family: Option<&str>) -> Option<Self> {
         if let Some(name) = family {
             if let Some((font, fc)) = load_font_with_family(Some(name)) {
-                return Some(Self {
-                    font,
-                    fallback_fonts: Vec::new(),
-                    fc,
-                    font_size,
-                    glyph_cache: HashMap::new(),
-                });
+                return Some(Self::with_font(font, fc, font_size));
             }
             log::warn!(
                 "[FONT] Mono family {:?} not found, falling back to monospace",
@@ -71,73 +154,183 @@ impl TextRenderer {
             );
         }
         if let Some((font, fc)) = load_font_with_family(Some("monospace")) {
-            Some(Self {
-                font,
-                fallback_fonts: Vec::new(),
-                fc,
-                font_size,
-                glyph_cache: HashMap::new(),
-            })
+            Some(Self::with_font(font, fc, font_size))
         } else {
-            Self::new_with_family(font_size, None)
+            Self::new(font_size, None)
         }
     }
 
-    /// Get or rasterize a glyph with font fallback
-    fn get_glyph(&mut self, c: char) -> GlyphData {
-        if let Some(cached) = self.glyph_cache.get(&c) {
-            return cached.clone();
+    /// Font that draws `c`: the primary font, a fallback that has it, or the
+    /// primary's .notdef. Emoji come from a color font when there is one,
+    /// even if a text font has a monochrome glyph for them.
+    fn font_index(&mut self, c: char) -> usize {
+        if let Some(&index) = self.font_for.get(&c) {
+            return index;
+        }
+        let color = if is_color_emoji(c) {
+            self.fallback_index(c, true)
+        } else {
+            None
+        };
+        let index = color.or_else(|| self.fallback_index(c, false)).unwrap_or(0);
+        self.font_for.insert(c, index);
+        index
+    }
+
+    /// A loaded font that has `c` (a color font if `color`), otherwise one
+    /// found by fontconfig
+    fn fallback_index(&mut self, c: char, color: bool) -> Option<usize> {
+        let has = |f: &LoadedFont| f.raster.has_glyph(c) && (f.color || !color);
+        if let Some(index) = self.fonts.iter().position(has) {
+            return Some(index);
         }
+        // Query fontconfig for a fallback font covering this character
+        let font = self.query_fallback_font(c, color).filter(has)?;
+        self.fonts.push(font);
+        Some(self.fonts.len() - 1)
+    }
 
-        // Try primary font
-        if self.font.has_glyph(c) {
-            let (metrics, bitmap) = self.font.rasterize(c, self.font_size);
-            let data = GlyphData {
+    /// Get or rasterize a glyph
+    fn get_glyph(&mut self, font: usize, glyph: u16) -> GlyphData {
+        if let Some(cached) = self.glyph_cache.get(&(font, glyph)) {
+            return cached.clone();
+        }
+        let color = match self.fonts[font].color {
+            true => self.color_glyph(font, glyph),
+            false => None,
+        };
+        let data = color.unwrap_or_else(|| {
+            let (metrics, bitmap) = self.fonts[font]
+                .raster
+                .rasterize_indexed(glyph, self.font_size);
+            GlyphData {
                 metrics,
-                bitmap: bitmap.into(),
+                image: GlyphImage::Coverage(bitmap.into()),
+            }
+        });
+        insert_capped(
+            &mut self.glyph_cache,
+            GLYPH_CACHE_LIMIT,
+            (font, glyph),
+            data.clone(),
+        );
+        data
+    }
+
+    /// Bitmap strike of `glyph` from a color font, scaled to the font size
+    /// (None if the glyph has no PNG image)
+    fn color_glyph(&self, font: usize, glyph: u16) -> Option<GlyphData> {
+        let loaded = &self.fonts[font];
+        let face = ttf_parser::Face::parse(&loaded.data, loaded.index).ok()?;
+        let ppem = self.font_size.round().clamp(1.0, u16::MAX as f32) as u16;
+        let image = face.glyph_raster_image(ttf_parser::GlyphId(glyph), ppem)?;
+        if image.format != ttf_parser::RasterImageFormat::PNG {
+            return None;
+        }
+        let scale = self.font_size / image.pixels_per_em as f32;
+        let pixmap = scaled_png(image.data, scale)?;
+        let metrics = fontdue::Metrics {
+            xmin: (image.x as f32 * scale).round() as i32,
+            ymin: (image.y as f32 * scale).round() as i32,
+            width: pixmap.width() as usize,
+            height: pixmap.height() as usize,
+            ..Default::default()
+        };
+        Some(GlyphData {
+            metrics,
+            image: GlyphImage::Color(Arc::new(pixmap)),
+        })
+    }
+
+    /// Tile for `glyph` of `font` in `color`
+    fn get_tile(&mut self, font: usize, glyph: u16, color: Color) -> Option<&Pixmap> {
+        let rgba = color.to_color_u8();
+        let key = (
+            font,
+            glyph,
+            [rgba.red(), rgba.green(), rgba.blue(), rgba.alpha()],
+        );
+        if !self.tile_cache.contains_key(&key) {
+            let data = self.get_glyph(font, glyph);
+            let tile = match &data.image {
+                GlyphImage::Coverage(bitmap) => {
+                    tint(bitmap, data.metrics.width, data.metrics.height, color)
+                }
+                GlyphImage::Color(pixmap) => Some(Pixmap::clone(pixmap)),
             };
-            self.glyph_cache.insert(c, data.clone());
-            return data;
+            insert_capped(&mut self.tile_cache, TILE_CACHE_LIMIT, key, tile);
         }
+        self.tile_cache[&key].as_ref()
+    }
 
-        // Try existing fallback fonts
-        for fb in &self.fallback_fonts {
-            if fb.has_glyph(c) {
-                let (metrics, bitmap) = fb.rasterize(c, self.font_size);
-                let data = GlyphData {
-                    metrics,
-                    bitmap: bitmap.into(),
-                };
-                self.glyph_cache.insert(c, data.clone());
-                return data;
+    /// Shape `text`: grapheme clusters are grouped into runs by the font
+    /// that has their base char, and each run is shaped with that font
+    fn shape(&mut self, text: &str) -> Arc<[ShapedGlyph]> {
+        if let Some(shaped) = self.shape_cache.get(text) {
+            return shaped.clone();
+        }
+        let mut runs: Vec<(usize, std::ops::Range<usize>)> = Vec::new();
+        let mut start = 0;
+        for cluster in grapheme::clusters(text) {
+            let font = cluster.chars().next().map_or(0, |c| self.font_index(c));
+            let end = start + cluster.len();
+            match runs.last_mut() {
+                Some((run_font, range)) if *run_font == font => range.end = end,
+                _ => runs.push((font, start..end)),
             }
+            start = end;
         }
 
-        // Query fontconfig for a fallback font covering this character
-        if let Some(fb) = self.query_fallback_font(c) {
-            let (metrics, bitmap) = fb.rasterize(c, self.font_size);
-            let data = GlyphData {
-                metrics,
-                bitmap: bitmap.into(),
+        let mut glyphs = Vec::new();
+        let mut pen = 0.0;
+        for (font, range) in runs {
+            let loaded = &self.fonts[font];
+            let Some(face) = rustybuzz::Face::from_slice(&loaded.data, loaded.index) else {
+                log::warn!("[FONT] Font can't be shaped, skipping {:?}", &text[range]);
+                continue;
             };
-            self.glyph_cache.insert(c, data.clone());
-            self.fallback_fonts.push(fb);
-            return data;
+            let scale = self.font_size / face.units_per_em() as f32;
+            let mut buffer = rustybuzz::UnicodeBuffer::new();
+            buffer.push_str(&text[range.clone()]);
+            // IME text runs left to right (cursor offsets rely on it)
+            buffer.set_direction(rustybuzz::Direction::LeftToRight);
+            let output = rustybuzz::shape(&face, &[], buffer);
+            for (info, pos) in output.glyph_infos().iter().zip(output.glyph_positions()) {
+                glyphs.push(ShapedGlyph {
+                    font,
+                    glyph: info.glyph_id as u16,
+                    cluster: range.start + info.cluster as usize,
+                    pen,
+                    x_offset: pos.x_offset as f32 * scale,
+                    y_offset: pos.y_offset as f32 * scale,
+                });
+                pen += pos.x_advance as f32 * scale;
+            }
         }
-
-        // Last resort: primary font's .notdef glyph
-        let (metrics, bitmap) = self.font.rasterize(c, self.font_size);
-        let data = GlyphData {
-            metrics,
-            bitmap: bitmap.into(),
-        };
-        self.glyph_cache.insert(c, data.clone());
-        data
+        // The end of the text, as a glyph-less position
+        glyphs.push(ShapedGlyph {
+            font: 0,
+            glyph: 0,
+            cluster: text.len(),
+            pen,
+            x_offset: 0.0,
+            y_offset: 0.0,
+        });
+
+        let shaped: Arc<[ShapedGlyph]> = glyphs.into();
+        insert_capped(
+            &mut self.shape_cache,
+            SHAPE_CACHE_LIMIT,
+            text.to_string(),
+            shaped.clone(),
+        );
+        shaped
     }
 
-    /// Query fontconfig for a font that covers the given character
+    /// Query fontconfig for a font that covers the given character, asking
+    /// for a color font if `color`
     #[allow(unexpected_cfgs)] // ffi_dispatch! macro checks cfg(feature = "dlopen") internally
-    fn query_fallback_font(&self, c: char) -> Option<Font> {
+    fn query_fallback_font(&self, c: char, color: bool) -> Option<LoadedFont> {
         unsafe {
             let cs = ffi_dispatch!(LIB, FcCharSetCreate,);
             ffi_dispatch!(LIB, FcCharSetAddChar, cs, c as u32);
@@ -150,6 +343,15 @@ impl TextRenderer {
                 FC_CHARSET.as_ptr(),
                 cs
             );
+            if color {
+                ffi_dispatch!(
+                    LIB,
+                    FcPatternAddBool,
+                    pat.as_mut_ptr(),
+                    constants::FC_COLOR.as_ptr(),
+                    1 // FcTrue
+                );
+            }
             let matched = pat.font_match();
             ffi_dispatch!(LIB, FcCharSetDestroy, cs);
 
@@ -160,15 +362,9 @@ impl TextRenderer {
                 .map_err(|e| log::warn!("[FONT] Failed to read fallback {}: {}", path, e))
                 .ok()?;
 
-            let font = Font::from_bytes(
-                data,
-                FontSettings {
-                    collection_index: index,
-                    ..Default::default()
-                },
-            )
-            .map_err(|e| log::warn!("[FONT] Failed to parse fallback {}: {}", path, e))
-            .ok()?;
+            let font = LoadedFont::parse(data, index)
+                .map_err(|e| log::warn!("[FONT] Failed to parse fallback {}: {}", path, e))
+                .ok()?;
 
             log::info!("[FONT] Fallback for '{}': {} (index={})", c, path, index);
             Some(font)
@@ -177,12 +373,13 @@ impl TextRenderer {
 
     /// Measure text width
     pub fn measure_text(&mut self, text: &str) -> f32 {
-        let mut width = 0.0;
-        for c in text.chars() {
-            let glyph = self.get_glyph(c);
-            width += glyph.metrics.advance_width;
-        }
-        width
+        self.shape(text).last().map_or(0.0, |end| end.pen)
+    }
+
+    /// X offset of each grapheme cluster of `text` from its start, followed
+    /// by the text's width. Cursor and selection positions come from these.
+    pub fn cluster_offsets(&mut self, text: &str) -> Vec<f32> {
+        cluster_offsets(text, &self.shape(text))
     }
 
     /// Get line height (includes some padding)
@@ -192,112 +389,112 @@ impl TextRenderer {
 
     /// Draw text at position
     pub fn draw_text(&mut self, pixmap: &mut Pixmap, text: &str, x: f32, y: f32, color: Color) {
-        let mut cursor_x = x;
-
-        for c in text.chars() {
-            let glyph = self.get_glyph(c);
-
-            // Calculate glyph position
-            let glyph_x = cursor_x + glyph.metrics.xmin as f32;
-            let glyph_y = y - glyph.metrics.ymin as f32 - glyph.metrics.height as f32;
-
-            // Draw glyph bitmap
-            if glyph.metrics.width > 0 && glyph.metrics.height > 0 {
-                draw_glyph_bitmap(
-                    pixmap,
-                    &glyph.bitmap,
-                    glyph.metrics.width,
-                    glyph.metrics.height,
-                    glyph_x as i32,
-                    glyph_y as i32,
-                    color,
-                );
-            }
-
-            cursor_x += glyph.metrics.advance_width;
+        let shaped = self.shape(text);
+        // The last entry only marks the end of the text
+        for glyph in &shaped[..shaped.len() - 1] {
+            let metrics = self.get_glyph(glyph.font, glyph.glyph).metrics;
+            let Some(tile) = self.get_tile(glyph.font, glyph.glyph, color) else {
+                continue;
+            };
+            let glyph_x = x + glyph.pen + glyph.x_offset + metrics.xmin as f32;
+            let glyph_y = y - glyph.y_offset - metrics.ymin as f32 - metrics.height as f32;
+            pixmap.draw_pixmap(
+                glyph_x as i32,
+                glyph_y as i32,
+                tile.as_ref(),
+                &PixmapPaint::default(),
+                Transform::identity(),
+                None,
+            );
         }
     }
 }
 
-fn draw_glyph_bitmap(
-    pixmap: &mut Pixmap,
-    bitmap: &[u8],
-    width: usize,
-    height: usize,
-    x: i32,
-    y: i32,
-    color: Color,
-) {
-    let pixmap_width = pixmap.width() as i32;
-    let pixmap_height = pixmap.height() as i32;
-    let pixels = pixmap.pixels_mut();
-
-    for gy in 0..height {
-        for gx in 0..width {
-            let px = x + gx as i32;
-            let py = y + gy as i32;
-
-            if px >= 0 && px < pixmap_width && py >= 0 && py < pixmap_height {
-                let alpha = bitmap[gy * width + gx];
-                if alpha > 0 {
-                    let idx = (py * pixmap_width + px) as usize;
-                    let existing = pixels[idx];
-
-                    // Alpha blend
-                    let a = (alpha as f32 / 255.0) * color.alpha();
-                    let inv_a = 1.0 - a;
-
-                    let r = (color.red() * a + existing.red() as f32 / 255.0 * inv_a) * 255.0;
-                    let g = (color.green() * a + existing.green() as f32 / 255.0 * inv_a) * 255.0;
-                    let b = (color.blue() * a + existing.blue() as f32 / 255.0 * inv_a) * 255.0;
-
-                    pixels[idx] =
-                        tiny_skia::PremultipliedColorU8::from_rgba(r as u8, g as u8, b as u8, 255)
-                            .unwrap();
-                }
-            }
+/// Offsets of the grapheme clusters of `text` in `shaped` (see
+/// [`TextRenderer::cluster_offsets`]). Grapheme clusters shaped into one glyph
+/// cluster (a ligature) share its advance evenly.
+fn cluster_offsets(text: &str, shaped: &[ShapedGlyph]) -> Vec<f32> {
+    // Start of each shaping cluster, ending with the end of the text
+    let mut marks: Vec<(usize, f32)> = Vec::new();
+    for glyph in shaped {
+        if marks
+            .last()
+            .is_none_or(|&(cluster, _)| cluster != glyph.cluster)
+        {
+            marks.push((glyph.cluster, glyph.pen));
         }
     }
+    let starts: Vec<usize> = grapheme::clusters(text)
+        .scan(0, |offset, cluster| {
+            let start = *offset;
+            *offset += cluster.len();
+            Some(start)
+        })
+        .collect();
+
+    let mut offsets: Vec<f32> = starts
+        .iter()
+        .map(|&byte| {
+            let i = marks
+                .partition_point(|&(cluster, _)| cluster <= byte)
+                .saturating_sub(1);
+            let (start, x) = marks[i];
+            let (end, next_x) = marks.get(i + 1).copied().unwrap_or((text.len(), x));
+            let sharing = starts.iter().filter(|&&s| s >= start && s < end);
+            let share = sharing.clone().count().max(1) as f32;
+            let nth = sharing.take_while(|&&s| s < byte).count() as f32;
+            x + (next_x - x) * nth / share
+        })
+        .collect();
+    offsets.push(marks.last().map_or(0.0, |&(_, x)| x));
+    offsets
 }
 
-/// Create a shared memory pool for Wayland surfaces
-pub fn create_shm_pool(
-    shm: &wl_shm::WlShm,
-    qh: &QueueHandle<State>,
-    size: usize,
-    name: &str,
-) -> Option<(wl_shm_pool::WlShmPool, MmapMut)> {
-    use std::os::fd::FromRawFd;
-
-    // Create anonymous file with memfd_create
-    let fd = unsafe {
-        let c_name = std::ffi::CString::new(name).ok()?;
-        libc::memfd_create(c_name.as_ptr(), libc::MFD_CLOEXEC)
-    };
-
-    if fd < 0 {
-        log::error!("[SHM] Failed to create memfd for {}", name);
-        return None;
+/// Coverage bitmap as premultiplied `color` pixels (None if empty)
+fn tint(bitmap: &[u8], width: usize, height: usize, color: Color) -> Option<Pixmap> {
+    let mut tile = Pixmap::new(width as u32, height as u32)?;
+    for (pixel, &coverage) in tile.pixels_mut().iter_mut().zip(bitmap) {
+        let mut tinted = color;
+        tinted.apply_opacity(coverage as f32 / 255.0);
+        *pixel = tinted.premultiply().to_color_u8();
     }
+    Some(tile)
+}
 
-    let file = unsafe { std::fs::File::from_raw_fd(fd) };
-
-    // Set file size
-    if file.set_len(size as u64).is_err() {
-        log::error!("[SHM] Failed to set memfd size for {}", name);
-        return None;
+/// Decode a PNG glyph image and resize it by `scale`
+fn scaled_png(data: &[u8], scale: f32) -> Option<Pixmap> {
+    let image = Pixmap::decode_png(data).ok()?;
+    if scale == 1.0 {
+        return Some(image);
     }
+    let width = (image.width() as f32 * scale).round().max(1.0) as u32;
+    let height = (image.height() as f32 * scale).round().max(1.0) as u32;
+    let mut scaled = Pixmap::new(width, height)?;
+    let paint = PixmapPaint {
+        quality: FilterQuality::Bicubic,
+        ..Default::default()
+    };
+    scaled.draw_pixmap(
+        0,
+        0,
+        image.as_ref(),
+        &paint,
+        Transform::from_scale(scale, scale),
+        None,
+    );
+    Some(scaled)
+}
 
-    // Memory map the file
-    let mmap = unsafe { MmapMut::map_mut(&file) }.ok()?;
-
-    // Create wl_shm_pool
-    let pool = shm.create_pool(file.as_fd(), size as i32, qh, ());
-
-    // Keep file alive by leaking it (pool owns the fd now)
-    std::mem::forget(file);
-
-    Some((pool, mmap))
+/// Whether `c` is drawn as an emoji picture by default (Emoji_Presentation
+/// blocks). Symbols like ☆ and ♪ that are text by default stay in the text font.
+fn is_color_emoji(c: char) -> bool {
+    matches!(c as u32,
+        0x1F1E6..=0x1F1FF // regional indicators (flags)
+        | 0x1F300..=0x1F5FF // symbols and pictographs
+        | 0x1F600..=0x1F64F // emoticons
+        | 0x1F680..=0x1F6FF // transport and map
+        | 0x1F900..=0x1F9FF // supplemental symbols and pictographs
+        | 0x1FA70..=0x1FAFF) // symbols and pictographs extended-A
 }
 
 /// Copy pixmap data to SHM buffer, converting RGBA to ARGB (Wayland format)
@@ -314,37 +511,68 @@ pub fn copy_pixmap_to_shm(pixmap: &Pixmap, dest: &mut [u8]) {
     }
 }
 
-/// Draw a 1-pixel border around the pixmap
-pub fn draw_border(pixmap: &mut Pixmap, width: u32, height: u32, color: Color) {
+/// Runs of rows in which `frame` differs from `previous`: all of them if the
+/// sizes differ, none if the frames are identical
+pub fn changed_rows(previous: &Pixmap, frame: &Pixmap) -> Vec<Range<u32>> {
+    if (previous.width(), previous.height()) != (frame.width(), frame.height()) {
+        return vec![Range {
+            start: 0,
+            end: frame.height(),
+        }];
+    }
+    let stride = frame.width() as usize * 4;
+    let mut runs: Vec<Range<u32>> = Vec::new();
+    let rows = previous
+        .data()
+        .chunks(stride)
+        .zip(frame.data().chunks(stride));
+    for (y, (old, new)) in (0..).zip(rows) {
+        if old == new {
+            continue;
+        }
+        match runs.last_mut() {
+            Some(run) if run.end == y => run.end = y + 1,
+            _ => runs.push(y..y + 1),
+        }
+    }
+    runs
+}
+
+/// Draw a `border_width`-pixel border around the pixmap (nothing for 0)
+pub fn draw_border(pixmap: &mut Pixmap, width: u32, height: u32, border_width: f32, color: Color) {
     let mut paint = Paint::default();
     paint.set_color(color);
+    let b = border_width.ceil();
+    if b <= 0.0 {
+        return;
+    }
 
     // Top
-    if let Some(rect) = Rect::from_xywh(0.0, 0.0, width as f32, 1.0) {
+    if let Some(rect) = Rect::from_xywh(0.0, 0.0, width as f32, b) {
         pixmap.fill_rect(rect, &paint, Transform::identity(), None);
     }
     // Bottom
-    if let Some(rect) = Rect::from_xywh(0.0, height as f32 - 1.0, width as f32, 1.0) {
+    if let Some(rect) = Rect::from_xywh(0.0, height as f32 - b, width as f32, b) {
         pixmap.fill_rect(rect, &paint, Transform::identity(), None);
     }
     // Left
-    if let Some(rect) = Rect::from_xywh(0.0, 0.0, 1.0, height as f32) {
+    if let Some(rect) = Rect::from_xywh(0.0, 0.0, b, height as f32) {
         pixmap.fill_rect(rect, &paint, Transform::identity(), None);
     }
     // Right
-    if let Some(rect) = Rect::from_xywh(width as f32 - 1.0, 0.0, 1.0, height as f32) {
+    if let Some(rect) = Rect::from_xywh(width as f32 - b, 0.0, b, height as f32) {
         pixmap.fill_rect(rect, &paint, Transform::identity(), None);
     }
 }
 
 /// Find and load a font via fontconfig (automatic detection, no preferences).
-fn load_font() -> Option<(Font, Fontconfig)> {
+fn load_font() -> Option<(LoadedFont, Fontconfig)> {
     load_font_with_family(None)
 }
 
 /// Load a font via fontconfig, optionally requesting a specific family (e.g., "monospace").
 #[allow(unexpected_cfgs)]
-fn load_font_with_family(family: Option<&str>) -> Option<(Font, Fontconfig)> {
+fn load_font_with_family(family: Option<&str>) -> Option<(LoadedFont, Fontconfig)> {
     let fc = Fontconfig::new().or_else(|| {
         log::warn!("[FONT] Failed to initialize fontconfig");
         None
@@ -382,17 +610,11 @@ fn load_font_with_family(family: Option<&str>) -> Option<(Font, Fontconfig)> {
         })
         .ok()?;
 
-    let font = Font::from_bytes(
-        data,
-        FontSettings {
-            collection_index: index,
-            ..Default::default()
-        },
-    )
-    .map_err(|e| {
-        log::warn!("[FONT] Failed to parse {}: {}", path, e);
-    })
-    .ok()?;
+    let font = LoadedFont::parse(data, index)
+        .map_err(|e| {
+            log::warn!("[FONT] Failed to parse {}: {}", path, e);
+        })
+        .ok()?;
 
     let family_label = family.unwrap_or("default");
     log::info!(
@@ -403,3 +625,127 @@ fn load_font_with_family(family: Option<&str>) -> Option<(Font, Fontconfig)> {
     );
     Some((font, fc))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capped_caches_start_over_when_full() {
+        let mut cache = HashMap::new();
+        for i in 0..3 {
+            insert_capped(&mut cache, 3, i, i);
+        }
+        assert_eq!(cache.len(), 3);
+        insert_capped(&mut cache, 3, 3, 3);
+        assert_eq!(cache, HashMap::from([(3, 3)]));
+    }
+
+    #[test]
+    fn tinted_tiles_blend_by_coverage() {
+        let red = Color::from_rgba8(255, 0, 0, 255);
+        let tile = tint(&[0, 128, 255], 3, 1, red).unwrap();
+        let mut pixmap = Pixmap::new(4, 1).unwrap();
+        pixmap.fill(Color::WHITE);
+        pixmap.draw_pixmap(
+            1,
+            0,
+            tile.as_ref(),
+            &PixmapPaint::default(),
+            Transform::identity(),
+            None,
+        );
+        let pixels: Vec<_> = pixmap
+            .pixels()
+            .iter()
+            .map(|p| (p.red(), p.green(), p.alpha()))
+            .collect();
+        assert_eq!(pixels[0], (255, 255, 255));
+        // No coverage leaves the background, full coverage is the text color
+        assert_eq!(pixels[1], (255, 255, 255));
+        let (r, g, a) = pixels[2];
+        assert_eq!((r, a), (255, 255));
+        assert!(
+            (126..=128).contains(&g),
+            "half coverage blends halfway: {g}"
+        );
+        assert_eq!(pixels[3], (255, 0, 255));
+
+        assert!(tint(&[], 0, 0, red).is_none());
+    }
+
+    #[test]
+    fn changed_rows_are_merged_into_runs() {
+        let mut previous = Pixmap::new(4, 8).unwrap();
+        previous.fill(Color::WHITE);
+        let mut frame = previous.clone();
+        assert!(changed_rows(&previous, &frame).is_empty());
+
+        let mut paint = Paint::default();
+        paint.set_color(Color::BLACK);
+        for y in [1.0, 2.0, 6.0] {
+            let rect = Rect::from_xywh(3.0, y, 1.0, 1.0).unwrap();
+            frame.fill_rect(rect, &paint, Transform::identity(), None);
+        }
+        assert_eq!(changed_rows(&previous, &frame), [1..3, 6..7]);
+
+        let smaller = Pixmap::new(4, 5).unwrap();
+        assert_eq!(
+            changed_rows(&previous, &smaller),
+            [Range { start: 0, end: 5 }]
+        );
+    }
+
+    #[test]
+    fn png_glyphs_scale_to_the_font_size() {
+        let mut strike = Pixmap::new(8, 4).unwrap();
+        strike.fill(Color::from_rgba8(255, 200, 0, 255));
+        let png = strike.encode_png().unwrap();
+        let scaled = scaled_png(&png, 0.5).unwrap();
+        assert_eq!((scaled.width(), scaled.height()), (4, 2));
+        let center = scaled.pixel(1, 1).unwrap();
+        assert_eq!((center.red(), center.alpha()), (255, 255));
+        assert_eq!(scaled_png(&png, 1.0).unwrap(), strike);
+        assert!(scaled_png(b"not a png", 0.5).is_none());
+    }
+
+    #[test]
+    fn emoji_presentation_chars_use_color_fonts() {
+        assert!(is_color_emoji('😀'));
+        assert!(is_color_emoji('🍣'));
+        assert!(is_color_emoji('🇯'));
+        assert!(!is_color_emoji('☆'));
+        assert!(!is_color_emoji('♪'));
+        assert!(!is_color_emoji('あ'));
+    }
+
+    fn glyph(cluster: usize, pen: f32) -> ShapedGlyph {
+        ShapedGlyph {
+            font: 0,
+            glyph: 1,
+            cluster,
+            pen,
+            x_offset: 0.0,
+            y_offset: 0.0,
+        }
+    }
+
+    #[test]
+    fn cluster_offsets_follow_shaped_positions() {
+        // Kerned pair: v starts at 9 rather than at A's advance of 10
+        let kerned = [glyph(0, 0.0), glyph(1, 9.0), glyph(2, 17.0)];
+        assert_eq!(cluster_offsets("Av", &kerned), [0.0, 9.0, 17.0]);
+        // A combining mark is shaped into its base's cluster: no stop between them
+        let marked = [
+            glyph(0, 0.0),
+            glyph(0, 16.0),
+            glyph(6, 16.0),
+            glyph(7, 24.0),
+        ];
+        assert_eq!(cluster_offsets("か\u{3099}a", &marked), [0.0, 16.0, 24.0]);
+        // A ligature's advance is split between the characters it joins
+        let ligature = [glyph(0, 0.0), glyph(2, 10.0), glyph(3, 14.0)];
+        assert_eq!(cluster_offsets("fi!", &ligature), [0.0, 5.0, 10.0, 14.0]);
+        assert_eq!(cluster_offsets("", &[glyph(0, 0.0)]), [0.0]);
+    }
+}
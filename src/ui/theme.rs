@@ -0,0 +1,379 @@
+//! Popup theme: colors, fonts and spacing resolved from `[theme]` and `[font]`
+
+use crate::appearance::ColorScheme;
+use crate::config::{Config, PopupOutputConfig, ThemeConfig};
+
+use super::layout::{
+    BG_COLOR, BORDER_COLOR, CURSOR_BG, KEYPRESS_TEXT_COLOR, MAX_VISIBLE_CANDIDATES,
+    MIN_POPUP_HEIGHT, MIN_POPUP_WIDTH, MODE_CMD_COLOR, MODE_INSERT_COLOR, MODE_NORMAL_COLOR,
+    MODE_OP_COLOR, MODE_RECORDING_COLOR, MODE_VISUAL_COLOR, NUMBER_COLOR, PADDING, Rgba,
+    SCROLLBAR_BG, SCROLLBAR_THUMB, SEARCH_BG, SELECTED_BG, TEXT_COLOR, VISUAL_BG,
+};
+
+const DEFAULT_FONT_SIZE: f32 = 16.0;
+const DEFAULT_BORDER_WIDTH: f32 = 1.0;
+const DEFAULT_MAX_WIDTH: u32 = 580;
+const DEFAULT_MAX_HEIGHT: u32 = 450;
+/// Largest accepted `popup.max_width` / `max_height`
+const MAX_SIZE_LIMIT: u32 = 2048;
+
+/// Resolved popup appearance
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    pub background: Rgba,
+    pub text: Rgba,
+    pub border: Rgba,
+    pub selected: Rgba,
+    pub cursor: Rgba,
+    pub visual: Rgba,
+    pub search: Rgba,
+    pub number: Rgba,
+    pub keypress: Rgba,
+    pub scrollbar: Rgba,
+    pub scrollbar_thumb: Rgba,
+    pub mode_insert: Rgba,
+    pub mode_normal: Rgba,
+    pub mode_visual: Rgba,
+    pub mode_operator: Rgba,
+    pub mode_command: Rgba,
+    pub recording: Rgba,
+    /// Proportional font family (None = fontconfig auto-detection)
+    pub font_family: Option<String>,
+    /// Monospace font family (None = fontconfig "monospace")
+    pub mono_family: Option<String>,
+    pub font_size: f32,
+    pub padding: f32,
+    pub border_width: f32,
+    /// Furigana above kanji candidates
+    pub furigana: bool,
+    /// Candidate rows shown at once (`popup.max_candidates`)
+    pub max_candidates: usize,
+    /// Largest popup size in pixels
+    pub max_width: u32,
+    pub max_height: u32,
+    /// `popup.offset_x` / `offset_y` in pixels
+    pub offset_x: u32,
+    pub offset_y: u32,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            background: BG_COLOR,
+            text: TEXT_COLOR,
+            border: BORDER_COLOR,
+            selected: SELECTED_BG,
+            cursor: CURSOR_BG,
+            visual: VISUAL_BG,
+            search: SEARCH_BG,
+            number: NUMBER_COLOR,
+            keypress: KEYPRESS_TEXT_COLOR,
+            scrollbar: SCROLLBAR_BG,
+            scrollbar_thumb: SCROLLBAR_THUMB,
+            mode_insert: MODE_INSERT_COLOR,
+            mode_normal: MODE_NORMAL_COLOR,
+            mode_visual: MODE_VISUAL_COLOR,
+            mode_operator: MODE_OP_COLOR,
+            mode_command: MODE_CMD_COLOR,
+            recording: MODE_RECORDING_COLOR,
+            font_family: None,
+            mono_family: None,
+            font_size: DEFAULT_FONT_SIZE,
+            padding: PADDING,
+            border_width: DEFAULT_BORDER_WIDTH,
+            furigana: false,
+            max_candidates: MAX_VISIBLE_CANDIDATES,
+            max_width: DEFAULT_MAX_WIDTH,
+            max_height: DEFAULT_MAX_HEIGHT,
+            offset_x: 0,
+            offset_y: 0,
+        }
+    }
+}
+
+/// Parse "#rgb", "#rrggbb" or "#rrggbbaa" (the leading '#' is optional)
+fn parse_hex_color(s: &str) -> Option<Rgba> {
+    let hex = s.trim().trim_start_matches('#');
+    if !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize, len: usize| u8::from_str_radix(&hex[i * len..(i + 1) * len], 16).ok();
+    match hex.len() {
+        3 => {
+            let c = |i| channel(i, 1).map(|v| v * 17);
+            Some((c(0)?, c(1)?, c(2)?, 255))
+        }
+        6 => Some((channel(0, 2)?, channel(1, 2)?, channel(2, 2)?, 255)),
+        8 => Some((
+            channel(0, 2)?,
+            channel(1, 2)?,
+            channel(2, 2)?,
+            channel(3, 2)?,
+        )),
+        _ => None,
+    }
+}
+
+/// Non-negative finite value clamped to `min..=max`, else `default`
+fn clamped(value: Option<f32>, min: f32, max: f32, default: f32) -> f32 {
+    value
+        .filter(|v| v.is_finite() && *v >= 0.0)
+        .map(|v| v.clamp(min, max))
+        .unwrap_or(default)
+}
+
+impl Theme {
+    /// Build the theme from `[theme]`, falling back to `[font]` and the defaults,
+    /// with the size limits of `[popup]`. Invalid colors are logged and ignored.
+    pub fn from_config(config: &Config) -> Self {
+        Self::for_scheme(config, ColorScheme::NoPreference)
+    }
+
+    /// The theme with the palette of `scheme` (`[theme.dark]` /
+    /// `[theme.light]`) over the colors of `[theme]`
+    pub fn for_scheme(config: &Config, scheme: ColorScheme) -> Self {
+        let t = &config.theme;
+        let mut theme = Self::default();
+        theme.apply_colors(t, "theme");
+        let palette = match scheme {
+            ColorScheme::Dark => t.dark.as_deref().map(|p| (p, "theme.dark")),
+            ColorScheme::Light => t.light.as_deref().map(|p| (p, "theme.light")),
+            ColorScheme::NoPreference => None,
+        };
+        if let Some((palette, section)) = palette {
+            theme.apply_colors(palette, section);
+        }
+
+        theme.font_family = t.font_family.clone().or_else(|| config.font.family.clone());
+        theme.mono_family = t
+            .mono_font_family
+            .clone()
+            .or_else(|| config.font.mono_family.clone());
+        theme.font_size = clamped(
+            t.font_size.or(config.font.size).filter(|s| *s > 0.0),
+            8.0,
+            48.0,
+            DEFAULT_FONT_SIZE,
+        );
+        theme.padding = clamped(t.padding, 0.0, 32.0, PADDING);
+        theme.border_width = clamped(t.border_width, 0.0, 8.0, DEFAULT_BORDER_WIDTH);
+        theme.furigana = t.furigana.unwrap_or(false);
+
+        let popup = &config.popup;
+        theme.max_candidates = popup.max_candidates.clamp(1, MAX_VISIBLE_CANDIDATES);
+        theme.max_width = popup.max_width.clamp(MIN_POPUP_WIDTH, MAX_SIZE_LIMIT);
+        theme.max_height = popup.max_height.clamp(MIN_POPUP_HEIGHT, MAX_SIZE_LIMIT);
+        theme.offset_x = popup.offset_x.min(MAX_SIZE_LIMIT);
+        theme.offset_y = popup.offset_y.min(MAX_SIZE_LIMIT);
+        theme
+    }
+
+    /// Colors set in `t` (a `[theme]` section named `section` in warnings)
+    fn apply_colors(&mut self, t: &ThemeConfig, section: &str) {
+        let theme = self;
+        let colors: [(&str, &Option<String>, &mut Rgba); 17] = [
+            ("background", &t.background, &mut theme.background),
+            ("text", &t.text, &mut theme.text),
+            ("border", &t.border, &mut theme.border),
+            ("selected", &t.selected, &mut theme.selected),
+            ("cursor", &t.cursor, &mut theme.cursor),
+            ("visual", &t.visual, &mut theme.visual),
+            ("search", &t.search, &mut theme.search),
+            ("number", &t.number, &mut theme.number),
+            ("keypress", &t.keypress, &mut theme.keypress),
+            ("scrollbar", &t.scrollbar, &mut theme.scrollbar),
+            (
+                "scrollbar_thumb",
+                &t.scrollbar_thumb,
+                &mut theme.scrollbar_thumb,
+            ),
+            ("mode_insert", &t.mode_insert, &mut theme.mode_insert),
+            ("mode_normal", &t.mode_normal, &mut theme.mode_normal),
+            ("mode_visual", &t.mode_visual, &mut theme.mode_visual),
+            ("mode_operator", &t.mode_operator, &mut theme.mode_operator),
+            ("mode_command", &t.mode_command, &mut theme.mode_command),
+            ("recording", &t.recording, &mut theme.recording),
+        ];
+        for (name, value, slot) in colors {
+            let Some(value) = value else { continue };
+            match parse_hex_color(value) {
+                Some(color) => *slot = color,
+                None => log::warn!("[CONFIG] Invalid {}.{} color {:?}", section, name, value),
+            }
+        }
+    }
+
+    /// This theme with the overrides of one `[[popup.output]]` applied
+    pub fn for_output(&self, output: &PopupOutputConfig) -> Self {
+        let mut theme = self.clone();
+        theme.font_size = clamped(
+            output.font_size.filter(|s| *s > 0.0),
+            8.0,
+            48.0,
+            self.font_size,
+        );
+        if let Some(width) = output.max_width {
+            theme.max_width = width.clamp(MIN_POPUP_WIDTH, MAX_SIZE_LIMIT);
+        }
+        if let Some(height) = output.max_height {
+            theme.max_height = height.clamp(MIN_POPUP_HEIGHT, MAX_SIZE_LIMIT);
+        }
+        theme
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(toml_str: &str) -> Config {
+        toml::from_str(toml_str).unwrap()
+    }
+
+    #[test]
+    fn hex_colors() {
+        assert_eq!(parse_hex_color("#1e1e2e"), Some((0x1e, 0x1e, 0x2e, 255)));
+        assert_eq!(parse_hex_color("1E1E2E"), Some((0x1e, 0x1e, 0x2e, 255)));
+        assert_eq!(parse_hex_color("#1e1e2ef0"), Some((0x1e, 0x1e, 0x2e, 0xf0)));
+        assert_eq!(parse_hex_color("#fa0"), Some((255, 170, 0, 255)));
+    }
+
+    #[test]
+    fn invalid_hex_colors() {
+        assert_eq!(parse_hex_color(""), None);
+        assert_eq!(parse_hex_color("#12345"), None);
+        assert_eq!(parse_hex_color("#gggggg"), None);
+        assert_eq!(parse_hex_color("red"), None);
+        assert_eq!(parse_hex_color("#ああ"), None);
+    }
+
+    #[test]
+    fn defaults_match_builtin_constants() {
+        let theme = Theme::from_config(&Config::default());
+        assert_eq!(theme, Theme::default());
+        assert_eq!(theme.font_size, 16.0);
+        assert_eq!(theme.padding, PADDING);
+        assert_eq!(theme.border_width, 1.0);
+        assert!(!theme.furigana);
+    }
+
+    #[test]
+    fn theme_colors_override_defaults() {
+        let theme = Theme::from_config(&config(
+            r##"
+            [theme]
+            background = "#000000cc"
+            mode_insert = "#0f0"
+            text = "not a color"
+            furigana = true
+            "##,
+        ));
+        assert!(theme.furigana);
+        assert_eq!(theme.background, (0, 0, 0, 0xcc));
+        assert_eq!(theme.mode_insert, (0, 255, 0, 255));
+        assert_eq!(theme.text, TEXT_COLOR);
+        assert_eq!(theme.border, BORDER_COLOR);
+    }
+
+    #[test]
+    fn color_scheme_palettes_override_theme_colors() {
+        let config = config(
+            r##"
+            [theme]
+            background = "#808080"
+            text = "#111"
+
+            [theme.dark]
+            background = "#000"
+            font_size = 30.0
+
+            [theme.light]
+            text = "#eee"
+            "##,
+        );
+        assert!(config.theme.follows_color_scheme());
+        let dark = Theme::for_scheme(&config, ColorScheme::Dark);
+        assert_eq!(dark.background, (0, 0, 0, 255));
+        assert_eq!(dark.text, (0x11, 0x11, 0x11, 255));
+        // Only colors come from the palette
+        assert_eq!(dark.font_size, 16.0);
+        let light = Theme::for_scheme(&config, ColorScheme::Light);
+        assert_eq!(light.background, (0x80, 0x80, 0x80, 255));
+        assert_eq!(light.text, (0xee, 0xee, 0xee, 255));
+        assert_eq!(
+            Theme::for_scheme(&config, ColorScheme::NoPreference),
+            Theme::from_config(&config)
+        );
+        assert!(!Config::default().theme.follows_color_scheme());
+    }
+
+    #[test]
+    fn theme_fonts_override_font_section() {
+        let theme = Theme::from_config(&config(
+            r#"
+            [font]
+            family = "Noto Sans CJK JP"
+            mono_family = "JetBrains Mono"
+            size = 14.0
+
+            [theme]
+            font_family = "IBM Plex Sans JP"
+            "#,
+        ));
+        assert_eq!(theme.font_family.as_deref(), Some("IBM Plex Sans JP"));
+        assert_eq!(theme.mono_family.as_deref(), Some("JetBrains Mono"));
+        assert_eq!(theme.font_size, 14.0);
+    }
+
+    #[test]
+    fn sizes_are_clamped() {
+        let theme = Theme::from_config(&config(
+            r#"
+            [theme]
+            font_size = 100.0
+            padding = -4.0
+            border_width = 20
+            "#,
+        ));
+        assert_eq!(theme.font_size, 48.0);
+        assert_eq!(theme.padding, PADDING);
+        assert_eq!(theme.border_width, 8.0);
+
+        let theme = Theme::from_config(&config("[theme]\nfont_size = 0.0\npadding = 0\n"));
+        assert_eq!(theme.font_size, 16.0);
+        assert_eq!(theme.padding, 0.0);
+    }
+
+    #[test]
+    fn popup_limits_are_clamped() {
+        let theme = Theme::from_config(&config(
+            "[popup]\nmax_candidates = 5\nmax_width = 800\nmax_height = 10\n",
+        ));
+        assert_eq!(theme.max_candidates, 5);
+        assert_eq!(theme.max_width, 800);
+        assert_eq!(theme.max_height, MIN_POPUP_HEIGHT);
+
+        let theme = Theme::from_config(&config("[popup]\nmax_candidates = 0\nmax_width = 99999\n"));
+        assert_eq!(theme.max_candidates, 1);
+        assert_eq!(theme.max_width, MAX_SIZE_LIMIT);
+        let theme = Theme::from_config(&config("[popup]\nmax_candidates = 20\n"));
+        assert_eq!(theme.max_candidates, MAX_VISIBLE_CANDIDATES);
+    }
+
+    #[test]
+    fn output_overrides_keep_the_rest_of_the_theme() {
+        let base = Theme::from_config(&config("[theme]\nfont_size = 14.0\nfurigana = true\n"));
+        let tv = base.for_output(&PopupOutputConfig {
+            name: "HDMI-A-1".into(),
+            font_size: Some(100.0),
+            max_width: Some(1200),
+            max_height: None,
+        });
+        assert_eq!(tv.font_size, 48.0);
+        assert_eq!((tv.max_width, tv.max_height), (1200, base.max_height));
+        assert!(tv.furigana);
+
+        let unset = base.for_output(&PopupOutputConfig::default());
+        assert_eq!(unset, base);
+    }
+}
@@ -1,31 +1,115 @@
 //! Unified popup window combining preedit, keypress display, and candidates
 //!
 //! Uses zwp_input_popup_surface_v2 which is automatically positioned near
-//! the text cursor by the compositor.
+//! the text cursor by the compositor, or with `[popup] mode = "corner"` a
+//! zwlr_layer_shell_v1 overlay anchored to a screen corner.
+
+use std::ops::Range;
+use std::os::fd::AsFd;
+use std::sync::Arc;
+use std::time::Duration;
 
 use memmap2::MmapMut;
-use tiny_skia::{Color, Paint, Pixmap, Rect, Transform};
+use tiny_skia::{BlendMode, Color, IntRect, Paint, Pixmap, PixmapPaint, Rect, Transform};
 use wayland_client::QueueHandle;
+use wayland_client::backend::ObjectId;
 use wayland_client::protocol::{wl_buffer, wl_shm, wl_shm_pool, wl_surface};
 use wayland_protocols_misc::zwp_input_method_v2::client::{
     zwp_input_method_v2, zwp_input_popup_surface_v2,
 };
+use wayland_protocols_wlr::layer_shell::v1::client::{zwlr_layer_shell_v1, zwlr_layer_surface_v1};
 
+use super::grapheme;
 pub use super::layout::PopupContent;
 use super::layout::{
-    BG_COLOR, BORDER_COLOR, CURSOR_BG, ICON_SEPARATOR_GAP, ICON_SEPARATOR_WIDTH,
-    KEYPRESS_ENTRY_GAP, KEYPRESS_TEXT_COLOR, Layout, MAX_VISIBLE_CANDIDATES, MODE_GAP,
-    MODE_RECORDING_COLOR, NUMBER_COLOR, NUMBER_WIDTH, PADDING, REC_CIRCLE_RADIUS,
-    REC_CIRCLE_TEXT_GAP, SCROLLBAR_BG, SCROLLBAR_THUMB, SCROLLBAR_WIDTH, SELECTED_BG, TEXT_COLOR,
-    VISUAL_BG, calculate_layout, format_recording_label, mode_label, preedit_scroll_offset, rgba,
-    scrollbar_thumb_geometry,
+    ANNOTATION_ALPHA, ANNOTATION_GAP, CMDLINE_CURSOR_WIDTH, CandidateScroll, ColumnGrid,
+    CursorShape, FURIGANA_SCALE, ICON_SEPARATOR_WIDTH, KEYPRESS_ENTRY_GAP, Layout, PRIVACY_LABEL,
+    REC_CIRCLE_RADIUS, SCROLLBAR_WIDTH, calculate_layout, candidate_at, candidate_window,
+    cursor_rect, cursor_shape, fit_text, format_recording_label, mode_label, popup_above_cursor,
+    preedit_scroll_offset, rgba, scrollbar_thumb_geometry, stable_width,
 };
-use super::text_render::{TextRenderer, copy_pixmap_to_shm, create_shm_pool, draw_border};
+use super::text_render::{TextRenderer, changed_rows, copy_pixmap_to_shm, draw_border};
+use super::theme::Theme;
 use crate::State;
-use crate::neovim::VisualSelection;
+use crate::config::PopupCorner;
+use crate::metrics::{self, Stage};
+use crate::neovim::{SegmentKind, VisualSelection};
+use crate::quirks::PopupHide;
+use crate::state::SeatId;
+use crate::trace::{self, Span};
+use crate::wakeups;
+
+/// Layer-shell namespace of the corner popup
+const LAYER_NAMESPACE: &str = "jacin-popup";
+
+/// Pixels the candidate list slides up into place (`animation.slide`)
+const SLIDE_DISTANCE: f32 = 12.0;
+
+/// Largest shm pool a popup grows to (two 2560×1600 buffers, with room)
+const MAX_POOL_SIZE: usize = 64 << 20;
+
+/// Shm pool size for double buffering the largest popup `theme` allows
+/// (~2MB with the default 580×450)
+fn pool_size(theme: &Theme) -> usize {
+    let width = (theme.max_width + theme.offset_x) as usize;
+    let height = (theme.max_height + theme.offset_y) as usize;
+    width * height * 4 * 2
+}
+
+/// Create a shared memory pool for Wayland surfaces
+fn create_shm_pool(
+    shm: &wl_shm::WlShm,
+    qh: &QueueHandle<State>,
+    size: usize,
+    name: &str,
+) -> Option<(wl_shm_pool::WlShmPool, MmapMut)> {
+    use std::os::fd::FromRawFd;
+
+    // Create anonymous file with memfd_create
+    let fd = unsafe {
+        let c_name = std::ffi::CString::new(name).ok()?;
+        libc::memfd_create(c_name.as_ptr(), libc::MFD_CLOEXEC)
+    };
+
+    if fd < 0 {
+        log::error!("[SHM] Failed to create memfd for {}", name);
+        return None;
+    }
+
+    let file = unsafe { std::fs::File::from_raw_fd(fd) };
 
-/// Pool size: 600×450×4×2 bytes for double buffering (~2MB)
-const POOL_SIZE: usize = 600 * 450 * 4 * 2;
+    // Set file size
+    if file.set_len(size as u64).is_err() {
+        log::error!("[SHM] Failed to set memfd size for {}", name);
+        return None;
+    }
+
+    // Memory map the file
+    let mmap = unsafe { MmapMut::map_mut(&file) }.ok()?;
+
+    // Create wl_shm_pool
+    let pool = shm.create_pool(file.as_fd(), size as i32, qh, ());
+
+    // Keep file alive by leaking it (pool owns the fd now)
+    std::mem::forget(file);
+
+    Some((pool, mmap))
+}
+
+/// Where the popup surface is placed (`[popup] mode`)
+#[derive(Debug, Clone, Default)]
+pub enum Placement {
+    /// Input popup surface, placed at the text cursor by the compositor. The
+    /// content is drawn `offset_x`/`offset_y` pixels into a transparent frame.
+    #[default]
+    Cursor,
+    /// Overlay layer surface anchored to a screen corner, `offset_x`/`offset_y`
+    /// pixels away from its edges
+    Corner {
+        layer_shell: zwlr_layer_shell_v1::ZwlrLayerShellV1,
+        corner: PopupCorner,
+    },
+}
 
 /// Double buffer state
 struct Buffer {
@@ -35,10 +119,37 @@ struct Buffer {
     height: u32,
 }
 
+/// Pool replaced by a larger one, kept until the compositor releases the
+/// buffers from it that it still shows
+struct RetiredPool {
+    pool: wl_shm_pool::WlShmPool,
+    _data: MmapMut,
+    buffers: Vec<wl_buffer::WlBuffer>,
+}
+
 /// Surface pair: wl_surface + popup role (created/destroyed together)
 struct PopupSurface {
     surface: wl_surface::WlSurface,
-    popup_surface: zwp_input_popup_surface_v2::ZwpInputPopupSurfaceV2,
+    role: SurfaceRole,
+}
+
+enum SurfaceRole {
+    Input(zwp_input_popup_surface_v2::ZwpInputPopupSurfaceV2),
+    /// Buffers may only be attached once the compositor configured it
+    Layer {
+        layer_surface: zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
+        configured: bool,
+    },
+}
+
+impl PopupSurface {
+    fn destroy(self) {
+        match self.role {
+            SurfaceRole::Input(popup_surface) => popup_surface.destroy(),
+            SurfaceRole::Layer { layer_surface, .. } => layer_surface.destroy(),
+        }
+        self.surface.destroy();
+    }
 }
 
 /// Unified popup window
@@ -46,8 +157,12 @@ pub struct UnifiedPopup {
     surfaces: Option<PopupSurface>,
     compositor: wayland_client::protocol::wl_compositor::WlCompositor,
     input_method: zwp_input_method_v2::ZwpInputMethodV2,
+    shm: wl_shm::WlShm,
     pool: wl_shm_pool::WlShmPool,
     pool_data: MmapMut,
+    pool_size: usize,
+    /// Earlier pools still holding buffers on screen
+    retired_pools: Vec<RetiredPool>,
     buffers: [Option<Buffer>; 2],
     current_buffer: usize,
     width: u32,
@@ -55,30 +170,100 @@ pub struct UnifiedPopup {
     pub visible: bool,
     renderer: TextRenderer,
     mono_renderer: TextRenderer,
-    scroll_offset: usize,
+    /// Small renderer for readings above candidates (None unless theme.furigana)
+    furigana_renderer: Option<TextRenderer>,
+    scroll: CandidateScroll,
+    /// Column positions, fixed for the lifetime of the renderers
+    grid: ColumnGrid,
+    theme: Theme,
+    /// Whether hiding keeps the surfaces for reuse (compositor quirk)
+    hide_strategy: PopupHide,
+    /// Whether the surface accepts pointer input (behavior.popup_pointer)
+    pointer_input: bool,
+    placement: Placement,
+    /// Layout of the last render (None while hidden), for pointer hit-testing
+    layout: Option<Layout>,
+    /// Candidates and selection of the last render: the list only scrolls to
+    /// the selection when either changes, so a pointer scroll survives redraws
+    shown_candidates: Arc<[String]>,
+    shown_selected: usize,
+    /// Whole-list index of the first shown candidate (see `PopupContent`)
+    shown_offset: usize,
+    /// Widest popup since the candidate menu opened (0 without one)
+    menu_width: u32,
+    /// Seat whose input method owns this popup (tags buffer release events)
+    seat: SeatId,
+    /// Output the popup was last shown on (wl_surface enter)
+    output: Option<ObjectId>,
+    /// The compositor placed the popup above the text cursor: sections are
+    /// stacked bottom-up to keep the preedit next to it
+    flipped: bool,
+    /// Opacity of the frames presented (fade in/out)
+    opacity: f32,
+    /// Candidate slide-in progress (1.0 = in place)
+    slide: f32,
+    /// Last frame presented, shown again while fading out
+    last_frame: Option<Pixmap>,
+    /// Opacity `last_frame` was presented at
+    presented_opacity: f32,
+    /// What the last render drew: an update that would draw the same again
+    /// is skipped
+    rendered: Option<Rendered>,
+}
+
+/// Inputs of a render
+#[derive(PartialEq)]
+struct Rendered {
+    content: PopupContent,
+    scroll_offset: f32,
+    opacity: f32,
+    slide: f32,
 }
 
 impl UnifiedPopup {
-    /// Create a new unified popup window
+    /// Create a new unified popup window with `theme` (None if no font or shm pool)
     pub fn new(
+        seat: SeatId,
         compositor: &wayland_client::protocol::wl_compositor::WlCompositor,
         input_method: &zwp_input_method_v2::ZwpInputMethodV2,
         shm: &wl_shm::WlShm,
         qh: &QueueHandle<State>,
-        renderer: TextRenderer,
-        mono_renderer: TextRenderer,
+        theme: Theme,
+        hide_strategy: PopupHide,
     ) -> Option<Self> {
-        let surfaces = Self::create_surfaces(compositor, input_method, qh);
+        let (Some(renderer), Some(mut mono_renderer)) = (
+            TextRenderer::new(theme.font_size, theme.font_family.as_deref()),
+            TextRenderer::new_monospace(theme.font_size, theme.mono_family.as_deref()),
+        ) else {
+            log::warn!("Font not available, popup window disabled");
+            return None;
+        };
+        let grid = ColumnGrid::measure(&mut mono_renderer, theme.padding);
+        let furigana_renderer = theme
+            .furigana
+            .then(|| {
+                TextRenderer::new(
+                    theme.font_size * FURIGANA_SCALE,
+                    theme.font_family.as_deref(),
+                )
+            })
+            .flatten();
 
         // Create shm pool for double-buffered rendering
-        let (pool, pool_data) = create_shm_pool(shm, qh, POOL_SIZE, "ime-unified-popup")?;
+        let pool_size = pool_size(&theme);
+        let (pool, pool_data) = create_shm_pool(shm, qh, pool_size, "ime-unified-popup")?;
 
+        // The surfaces are created on the first update, with the placement and
+        // input region set by then
         Some(Self {
-            surfaces: Some(surfaces),
+            surfaces: None,
             compositor: compositor.clone(),
             input_method: input_method.clone(),
+            shm: shm.clone(),
             pool,
             pool_data,
+            pool_size,
+            retired_pools: Vec::new(),
             buffers: [None, None],
             current_buffer: 0,
             width: 200,
@@ -86,29 +271,270 @@ impl UnifiedPopup {
             visible: false,
             renderer,
             mono_renderer,
-            scroll_offset: 0,
+            furigana_renderer,
+            scroll: CandidateScroll::default(),
+            grid,
+            theme,
+            hide_strategy,
+            pointer_input: false,
+            placement: Placement::Cursor,
+            layout: None,
+            shown_candidates: Arc::default(),
+            shown_selected: 0,
+            shown_offset: 0,
+            menu_width: 0,
+            seat,
+            output: None,
+            flipped: false,
+            opacity: 1.0,
+            slide: 1.0,
+            last_frame: None,
+            presented_opacity: 1.0,
+            rendered: None,
         })
     }
 
-    /// Create a new wl_surface + popup_surface pair
-    fn create_surfaces(
-        compositor: &wayland_client::protocol::wl_compositor::WlCompositor,
-        input_method: &zwp_input_method_v2::ZwpInputMethodV2,
-        qh: &QueueHandle<State>,
-    ) -> PopupSurface {
-        let surface = compositor.create_surface(qh, ());
+    /// Let the popup receive pointer events (clicks and scrolling on candidates)
+    pub fn with_pointer_input(mut self, enabled: bool) -> Self {
+        self.pointer_input = enabled;
+        self
+    }
+
+    /// Place the popup at the text cursor or in a screen corner
+    pub fn with_placement(mut self, placement: Placement) -> Self {
+        self.placement = placement;
+        self
+    }
 
-        // Set empty input region so compositor ignores mouse events on the popup.
-        let empty_region = compositor.create_region(qh, ());
-        surface.set_input_region(Some(&empty_region));
-        empty_region.destroy();
+    pub fn output(&self) -> Option<&ObjectId> {
+        self.output.as_ref()
+    }
 
-        let popup_surface = input_method.get_input_popup_surface(&surface, qh, ());
+    pub fn set_output(&mut self, output: Option<ObjectId>) {
+        self.output = output;
+    }
 
-        PopupSurface {
-            surface,
-            popup_surface,
+    /// The compositor reported where the text cursor is relative to the
+    /// popup surface. Returns true if the popup turned out to be flipped
+    /// above it (or back); the caller re-renders then.
+    pub fn set_text_input_rectangle(&mut self, y: i32, height: i32) -> bool {
+        if !matches!(self.placement, Placement::Cursor) {
+            return false;
         }
+        let (_, inset_y) = self.inset();
+        let flipped = popup_above_cursor(y, height, self.height + inset_y);
+        if flipped == self.flipped {
+            return false;
+        }
+        log::debug!(
+            "[POPUP] Popup {} the text cursor",
+            if flipped { "above" } else { "below" }
+        );
+        self.flipped = flipped;
+        self.rendered = None;
+        true
+    }
+
+    /// Opacity and candidate slide-in progress of the next render
+    pub fn set_animation(&mut self, opacity: f32, slide: f32) {
+        self.opacity = opacity.clamp(0.0, 1.0);
+        self.slide = slide.clamp(0.0, 1.0);
+    }
+
+    /// Show the last frame again at `opacity` (fading out: the content that
+    /// was on it is gone). Returns false if there is nothing shown to fade.
+    pub fn fade(&mut self, opacity: f32, qh: &QueueHandle<State>) -> bool {
+        let Some(frame) = self.last_frame.take().filter(|_| self.visible) else {
+            return false;
+        };
+        self.opacity = opacity.clamp(0.0, 1.0);
+        self.present(&frame, qh);
+        self.last_frame = Some(frame);
+        true
+    }
+
+    /// Switch to `theme` (the popup moved to an output with other sizes).
+    /// Returns true if anything changed; the caller re-renders then. Keeps
+    /// the current theme if its fonts or a larger shm pool are not available.
+    pub fn set_theme(&mut self, theme: Theme, qh: &QueueHandle<State>) -> bool {
+        if theme == self.theme {
+            return false;
+        }
+        let (Some(renderer), Some(mut mono_renderer)) = (
+            TextRenderer::new(theme.font_size, theme.font_family.as_deref()),
+            TextRenderer::new_monospace(theme.font_size, theme.mono_family.as_deref()),
+        ) else {
+            log::warn!("[POPUP] Font not available at size {}", theme.font_size);
+            return false;
+        };
+        let size = pool_size(&theme);
+        if size > self.pool_size && !self.grow_pool(size, qh) {
+            return false;
+        }
+        self.grid = ColumnGrid::measure(&mut mono_renderer, theme.padding);
+        self.furigana_renderer = theme
+            .furigana
+            .then(|| {
+                TextRenderer::new(
+                    theme.font_size * FURIGANA_SCALE,
+                    theme.font_family.as_deref(),
+                )
+            })
+            .flatten();
+        self.renderer = renderer;
+        self.mono_renderer = mono_renderer;
+        self.theme = theme;
+        self.rendered = None;
+        true
+    }
+
+    /// Replace the shm pool with one of at least `size` bytes. The next
+    /// render creates its buffers in the new pool; the old one stays until
+    /// the compositor releases the buffers it still shows. Returns false if
+    /// `size` is over the limit or the pool could not be created.
+    fn grow_pool(&mut self, size: usize, qh: &QueueHandle<State>) -> bool {
+        if size > MAX_POOL_SIZE {
+            return false;
+        }
+        // Round up so that a popup growing a row at a time doesn't replace
+        // the pool each time
+        let size = size.next_power_of_two().min(MAX_POOL_SIZE);
+        let Some((pool, data)) = create_shm_pool(&self.shm, qh, size, "ime-unified-popup") else {
+            return false;
+        };
+        let pool = std::mem::replace(&mut self.pool, pool);
+        let data = std::mem::replace(&mut self.pool_data, data);
+        let mut held = Vec::new();
+        for slot in self.buffers.iter_mut().filter_map(Option::take) {
+            if slot.in_use {
+                held.push(slot.buffer);
+            } else {
+                slot.buffer.destroy();
+            }
+        }
+        if held.is_empty() {
+            pool.destroy();
+        } else {
+            self.retired_pools.push(RetiredPool {
+                pool,
+                _data: data,
+                buffers: held,
+            });
+        }
+        log::info!(
+            "[POPUP] Shm pool grown from {} to {} KiB",
+            self.pool_size / 1024,
+            size / 1024
+        );
+        self.pool_size = size;
+        true
+    }
+
+    /// Transparent frame left and above the content (cursor placement offsets)
+    fn inset(&self) -> (u32, u32) {
+        match self.placement {
+            Placement::Cursor => (self.theme.offset_x, self.theme.offset_y),
+            Placement::Corner { .. } => (0, 0),
+        }
+    }
+
+    /// Create a new wl_surface + popup role pair
+    fn create_surfaces(&self, qh: &QueueHandle<State>) -> PopupSurface {
+        let surface = self.compositor.create_surface(qh, self.seat);
+
+        // Without pointer input, set an empty input region so the compositor
+        // ignores mouse events on the popup. With it, leave out the
+        // transparent frame so it doesn't swallow clicks next to the cursor.
+        let (inset_x, inset_y) = self.inset();
+        if !self.pointer_input || inset_x > 0 || inset_y > 0 {
+            let region = self.compositor.create_region(qh, ());
+            if self.pointer_input {
+                region.add(
+                    inset_x as i32,
+                    inset_y as i32,
+                    i32::MAX - inset_x as i32,
+                    i32::MAX - inset_y as i32,
+                );
+            }
+            surface.set_input_region(Some(&region));
+            region.destroy();
+        }
+
+        let role = match &self.placement {
+            Placement::Cursor => SurfaceRole::Input(
+                self.input_method
+                    .get_input_popup_surface(&surface, qh, self.seat),
+            ),
+            Placement::Corner {
+                layer_shell,
+                corner,
+            } => {
+                use zwlr_layer_surface_v1::Anchor;
+                let layer_surface = layer_shell.get_layer_surface(
+                    &surface,
+                    None,
+                    zwlr_layer_shell_v1::Layer::Overlay,
+                    LAYER_NAMESPACE.to_string(),
+                    qh,
+                    self.seat,
+                );
+                let (vertical, horizontal) = match corner {
+                    PopupCorner::TopLeft => (Anchor::Top, Anchor::Left),
+                    PopupCorner::TopRight => (Anchor::Top, Anchor::Right),
+                    PopupCorner::BottomLeft => (Anchor::Bottom, Anchor::Left),
+                    PopupCorner::BottomRight => (Anchor::Bottom, Anchor::Right),
+                };
+                layer_surface.set_anchor(vertical | horizontal);
+                let (x, y) = (self.theme.offset_x as i32, self.theme.offset_y as i32);
+                // Margins: top, right, bottom, left; only the anchored edges apply
+                layer_surface.set_margin(y, x, y, x);
+                layer_surface
+                    .set_keyboard_interactivity(zwlr_layer_surface_v1::KeyboardInteractivity::None);
+                layer_surface.set_size(self.width, self.height);
+                // Initial commit without a buffer; the compositor answers with configure
+                surface.commit();
+                SurfaceRole::Layer {
+                    layer_surface,
+                    configured: false,
+                }
+            }
+        };
+
+        PopupSurface { surface, role }
+    }
+
+    /// The compositor configured the corner popup (layer surface `serial`).
+    /// Returns true if the popup has content waiting to be drawn.
+    pub fn layer_configured(&mut self, serial: u32) -> bool {
+        let Some(PopupSurface {
+            role:
+                SurfaceRole::Layer {
+                    layer_surface,
+                    configured,
+                },
+            ..
+        }) = self.surfaces.as_mut()
+        else {
+            return false;
+        };
+        layer_surface.ack_configure(serial);
+        let first = !std::mem::replace(configured, true);
+        if first {
+            // The update before this drew nothing
+            self.rendered = None;
+        }
+        first
+    }
+
+    /// The compositor closed the corner popup (e.g. its output went away); a
+    /// new one is created on the next update
+    pub fn layer_closed(&mut self) {
+        if let Some(s) = self.surfaces.take() {
+            s.destroy();
+        }
+        self.visible = false;
+        self.last_frame = None;
+        self.rendered = None;
     }
 
     /// Update the popup with new content
@@ -118,62 +544,196 @@ impl UnifiedPopup {
             return;
         }
 
-        // Recreate surface pair if it was destroyed on hide
+        // Create the surface pair (first update, or destroyed on hide)
         if self.surfaces.is_none() {
-            self.surfaces = Some(Self::create_surfaces(
-                &self.compositor,
-                &self.input_method,
-                qh,
-            ));
+            self.surfaces = Some(self.create_surfaces(qh));
         }
 
-        // Adjust scroll offset to keep selection visible
-        if !content.candidates.is_empty() {
-            let visible_count = MAX_VISIBLE_CANDIDATES.min(content.candidates.len());
-            if content.selected < self.scroll_offset {
-                self.scroll_offset = content.selected;
-            } else if content.selected >= self.scroll_offset + visible_count {
-                self.scroll_offset = content.selected - visible_count + 1;
-            }
-        } else {
-            self.scroll_offset = 0;
+        // Adjust scroll offset to keep a new selection visible
+        if content.candidates.is_empty() {
+            self.scroll.reset();
+        } else if !Arc::ptr_eq(&content.candidates, &self.shown_candidates)
+            || content.selected != self.shown_selected
+        {
+            // Another window of a long list: the rows in view stay in place
+            self.scroll.offset += self.shown_offset as f32 - content.candidate_offset as f32;
+            let total_count = content.candidates.len();
+            let visible_count = self.theme.max_candidates.min(total_count);
+            self.scroll
+                .ensure_visible(content.selected, visible_count, total_count);
+        }
+        self.shown_candidates = content.candidates.clone();
+        self.shown_selected = content.selected;
+        self.shown_offset = content.candidate_offset;
+
+        // Nothing to do if the frame shown was drawn from the same content
+        // (e.g. a snapshot that only moved Neovim's cursor)
+        let rendered = Rendered {
+            content: content.clone(),
+            scroll_offset: self.scroll.offset,
+            opacity: self.opacity,
+            slide: self.slide,
+        };
+        if self.visible && self.rendered.as_ref() == Some(&rendered) {
+            log::trace!("[POPUP] Content unchanged, render skipped");
+            return;
         }
 
         // Calculate layout and size
-        let layout = calculate_layout(content, &mut self.renderer, &mut self.mono_renderer);
+        let mut layout = calculate_layout(
+            content,
+            self.scroll.offset,
+            &self.grid,
+            &self.theme,
+            &mut self.renderer,
+            &mut self.mono_renderer,
+            self.furigana_renderer.as_mut(),
+        );
+        layout.width = stable_width(layout.width, layout.has_candidates, &mut self.menu_width);
+        // Keep the preedit next to the text cursor
+        if self.flipped {
+            layout.invert(
+                self.theme.padding,
+                self.renderer.line_height(),
+                self.mono_renderer.line_height(),
+            );
+        }
         self.width = layout.width;
         self.height = layout.height;
 
         // Render
         self.render(content, &layout, qh);
+        self.layout = Some(layout);
         self.visible = true;
+        self.rendered = Some(rendered);
+    }
+
+    /// Whether `surface` is this popup's surface (pointer focus)
+    pub fn owns_surface(&self, surface: &wl_surface::WlSurface) -> bool {
+        self.surfaces
+            .as_ref()
+            .is_some_and(|s| s.surface == *surface)
+    }
+
+    /// Candidate under the popup-local point (`x`, `y`)
+    pub fn candidate_at(&self, x: f32, y: f32) -> Option<usize> {
+        let layout = self.layout.as_ref()?;
+        let (inset_x, inset_y) = self.inset();
+        candidate_at(
+            layout,
+            self.scroll.offset,
+            self.shown_candidates.len(),
+            x - inset_x as f32,
+            y - inset_y as f32,
+        )
+    }
+
+    /// Height of a candidate row in pixels (None without candidates)
+    pub fn candidate_row_height(&self) -> Option<f32> {
+        self.layout
+            .as_ref()
+            .filter(|l| l.has_candidates)
+            .map(|l| l.row_height)
+    }
+
+    fn candidate_counts(&self) -> (usize, usize) {
+        let total_count = self.shown_candidates.len();
+        (self.theme.max_candidates.min(total_count), total_count)
+    }
+
+    /// Scroll the candidate list by `rows`, stopping any fling. Returns true if it moved.
+    pub fn scroll_candidates(&mut self, rows: f32) -> bool {
+        let (visible_count, total_count) = self.candidate_counts();
+        self.scroll.fling(0.0);
+        self.scroll.scroll_by(rows, visible_count, total_count)
+    }
+
+    /// Start a kinetic scroll of the candidate list (rows per second)
+    pub fn fling_candidates(&mut self, velocity: f32) {
+        self.scroll.fling(velocity);
+    }
+
+    /// Whether a kinetic scroll is running (drives the scroll timer)
+    pub fn is_scroll_animating(&self) -> bool {
+        self.visible && self.scroll.is_animating()
+    }
+
+    /// Advance a kinetic scroll by `dt`. Returns true if the list moved.
+    pub fn tick_scroll(&mut self, dt: Duration) -> bool {
+        let (visible_count, total_count) = self.candidate_counts();
+        self.scroll.tick(dt, visible_count, total_count)
+    }
+
+    /// Index of the candidate in the top visible row (numbered "1.")
+    pub fn candidate_page_start(&self) -> usize {
+        self.scroll.first_row()
+    }
+
+    /// Turn the candidate list by `pages` (negative = up). Returns the new top row.
+    pub fn page_candidates(&mut self, pages: i32, total_count: usize) -> usize {
+        self.scroll.page(
+            pages,
+            self.theme.max_candidates.min(total_count),
+            total_count,
+        )
     }
 
     /// Hide the popup
     pub fn hide(&mut self) {
         if self.visible {
-            // First unmap the surface for immediate visual feedback, then
-            // destroy both the popup surface role and wl_surface so the
-            // compositor stops tracking them for hit-testing. Without the
-            // destroy, the unmapped popup surface can absorb pointer clicks
-            // and prevent refocusing text fields. Both are recreated on
-            // next update().
-            if let Some(s) = self.surfaces.take() {
+            // Unmap the surface for immediate visual feedback. With
+            // `PopupHide::Destroy`, also destroy the popup surface role and
+            // wl_surface so the compositor stops tracking them for hit-testing:
+            // on some compositors the unmapped popup surface absorbs pointer
+            // clicks and prevents refocusing text fields. Destroyed surfaces
+            // are recreated on next update(). An unmapped layer surface has
+            // to be configured again before it can be shown, so the corner
+            // popup is always destroyed.
+            if let Some(ref s) = self.surfaces {
                 s.surface.attach(None, 0, 0);
                 s.surface.commit();
-                s.popup_surface.destroy();
-                s.surface.destroy();
+            }
+            let destroy = self.hide_strategy == PopupHide::Destroy
+                || matches!(self.placement, Placement::Corner { .. });
+            if destroy && let Some(s) = self.surfaces.take() {
+                s.destroy();
             }
             self.visible = false;
-            self.scroll_offset = 0;
+            self.scroll.reset();
+            self.layout = None;
+            self.last_frame = None;
+            self.rendered = None;
+            self.opacity = 1.0;
+            self.shown_candidates = Arc::default();
+            self.shown_selected = 0;
+            self.shown_offset = 0;
+            self.menu_width = 0;
         }
     }
 
-    /// Mark a buffer as released (called from Dispatch)
-    pub fn buffer_released(&mut self, buffer_idx: usize) {
-        if let Some(buf) = self.buffers[buffer_idx].as_mut() {
-            buf.in_use = false;
+    /// Mark a buffer as released (called from Dispatch). A buffer from a
+    /// retired pool is destroyed, and the pool with its last buffer.
+    pub fn buffer_released(&mut self, buffer: &wl_buffer::WlBuffer) {
+        if let Some(slot) = self
+            .buffers
+            .iter_mut()
+            .flatten()
+            .find(|b| b.buffer == *buffer)
+        {
+            slot.in_use = false;
+            return;
         }
+        for retired in &mut self.retired_pools {
+            if let Some(i) = retired.buffers.iter().position(|b| b == buffer) {
+                retired.buffers.swap_remove(i).destroy();
+            }
+        }
+        self.retired_pools.retain(|retired| {
+            if retired.buffers.is_empty() {
+                retired.pool.destroy();
+            }
+            !retired.buffers.is_empty()
+        });
     }
 
     /// Destroy the window
@@ -182,29 +742,53 @@ impl UnifiedPopup {
             slot.buffer.destroy();
         }
         if let Some(s) = self.surfaces {
-            s.popup_surface.destroy();
-            s.surface.destroy();
+            s.destroy();
         }
         self.pool.destroy();
+        for retired in self.retired_pools {
+            retired
+                .buffers
+                .iter()
+                .for_each(wl_buffer::WlBuffer::destroy);
+            retired.pool.destroy();
+        }
     }
 
     /// Render the popup content
     fn render(&mut self, content: &PopupContent, layout: &Layout, qh: &QueueHandle<State>) {
         let _perf_start = std::time::Instant::now();
-        let buffer_size = (self.width * self.height * 4) as usize;
-        if buffer_size * 2 > POOL_SIZE {
+        let _trace = trace::span(Span::Render);
+        let _metrics = metrics::timer(Stage::Render);
+        wakeups::rendered();
+        let (inset_x, inset_y) = self.inset();
+        let (surface_width, surface_height) = (self.width + inset_x, self.height + inset_y);
+
+        // A layer surface takes no buffer before its first configure, which
+        // redraws the popup
+        if let Some(PopupSurface {
+            surface,
+            role:
+                SurfaceRole::Layer {
+                    layer_surface,
+                    configured: false,
+                },
+        }) = &self.surfaces
+        {
+            layer_surface.set_size(surface_width, surface_height);
+            surface.commit();
+            return;
+        }
+
+        let buffer_size = (surface_width * surface_height * 4) as usize;
+        if buffer_size * 2 > self.pool_size && !self.grow_pool(buffer_size * 2, qh) {
             log::warn!(
-                "[POPUP] Buffer too large ({}x{}), skipping render",
-                self.width,
-                self.height
+                "[POPUP] Buffer too large ({}x{}) for the shm pool limit, skipping render",
+                surface_width,
+                surface_height
             );
             return;
         }
 
-        // Find available buffer slot
-        let buffer_idx = self.find_available_buffer();
-        let offset = buffer_idx * buffer_size;
-
         // Create pixmap
         let Some(mut pixmap) = Pixmap::new(self.width, self.height) else {
             log::warn!(
@@ -216,49 +800,123 @@ impl UnifiedPopup {
         };
 
         // Background
-        pixmap.fill(rgba(BG_COLOR));
+        pixmap.fill(rgba(self.theme.background));
 
         // Border
-        draw_border(&mut pixmap, self.width, self.height, rgba(BORDER_COLOR));
+        draw_border(
+            &mut pixmap,
+            self.width,
+            self.height,
+            self.theme.border_width,
+            rgba(self.theme.border),
+        );
 
         // Render sections
-        if layout.has_preedit {
-            if !content.preedit.is_empty() {
-                self.render_preedit_section(&mut pixmap, content, layout, PADDING);
-            }
-
-            // Draw separator below preedit if more sections follow
-            if layout.has_keypress || layout.has_candidates || layout.has_transient_message {
-                let line_height = self.renderer.line_height();
-                let sep_y = layout.preedit_y + line_height;
-                if let Some(rect) =
-                    Rect::from_xywh(PADDING, sep_y, self.width as f32 - PADDING * 2.0, 1.0)
-                {
-                    let mut paint = Paint::default();
-                    paint.set_color(rgba(BORDER_COLOR));
-                    pixmap.fill_rect(rect, &paint, Transform::identity(), None);
-                }
-            }
+        if layout.has_preedit && !content.preedit.is_empty() {
+            self.render_preedit_section(&mut pixmap, content, layout, self.theme.padding);
         }
 
         if layout.has_keypress {
             self.render_keypress_section(&mut pixmap, content, layout);
         }
 
+        if layout.has_macro {
+            self.render_macro_row(&mut pixmap, content, layout);
+        }
+
         if layout.has_candidates {
             self.render_candidate_section(&mut pixmap, content, layout);
         } else if layout.has_transient_message {
             self.render_transient_message(&mut pixmap, content, layout);
         }
 
+        if layout.has_debug {
+            self.render_debug_line(&mut pixmap, content, layout);
+        }
+
+        // Lines between the sections
+        let mut paint = Paint::default();
+        paint.set_color(rgba(self.theme.border));
+        for &sep_y in &layout.separators {
+            if let Some(rect) = self.separator_rect(sep_y) {
+                pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+            }
+        }
+
+        if layout.has_candidates && self.slide < 1.0 {
+            self.slide_candidates(&mut pixmap, layout);
+        }
+
+        // Offset the content inside a transparent frame
+        if inset_x > 0 || inset_y > 0 {
+            let Some(mut framed) = Pixmap::new(surface_width, surface_height) else {
+                return;
+            };
+            framed.draw_pixmap(
+                inset_x as i32,
+                inset_y as i32,
+                pixmap.as_ref(),
+                &PixmapPaint::default(),
+                Transform::identity(),
+                None,
+            );
+            pixmap = framed;
+        }
+
+        self.present(&pixmap, qh);
+        self.last_frame = Some(pixmap);
+        log::trace!(
+            "[PERF] render: {:.2}ms ({}x{})",
+            _perf_start.elapsed().as_secs_f64() * 1000.0,
+            self.width,
+            self.height
+        );
+    }
+
+    /// Copy `frame` (the whole surface), faded to the popup's opacity, to a
+    /// free buffer and commit it
+    fn present(&mut self, frame: &Pixmap, qh: &QueueHandle<State>) {
+        let (surface_width, surface_height) = (frame.width(), frame.height());
+
+        // Damage only the rows that differ from the frame on screen (moving
+        // the selection touches two candidate rows); an identical frame is
+        // not committed at all
+        let damage = match self.last_frame {
+            Some(ref previous) if self.presented_opacity == self.opacity => {
+                changed_rows(previous, frame)
+            }
+            _ => vec![Range {
+                start: 0,
+                end: surface_height,
+            }],
+        };
+        if damage.is_empty() {
+            return;
+        }
+        let buffer_size = (surface_width * surface_height * 4) as usize;
+        let buffer_idx = self.find_available_buffer();
+        let offset = buffer_idx * buffer_size;
+
         // Copy to SHM buffer
         let dest = &mut self.pool_data[offset..offset + buffer_size];
-        copy_pixmap_to_shm(&pixmap, dest);
+        if self.opacity < 1.0 {
+            let Some(mut faded) = Pixmap::new(surface_width, surface_height) else {
+                return;
+            };
+            let paint = PixmapPaint {
+                opacity: self.opacity,
+                ..PixmapPaint::default()
+            };
+            faded.draw_pixmap(0, 0, frame.as_ref(), &paint, Transform::identity(), None);
+            copy_pixmap_to_shm(&faded, dest);
+        } else {
+            copy_pixmap_to_shm(frame, dest);
+        }
 
         // Get or create wl_buffer for this slot (reuse if dimensions match)
         let needs_new_buffer = match &self.buffers[buffer_idx] {
             None => true,
-            Some(buf) => buf.width != self.width || buf.height != self.height,
+            Some(buf) => buf.width != surface_width || buf.height != surface_height,
         };
         if needs_new_buffer {
             if let Some(old) = self.buffers[buffer_idx].take() {
@@ -266,18 +924,18 @@ impl UnifiedPopup {
             }
             let buffer = self.pool.create_buffer(
                 offset as i32,
-                self.width as i32,
-                self.height as i32,
-                (self.width * 4) as i32,
+                surface_width as i32,
+                surface_height as i32,
+                (surface_width * 4) as i32,
                 wl_shm::Format::Argb8888,
                 qh,
-                buffer_idx,
+                (self.seat, buffer_idx),
             );
             self.buffers[buffer_idx] = Some(Buffer {
                 buffer,
                 in_use: true,
-                width: self.width,
-                height: self.height,
+                width: surface_width,
+                height: surface_height,
             });
         } else {
             self.buffers[buffer_idx].as_mut().unwrap().in_use = true;
@@ -287,18 +945,78 @@ impl UnifiedPopup {
         let Some(ref s) = self.surfaces else {
             return;
         };
+        if let SurfaceRole::Layer { layer_surface, .. } = &s.role {
+            layer_surface.set_size(surface_width, surface_height);
+        }
         let buffer = &self.buffers[buffer_idx].as_ref().unwrap().buffer;
         s.surface.attach(Some(buffer), 0, 0);
-        s.surface
-            .damage_buffer(0, 0, self.width as i32, self.height as i32);
+        for rows in damage {
+            s.surface.damage_buffer(
+                0,
+                rows.start as i32,
+                surface_width as i32,
+                rows.len() as i32,
+            );
+        }
         s.surface.commit();
 
         self.current_buffer = buffer_idx;
-        log::trace!(
-            "[PERF] render: {:.2}ms ({}x{})",
-            _perf_start.elapsed().as_secs_f64() * 1000.0,
+        self.presented_opacity = self.opacity;
+    }
+
+    /// Move the candidate section (and the rows below it) down by the part of
+    /// the slide-in still to go, faded to match
+    fn slide_candidates(&self, pixmap: &mut Pixmap, layout: &Layout) {
+        let border = self.theme.border_width.ceil() as u32;
+        // The candidates and what follows them: to the bottom, or from the
+        // top when the sections are stacked bottom-up
+        let (top, bottom) = if layout.inverted {
+            let end = layout.candidates_y + layout.visible_count as f32 * layout.row_height;
+            (border, end.ceil() as u32)
+        } else {
+            (
+                layout.candidates_y as u32,
+                self.height.saturating_sub(border),
+            )
+        };
+        let Some(rect) = IntRect::from_xywh(
+            border as i32,
+            top as i32,
+            self.width.saturating_sub(2 * border),
+            bottom.saturating_sub(top),
+        ) else {
+            return;
+        };
+        let Some(section) = pixmap.clone_rect(rect) else {
+            return;
+        };
+        let mut paint = Paint::default();
+        paint.set_color(rgba(self.theme.background));
+        paint.blend_mode = BlendMode::Source;
+        pixmap.fill_rect(rect.to_rect(), &paint, Transform::identity(), None);
+
+        // Towards the preedit
+        let offset = ((1.0 - self.slide) * SLIDE_DISTANCE).round() as i32;
+        let offset = if layout.inverted { -offset } else { offset };
+        let section_paint = PixmapPaint {
+            opacity: self.slide,
+            ..PixmapPaint::default()
+        };
+        pixmap.draw_pixmap(
+            rect.x(),
+            rect.y() + offset,
+            section.as_ref(),
+            &section_paint,
+            Transform::identity(),
+            None,
+        );
+        // The shifted rows run over the border
+        draw_border(
+            pixmap,
             self.width,
-            self.height
+            self.height,
+            self.theme.border_width,
+            rgba(self.theme.border),
         );
     }
 
@@ -310,20 +1028,14 @@ impl UnifiedPopup {
         layout: &Layout,
         preedit_left: f32,
     ) {
-        let text_color = rgba(TEXT_COLOR);
-        let cursor_bg = rgba(CURSOR_BG);
+        let text_color = rgba(self.theme.text);
+        let cursor_bg = rgba(self.theme.cursor);
+        let padding = self.theme.padding;
         let line_height = self.renderer.line_height();
         let y_baseline = layout.preedit_y + line_height * 0.75;
 
-        // Convert byte offsets to character positions
-        let chars: Vec<char> = content.preedit.chars().collect();
-        let mut byte_to_char: Vec<usize> = Vec::with_capacity(content.preedit.len() + 1);
-        for (i, c) in chars.iter().enumerate() {
-            for _ in 0..c.len_utf8() {
-                byte_to_char.push(i);
-            }
-        }
-        byte_to_char.push(chars.len());
+        // Convert byte offsets to character (grapheme cluster) positions
+        let (byte_to_char, chars) = grapheme::byte_to_cluster(&content.preedit);
 
         let cursor_char_begin = byte_to_char.get(content.cursor_begin).copied().unwrap_or(0);
         let cursor_char_end = byte_to_char
@@ -331,24 +1043,19 @@ impl UnifiedPopup {
             .copied()
             .unwrap_or(chars.len());
 
-        let is_normal_mode = content.vim_mode == "n"
-            || content.vim_mode == "v"
-            || content.vim_mode == "V"
-            || content.vim_mode == "\x16"
-            || content.vim_mode.starts_with('v');
+        let shape = cursor_shape(&content.vim_mode);
 
-        // Calculate character positions (absolute, starting from preedit_left)
-        let mut char_x_positions: Vec<f32> = Vec::with_capacity(chars.len() + 1);
-        let mut x = preedit_left;
-        for c in &chars {
-            char_x_positions.push(x);
-            x += self.renderer.measure_text(&c.to_string());
-        }
-        char_x_positions.push(x);
+        // Character positions from the shaped text (absolute, starting from preedit_left)
+        let char_x_positions: Vec<f32> = self
+            .renderer
+            .cluster_offsets(&content.preedit)
+            .into_iter()
+            .map(|x| preedit_left + x)
+            .collect();
 
         // Calculate total text width and visible area
-        let total_text_width = x - preedit_left;
-        let visible_width = layout.width as f32 - PADDING - preedit_left;
+        let total_text_width = char_x_positions[chars.len()] - preedit_left;
+        let visible_width = layout.width as f32 - padding - preedit_left;
 
         // Calculate scroll offset to keep cursor visible
         let cursor_x = char_x_positions
@@ -358,22 +1065,53 @@ impl UnifiedPopup {
         let cursor_rel = cursor_x - preedit_left;
         let scroll_offset = preedit_scroll_offset(total_text_width, visible_width, cursor_rel);
 
-        if is_normal_mode && cursor_char_begin <= chars.len() {
-            // Convert visual selection byte offsets to char positions
-            let visual_char_range = match &content.visual_selection {
+        // Search matches (behind the visual selection and the cursor)
+        if !content.preedit_highlights.is_empty() {
+            let mut paint = Paint::default();
+            paint.set_color(rgba(self.theme.search));
+            let char_x = |byte: usize| {
+                let index = byte_to_char.get(byte).copied().unwrap_or(chars.len());
+                char_x_positions[index.min(chars.len())] - scroll_offset
+            };
+            for &(begin, end) in &content.preedit_highlights {
+                let (x_start, x_end) = (char_x(begin), char_x(end));
+                if let Some(rect) =
+                    Rect::from_xywh(x_start, layout.preedit_y, x_end - x_start, line_height)
+                {
+                    pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+                }
+            }
+        }
+
+        if shape != CursorShape::Bar && cursor_char_begin <= chars.len() {
+            // Convert visual selection byte offsets to x positions
+            let char_x = |byte: usize, default: usize| {
+                let index = byte_to_char.get(byte).copied().unwrap_or(default);
+                char_x_positions[index.min(chars.len())] - scroll_offset
+            };
+            let visual_x_range = match &content.visual_selection {
                 Some(VisualSelection::Charwise { begin, end }) => {
-                    let vbegin = byte_to_char.get(*begin).copied().unwrap_or(0);
-                    let vend = byte_to_char.get(*end).copied().unwrap_or(chars.len());
-                    Some((vbegin, vend))
+                    Some((char_x(*begin, 0), char_x(*end, chars.len())))
+                }
+                // Full row, also past the end of the text
+                Some(VisualSelection::Linewise) => {
+                    Some((preedit_left, layout.width as f32 - padding))
                 }
+                // Columns past the end of the line are as wide as a space
+                Some(VisualSelection::Blockwise {
+                    begin,
+                    end,
+                    overhang,
+                }) => Some((
+                    char_x(*begin, 0),
+                    char_x(*end, chars.len()) + *overhang as f32 * self.renderer.measure_text(" "),
+                )),
                 None => None,
             };
 
             // Draw visual selection background (behind cursor)
-            if let Some((vbegin, vend)) = visual_char_range {
-                let visual_bg = rgba(VISUAL_BG);
-                let vx_start = char_x_positions[vbegin] - scroll_offset;
-                let vx_end = char_x_positions[vend.min(chars.len())] - scroll_offset;
+            if let Some((vx_start, vx_end)) = visual_x_range {
+                let visual_bg = rgba(self.theme.visual);
                 if let Some(rect) =
                     Rect::from_xywh(vx_start, layout.preedit_y, vx_end - vx_start, line_height)
                 {
@@ -383,60 +1121,91 @@ impl UnifiedPopup {
                 }
             }
 
-            // Block cursor (drawn on top of visual selection)
+            // Cursor (drawn on top of visual selection) over the character
+            // after an insert-style position (replace mode)
+            let cell_end = if cursor_char_end > cursor_char_begin {
+                cursor_char_end
+            } else {
+                cursor_char_begin + 1
+            };
             let x_start = char_x_positions[cursor_char_begin] - scroll_offset;
-            let x_end = char_x_positions[cursor_char_end.min(chars.len())] - scroll_offset;
+            let x_end = char_x_positions[cell_end.min(chars.len())] - scroll_offset;
             let cursor_width = (x_end - x_start).max(self.renderer.measure_text(" "));
 
-            if let Some(rect) =
-                Rect::from_xywh(x_start, layout.preedit_y, cursor_width, line_height)
-            {
+            let (x, y, w, h) =
+                cursor_rect(shape, x_start, layout.preedit_y, cursor_width, line_height);
+            if let Some(rect) = Rect::from_xywh(x, y, w, h) {
                 let mut paint = Paint::default();
                 paint.set_color(cursor_bg);
                 pixmap.fill_rect(rect, &paint, Transform::identity(), None);
             }
 
-            // Draw text - cursor chars dark, visual chars light on VISUAL_BG, others normal
+            // Draw text - chars under a block cursor dark, visual chars light on
+            // the visual color, others normal
             let cursor_text_color = Color::from_rgba8(40, 44, 52, 255);
             for (i, c) in chars.iter().enumerate() {
                 let char_x = char_x_positions[i] - scroll_offset;
-                let char_width = self.renderer.measure_text(&c.to_string());
+                let char_width = char_x_positions[i + 1] - char_x_positions[i];
 
                 // Skip characters outside visible area
-                if char_x + char_width < preedit_left || char_x > layout.width as f32 - PADDING {
+                if char_x + char_width < preedit_left || char_x > layout.width as f32 - padding {
                     continue;
                 }
 
-                let color = if i >= cursor_char_begin && i < cursor_char_end {
-                    cursor_text_color
-                } else {
-                    text_color
-                };
+                let color =
+                    if shape == CursorShape::Block && i >= cursor_char_begin && i < cursor_char_end
+                    {
+                        cursor_text_color
+                    } else {
+                        text_color
+                    };
                 self.renderer
-                    .draw_text(pixmap, &c.to_string(), char_x, y_baseline, color);
+                    .draw_text(pixmap, c, char_x, y_baseline, color);
             }
         } else {
             // Insert mode - draw text then line cursor
             // Draw characters individually to handle scrolling
             for (i, c) in chars.iter().enumerate() {
                 let char_x = char_x_positions[i] - scroll_offset;
-                let char_width = self.renderer.measure_text(&c.to_string());
+                let char_width = char_x_positions[i + 1] - char_x_positions[i];
 
                 // Skip characters outside visible area
-                if char_x + char_width < preedit_left || char_x > layout.width as f32 - PADDING {
+                if char_x + char_width < preedit_left || char_x > layout.width as f32 - padding {
                     continue;
                 }
 
                 self.renderer
-                    .draw_text(pixmap, &c.to_string(), char_x, y_baseline, text_color);
+                    .draw_text(pixmap, c, char_x, y_baseline, text_color);
+            }
+
+            // Underline the conversion segment: thin while the reading is
+            // typed, thick once it is converted
+            if let Some(segment) = content.preedit_segment {
+                let char_x = |byte: usize| {
+                    let index = byte_to_char.get(byte).copied().unwrap_or(chars.len());
+                    char_x_positions[index.min(chars.len())] - scroll_offset
+                };
+                let thickness = match segment.kind {
+                    SegmentKind::Reading => 1.0,
+                    SegmentKind::Converted => 2.0,
+                };
+                let x_start = char_x(segment.begin).max(preedit_left);
+                let x_end = char_x(content.cursor_begin).min(layout.width as f32 - padding);
+                let y = layout.preedit_y + line_height - thickness - 1.0;
+                if let Some(rect) = Rect::from_xywh(x_start, y, x_end - x_start, thickness) {
+                    let mut paint = Paint::default();
+                    paint.set_color(text_color);
+                    pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+                }
             }
 
             // Draw line cursor
             let cursor_draw_x = cursor_x - scroll_offset;
             if cursor_draw_x >= preedit_left
-                && cursor_draw_x <= layout.width as f32 - PADDING
-                && let Some(rect) =
-                    Rect::from_xywh(cursor_draw_x, layout.preedit_y, 2.0, line_height)
+                && cursor_draw_x <= layout.width as f32 - padding
+                && let (x, y, w, h) =
+                    cursor_rect(shape, cursor_draw_x, layout.preedit_y, 0.0, line_height)
+                && let Some(rect) = Rect::from_xywh(x, y, w, h)
             {
                 let mut paint = Paint::default();
                 paint.set_color(text_color);
@@ -455,17 +1224,19 @@ impl UnifiedPopup {
         let line_height = self.renderer.line_height();
         let y_baseline = layout.keypress_y + line_height * 0.75;
 
-        // Draw mode label using monospace font
-        let (mode_text, mode_color) = mode_label(&content.vim_mode);
-        let mode_x = PADDING;
-        self.mono_renderer
-            .draw_text(pixmap, mode_text, mode_x, y_baseline, rgba(mode_color));
+        // Draw mode label using monospace font, in the fixed-width mode column
+        let (mode_text, mode_color) = mode_label(&content.vim_mode, &self.theme);
+        self.mono_renderer.draw_text(
+            pixmap,
+            mode_text,
+            self.grid.mode_x(),
+            y_baseline,
+            rgba(mode_color),
+        );
 
         // Draw recording indicator if active
-        let mode_text_width = self.mono_renderer.measure_text(mode_text);
-        let mut after_mode_x = mode_x + mode_text_width;
         if !content.recording.is_empty() {
-            let rec_x = after_mode_x + MODE_GAP;
+            let rec_x = self.grid.rec_x();
 
             // Draw red filled circle (hidden during blink-off phase)
             let circle_cy = layout.keypress_y + line_height * 0.5;
@@ -476,76 +1247,110 @@ impl UnifiedPopup {
                     circle_cx,
                     circle_cy,
                     REC_CIRCLE_RADIUS,
-                    rgba(MODE_RECORDING_COLOR),
+                    rgba(self.theme.recording),
                 );
             }
 
             // Draw @reg text using monospace font
             let rec_label = format_recording_label(&content.recording);
-            let text_x = rec_x + REC_CIRCLE_RADIUS * 2.0 + REC_CIRCLE_TEXT_GAP;
             self.mono_renderer.draw_text(
                 pixmap,
                 &rec_label,
-                text_x,
+                self.grid.rec_text_x(),
+                y_baseline,
+                rgba(self.theme.recording),
+            );
+        }
+
+        // Draw undo depth / dot-repeat indicator
+        if let Some(history) = content.history_label() {
+            self.mono_renderer.draw_text(
+                pixmap,
+                &history,
+                self.grid.history_x(content),
                 y_baseline,
-                rgba(MODE_RECORDING_COLOR),
+                rgba(self.theme.number),
+            );
+        }
+
+        // Draw privacy badge if active
+        if content.privacy {
+            self.mono_renderer.draw_text(
+                pixmap,
+                PRIVACY_LABEL,
+                self.grid.privacy_x(content),
+                y_baseline,
+                rgba(self.theme.recording),
+            );
+        }
+
+        // Draw the latency indicator at the right end of the row
+        if let Some(ref latency) = content.latency {
+            let x =
+                self.width as f32 - self.theme.padding - self.mono_renderer.measure_text(latency);
+            self.mono_renderer.draw_text(
+                pixmap,
+                latency,
+                x.round(),
+                y_baseline,
+                rgba(self.theme.keypress),
             );
-            after_mode_x = text_x + self.mono_renderer.measure_text(&rec_label);
         }
 
         // Draw vertical separator
-        let sep_x = after_mode_x + ICON_SEPARATOR_GAP;
+        let sep_x = self.grid.separator_x(content);
         if let Some(rect) =
             Rect::from_xywh(sep_x, layout.keypress_y, ICON_SEPARATOR_WIDTH, line_height)
         {
             let mut paint = Paint::default();
-            paint.set_color(rgba(BORDER_COLOR));
+            paint.set_color(rgba(self.theme.border));
             pixmap.fill_rect(rect, &paint, Transform::identity(), None);
         }
 
         // Draw keypress entries with gap between each (hidden when candidates are shown,
         // matching calculate_layout which excludes keypress text width)
         if !content.keypress_entries.is_empty() && !layout.has_candidates {
-            if let Some(cursor_byte) = content.cmdline_cursor_pos {
+            if let (true, Some(prompt), Some(cursor_byte)) = (
+                layout.has_prompt,
+                content.cmdline_prompt.as_deref(),
+                content.cmdline_cursor_pos,
+            ) {
+                // input() prompt: the prompt here, the text on the row below
+                self.mono_renderer.draw_text(
+                    pixmap,
+                    prompt,
+                    layout.keypress_icon_width,
+                    y_baseline,
+                    rgba(self.theme.number),
+                );
+                self.render_prompt_text(pixmap, &content.keypress_entries[0], cursor_byte, layout);
+            } else if let Some(cursor_byte) = content.cmdline_cursor_pos {
                 // Command-line mode: render single entry char-by-char with line cursor
                 let text = &content.keypress_entries[0];
                 let text_left = layout.keypress_icon_width;
-                let text_color = rgba(KEYPRESS_TEXT_COLOR);
+                let text_color = rgba(self.theme.keypress);
 
-                // Build byte-to-char mapping
-                let chars: Vec<char> = text.chars().collect();
-                let mut byte_to_char: Vec<usize> = Vec::with_capacity(text.len() + 1);
-                for (i, c) in chars.iter().enumerate() {
-                    for _ in 0..c.len_utf8() {
-                        byte_to_char.push(i);
-                    }
-                }
-                byte_to_char.push(chars.len());
+                // Build byte-to-char (grapheme cluster) mapping
+                let (byte_to_char, chars) = grapheme::byte_to_cluster(text);
 
                 let cursor_char = byte_to_char
                     .get(cursor_byte)
                     .copied()
                     .unwrap_or(chars.len());
 
-                // Calculate character x positions
-                let mut char_x_positions: Vec<f32> = Vec::with_capacity(chars.len() + 1);
-                let mut x = text_left;
-                for c in &chars {
-                    char_x_positions.push(x);
-                    x += self.mono_renderer.measure_text(&c.to_string());
-                }
-                char_x_positions.push(x);
+                // Character x positions from the shaped text
+                let char_x_positions: Vec<f32> = self
+                    .mono_renderer
+                    .cluster_offsets(text)
+                    .into_iter()
+                    .map(|x| text_left + x)
+                    .collect();
 
                 // Draw characters
                 for (i, c) in chars.iter().enumerate() {
                     let char_x = char_x_positions[i];
-                    self.mono_renderer.draw_text(
-                        pixmap,
-                        &c.to_string(),
-                        char_x,
-                        y_baseline,
-                        text_color,
-                    );
+                    self.mono_renderer
+                        .draw_text(pixmap, c, char_x, y_baseline, text_color);
                 }
 
                 // Draw line cursor (2px vertical line)
@@ -568,28 +1373,103 @@ impl UnifiedPopup {
                     self.mono_renderer.draw_text(
                         pixmap,
                         entry,
-                        text_x,
+                        text_x.round(),
                         y_baseline,
-                        rgba(KEYPRESS_TEXT_COLOR),
+                        rgba(self.theme.keypress),
                     );
                     text_x += self.mono_renderer.measure_text(entry);
                 }
+                if let Some(hint) = content.keypress_hint {
+                    self.mono_renderer.draw_text(
+                        pixmap,
+                        hint,
+                        (text_x + KEYPRESS_ENTRY_GAP).round(),
+                        y_baseline,
+                        rgba(self.theme.number),
+                    );
+                }
             }
         }
+    }
 
-        // Draw separator if candidates follow
-        if layout.has_candidates {
-            let sep_y = layout.keypress_y + line_height;
-            if let Some(rect) =
-                Rect::from_xywh(PADDING, sep_y, self.width as f32 - PADDING * 2.0, 1.0)
-            {
-                let mut paint = Paint::default();
-                paint.set_color(rgba(BORDER_COLOR));
-                pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+    /// Render the text typed at an `input()` prompt on its own row, scrolled
+    /// like the preedit to keep the line cursor visible
+    fn render_prompt_text(
+        &mut self,
+        pixmap: &mut Pixmap,
+        text: &str,
+        cursor_byte: usize,
+        layout: &Layout,
+    ) {
+        let text_color = rgba(self.theme.text);
+        let padding = self.theme.padding;
+        let line_height = self.renderer.line_height();
+        let y_baseline = layout.prompt_y + line_height * 0.75;
+        let right = layout.width as f32 - padding;
+
+        let (byte_to_char, chars) = grapheme::byte_to_cluster(text);
+        let cursor_char = byte_to_char
+            .get(cursor_byte)
+            .copied()
+            .unwrap_or(chars.len());
+        let char_x_positions: Vec<f32> = self
+            .renderer
+            .cluster_offsets(text)
+            .into_iter()
+            .map(|x| padding + x)
+            .collect();
+        let cursor_rel = char_x_positions[cursor_char.min(chars.len())] - padding;
+        let scroll_offset = preedit_scroll_offset(
+            char_x_positions[chars.len()] - padding,
+            right - padding - CMDLINE_CURSOR_WIDTH,
+            cursor_rel,
+        );
+
+        for (i, c) in chars.iter().enumerate() {
+            let char_x = char_x_positions[i] - scroll_offset;
+            // Skip characters outside visible area
+            if char_x_positions[i + 1] - scroll_offset < padding || char_x > right {
+                continue;
             }
+            self.renderer
+                .draw_text(pixmap, c, char_x, y_baseline, text_color);
+        }
+
+        let cursor_x = padding + cursor_rel - scroll_offset;
+        if let Some(rect) =
+            Rect::from_xywh(cursor_x, layout.prompt_y, CMDLINE_CURSOR_WIDTH, line_height)
+        {
+            let mut paint = Paint::default();
+            paint.set_color(text_color);
+            pixmap.fill_rect(rect, &paint, Transform::identity(), None);
         }
     }
 
+    /// Render the macro row: the playback indicator, then the keys (cut to
+    /// the popup's width)
+    fn render_macro_row(&mut self, pixmap: &mut Pixmap, content: &PopupContent, layout: &Layout) {
+        let Some(ref row) = content.macro_row else {
+            return;
+        };
+        let padding = self.theme.padding;
+        let y_baseline = layout.macro_y + self.renderer.line_height() * 0.75;
+        let mut x = padding;
+        if let Some(ref label) = row.playing {
+            self.mono_renderer
+                .draw_text(pixmap, label, x, y_baseline, rgba(self.theme.recording));
+            x += self.mono_renderer.measure_text(label) + KEYPRESS_ENTRY_GAP;
+        }
+        let room = layout.width as f32 - padding - x;
+        let keys = fit_text(&mut self.mono_renderer, &row.keys, room);
+        self.mono_renderer.draw_text(
+            pixmap,
+            &keys,
+            x.round(),
+            y_baseline,
+            rgba(self.theme.keypress),
+        );
+    }
+
     /// Render candidate section with scrollbar
     fn render_candidate_section(
         &mut self,
@@ -597,60 +1477,131 @@ impl UnifiedPopup {
         content: &PopupContent,
         layout: &Layout,
     ) {
-        let text_color = rgba(TEXT_COLOR);
-        let selected_bg = rgba(SELECTED_BG);
-        let number_color = rgba(NUMBER_COLOR);
-        let scrollbar_bg = rgba(SCROLLBAR_BG);
-        let scrollbar_thumb = rgba(SCROLLBAR_THUMB);
+        let text_color = rgba(self.theme.text);
+        let selected_bg = rgba(self.theme.selected);
+        let number_color = rgba(self.theme.number);
+        let mut annotation_color = text_color;
+        annotation_color.apply_opacity(ANNOTATION_ALPHA);
+        let scrollbar_bg = rgba(self.theme.scrollbar);
+        let scrollbar_thumb = rgba(self.theme.scrollbar_thumb);
+        // Rows are inset by the border so it stays intact
+        let inset = self.theme.border_width.ceil();
+        let text_x = self.theme.padding - inset + layout.number_width;
 
         let line_height = self.renderer.line_height();
+        let row_height = layout.row_height;
         let total_count = content.candidates.len();
 
-        // Render visible candidates
-        for (visible_idx, candidate) in content
-            .candidates
-            .iter()
-            .skip(self.scroll_offset)
-            .take(layout.visible_count)
-            .enumerate()
-        {
-            let actual_idx = self.scroll_offset + visible_idx;
-            let y_base = layout.candidates_y + (visible_idx as f32 * line_height);
-            let y_text = y_base + line_height * 0.75;
+        // Rows are drawn into a viewport-sized pixmap so a fractional scroll
+        // offset clips the partially visible rows at the top and bottom edges.
+        // x starts after the left border to keep it intact.
+        let area_width = if layout.has_scrollbar {
+            self.width as f32 - SCROLLBAR_WIDTH - 4.0
+        } else {
+            self.width as f32 - inset
+        } - inset;
+        let area_height = layout.visible_count as f32 * row_height;
+        let Some(mut area) = Pixmap::new(area_width.max(1.0) as u32, area_height.max(1.0) as u32)
+        else {
+            return;
+        };
+        area.fill(rgba(self.theme.background));
+
+        // Only rows intersecting the viewport are drawn, however long the list is
+        let offset = self.scroll.offset;
+        let window = candidate_window(total_count, offset, layout.visible_count);
+        let first_row = self.scroll.first_row();
+        for actual_idx in first_row..window.end {
+            let y_base = (actual_idx as f32 - offset) * row_height;
+            if y_base >= area_height {
+                break;
+            }
+            let y_text = y_base + layout.furigana_height + line_height * 0.75;
 
             // Draw selection highlight
-            if actual_idx == content.selected {
-                let highlight_width = if layout.has_scrollbar {
-                    self.width as f32 - SCROLLBAR_WIDTH - 4.0
+            if actual_idx == content.selected
+                && let Some(rect) = Rect::from_xywh(0.0, y_base, area_width, row_height)
+            {
+                let mut paint = Paint::default();
+                paint.set_color(selected_bg);
+                area.fill_rect(rect, &paint, Transform::identity(), None);
+            }
+
+            // Draw label (monospace, right-aligned to the label column's gap cell).
+            // Labels follow the page so they match the selection keys.
+            let row = actual_idx - first_row;
+            if let Some(label) = content.candidate_labels.labels().get(row) {
+                let label = format!("{label}.");
+                let label_x = if label.is_ascii() {
+                    text_x - (label.len() as f32 + 1.0) * self.grid.cell
                 } else {
-                    self.width as f32
+                    (text_x - self.mono_renderer.measure_text(&label) - self.grid.cell).round()
                 };
-                if let Some(rect) = Rect::from_xywh(0.0, y_base, highlight_width, line_height) {
-                    let mut paint = Paint::default();
-                    paint.set_color(selected_bg);
-                    pixmap.fill_rect(rect, &paint, Transform::identity(), None);
-                }
+                self.mono_renderer
+                    .draw_text(&mut area, &label, label_x, y_text, number_color);
             }
 
-            // Draw number
-            let number = format!("{}.", actual_idx + 1);
+            // Draw candidate text, then its annotation dimmed after it, both
+            // cut to the row
+            let room = layout.candidate_text_width;
+            let candidate = fit_text(&mut self.renderer, &content.candidates[actual_idx], room);
             self.renderer
-                .draw_text(pixmap, &number, PADDING, y_text, number_color);
+                .draw_text(&mut area, &candidate, text_x, y_text, text_color);
+            let candidate_width = self.renderer.measure_text(&candidate);
+            if let Some(annotation) = content.annotation(actual_idx) {
+                let room = room - candidate_width - ANNOTATION_GAP;
+                let annotation = fit_text(&mut self.renderer, annotation, room);
+                let annotation_x = text_x + candidate_width + ANNOTATION_GAP;
+                self.renderer.draw_text(
+                    &mut area,
+                    &annotation,
+                    annotation_x,
+                    y_text,
+                    annotation_color,
+                );
+            }
 
-            // Draw candidate text
-            self.renderer.draw_text(
-                pixmap,
-                candidate,
-                PADDING + NUMBER_WIDTH,
-                y_text,
-                text_color,
-            );
+            // Draw the kind dimmed in its column at the right edge
+            if let Some(kind) = content.kind(actual_idx) {
+                let kind_x = area_width - self.theme.padding + inset - layout.kind_width;
+                self.mono_renderer.draw_text(
+                    &mut area,
+                    kind,
+                    kind_x.round(),
+                    y_text,
+                    annotation_color,
+                );
+            }
+
+            // Draw furigana centered above the candidate (left-aligned if wider)
+            if layout.furigana_height > 0.0
+                && let Some(ref mut ruby) = self.furigana_renderer
+                && let Some(reading) = content.furigana(actual_idx)
+            {
+                let ruby_x =
+                    text_x + ((candidate_width - ruby.measure_text(reading)) / 2.0).max(0.0);
+                let ruby_y = y_base + layout.furigana_height * 0.75;
+                ruby.draw_text(&mut area, reading, ruby_x, ruby_y, text_color);
+            }
         }
 
+        let blit = PixmapPaint {
+            blend_mode: BlendMode::Source,
+            ..PixmapPaint::default()
+        };
+        pixmap.draw_pixmap(
+            inset as i32,
+            layout.candidates_y as i32,
+            area.as_ref(),
+            &blit,
+            Transform::identity(),
+            None,
+        );
+
         // Draw scrollbar if needed
         if layout.has_scrollbar {
-            let scrollbar_x = self.width as f32 - SCROLLBAR_WIDTH - 2.0;
-            let scrollbar_height = layout.visible_count as f32 * line_height;
+            let scrollbar_x = self.width as f32 - SCROLLBAR_WIDTH - inset - 1.0;
+            let scrollbar_height = layout.visible_count as f32 * row_height;
 
             // Scrollbar track
             if let Some(rect) = Rect::from_xywh(
@@ -665,11 +1616,12 @@ impl UnifiedPopup {
             }
 
             // Scrollbar thumb
+            // Against the whole list, of which only a window may be here
             let thumb = scrollbar_thumb_geometry(
                 layout.visible_count,
-                total_count,
+                content.candidate_total(),
                 scrollbar_height,
-                self.scroll_offset,
+                offset + content.candidate_offset as f32,
                 layout.candidates_y,
             );
 
@@ -680,6 +1632,28 @@ impl UnifiedPopup {
                 pixmap.fill_rect(rect, &paint, Transform::identity(), None);
             }
         }
+
+        // The selected candidate in full when its row cut it
+        if !layout.detail.is_empty() {
+            for (i, line) in layout.detail.iter().enumerate() {
+                let y_text = layout.detail_y + (i as f32 + 0.75) * line_height;
+                self.renderer
+                    .draw_text(pixmap, line, self.theme.padding, y_text, text_color);
+            }
+        }
+
+        // Footer: selection position and page, right-aligned
+        let footer_x = self.width as f32
+            - self.theme.padding
+            - self.mono_renderer.measure_text(&layout.footer);
+        let y_text = layout.footer_y + self.mono_renderer.line_height() * 0.75;
+        self.mono_renderer.draw_text(
+            pixmap,
+            &layout.footer,
+            footer_x.max(self.theme.padding),
+            y_text,
+            rgba(self.theme.keypress),
+        );
     }
 
     /// Render a transient message in the candidate area
@@ -692,11 +1666,37 @@ impl UnifiedPopup {
         if let Some(ref msg) = content.transient_message {
             let line_height = self.renderer.line_height();
             let y_text = layout.candidates_y + line_height * 0.75;
-            self.renderer
-                .draw_text(pixmap, msg, PADDING, y_text, rgba(TEXT_COLOR));
+            self.renderer.draw_text(
+                pixmap,
+                msg,
+                self.theme.padding,
+                y_text,
+                rgba(self.theme.text),
+            );
         }
     }
 
+    /// Render the diagnostics row
+    fn render_debug_line(&mut self, pixmap: &mut Pixmap, content: &PopupContent, layout: &Layout) {
+        let Some(ref line) = content.debug_line else {
+            return;
+        };
+        let y_text = layout.debug_y + self.mono_renderer.line_height() * 0.75;
+        self.mono_renderer.draw_text(
+            pixmap,
+            line,
+            self.theme.padding,
+            y_text,
+            rgba(self.theme.keypress),
+        );
+    }
+
+    /// Full-width 1px separator line at `y`, inside the padding
+    fn separator_rect(&self, y: f32) -> Option<Rect> {
+        let padding = self.theme.padding;
+        Rect::from_xywh(padding, y, self.width as f32 - padding * 2.0, 1.0)
+    }
+
     /// Find an available buffer slot
     fn find_available_buffer(&mut self) -> usize {
         let other = 1 - self.current_buffer;
@@ -0,0 +1,182 @@
+//! Startup check for compositors jacin can't run under
+//!
+//! jacin needs zwp_input_method_v2, which wlroots-based compositors (Sway,
+//! Hyprland, river, ...) and niri provide, but GNOME (Mutter) doesn't, and KDE
+//! (KWin) only offers it to the input method Plasma launches. Instead of
+//! failing on the first missing global, jacin lists what is missing, explains
+//! the alternatives for the detected desktop and exits with
+//! [`EXIT_UNSUPPORTED`], so launch scripts and status bars can tell an
+//! unsupported session from a crash.
+
+use crate::quirks::Compositor;
+
+/// Exit status when the compositor lacks a required protocol (sysexits
+/// `EX_UNAVAILABLE`)
+pub const EXIT_UNSUPPORTED: i32 = 69;
+
+/// Globals jacin can't run without, with the lowest usable version
+pub const REQUIRED_GLOBALS: [(&str, u32); 4] = [
+    ("zwp_input_method_manager_v2", 1),
+    ("wl_seat", 1),
+    ("wl_compositor", 4),
+    ("wl_shm", 1),
+];
+
+/// A required global the compositor doesn't offer (or offers too old)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Missing {
+    pub interface: &'static str,
+    pub required_version: u32,
+    /// Version offered, if any
+    pub offered_version: Option<u32>,
+}
+
+impl std::fmt::Display for Missing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.offered_version {
+            None => write!(f, "{}", self.interface),
+            Some(offered) => write!(
+                f,
+                "{} (version {}+ needed, compositor offers {})",
+                self.interface, self.required_version, offered
+            ),
+        }
+    }
+}
+
+/// Required globals missing from `advertised` (interface, version)
+pub fn missing_globals(advertised: &[(String, u32)]) -> Vec<Missing> {
+    REQUIRED_GLOBALS
+        .iter()
+        .filter_map(|&(interface, required_version)| {
+            let offered_version = advertised
+                .iter()
+                .filter(|(name, _)| name == interface)
+                .map(|&(_, version)| version)
+                .max();
+            match offered_version {
+                Some(version) if version >= required_version => None,
+                _ => Some(Missing {
+                    interface,
+                    required_version,
+                    offered_version,
+                }),
+            }
+        })
+        .collect()
+}
+
+/// Application side of input methods: often offered where input-method-v2
+/// isn't, but of no use to an input method
+const TEXT_INPUT_V3: &str = "zwp_text_input_manager_v3";
+
+/// Message printed before exiting with `EXIT_UNSUPPORTED`. `advertised` is
+/// what the compositor offers (interface, version).
+pub fn explanation(
+    compositor: Compositor,
+    missing: &[Missing],
+    advertised: &[(String, u32)],
+) -> String {
+    let mut message =
+        String::from("jacin can't run under this compositor. Missing Wayland protocols:\n");
+    for m in missing {
+        message.push_str(&format!("  - {m}\n"));
+    }
+    message.push('\n');
+    message.push_str(match compositor {
+        Compositor::Gnome => {
+            "GNOME (Mutter) doesn't implement the input method protocol (input-method-v2); \
+             its input methods go through IBus. Use an IBus engine such as ibus-mozc, \
+             or run jacin in a wlroots-based session (Sway, Hyprland, river) or niri."
+        }
+        Compositor::Kwin => {
+            "This KWin doesn't offer input-method-v2 to clients. Plasma launches its own \
+             input method (System Settings > Keyboard > Virtual Keyboard), where Fcitx 5 \
+             can be selected; otherwise run jacin in a wlroots-based session (Sway, \
+             Hyprland, river) or niri."
+        }
+        _ => {
+            "jacin needs a compositor with input-method-v2, such as Sway, Hyprland, river \
+             or niri. If this compositor has it, check that it is enabled for clients."
+        }
+    });
+    if advertised.iter().any(|(name, _)| name == TEXT_INPUT_V3) {
+        message.push_str(
+            "\n\nThe compositor does offer text-input-v3, but that is the application \
+             side of input methods: through it a client receives composed text for its \
+             own windows, it can't compose text for other applications. jacin can't \
+             use it in place of input-method-v2.",
+        );
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn advertised(globals: &[(&str, u32)]) -> Vec<(String, u32)> {
+        globals
+            .iter()
+            .map(|&(name, version)| (name.to_string(), version))
+            .collect()
+    }
+
+    #[test]
+    fn wlroots_globals_are_complete() {
+        let globals = advertised(&[
+            ("wl_compositor", 6),
+            ("wl_shm", 1),
+            ("wl_seat", 9),
+            ("zwp_input_method_manager_v2", 1),
+            ("zwp_virtual_keyboard_manager_v1", 1),
+        ]);
+        assert!(missing_globals(&globals).is_empty());
+    }
+
+    #[test]
+    fn reports_absent_and_outdated_globals() {
+        // Mutter: no input method, and an old wl_compositor for illustration
+        let globals = advertised(&[("wl_compositor", 3), ("wl_shm", 1), ("wl_seat", 8)]);
+        let missing = missing_globals(&globals);
+        assert_eq!(
+            missing,
+            [
+                Missing {
+                    interface: "zwp_input_method_manager_v2",
+                    required_version: 1,
+                    offered_version: None,
+                },
+                Missing {
+                    interface: "wl_compositor",
+                    required_version: 4,
+                    offered_version: Some(3),
+                },
+            ]
+        );
+        assert_eq!(missing[0].to_string(), "zwp_input_method_manager_v2");
+        assert_eq!(
+            missing[1].to_string(),
+            "wl_compositor (version 4+ needed, compositor offers 3)"
+        );
+    }
+
+    #[test]
+    fn explanation_names_the_desktop_alternative() {
+        let missing = missing_globals(&[]);
+        let gnome = explanation(Compositor::Gnome, &missing, &[]);
+        assert!(gnome.contains("  - zwp_input_method_manager_v2\n"));
+        assert!(gnome.contains("IBus"));
+        assert!(!gnome.contains("text-input-v3"));
+        assert!(explanation(Compositor::Kwin, &missing, &[]).contains("Fcitx 5"));
+        assert!(explanation(Compositor::Other, &missing, &[]).contains("input-method-v2"));
+    }
+
+    #[test]
+    fn explanation_says_text_input_v3_is_no_substitute() {
+        let globals = advertised(&[("wl_seat", 8), ("zwp_text_input_manager_v3", 1)]);
+        let message = explanation(Compositor::Gnome, &missing_globals(&globals), &globals);
+        assert!(message.contains("text-input-v3"));
+        assert!(message.contains("can't compose text for other applications"));
+    }
+}
@@ -0,0 +1,103 @@
+//! Wake-up counters (`jacinctl wakeups`)
+//!
+//! Every timer is inserted on demand and dropped once it has nothing to do, so
+//! an idle jacin (IME disabled, no key held) should not wake up at all. These
+//! counters make that checkable: event loop iterations, timer fires per timer
+//! and popup renders since startup. The idle audit in
+//! `tests/idle_wakeups.rs` reads them before and after an idle window.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+
+/// On-demand timers in the event loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timer {
+    Repeat,
+    Keypress,
+    BufferClear,
+    GrabWatchdog,
+    Scroll,
+    Animation,
+    Contention,
+    HistorySave,
+}
+
+impl Timer {
+    const COUNT: usize = 8;
+}
+
+static LOOP_ITERATIONS: AtomicU64 = AtomicU64::new(0);
+static TIMER_FIRES: [AtomicU64; Timer::COUNT] = [const { AtomicU64::new(0) }; Timer::COUNT];
+static RENDERS: AtomicU64 = AtomicU64::new(0);
+
+/// The event loop woke up (any source)
+pub fn loop_iteration() {
+    LOOP_ITERATIONS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn timer_fired(timer: Timer) {
+    TIMER_FIRES[timer as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// The popup was drawn
+pub fn rendered() {
+    RENDERS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Counter values at one point in time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Counts {
+    pub loop_iterations: u64,
+    pub repeat_timer: u64,
+    pub keypress_timer: u64,
+    pub buffer_clear_timer: u64,
+    pub grab_watchdog_timer: u64,
+    pub scroll_timer: u64,
+    pub animation_timer: u64,
+    pub contention_timer: u64,
+    pub history_save_timer: u64,
+    pub renders: u64,
+}
+
+impl Counts {
+    pub fn now() -> Self {
+        let timer = |t: Timer| TIMER_FIRES[t as usize].load(Ordering::Relaxed);
+        Self {
+            loop_iterations: LOOP_ITERATIONS.load(Ordering::Relaxed),
+            repeat_timer: timer(Timer::Repeat),
+            keypress_timer: timer(Timer::Keypress),
+            buffer_clear_timer: timer(Timer::BufferClear),
+            grab_watchdog_timer: timer(Timer::GrabWatchdog),
+            scroll_timer: timer(Timer::Scroll),
+            animation_timer: timer(Timer::Animation),
+            contention_timer: timer(Timer::Contention),
+            history_save_timer: timer(Timer::HistorySave),
+            renders: RENDERS.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_accumulate() {
+        // Counters are process-wide; other tests may render concurrently
+        let before = Counts::now();
+        timer_fired(Timer::GrabWatchdog);
+        timer_fired(Timer::Scroll);
+        timer_fired(Timer::Scroll);
+        timer_fired(Timer::Contention);
+        timer_fired(Timer::HistorySave);
+        loop_iteration();
+        let after = Counts::now();
+        assert_eq!(after.grab_watchdog_timer - before.grab_watchdog_timer, 1);
+        assert_eq!(after.scroll_timer - before.scroll_timer, 2);
+        assert_eq!(after.contention_timer - before.contention_timer, 1);
+        assert_eq!(after.history_save_timer - before.history_save_timer, 1);
+        assert_eq!(after.repeat_timer, before.repeat_timer);
+        assert!(after.loop_iterations > before.loop_iterations);
+    }
+}
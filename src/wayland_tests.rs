@@ -0,0 +1,599 @@
+//! Wayland integration tests against a mock compositor.
+//!
+//! [`MockCompositor`] serves one client on a socket pair with wayland-backend's
+//! server side, on a thread of its own. It advertises the globals jacin needs,
+//! records every request it receives, and sends the input method and keyboard
+//! grab events a test asks for, and offers a selection through
+//! wlr-data-control. The state under test is built by
+//! [`init_state`] as in `main`, so events go through the real Dispatch impls
+//! and requests over the wire. The romaji backend or a scripted
+//! [`FakeBackend`](crate::fake_backend::FakeBackend) keeps Neovim out of it.
+
+use std::fs::File;
+use std::io::Write;
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use wayland_backend::protocol::{Argument, Interface, Message};
+use wayland_backend::server::{
+    Backend, ClientData, ClientId, GlobalHandler, GlobalId, Handle, ObjectData, ObjectId,
+};
+use wayland_client::protocol::{wl_compositor, wl_seat, wl_shm};
+use wayland_client::{Connection, EventQueue, Proxy};
+use wayland_protocols_misc::zwp_input_method_v2::client::zwp_input_method_manager_v2;
+use wayland_protocols_wlr::data_control::v1::client::{
+    zwlr_data_control_manager_v1, zwlr_data_control_offer_v1,
+};
+
+use crate::config::{BackendKind, Config, OnFocusLoss};
+use crate::neovim::ClipboardSelection;
+use crate::state::KeyboardState;
+use crate::{Startup, State, init_state};
+
+/// How long the compositor thread waits for requests before checking whether
+/// to stop
+const POLL_TIMEOUT_MS: i32 = 5;
+
+/// Evdev codes of the keys the tests type
+const KEY_A: u32 = 30;
+const KEY_ENTER: u32 = 28;
+
+#[derive(Debug, Default)]
+struct Recorded {
+    /// Requests as `interface.request(args)`, in order
+    requests: Vec<String>,
+    input_method: Option<ObjectId>,
+    /// Latest keyboard grab
+    grab: Option<ObjectId>,
+    data_device: Option<ObjectId>,
+    /// Text sent to whoever receives the offered selection
+    selection: Option<String>,
+}
+
+type Log = Arc<Mutex<Recorded>>;
+
+/// Data of every object: records its requests and the objects they create
+struct Recorder(Log);
+
+impl ObjectData<()> for Recorder {
+    fn request(
+        self: Arc<Self>,
+        _handle: &Handle,
+        _data: &mut (),
+        _client_id: ClientId,
+        msg: Message<ObjectId, OwnedFd>,
+    ) -> Option<Arc<dyn ObjectData<()>>> {
+        let interface = msg.sender_id.interface();
+        let request = interface.requests[msg.opcode as usize].name;
+        let mut recorded = self.0.lock().unwrap();
+        recorded
+            .requests
+            .push(format_request(interface.name, request, &msg.args));
+        if interface.name == "zwlr_data_control_offer_v1" && request == "receive" {
+            // Played by the selection source; the pipe holds this much without blocking
+            let text = recorded.selection.clone().unwrap_or_default();
+            if let Some(Argument::Fd(fd)) = msg.args.into_iter().nth(1) {
+                let _ = File::from(fd).write_all(text.as_bytes());
+            }
+            return None;
+        }
+        let created = msg.args.iter().find_map(|arg| match arg {
+            Argument::NewId(id) => Some(id.clone()),
+            _ => None,
+        })?;
+        match created.interface().name {
+            "zwp_input_method_v2" => recorded.input_method = Some(created),
+            "zwp_input_method_keyboard_grab_v2" => recorded.grab = Some(created),
+            "zwlr_data_control_device_v1" => recorded.data_device = Some(created),
+            _ => {}
+        }
+        Some(Arc::new(Recorder(self.0.clone())))
+    }
+
+    fn destroyed(
+        self: Arc<Self>,
+        _handle: &Handle,
+        _data: &mut (),
+        _client_id: ClientId,
+        _object_id: ObjectId,
+    ) {
+    }
+}
+
+impl GlobalHandler<()> for Recorder {
+    fn bind(
+        self: Arc<Self>,
+        _handle: &Handle,
+        _data: &mut (),
+        _client_id: ClientId,
+        _global_id: GlobalId,
+        _object_id: ObjectId,
+    ) -> Arc<dyn ObjectData<()>> {
+        Arc::new(Recorder(self.0.clone()))
+    }
+}
+
+struct Client;
+
+impl ClientData for Client {}
+
+fn format_request(interface: &str, request: &str, args: &[Argument<ObjectId, OwnedFd>]) -> String {
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| match arg {
+            Argument::Int(i) => i.to_string(),
+            Argument::Uint(u) => u.to_string(),
+            Argument::Fixed(f) => (*f as f64 / 256.0).to_string(),
+            Argument::Str(s) => format!(
+                "{:?}",
+                s.as_deref()
+                    .map(|s| s.to_string_lossy())
+                    .unwrap_or_default()
+            ),
+            Argument::Object(id) | Argument::NewId(id) => id.interface().name.to_string(),
+            Argument::Array(_) => "[..]".to_string(),
+            Argument::Fd(_) => "fd".to_string(),
+        })
+        .collect();
+    format!("{interface}.{request}({})", args.join(", "))
+}
+
+/// A compositor with an input method for one seat
+struct MockCompositor {
+    handle: Handle,
+    recorded: Log,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    /// Keymaps sent, open until the compositor goes
+    keymaps: Vec<OwnedFd>,
+}
+
+impl MockCompositor {
+    /// Start serving; the client connects through the returned stream
+    fn start() -> (Self, UnixStream) {
+        let (server, client) = UnixStream::pair().unwrap();
+        let mut backend = Backend::<()>::new().unwrap();
+        let handle = backend.handle();
+        let recorded = Log::default();
+        let globals: [(&'static Interface, u32); 5] = [
+            (
+                zwp_input_method_manager_v2::ZwpInputMethodManagerV2::interface(),
+                1,
+            ),
+            (wl_seat::WlSeat::interface(), 7),
+            (wl_compositor::WlCompositor::interface(), 6),
+            (wl_shm::WlShm::interface(), 1),
+            (
+                zwlr_data_control_manager_v1::ZwlrDataControlManagerV1::interface(),
+                2,
+            ),
+        ];
+        for (interface, version) in globals {
+            handle.create_global::<()>(interface, version, Arc::new(Recorder(recorded.clone())));
+        }
+        backend
+            .handle()
+            .insert_client(server, Arc::new(Client))
+            .unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = std::thread::spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                let mut fd = libc::pollfd {
+                    fd: backend.poll_fd().as_raw_fd(),
+                    events: libc::POLLIN,
+                    revents: 0,
+                };
+                // SAFETY: one valid pollfd
+                unsafe { libc::poll(&mut fd, 1, POLL_TIMEOUT_MS) };
+                if backend.dispatch_all_clients(&mut ()).is_err() {
+                    break;
+                }
+                let _ = backend.flush(None);
+            }
+        });
+        let compositor = Self {
+            handle,
+            recorded,
+            stop,
+            thread: Some(thread),
+            keymaps: Vec::new(),
+        };
+        (compositor, client)
+    }
+
+    fn input_method(&self) -> ObjectId {
+        let recorded = self.recorded.lock().unwrap();
+        recorded.input_method.clone().expect("no input method yet")
+    }
+
+    fn grab(&self) -> ObjectId {
+        let recorded = self.recorded.lock().unwrap();
+        recorded.grab.clone().expect("keyboard not grabbed")
+    }
+
+    /// Send `event` of `target`'s interface (the next client roundtrip
+    /// delivers it)
+    fn send(&self, target: ObjectId, event: &str, args: Vec<Argument<ObjectId, RawFd>>) {
+        let opcode = target
+            .interface()
+            .events
+            .iter()
+            .position(|e| e.name == event)
+            .unwrap_or_else(|| panic!("no event {event} on {}", target.interface().name));
+        self.handle
+            .send_event(Message {
+                sender_id: target,
+                opcode: opcode as u16,
+                args: args.into(),
+            })
+            .unwrap();
+    }
+
+    fn activate(&self) {
+        self.send(self.input_method(), "activate", Vec::new());
+    }
+
+    fn deactivate(&self) {
+        self.send(self.input_method(), "deactivate", Vec::new());
+    }
+
+    fn done(&self) {
+        self.send(self.input_method(), "done", Vec::new());
+    }
+
+    /// Send the default XKB keymap on the grab
+    fn keymap(&mut self) {
+        let text = KeyboardState::new()
+            .keymap_text()
+            .expect("no default keymap");
+        // SAFETY: memfd_create has no preconditions; the result is checked
+        let fd = unsafe { libc::memfd_create(c"mock-keymap".as_ptr(), libc::MFD_CLOEXEC) };
+        assert!(fd >= 0, "memfd_create failed");
+        // SAFETY: fd was just created and is owned by nothing else
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        file.write_all(text.as_bytes()).unwrap();
+        file.write_all(&[0]).unwrap();
+        let fd = OwnedFd::from(file);
+        let args = vec![
+            Argument::Uint(1), // xkb_v1
+            Argument::Fd(fd.as_fd().as_raw_fd()),
+            Argument::Uint(text.len() as u32 + 1),
+        ];
+        self.send(self.grab(), "keymap", args);
+        self.keymaps.push(fd);
+    }
+
+    /// Another client copied `text`: offer it as the clipboard selection
+    fn offer_selection(&self, text: &str) {
+        let device = {
+            let mut recorded = self.recorded.lock().unwrap();
+            recorded.selection = Some(text.to_string());
+            recorded.data_device.clone().expect("no data device yet")
+        };
+        let client = self.handle.get_client(device.clone()).unwrap();
+        let offer = self
+            .handle
+            .create_object::<()>(
+                client,
+                zwlr_data_control_offer_v1::ZwlrDataControlOfferV1::interface(),
+                self.handle.object_info(device.clone()).unwrap().version,
+                Arc::new(Recorder(self.recorded.clone())),
+            )
+            .unwrap();
+        self.send(
+            device.clone(),
+            "data_offer",
+            vec![Argument::NewId(offer.clone())],
+        );
+        let mime = std::ffi::CString::new("text/plain;charset=utf-8").unwrap();
+        self.send(
+            offer.clone(),
+            "offer",
+            vec![Argument::Str(Some(Box::new(mime)))],
+        );
+        self.send(device, "selection", vec![Argument::Object(offer)]);
+    }
+
+    /// Press and release evdev key `key` on the grab
+    fn tap(&self, key: u32) {
+        for pressed in [1, 0] {
+            let args = vec![
+                Argument::Uint(0),
+                Argument::Uint(0),
+                Argument::Uint(key),
+                Argument::Uint(pressed),
+            ];
+            self.send(self.grab(), "key", args);
+        }
+    }
+
+    /// Input method and grab requests since the last call, without the popup
+    /// surface (created whenever fonts are available)
+    fn take_ime_requests(&self) -> Vec<String> {
+        let mut recorded = self.recorded.lock().unwrap();
+        std::mem::take(&mut recorded.requests)
+            .into_iter()
+            .filter(|r| {
+                r.starts_with("zwp_input_method_v2.") && !r.contains("get_input_popup_surface")
+                    || r.starts_with("zwp_input_method_keyboard_grab_v2.")
+            })
+            .collect()
+    }
+}
+
+impl Drop for MockCompositor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// jacin connected to a [`MockCompositor`]
+struct Session {
+    compositor: MockCompositor,
+    state: State,
+    event_queue: EventQueue<State>,
+}
+
+impl Session {
+    fn start() -> Self {
+        Self::with_backend(BackendKind::Romaji)
+    }
+
+    fn with_backend(kind: BackendKind) -> Self {
+        let mut config = Config::default();
+        config.backend.kind = kind;
+        Self::with_config(config)
+    }
+
+    fn with_config(config: Config) -> Self {
+        let (compositor, stream) = MockCompositor::start();
+        let conn = Connection::from_socket(stream).unwrap();
+        let Startup {
+            state, event_queue, ..
+        } = init_state(&conn, config, None, false, false).unwrap();
+        let mut session = Self {
+            compositor,
+            state,
+            event_queue,
+        };
+        session.roundtrip();
+        session.compositor.take_ime_requests();
+        session
+    }
+
+    /// Deliver what the compositor sent and let it see the requests. Requests
+    /// made while handling the events go out after the first sync, hence the
+    /// second.
+    fn roundtrip(&mut self) {
+        for _ in 0..2 {
+            self.event_queue.roundtrip(&mut self.state).unwrap();
+        }
+    }
+
+    fn focus(&mut self) {
+        self.compositor.activate();
+        self.compositor.done();
+        self.roundtrip();
+    }
+
+    /// Enable the IME in the focused input, ready for keys
+    fn enable(&mut self) {
+        self.state.handle_ime_toggle();
+        self.roundtrip();
+        self.compositor.keymap();
+        self.roundtrip();
+        // No debounce: the test's keys aren't left over from the toggle keybind
+        self.state.keyboard.ready_time = None;
+    }
+
+    fn type_key(&mut self, key: u32) {
+        self.compositor.tap(key);
+        self.roundtrip();
+    }
+}
+
+#[test]
+fn typed_text_is_shown_and_committed() {
+    let mut session = Session::start();
+    session.focus();
+    session.enable();
+    assert_eq!(
+        session.compositor.take_ime_requests(),
+        ["zwp_input_method_v2.grab_keyboard(zwp_input_method_keyboard_grab_v2)"]
+    );
+
+    session.type_key(KEY_A);
+    assert_eq!(
+        session.compositor.take_ime_requests(),
+        [
+            r#"zwp_input_method_v2.set_preedit_string("あ", 3, 3)"#,
+            "zwp_input_method_v2.commit(1)",
+        ]
+    );
+    session.type_key(KEY_ENTER);
+    assert_eq!(
+        session.compositor.take_ime_requests(),
+        [
+            r#"zwp_input_method_v2.commit_string("あ")"#,
+            r#"zwp_input_method_v2.set_preedit_string("", 0, 0)"#,
+            "zwp_input_method_v2.commit(1)",
+        ]
+    );
+}
+
+#[test]
+fn disabling_commits_the_preedit_before_releasing_the_grab() {
+    let mut session = Session::start();
+    session.focus();
+    session.enable();
+    session.type_key(KEY_A);
+    session.compositor.take_ime_requests();
+
+    session.state.handle_ime_toggle();
+    session.roundtrip();
+    assert_eq!(
+        session.compositor.take_ime_requests(),
+        [
+            r#"zwp_input_method_v2.commit_string("あ")"#,
+            r#"zwp_input_method_v2.set_preedit_string("", 0, 0)"#,
+            "zwp_input_method_v2.commit(1)",
+            "zwp_input_method_keyboard_grab_v2.release()",
+        ]
+    );
+}
+
+#[test]
+fn focus_loss_commits_the_preedit_when_configured() {
+    let mut config = Config::default();
+    config.backend.kind = BackendKind::Romaji;
+    config.behavior.on_focus_loss = OnFocusLoss::Commit;
+    let mut session = Session::with_config(config);
+    session.focus();
+    session.enable();
+    session.type_key(KEY_A);
+    session.compositor.take_ime_requests();
+
+    session.compositor.deactivate();
+    session.compositor.done();
+    session.roundtrip();
+    assert_eq!(
+        session.compositor.take_ime_requests(),
+        [
+            r#"zwp_input_method_v2.commit_string("あ")"#,
+            r#"zwp_input_method_v2.set_preedit_string("", 0, 0)"#,
+            "zwp_input_method_v2.commit(2)",
+            "zwp_input_method_keyboard_grab_v2.release()",
+        ]
+    );
+}
+
+#[test]
+fn reactivation_regrabs_without_debounce() {
+    let mut session = Session::start();
+    session.focus();
+    session.enable();
+    session.type_key(KEY_A);
+    session.compositor.take_ime_requests();
+
+    // Focus leaves: the grab goes, the preedit is dropped without a request
+    session.compositor.deactivate();
+    session.compositor.done();
+    session.roundtrip();
+    assert_eq!(
+        session.compositor.take_ime_requests(),
+        ["zwp_input_method_keyboard_grab_v2.release()"]
+    );
+    assert!(session.state.ime.preedit.is_empty());
+
+    // Focus comes back: one new grab, and keys count right after its keymap
+    session.compositor.activate();
+    session.compositor.done();
+    session.roundtrip();
+    assert_eq!(
+        session.compositor.take_ime_requests(),
+        ["zwp_input_method_v2.grab_keyboard(zwp_input_method_keyboard_grab_v2)"]
+    );
+    session.compositor.keymap();
+    session.roundtrip();
+    session.type_key(KEY_A);
+    assert_eq!(
+        session.compositor.take_ime_requests(),
+        [
+            r#"zwp_input_method_v2.set_preedit_string("あ", 3, 3)"#,
+            "zwp_input_method_v2.commit(3)",
+        ]
+    );
+}
+
+#[test]
+fn idle_grab_needs_no_watchdog_unless_opted_in() {
+    let mut session = Session::start();
+    session.focus();
+    session.enable();
+    assert!(session.state.wayland.keyboard_grab.is_some());
+    assert!(!session.state.grab_watchdog_needed());
+    // A grab that outlived its text input is checked
+    session.state.wayland.active = false;
+    assert!(session.state.grab_watchdog_needed());
+
+    let mut config = Config::default();
+    config.behavior.stuck_grab_timeout = 60;
+    let mut session = Session::with_config(config);
+    session.focus();
+    session.enable();
+    assert!(session.state.grab_watchdog_needed());
+}
+
+/// Backend script typing あ and committing it on Enter
+const FAKE_SCRIPT: &str = r#"{ "steps": [
+    { "messages": ["Ready"] },
+    { "on": { "Key": "a" }, "delay_ms": 20, "messages": [
+        { "Preedit": { "text": "あ", "cursor_begin": 3, "cursor_end": 3,
+                       "mode": "i", "recording": "" } },
+        "KeyProcessed"
+    ] },
+    { "on": { "Key": "<CR>" }, "messages": [
+        { "Commit": "あ" },
+        "KeyProcessed"
+    ] }
+] }"#;
+
+#[test]
+fn scripted_backend_drives_the_preedit() {
+    let script = std::env::temp_dir().join(format!("jacin-test-fake-{}.json", std::process::id()));
+    std::fs::write(&script, FAKE_SCRIPT).unwrap();
+    let mut session = Session::with_backend(BackendKind::Fake(script.clone()));
+    session.focus();
+    session.enable();
+    session.compositor.take_ime_requests();
+
+    session.type_key(KEY_A);
+    assert_eq!(session.state.ime.preedit, "あ");
+    assert_eq!(
+        session.compositor.take_ime_requests(),
+        [
+            r#"zwp_input_method_v2.set_preedit_string("あ", 3, 3)"#,
+            "zwp_input_method_v2.commit(1)",
+        ]
+    );
+    session.type_key(KEY_ENTER);
+    assert_eq!(
+        session.compositor.take_ime_requests(),
+        [
+            r#"zwp_input_method_v2.commit_string("あ")"#,
+            r#"zwp_input_method_v2.set_preedit_string("", 0, 0)"#,
+            "zwp_input_method_v2.commit(1)",
+        ]
+    );
+    let _ = std::fs::remove_file(script);
+}
+
+#[test]
+fn register_paste_reads_a_fresh_selection() {
+    let mut session = Session::start();
+    session.focus();
+    session.enable();
+    session.compositor.offer_selection("copied");
+    session.roundtrip();
+
+    // The receive request must reach the source while the paste waits
+    let started = Instant::now();
+    session
+        .state
+        .sync_clipboard_for_paste(ClipboardSelection::Clipboard);
+    let clipboard = session.state.clipboard.as_ref().unwrap();
+    assert_eq!(
+        clipboard.synced(ClipboardSelection::Clipboard),
+        Some("copied")
+    );
+    assert!(started.elapsed() < Duration::from_millis(150));
+}
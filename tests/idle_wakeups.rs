@@ -0,0 +1,170 @@
+//! Idle wake-up audit: an idle jacin must not poll, disabled or enabled.
+//!
+//! Starts jacin on a running compositor, lets it settle, then checks over an
+//! idle window (60s) that no timer fired and nothing was rendered, using the
+//! counters from `jacinctl wakeups`. The enabled case holds the keyboard grab
+//! of the text field focused at startup (e.g. the terminal running the
+//! audit), and is skipped without one. Needs a Wayland session with
+//! zwp_input_method_v2, so it only builds with the `idle-audit` feature:
+//!
+//! ```sh
+//! cargo test --features idle-audit --test idle_wakeups
+//! ```
+//!
+//! `JACIN_IDLE_AUDIT_SECS` shortens the window for a quick check.
+#![cfg(feature = "idle-audit")]
+
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+const IDLE_WINDOW: Duration = Duration::from_secs(60);
+/// Startup (Neovim spawn, keymap, first status) finishes within this
+const SETTLE: Duration = Duration::from_secs(3);
+/// Loop wake-ups the second `wakeups` request itself may cause
+const REQUEST_WAKEUPS: u64 = 2;
+
+/// One jacin at a time: a second one couldn't get the input method
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// jacin with its own runtime dir, so the control socket can't clash with a
+/// jacin already running in the session
+struct Jacin {
+    child: Child,
+    runtime_dir: PathBuf,
+}
+
+impl Jacin {
+    fn start() -> Self {
+        let session_runtime = std::env::var("XDG_RUNTIME_DIR")
+            .expect("idle audit needs XDG_RUNTIME_DIR of a Wayland session");
+        let display = std::env::var("WAYLAND_DISPLAY")
+            .expect("idle audit needs WAYLAND_DISPLAY of a compositor with input-method-v2");
+        // An absolute WAYLAND_DISPLAY is used as-is
+        let display = Path::new(&session_runtime).join(display);
+
+        let runtime_dir =
+            std::env::temp_dir().join(format!("jacin-idle-audit-{}", std::process::id()));
+        // Private, as the control socket requires of its directory
+        std::fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&runtime_dir)
+            .unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_jacin"))
+            .arg("--clean")
+            .env("XDG_RUNTIME_DIR", &runtime_dir)
+            .env("WAYLAND_DISPLAY", display)
+            .env("XDG_CONFIG_HOME", &runtime_dir)
+            .stdout(Stdio::null())
+            .spawn()
+            .expect("failed to start jacin");
+        Self { child, runtime_dir }
+    }
+
+    /// JSON response of `jacinctl <command> --json`
+    fn ctl(&self, command: &str) -> Option<Value> {
+        let output = Command::new(env!("CARGO_BIN_EXE_jacinctl"))
+            .args([command, "--json"])
+            .env("XDG_RUNTIME_DIR", &self.runtime_dir)
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        serde_json::from_slice(&output.stdout).ok()
+    }
+
+    fn wakeups(&self) -> Option<Value> {
+        self.ctl("wakeups")
+    }
+
+    fn wait_ready(&mut self) -> Value {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                panic!("jacin exited during startup: {status}");
+            }
+            if let Some(counts) = self.wakeups() {
+                return counts;
+            }
+            assert!(Instant::now() < deadline, "control socket never came up");
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+impl Drop for Jacin {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.runtime_dir);
+    }
+}
+
+fn counter(counts: &Value, name: &str) -> u64 {
+    counts[name]
+        .as_u64()
+        .unwrap_or_else(|| panic!("missing counter {name}: {counts}"))
+}
+
+/// Check that nothing woke `jacin` up over an idle window
+fn assert_idle(jacin: &Jacin) {
+    let window = std::env::var("JACIN_IDLE_AUDIT_SECS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .map_or(IDLE_WINDOW, Duration::from_secs);
+
+    let before = jacin.wakeups().expect("wakeups before the idle window");
+    std::thread::sleep(window);
+    let after = jacin.wakeups().expect("wakeups after the idle window");
+
+    let delta = |name| counter(&after, name) - counter(&before, name);
+    for timer in [
+        "repeat_timer",
+        "keypress_timer",
+        "buffer_clear_timer",
+        "grab_watchdog_timer",
+        "scroll_timer",
+        "animation_timer",
+        "contention_timer",
+        "history_save_timer",
+    ] {
+        assert_eq!(delta(timer), 0, "{timer} fired while idle");
+    }
+    assert_eq!(delta("renders"), 0, "popup rendered while idle");
+    assert!(
+        delta("loop_iterations") <= REQUEST_WAKEUPS,
+        "event loop woke up {} times while idle",
+        delta("loop_iterations")
+    );
+}
+
+#[test]
+fn disabled_ime_does_not_wake_up() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let mut jacin = Jacin::start();
+    jacin.wait_ready();
+    std::thread::sleep(SETTLE);
+    assert_idle(&jacin);
+}
+
+#[test]
+fn active_idle_grab_does_not_wake_up() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let mut jacin = Jacin::start();
+    jacin.wait_ready();
+    jacin.ctl("enable").expect("enable");
+    std::thread::sleep(SETTLE);
+
+    // Enabled in a focused text field = the keyboard is grabbed
+    let status = jacin.ctl("status").expect("status");
+    if status["active"] != true || status["status"] != "enabled" {
+        eprintln!("skipped: no text field focused at startup ({status})");
+        return;
+    }
+    assert_idle(&jacin);
+}